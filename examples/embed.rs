@@ -0,0 +1,169 @@
+use euclid::{point2, point3, vec3};
+use futures::executor::block_on;
+use hinoki::game::camera::Camera;
+use hinoki::game::lod;
+use hinoki::game::settings::{GraphicsSettings, LodSettings, StreamingSettings};
+use hinoki::game::terrain::Terrain;
+use hinoki::gfx::Instance;
+use std::sync::Arc;
+use wgpu::*;
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::{ControlFlow, EventLoop};
+use winit::window::WindowBuilder;
+
+// Of the rings around the origin, in world units
+const RADIUS: f32 = 1.0;
+
+// Draws the terrain in an app that creates its own window, device and surface
+// and only hands the device to the library. Everything but the terrain is
+// left to the app, here a clear color and a depth target.
+//
+//     cargo run --release --example embed
+fn main() {
+    env_logger::init();
+    let event_loop = EventLoop::new();
+    let window = WindowBuilder::new()
+        .with_title("hinoki embedding")
+        .build(&event_loop)
+        .unwrap();
+
+    let wgpu_instance = wgpu::Instance::new(Backends::all());
+    let surface = unsafe { wgpu_instance.create_surface(&window) };
+    let adapter = block_on(wgpu_instance.request_adapter(&RequestAdapterOptions {
+        power_preference: PowerPreference::default(),
+        compatible_surface: Some(&surface),
+    }))
+    .unwrap();
+    let (device, queue) = block_on(adapter.request_device(
+        &DeviceDescriptor {
+            label: None,
+            features: Features::POLYGON_MODE_LINE,
+            limits: adapter.limits(),
+        },
+        None,
+    ))
+    .unwrap();
+    let size = window.inner_size();
+    let mut surface_config = SurfaceConfiguration {
+        usage: TextureUsages::RENDER_ATTACHMENT,
+        format: surface.get_preferred_format(&adapter).unwrap(),
+        width: size.width,
+        height: size.height,
+        present_mode: PresentMode::Fifo,
+    };
+    surface.configure(&device, &surface_config);
+    let mut depth_view = create_depth_view(&device, &surface_config);
+    let instance = Arc::new(Instance::with_device(adapter, device, queue));
+
+    // Looking down at the origin from above, the view does not move so the
+    // rings are built once
+    let mut camera = Camera::new(
+        point3(-RADIUS, 0.0, 0.6),
+        vec3(1.0, 0.0, -0.4),
+        std::f32::consts::PI / 4.0,
+        size.width as f32 / size.height.max(1) as f32,
+        0.001,
+        GraphicsSettings::default().draw_distance,
+    );
+    camera.init(&instance);
+    let mut terrain = Terrain::new(&StreamingSettings::default());
+    terrain.init(
+        instance.clone(),
+        surface_config.format,
+        1,
+        camera.buffers(),
+        0.5,
+    );
+    let rings = lod::square_regions(&LodSettings::default(), &point2(0.0, 0.0), RADIUS);
+    let regions = rings.iter().map(|x| x.region.clone()).collect::<Vec<_>>();
+
+    event_loop.run(move |event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        // Chunk passes are read back as the device is polled
+        instance.device().poll(Maintain::Poll);
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                ..
+            } => *control_flow = ControlFlow::Exit,
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                surface_config.width = size.width;
+                surface_config.height = size.height;
+                surface.configure(instance.device(), &surface_config);
+                depth_view = create_depth_view(instance.device(), &surface_config);
+                camera.set_aspect_ratio(size.width as f32 / size.height.max(1) as f32);
+            }
+            Event::MainEventsCleared => {
+                window.request_redraw();
+            }
+            Event::RedrawRequested(_) => {
+                // Queues whatever is missing of the rings, nothing once they
+                // are meshed
+                terrain.update_terrain(camera.position(), &rings);
+                let projection_matrix = camera.projection_matrix();
+                camera.write_buffer(&instance, &projection_matrix);
+
+                let target = surface.get_current_frame().unwrap();
+                let view = target
+                    .output
+                    .texture
+                    .create_view(&TextureViewDescriptor::default());
+                let mut encoder = instance
+                    .device()
+                    .create_command_encoder(&CommandEncoderDescriptor { label: None });
+                {
+                    let bundles = terrain.render(&regions, camera.frame());
+                    let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+                        label: None,
+                        color_attachments: &[RenderPassColorAttachment {
+                            view: &view,
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Clear(Color {
+                                    r: 0.5,
+                                    g: 0.7,
+                                    b: 0.9,
+                                    a: 1.0,
+                                }),
+                                store: true,
+                            },
+                        }],
+                        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                            view: &depth_view,
+                            depth_ops: Some(Operations {
+                                load: LoadOp::Clear(1.0),
+                                store: true,
+                            }),
+                            stencil_ops: None,
+                        }),
+                    });
+                    rp.execute_bundles(bundles.iter().map(|x| x.into()));
+                }
+                instance.queue().submit(Some(encoder.finish()));
+            }
+            _ => {}
+        }
+    });
+}
+
+// The terrain pipelines test against a Depth32Float target
+fn create_depth_view(device: &Device, surface_config: &SurfaceConfiguration) -> TextureView {
+    device
+        .create_texture(&TextureDescriptor {
+            label: Some("embed_depth"),
+            size: Extent3d {
+                width: surface_config.width,
+                height: surface_config.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
@@ -0,0 +1,64 @@
+use euclid::{point2, point3, size2, vec3, Box2D};
+use hinoki::game::camera::Camera;
+use hinoki::game::random::{RandomStreams, Stream};
+use hinoki::game::settings::{self, SettingsFile};
+use hinoki::game::terrain::{ExportSource, Terrain};
+use hinoki::game::{self, lod};
+use hinoki::gfx::Instance;
+use std::path::Path;
+use std::sync::Arc;
+use wgpu::*;
+
+const IMAGE_SIZE: u32 = 1024;
+
+// Generates the terrain within the radius of the origin without a window and
+// writes its height map to a png
+//
+//     cargo run --release --example export -- <radius> <path>
+fn main() {
+    env_logger::init();
+    let args = std::env::args().collect::<Vec<_>>();
+    let radius = game::parse_radius(args.get(1).map(|x| x.as_str()));
+    let path = args.get(2).map_or("terrain.png", |x| x.as_str());
+
+    let instance = Arc::new(Instance::headless());
+    let settings = SettingsFile::new(settings::CONFIG_PATH).load();
+    let mut camera = Camera::new(
+        point3(0.0, 0.0, 0.3),
+        vec3(1.0, 0.0, 0.0),
+        std::f32::consts::PI / 4.0,
+        1.0,
+        0.001,
+        settings.graphics.draw_distance,
+    );
+    camera.init(&instance);
+    let mut terrain = Terrain::new(&settings.streaming);
+    terrain.init(
+        instance.clone(),
+        TextureFormat::Rgba8Unorm,
+        settings.graphics.sample_count(),
+        camera.buffers(),
+        0.5,
+    );
+    terrain.set_seed(RandomStreams::new(0).stream(Stream::Terrain).next_u32());
+
+    let regions = lod::square_regions(&settings.lod, &point2(0.0, 0.0), radius);
+    terrain.update_terrain(&point3(0.0, 0.0, 0.0), &regions);
+    let keys = terrain.region_keys(&regions);
+    log::info!("Generating {} chunks within {}", keys.len(), radius);
+    game::generate_chunks(&instance, &terrain, keys);
+
+    let bounds = Box2D::new(point2(-radius, -radius), point2(radius, radius));
+    let image = terrain.export_image(
+        &bounds,
+        size2(IMAGE_SIZE, IMAGE_SIZE),
+        ExportSource::SurfaceHeight,
+    );
+    image.save_png(Path::new(path)).unwrap();
+    log::info!(
+        "Exported {} with heights {:?}, {} texels missing",
+        path,
+        image.range(),
+        image.missing_count()
+    );
+}
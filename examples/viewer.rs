@@ -0,0 +1,155 @@
+use euclid::{point3, vec3};
+use hinoki::game::camera::Camera;
+use hinoki::game::lod;
+use hinoki::game::random::{RandomStreams, Stream};
+use hinoki::game::settings::{self, SettingsFile};
+use hinoki::game::terrain::Terrain;
+use hinoki::gfx::Instance;
+use hinoki::windowing::Window;
+use std::sync::Arc;
+use std::time::Instant;
+use wgpu::*;
+use winit::dpi::PhysicalSize;
+use winit::event::{Event, WindowEvent};
+use winit::event_loop::ControlFlow;
+
+// World units the camera flies per second
+const FLY_SPEED: f32 = 0.05;
+const FLY_HEIGHT: f32 = 0.3;
+// The rings follow the camera once it flew this far
+const REGION_UPDATE_DISTANCE: f32 = 0.01;
+
+// Flies the camera in a straight line over the world of the settings file,
+// streaming the rings in ahead of it. Only the terrain is drawn, straight into
+// the swapchain.
+//
+//     cargo run --release --example viewer
+fn main() {
+    env_logger::init();
+    let window = Window::new();
+    let instance = Arc::new(Instance::new(&window));
+    let settings = SettingsFile::new(settings::CONFIG_PATH).load();
+    let size = window.winit_window().inner_size();
+    let mut camera = Camera::new(
+        point3(0.0, 0.0, FLY_HEIGHT),
+        vec3(1.0, 0.0, -0.2),
+        std::f32::consts::PI / 4.0,
+        aspect_ratio(size),
+        0.001,
+        settings.graphics.draw_distance,
+    );
+    camera.init(&instance);
+    let mut terrain = Terrain::new(&settings.streaming);
+    terrain.init(
+        instance.clone(),
+        instance.surface_format(),
+        1,
+        camera.buffers(),
+        0.5,
+    );
+    terrain.set_seed(RandomStreams::new(0).stream(Stream::Terrain).next_u32());
+
+    let mut depth_view = create_depth_view(&instance, size);
+    let mut rings = vec![];
+    let mut rings_position = None;
+    let start = Instant::now();
+    window.run(move |window, event, _, control_flow| {
+        *control_flow = ControlFlow::Poll;
+        instance.device().poll(Maintain::Poll);
+        match event {
+            Event::WindowEvent {
+                event: WindowEvent::CloseRequested,
+                window_id,
+            } if window_id == window.id() => *control_flow = ControlFlow::Exit,
+            Event::WindowEvent {
+                event: WindowEvent::Resized(size),
+                ..
+            } => {
+                instance.recreate_swapchain(size);
+                camera.set_aspect_ratio(aspect_ratio(size));
+                depth_view = create_depth_view(&instance, size);
+            }
+            Event::RedrawEventsCleared => {
+                window.request_redraw();
+            }
+            Event::RedrawRequested(_) => {
+                let distance = start.elapsed().as_secs_f32() * FLY_SPEED;
+                camera.move_to(&point3(distance, 0.0, FLY_HEIGHT));
+                let outdated = rings_position.map_or(true, |x| {
+                    camera.position().distance_to(x) > REGION_UPDATE_DISTANCE
+                });
+                if outdated {
+                    rings_position = Some(*camera.position());
+                    rings = lod::terrain_regions(
+                        &camera,
+                        &settings.lod,
+                        &terrain.geometric_errors(),
+                        0,
+                    );
+                }
+                terrain.update_terrain(camera.position(), &rings);
+                let projection_matrix = camera.projection_matrix();
+                camera.write_buffer(&instance, &projection_matrix);
+                let regions = rings.iter().map(|x| x.region.clone()).collect::<Vec<_>>();
+
+                let target = instance.surface().get_current_frame().unwrap();
+                let view = target
+                    .output
+                    .texture
+                    .create_view(&TextureViewDescriptor::default());
+                let mut encoder = instance
+                    .device()
+                    .create_command_encoder(&CommandEncoderDescriptor { label: None });
+                {
+                    let bundles = terrain.render(&regions, camera.frame());
+                    let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+                        label: None,
+                        color_attachments: &[RenderPassColorAttachment {
+                            view: &view,
+                            resolve_target: None,
+                            ops: Operations {
+                                load: LoadOp::Clear(Color::BLACK),
+                                store: true,
+                            },
+                        }],
+                        depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                            view: &depth_view,
+                            depth_ops: Some(Operations {
+                                load: LoadOp::Clear(1.0),
+                                store: true,
+                            }),
+                            stencil_ops: None,
+                        }),
+                    });
+                    rp.execute_bundles(bundles.iter().map(|x| x.into()));
+                }
+                instance.queue().submit(Some(encoder.finish()));
+            }
+            _ => {}
+        }
+    });
+}
+
+fn aspect_ratio(size: PhysicalSize<u32>) -> f32 {
+    size.width as f32 / size.height.max(1) as f32
+}
+
+// The terrain pipelines test against a Depth32Float target
+fn create_depth_view(instance: &Instance, size: PhysicalSize<u32>) -> TextureView {
+    instance
+        .device()
+        .create_texture(&TextureDescriptor {
+            label: Some("viewer_depth"),
+            size: Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        })
+        .create_view(&TextureViewDescriptor::default())
+}
@@ -0,0 +1,93 @@
+use crate::game::base::WorldSpace;
+use crate::game::terrain::{dominant_biome, Terrain, BIOME_NAMES, CAVE_ENCLOSURE};
+use euclid::Point3D;
+use std::time::Duration;
+
+// Height in world units from which the camera hears the wind of the peaks
+// whatever the biome below
+const HIGH_ALTITUDE: f32 = 0.6;
+// A new zone has to hold this long before the soundscape changes, so that
+// walking along a border does not flip between the two
+const ZONE_HOLD: Duration = Duration::from_millis(750);
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Soundscape {
+    Biome(usize),
+    HighAltitude,
+    Cave,
+}
+
+impl Soundscape {
+    pub fn name(&self) -> &'static str {
+        match self {
+            Soundscape::Biome(biome) => BIOME_NAMES[*biome],
+            Soundscape::HighAltitude => "high altitude",
+            Soundscape::Cave => "cave",
+        }
+    }
+}
+
+// Sent to the audio backend when the camera settles in another zone
+#[derive(Debug, Copy, Clone)]
+pub struct ZoneChange {
+    pub from: Option<Soundscape>,
+    pub to: Soundscape,
+}
+
+// Picks the soundscape around the camera from the meshed chunks, the zone is
+// unknown until the chunk around the camera has been generated
+pub struct Ambience {
+    current: Option<Soundscape>,
+    candidate: Option<(Soundscape, Duration)>,
+}
+
+impl Ambience {
+    pub fn new() -> Self {
+        Self {
+            current: None,
+            candidate: None,
+        }
+    }
+
+    pub fn current(&self) -> Option<Soundscape> {
+        self.current
+    }
+
+    pub fn update(
+        &mut self,
+        terrain: &Terrain,
+        position: &Point3D<f32, WorldSpace>,
+        elapsed_time: Duration,
+    ) -> Option<ZoneChange> {
+        let zone = match zone_at(terrain, position) {
+            Some(zone) if Some(zone) != self.current => zone,
+            _ => {
+                self.candidate = None;
+                return None;
+            }
+        };
+        let held = match self.candidate {
+            Some((candidate, held)) if candidate == zone => held + elapsed_time,
+            _ => Duration::ZERO,
+        };
+        // The first zone is taken right away, there is nothing to fade from
+        if held < ZONE_HOLD && self.current.is_some() {
+            self.candidate = Some((zone, held));
+            return None;
+        }
+        self.candidate = None;
+        let from = self.current.replace(zone);
+        Some(ZoneChange { from, to: zone })
+    }
+}
+
+fn zone_at(terrain: &Terrain, position: &Point3D<f32, WorldSpace>) -> Option<Soundscape> {
+    if terrain.enclosure_around(position)? >= CAVE_ENCLOSURE {
+        return Some(Soundscape::Cave);
+    }
+    if position.z >= HIGH_ALTITUDE {
+        return Some(Soundscape::HighAltitude);
+    }
+    let weights = terrain.biome_at(&position.xy())?;
+    Some(Soundscape::Biome(dominant_biome(&weights)))
+}
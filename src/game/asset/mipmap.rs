@@ -0,0 +1,131 @@
+use crate::gfx::{Instance, SamplerKey};
+use std::collections::HashMap;
+use std::sync::Arc;
+use wgpu::*;
+
+// Fills the mip chain of a texture on the GPU, every level is rendered from
+// the previous one with a linear sampler
+pub struct MipmapGenerator {
+    shader_module: ShaderModule,
+    bind_group_layout: BindGroupLayout,
+    pipeline_layout: PipelineLayout,
+    sampler: Arc<Sampler>,
+    pipelines: HashMap<TextureFormat, RenderPipeline>,
+}
+
+impl MipmapGenerator {
+    pub fn new(instance: &Instance) -> Self {
+        let device = instance.device();
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("mipmap_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("mipmap_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        Self {
+            shader_module: device.create_shader_module(&include_wgsl!("shaders/mipmap.wgsl")),
+            bind_group_layout,
+            pipeline_layout,
+            sampler: instance.sampler(&SamplerKey::linear()),
+            pipelines: HashMap::new(),
+        }
+    }
+
+    // Levels before first_level must already hold data
+    pub fn generate(
+        &mut self,
+        instance: &Instance,
+        encoder: &mut CommandEncoder,
+        texture: &Texture,
+        format: TextureFormat,
+        first_level: u32,
+        mip_level_count: u32,
+    ) {
+        let device = instance.device();
+        let shader_module = &self.shader_module;
+        let pipeline_layout = &self.pipeline_layout;
+        let pipeline = self.pipelines.entry(format).or_insert_with(|| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("mipmap_pipeline"),
+                layout: Some(pipeline_layout),
+                vertex: VertexState {
+                    module: shader_module,
+                    entry_point: "main",
+                    buffers: &[],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                fragment: Some(FragmentState {
+                    module: shader_module,
+                    entry_point: "main",
+                    targets: &[format.into()],
+                }),
+            })
+        });
+        let level_view = |level: u32| {
+            texture.create_view(&TextureViewDescriptor {
+                label: Some("mipmap_level_view"),
+                base_mip_level: level,
+                mip_level_count: std::num::NonZeroU32::new(1),
+                ..Default::default()
+            })
+        };
+        for level in first_level.max(1)..mip_level_count {
+            let source = level_view(level - 1);
+            let target = level_view(level);
+            let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                label: Some("mipmap_bind_group"),
+                layout: &self.bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Sampler(&self.sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::TextureView(&source),
+                    },
+                ],
+            });
+            let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("mipmap"),
+                color_attachments: &[RenderPassColorAttachment {
+                    view: &target,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            rp.set_pipeline(pipeline);
+            rp.set_bind_group(0, &bind_group, &[]);
+            rp.draw(0..3, 0..1);
+        }
+    }
+}
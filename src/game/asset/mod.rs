@@ -0,0 +1,6 @@
+mod mipmap;
+mod model;
+mod texture;
+
+pub use model::load_obj;
+pub use texture::{TextureAsset, TextureError, TextureRegistry};
@@ -0,0 +1,79 @@
+use crate::game::base::LocalSpace;
+use euclid::Point3D;
+use std::fmt;
+use std::path::Path;
+
+#[derive(Debug)]
+pub enum ModelError {
+    Io(std::io::Error),
+    // Line number and what could not be read on it
+    Obj(usize, &'static str),
+}
+
+impl fmt::Display for ModelError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ModelError::Io(e) => write!(f, "{}", e),
+            ModelError::Obj(line, e) => write!(f, "invalid obj file at line {}: {}", line, e),
+        }
+    }
+}
+
+impl From<std::io::Error> for ModelError {
+    fn from(e: std::io::Error) -> Self {
+        ModelError::Io(e)
+    }
+}
+
+// Triangles of a Wavefront OBJ file. Only the positions of the faces are
+// read, as is with z up, and polygons are split into fans.
+pub fn load_obj(path: &Path) -> Result<Vec<[Point3D<f32, LocalSpace>; 3]>, ModelError> {
+    let source = std::fs::read_to_string(path)?;
+    let mut vertex = vec![];
+    let mut triangles = vec![];
+    for (i, line) in source.lines().enumerate() {
+        let error = |e| ModelError::Obj(i + 1, e);
+        let mut words = line.split_whitespace();
+        match words.next() {
+            Some("v") => {
+                let mut coordinate = || -> Result<f32, ModelError> {
+                    words
+                        .next()
+                        .and_then(|x| x.parse().ok())
+                        .ok_or_else(|| error("expected a vertex coordinate"))
+                };
+                vertex.push(Point3D::new(coordinate()?, coordinate()?, coordinate()?));
+            }
+            Some("f") => {
+                // Indices start at one, negative ones count back from the
+                // last vertex
+                let corners = words
+                    .map(|x| {
+                        let index = x
+                            .split('/')
+                            .next()
+                            .and_then(|x| x.parse::<isize>().ok())
+                            .ok_or_else(|| error("expected a vertex index"))?;
+                        let index = if index < 0 {
+                            vertex.len() as isize + index
+                        } else {
+                            index - 1
+                        };
+                        vertex
+                            .get(index as usize)
+                            .copied()
+                            .ok_or_else(|| error("vertex index out of range"))
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                if corners.len() < 3 {
+                    return Err(error("a face needs three vertices"));
+                }
+                for corner in 1..corners.len() - 1 {
+                    triangles.push([corners[0], corners[corner], corners[corner + 1]]);
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(triangles)
+}
@@ -0,0 +1,278 @@
+use super::mipmap::MipmapGenerator;
+use crate::gfx::Instance;
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::Arc;
+use wgpu::*;
+
+const KTX2_IDENTIFIER: [u8; 12] = [
+    0xab, 0x4b, 0x54, 0x58, 0x20, 0x32, 0x30, 0xbb, 0x0d, 0x0a, 0x1a, 0x0a,
+];
+// VkFormat values of the supported KTX2 formats
+const VK_FORMAT_R8G8B8A8_UNORM: u32 = 37;
+const VK_FORMAT_R8G8B8A8_SRGB: u32 = 43;
+
+#[derive(Debug)]
+pub enum TextureError {
+    Io(std::io::Error),
+    Png(png::DecodingError),
+    Ktx2(&'static str),
+    UnknownExtension,
+}
+
+impl fmt::Display for TextureError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            TextureError::Io(e) => write!(f, "{}", e),
+            TextureError::Png(e) => write!(f, "{}", e),
+            TextureError::Ktx2(e) => write!(f, "invalid ktx2 file: {}", e),
+            TextureError::UnknownExtension => write!(f, "expected a .png or .ktx2 file"),
+        }
+    }
+}
+
+impl From<std::io::Error> for TextureError {
+    fn from(e: std::io::Error) -> Self {
+        TextureError::Io(e)
+    }
+}
+
+impl From<png::DecodingError> for TextureError {
+    fn from(e: png::DecodingError) -> Self {
+        TextureError::Png(e)
+    }
+}
+
+// RGBA8 image, levels start at the full size. Missing levels of the mip chain
+// are generated on the GPU.
+struct Image {
+    width: u32,
+    height: u32,
+    format: TextureFormat,
+    levels: Vec<Vec<u8>>,
+}
+
+impl Image {
+    fn load_png(path: &Path) -> Result<Self, TextureError> {
+        let mut decoder = png::Decoder::new(BufReader::new(File::open(path)?));
+        decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+        let (info, mut reader) = decoder.read_info()?;
+        let mut data = vec![0; info.buffer_size()];
+        reader.next_frame(&mut data)?;
+        let data = match info.color_type {
+            png::ColorType::RGBA => data,
+            png::ColorType::RGB => data
+                .chunks(3)
+                .flat_map(|x| [x[0], x[1], x[2], 255])
+                .collect(),
+            png::ColorType::GrayscaleAlpha => data
+                .chunks(2)
+                .flat_map(|x| [x[0], x[0], x[0], x[1]])
+                .collect(),
+            // Indexed is expanded to RGB
+            _ => data.iter().flat_map(|x| [*x, *x, *x, 255]).collect(),
+        };
+        Ok(Self {
+            width: info.width,
+            height: info.height,
+            format: TextureFormat::Rgba8UnormSrgb,
+            levels: vec![data],
+        })
+    }
+
+    // Only uncompressed 2D RGBA8 textures without supercompression
+    fn load_ktx2(path: &Path) -> Result<Self, TextureError> {
+        let data = std::fs::read(path)?;
+        if data.len() < 80 || data[..12] != KTX2_IDENTIFIER {
+            return Err(TextureError::Ktx2("missing identifier"));
+        }
+        let u32_at =
+            |offset: usize| u32::from_le_bytes(data[offset..offset + 4].try_into().unwrap());
+        let u64_at =
+            |offset: usize| u64::from_le_bytes(data[offset..offset + 8].try_into().unwrap());
+        let format = match u32_at(12) {
+            VK_FORMAT_R8G8B8A8_UNORM => TextureFormat::Rgba8Unorm,
+            VK_FORMAT_R8G8B8A8_SRGB => TextureFormat::Rgba8UnormSrgb,
+            _ => return Err(TextureError::Ktx2("only rgba8 textures are supported")),
+        };
+        let (width, height, depth) = (u32_at(20), u32_at(24), u32_at(28));
+        let (layer_count, face_count, level_count) = (u32_at(32), u32_at(36), u32_at(40));
+        if width == 0 || height == 0 || depth != 0 || layer_count > 1 || face_count != 1 {
+            return Err(TextureError::Ktx2("only 2d textures are supported"));
+        }
+        if u32_at(44) != 0 {
+            return Err(TextureError::Ktx2("supercompression is not supported"));
+        }
+        // The level index follows the 80 byte header, a level count of zero
+        // asks for the mip chain to be generated
+        let mut levels = vec![];
+        for level in 0..level_count.max(1) {
+            let entry = 80 + level as usize * 24;
+            if entry + 24 > data.len() {
+                return Err(TextureError::Ktx2("truncated level index"));
+            }
+            let offset = u64_at(entry) as usize;
+            let length = u64_at(entry + 8) as usize;
+            let expected = ((width >> level).max(1) * (height >> level).max(1) * 4) as usize;
+            if length != expected || offset + length > data.len() {
+                return Err(TextureError::Ktx2("level size does not match"));
+            }
+            levels.push(data[offset..offset + length].to_vec());
+        }
+        Ok(Self {
+            width,
+            height,
+            format,
+            levels,
+        })
+    }
+}
+
+pub struct TextureAsset {
+    texture: Texture,
+    width: u32,
+    height: u32,
+    mip_level_count: u32,
+}
+
+impl TextureAsset {
+    // View of a single level, for inspecting the mip chain
+    pub fn mip_view(&self, level: u32) -> TextureView {
+        self.texture.create_view(&TextureViewDescriptor {
+            base_mip_level: level.min(self.mip_level_count - 1),
+            mip_level_count: NonZeroU32::new(1),
+            ..Default::default()
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn mip_level_count(&self) -> u32 {
+        self.mip_level_count
+    }
+}
+
+// Textures by path, shared by every material that uses them
+pub struct TextureRegistry {
+    textures: HashMap<String, Arc<TextureAsset>>,
+    mipmap_generator: Option<MipmapGenerator>,
+}
+
+impl TextureRegistry {
+    pub fn new() -> Self {
+        Self {
+            textures: HashMap::new(),
+            mipmap_generator: None,
+        }
+    }
+
+    pub fn init(&mut self, instance: &Instance) {
+        self.mipmap_generator = Some(MipmapGenerator::new(instance));
+    }
+
+    // Returns the registered texture if the path was already loaded
+    #[profiling::function]
+    pub fn load(
+        &mut self,
+        instance: &Instance,
+        path: &Path,
+    ) -> Result<Arc<TextureAsset>, TextureError> {
+        let name = path.to_string_lossy().to_string();
+        if let Some(texture) = self.textures.get(&name) {
+            return Ok(texture.clone());
+        }
+        let image = match path.extension().and_then(|x| x.to_str()) {
+            Some("png") => Image::load_png(path)?,
+            Some("ktx2") => Image::load_ktx2(path)?,
+            _ => return Err(TextureError::UnknownExtension),
+        };
+        let texture = Arc::new(self.create_texture(instance, &name, &image));
+        self.textures.insert(name, texture.clone());
+        Ok(texture)
+    }
+
+    pub fn get(&self, name: &str) -> Option<Arc<TextureAsset>> {
+        self.textures.get(name).cloned()
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &String> {
+        self.textures.keys()
+    }
+
+    fn create_texture(&mut self, instance: &Instance, name: &str, image: &Image) -> TextureAsset {
+        let device = instance.device();
+        let mip_level_count = 32 - image.width.max(image.height).leading_zeros();
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some(name),
+            size: Extent3d {
+                width: image.width,
+                height: image.height,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: image.format,
+            usage: TextureUsages::TEXTURE_BINDING
+                | TextureUsages::COPY_DST
+                | TextureUsages::RENDER_ATTACHMENT,
+        });
+        for (level, data) in image
+            .levels
+            .iter()
+            .enumerate()
+            .take(mip_level_count as usize)
+        {
+            let width = (image.width >> level).max(1);
+            let height = (image.height >> level).max(1);
+            instance.queue().write_texture(
+                ImageCopyTexture {
+                    texture: &texture,
+                    mip_level: level as u32,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                data,
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(width * 4),
+                    rows_per_image: None,
+                },
+                Extent3d {
+                    width,
+                    height,
+                    depth_or_array_layers: 1,
+                },
+            );
+        }
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("mipmap_encoder"),
+        });
+        self.mipmap_generator.as_mut().unwrap().generate(
+            instance,
+            &mut encoder,
+            &texture,
+            image.format,
+            image.levels.len() as u32,
+            mip_level_count,
+        );
+        instance.queue().submit(std::iter::once(encoder.finish()));
+        TextureAsset {
+            texture,
+            width: image.width,
+            height: image.height,
+            mip_level_count,
+        }
+    }
+}
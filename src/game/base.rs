@@ -1,4 +1,4 @@
-use euclid::{point2, Box2D, Point2D};
+use euclid::{point2, Box2D, Box3D, Point2D, Point3D, Vector3D};
 
 #[derive(Debug)]
 pub struct WorldSpace;
@@ -12,7 +12,7 @@ pub struct ScreenSpace;
 #[derive(Debug)]
 pub struct LocalSpace;
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq)]
 pub struct Region(Vec<Point2D<f32, WorldSpace>>);
 
 impl Region {
@@ -117,6 +117,17 @@ impl Region {
     pub fn points(&self) -> std::slice::Iter<Point2D<f32, WorldSpace>> {
         self.0.iter()
     }
+
+    // Shoelace formula, expects a simple polygon
+    pub fn area(&self) -> f32 {
+        let mut area = 0.0;
+        for i in 0..self.0.len() {
+            let a = self.0[i];
+            let b = self.0[(i + 1) % self.0.len()];
+            area += a.x * b.y - b.x * a.y;
+        }
+        area.abs() / 2.0
+    }
 }
 
 // Check if line ab intersects with cd
@@ -136,3 +147,107 @@ fn line_intersects(
     }
     (ccw(a, c, d) != ccw(b, c, d)) && (ccw(a, b, c) != ccw(a, b, d))
 }
+
+// Moller-Trumbore ray triangle intersection, returns the distance along the ray
+pub fn ray_intersects_triangle(
+    origin: &Point3D<f32, WorldSpace>,
+    direction: &Vector3D<f32, WorldSpace>,
+    triangle: &[Point3D<f32, WorldSpace>; 3],
+) -> Option<f32> {
+    let edge1 = triangle[1] - triangle[0];
+    let edge2 = triangle[2] - triangle[0];
+    let h = direction.cross(edge2);
+    let a = edge1.dot(h);
+    if a.abs() < f32::EPSILON {
+        return None;
+    }
+    let f = 1.0 / a;
+    let s = *origin - triangle[0];
+    let u = f * s.dot(h);
+    if !(0.0..=1.0).contains(&u) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let v = f * direction.dot(q);
+    if v < 0.0 || u + v > 1.0 {
+        return None;
+    }
+    let t = f * edge2.dot(q);
+    if t > f32::EPSILON {
+        Some(t)
+    } else {
+        None
+    }
+}
+
+// Closest point on a triangle, from Real-Time Collision Detection 5.1.5
+pub fn closest_point_on_triangle(
+    point: &Point3D<f32, WorldSpace>,
+    triangle: &[Point3D<f32, WorldSpace>; 3],
+) -> Point3D<f32, WorldSpace> {
+    let [a, b, c] = *triangle;
+    let ab = b - a;
+    let ac = c - a;
+    let ap = *point - a;
+    let d1 = ab.dot(ap);
+    let d2 = ac.dot(ap);
+    if d1 <= 0.0 && d2 <= 0.0 {
+        return a;
+    }
+    let bp = *point - b;
+    let d3 = ab.dot(bp);
+    let d4 = ac.dot(bp);
+    if d3 >= 0.0 && d4 <= d3 {
+        return b;
+    }
+    let vc = d1 * d4 - d3 * d2;
+    if vc <= 0.0 && d1 >= 0.0 && d3 <= 0.0 {
+        return a + ab * (d1 / (d1 - d3));
+    }
+    let cp = *point - c;
+    let d5 = ab.dot(cp);
+    let d6 = ac.dot(cp);
+    if d6 >= 0.0 && d5 <= d6 {
+        return c;
+    }
+    let vb = d5 * d2 - d1 * d6;
+    if vb <= 0.0 && d2 >= 0.0 && d6 <= 0.0 {
+        return a + ac * (d2 / (d2 - d6));
+    }
+    let va = d3 * d6 - d5 * d4;
+    if va <= 0.0 && d4 - d3 >= 0.0 && d5 - d6 >= 0.0 {
+        return b + (c - b) * ((d4 - d3) / ((d4 - d3) + (d5 - d6)));
+    }
+    let denom = 1.0 / (va + vb + vc);
+    a + ab * (vb * denom) + ac * (vc * denom)
+}
+
+// Slab test, returns the entry and exit distance along the ray
+pub fn ray_intersects_box(
+    origin: &Point3D<f32, WorldSpace>,
+    direction: &Vector3D<f32, WorldSpace>,
+    bounds: &Box3D<f32, WorldSpace>,
+) -> Option<(f32, f32)> {
+    let mut t_min = 0.0f32;
+    let mut t_max = f32::INFINITY;
+    for (o, d, min, max) in [
+        (origin.x, direction.x, bounds.min.x, bounds.max.x),
+        (origin.y, direction.y, bounds.min.y, bounds.max.y),
+        (origin.z, direction.z, bounds.min.z, bounds.max.z),
+    ] {
+        if d.abs() < f32::EPSILON {
+            if o < min || o > max {
+                return None;
+            }
+        } else {
+            let t1 = (min - o) / d;
+            let t2 = (max - o) / d;
+            t_min = t_min.max(t1.min(t2));
+            t_max = t_max.min(t1.max(t2));
+            if t_min > t_max {
+                return None;
+            }
+        }
+    }
+    Some((t_min, t_max))
+}
@@ -0,0 +1,190 @@
+use crate::gfx::{Instance, SamplerKey};
+use wgpu::*;
+
+// The glow is blurred at this fraction of the scene resolution
+const BLOOM_DOWNSAMPLE: u32 = 2;
+
+struct BloomPass {
+    pipeline: RenderPipeline,
+    bind_group: BindGroup,
+}
+
+// Blurs the emissive parts of the scene and adds them back. The scene target
+// carries the glow of each pixel in its alpha, pipelines that do not emit
+// light keep alpha out of their writes.
+pub struct Bloom {
+    output: Texture,
+    output_view: TextureView,
+    glow_views: [TextureView; 2],
+    // Extract into the first glow target, blur into the second one and back,
+    // then composite into the output
+    passes: Vec<BloomPass>,
+}
+
+impl Bloom {
+    // Needs to be created again when the scene target changes
+    pub fn new(
+        instance: &Instance,
+        scene_view: &TextureView,
+        size: Extent3d,
+        usage: TextureUsages,
+    ) -> Self {
+        let device = instance.device();
+        let create_target = |label, size, usage| {
+            device.create_texture(&TextureDescriptor {
+                label: Some(label),
+                size,
+                mip_level_count: 1,
+                sample_count: 1,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::RENDER_ATTACHMENT | usage,
+            })
+        };
+        let glow_size = Extent3d {
+            width: (size.width / BLOOM_DOWNSAMPLE).max(1),
+            height: (size.height / BLOOM_DOWNSAMPLE).max(1),
+            depth_or_array_layers: 1,
+        };
+        let glow_views = [
+            create_target(
+                "bloom_glow_target",
+                glow_size,
+                TextureUsages::TEXTURE_BINDING,
+            )
+            .create_view(&TextureViewDescriptor::default()),
+            create_target(
+                "bloom_glow_target",
+                glow_size,
+                TextureUsages::TEXTURE_BINDING,
+            )
+            .create_view(&TextureViewDescriptor::default()),
+        ];
+        let output = create_target("bloom_output_target", size, usage);
+        let output_view = output.create_view(&TextureViewDescriptor::default());
+
+        let texture_entry = |binding| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::FRAGMENT,
+            ty: BindingType::Texture {
+                view_dimension: TextureViewDimension::D2,
+                sample_type: TextureSampleType::Float { filterable: true },
+                multisampled: false,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("bloom_bind_group_layout"),
+            entries: &[
+                texture_entry(0),
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler {
+                        filtering: true,
+                        comparison: false,
+                    },
+                    count: None,
+                },
+                texture_entry(2),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("bloom_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/bloom.wgsl"));
+        let sampler = instance.sampler(&SamplerKey::linear());
+        // The glow texture is only read by the composite pass, the others
+        // bind their source in its place
+        let create_pass = |entry_point, source: &TextureView, bloom: &TextureView| BloomPass {
+            pipeline: device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("bloom_pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: &shader_module,
+                    entry_point: "main",
+                    buffers: &[],
+                },
+                primitive: PrimitiveState::default(),
+                depth_stencil: None,
+                multisample: MultisampleState::default(),
+                fragment: Some(FragmentState {
+                    module: &shader_module,
+                    entry_point,
+                    targets: &[ColorTargetState {
+                        format: TextureFormat::Rgba8Unorm,
+                        blend: None,
+                        write_mask: ColorWrites::ALL,
+                    }],
+                }),
+            }),
+            bind_group: device.create_bind_group(&BindGroupDescriptor {
+                label: Some("bloom_bind_group"),
+                layout: &bind_group_layout,
+                entries: &[
+                    BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::TextureView(source),
+                    },
+                    BindGroupEntry {
+                        binding: 1,
+                        resource: BindingResource::Sampler(&sampler),
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(bloom),
+                    },
+                ],
+            }),
+        };
+        let passes = vec![
+            create_pass("extract", scene_view, scene_view),
+            create_pass("blur_x", &glow_views[0], &glow_views[0]),
+            create_pass("blur_y", &glow_views[1], &glow_views[1]),
+            create_pass("composite", scene_view, &glow_views[0]),
+        ];
+        Self {
+            output,
+            output_view,
+            glow_views,
+            passes,
+        }
+    }
+
+    pub fn output(&self) -> &Texture {
+        &self.output
+    }
+
+    pub fn output_view(&self) -> &TextureView {
+        &self.output_view
+    }
+
+    // Call after the scene is rendered and resolved
+    pub fn render(&self, encoder: &mut CommandEncoder) {
+        let targets = [
+            &self.glow_views[0],
+            &self.glow_views[1],
+            &self.glow_views[0],
+            &self.output_view,
+        ];
+        for (pass, target) in self.passes.iter().zip(targets.iter()) {
+            let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("bloom_pass"),
+                color_attachments: &[RenderPassColorAttachment {
+                    view: target,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::BLACK),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: None,
+            });
+            rp.set_pipeline(&pass.pipeline);
+            rp.set_bind_group(0, &pass.bind_group, &[]);
+            rp.draw(0..3, 0..1);
+        }
+    }
+}
@@ -1,4 +1,5 @@
 use crate::game::base::{Region, ScreenSpace, ViewSpace, WorldSpace};
+use crate::game::frames::FRAMES_IN_FLIGHT;
 use crate::gfx::Instance;
 use euclid::{point2, vec3, Length, Point2D, Point3D, Transform3D, Vector3D};
 use std::mem::size_of;
@@ -6,14 +7,22 @@ use std::sync::Arc;
 use wgpu::util::StagingBelt;
 use wgpu::*;
 
+const MAX_PITCH: f32 = 1.5;
+
 pub struct Camera {
     position: Point3D<f32, WorldSpace>,
     direction: Vector3D<f32, WorldSpace>,
     fov: f32,
+    // Rotation around the view direction, only applied to the view
+    roll: f32,
     aspect_ratio: f32,
     near: f32,
     far: f32,
-    buffer: Option<Arc<Buffer>>,
+    // One per frame in flight, each frame writes the next so that the
+    // matrices of a frame still on the GPU are never overwritten
+    buffers: Option<Arc<Vec<Buffer>>>,
+    // Index of the buffer of the frame being recorded
+    frame: usize,
 }
 
 #[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
@@ -36,21 +45,29 @@ impl Camera {
             position,
             direction: direction.normalize(),
             fov,
+            roll: 0.0,
             aspect_ratio,
             near,
             far,
-            buffer: None,
+            buffers: None,
+            frame: 0,
         }
     }
 
     pub fn init(&mut self, instance: &Instance) {
         let device = instance.device();
-        self.buffer = Some(Arc::new(device.create_buffer(&BufferDescriptor {
-            label: Some("camera_uniform_buffer"),
-            size: size_of::<UniformData>() as u64,
-            usage: BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
-            mapped_at_creation: false,
-        })));
+        self.buffers = Some(Arc::new(
+            (0..FRAMES_IN_FLIGHT)
+                .map(|_| {
+                    device.create_buffer(&BufferDescriptor {
+                        label: Some("camera_uniform_buffer"),
+                        size: size_of::<UniformData>() as u64,
+                        usage: BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+                        mapped_at_creation: false,
+                    })
+                })
+                .collect(),
+        ));
     }
 
     pub fn position(&self) -> &Point3D<f32, WorldSpace> {
@@ -77,10 +94,78 @@ impl Camera {
         self.direction = direction.normalize();
     }
 
+    pub fn rotate(&mut self, yaw: f32, pitch: f32) {
+        let yaw = self.direction.y.atan2(self.direction.x) + yaw;
+        let pitch = (self.direction.z.clamp(-1.0, 1.0).asin() + pitch).clamp(-MAX_PITCH, MAX_PITCH);
+        self.direction = vec3(
+            yaw.cos() * pitch.cos(),
+            yaw.sin() * pitch.cos(),
+            pitch.sin(),
+        );
+    }
+
+    // Forward, right and up axes of the view with the roll applied
+    fn view_axes(
+        &self,
+    ) -> (
+        Vector3D<f32, WorldSpace>,
+        Vector3D<f32, WorldSpace>,
+        Vector3D<f32, WorldSpace>,
+    ) {
+        let f = self.direction.normalize();
+        let s = f.cross(self.up()).normalize();
+        let u = s.cross(f);
+        let (sin, cos) = self.roll.sin_cos();
+        (f, s * cos + u * sin, u * cos - s * sin)
+    }
+
+    // Direction of the ray going through a point in normalized device coordinates
+    pub fn ray_direction(&self, point: Point2D<f32, ScreenSpace>) -> Vector3D<f32, WorldSpace> {
+        let (f, s, u) = self.view_axes();
+        let tan_half_fov = (self.fov / 2.0).tan();
+        (f + s * (point.x * tan_half_fov * self.aspect_ratio) + u * (point.y * tan_half_fov))
+            .normalize()
+    }
+
+    pub fn far(&self) -> f32 {
+        self.far
+    }
+
+    pub fn set_far(&mut self, far: f32) {
+        self.far = far;
+    }
+
+    pub fn fov(&self) -> f32 {
+        self.fov
+    }
+
+    pub fn set_fov(&mut self, fov: f32) {
+        self.fov = fov;
+    }
+
+    pub fn set_aspect_ratio(&mut self, aspect_ratio: f32) {
+        self.aspect_ratio = aspect_ratio;
+    }
+
+    pub fn roll(&self) -> f32 {
+        self.roll
+    }
+
+    pub fn set_roll(&mut self, roll: f32) {
+        self.roll = roll;
+    }
+
     pub fn fov_x(&self) -> f32 {
         (self.aspect_ratio * (self.fov / 2.0).tan()).atan() * 2.0
     }
 
+    // Cosine of the angle between the direction and a corner of the view
+    pub fn corner_cosine(&self) -> f32 {
+        let y = (self.fov / 2.0).tan();
+        let x = self.aspect_ratio * y;
+        (1.0 + x * x + y * y).sqrt().recip()
+    }
+
     pub fn up(&self) -> Vector3D<f32, WorldSpace> {
         self.direction.cross(self.side()).normalize()
     }
@@ -101,9 +186,7 @@ impl Camera {
     }
 
     pub fn view_matrix(&self) -> Transform3D<f32, WorldSpace, ViewSpace> {
-        let f = self.direction.normalize();
-        let s = f.cross(self.up()).normalize();
-        let u = s.cross(f);
+        let (f, s, u) = self.view_axes();
         let eye = self.position.to_vector();
         Transform3D::new(
             s.x,
@@ -153,6 +236,42 @@ impl Camera {
         )
     }
 
+    // Projection of one tile of a square grid splitting the view, row 0 is
+    // the top row. Every tile is rendered at the full target size.
+    pub fn tile_projection_matrix(
+        &self,
+        column: u32,
+        row: u32,
+        tiles: u32,
+    ) -> Transform3D<f32, ViewSpace, ScreenSpace> {
+        let tiles = tiles as f32;
+        self.projection_matrix()
+            .then(&Transform3D::scale(tiles, tiles, 1.0))
+            .then(&Transform3D::translation(
+                tiles - 1.0 - 2.0 * column as f32,
+                2.0 * row as f32 + 1.0 - tiles,
+                0.0,
+            ))
+    }
+
+    // For renders outside of the frame, the staging belt is only used once
+    // per frame
+    pub fn write_buffer(
+        &mut self,
+        instance: &Instance,
+        projection_matrix: &Transform3D<f32, ViewSpace, ScreenSpace>,
+    ) {
+        self.frame = (self.frame + 1) % FRAMES_IN_FLIGHT;
+        instance.queue().write_buffer(
+            &self.buffers.as_ref().unwrap()[self.frame],
+            0,
+            bytemuck::bytes_of(&UniformData {
+                view_matrix: self.view_matrix().to_array(),
+                projection_matrix: projection_matrix.to_array(),
+            }),
+        );
+    }
+
     pub fn update_buffer(
         &mut self,
         instance: &Instance,
@@ -160,10 +279,11 @@ impl Camera {
         encoder: &mut CommandEncoder,
     ) {
         let device = instance.device();
+        self.frame = (self.frame + 1) % FRAMES_IN_FLIGHT;
         staging_belt
             .write_buffer(
                 encoder,
-                self.buffer.as_ref().unwrap(),
+                &self.buffers.as_ref().unwrap()[self.frame],
                 0,
                 BufferSize::new(size_of::<UniformData>() as _).unwrap(),
                 device,
@@ -174,37 +294,35 @@ impl Camera {
             }));
     }
 
-    pub fn buffer(&self) -> Arc<Buffer> {
-        self.buffer.as_ref().unwrap().clone()
+    pub fn buffers(&self) -> Arc<Vec<Buffer>> {
+        self.buffers.as_ref().unwrap().clone()
+    }
+
+    pub fn frame(&self) -> usize {
+        self.frame
     }
 
-    pub fn lod_regions(&self, distance: f32, growth_factor: f32, count: usize) -> Vec<Region> {
+    // Build one region per ring, distances are the outer edge of each ring
+    pub fn lod_regions(&self, distances: &[f32]) -> Vec<Region> {
         let mut regions = vec![];
         let y = if self.direction().z > 0.0 { -1.0 } else { 1.0 };
-        regions.push(Region::new([
-            self.point_from_distance(point2(-1.0, y), Length::new(distance))
-                .xy(),
-            self.point_from_distance(point2(1.0, y), Length::new(distance))
-                .xy(),
-            self.position().xy(),
-        ]));
-        let mut cummulate_growth = 1.0;
-        for i in 1..count {
-            let depth = growth_factor.powf(i as f32);
-            let (p1, p2, p3, p4) = (
-                self.point_from_distance(
-                    point2(-1.0, y),
-                    Length::new(distance * (cummulate_growth + depth)),
-                ),
-                self.point_from_distance(
-                    point2(1.0, y),
-                    Length::new(distance * (cummulate_growth + depth)),
-                ),
-                self.point_from_distance(point2(1.0, y), Length::new(distance * cummulate_growth)),
-                self.point_from_distance(point2(-1.0, y), Length::new(distance * cummulate_growth)),
+        let mut inner = None;
+        for &distance in distances {
+            let (p1, p2) = (
+                self.point_from_distance(point2(-1.0, y), Length::new(distance)),
+                self.point_from_distance(point2(1.0, y), Length::new(distance)),
             );
-            regions.push(Region::new([p1.xy(), p2.xy(), p3.xy(), p4.xy()]));
-            cummulate_growth += depth;
+            regions.push(match inner {
+                None => Region::new([p1.xy(), p2.xy(), self.position().xy()]),
+                Some(inner) => {
+                    let (p3, p4) = (
+                        self.point_from_distance(point2(1.0, y), Length::new(inner)),
+                        self.point_from_distance(point2(-1.0, y), Length::new(inner)),
+                    );
+                    Region::new([p1.xy(), p2.xy(), p3.xy(), p4.xy()])
+                }
+            });
+            inner = Some(distance);
         }
         regions
     }
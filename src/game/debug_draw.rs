@@ -0,0 +1,196 @@
+use crate::game::base::WorldSpace;
+use crate::gfx::Instance;
+use euclid::Point3D;
+use std::mem::size_of;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct DebugVertex {
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+// Immediate mode world space shapes drawn on top of the scene. Shapes are
+// queued during the frame and dropped once they are rendered.
+pub struct DebugDraw {
+    triangles: Vec<DebugVertex>,
+    lines: Vec<DebugVertex>,
+    triangle_pipeline: Option<RenderPipeline>,
+    line_pipeline: Option<RenderPipeline>,
+    // One for each camera buffer
+    bind_groups: Vec<BindGroup>,
+    triangle_buffer: Option<(Buffer, u32)>,
+    line_buffer: Option<(Buffer, u32)>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Self {
+            triangles: vec![],
+            lines: vec![],
+            triangle_pipeline: None,
+            line_pipeline: None,
+            bind_groups: vec![],
+            triangle_buffer: None,
+            line_buffer: None,
+        }
+    }
+
+    // Needs to be called again when the sample count changes
+    pub fn init(
+        &mut self,
+        instance: &Instance,
+        camera_buffers: &[Buffer],
+        target_format: TextureFormat,
+        sample_count: u32,
+    ) {
+        let device = instance.device();
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("debug_draw_bind_group_layout"),
+            entries: &[
+                // view + projection matrix
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        self.bind_groups = camera_buffers
+            .iter()
+            .map(|camera_buffer| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("debug_draw_bind_group"),
+                    layout: &bind_group_layout,
+                    entries: &[BindGroupEntry {
+                        binding: 0,
+                        resource: BindingResource::Buffer(BufferBinding {
+                            buffer: camera_buffer,
+                            offset: 0,
+                            size: None,
+                        }),
+                    }],
+                })
+            })
+            .collect();
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("debug_draw_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/debug.wgsl"));
+        let create_pipeline = |topology: PrimitiveTopology| {
+            device.create_render_pipeline(&RenderPipelineDescriptor {
+                label: Some("debug_draw_pipeline"),
+                layout: Some(&pipeline_layout),
+                vertex: VertexState {
+                    module: &shader_module,
+                    entry_point: "main",
+                    buffers: &[VertexBufferLayout {
+                        array_stride: size_of::<DebugVertex>() as u64,
+                        step_mode: VertexStepMode::Vertex,
+                        attributes: &vertex_attr_array![
+                            0 => Float32x4,
+                            1 => Float32x4,
+                        ],
+                    }],
+                },
+                primitive: PrimitiveState {
+                    topology,
+                    ..Default::default()
+                },
+                // Tested against the scene but never hidden by other shapes
+                depth_stencil: Some(DepthStencilState {
+                    format: TextureFormat::Depth32Float,
+                    depth_write_enabled: false,
+                    depth_compare: CompareFunction::LessEqual,
+                    stencil: StencilState::default(),
+                    bias: DepthBiasState::default(),
+                }),
+                multisample: MultisampleState {
+                    count: sample_count,
+                    ..Default::default()
+                },
+                fragment: Some(FragmentState {
+                    module: &shader_module,
+                    entry_point: "main",
+                    targets: &[ColorTargetState {
+                        format: target_format,
+                        blend: Some(BlendState::ALPHA_BLENDING),
+                        // The alpha of the scene is its glow
+                        write_mask: ColorWrites::COLOR,
+                    }],
+                }),
+            })
+        };
+        self.triangle_pipeline = Some(create_pipeline(PrimitiveTopology::TriangleList));
+        self.line_pipeline = Some(create_pipeline(PrimitiveTopology::LineList));
+    }
+
+    pub fn triangle(&mut self, triangle: &[Point3D<f32, WorldSpace>; 3], color: [f32; 4]) {
+        for p in triangle {
+            self.triangles.push(DebugVertex {
+                position: [p.x, p.y, p.z, 1.0],
+                color,
+            });
+        }
+    }
+
+    pub fn line(
+        &mut self,
+        p0: &Point3D<f32, WorldSpace>,
+        p1: &Point3D<f32, WorldSpace>,
+        color: [f32; 4],
+    ) {
+        for p in [p0, p1] {
+            self.lines.push(DebugVertex {
+                position: [p.x, p.y, p.z, 1.0],
+                color,
+            });
+        }
+    }
+
+    // Upload the shapes queued this frame, call before the render pass
+    pub fn prepare(&mut self, instance: &Instance) {
+        let device = instance.device();
+        let upload = |vertices: &mut Vec<DebugVertex>| {
+            if vertices.is_empty() {
+                return None;
+            }
+            let buffer = device.create_buffer_init(&BufferInitDescriptor {
+                label: Some("debug_draw_vertex_buffer"),
+                contents: bytemuck::cast_slice(vertices),
+                usage: BufferUsages::VERTEX,
+            });
+            let count = vertices.len() as u32;
+            vertices.clear();
+            Some((buffer, count))
+        };
+        self.triangle_buffer = upload(&mut self.triangles);
+        self.line_buffer = upload(&mut self.lines);
+    }
+
+    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, frame: usize) {
+        if self.bind_groups.is_empty() {
+            return;
+        }
+        rp.set_bind_group(0, &self.bind_groups[frame], &[]);
+        if let Some((buffer, count)) = &self.triangle_buffer {
+            rp.set_pipeline(self.triangle_pipeline.as_ref().unwrap());
+            rp.set_vertex_buffer(0, buffer.slice(..));
+            rp.draw(0..*count, 0..1);
+        }
+        if let Some((buffer, count)) = &self.line_buffer {
+            rp.set_pipeline(self.line_pipeline.as_ref().unwrap());
+            rp.set_vertex_buffer(0, buffer.slice(..));
+            rp.draw(0..*count, 0..1);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+use crate::gfx::Instance;
+use futures::task::SpawnExt;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use wgpu::util::StagingBelt;
+use wgpu::*;
+
+// Frames recorded while the GPU still works on the previous ones. The camera
+// keeps one uniform buffer per frame and everything binding it one bind group
+// per buffer.
+pub const FRAMES_IN_FLIGHT: usize = 2;
+const STAGING_BELT_CHUNK_SIZE: BufferAddress = 0x100;
+
+struct Frame {
+    staging_belt: StagingBelt,
+    // Set once the GPU finished the last submission of the frame
+    done: Arc<AtomicBool>,
+}
+
+// Each frame uploads through its own staging belt and waits for the GPU only
+// when it comes around again, so recording overlaps with the frames before
+pub struct Frames {
+    frames: Vec<Frame>,
+    current: usize,
+}
+
+impl Frames {
+    pub fn new() -> Self {
+        Self {
+            frames: (0..FRAMES_IN_FLIGHT)
+                .map(|_| Frame {
+                    staging_belt: StagingBelt::new(STAGING_BELT_CHUNK_SIZE),
+                    done: Arc::new(AtomicBool::new(true)),
+                })
+                .collect(),
+            current: 0,
+        }
+    }
+
+    // Moves to the next frame, blocks until the GPU is done with it
+    #[profiling::function]
+    pub fn begin(&mut self, instance: &Instance) {
+        self.current = (self.current + 1) % FRAMES_IN_FLIGHT;
+        let done = &self.frames[self.current].done;
+        while !done.load(Ordering::Acquire) {
+            instance.device().poll(Maintain::Poll);
+            std::thread::yield_now();
+        }
+    }
+
+    pub fn staging_belt(&mut self) -> &mut StagingBelt {
+        &mut self.frames[self.current].staging_belt
+    }
+
+    pub fn submit(&mut self, instance: &Instance, encoder: CommandEncoder) {
+        let frame = &mut self.frames[self.current];
+        frame.staging_belt.finish();
+        instance.queue().submit(std::iter::once(encoder.finish()));
+        frame.done.store(false, Ordering::Release);
+        let done = frame.done.clone();
+        let submitted = instance.queue().on_submitted_work_done();
+        let pool = instance.async_pool();
+        pool.spawn(async move {
+            submitted.await;
+            done.store(true, Ordering::Release);
+        })
+        .unwrap();
+        pool.spawn(frame.staging_belt.recall()).unwrap();
+    }
+}
@@ -0,0 +1,106 @@
+use crate::game::base::WorldSpace;
+use crate::game::camera::Camera;
+use crate::game::terrain::{
+    is_near_layer, ChunkCacheKey, Terrain, TerrainRegion, TerrainStats, MAX_PENDING_CHUNKS,
+};
+use euclid::{Box2D, Point3D, Vector3D};
+use std::time::{Duration, Instant};
+
+// The camera has to stand still this long with nothing queued
+const IDLE_DELAY: Duration = Duration::from_secs(1);
+// Fraction of the scheduler the idle chunks may take up, the rest is left
+// for the rings once the camera moves again
+const IDLE_PENDING_DIVISOR: usize = 4;
+
+// Waits for the streaming to catch up with a still camera before handing out
+// the work that only improves the quality, which is dropped to the back of
+// the task queue so that it never holds up the chunks in the rings. Idle
+// time goes to the chunks beyond the rings and to the impostors. There is no
+// ambient occlusion to bake, the meshes only carry the enclosure of their
+// voxels, and baking it would need its own vertex attribute and pass on both
+// meshing paths, so it is left out of the refinement.
+pub struct IdleRefinement {
+    camera: (Point3D<f32, WorldSpace>, Vector3D<f32, WorldSpace>),
+    still_since: Instant,
+    // Refinement was requested since the camera last moved
+    refining: bool,
+    // Cancelled once the camera moves, unless the rings requested them since
+    requested: Vec<ChunkCacheKey>,
+}
+
+impl IdleRefinement {
+    pub fn new() -> Self {
+        Self {
+            camera: (Point3D::zero(), Vector3D::zero()),
+            still_since: Instant::now(),
+            refining: false,
+            requested: vec![],
+        }
+    }
+
+    // Returns true once per stop of the camera, when the refinement should be
+    // requested
+    pub fn update(&mut self, camera: &Camera, terrain: &Terrain, stats: &TerrainStats) -> bool {
+        let pose = (*camera.position(), *camera.direction());
+        if pose != self.camera {
+            self.camera = pose;
+            self.still_since = Instant::now();
+            self.refining = false;
+            terrain.cancel_chunks(&std::mem::take(&mut self.requested));
+            return false;
+        }
+        let drained = stats.queued_tasks == 0 && stats.pending_chunks == 0;
+        if self.refining || !drained || self.still_since.elapsed() < IDLE_DELAY {
+            return false;
+        }
+        self.refining = true;
+        true
+    }
+
+    pub fn is_idle(&self) -> bool {
+        self.refining
+    }
+
+    // Queues the first keys behind every other task, as many as fit in the
+    // share of the scheduler that is still free
+    pub fn request(
+        &mut self,
+        terrain: &Terrain,
+        stats: &TerrainStats,
+        mut keys: Vec<ChunkCacheKey>,
+    ) {
+        let free = MAX_PENDING_CHUNKS.saturating_sub(stats.pending_chunks);
+        keys.truncate(free.min(MAX_PENDING_CHUNKS / IDLE_PENDING_DIVISOR));
+        log::info!("Idle, refining {} chunks", keys.len());
+        terrain.request_idle_chunks(&keys);
+        self.requested = keys;
+    }
+}
+
+// Chunks of each level between its ring and the same ring grown, the keys
+// the rings would need first once the camera moves on. The finest levels
+// come first and the ones too far along z from the camera are left out.
+pub fn refinement_keys(
+    terrain: &Terrain,
+    camera: &Camera,
+    rings: &[TerrainRegion],
+    grown_rings: &[TerrainRegion],
+) -> Vec<ChunkCacheKey> {
+    let layer = camera.position().z.floor() as i32;
+    let mut keys = grown_rings
+        .iter()
+        .flat_map(|grown| {
+            let ring = rings.iter().find(|x| x.level == grown.level);
+            terrain
+                .keys_in_region(&grown.region, grown.level)
+                .into_iter()
+                .filter(move |key| {
+                    let bounds = Box2D::new(key.bounds.min.xy(), key.bounds.max.xy()).to_f32();
+                    ring.map_or(true, |x| !x.region.intersects_box(&bounds))
+                        && is_near_layer(&key.bounds, key.level, layer)
+                })
+        })
+        .collect::<Vec<_>>();
+    keys.sort_by(|a, b| b.level.cmp(&a.level));
+    keys
+}
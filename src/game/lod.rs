@@ -0,0 +1,182 @@
+use crate::game::base::{Region, WorldSpace};
+use crate::game::camera::Camera;
+use crate::game::settings::LodSettings;
+use crate::game::terrain::{TerrainRegion, MAX_LEVEL, MIN_LEVEL};
+use euclid::{point2, Point2D};
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+// A chunk has 31x31 cells per layer and the surface usually crosses each
+// column once with two triangles per cell
+const ESTIMATED_TRIANGLES_PER_CHUNK: f32 = 2.0 * 31.0 * 31.0;
+// Height of the scene in pixels
+const SCREEN_HEIGHT: f32 = 480.0;
+// The farthest ring boundaries are pulled in by this factor for each step of
+// coarsening, see TriangleBudget
+const COARSEN_FACTOR: f32 = 0.7;
+const MAX_COARSENING: u32 = 8;
+// The meshes of the new rings take a while to replace the old ones so the
+// coarsening moves at most once per interval
+const COARSEN_INTERVAL: Duration = Duration::from_secs(2);
+// Under this fraction of the budget the rings grow back
+const RELAX_FRACTION: f32 = 0.8;
+
+// Pixels covered by one world unit at a distance of one
+fn pixels_per_unit(camera: &Camera) -> f32 {
+    SCREEN_HEIGHT / (2.0 * (camera.fov() / 2.0).tan())
+}
+
+// Size on screen in pixels of a geometric error at the distance
+pub fn screen_space_error(camera: &Camera, error: f32, distance: f32) -> f32 {
+    error * pixels_per_unit(camera) / distance.max(f32::EPSILON)
+}
+
+// Rings start from the finest level and double in depth so that chunks keep
+// roughly the same size on screen. The coarsest ring is stretched to the far
+// plane and rings stop early when the estimated triangle count goes over budget.
+// Regions are ordered from the outermost ring so finer levels are applied last.
+// With a max screen error, a ring ends where the coarser level is within the
+// error instead, using the geometric error of the level against its parent.
+// Each step of coarsening pulls the farthest boundaries in the most so that
+// the coarse levels take over from the far rings first.
+pub fn terrain_regions(
+    camera: &Camera,
+    settings: &LodSettings,
+    errors: &HashMap<u32, f32>,
+    coarsening: u32,
+) -> Vec<TerrainRegion> {
+    // Regions are built from the view frustum so area grows with distance squared
+    let unit_area = camera.lod_regions(&[1.0])[0].area().max(f32::EPSILON);
+    let far = camera.far();
+    let mut budget = settings.triangle_budget as f32;
+    let mut distances = vec![];
+    let mut levels = vec![];
+    let mut inner = 0.0f32;
+    let mut depth = settings.base_distance;
+    for level in (MIN_LEVEL..=MAX_LEVEL).rev() {
+        let chunk_size = (1 << (MAX_LEVEL - level)) as f32;
+        let triangles_per_area = ESTIMATED_TRIANGLES_PER_CHUNK / (chunk_size * chunk_size);
+        let error_distance = errors
+            .get(&level)
+            .filter(|_| settings.max_screen_error > 0.0)
+            .map(|error| error * pixels_per_unit(camera) / settings.max_screen_error);
+        let outer = match error_distance {
+            _ if level == MIN_LEVEL => far,
+            // The coarser level is already good enough here
+            Some(distance) if distance <= inner => continue,
+            Some(distance) => distance.min(far),
+            None => (inner + depth).min(far),
+        };
+        let max_outer = (inner * inner + budget / (triangles_per_area * unit_area)).sqrt();
+        let over_budget = outer > max_outer;
+        let outer = outer.min(max_outer);
+        if outer <= inner {
+            break;
+        }
+        budget -= triangles_per_area * unit_area * (outer * outer - inner * inner);
+        distances.push(outer);
+        levels.push(level);
+        if over_budget || outer >= far {
+            break;
+        }
+        inner = outer;
+        depth *= settings.growth_factor;
+    }
+    // The last ring always reaches its outer distance
+    let boundaries = distances.len().saturating_sub(1);
+    let mut previous = 0.0f32;
+    for (i, distance) in distances.iter_mut().take(boundaries).enumerate() {
+        let steps = (coarsening as usize + i + 1).saturating_sub(boundaries);
+        *distance = (*distance * COARSEN_FACTOR.powi(steps as i32)).max(previous);
+        previous = *distance;
+    }
+    camera
+        .lod_regions(&distances)
+        .into_iter()
+        .zip(levels)
+        .rev()
+        .map(|(region, level)| TerrainRegion { region, level })
+        .collect()
+}
+
+// Square rings around the center with the depths of the streamed rings, for
+// every direction at once. The coarsest ring is stretched to the radius and
+// regions are ordered from the outermost ring like terrain_regions.
+pub fn square_regions(
+    settings: &LodSettings,
+    center: &Point2D<f32, WorldSpace>,
+    radius: f32,
+) -> Vec<TerrainRegion> {
+    let mut regions = vec![];
+    let mut inner = 0.0f32;
+    let mut depth = settings.base_distance;
+    for level in (MIN_LEVEL..=MAX_LEVEL).rev() {
+        let outer = if level == MIN_LEVEL {
+            radius
+        } else {
+            (inner + depth).min(radius)
+        };
+        let region = Region::new(vec![
+            point2(center.x - outer, center.y - outer),
+            point2(center.x + outer, center.y - outer),
+            point2(center.x + outer, center.y + outer),
+            point2(center.x - outer, center.y + outer),
+        ]);
+        regions.push(TerrainRegion { region, level });
+        if outer >= radius {
+            break;
+        }
+        inner = outer;
+        depth *= settings.growth_factor;
+    }
+    regions.reverse();
+    regions
+}
+
+// Coarsens the farthest rings one step at a time while the resident
+// triangles are over the budget, and refines them back once well under it
+pub struct TriangleBudget {
+    coarsening: u32,
+    last_change: Instant,
+}
+
+impl TriangleBudget {
+    pub fn new() -> Self {
+        Self {
+            coarsening: 0,
+            last_change: Instant::now(),
+        }
+    }
+
+    pub fn coarsening(&self) -> u32 {
+        self.coarsening
+    }
+
+    // Returns true if the coarsening changed. A budget of zero is unlimited.
+    pub fn update(&mut self, resident_triangles: usize, budget: usize) -> bool {
+        if self.last_change.elapsed() < COARSEN_INTERVAL {
+            return false;
+        }
+        let resident = resident_triangles as f32;
+        let coarsening = if budget > 0 && resident > budget as f32 {
+            (self.coarsening + 1).min(MAX_COARSENING)
+        } else if budget == 0 || resident < budget as f32 * RELAX_FRACTION {
+            self.coarsening.saturating_sub(1)
+        } else {
+            self.coarsening
+        };
+        if coarsening == self.coarsening {
+            return false;
+        }
+        log::info!(
+            "{} resident triangles for a budget of {}, coarsening {} -> {}",
+            resident_triangles,
+            budget,
+            self.coarsening,
+            coarsening
+        );
+        self.coarsening = coarsening;
+        self.last_change = Instant::now();
+        true
+    }
+}
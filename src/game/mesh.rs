@@ -1,83 +1,121 @@
+use crate::game::terrain::EdgeId;
 use euclid::{Point3D, Vector3D};
+use rayon::prelude::*;
 use std::borrow::Borrow;
-use std::collections::HashMap;
 
 #[derive(Debug)]
 pub struct Triangle<T> {
     pub position: [Point3D<f32, T>; 3],
-    pub id: [u64; 3],
+    pub id: [EdgeId; 3],
 }
 
 #[derive(Debug)]
 pub struct Mesh<T> {
-    ids: Vec<u64>,
+    ids: Vec<EdgeId>,
     vertex: Vec<Point3D<f32, T>>,
     faces: Vec<[usize; 3]>,
     normals: Option<Vec<Vector3D<f32, T>>>,
+    // Baked by the meshing passes, one for each vertex
+    colors: Option<Vec<[f32; 3]>>,
 }
 
-impl<T> Mesh<T> {
+impl<T> Mesh<T>
+where
+    T: Send + Sync,
+{
+    // Vertices are deduplicated by sorting every triangle corner by id in
+    // parallel instead of going through a hash map one corner at a time
+    #[profiling::function]
     pub fn from_triangles<I>(triangles: I) -> Self
     where
         I: IntoIterator,
-        I::Item: Borrow<Triangle<T>>,
+        I::Item: Borrow<Triangle<T>> + Sync,
     {
-        let mut index = 0;
-        let mut id_to_index = HashMap::new();
+        let triangles = triangles.into_iter().collect::<Vec<_>>();
+        let mut corners = (0..triangles.len() * 3)
+            .into_par_iter()
+            .map(|i| (triangles[i / 3].borrow().id[i % 3], i))
+            .collect::<Vec<_>>();
+        corners.par_sort_unstable_by_key(|(id, _)| *id);
         let mut vertex = vec![];
-        let mut faces = vec![];
         let mut ids = vec![];
-        for triangle in triangles.into_iter() {
-            let triangle = triangle.borrow();
-            let mut face_indices = triangle.id.iter().enumerate().map(|(i, &x)| {
-                *id_to_index.entry(x).or_insert_with(|| {
-                    let new_index = index;
-                    index += 1;
-                    vertex.push(triangle.position[i]);
-                    ids.push(triangle.id[i]);
-                    debug_assert_eq!(index, vertex.len());
-                    new_index
-                })
-            });
-            faces.push([
-                face_indices.next().unwrap(),
-                face_indices.next().unwrap(),
-                face_indices.next().unwrap(),
-            ]);
+        let mut corner_to_index = vec![0; corners.len()];
+        for (id, corner) in corners {
+            if ids.last() != Some(&id) {
+                vertex.push(triangles[corner / 3].borrow().position[corner % 3]);
+                ids.push(id);
+            }
+            corner_to_index[corner] = ids.len() - 1;
         }
+        debug_assert_eq!(ids.len(), vertex.len());
+        let faces = corner_to_index
+            .par_chunks(3)
+            .map(|x| [x[0], x[1], x[2]])
+            .collect();
         Mesh {
             ids,
             vertex,
             faces,
             normals: None,
+            colors: None,
         }
     }
 
-    pub fn calculate_normals(&mut self) {
-        let mut normals = vec![];
-        let mut per_face_normals: HashMap<usize, Vec<_>> = HashMap::new();
-        for face in &self.faces {
-            let p0 = self.vertex[face[0]];
-            let p1 = self.vertex[face[1]];
-            let p2 = self.vertex[face[2]];
-            let normal = (p1 - p0).cross(p0 - p2);
-            for i in face.iter().take(3) {
-                per_face_normals.entry(*i).or_default().push(normal);
-            }
+    // Vertices already welded on the GPU, see weld.wgsl
+    pub fn from_indexed(
+        ids: Vec<EdgeId>,
+        vertex: Vec<Point3D<f32, T>>,
+        faces: Vec<[usize; 3]>,
+    ) -> Self {
+        debug_assert_eq!(ids.len(), vertex.len());
+        Mesh {
+            ids,
+            vertex,
+            faces,
+            normals: None,
+            colors: None,
         }
-        for i in 0..self.vertex.len() {
-            normals.push(
-                per_face_normals
-                    .get(&i)
-                    .unwrap()
-                    .iter()
-                    .fold(Vector3D::zero(), |acc, x| acc + x)
-                    .normalize(),
+    }
+
+    pub fn with_colors(mut self, colors: Vec<[f32; 3]>) -> Self {
+        debug_assert_eq!(colors.len(), self.vertex.len());
+        self.colors = Some(colors);
+        self
+    }
+
+    #[profiling::function]
+    pub fn calculate_normals(&mut self) {
+        let vertex = &self.vertex;
+        let vertex_count = vertex.len();
+        let mut normals = self
+            .faces
+            .par_iter()
+            .fold(
+                || vec![Vector3D::zero(); vertex_count],
+                |mut normals, face| {
+                    let p0 = vertex[face[0]];
+                    let p1 = vertex[face[1]];
+                    let p2 = vertex[face[2]];
+                    let normal = (p1 - p0).cross(p0 - p2);
+                    for i in face.iter() {
+                        normals[*i] += normal;
+                    }
+                    normals
+                },
+            )
+            .reduce(
+                || vec![Vector3D::zero(); vertex_count],
+                |mut a, b| {
+                    a.iter_mut().zip(b).for_each(|(a, b)| *a += b);
+                    a
+                },
             );
-        }
+        normals.par_iter_mut().for_each(|x| *x = x.normalize());
         self.normals = Some(normals);
     }
+}
 
+impl<T> Mesh<T> {
     pub fn vertex(&self) -> &[Point3D<f32, T>] {
         &self.vertex
     }
@@ -90,7 +128,12 @@ impl<T> Mesh<T> {
         self.normals.as_ref().unwrap()
     }
 
-    pub fn ids(&self) -> &[u64] {
+    pub fn ids(&self) -> &[EdgeId] {
         &self.ids
     }
+
+    // None for meshes built on the CPU
+    pub fn colors(&self) -> Option<&[[f32; 3]]> {
+        self.colors.as_deref()
+    }
 }
@@ -1,63 +1,275 @@
+mod ambience;
+mod asset;
 mod base;
-mod camera;
+mod bloom;
+pub mod camera;
+mod debug_draw;
+mod frames;
+mod idle;
+pub mod lod;
 mod mesh;
+mod normal_map;
 mod object;
-mod terrain;
+mod outline;
+mod pregen;
+mod quality;
+pub mod random;
+mod screenshot;
+pub mod settings;
+mod sky;
+pub mod terrain;
 mod ui;
+mod warmup;
+mod wind;
 
-use crate::gfx::Instance;
+use crate::gfx::{Instance, SamplerKey};
+use ambience::Ambience;
+use asset::TextureRegistry;
 use base::Region;
+use bloom::Bloom;
 use camera::Camera;
-use euclid::{point3, vec3, Rotation2D, Scale};
-use futures::task::SpawnExt;
+use debug_draw::DebugDraw;
+use euclid::{point2, point3, size2, vec2, vec3, Box3D, Point3D, Rotation2D, Scale, Vector3D};
+use frames::Frames;
+use idle::IdleRefinement;
+use lod::TriangleBudget;
+use object::{
+    cluster_key, ClusterKey, ImpostorAtlas, Object, PointLight, RockLibrary, CLUSTER_SIZE,
+};
+use outline::Outline;
+pub use pregen::{generate_chunks, parse_radius, pregenerate};
+use quality::QualityController;
+use random::{RandomStreams, Stream};
+use screenshot::Screenshot;
+use settings::{Settings, SettingsFile};
+use sky::Sky;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::{Hash, Hasher};
 use std::sync::Arc;
 use std::time::Duration;
-use terrain::{Terrain, TerrainRegion};
-use ui::{ImguiRenderer, TerrainVisualizer};
-use wgpu::util::StagingBelt;
+use terrain::{
+    dominant_biome, CaveSettings, ChunkCacheKey, DomainWarp, ErosionSettings, MagmaSettings,
+    PreviewWorldParams, RaycastHit, StitchStatus, TaskFocus, Terrain, TerrainEdit, TerrainOverlay,
+    TerrainRegion, TerrainStats, BIOME_NAMES, CAVE_ENCLOSURE, MESHING_ALGORITHMS, MIN_LEVEL,
+    NOISE_ALGORITHMS,
+};
+use ui::{
+    draw_loading_screen, draw_stats_overlay, CacheWindow, EditWindow, ErrorWindow, GeneratorWindow,
+    ImguiRenderer, LightWindow, MeasureWindow, NormalMapWindow, ObjectWindow, PhotoWindow,
+    PreviewWorldWindow, ProfileWindow, SettingsResponse, SettingsWindow, TerrainVisualizer,
+    TextureWindow, PREVIEW_TEXTURE_ID,
+};
+use warmup::Warmup;
 use wgpu::*;
-use winit::{event::Event, window::Window};
+use wind::Wind;
+use winit::{
+    event::{DeviceEvent, ElementState, Event, KeyboardInput, WindowEvent},
+    window::Window,
+};
+
+const PICK_DISTANCE: f32 = 100.0;
+// Height above the ground the camera is lifted to when it spawns inside it
+const SPAWN_CLEARANCE: f32 = 0.05;
+// The regions follow the camera once it moved or turned past these
+const REGION_UPDATE_DISTANCE: f32 = 0.01;
+const REGION_UPDATE_ANGLE: f32 = 0.005;
+const CROSSHAIR_SIZE: f32 = 8.0;
+const BRUSH_STRENGTH: f32 = 0.5;
+const DIFF_ADDED_COLOR: [f32; 4] = [0.0, 1.0, 0.0, 0.5];
+const DIFF_REMOVED_COLOR: [f32; 4] = [1.0, 0.0, 0.0, 0.5];
+const DIFF_BOUNDS_COLOR: [f32; 4] = [1.0, 1.0, 0.0, 1.0];
+const MEASURE_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+const PROFILE_COLOR: [f32; 4] = [0.0, 1.0, 1.0, 1.0];
+const OBJECT_AXIS_LENGTH: f32 = 0.05;
+// Distance between rock lod levels
+const ROCK_LOD_DISTANCE: f32 = 1.0;
+// Clusters further than this are drawn as impostors
+const IMPOSTOR_DISTANCE: f32 = 6.0;
+// While idle the clusters past this fraction of the impostor distance are
+// baked before they turn into impostors
+const IDLE_BAKE_FRACTION: f32 = 0.75;
+// The rings the refinement prepares chunks for are this many times deeper
+const IDLE_RING_GROWTH: f32 = 1.5;
+const IMPOSTOR_LOD: usize = 1;
+const OBJECT_AXIS_COLORS: [[f32; 4]; 3] = [
+    [1.0, 0.0, 0.0, 1.0],
+    [0.0, 1.0, 0.0, 1.0],
+    [0.0, 0.0, 1.0, 1.0],
+];
+// Chunk outlines float this far over the surface to win the depth test
+const CHUNK_OUTLINE_LIFT: f32 = 0.002;
+// From the coarsest level, wrapping around past the last color
+const CHUNK_LEVEL_COLORS: [[f32; 4]; 7] = [
+    [1.0, 0.2, 0.2, 1.0],
+    [1.0, 0.6, 0.1, 1.0],
+    [1.0, 1.0, 0.2, 1.0],
+    [0.3, 1.0, 0.3, 1.0],
+    [0.2, 0.9, 1.0, 1.0],
+    [0.3, 0.4, 1.0, 1.0],
+    [0.9, 0.3, 1.0, 1.0],
+];
+const MATCHED_SIDE_COLOR: [f32; 4] = [0.7, 0.7, 0.7, 1.0];
+const STITCHED_SIDE_COLOR: [f32; 4] = [0.2, 1.0, 0.2, 1.0];
+const PENDING_SIDE_COLOR: [f32; 4] = [1.0, 0.1, 0.1, 1.0];
+const SKIRTED_SIDE_COLOR: [f32; 4] = [0.2, 0.5, 1.0, 1.0];
+const CRACK_COLOR: [f32; 4] = [1.0, 0.0, 1.0, 1.0];
+// Height of the marker over each gap of a crack
+const CRACK_MARKER_HEIGHT: f32 = 0.01;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+enum InteractionMode {
+    // Free cursor, interactions target the point under the mouse
+    Editor,
+    // Grabbed cursor with mouse look, interactions target the screen center
+    Crosshair,
+}
 
 pub struct Game {
     instance: Arc<Instance>,
     imgui_renderer: ImguiRenderer,
     terrain_visualizer: TerrainVisualizer,
+    settings_window: SettingsWindow,
+    normal_map_window: NormalMapWindow,
+    edit_window: EditWindow,
+    generator_window: GeneratorWindow,
+    texture_window: TextureWindow,
+    textures: TextureRegistry,
+    object_window: ObjectWindow,
+    light_window: LightWindow,
+    measure_window: MeasureWindow,
+    profile_window: ProfileWindow,
+    error_window: ErrorWindow,
+    preview_world_window: PreviewWorldWindow,
+    cache_window: CacheWindow,
+    photo_window: PhotoWindow,
+    // Super-resolution scale and tiles per side of the screenshot taken
+    // after the next frame
+    screenshot: Option<(u32, u32)>,
+    objects: Vec<Object>,
+    lights: Vec<PointLight>,
+    rocks: RockLibrary,
+    impostors: ImpostorAtlas,
     camera: Camera,
     terrain: Terrain,
+    debug_draw: DebugDraw,
+    sky: Sky,
     render_target_view: Option<TextureView>,
+    msaa_target_view: Option<TextureView>,
     depth_stencil_view: Option<TextureView>,
-    staging_belt: StagingBelt,
+    bloom: Option<Bloom>,
+    // Only with the stylized shading
+    outline: Option<Outline>,
+    frames: Frames,
     regions: Vec<Region>,
+    terrain_regions: Vec<TerrainRegion>,
+    // Camera position and direction the regions were built for
+    regions_camera: (
+        Point3D<f32, base::WorldSpace>,
+        Vector3D<f32, base::WorldSpace>,
+    ),
     isolevel: f32,
+    domain_warp: DomainWarp,
+    caves: CaveSettings,
+    magma: MagmaSettings,
+    erosion: ErosionSettings,
+    world_seed: i32,
+    random: RandomStreams,
+    wind: Wind,
+    ambience: Ambience,
+    settings: Settings,
+    applied_settings: Settings,
+    settings_file: SettingsFile,
+    sample_count: u32,
+    interaction_mode: InteractionMode,
+    mouse_delta: (f64, f64),
+    brush_radius: f32,
+    quality: QualityController,
+    triangle_budget: TriangleBudget,
+    idle: IdleRefinement,
+    // Coarse chunks around the spawn point generated before the player gets
+    // control of a new world
+    warmup: Option<Warmup>,
 }
 
 impl Game {
     pub fn new(instance: Arc<Instance>) -> Self {
+        let settings_file = SettingsFile::new(settings::CONFIG_PATH);
+        let settings = settings_file.load();
         let camera = Camera::new(
             point3(0.0, 0.0, 0.3),
             vec3(1.0, 0.0, 0.0),
             std::f32::consts::PI / 4.0,
             640.0 / 480.0,
             0.001,
-            9000.0,
+            settings.graphics.draw_distance,
         );
-        let regions = camera.lod_regions(1.0, 2.0, 3);
+        let terrain_regions = lod::terrain_regions(&camera, &settings.lod, &HashMap::new(), 0);
+        let regions = terrain_regions.iter().map(|x| x.region.clone()).collect();
+        let regions_camera = (*camera.position(), *camera.direction());
+        let terrain = Terrain::new(&settings.streaming);
         Self {
             instance,
             imgui_renderer: ImguiRenderer::new(),
             camera,
-            terrain: Terrain::new(),
+            error_window: ErrorWindow::new(&terrain),
+            preview_world_window: PreviewWorldWindow::new(),
+            cache_window: CacheWindow::new(),
+            terrain,
+            debug_draw: DebugDraw::new(),
+            sky: Sky::new(),
             terrain_visualizer: TerrainVisualizer::new(Scale::new(32.0)),
+            settings_window: SettingsWindow::new(),
+            normal_map_window: NormalMapWindow::new(),
+            edit_window: EditWindow::new(),
+            generator_window: GeneratorWindow::new(),
+            texture_window: TextureWindow::new(),
+            textures: TextureRegistry::new(),
+            object_window: ObjectWindow::new(),
+            light_window: LightWindow::new(),
+            measure_window: MeasureWindow::new(),
+            profile_window: ProfileWindow::new(),
+            photo_window: PhotoWindow::new(),
+            screenshot: None,
+            objects: vec![],
+            lights: vec![],
+            rocks: RockLibrary::new(),
+            impostors: ImpostorAtlas::new(),
             render_target_view: None,
+            msaa_target_view: None,
             depth_stencil_view: None,
-            staging_belt: StagingBelt::new(0x100),
+            bloom: None,
+            outline: None,
+            frames: Frames::new(),
             regions,
+            terrain_regions,
+            regions_camera,
             isolevel: 0.5,
+            domain_warp: DomainWarp::default(),
+            caves: CaveSettings::default(),
+            magma: MagmaSettings::default(),
+            erosion: ErosionSettings::default(),
+            world_seed: 0,
+            random: RandomStreams::new(0),
+            wind: Wind::new(&RandomStreams::new(0)),
+            ambience: Ambience::new(),
+            sample_count: settings.graphics.sample_count(),
+            applied_settings: settings.clone(),
+            settings,
+            settings_file,
+            interaction_mode: InteractionMode::Editor,
+            mouse_delta: (0.0, 0.0),
+            brush_radius: 0.1,
+            quality: QualityController::new(),
+            triangle_budget: TriangleBudget::new(),
+            idle: IdleRefinement::new(),
+            warmup: None,
         }
     }
 
     #[profiling::function]
     pub fn render(&mut self, _window: &Window) {
+        self.frames.begin(&self.instance);
         let target = self.instance.surface().get_current_frame().unwrap();
         let view = target
             .output
@@ -68,9 +280,13 @@ impl Game {
             .device()
             .create_command_encoder(&CommandEncoderDescriptor { label: None });
         self.imgui_renderer
-            .update_buffer(&self.instance, &mut self.staging_belt, &mut encoder);
+            .update_buffer(&self.instance, self.frames.staging_belt(), &mut encoder);
         self.camera
-            .update_buffer(&self.instance, &mut self.staging_belt, &mut encoder);
+            .update_buffer(&self.instance, self.frames.staging_belt(), &mut encoder);
+        self.impostors.bake(&self.instance, &mut encoder);
+        self.impostors.prepare(&self.instance);
+        self.debug_draw.prepare(&self.instance);
+        self.sky.prepare(&self.instance);
         {
             let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
                 label: None,
@@ -87,78 +303,285 @@ impl Game {
             self.imgui_renderer.render(&mut rp);
         }
         {
-            let x = self.terrain.render(&self.regions);
-            let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: None,
-                color_attachments: &[RenderPassColorAttachment {
-                    view: self.render_target_view.as_ref().unwrap(),
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color {
-                            r: 0.0,
-                            g: 0.0,
-                            b: 0.0,
-                            a: 1.0,
-                        }),
-                        store: true,
-                    },
-                }],
-                depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
-                    view: self.depth_stencil_view.as_ref().unwrap(),
-                    depth_ops: Some(Operations {
-                        load: LoadOp::Clear(1.0),
-                        store: true,
+            let (view, resolve_target) = match &self.msaa_target_view {
+                Some(msaa_target_view) => (msaa_target_view, self.render_target_view.as_ref()),
+                None => (self.render_target_view.as_ref().unwrap(), None),
+            };
+            self.render_scene(
+                &mut encoder,
+                view,
+                resolve_target,
+                self.depth_stencil_view.as_ref().unwrap(),
+            );
+            if let Some(outline) = &self.outline {
+                outline.render(&mut encoder, view, self.camera.frame());
+            }
+            self.bloom.as_ref().unwrap().render(&mut encoder);
+        }
+        self.frames.submit(&self.instance, encoder);
+        if let Some((scale, tiles)) = self.screenshot.take() {
+            self.save_screenshot(scale, tiles);
+        }
+    }
+
+    fn render_scene(
+        &self,
+        encoder: &mut CommandEncoder,
+        view: &TextureView,
+        resolve_target: Option<&TextureView>,
+        depth_stencil_view: &TextureView,
+    ) {
+        let frame = self.camera.frame();
+        let x = self.terrain.render(&self.regions, frame);
+        let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: None,
+            color_attachments: &[RenderPassColorAttachment {
+                view,
+                resolve_target,
+                ops: wgpu::Operations {
+                    // Nothing glows until something is drawn
+                    load: wgpu::LoadOp::Clear(wgpu::Color {
+                        r: 0.0,
+                        g: 0.0,
+                        b: 0.0,
+                        a: 0.0,
                     }),
-                    stencil_ops: None,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: Some(wgpu::RenderPassDepthStencilAttachment {
+                view: depth_stencil_view,
+                depth_ops: Some(Operations {
+                    load: LoadOp::Clear(1.0),
+                    store: true,
                 }),
-            });
-            rp.execute_bundles(x.iter().map(|x| x.into()));
-        }
-        self.staging_belt.finish();
-        let command_buffer = encoder.finish();
-        self.instance
-            .queue()
-            .submit(std::iter::once(command_buffer));
-        self.instance
-            .async_pool()
-            .spawn(self.staging_belt.recall())
-            .unwrap();
+                stencil_ops: None,
+            }),
+        });
+        self.sky.render(&mut rp, frame);
+        rp.execute_bundles(x.iter().map(|x| x.into()));
+        self.impostors.render(&mut rp, frame);
+        self.debug_draw.render(&mut rp, frame);
+    }
+
+    // Renders the last frame again into an offscreen target, the size does
+    // not depend on the render scale or the quality level. With more than one
+    // tile per side every tile is rendered with its own projection and the
+    // tiles are stitched together.
+    #[profiling::function]
+    fn save_screenshot(&mut self, scale: u32, tiles: u32) {
+        let size = Extent3d {
+            width: 640 * scale,
+            height: 480 * scale,
+            depth_or_array_layers: 1,
+        };
+        let (render_target, msaa_target_view, depth_stencil_view) =
+            self.create_scene_targets(size, TextureUsages::TEXTURE_BINDING);
+        let render_target_view = render_target.create_view(&TextureViewDescriptor::default());
+        let bloom = Bloom::new(
+            &self.instance,
+            &render_target_view,
+            size,
+            TextureUsages::COPY_SRC,
+        );
+        let outline = self.create_outline(&depth_stencil_view);
+        let (view, resolve_target) = match &msaa_target_view {
+            Some(msaa_target_view) => (msaa_target_view, Some(&render_target_view)),
+            None => (&render_target_view, None),
+        };
+        let mut screenshot = Screenshot::new(size2(size.width * tiles, size.height * tiles));
+        for row in 0..tiles {
+            for column in 0..tiles {
+                let projection_matrix = self.camera.tile_projection_matrix(column, row, tiles);
+                self.camera.write_buffer(&self.instance, &projection_matrix);
+                let mut encoder =
+                    self.instance
+                        .device()
+                        .create_command_encoder(&CommandEncoderDescriptor {
+                            label: Some("screenshot_encoder"),
+                        });
+                self.render_scene(&mut encoder, view, resolve_target, &depth_stencil_view);
+                if let Some(outline) = &outline {
+                    outline.render(&mut encoder, view, self.camera.frame());
+                }
+                bloom.render(&mut encoder);
+                let tile = Screenshot::capture(
+                    &self.instance,
+                    encoder,
+                    bloom.output(),
+                    size2(size.width, size.height),
+                );
+                screenshot.paste(&tile, column * size.width, row * size.height);
+            }
+        }
+        self.camera
+            .write_buffer(&self.instance, &self.camera.projection_matrix());
+        let path = self.photo_window.path().to_path_buf();
+        let status = match screenshot.save_png(&path) {
+            Ok(_) => format!(
+                "Saved {}x{} to {}",
+                screenshot.size().width,
+                screenshot.size().height,
+                path.display()
+            ),
+            Err(e) => format!("Failed to save: {}", e),
+        };
+        self.photo_window.set_status(status);
     }
 
     #[profiling::function]
     pub fn step(&mut self, window: &Window, elapsed_time: Duration) {
+        let warming_up = match self.warmup.as_mut() {
+            Some(warmup) => !warmup.update(&self.terrain),
+            None => false,
+        };
+        if warming_up {
+            let warmup = self.warmup.as_ref().unwrap();
+            self.imgui_renderer
+                .draw(window, |ui| draw_loading_screen(ui, warmup));
+            self.mouse_delta = (0.0, 0.0);
+            profiling::finish_frame!();
+            return;
+        }
+        if self.warmup.take().is_some() {
+            self.lift_camera_out_of_ground();
+        }
         let mut moved = false;
-        let terrain_visualizer = &self.terrain_visualizer;
+        let terrain_visualizer = &mut self.terrain_visualizer;
         let camera = &mut self.camera;
         let terrain = &self.terrain;
-        let regions = &mut self.regions;
+        let regions = &self.regions;
         let mut isolevel_changed = false;
+        let mut isolevel_preview = None;
         let mut isolevel = &mut self.isolevel;
+        let domain_warp = &mut self.domain_warp;
+        let mut domain_warp_changed = false;
+        let caves = &mut self.caves;
+        let mut caves_changed = false;
+        let magma = &mut self.magma;
+        let erosion = &mut self.erosion;
+        let mut erosion_changed = false;
+        let world_seed = &mut self.world_seed;
+        let random = &self.random;
+        let mut world_seed_changed = false;
+        let settings = &mut self.settings;
+        let settings_window = &mut self.settings_window;
+        let normal_map_window = &mut self.normal_map_window;
+        let edit_window = &mut self.edit_window;
+        let generator_window = &mut self.generator_window;
+        let texture_window = &mut self.texture_window;
+        let textures = &mut self.textures;
+        let instance = &self.instance;
+        let mut texture_preview = None;
+        let object_window = &mut self.object_window;
+        let objects = &mut self.objects;
+        let light_window = &mut self.light_window;
+        let lights = &mut self.lights;
+        let measure_window = &mut self.measure_window;
+        let mut measure_point = None;
+        let profile_window = &mut self.profile_window;
+        let error_window = &mut self.error_window;
+        let preview_world_window = &mut self.preview_world_window;
+        let cache_window = &mut self.cache_window;
+        let preview_world = terrain.preview_world();
+        let mut preview_world_applied = false;
+        let mut profile_point = None;
+        let photo_window = &mut self.photo_window;
+        let photo_active = photo_window.is_active();
+        let mut screenshot = None;
+        let mut settings_response = SettingsResponse::default();
+        let interaction_mode = self.interaction_mode;
+        let mouse_delta = std::mem::take(&mut self.mouse_delta);
+        let brush_radius = &mut self.brush_radius;
+        let mut brush: Option<(RaycastHit, f32)> = None;
+        let mut diff_selection: Option<Option<ChunkCacheKey>> = None;
+        let mut placement: Option<RaycastHit> = None;
+        let quality = &self.quality;
+        let terrain_stats = terrain.stats();
+        let soundscape = self.ambience.current();
+        let sky = &mut self.sky;
         self.imgui_renderer.draw(window, |ui| {
+            if !photo_active {
+                draw_stats_overlay(
+                    ui,
+                    elapsed_time,
+                    &terrain_stats,
+                    Some(quality).filter(|_| settings.graphics.auto_quality),
+                );
+            }
+            let input = &settings.input;
+            if mouse_delta != (0.0, 0.0) {
+                camera.rotate(
+                    -mouse_delta.0 as f32 * input.mouse_sensitivity,
+                    -mouse_delta.1 as f32 * input.mouse_sensitivity,
+                );
+                moved = true;
+            }
             let mut direction = camera.direction().xy();
             let mut speed = 0.0;
-            if ui.is_key_down(imgui::Key::UpArrow) {
+            let keys_down = &ui.io().keys_down;
+            if keys_down[input.move_forward as usize] {
                 speed += 1.0 * elapsed_time.as_secs_f32();
                 moved = true;
             }
-            if ui.is_key_down(imgui::Key::DownArrow) {
+            if keys_down[input.move_backward as usize] {
                 speed -= 1.0 * elapsed_time.as_secs_f32();
                 moved = true;
             }
-            if ui.is_key_down(imgui::Key::LeftArrow) {
+            if keys_down[input.turn_left as usize] {
                 direction = Rotation2D::radians(2.0 * elapsed_time.as_secs_f32())
                     .transform_vector(direction);
                 moved = true;
             }
-            if ui.is_key_down(imgui::Key::RightArrow) {
+            if keys_down[input.turn_right as usize] {
                 direction = Rotation2D::radians(-2.0 * elapsed_time.as_secs_f32())
                     .transform_vector(direction);
                 moved = true;
             }
             if moved {
-                camera.move_by(&(direction * speed).extend(0.0));
-                camera.look_in_direction(&direction.extend(-0.1));
-                std::mem::swap(regions, &mut camera.lod_regions(1.0, 2.0, 3));
+                // Mouse look controls the pitch in crosshair mode
+                let pitch = match interaction_mode {
+                    InteractionMode::Editor => -0.1,
+                    InteractionMode::Crosshair => camera.direction().z,
+                };
+                if photo_active {
+                    // The free camera keeps its pitch and flies along the
+                    // view direction
+                    camera.look_in_direction(&direction.extend(camera.direction().z));
+                    let forward = *camera.direction();
+                    camera.move_by(&(forward * speed));
+                } else {
+                    camera.move_by(&(direction * speed).extend(0.0));
+                    camera.look_in_direction(&direction.extend(pitch));
+                }
+            }
+            // Photo mode only shows the scene, fitted to the display
+            if photo_active {
+                let display_size = ui.io().display_size;
+                let scale = (display_size[0] / 640.0).min(display_size[1] / 480.0);
+                imgui::Window::new(imgui::im_str!("Photo Mode View"))
+                    .position([0.0, 0.0], imgui::Condition::Always)
+                    .size(display_size, imgui::Condition::Always)
+                    .title_bar(false)
+                    .resizable(false)
+                    .movable(false)
+                    .scroll_bar(false)
+                    .bring_to_front_on_focus(false)
+                    .build(ui, || {
+                        ui.set_cursor_pos([
+                            (display_size[0] - 640.0 * scale) / 2.0,
+                            (display_size[1] - 480.0 * scale) / 2.0,
+                        ]);
+                        imgui::Image::new(1.into(), [640.0 * scale, 480.0 * scale]).build(ui);
+                    });
+                if !photo_window.hide_ui() {
+                    imgui::Window::new(imgui::im_str!("Photo Mode"))
+                        .size([320.0, 200.0], imgui::Condition::Once)
+                        .build(ui, || {
+                            screenshot = photo_window.draw(ui, camera);
+                        });
+                }
+                return;
             }
             imgui::Window::new(imgui::im_str!("Terrain Chunk Viewer"))
                 .size([640.0, 480.0], imgui::Condition::Once)
@@ -172,86 +595,1016 @@ impl Game {
                     imgui::Slider::new(imgui::im_str!("isolevel"))
                         .range(0.0..=1.0)
                         .build(ui, &mut isolevel);
+                    // Show a GPU only preview while dragging and run the full
+                    // pipeline once the slider is released
+                    if ui.is_item_active() && ui.is_item_edited() {
+                        isolevel_preview = Some(*isolevel);
+                    }
                     isolevel_changed = ui.is_item_deactivated();
+                    // Every chunk is generated again so the warp is only
+                    // applied once a slider is released
+                    imgui::Slider::new(imgui::im_str!("warp strength"))
+                        .range(0.0..=1.0)
+                        .build(ui, &mut domain_warp.strength);
+                    domain_warp_changed |= ui.is_item_deactivated_after_edit();
+                    imgui::Slider::new(imgui::im_str!("warp frequency"))
+                        .range(0.1..=8.0)
+                        .flags(imgui::SliderFlags::LOGARITHMIC)
+                        .build(ui, &mut domain_warp.frequency);
+                    domain_warp_changed |= ui.is_item_deactivated_after_edit();
+                    caves_changed |= ui.checkbox(imgui::im_str!("caves"), &mut caves.enabled);
+                    if caves.enabled {
+                        imgui::Slider::new(imgui::im_str!("cave frequency"))
+                            .range(0.5..=16.0)
+                            .flags(imgui::SliderFlags::LOGARITHMIC)
+                            .build(ui, &mut caves.frequency);
+                        caves_changed |= ui.is_item_deactivated_after_edit();
+                        imgui::Slider::new(imgui::im_str!("cave radius"))
+                            .range(0.01..=0.5)
+                            .build(ui, &mut caves.radius);
+                        caves_changed |= ui.is_item_deactivated_after_edit();
+                    }
+                    // Only shading, applied while dragging
+                    ui.checkbox(imgui::im_str!("magma"), &mut magma.enabled);
+                    if magma.enabled {
+                        imgui::Slider::new(imgui::im_str!("magma depth"))
+                            .range(0.0..=1.0)
+                            .build(ui, &mut magma.depth);
+                        imgui::Slider::new(imgui::im_str!("magma blend"))
+                            .range(0.01..=0.5)
+                            .build(ui, &mut magma.blend);
+                        imgui::Slider::new(imgui::im_str!("magma intensity"))
+                            .range(0.0..=4.0)
+                            .build(ui, &mut magma.intensity);
+                        imgui::ColorEdit::new(imgui::im_str!("magma color"), &mut magma.color)
+                            .build(ui);
+                    }
+                    erosion_changed |= ui.checkbox(imgui::im_str!("erosion"), &mut erosion.enabled);
+                    if erosion.enabled {
+                        imgui::Slider::new(imgui::im_str!("erosion iterations"))
+                            .range(1..=512)
+                            .build(ui, &mut erosion.iterations);
+                        erosion_changed |= ui.is_item_deactivated_after_edit();
+                        imgui::Slider::new(imgui::im_str!("rain"))
+                            .range(0.0001..=0.01)
+                            .flags(imgui::SliderFlags::LOGARITHMIC)
+                            .build(ui, &mut erosion.rain);
+                        erosion_changed |= ui.is_item_deactivated_after_edit();
+                        imgui::Slider::new(imgui::im_str!("evaporation"))
+                            .range(0.001..=0.5)
+                            .flags(imgui::SliderFlags::LOGARITHMIC)
+                            .build(ui, &mut erosion.evaporation);
+                        erosion_changed |= ui.is_item_deactivated_after_edit();
+                        imgui::Slider::new(imgui::im_str!("sediment capacity"))
+                            .range(0.1..=16.0)
+                            .build(ui, &mut erosion.capacity);
+                        erosion_changed |= ui.is_item_deactivated_after_edit();
+                        imgui::Slider::new(imgui::im_str!("erosion rate"))
+                            .range(0.0..=1.0)
+                            .build(ui, &mut erosion.erosion);
+                        erosion_changed |= ui.is_item_deactivated_after_edit();
+                        imgui::Slider::new(imgui::im_str!("deposition rate"))
+                            .range(0.0..=1.0)
+                            .build(ui, &mut erosion.deposition);
+                        erosion_changed |= ui.is_item_deactivated_after_edit();
+                    }
+                    ui.input_int(imgui::im_str!("world seed"), world_seed)
+                        .build();
+                    world_seed_changed = ui.is_item_deactivated_after_edit();
+                    if imgui::CollapsingHeader::new(imgui::im_str!("random streams")).build(ui) {
+                        for stream in Stream::ALL {
+                            ui.text(format!("{}: {:016x}", stream.name(), random.seed(stream)));
+                        }
+                    }
+                    let mut hours = sky.hours();
+                    if imgui::Slider::new(imgui::im_str!("time of day"))
+                        .range(0.0..=24.0)
+                        .build(ui, &mut hours)
+                    {
+                        sky.set_hours(hours);
+                    }
+                    ui.checkbox(imgui::im_str!("day cycle"), sky.cycle_mut());
+                    ui.text(format!(
+                        "ambience: {}",
+                        soundscape.map_or("-", |x| x.name())
+                    ));
+                    imgui::Slider::new(imgui::im_str!("brush radius"))
+                        .range(0.01..=0.5)
+                        .build(ui, brush_radius);
+                    let mut traversability_overlay =
+                        terrain.overlay() == TerrainOverlay::Traversability;
+                    if ui.checkbox(
+                        imgui::im_str!("traversability overlay"),
+                        &mut traversability_overlay,
+                    ) {
+                        let overlay = if traversability_overlay {
+                            TerrainOverlay::Traversability
+                        } else {
+                            TerrainOverlay::None
+                        };
+                        if let Err(error) = terrain.set_overlay(overlay) {
+                            log::error!("Failed to build the terrain overlay: {}", error);
+                        }
+                    }
+                    let mut noise = NOISE_ALGORITHMS
+                        .iter()
+                        .position(|x| *x == terrain.noise())
+                        .unwrap();
+                    if imgui::ComboBox::new(imgui::im_str!("noise")).build_simple(
+                        ui,
+                        &mut noise,
+                        &NOISE_ALGORITHMS,
+                        &|x| imgui::im_str!("{}", x.name()).into(),
+                    ) {
+                        terrain.set_noise(NOISE_ALGORITHMS[noise]);
+                    }
+                    let mut meshing = MESHING_ALGORITHMS
+                        .iter()
+                        .position(|x| *x == terrain.meshing_algorithm())
+                        .unwrap();
+                    if imgui::ComboBox::new(imgui::im_str!("meshing")).build_simple(
+                        ui,
+                        &mut meshing,
+                        &MESHING_ALGORITHMS,
+                        &|x| imgui::im_str!("{}", x.name()).into(),
+                    ) {
+                        terrain.set_meshing_algorithm(MESHING_ALGORITHMS[meshing]);
+                    }
+                    let mut skirts = terrain.skirts();
+                    if ui.checkbox(imgui::im_str!("skirts instead of stitching"), &mut skirts) {
+                        terrain.set_skirts(skirts);
+                    }
+                    let mut flat_shading = terrain.flat_shading();
+                    if ui.checkbox(imgui::im_str!("flat shaded (low poly)"), &mut flat_shading) {
+                        terrain.set_flat_shading(flat_shading);
+                    }
+                    let mut gpu_meshes = terrain.gpu_meshes();
+                    if ui.checkbox(
+                        imgui::im_str!("draw unstitched chunks from the GPU"),
+                        &mut gpu_meshes,
+                    ) {
+                        terrain.set_gpu_meshes(gpu_meshes);
+                    }
                     imgui::Image::new(1.into(), [640.0, 480.0])
                         .border_col([1.0, 0.0, 0.0, 1.0])
-                        .build(ui)
+                        .build(ui);
+                    let image_min = ui.item_rect_min();
+                    let image_max = ui.item_rect_max();
+                    let image_hovered = ui.is_item_hovered();
+                    let pick_point = match interaction_mode {
+                        InteractionMode::Crosshair => {
+                            let center = [
+                                (image_min[0] + image_max[0]) / 2.0,
+                                (image_min[1] + image_max[1]) / 2.0,
+                            ];
+                            let draw_list = ui.get_window_draw_list();
+                            draw_list
+                                .add_line(
+                                    [center[0] - CROSSHAIR_SIZE, center[1]],
+                                    [center[0] + CROSSHAIR_SIZE, center[1]],
+                                    [1.0, 1.0, 1.0],
+                                )
+                                .thickness(2.0)
+                                .build();
+                            draw_list
+                                .add_line(
+                                    [center[0], center[1] - CROSSHAIR_SIZE],
+                                    [center[0], center[1] + CROSSHAIR_SIZE],
+                                    [1.0, 1.0, 1.0],
+                                )
+                                .thickness(2.0)
+                                .build();
+                            Some(point2(0.0, 0.0))
+                        }
+                        InteractionMode::Editor if image_hovered => {
+                            let mouse_pos = ui.io().mouse_pos;
+                            Some(point2(
+                                (mouse_pos[0] - image_min[0]) / (image_max[0] - image_min[0]) * 2.0
+                                    - 1.0,
+                                1.0 - (mouse_pos[1] - image_min[1]) / (image_max[1] - image_min[1])
+                                    * 2.0,
+                            ))
+                        }
+                        InteractionMode::Editor => None,
+                    };
+                    let hit = pick_point.and_then(|x| {
+                        terrain.raycast(camera.position(), &camera.ray_direction(x), PICK_DISTANCE)
+                    });
+                    match hit {
+                        Some(hit) => {
+                            ui.text(format!(
+                                "position: {:.3} {:.3} {:.3}",
+                                hit.position.x, hit.position.y, hit.position.z
+                            ));
+                            ui.text(format!(
+                                "normal: {:.3} {:.3} {:.3} (chunk level {})",
+                                hit.normal.x, hit.normal.y, hit.normal.z, hit.key.level
+                            ));
+                            match terrain.sample_density(&hit.position) {
+                                Some(density) => ui.text(format!("density: {:.3}", density)),
+                                None => ui.text("density: -"),
+                            }
+                            match terrain.line_of_sight(camera.position(), &hit.position) {
+                                Some(true) => ui.text("line of sight: clear"),
+                                Some(false) => ui.text("line of sight: blocked"),
+                                None => ui.text("line of sight: -"),
+                            }
+                            match terrain.surface_metadata(&hit.key).and_then(|x| {
+                                x.cell_at(&hit.position.xy()).map(|x| x.traversability)
+                            }) {
+                                Some(traversability) => {
+                                    ui.text(format!("traversability: {:?}", traversability))
+                                }
+                                None => ui.text("traversability: -"),
+                            }
+                            match terrain.biome_at(&hit.position.xy()) {
+                                Some(weights) => ui.text(format!(
+                                    "biome: {}",
+                                    BIOME_NAMES[dominant_biome(&weights)]
+                                )),
+                                None => ui.text("biome: -"),
+                            }
+                            match terrain.enclosure_at(&hit.key, &hit.position) {
+                                Some(enclosure) if enclosure >= CAVE_ENCLOSURE => {
+                                    ui.text(format!("enclosure: {:.2} (cave)", enclosure))
+                                }
+                                Some(enclosure) => ui.text(format!("enclosure: {:.2}", enclosure)),
+                                None => ui.text("enclosure: -"),
+                            }
+                            // Left click digs, right click places, middle click
+                            // selects the chunk to diff, shift + left click
+                            // places an object, ctrl + left click measures and
+                            // alt + left click picks the profile line
+                            if interaction_mode == InteractionMode::Crosshair || image_hovered {
+                                if ui.is_mouse_clicked(imgui::MouseButton::Left)
+                                    && ui.io().key_shift
+                                {
+                                    placement = Some(hit);
+                                } else if ui.is_mouse_clicked(imgui::MouseButton::Left)
+                                    && ui.io().key_ctrl
+                                {
+                                    measure_point = Some(hit.position);
+                                } else if ui.is_mouse_clicked(imgui::MouseButton::Left)
+                                    && ui.io().key_alt
+                                {
+                                    profile_point = Some(hit.position);
+                                } else if ui.is_mouse_clicked(imgui::MouseButton::Left) {
+                                    brush = Some((hit, -BRUSH_STRENGTH));
+                                } else if ui.is_mouse_clicked(imgui::MouseButton::Right) {
+                                    brush = Some((hit, BRUSH_STRENGTH));
+                                } else if ui.is_mouse_clicked(imgui::MouseButton::Middle) {
+                                    diff_selection = Some(Some(hit.key));
+                                }
+                            }
+                        }
+                        None => {
+                            ui.text("position: -");
+                            ui.text("density: -");
+                        }
+                    }
+                    match terrain.chunk_diff() {
+                        Some((key, diff)) => {
+                            match diff {
+                                Some(diff) => ui.text(format!(
+                                    "diff (chunk level {}): +{} -{} triangles",
+                                    key.level,
+                                    diff.added.len(),
+                                    diff.removed.len()
+                                )),
+                                None => ui.text(format!(
+                                    "diff (chunk level {}): waiting for regeneration",
+                                    key.level
+                                )),
+                            }
+                            ui.same_line(0.0);
+                            if ui.button(imgui::im_str!("Clear diff"), [0.0, 0.0]) {
+                                diff_selection = Some(None);
+                            }
+                        }
+                        None => ui.text("diff: middle click a chunk"),
+                    }
+                });
+            imgui::Window::new(imgui::im_str!("Settings"))
+                .size([320.0, 480.0], imgui::Condition::Once)
+                .build(ui, || {
+                    settings_response = settings_window.draw(ui, settings);
+                });
+            imgui::Window::new(imgui::im_str!("Terrain Export"))
+                .size([320.0, 200.0], imgui::Condition::Once)
+                .build(ui, || {
+                    normal_map_window.draw(ui, terrain, camera);
+                });
+            imgui::Window::new(imgui::im_str!("Terrain Edits"))
+                .size([320.0, 240.0], imgui::Condition::Once)
+                .build(ui, || {
+                    edit_window.draw(ui, terrain, camera);
+                });
+            imgui::Window::new(imgui::im_str!("Terrain Generator"))
+                .size([520.0, 420.0], imgui::Condition::Once)
+                .build(ui, || {
+                    generator_window.draw(ui, terrain);
+                });
+            imgui::Window::new(imgui::im_str!("Textures"))
+                .size([320.0, 400.0], imgui::Condition::Once)
+                .build(ui, || {
+                    texture_preview = texture_window.draw(ui, instance, textures);
+                });
+            imgui::Window::new(imgui::im_str!("Objects"))
+                .size([320.0, 200.0], imgui::Condition::Once)
+                .build(ui, || {
+                    object_window.draw(ui, terrain, camera, random, objects);
+                });
+            imgui::Window::new(imgui::im_str!("Lights"))
+                .size([320.0, 300.0], imgui::Condition::Once)
+                .build(ui, || {
+                    light_window.draw(ui, lights);
+                });
+            imgui::Window::new(imgui::im_str!("Measure"))
+                .size([320.0, 200.0], imgui::Condition::Once)
+                .build(ui, || {
+                    measure_window.draw(ui);
+                });
+            imgui::Window::new(imgui::im_str!("Elevation Profile"))
+                .size([340.0, 280.0], imgui::Condition::Once)
+                .build(ui, || {
+                    profile_window.draw(ui, terrain);
+                });
+            imgui::Window::new(imgui::im_str!("Terrain Errors"))
+                .size([420.0, 200.0], imgui::Condition::Once)
+                .build(ui, || {
+                    error_window.draw(ui);
+                });
+            imgui::Window::new(imgui::im_str!("Preview World"))
+                .size([300.0, 360.0], imgui::Condition::Once)
+                .build(ui, || {
+                    preview_world_applied = preview_world_window.draw(ui, preview_world.as_ref());
+                });
+            imgui::Window::new(imgui::im_str!("Caches"))
+                .size([420.0, 400.0], imgui::Condition::Once)
+                .build(ui, || {
+                    cache_window.draw(ui, terrain);
                 });
             // ui.show_demo_window(&mut true);
         });
+        if screenshot.is_some() {
+            self.screenshot = screenshot;
+        }
+        if let Some(isolevel) = isolevel_preview {
+            self.terrain.preview_isolevel(isolevel);
+        }
         if isolevel_changed {
-            terrain.set_isolevel(self.isolevel);
-        }
-        terrain.update_terrain(
-            self.camera.position(),
-            regions
-                .iter()
-                .rev()
-                .enumerate()
-                .map(|(i, region)| TerrainRegion {
-                    region: region.clone(),
-                    level: ((9 - regions.len() as u32)..=8).nth(i).unwrap(),
-                })
-                .collect::<Vec<_>>()
-                .as_slice(),
-        );
+            self.terrain.clear_preview();
+            self.terrain.set_isolevel(self.isolevel);
+        }
+        if world_seed_changed {
+            self.random = RandomStreams::new(self.world_seed as u64);
+            self.wind.set_seed(&self.random);
+            self.terrain
+                .set_seed(self.random.stream(Stream::Terrain).next_u32());
+            self.warmup = Some(Warmup::new(&self.terrain, &self.camera.position().xy()));
+        }
+        // Held changes are only simulated in the preview world until applied
+        if preview_world_applied {
+            caves_changed = true;
+            erosion_changed = true;
+            domain_warp_changed = true;
+        } else if self.preview_world_window.holds_changes() {
+            caves_changed = false;
+            erosion_changed = false;
+            domain_warp_changed = false;
+        }
+        if caves_changed {
+            self.terrain.set_caves(self.caves);
+        }
+        if erosion_changed {
+            self.terrain.set_erosion(self.erosion);
+        }
+        if domain_warp_changed {
+            self.terrain.set_domain_warp(self.domain_warp);
+        }
+        self.terrain.update_preview_world(&PreviewWorldParams {
+            center: self.camera.position().xy(),
+            isolevel: self.isolevel,
+            domain_warp: self.domain_warp,
+            caves: self.caves,
+            erosion: self.erosion,
+        });
+        // The preview shows the texels of a single level so it is not filtered
+        if let Some(view) = texture_preview {
+            self.imgui_renderer.register_texture(
+                &self.instance,
+                &view,
+                &self.instance.sampler(&SamplerKey::default()),
+                PREVIEW_TEXTURE_ID.into(),
+            );
+        }
+        if let Some(key) = diff_selection {
+            self.terrain.select_diff_chunk(key);
+        }
+        if let Some(hit) = placement.filter(|_| self.light_window.is_placing()) {
+            self.light_window.place(&hit.position, &mut self.lights);
+        } else if let Some(hit) = placement {
+            let seed = self.object_window.next_seed();
+            if let Some(object) = Object::place(
+                &self.terrain,
+                &hit.position,
+                self.object_window.settings(),
+                seed,
+            ) {
+                self.objects.push(object);
+            }
+        }
+        if let Some(point) = measure_point {
+            self.measure_window.add_point(point);
+        }
+        if let Some(point) = profile_point {
+            self.profile_window.add_point(point);
+        }
+        self.draw_chunk_diff();
+        self.draw_chunk_outlines();
+        self.draw_cracks();
+        self.draw_objects();
+        self.draw_lights();
+        self.terrain.set_wind(&self.wind.data());
+        self.terrain.set_magma(&self.magma);
+        for (p0, p1) in self.measure_window.segments() {
+            self.debug_draw.line(&p0, &p1, MEASURE_COLOR);
+        }
+        if let Some((p0, p1)) = self.profile_window.line() {
+            self.debug_draw.line(&p0, &p1, PROFILE_COLOR);
+        }
+        if let Some((hit, strength)) = brush {
+            self.terrain.sculpt(&TerrainEdit {
+                center: hit.position,
+                radius: self.brush_radius,
+                strength,
+            });
+        }
+        // The world is paused in photo mode, streaming and the quality
+        // controller resume with the restored camera
+        if !self.photo_window.is_active() {
+            self.wind.update(elapsed_time);
+            self.sky.update(elapsed_time);
+            // There is no audio backend yet, zone changes are only logged
+            if let Some(change) =
+                self.ambience
+                    .update(&self.terrain, self.camera.position(), elapsed_time)
+            {
+                log::info!("Ambience {:?} -> {:?}", change.from, change.to);
+            }
+            if self.settings.graphics.auto_quality
+                && self.quality.update(
+                    elapsed_time,
+                    self.settings.graphics.target_fps,
+                    &terrain_stats,
+                )
+            {
+                self.init_render_target();
+                self.update_regions();
+            } else if self.triangle_budget.update(
+                terrain_stats.resident_triangles,
+                self.settings.streaming.resident_triangle_budget,
+            ) || (moved && self.regions_outdated())
+            {
+                self.update_regions();
+            }
+            self.terrain
+                .update_terrain(self.camera.position(), &self.terrain_regions);
+            if self
+                .idle
+                .update(&self.camera, &self.terrain, &terrain_stats)
+                && self.settings.streaming.idle_refinement
+            {
+                self.refine_while_idle(&terrain_stats);
+            }
+        }
+        let mut apply_settings = settings_response.changed;
+        if settings_response.reload {
+            self.settings = self.settings_file.load();
+            apply_settings = true;
+        }
+        if let Some(settings) = self.settings_file.poll_reload() {
+            self.settings = settings;
+            apply_settings = true;
+        }
+        if apply_settings {
+            self.apply_settings();
+        }
+        if settings_response.save {
+            self.settings_file.save(&self.settings);
+        }
         profiling::finish_frame!();
     }
 
     pub fn init(&mut self, window: &Window) {
         self.imgui_renderer.init(window, &self.instance);
         self.camera.init(&self.instance);
+        self.textures.init(&self.instance);
+        self.instance.set_vsync(self.settings.graphics.vsync);
         self.init_render_target();
+        self.debug_draw.init(
+            &self.instance,
+            &self.camera.buffers(),
+            TextureFormat::Rgba8Unorm,
+            self.sample_count,
+        );
+        self.sky.init(
+            &self.instance,
+            &self.camera.buffers(),
+            TextureFormat::Rgba8Unorm,
+            self.sample_count,
+        );
+        self.impostors.init(
+            &self.instance,
+            &self.camera.buffers(),
+            TextureFormat::Rgba8Unorm,
+            self.sample_count,
+        );
         self.terrain.init(
             self.instance.clone(),
             TextureFormat::Rgba8Unorm,
-            self.camera.buffer(),
+            self.sample_count,
+            self.camera.buffers(),
             0.5,
         );
+        self.set_stylized(self.settings.graphics.stylized);
+        self.focus_terrain_tasks();
+        self.terrain
+            .set_seed(self.random.stream(Stream::Terrain).next_u32());
+        self.warmup = Some(Warmup::new(&self.terrain, &self.camera.position().xy()));
+    }
+
+    fn draw_chunk_diff(&mut self) {
+        let (key, diff) = match self.terrain.chunk_diff() {
+            Some(x) => x,
+            None => return,
+        };
+        draw_box(
+            &mut self.debug_draw,
+            &key.bounds.to_f32(),
+            DIFF_BOUNDS_COLOR,
+        );
+        if let Some(diff) = diff {
+            for triangle in &diff.added {
+                self.debug_draw.triangle(triangle, DIFF_ADDED_COLOR);
+            }
+            for triangle in &diff.removed {
+                self.debug_draw.triangle(triangle, DIFF_REMOVED_COLOR);
+            }
+        }
+    }
+
+    // Seams are easier to follow in the scene than in the chunk viewer
+    fn draw_chunk_outlines(&mut self) {
+        if !self.terrain_visualizer.chunk_outlines() {
+            return;
+        }
+        let by_stitch = self.terrain_visualizer.outline_stitches();
+        let lift = vec3(0.0, 0.0, CHUNK_OUTLINE_LIFT);
+        for chunk_outline in self.terrain.chunk_outlines() {
+            let level = (chunk_outline.key.level - MIN_LEVEL) as usize;
+            let level_color = CHUNK_LEVEL_COLORS[level % CHUNK_LEVEL_COLORS.len()];
+            for side in &chunk_outline.sides {
+                let color = if by_stitch {
+                    match side.status {
+                        StitchStatus::Matched => MATCHED_SIDE_COLOR,
+                        StitchStatus::Stitched => STITCHED_SIDE_COLOR,
+                        StitchStatus::Pending => PENDING_SIDE_COLOR,
+                        StitchStatus::Skirted => SKIRTED_SIDE_COLOR,
+                    }
+                } else {
+                    level_color
+                };
+                for pair in side.points.windows(2) {
+                    if let [Some(p0), Some(p1)] = pair {
+                        self.debug_draw.line(&(*p0 + lift), &(*p1 + lift), color);
+                    }
+                }
+            }
+        }
+    }
+
+    // Each gap joins a border vertex to the other border, the markers find the
+    // gaps too small to see from afar
+    fn draw_cracks(&mut self) {
+        let marker = vec3(0.0, 0.0, CRACK_MARKER_HEIGHT);
+        for crack in self.terrain_visualizer.cracks() {
+            for [p0, p1] in &crack.gaps {
+                self.debug_draw.line(p0, p1, CRACK_COLOR);
+                self.debug_draw.line(p0, &(*p0 + marker), CRACK_COLOR);
+            }
+        }
+    }
+
+    // Objects are drawn as rocks with their local axes, distant clusters are
+    // replaced by impostors once they are baked
+    fn draw_objects(&mut self) {
+        let mut clusters: HashMap<ClusterKey, Vec<&Object>> = HashMap::new();
+        for object in &self.objects {
+            clusters
+                .entry(cluster_key(&object.position))
+                .or_default()
+                .push(object);
+        }
+        let camera_position = *self.camera.position();
+        let impostor_distance = IMPOSTOR_DISTANCE * self.quality.quality().detail_distance;
+        let idle = self.idle.is_idle() && self.settings.streaming.idle_refinement;
+        for (key, objects) in clusters {
+            let center = (key.to_f32() + vec2(0.5, 0.5)) * CLUSTER_SIZE;
+            let distance = center.distance_to(camera_position.xy());
+            let far = distance > impostor_distance;
+            if far || (idle && distance > impostor_distance * IDLE_BAKE_FRACTION) {
+                let signature = cluster_signature(&objects);
+                let baked = self.impostors.is_baked(&key, signature);
+                if far && baked {
+                    self.impostors.billboard(&key, &camera_position);
+                    continue;
+                }
+                if !baked && self.impostors.wants_bake() {
+                    let triangles = objects
+                        .iter()
+                        .map(|x| {
+                            self.rocks
+                                .get(x.seed)
+                                .map(|rock| x.rock_triangles(rock.lod(IMPOSTOR_LOD)))
+                        })
+                        .collect::<Option<Vec<_>>>();
+                    if let Some(triangles) = triangles {
+                        self.impostors
+                            .request_bake(key, signature, &triangles.concat());
+                    }
+                }
+            }
+            for object in objects {
+                if let Some(rock) = self.rocks.get(object.seed) {
+                    let distance = object.position.distance_to(camera_position);
+                    let mesh = rock.lod((distance / ROCK_LOD_DISTANCE) as usize);
+                    for (triangle, color) in object.rock_triangles(mesh) {
+                        self.debug_draw.triangle(&triangle, color);
+                    }
+                }
+                for (axis, color) in object.axis().iter().zip(OBJECT_AXIS_COLORS) {
+                    self.debug_draw.line(
+                        &object.position,
+                        &(object.position + *axis * OBJECT_AXIS_LENGTH),
+                        color,
+                    );
+                }
+            }
+        }
+    }
+
+    // Lights are drawn with a gizmo in their color and passed to the terrain
+    // shader every frame
+    fn draw_lights(&mut self) {
+        for light in &self.lights {
+            for (p0, p1) in light.gizmo() {
+                self.debug_draw.line(&p0, &p1, light.gizmo_color());
+            }
+        }
+        self.terrain
+            .set_point_lights(&self.lights.iter().map(|x| x.data()).collect::<Vec<_>>());
+    }
+
+    // Chunks the rings would need first if they were deeper, queued behind
+    // every other task. The impostors are baked in draw_objects, ambient
+    // occlusion is not baked, see IdleRefinement.
+    fn refine_while_idle(&mut self, terrain_stats: &TerrainStats) {
+        let mut lod = self.settings.lod.clone();
+        lod.base_distance *= self.quality.quality().lod_distance * IDLE_RING_GROWTH;
+        let grown_rings = lod::terrain_regions(
+            &self.camera,
+            &lod,
+            &self.terrain.geometric_errors(),
+            self.triangle_budget.coarsening(),
+        );
+        let keys = idle::refinement_keys(
+            &self.terrain,
+            &self.camera,
+            &self.terrain_regions,
+            &grown_rings,
+        );
+        self.idle.request(&self.terrain, terrain_stats, keys);
+    }
+
+    fn regions_outdated(&self) -> bool {
+        let (position, direction) = self.regions_camera;
+        self.camera.position().distance_to(position) > REGION_UPDATE_DISTANCE
+            || self.camera.direction().angle_to(direction).radians > REGION_UPDATE_ANGLE
+    }
+
+    fn update_regions(&mut self) {
+        self.regions_camera = (*self.camera.position(), *self.camera.direction());
+        let mut lod = self.settings.lod.clone();
+        lod.base_distance *= self.quality.quality().lod_distance;
+        self.terrain_regions = lod::terrain_regions(
+            &self.camera,
+            &lod,
+            &self.terrain.geometric_errors(),
+            self.triangle_budget.coarsening(),
+        );
+        self.regions = self
+            .terrain_regions
+            .iter()
+            .map(|x| x.region.clone())
+            .collect();
+        self.focus_terrain_tasks();
+    }
+
+    // Checked once the chunks around the spawn point are meshed, the coarse
+    // occupancy is enough to keep the camera out of the ground
+    fn lift_camera_out_of_ground(&mut self) {
+        let position = *self.camera.position();
+        if self.terrain.is_occupied(&position) != Some(true) {
+            return;
+        }
+        if let Some(top) = self.terrain.occupied_top(&position.xy()) {
+            self.camera
+                .move_to(&position.xy().extend(top + SPAWN_CLEARANCE));
+        }
+    }
+
+    fn focus_terrain_tasks(&self) {
+        self.terrain.reprioritize(TaskFocus {
+            position: *self.camera.position(),
+            direction: *self.camera.direction(),
+            view_cosine: self.camera.corner_cosine(),
+        });
+    }
+
+    fn set_stylized(&self, stylized: bool) {
+        if let Err(error) = self.terrain.set_stylized(stylized) {
+            log::error!("Failed to build the stylized terrain: {}", error);
+        }
+    }
+
+    fn apply_settings(&mut self) {
+        let previous = std::mem::replace(&mut self.applied_settings, self.settings.clone());
+        let graphics = &self.settings.graphics;
+        if graphics.vsync != previous.graphics.vsync {
+            self.instance.set_vsync(graphics.vsync);
+        }
+        if graphics.stylized != previous.graphics.stylized {
+            self.set_stylized(graphics.stylized);
+        }
+        if graphics.sample_count() != previous.graphics.sample_count() {
+            // MSAA sample count is baked into the terrain pipelines so they
+            // have to be rebuilt along with the render target. If they fail
+            // to build the old sample count is kept with the old pipelines.
+            let sample_count = graphics.sample_count();
+            match self
+                .terrain
+                .rebuild_pipelines(TextureFormat::Rgba8Unorm, sample_count)
+            {
+                Err(error) => log::error!("Failed to rebuild the terrain pipelines: {}", error),
+                Ok(()) => {
+                    self.sample_count = sample_count;
+                    self.init_render_target();
+                    self.debug_draw.init(
+                        &self.instance,
+                        &self.camera.buffers(),
+                        TextureFormat::Rgba8Unorm,
+                        self.sample_count,
+                    );
+                    self.sky.init(
+                        &self.instance,
+                        &self.camera.buffers(),
+                        TextureFormat::Rgba8Unorm,
+                        self.sample_count,
+                    );
+                    self.impostors.init(
+                        &self.instance,
+                        &self.camera.buffers(),
+                        TextureFormat::Rgba8Unorm,
+                        self.sample_count,
+                    );
+                }
+            }
+        } else if graphics.render_scale != previous.graphics.render_scale
+            || graphics.sampler_key() != previous.graphics.sampler_key()
+            || graphics.stylized != previous.graphics.stylized
+        {
+            self.init_render_target();
+        }
+        if self.settings.graphics.draw_distance != previous.graphics.draw_distance
+            || self.settings.lod != previous.lod
+        {
+            self.camera.set_far(self.settings.graphics.draw_distance);
+            self.update_regions();
+        }
+        if !self.settings.graphics.auto_quality && previous.graphics.auto_quality {
+            self.quality.reset();
+            self.init_render_target();
+            self.update_regions();
+        }
+        let streaming = &self.settings.streaming;
+        if streaming.chunk_cache_mib != previous.streaming.chunk_cache_mib
+            || streaming.mesh_cache_mib != previous.streaming.mesh_cache_mib
+        {
+            self.terrain
+                .set_cache_sizes(streaming.chunk_cache_mib, streaming.mesh_cache_mib);
+        }
+        if streaming.gpu_budget_mib != previous.streaming.gpu_budget_mib {
+            self.terrain.set_gpu_budget(streaming.gpu_budget_mib);
+        }
+        if streaming.resident_triangle_budget != previous.streaming.resident_triangle_budget {
+            self.terrain
+                .set_triangle_budget(streaming.resident_triangle_budget);
+        }
+        if streaming.max_gpu_submissions != previous.streaming.max_gpu_submissions {
+            self.terrain
+                .set_max_gpu_submissions(streaming.max_gpu_submissions);
+        }
+        if streaming.disk_cache != previous.streaming.disk_cache {
+            self.terrain.set_disk_cache(streaming.disk_cache);
+        }
+        if streaming.spill_evicted_chunks != previous.streaming.spill_evicted_chunks {
+            self.terrain.set_chunk_spill(streaming.spill_evicted_chunks);
+        }
+    }
+
+    fn render_target_size(&self) -> Extent3d {
+        let scale = self.settings.graphics.render_scale * self.quality.quality().render_scale;
+        Extent3d {
+            width: ((640.0 * scale) as u32).max(1),
+            height: ((480.0 * scale) as u32).max(1),
+            depth_or_array_layers: 1,
+        }
     }
 
     fn init_render_target(&mut self) {
+        let size = self.render_target_size();
+        let (render_target, msaa_target_view, depth_stencil_view) =
+            self.create_scene_targets(size, TextureUsages::TEXTURE_BINDING);
+        let render_target_view = render_target.create_view(&TextureViewDescriptor::default());
+        let bloom = Bloom::new(
+            &self.instance,
+            &render_target_view,
+            size,
+            TextureUsages::TEXTURE_BINDING,
+        );
+        self.imgui_renderer.register_texture(
+            &self.instance,
+            bloom.output_view(),
+            &self.instance.sampler(&self.settings.graphics.sampler_key()),
+            1.into(),
+        );
+        self.render_target_view = Some(render_target_view);
+        self.msaa_target_view = msaa_target_view;
+        self.outline = self.create_outline(&depth_stencil_view);
+        self.depth_stencil_view = Some(depth_stencil_view);
+        self.bloom = Some(bloom);
+    }
+
+    fn create_outline(&self, depth_stencil_view: &TextureView) -> Option<Outline> {
+        if self.settings.graphics.stylized {
+            Some(Outline::new(
+                &self.instance,
+                depth_stencil_view,
+                &self.camera.buffers(),
+                TextureFormat::Rgba8Unorm,
+            ))
+        } else {
+            None
+        }
+    }
+
+    // Returns the resolved color target, the multisampled target when MSAA
+    // is enabled and the depth target
+    fn create_scene_targets(
+        &self,
+        size: Extent3d,
+        usage: TextureUsages,
+    ) -> (Texture, Option<TextureView>, TextureView) {
         let device = &self.instance.device();
         let render_target = device.create_texture(&TextureDescriptor {
             label: Some("scene_render_target"),
-            size: Extent3d {
-                width: 640,
-                height: 480,
-                depth_or_array_layers: 1,
-            },
+            size,
             mip_level_count: 1,
             sample_count: 1,
             dimension: TextureDimension::D2,
             format: TextureFormat::Rgba8Unorm,
-            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+            usage: TextureUsages::RENDER_ATTACHMENT | usage,
         });
-        self.render_target_view =
-            Some(render_target.create_view(&TextureViewDescriptor::default()));
-        self.imgui_renderer.register_texture(
-            &self.instance,
-            self.render_target_view.as_ref().unwrap(),
-            1.into(),
-        );
+        let msaa_target_view = if self.sample_count > 1 {
+            let msaa_target = device.create_texture(&TextureDescriptor {
+                label: Some("scene_msaa_target"),
+                size,
+                mip_level_count: 1,
+                sample_count: self.sample_count,
+                dimension: TextureDimension::D2,
+                format: TextureFormat::Rgba8Unorm,
+                usage: TextureUsages::RENDER_ATTACHMENT,
+            });
+            Some(msaa_target.create_view(&TextureViewDescriptor::default()))
+        } else {
+            None
+        };
         let depth_stencil = device.create_texture(&wgpu::TextureDescriptor {
-            size: wgpu::Extent3d {
-                width: 640,
-                height: 480,
-                depth_or_array_layers: 1,
-            },
+            size,
             mip_level_count: 1,
-            sample_count: 1,
+            sample_count: self.sample_count,
             dimension: wgpu::TextureDimension::D2,
             format: TextureFormat::Depth32Float,
-            usage: wgpu::TextureUsages::RENDER_ATTACHMENT,
+            // Read by the outline pass
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING,
             label: Some("scene_depth_stencil"),
         });
-
-        self.depth_stencil_view =
-            Some(depth_stencil.create_view(&TextureViewDescriptor::default()));
+        (
+            render_target,
+            msaa_target_view,
+            depth_stencil.create_view(&TextureViewDescriptor::default()),
+        )
     }
 
     #[profiling::function]
     pub fn handle_event(&mut self, window: &Window, event: &Event<()>) {
+        if let Event::WindowEvent {
+            event:
+                WindowEvent::KeyboardInput {
+                    input:
+                        KeyboardInput {
+                            state: ElementState::Pressed,
+                            virtual_keycode: Some(key),
+                            ..
+                        },
+                    ..
+                },
+            ..
+        } = event
+        {
+            if self.settings_window.handle_key(&mut self.settings, *key) {
+                self.apply_settings();
+                return;
+            }
+            if *key == self.settings.input.toggle_crosshair {
+                let mode = match self.interaction_mode {
+                    InteractionMode::Editor => InteractionMode::Crosshair,
+                    InteractionMode::Crosshair => InteractionMode::Editor,
+                };
+                self.set_interaction_mode(window, mode);
+                return;
+            }
+            if *key == self.settings.input.toggle_photo_mode {
+                if self.photo_window.is_active() {
+                    self.photo_window.exit(&mut self.camera);
+                } else {
+                    self.photo_window.enter(&self.camera);
+                }
+                return;
+            }
+        }
+        if let Event::DeviceEvent {
+            event: DeviceEvent::MouseMotion { delta },
+            ..
+        } = event
+        {
+            if self.interaction_mode == InteractionMode::Crosshair {
+                self.mouse_delta.0 += delta.0;
+                self.mouse_delta.1 += delta.1;
+            }
+        }
         self.imgui_renderer.handle_event(window, event);
     }
+
+    fn set_interaction_mode(&mut self, window: &Window, mode: InteractionMode) {
+        let grab = mode == InteractionMode::Crosshair;
+        if let Err(e) = window.set_cursor_grab(grab) {
+            log::warn!("Failed to grab cursor: {}", e);
+        }
+        window.set_cursor_visible(!grab);
+        self.mouse_delta = (0.0, 0.0);
+        self.interaction_mode = mode;
+    }
+}
+
+// Changes whenever an object of the cluster is placed, moved or removed
+fn cluster_signature(objects: &[&Object]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    for object in objects {
+        object.seed.hash(&mut hasher);
+        for x in object.position.to_array() {
+            x.to_bits().hash(&mut hasher);
+        }
+    }
+    hasher.finish()
+}
+
+fn draw_box(debug_draw: &mut DebugDraw, bounds: &Box3D<f32, base::WorldSpace>, color: [f32; 4]) {
+    let corner = |i: usize| {
+        let pick = |bit: usize, min: f32, max: f32| if i & bit == 0 { min } else { max };
+        point3(
+            pick(1, bounds.min.x, bounds.max.x),
+            pick(2, bounds.min.y, bounds.max.y),
+            pick(4, bounds.min.z, bounds.max.z),
+        )
+    };
+    // Every pair of corners differing by one axis is an edge
+    for i in 0..8 {
+        for axis in [1, 2, 4] {
+            if i & axis == 0 {
+                debug_draw.line(&corner(i), &corner(i | axis), color);
+            }
+        }
+    }
 }
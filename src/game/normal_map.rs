@@ -0,0 +1,79 @@
+use crate::game::base::WorldSpace;
+use crate::game::terrain::Terrain;
+use euclid::{point2, vec3, Box2D, Size2D, UnknownUnit};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+// World space normals packed as RGBA8, alpha is zero where no cached chunk
+// covers the texel
+pub struct NormalMap {
+    size: Size2D<u32, UnknownUnit>,
+    data: Vec<u8>,
+}
+
+impl NormalMap {
+    // Normals are computed from central differences of the surface height so
+    // only the topmost surface is captured. Row 0 is the max y edge.
+    #[profiling::function]
+    pub fn bake(
+        terrain: &Terrain,
+        bounds: &Box2D<f32, WorldSpace>,
+        size: Size2D<u32, UnknownUnit>,
+    ) -> Self {
+        let step_x = bounds.width() / size.width as f32;
+        let step_y = bounds.height() / size.height as f32;
+        // Sample one extra texel on each side for the differences
+        let (width, height) = (size.width as usize + 2, size.height as usize + 2);
+        let mut heights = Vec::with_capacity(width * height);
+        for row in 0..height {
+            for column in 0..width {
+                heights.push(terrain.height_at(&point2(
+                    bounds.min.x + (column as f32 - 0.5) * step_x,
+                    bounds.max.y - (row as f32 - 0.5) * step_y,
+                )));
+            }
+        }
+        let height_at = |column: usize, row: usize| heights[column + row * width];
+        let mut data = Vec::with_capacity(size.area() as usize * 4);
+        for row in 1..height - 1 {
+            for column in 1..width - 1 {
+                let samples = (
+                    height_at(column - 1, row),
+                    height_at(column + 1, row),
+                    height_at(column, row + 1),
+                    height_at(column, row - 1),
+                );
+                match samples {
+                    (Some(left), Some(right), Some(bottom), Some(top)) => {
+                        let normal = vec3::<_, WorldSpace>(
+                            (left - right) / (2.0 * step_x),
+                            (bottom - top) / (2.0 * step_y),
+                            1.0,
+                        )
+                        .normalize();
+                        data.extend_from_slice(&[
+                            ((normal.x * 0.5 + 0.5) * 255.0) as u8,
+                            ((normal.y * 0.5 + 0.5) * 255.0) as u8,
+                            ((normal.z * 0.5 + 0.5) * 255.0) as u8,
+                            255,
+                        ]);
+                    }
+                    _ => data.extend_from_slice(&[128, 128, 255, 0]),
+                }
+            }
+        }
+        Self { size, data }
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), png::EncodingError> {
+        let file = File::create(path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        Ok(())
+    }
+}
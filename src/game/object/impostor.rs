@@ -0,0 +1,472 @@
+use super::ShadedTriangle;
+use crate::game::base::WorldSpace;
+use crate::gfx::{Instance, SamplerKey};
+use euclid::{point3, vec2, vec3, Box3D, Point2D, Point3D};
+use std::collections::HashMap;
+use std::mem::size_of;
+use std::num::NonZeroU32;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+// Side of the square cells that props are clustered in
+pub const CLUSTER_SIZE: f32 = 2.0;
+const ATLAS_LAYERS: u32 = 64;
+const TILE_SIZE: u32 = 128;
+// Minimum number of frames between two bakes
+const BAKE_INTERVAL: u64 = 10;
+
+pub type ClusterKey = Point2D<i32, WorldSpace>;
+
+pub fn cluster_key(position: &Point3D<f32, WorldSpace>) -> ClusterKey {
+    (position.xy() / CLUSTER_SIZE).floor().to_i32()
+}
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct BakeVertex {
+    position: [f32; 4],
+    color: [f32; 4],
+}
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct BillboardVertex {
+    position: [f32; 4],
+    // uv and atlas layer
+    uv_layer: [f32; 4],
+}
+
+#[derive(Debug, Copy, Clone)]
+struct Tile {
+    layer: u32,
+    signature: u64,
+    // The billboard turns around the vertical axis through the center
+    bounds: Box3D<f32, WorldSpace>,
+    last_used: u64,
+}
+
+struct PendingBake {
+    key: ClusterKey,
+    signature: u64,
+    bounds: Box3D<f32, WorldSpace>,
+    vertices: Vec<BakeVertex>,
+}
+
+// Distant prop clusters rendered as camera facing billboards. Every cluster
+// is baked from the side into one layer of the atlas, at most one bake is
+// done every few frames.
+pub struct ImpostorAtlas {
+    tiles: HashMap<ClusterKey, Tile>,
+    pending: Option<PendingBake>,
+    frame: u64,
+    last_bake: u64,
+    billboards: Vec<BillboardVertex>,
+    atlas: Option<Texture>,
+    depth_view: Option<TextureView>,
+    bake_pipeline: Option<RenderPipeline>,
+    billboard_pipeline: Option<RenderPipeline>,
+    // One for each camera buffer
+    bind_groups: Vec<BindGroup>,
+    billboard_buffer: Option<(Buffer, u32)>,
+}
+
+impl ImpostorAtlas {
+    pub fn new() -> Self {
+        Self {
+            tiles: HashMap::new(),
+            pending: None,
+            frame: 0,
+            last_bake: 0,
+            billboards: vec![],
+            atlas: None,
+            depth_view: None,
+            bake_pipeline: None,
+            billboard_pipeline: None,
+            bind_groups: vec![],
+            billboard_buffer: None,
+        }
+    }
+
+    // Needs to be called again when the sample count changes, every cluster
+    // is baked again afterwards
+    pub fn init(
+        &mut self,
+        instance: &Instance,
+        camera_buffers: &[Buffer],
+        target_format: TextureFormat,
+        sample_count: u32,
+    ) {
+        let device = instance.device();
+        let atlas = device.create_texture(&TextureDescriptor {
+            label: Some("impostor_atlas"),
+            size: Extent3d {
+                width: TILE_SIZE,
+                height: TILE_SIZE,
+                depth_or_array_layers: ATLAS_LAYERS,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING,
+        });
+        let depth = device.create_texture(&TextureDescriptor {
+            label: Some("impostor_bake_depth"),
+            size: Extent3d {
+                width: TILE_SIZE,
+                height: TILE_SIZE,
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Depth32Float,
+            usage: TextureUsages::RENDER_ATTACHMENT,
+        });
+        self.depth_view = Some(depth.create_view(&TextureViewDescriptor::default()));
+        let vertex_attributes = vertex_attr_array![
+            0 => Float32x4,
+            1 => Float32x4,
+        ];
+        let depth_stencil = DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        };
+
+        let bake_shader_module =
+            device.create_shader_module(&include_wgsl!("shaders/impostor_bake.wgsl"));
+        let bake_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("impostor_bake_pipeline_layout"),
+            bind_group_layouts: &[],
+            push_constant_ranges: &[],
+        });
+        self.bake_pipeline = Some(device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("impostor_bake_pipeline"),
+            layout: Some(&bake_pipeline_layout),
+            vertex: VertexState {
+                module: &bake_shader_module,
+                entry_point: "main",
+                buffers: &[VertexBufferLayout {
+                    array_stride: size_of::<BakeVertex>() as u64,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: &vertex_attributes,
+                }],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &bake_shader_module,
+                entry_point: "main",
+                targets: &[ColorTargetState {
+                    format: TextureFormat::Rgba8Unorm,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+        }));
+
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("impostor_bind_group_layout"),
+            entries: &[
+                // view + projection matrix
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler {
+                        comparison: false,
+                        filtering: true,
+                    },
+                    count: None,
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        view_dimension: TextureViewDimension::D2Array,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let sampler = instance.sampler(&SamplerKey::linear());
+        let atlas_view = atlas.create_view(&TextureViewDescriptor {
+            dimension: Some(TextureViewDimension::D2Array),
+            ..Default::default()
+        });
+        self.bind_groups = camera_buffers
+            .iter()
+            .map(|camera_buffer| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("impostor_bind_group"),
+                    layout: &bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: camera_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Sampler(&sampler),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::TextureView(&atlas_view),
+                        },
+                    ],
+                })
+            })
+            .collect();
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/impostor.wgsl"));
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("impostor_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        self.billboard_pipeline = Some(device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("impostor_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "main",
+                buffers: &[VertexBufferLayout {
+                    array_stride: size_of::<BillboardVertex>() as u64,
+                    step_mode: VertexStepMode::Vertex,
+                    attributes: &vertex_attributes,
+                }],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(depth_stencil.clone()),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "main",
+                targets: &[ColorTargetState {
+                    format: target_format,
+                    blend: None,
+                    // The alpha of the scene is its glow
+                    write_mask: ColorWrites::COLOR,
+                }],
+            }),
+        }));
+        self.atlas = Some(atlas);
+        self.tiles.clear();
+        self.pending = None;
+    }
+
+    // Returns true if the cluster was baked with the same signature
+    pub fn is_baked(&mut self, key: &ClusterKey, signature: u64) -> bool {
+        match self.tiles.get_mut(key) {
+            Some(tile) if tile.signature == signature => {
+                tile.last_used = self.frame;
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub fn wants_bake(&self) -> bool {
+        self.atlas.is_some()
+            && self.pending.is_none()
+            && self.frame - self.last_bake >= BAKE_INTERVAL
+    }
+
+    // Queue the triangles of a cluster to be baked on the next render
+    pub fn request_bake(&mut self, key: ClusterKey, signature: u64, triangles: &[ShadedTriangle]) {
+        if triangles.is_empty() {
+            return;
+        }
+        let points = triangles.iter().flat_map(|x| x.0).collect::<Vec<_>>();
+        let extent = Box3D::from_points(&points);
+        let center = extent.center();
+        // Wide enough for the cluster seen from any side
+        let radius = points
+            .iter()
+            .map(|x| x.xy().distance_to(center.xy()))
+            .fold(f32::EPSILON, f32::max);
+        let half_height = (extent.depth() / 2.0).max(f32::EPSILON);
+        let bounds = Box3D::new(
+            point3(center.x - radius, center.y - radius, extent.min.z),
+            point3(center.x + radius, center.y + radius, extent.max.z),
+        );
+        // Orthographic view looking along +y
+        let vertices = triangles
+            .iter()
+            .flat_map(|(triangle, color)| {
+                triangle.map(|p| BakeVertex {
+                    position: [
+                        (p.x - center.x) / radius,
+                        (p.z - center.z) / half_height,
+                        (p.y - bounds.min.y) / (radius * 2.0),
+                        1.0,
+                    ],
+                    color: *color,
+                })
+            })
+            .collect();
+        self.pending = Some(PendingBake {
+            key,
+            signature,
+            bounds,
+            vertices,
+        });
+    }
+
+    // Queue the billboard of a baked cluster for this frame
+    pub fn billboard(&mut self, key: &ClusterKey, camera_position: &Point3D<f32, WorldSpace>) {
+        let tile = match self.tiles.get(key) {
+            Some(tile) => *tile,
+            None => return,
+        };
+        let center = tile.bounds.center();
+        let direction = (*camera_position - center).xy();
+        let direction = if direction.square_length() > f32::EPSILON {
+            direction.normalize()
+        } else {
+            vec2(0.0, -1.0)
+        };
+        // The bake looks along +y with +x to the right
+        let right = vec3(-direction.y, direction.x, 0.0) * (tile.bounds.width() / 2.0);
+        let bottom = point3(center.x, center.y, tile.bounds.min.z);
+        let top = point3(center.x, center.y, tile.bounds.max.z);
+        let layer = tile.layer as f32;
+        let vertex = |p: Point3D<f32, WorldSpace>, u: f32, v: f32| BillboardVertex {
+            position: [p.x, p.y, p.z, 1.0],
+            uv_layer: [u, v, layer, 0.0],
+        };
+        let quad = [
+            vertex(bottom - right, 0.0, 1.0),
+            vertex(bottom + right, 1.0, 1.0),
+            vertex(top + right, 1.0, 0.0),
+            vertex(top - right, 0.0, 0.0),
+        ];
+        for i in [0, 1, 2, 0, 2, 3] {
+            self.billboards.push(quad[i]);
+        }
+    }
+
+    // Run the pending bake, call before the render pass
+    pub fn bake(&mut self, instance: &Instance, encoder: &mut CommandEncoder) {
+        let pending = match self.pending.take() {
+            Some(pending) => pending,
+            None => return,
+        };
+        let layer = match self.tiles.get(&pending.key) {
+            Some(tile) => tile.layer,
+            None => self.allocate_layer(),
+        };
+        let device = instance.device();
+        let vertex_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("impostor_bake_vertex_buffer"),
+            contents: bytemuck::cast_slice(&pending.vertices),
+            usage: BufferUsages::VERTEX,
+        });
+        let view = self
+            .atlas
+            .as_ref()
+            .unwrap()
+            .create_view(&TextureViewDescriptor {
+                dimension: Some(TextureViewDimension::D2),
+                base_array_layer: layer,
+                array_layer_count: NonZeroU32::new(1),
+                ..Default::default()
+            });
+        {
+            let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+                label: Some("impostor_bake"),
+                color_attachments: &[RenderPassColorAttachment {
+                    view: &view,
+                    resolve_target: None,
+                    ops: Operations {
+                        load: LoadOp::Clear(Color::TRANSPARENT),
+                        store: true,
+                    },
+                }],
+                depth_stencil_attachment: Some(RenderPassDepthStencilAttachment {
+                    view: self.depth_view.as_ref().unwrap(),
+                    depth_ops: Some(Operations {
+                        load: LoadOp::Clear(1.0),
+                        store: false,
+                    }),
+                    stencil_ops: None,
+                }),
+            });
+            rp.set_pipeline(self.bake_pipeline.as_ref().unwrap());
+            rp.set_vertex_buffer(0, vertex_buffer.slice(..));
+            rp.draw(0..pending.vertices.len() as u32, 0..1);
+        }
+        self.tiles.insert(
+            pending.key,
+            Tile {
+                layer,
+                signature: pending.signature,
+                bounds: pending.bounds,
+                last_used: self.frame,
+            },
+        );
+        self.last_bake = self.frame;
+    }
+
+    // Free layer, or the one of the least recently used cluster
+    fn allocate_layer(&mut self) -> u32 {
+        if let Some(layer) =
+            (0..ATLAS_LAYERS).find(|x| self.tiles.values().all(|tile| tile.layer != *x))
+        {
+            return layer;
+        }
+        let key = *self
+            .tiles
+            .iter()
+            .min_by_key(|(_, tile)| tile.last_used)
+            .unwrap()
+            .0;
+        self.tiles.remove(&key).unwrap().layer
+    }
+
+    // Upload the billboards queued this frame, call before the render pass
+    pub fn prepare(&mut self, instance: &Instance) {
+        self.billboard_buffer = if self.billboards.is_empty() {
+            None
+        } else {
+            let buffer = instance.device().create_buffer_init(&BufferInitDescriptor {
+                label: Some("impostor_vertex_buffer"),
+                contents: bytemuck::cast_slice(&self.billboards),
+                usage: BufferUsages::VERTEX,
+            });
+            Some((buffer, self.billboards.len() as u32))
+        };
+        self.billboards.clear();
+        self.frame += 1;
+    }
+
+    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, frame: usize) {
+        if let Some((buffer, count)) = &self.billboard_buffer {
+            rp.set_pipeline(self.billboard_pipeline.as_ref().unwrap());
+            rp.set_bind_group(0, &self.bind_groups[frame], &[]);
+            rp.set_vertex_buffer(0, buffer.slice(..));
+            rp.draw(0..*count, 0..1);
+        }
+    }
+}
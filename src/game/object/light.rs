@@ -0,0 +1,103 @@
+use crate::game::base::WorldSpace;
+use crate::game::terrain::PointLightData;
+use euclid::{vec3, Point3D};
+
+// Segments of the ring drawn at the reach of a light
+const GIZMO_SEGMENTS: usize = 24;
+// Half length of the cross drawn at a light
+const GIZMO_SIZE: f32 = 0.01;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LightKind {
+    Torch,
+    Marker,
+}
+
+pub const LIGHT_KINDS: [LightKind; 2] = [LightKind::Torch, LightKind::Marker];
+
+impl LightKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LightKind::Torch => "torch",
+            LightKind::Marker => "marker",
+        }
+    }
+
+    fn color(&self) -> [f32; 3] {
+        match self {
+            LightKind::Torch => [1.0, 0.6, 0.25],
+            LightKind::Marker => [0.3, 0.8, 1.0],
+        }
+    }
+
+    fn radius(&self) -> f32 {
+        match self {
+            LightKind::Torch => 0.15,
+            LightKind::Marker => 0.08,
+        }
+    }
+
+    // Height above the surface the light is placed at
+    fn height(&self) -> f32 {
+        match self {
+            LightKind::Torch => 0.02,
+            LightKind::Marker => 0.04,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PointLight {
+    pub kind: LightKind,
+    pub position: Point3D<f32, WorldSpace>,
+    pub color: [f32; 3],
+    pub radius: f32,
+    pub intensity: f32,
+}
+
+impl PointLight {
+    // Above the surface point so that the ground around it is lit
+    pub fn place(kind: LightKind, surface: &Point3D<f32, WorldSpace>) -> Self {
+        Self {
+            kind,
+            position: *surface + vec3(0.0, 0.0, kind.height()),
+            color: kind.color(),
+            radius: kind.radius(),
+            intensity: 1.0,
+        }
+    }
+
+    pub fn data(&self) -> PointLightData {
+        PointLightData::new(&self.position, self.radius, self.color, self.intensity)
+    }
+
+    // A cross at the light and a ring at its reach, as line segments
+    pub fn gizmo(&self) -> Vec<(Point3D<f32, WorldSpace>, Point3D<f32, WorldSpace>)> {
+        let p = self.position;
+        let mut lines = vec![
+            (
+                p - vec3(GIZMO_SIZE, 0.0, 0.0),
+                p + vec3(GIZMO_SIZE, 0.0, 0.0),
+            ),
+            (
+                p - vec3(0.0, GIZMO_SIZE, 0.0),
+                p + vec3(0.0, GIZMO_SIZE, 0.0),
+            ),
+            (
+                p - vec3(0.0, 0.0, GIZMO_SIZE),
+                p + vec3(0.0, 0.0, GIZMO_SIZE),
+            ),
+        ];
+        let ring = |i: usize| {
+            let angle = i as f32 / GIZMO_SEGMENTS as f32 * std::f32::consts::TAU;
+            p + vec3(angle.cos(), angle.sin(), 0.0) * self.radius
+        };
+        lines.extend((0..GIZMO_SEGMENTS).map(|i| (ring(i), ring(i + 1))));
+        lines
+    }
+
+    pub fn gizmo_color(&self) -> [f32; 4] {
+        let [r, g, b] = self.color;
+        [r, g, b, 1.0]
+    }
+}
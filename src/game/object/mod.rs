@@ -1 +1,169 @@
-// Module for future in game objects. For example, a cat that follows you :)
+// In game objects placed on the terrain. For example, a cat that follows you :)
+mod impostor;
+mod light;
+mod rock;
+
+use crate::game::base::{LocalSpace, WorldSpace};
+use crate::game::mesh::Mesh;
+use crate::game::random::Random;
+use crate::game::terrain::{pick_biome, Terrain, BIOME_STYLES};
+use euclid::{point2, vec3, Angle, Point2D, Point3D, Rotation3D, Vector3D};
+
+pub use impostor::{cluster_key, ClusterKey, ImpostorAtlas, CLUSTER_SIZE};
+pub use light::{LightKind, PointLight, LIGHT_KINDS};
+pub use rock::RockLibrary;
+
+const ROCK_SCALE: f32 = 0.03;
+const ROCK_COLOR: [f32; 3] = [0.5, 0.47, 0.44];
+// Most candidate points tried by one scatter
+const MAX_SCATTER_CANDIDATES: usize = 4096;
+
+pub type ShadedTriangle = ([Point3D<f32, WorldSpace>; 3], [f32; 4]);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Orientation {
+    // Local up follows the surface normal
+    AlignToNormal,
+    // Local up stays on the world z axis, for trees and buildings
+    Upright,
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct PlacementSettings {
+    pub orientation: Orientation,
+    // Distance the object is pushed into the ground so that its base does not
+    // float above a slope
+    pub sink_depth: f32,
+    // Points further than this from the terrain are not placed
+    pub snap_distance: f32,
+}
+
+impl Default for PlacementSettings {
+    fn default() -> Self {
+        Self {
+            orientation: Orientation::AlignToNormal,
+            sink_depth: 0.0,
+            snap_distance: 0.1,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct Object {
+    pub position: Point3D<f32, WorldSpace>,
+    pub rotation: Rotation3D<f32, LocalSpace, WorldSpace>,
+    // Seed of the rock mesh
+    pub seed: u32,
+    // Snapped surface point, used to place the object again after an edit
+    anchor: Point3D<f32, WorldSpace>,
+}
+
+impl Object {
+    // Snap to the closest terrain surface around the point
+    pub fn place(
+        terrain: &Terrain,
+        point: &Point3D<f32, WorldSpace>,
+        settings: &PlacementSettings,
+        seed: u32,
+    ) -> Option<Self> {
+        let hit = terrain.closest_surface(point, settings.snap_distance)?;
+        let up = match settings.orientation {
+            Orientation::AlignToNormal => hit.normal,
+            Orientation::Upright => vec3(0.0, 0.0, 1.0),
+        };
+        Some(Self {
+            position: hit.position - up * settings.sink_depth,
+            rotation: rotation_to(&up),
+            seed,
+            anchor: hit.position,
+        })
+    }
+
+    // Rocks over the square around the center. Candidate points are drawn at
+    // the highest scatter density and each is kept with the density of the
+    // biome dithered from the weights at the point, so that the borders
+    // between biomes blend like in the render shader.
+    pub fn scatter(
+        terrain: &Terrain,
+        center: &Point2D<f32, WorldSpace>,
+        radius: f32,
+        settings: &PlacementSettings,
+        random: &mut Random,
+    ) -> Vec<Self> {
+        let max_density = BIOME_STYLES
+            .iter()
+            .map(|x| x.scatter_density)
+            .fold(0.0f32, f32::max);
+        let candidates =
+            ((4.0 * radius * radius * max_density) as usize).min(MAX_SCATTER_CANDIDATES);
+        let mut objects = vec![];
+        for _ in 0..candidates {
+            let point = point2(
+                center.x + (random.next_f32() * 2.0 - 1.0) * radius,
+                center.y + (random.next_f32() * 2.0 - 1.0) * radius,
+            );
+            let (threshold, keep, seed) = (random.next_f32(), random.next_f32(), random.next_u32());
+            let biome = match terrain.biome_at(&point) {
+                Some(weights) => pick_biome(&weights, threshold),
+                None => continue,
+            };
+            if keep >= BIOME_STYLES[biome].scatter_density / max_density {
+                continue;
+            }
+            let object = terrain
+                .height_at(&point)
+                .and_then(|z| Self::place(terrain, &point.extend(z), settings, seed));
+            objects.extend(object);
+        }
+        objects
+    }
+
+    // Returns None if the terrain moved away from the object
+    pub fn resnap(&self, terrain: &Terrain, settings: &PlacementSettings) -> Option<Self> {
+        Self::place(terrain, &self.anchor, settings, self.seed)
+    }
+
+    // Rock mesh in world space, lit from above
+    pub fn rock_triangles(&self, mesh: &Mesh<LocalSpace>) -> Vec<ShadedTriangle> {
+        let vertex = mesh
+            .vertex()
+            .iter()
+            .map(|x| self.position + self.rotation.transform_vector3d(x.to_vector()) * ROCK_SCALE)
+            .collect::<Vec<_>>();
+        mesh.faces()
+            .iter()
+            .map(|face| {
+                let triangle = [vertex[face[0]], vertex[face[1]], vertex[face[2]]];
+                let normal = (triangle[1] - triangle[0])
+                    .cross(triangle[2] - triangle[0])
+                    .normalize();
+                let shade = 0.4 + 0.6 * normal.z.max(0.0);
+                let [r, g, b] = ROCK_COLOR;
+                (triangle, [r * shade, g * shade, b * shade, 1.0])
+            })
+            .collect()
+    }
+
+    pub fn axis(&self) -> [Vector3D<f32, WorldSpace>; 3] {
+        [
+            self.rotation.transform_vector3d(vec3(1.0, 0.0, 0.0)),
+            self.rotation.transform_vector3d(vec3(0.0, 1.0, 0.0)),
+            self.rotation.transform_vector3d(vec3(0.0, 0.0, 1.0)),
+        ]
+    }
+}
+
+// Shortest rotation taking the local z axis to the direction
+fn rotation_to(direction: &Vector3D<f32, WorldSpace>) -> Rotation3D<f32, LocalSpace, WorldSpace> {
+    let direction = direction.normalize();
+    let axis: Vector3D<f32, LocalSpace> = vec3(-direction.y, direction.x, 0.0);
+    let sin = axis.length();
+    if sin < f32::EPSILON {
+        return if direction.z > 0.0 {
+            Rotation3D::identity()
+        } else {
+            Rotation3D::around_x(Angle::pi())
+        };
+    }
+    Rotation3D::around_axis(axis / sin, Angle::radians(sin.atan2(direction.z)))
+}
@@ -0,0 +1,209 @@
+use crate::game::base::LocalSpace;
+use crate::game::mesh::{Mesh, Triangle};
+use crate::game::terrain::EdgeId;
+use euclid::{point3, vec3, Point3D, Vector3D};
+use parking_lot::RwLock;
+use std::collections::hash_map::Entry;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+// Subdivisions of the most detailed level, every following level has one less
+const MAX_SUBDIVISION: usize = 3;
+const LOD_COUNT: usize = MAX_SUBDIVISION + 1;
+const NOISE_OCTAVES: u32 = 4;
+const NOISE_FREQUENCY: f32 = 1.5;
+// Displacement relative to the unit radius
+const DISPLACEMENT: f32 = 0.35;
+// Rocks are flatter than they are wide
+const SQUASH: f32 = 0.7;
+
+// Noise displaced icosphere of about unit radius, level 0 is the most
+// detailed. Every level is displaced by the same noise so they line up.
+pub struct RockMesh {
+    lods: Vec<Mesh<LocalSpace>>,
+}
+
+impl RockMesh {
+    #[profiling::function]
+    pub fn generate(seed: u32) -> Self {
+        let mut spheres = vec![icosahedron()];
+        for _ in 0..MAX_SUBDIVISION {
+            let sphere = subdivide(spheres.last().unwrap());
+            spheres.push(sphere);
+        }
+        // Stretch every rock a bit differently
+        let stretch = vec3(
+            1.0 + 0.2 * hash(seed, 1, 0, 0),
+            1.0 + 0.2 * hash(seed, 0, 1, 0),
+            SQUASH * (1.0 + 0.2 * hash(seed, 0, 0, 1)),
+        );
+        let displace = |p: &Point3D<f32, LocalSpace>| {
+            let radius = 1.0 + DISPLACEMENT * fbm(seed, p.to_vector() * NOISE_FREQUENCY);
+            let p = p.to_vector() * radius;
+            point3(p.x * stretch.x, p.y * stretch.y, p.z * stretch.z) / (1.0 + DISPLACEMENT)
+        };
+        let lods = spheres
+            .iter()
+            .rev()
+            .map(|sphere| {
+                let vertex = sphere.vertex();
+                let ids = sphere.ids();
+                Mesh::from_triangles(sphere.faces().iter().map(|face| Triangle {
+                    position: [
+                        displace(&vertex[face[0]]),
+                        displace(&vertex[face[1]]),
+                        displace(&vertex[face[2]]),
+                    ],
+                    id: [ids[face[0]], ids[face[1]], ids[face[2]]],
+                }))
+            })
+            .collect();
+        Self { lods }
+    }
+
+    pub fn lod(&self, level: usize) -> &Mesh<LocalSpace> {
+        &self.lods[level.min(LOD_COUNT - 1)]
+    }
+}
+
+// Rock meshes by seed, a mesh is generated on the rayon pool the first time
+// its seed is requested
+pub struct RockLibrary {
+    meshes: Arc<RwLock<HashMap<u32, Option<Arc<RockMesh>>>>>,
+}
+
+impl RockLibrary {
+    pub fn new() -> Self {
+        Self {
+            meshes: Arc::new(RwLock::new(HashMap::new())),
+        }
+    }
+
+    // Returns None while the mesh is being generated
+    pub fn get(&self, seed: u32) -> Option<Arc<RockMesh>> {
+        if let Some(mesh) = self.meshes.read().get(&seed) {
+            return mesh.clone();
+        }
+        if let Entry::Vacant(entry) = self.meshes.write().entry(seed) {
+            entry.insert(None);
+            let meshes = self.meshes.clone();
+            rayon::spawn(move || {
+                let mesh = Arc::new(RockMesh::generate(seed));
+                meshes.write().insert(seed, Some(mesh));
+            });
+        }
+        None
+    }
+}
+
+fn icosahedron() -> Mesh<LocalSpace> {
+    let t = (1.0 + 5.0f32.sqrt()) / 2.0;
+    let vertex = [
+        vec3(-1.0, t, 0.0),
+        vec3(1.0, t, 0.0),
+        vec3(-1.0, -t, 0.0),
+        vec3(1.0, -t, 0.0),
+        vec3(0.0, -1.0, t),
+        vec3(0.0, 1.0, t),
+        vec3(0.0, -1.0, -t),
+        vec3(0.0, 1.0, -t),
+        vec3(t, 0.0, -1.0),
+        vec3(t, 0.0, 1.0),
+        vec3(-t, 0.0, -1.0),
+        vec3(-t, 0.0, 1.0),
+    ]
+    .map(|x: Vector3D<f32, LocalSpace>| x.normalize().to_point());
+    let faces: [[usize; 3]; 20] = [
+        [0, 11, 5],
+        [0, 5, 1],
+        [0, 1, 7],
+        [0, 7, 10],
+        [0, 10, 11],
+        [1, 5, 9],
+        [5, 11, 4],
+        [11, 10, 2],
+        [10, 7, 6],
+        [7, 1, 8],
+        [3, 9, 4],
+        [3, 4, 2],
+        [3, 2, 6],
+        [3, 6, 8],
+        [3, 8, 9],
+        [4, 9, 5],
+        [2, 4, 11],
+        [6, 2, 10],
+        [8, 6, 7],
+        [9, 8, 1],
+    ];
+    Mesh::from_triangles(faces.iter().map(|face| Triangle {
+        position: face.map(|i| vertex[i]),
+        id: face.map(|i| EdgeId::new(i as u32, i as u32)),
+    }))
+}
+
+// Split every triangle in four. Like marching cubes, a new vertex lies on an
+// edge of the previous level so the pair of vertex indices identifies it.
+fn subdivide(mesh: &Mesh<LocalSpace>) -> Mesh<LocalSpace> {
+    let vertex = mesh.vertex();
+    let corner = |a: usize| (vertex[a], EdgeId::new(a as u32, a as u32));
+    let middle = |a: usize, b: usize| {
+        let p = ((vertex[a].to_vector() + vertex[b].to_vector()) / 2.0).normalize();
+        (p.to_point(), EdgeId::new(a as u32, b as u32))
+    };
+    let triangle = |x: [(Point3D<f32, LocalSpace>, EdgeId); 3]| Triangle {
+        position: x.map(|x| x.0),
+        id: x.map(|x| x.1),
+    };
+    Mesh::from_triangles(mesh.faces().iter().flat_map(|&[a, b, c]| {
+        let (ab, bc, ca) = (middle(a, b), middle(b, c), middle(c, a));
+        [
+            triangle([corner(a), ab, ca]),
+            triangle([ab, corner(b), bc]),
+            triangle([ca, bc, corner(c)]),
+            triangle([ab, bc, ca]),
+        ]
+    }))
+}
+
+// Integer hash mapped to [-1, 1]
+fn hash(seed: u32, x: i32, y: i32, z: i32) -> f32 {
+    let mut h = seed.wrapping_mul(0x27d4_eb2d)
+        ^ (x as u32).wrapping_mul(0x8da6_b343)
+        ^ (y as u32).wrapping_mul(0xd816_3841)
+        ^ (z as u32).wrapping_mul(0xcb1a_b31f);
+    h ^= h >> 15;
+    h = h.wrapping_mul(0x2c1b_3c6d);
+    h ^= h >> 12;
+    h = h.wrapping_mul(0x297a_2d39);
+    h ^= h >> 15;
+    h as f32 / u32::MAX as f32 * 2.0 - 1.0
+}
+
+fn value_noise(seed: u32, p: Vector3D<f32, LocalSpace>) -> f32 {
+    let cell = p.floor();
+    let fade = |t: f32| t * t * (3.0 - 2.0 * t);
+    let (fx, fy, fz) = (fade(p.x - cell.x), fade(p.y - cell.y), fade(p.z - cell.z));
+    let (x, y, z) = (cell.x as i32, cell.y as i32, cell.z as i32);
+    let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+    let plane = |z: i32| {
+        lerp(
+            lerp(hash(seed, x, y, z), hash(seed, x + 1, y, z), fx),
+            lerp(hash(seed, x, y + 1, z), hash(seed, x + 1, y + 1, z), fx),
+            fy,
+        )
+    };
+    lerp(plane(z), plane(z + 1), fz)
+}
+
+// Fractal noise in [-1, 1]
+fn fbm(seed: u32, p: Vector3D<f32, LocalSpace>) -> f32 {
+    let mut value = 0.0;
+    let mut amplitude = 0.5;
+    let mut total = 0.0;
+    for octave in 0..NOISE_OCTAVES {
+        value += value_noise(seed.wrapping_add(octave), p * (1 << octave) as f32) * amplitude;
+        total += amplitude;
+        amplitude *= 0.5;
+    }
+    value / total
+}
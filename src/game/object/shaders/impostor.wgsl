@@ -0,0 +1,40 @@
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+    [[location(1)]] layer: f32;
+};
+
+[[block]]
+struct CameraData {
+    view_matrix: mat4x4<f32>;
+    projection_matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var camera_data: CameraData;
+
+[[group(0), binding(1)]]
+var atlas_sampler: sampler;
+
+[[group(0), binding(2)]]
+var atlas: texture_2d_array<f32>;
+
+// Billboards are built in world space on the CPU
+[[stage(vertex)]]
+fn main([[location(0)]] position: vec4<f32>, [[location(1)]] uv_layer: vec4<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = camera_data.projection_matrix * camera_data.view_matrix * position;
+    out.uv = uv_layer.xy;
+    out.layer = uv_layer.z;
+    return out;
+}
+
+// Alpha tested so that impostors write depth like the geometry they replace
+[[stage(fragment)]]
+fn main([[location(0)]] uv: vec2<f32>, [[location(1)]] layer: f32) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(atlas, atlas_sampler, uv, i32(layer + 0.5));
+    if (color.a < 0.5) {
+        discard;
+    }
+    return vec4<f32>(color.rgb, 1.0);
+}
@@ -0,0 +1,18 @@
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+// Positions are already projected into the tile
+[[stage(vertex)]]
+fn main([[location(0)]] position: vec4<f32>, [[location(1)]] color: vec4<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = position;
+    out.color = color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn main([[location(0)]] color: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    return color;
+}
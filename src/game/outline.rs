@@ -0,0 +1,123 @@
+use crate::gfx::Instance;
+use wgpu::*;
+
+// Dark lines drawn over the scene where the depth jumps or bends, for the
+// stylized shading. The depth target is read directly so it can not be
+// multisampled, MSAA is off while the outlines are drawn.
+pub struct Outline {
+    pipeline: RenderPipeline,
+    // One for each camera buffer
+    bind_groups: Vec<BindGroup>,
+}
+
+impl Outline {
+    // Needs to be created again when the depth target changes
+    pub fn new(
+        instance: &Instance,
+        depth_view: &TextureView,
+        camera_buffers: &[Buffer],
+        target_format: TextureFormat,
+    ) -> Self {
+        let device = instance.device();
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("outline_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Depth,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                // view + projection matrix, the projection linearizes the depth
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let bind_groups = camera_buffers
+            .iter()
+            .map(|camera_buffer| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("outline_bind_group"),
+                    layout: &bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::TextureView(depth_view),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: camera_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                    ],
+                })
+            })
+            .collect();
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("outline_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/outline.wgsl"));
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("outline_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: MultisampleState::default(),
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "main",
+                targets: &[ColorTargetState {
+                    format: target_format,
+                    blend: Some(BlendState::ALPHA_BLENDING),
+                    // The alpha of the scene is its glow
+                    write_mask: ColorWrites::COLOR,
+                }],
+            }),
+        });
+        Self {
+            pipeline,
+            bind_groups,
+        }
+    }
+
+    // Call after the scene is rendered into the target and before the bloom
+    pub fn render(&self, encoder: &mut CommandEncoder, target_view: &TextureView, frame: usize) {
+        let mut rp = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("outline_render_pass"),
+            color_attachments: &[RenderPassColorAttachment {
+                view: target_view,
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: true,
+                },
+            }],
+            depth_stencil_attachment: None,
+        });
+        rp.set_pipeline(&self.pipeline);
+        rp.set_bind_group(0, &self.bind_groups[frame], &[]);
+        rp.draw(0..3, 0..1);
+    }
+}
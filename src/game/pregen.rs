@@ -0,0 +1,94 @@
+use crate::game::camera::Camera;
+use crate::game::lod;
+use crate::game::random::{RandomStreams, Stream};
+use crate::game::settings::{self, SettingsFile};
+use crate::game::terrain::{ChunkCacheKey, ChunkState, Terrain};
+use crate::gfx::Instance;
+use euclid::{point2, point3, vec3};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use wgpu::*;
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+const PROGRESS_INTERVAL: Duration = Duration::from_secs(1);
+// Chunks that fail this many times are left out of the cache
+const MAX_ATTEMPTS: u32 = 5;
+
+// Generates every chunk within the radius of the origin into the disk cache,
+// with the rings of the lod settings and the seed a new world starts with
+pub fn pregenerate(instance: Arc<Instance>, radius: f32) {
+    let mut settings = SettingsFile::new(settings::CONFIG_PATH).load();
+    settings.streaming.disk_cache = true;
+    // Only the camera buffers are used, to build the render bundles of the meshes
+    let mut camera = Camera::new(
+        point3(0.0, 0.0, 0.3),
+        vec3(1.0, 0.0, 0.0),
+        std::f32::consts::PI / 4.0,
+        640.0 / 480.0,
+        0.001,
+        settings.graphics.draw_distance,
+    );
+    camera.init(&instance);
+    let mut terrain = Terrain::new(&settings.streaming);
+    terrain.init(
+        instance.clone(),
+        TextureFormat::Rgba8Unorm,
+        settings.graphics.sample_count(),
+        camera.buffers(),
+        0.5,
+    );
+    terrain.set_seed(RandomStreams::new(0).stream(Stream::Terrain).next_u32());
+
+    let regions = lod::square_regions(&settings.lod, &point2(0.0, 0.0), radius);
+    terrain.update_terrain(&point3(0.0, 0.0, 0.0), &regions);
+    let keys = terrain.region_keys(&regions);
+    let total = keys.len();
+    let start = Instant::now();
+    log::info!("Pregenerating {} chunks within {}", total, radius);
+    let failed = generate_chunks(&instance, &terrain, keys);
+    log::info!(
+        "Pregenerated {} chunks in {:.1} s, {} failed",
+        total - failed,
+        start.elapsed().as_secs_f32(),
+        failed
+    );
+}
+
+// Radius argument of the headless commands
+pub fn parse_radius(arg: Option<&str>) -> f32 {
+    arg.and_then(|x| x.parse().ok()).expect("Needs a radius")
+}
+
+// Drives the terrain without rendering until every key is meshed or has
+// failed MAX_ATTEMPTS times, returns how many failed. Nothing else submits
+// the chunk passes or polls the device in between.
+pub fn generate_chunks(
+    instance: &Instance,
+    terrain: &Terrain,
+    mut remaining: Vec<ChunkCacheKey>,
+) -> usize {
+    let total = remaining.len();
+    let mut failed = 0;
+    let mut last_progress = Instant::now();
+    while !remaining.is_empty() {
+        instance.device().poll(Maintain::Poll);
+        std::thread::sleep(POLL_INTERVAL);
+        remaining.retain(|key| match terrain.chunk_state(key) {
+            ChunkState::Meshed => false,
+            ChunkState::Failed(failure) if failure.attempts >= MAX_ATTEMPTS => {
+                log::error!("Giving up on chunk {:?}: {}", key, failure.error);
+                failed += 1;
+                false
+            }
+            ChunkState::Failed(_) | ChunkState::Pending | ChunkState::Generated => true,
+        });
+        // Only as many chunks as the scheduler takes are queued at once, the
+        // others and the retries are queued as it drains
+        terrain.request_chunks(&remaining);
+        if last_progress.elapsed() >= PROGRESS_INTERVAL {
+            last_progress = Instant::now();
+            log::info!("Generated {}/{} chunks", total - remaining.len(), total);
+        }
+    }
+    failed
+}
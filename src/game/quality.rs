@@ -0,0 +1,138 @@
+use crate::game::terrain::{TerrainStats, MAX_PENDING_CHUNKS};
+use std::collections::VecDeque;
+use std::time::{Duration, Instant};
+
+// Frames averaged before deciding
+const SAMPLE_COUNT: usize = 30;
+// Changing the render scale recreates the render target so the level is moved
+// at most once per interval
+const ADJUST_INTERVAL: Duration = Duration::from_secs(1);
+// Frame time deviation from the target that is tolerated
+const TOLERANCE: f32 = 0.1;
+const MAX_DECISIONS: usize = 8;
+
+// Multipliers applied on top of the configured settings
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct QualityLevel {
+    pub render_scale: f32,
+    pub lod_distance: f32,
+    pub detail_distance: f32,
+}
+
+// From the lowest quality to the configured settings
+const LEVELS: [QualityLevel; 5] = [
+    QualityLevel {
+        render_scale: 0.5,
+        lod_distance: 0.5,
+        detail_distance: 0.25,
+    },
+    QualityLevel {
+        render_scale: 0.65,
+        lod_distance: 0.65,
+        detail_distance: 0.5,
+    },
+    QualityLevel {
+        render_scale: 0.8,
+        lod_distance: 0.8,
+        detail_distance: 0.75,
+    },
+    QualityLevel {
+        render_scale: 0.9,
+        lod_distance: 0.9,
+        detail_distance: 0.9,
+    },
+    QualityLevel {
+        render_scale: 1.0,
+        lod_distance: 1.0,
+        detail_distance: 1.0,
+    },
+];
+
+// Moves the quality one level at a time to keep the average frame time
+// around the target
+pub struct QualityController {
+    level: usize,
+    frame_times: VecDeque<Duration>,
+    last_change: Instant,
+    decisions: VecDeque<String>,
+}
+
+impl QualityController {
+    pub fn new() -> Self {
+        Self {
+            level: LEVELS.len() - 1,
+            frame_times: VecDeque::with_capacity(SAMPLE_COUNT),
+            last_change: Instant::now(),
+            decisions: VecDeque::new(),
+        }
+    }
+
+    // Back to the configured settings, used when the controller is turned off
+    pub fn reset(&mut self) {
+        if self.level != LEVELS.len() - 1 {
+            self.level = LEVELS.len() - 1;
+            self.decide("reset to full quality".to_string());
+        }
+        self.frame_times.clear();
+    }
+
+    // Returns true if the level changed
+    pub fn update(&mut self, frame_time: Duration, target_fps: f32, stats: &TerrainStats) -> bool {
+        if self.frame_times.len() == SAMPLE_COUNT {
+            self.frame_times.pop_front();
+        }
+        self.frame_times.push_back(frame_time);
+        if self.frame_times.len() < SAMPLE_COUNT || self.last_change.elapsed() < ADJUST_INTERVAL {
+            return false;
+        }
+        let average = self.average_frame_time().as_secs_f32() * 1000.0;
+        let target = 1000.0 / target_fps.max(1.0);
+        let level = if average > target * (1.0 + TOLERANCE) && self.level > 0 {
+            self.level - 1
+        } else if average < target * (1.0 - TOLERANCE) && self.level < LEVELS.len() - 1 {
+            // A higher level streams in more chunks, wait for the backlog
+            if stats.pending_chunks >= MAX_PENDING_CHUNKS {
+                return false;
+            }
+            self.level + 1
+        } else {
+            return false;
+        };
+        self.decide(format!(
+            "{:.1}ms for {:.1}ms target, level {} -> {}",
+            average, target, self.level, level
+        ));
+        self.level = level;
+        // Frames rendered at the previous level do not count for the next one
+        self.frame_times.clear();
+        true
+    }
+
+    pub fn level(&self) -> usize {
+        self.level
+    }
+
+    pub fn quality(&self) -> QualityLevel {
+        LEVELS[self.level]
+    }
+
+    pub fn average_frame_time(&self) -> Duration {
+        if self.frame_times.is_empty() {
+            return Duration::ZERO;
+        }
+        self.frame_times.iter().sum::<Duration>() / self.frame_times.len() as u32
+    }
+
+    // Most recent first
+    pub fn decisions(&self) -> impl Iterator<Item = &String> {
+        self.decisions.iter().rev()
+    }
+
+    fn decide(&mut self, decision: String) {
+        if self.decisions.len() == MAX_DECISIONS {
+            self.decisions.pop_front();
+        }
+        self.decisions.push_back(decision);
+        self.last_change = Instant::now();
+    }
+}
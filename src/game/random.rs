@@ -0,0 +1,90 @@
+// Deterministic random numbers. Every subsystem draws from its own stream,
+// seeded from the world seed and the stream name only, so that a new consumer
+// does not shift the numbers seen by the existing ones.
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Stream {
+    Terrain,
+    Scattering,
+    Structures,
+    Weather,
+}
+
+impl Stream {
+    pub const ALL: [Stream; 4] = [
+        Stream::Terrain,
+        Stream::Scattering,
+        Stream::Structures,
+        Stream::Weather,
+    ];
+
+    // Part of the stream seed, renaming a stream changes its numbers
+    pub fn name(&self) -> &'static str {
+        match self {
+            Stream::Terrain => "terrain",
+            Stream::Scattering => "scattering",
+            Stream::Structures => "structures",
+            Stream::Weather => "weather",
+        }
+    }
+}
+
+pub struct RandomStreams {
+    world_seed: u64,
+}
+
+impl RandomStreams {
+    pub fn new(world_seed: u64) -> Self {
+        Self { world_seed }
+    }
+
+    pub fn seed(&self, stream: Stream) -> u64 {
+        // FNV-1a, unlike the std hashers it is stable across builds
+        let mut hash = 0xcbf2_9ce4_8422_2325u64;
+        for byte in stream.name().bytes() {
+            hash ^= byte as u64;
+            hash = hash.wrapping_mul(0x0100_0000_01b3);
+        }
+        mix(self.world_seed ^ mix(hash))
+    }
+
+    // A new generator at the start of the stream
+    pub fn stream(&self, stream: Stream) -> Random {
+        Random {
+            state: self.seed(stream),
+        }
+    }
+}
+
+// SplitMix64
+pub struct Random {
+    state: u64,
+}
+
+impl Random {
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        mix(self.state)
+    }
+
+    pub fn next_u32(&mut self) -> u32 {
+        (self.next_u64() >> 32) as u32
+    }
+
+    // Uniform in [0, 1), from the 24 bits an f32 mantissa holds
+    pub fn next_f32(&mut self) -> f32 {
+        (self.next_u64() >> 40) as f32 / (1u64 << 24) as f32
+    }
+}
+
+fn mix(x: u64) -> u64 {
+    let mut z = x;
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+// Uniform in [0, 1) at every point of an integer lattice, for value noise
+pub fn lattice_value(seed: u64, index: i64) -> f32 {
+    (mix(seed ^ mix(index as u64)) >> 40) as f32 / (1u64 << 24) as f32
+}
@@ -0,0 +1,105 @@
+use crate::gfx::Instance;
+use euclid::{Size2D, UnknownUnit};
+use futures::executor::block_on;
+use std::fs::File;
+use std::io::BufWriter;
+use std::num::NonZeroU32;
+use std::path::Path;
+use wgpu::*;
+
+// RGBA8 pixels read back from a color target, row 0 is the top of the image
+pub struct Screenshot {
+    size: Size2D<u32, UnknownUnit>,
+    data: Vec<u8>,
+}
+
+impl Screenshot {
+    pub fn new(size: Size2D<u32, UnknownUnit>) -> Self {
+        Self {
+            size,
+            data: vec![0; size.area() as usize * 4],
+        }
+    }
+
+    // The texture needs COPY_SRC usage and an RGBA8 format
+    #[profiling::function]
+    pub fn capture(
+        instance: &Instance,
+        mut encoder: CommandEncoder,
+        texture: &Texture,
+        size: Size2D<u32, UnknownUnit>,
+    ) -> Self {
+        let device = instance.device();
+        // Rows of a texture copy are padded to the copy alignment
+        let row_size = size.width * 4;
+        let padded_row_size = (row_size + COPY_BYTES_PER_ROW_ALIGNMENT - 1)
+            / COPY_BYTES_PER_ROW_ALIGNMENT
+            * COPY_BYTES_PER_ROW_ALIGNMENT;
+        let buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("screenshot_buffer"),
+            size: (padded_row_size * size.height) as u64,
+            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: NonZeroU32::new(padded_row_size),
+                    rows_per_image: None,
+                },
+            },
+            Extent3d {
+                width: size.width,
+                height: size.height,
+                depth_or_array_layers: 1,
+            },
+        );
+        instance.queue().submit(std::iter::once(encoder.finish()));
+        let buffer_slice = buffer.slice(..);
+        // Called from the main thread so the device is polled here instead
+        // of waiting for the poll in the event loop
+        let mapping = buffer_slice.map_async(MapMode::Read);
+        device.poll(Maintain::Wait);
+        block_on(mapping).unwrap();
+        let data = buffer_slice
+            .get_mapped_range()
+            .chunks(padded_row_size as usize)
+            .flat_map(|x| x[..row_size as usize].to_vec())
+            .collect();
+        buffer.unmap();
+        Self { size, data }
+    }
+
+    // Copies a tile with its top left corner at the pixel, the tile has to
+    // fit inside the image
+    pub fn paste(&mut self, tile: &Screenshot, x: u32, y: u32) {
+        let row_size = tile.size.width as usize * 4;
+        for (row, pixels) in tile.data.chunks(row_size).enumerate() {
+            let offset = ((y as usize + row) * self.size.width as usize + x as usize) * 4;
+            self.data[offset..offset + row_size].copy_from_slice(pixels);
+        }
+    }
+
+    pub fn size(&self) -> Size2D<u32, UnknownUnit> {
+        self.size
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), png::EncodingError> {
+        let file = File::create(path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::RGBA);
+        encoder.set_depth(png::BitDepth::Eight);
+        let mut writer = encoder.write_header()?;
+        writer.write_image_data(&self.data)?;
+        Ok(())
+    }
+}
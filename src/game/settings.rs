@@ -0,0 +1,319 @@
+use crate::gfx::SamplerKey;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
+use wgpu::FilterMode;
+use winit::event::VirtualKeyCode;
+
+pub const CONFIG_PATH: &str = "hinoki.toml";
+const RELOAD_CHECK_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum Action {
+    MoveForward,
+    MoveBackward,
+    TurnLeft,
+    TurnRight,
+    ToggleCrosshair,
+    TogglePhotoMode,
+}
+
+impl Action {
+    pub const ALL: [Action; 6] = [
+        Action::MoveForward,
+        Action::MoveBackward,
+        Action::TurnLeft,
+        Action::TurnRight,
+        Action::ToggleCrosshair,
+        Action::TogglePhotoMode,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Action::MoveForward => "move forward",
+            Action::MoveBackward => "move backward",
+            Action::TurnLeft => "turn left",
+            Action::TurnRight => "turn right",
+            Action::ToggleCrosshair => "toggle crosshair",
+            Action::TogglePhotoMode => "toggle photo mode",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct InputSettings {
+    pub move_forward: VirtualKeyCode,
+    pub move_backward: VirtualKeyCode,
+    pub turn_left: VirtualKeyCode,
+    pub turn_right: VirtualKeyCode,
+    pub toggle_crosshair: VirtualKeyCode,
+    pub toggle_photo_mode: VirtualKeyCode,
+    pub mouse_sensitivity: f32,
+}
+
+impl Default for InputSettings {
+    fn default() -> Self {
+        Self {
+            move_forward: VirtualKeyCode::Up,
+            move_backward: VirtualKeyCode::Down,
+            turn_left: VirtualKeyCode::Left,
+            turn_right: VirtualKeyCode::Right,
+            toggle_crosshair: VirtualKeyCode::F2,
+            toggle_photo_mode: VirtualKeyCode::F3,
+            mouse_sensitivity: 0.003,
+        }
+    }
+}
+
+impl InputSettings {
+    pub fn key(&self, action: Action) -> VirtualKeyCode {
+        match action {
+            Action::MoveForward => self.move_forward,
+            Action::MoveBackward => self.move_backward,
+            Action::TurnLeft => self.turn_left,
+            Action::TurnRight => self.turn_right,
+            Action::ToggleCrosshair => self.toggle_crosshair,
+            Action::TogglePhotoMode => self.toggle_photo_mode,
+        }
+    }
+
+    pub fn set_key(&mut self, action: Action, key: VirtualKeyCode) {
+        match action {
+            Action::MoveForward => self.move_forward = key,
+            Action::MoveBackward => self.move_backward = key,
+            Action::TurnLeft => self.turn_left = key,
+            Action::TurnRight => self.turn_right = key,
+            Action::ToggleCrosshair => self.toggle_crosshair = key,
+            Action::TogglePhotoMode => self.toggle_photo_mode = key,
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TextureFilter {
+    Nearest,
+    Bilinear,
+    Trilinear,
+}
+
+impl TextureFilter {
+    pub const ALL: [TextureFilter; 3] = [
+        TextureFilter::Nearest,
+        TextureFilter::Bilinear,
+        TextureFilter::Trilinear,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            TextureFilter::Nearest => "nearest",
+            TextureFilter::Bilinear => "bilinear",
+            TextureFilter::Trilinear => "trilinear",
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct GraphicsSettings {
+    pub vsync: bool,
+    pub msaa: u32,
+    pub render_scale: f32,
+    pub draw_distance: f32,
+    pub texture_filter: TextureFilter,
+    pub anisotropy: u8,
+    // Lower the render scale and detail distances to hold the target
+    pub auto_quality: bool,
+    pub target_fps: f32,
+    // Quantized lighting, flat faces and outlines, MSAA is off while it is on
+    pub stylized: bool,
+}
+
+impl Default for GraphicsSettings {
+    fn default() -> Self {
+        Self {
+            vsync: false,
+            msaa: 1,
+            render_scale: 1.0,
+            draw_distance: 9000.0,
+            texture_filter: TextureFilter::Trilinear,
+            anisotropy: 1,
+            auto_quality: false,
+            target_fps: 60.0,
+            stylized: false,
+        }
+    }
+}
+
+impl GraphicsSettings {
+    // The outline pass reads the depth target which can not be multisampled
+    pub fn sample_count(&self) -> u32 {
+        if self.stylized {
+            1
+        } else {
+            self.msaa
+        }
+    }
+
+    // Anisotropic filtering needs every filter to be linear so it is only
+    // used with trilinear filtering
+    pub fn sampler_key(&self) -> SamplerKey {
+        let (filter, mipmap_filter) = match self.texture_filter {
+            TextureFilter::Nearest => (FilterMode::Nearest, FilterMode::Nearest),
+            TextureFilter::Bilinear => (FilterMode::Linear, FilterMode::Nearest),
+            TextureFilter::Trilinear => (FilterMode::Linear, FilterMode::Linear),
+        };
+        SamplerKey {
+            mag_filter: filter,
+            min_filter: filter,
+            mipmap_filter,
+            anisotropy: if self.texture_filter == TextureFilter::Trilinear {
+                self.anisotropy
+            } else {
+                1
+            },
+            ..Default::default()
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct LodSettings {
+    pub base_distance: f32,
+    pub growth_factor: f32,
+    pub triangle_budget: u32,
+    // Rings end where the coarser level deviates by fewer pixels, zero only
+    // uses the distances
+    pub max_screen_error: f32,
+}
+
+impl Default for LodSettings {
+    fn default() -> Self {
+        Self {
+            base_distance: 1.0,
+            growth_factor: 2.0,
+            triangle_budget: 100_000,
+            max_screen_error: 0.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
+pub struct StreamingSettings {
+    // Bytes of the voxels of the cached chunks and of the data of the cached
+    // meshes, the least recently used are evicted past them
+    pub chunk_cache_mib: usize,
+    pub mesh_cache_mib: usize,
+    // Least recently used chunks and meshes are evicted once their buffers go
+    // over this, the cache sizes only bound the entries kept on the CPU
+    pub gpu_budget_mib: usize,
+    // Chunk submissions the GPU may work on at once, new chunks wait until
+    // one finishes
+    pub max_gpu_submissions: usize,
+    // Triangles of the cached meshes, over it the far rings are coarsened and
+    // the finest meshes outside of the view evicted. Zero is unlimited.
+    pub resident_triangle_budget: usize,
+    pub worker_count: usize,
+    // Generated chunks are kept on disk and read back when the same chunk is
+    // generated again
+    pub disk_cache: bool,
+    // Chunks evicted from the chunk cache are written to disk for the
+    // session and read back when they are requested again
+    pub spill_evicted_chunks: bool,
+    // While the camera stands still with nothing left to stream, the chunks
+    // just beyond the rings and the impostors about to be needed are prepared
+    pub idle_refinement: bool,
+}
+
+impl Default for StreamingSettings {
+    fn default() -> Self {
+        Self {
+            chunk_cache_mib: 512,
+            mesh_cache_mib: 512,
+            gpu_budget_mib: 512,
+            max_gpu_submissions: 8,
+            resident_triangle_budget: 2_000_000,
+            worker_count: 1,
+            disk_cache: false,
+            spill_evicted_chunks: false,
+            idle_refinement: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Default, Serialize, Deserialize)]
+#[serde(default)]
+pub struct Settings {
+    pub input: InputSettings,
+    pub graphics: GraphicsSettings,
+    pub lod: LodSettings,
+    pub streaming: StreamingSettings,
+}
+
+impl Settings {
+    pub fn load(path: &Path) -> Self {
+        match std::fs::read_to_string(path) {
+            Ok(content) => toml::from_str(&content).unwrap_or_else(|e| {
+                log::warn!("Failed to parse {}: {}", path.display(), e);
+                Self::default()
+            }),
+            Err(_) => Self::default(),
+        }
+    }
+
+    pub fn save(&self, path: &Path) {
+        let content = toml::to_string_pretty(self).unwrap();
+        if let Err(e) = std::fs::write(path, content) {
+            log::warn!("Failed to write {}: {}", path.display(), e);
+        }
+    }
+}
+
+// Keep track of the config file modified time so that editing the file
+// by hand is picked up while the game is running
+pub struct SettingsFile {
+    path: PathBuf,
+    modified: Option<SystemTime>,
+    last_check: Instant,
+}
+
+impl SettingsFile {
+    pub fn new<P: Into<PathBuf>>(path: P) -> Self {
+        let path = path.into();
+        let modified = Self::modified_time(&path);
+        Self {
+            path,
+            modified,
+            last_check: Instant::now(),
+        }
+    }
+
+    pub fn load(&self) -> Settings {
+        Settings::load(&self.path)
+    }
+
+    pub fn save(&mut self, settings: &Settings) {
+        settings.save(&self.path);
+        self.modified = Self::modified_time(&self.path);
+    }
+
+    pub fn poll_reload(&mut self) -> Option<Settings> {
+        if self.last_check.elapsed() < RELOAD_CHECK_INTERVAL {
+            return None;
+        }
+        self.last_check = Instant::now();
+        let modified = Self::modified_time(&self.path);
+        if modified.is_some() && modified != self.modified {
+            self.modified = modified;
+            Some(self.load())
+        } else {
+            None
+        }
+    }
+
+    fn modified_time(path: &Path) -> Option<SystemTime> {
+        std::fs::metadata(path).and_then(|x| x.modified()).ok()
+    }
+}
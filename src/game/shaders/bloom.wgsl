@@ -0,0 +1,66 @@
+// GLOBALS
+// Strength of the blurred glow added back to the scene
+let BLOOM_INTENSITY: f32 = 1.5;
+
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] uv: vec2<f32>;
+};
+
+[[group(0), binding(0)]]
+var source: texture_2d<f32>;
+[[group(0), binding(1)]]
+var source_sampler: sampler;
+// Only read by the composite pass
+[[group(0), binding(2)]]
+var bloom: texture_2d<f32>;
+
+// One triangle covering the whole target
+[[stage(vertex)]]
+fn main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    out.position = vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 0.0, 1.0);
+    out.uv = uv;
+    return out;
+}
+
+// The scene carries the glow of each pixel in its alpha
+[[stage(fragment)]]
+fn extract([[location(0)]] uv: vec2<f32>) -> [[location(0)]] vec4<f32> {
+    let color = textureSample(source, source_sampler, uv);
+    return vec4<f32>(color.rgb * color.a, 1.0);
+}
+
+// 9 tap gaussian in 5 linear samples
+fn blur(uv: vec2<f32>, direction: vec2<f32>) -> vec4<f32> {
+    let texel = direction / vec2<f32>(textureDimensions(source));
+    var color = textureSample(source, source_sampler, uv) * 0.227027;
+    color = color + (
+        textureSample(source, source_sampler, uv + texel * 1.384615) +
+        textureSample(source, source_sampler, uv - texel * 1.384615)
+    ) * 0.316216;
+    color = color + (
+        textureSample(source, source_sampler, uv + texel * 3.230769) +
+        textureSample(source, source_sampler, uv - texel * 3.230769)
+    ) * 0.070270;
+    return color;
+}
+
+[[stage(fragment)]]
+fn blur_x([[location(0)]] uv: vec2<f32>) -> [[location(0)]] vec4<f32> {
+    return blur(uv, vec2<f32>(1.0, 0.0));
+}
+
+[[stage(fragment)]]
+fn blur_y([[location(0)]] uv: vec2<f32>) -> [[location(0)]] vec4<f32> {
+    return blur(uv, vec2<f32>(0.0, 1.0));
+}
+
+// The output is opaque, the glow in the alpha of the scene is dropped
+[[stage(fragment)]]
+fn composite([[location(0)]] uv: vec2<f32>) -> [[location(0)]] vec4<f32> {
+    let scene = textureSample(source, source_sampler, uv).rgb;
+    let glow = textureSample(bloom, source_sampler, uv).rgb;
+    return vec4<f32>(scene + glow * BLOOM_INTENSITY, 1.0);
+}
@@ -0,0 +1,27 @@
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] color: vec4<f32>;
+};
+
+[[block]]
+struct CameraData {
+    view_matrix: mat4x4<f32>;
+    projection_matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var camera_data: CameraData;
+
+// Positions are already in world space
+[[stage(vertex)]]
+fn main([[location(0)]] position: vec4<f32>, [[location(1)]] color: vec4<f32>) -> VertexOutput {
+    var out: VertexOutput;
+    out.position = camera_data.projection_matrix * camera_data.view_matrix * position;
+    out.color = color;
+    return out;
+}
+
+[[stage(fragment)]]
+fn main([[location(0)]] color: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    return color;
+}
@@ -0,0 +1,53 @@
+[[block]]
+struct CameraData {
+    view_matrix: mat4x4<f32>;
+    projection_matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var depth: texture_depth_2d;
+
+[[group(0), binding(1)]]
+var camera_data: CameraData;
+
+let OUTLINE_COLOR: vec3<f32> = vec3<f32>(0.02, 0.02, 0.04);
+// Relative jump of the distance to a neighbour pixel drawn as a silhouette
+let SILHOUETTE_THRESHOLD: f32 = 0.04;
+// Relative bend of the inverse distance drawn as a crease, it is linear in
+// screen space over flat faces so only edges between faces bend it
+let CREASE_THRESHOLD: f32 = 0.004;
+// Creases are lighter than silhouettes
+let CREASE_OPACITY: f32 = 0.5;
+
+// One triangle covering the whole target
+[[stage(vertex)]]
+fn main([[builtin(vertex_index)]] vertex_index: u32) -> [[builtin(position)]] vec4<f32> {
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    return vec4<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0, 1.0, 1.0);
+}
+
+// Inverse of the view space distance of the pixel, from the depth through the
+// projection
+fn inverse_distance(coord: vec2<i32>) -> f32 {
+    let size = textureDimensions(depth);
+    let d = textureLoad(depth, clamp(coord, vec2<i32>(0, 0), size - vec2<i32>(1, 1)), 0);
+    let p = camera_data.projection_matrix;
+    return (d + p[2].z) / p[3].z;
+}
+
+[[stage(fragment)]]
+fn main([[builtin(position)]] coord: vec4<f32>) -> [[location(0)]] vec4<f32> {
+    let c = vec2<i32>(coord.xy);
+    let center = inverse_distance(c);
+    let left = inverse_distance(c - vec2<i32>(1, 0));
+    let right = inverse_distance(c + vec2<i32>(1, 0));
+    let up = inverse_distance(c - vec2<i32>(0, 1));
+    let down = inverse_distance(c + vec2<i32>(0, 1));
+    // Only the farther side of a jump is drawn, the line lies just outside of
+    // the object in front
+    let nearest = max(max(left, right), max(up, down));
+    let silhouette = step(SILHOUETTE_THRESHOLD, 1.0 - center / max(nearest, 0.000001));
+    let crease = step(CREASE_THRESHOLD, abs(left + right + up + down - 4.0 * center) / max(center, 0.000001));
+    let opacity = max(silhouette, crease * CREASE_OPACITY);
+    return vec4<f32>(OUTLINE_COLOR, opacity);
+}
@@ -0,0 +1,142 @@
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] direction: vec3<f32>;
+};
+
+[[block]]
+struct CameraData {
+    view_matrix: mat4x4<f32>;
+    projection_matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var camera_data: CameraData;
+
+// Keep in sync with sky.rs
+[[block]]
+struct SkyData {
+    sun_direction: vec3<f32>;
+    star_rotation: f32;
+    moon_direction: vec3<f32>;
+    pole: vec3<f32>;
+};
+
+[[group(0), binding(1)]]
+var sky_data: SkyData;
+
+let DAY_ZENITH: vec3<f32> = vec3<f32>(0.25, 0.45, 0.85);
+let DAY_HORIZON: vec3<f32> = vec3<f32>(0.65, 0.8, 0.95);
+let NIGHT_ZENITH: vec3<f32> = vec3<f32>(0.005, 0.01, 0.03);
+let NIGHT_HORIZON: vec3<f32> = vec3<f32>(0.03, 0.05, 0.1);
+let TWILIGHT_COLOR: vec3<f32> = vec3<f32>(0.9, 0.45, 0.2);
+let SUN_COLOR: vec3<f32> = vec3<f32>(1.0, 0.95, 0.8);
+let MOON_COLOR: vec3<f32> = vec3<f32>(0.85, 0.88, 0.95);
+// Cosine of the angular radius of the discs
+let SUN_SIZE: f32 = 0.9995;
+let MOON_SIZE: f32 = 0.9993;
+// Light of the unlit part of the moon
+let EARTHSHINE: f32 = 0.06;
+// Star cells per unit of the direction, the share of cells holding a star and
+// the radius of a star inside its cell
+let STAR_DENSITY: f32 = 150.0;
+let STAR_COVERAGE: f32 = 0.08;
+let STAR_SIZE: f32 = 0.12;
+// Glow of the sun, the moon and the stars picked up by the bloom pass
+let SUN_GLOW: f32 = 1.0;
+let MOON_GLOW: f32 = 0.6;
+let STAR_GLOW: f32 = 0.4;
+
+// One triangle covering the whole target, each vertex carries the direction
+// of its view ray so that tiled screenshots see the sky of their tile
+[[stage(vertex)]]
+fn main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let uv = vec2<f32>(f32((vertex_index << 1u) & 2u), f32(vertex_index & 2u));
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    out.position = vec4<f32>(ndc, 1.0, 1.0);
+    let p = camera_data.projection_matrix;
+    let view = vec3<f32>(
+        (ndc.x + p[2].x) / p[0].x,
+        (ndc.y + p[2].y) / p[1].y,
+        -1.0
+    );
+    // The rotation of the view matrix is orthonormal, its transpose takes
+    // the ray back to world space
+    let v = camera_data.view_matrix;
+    out.direction = vec3<f32>(dot(v[0].xyz, view), dot(v[1].xyz, view), dot(v[2].xyz, view));
+    return out;
+}
+
+fn hash3(p: vec3<f32>) -> vec3<f32> {
+    let q = vec3<f32>(
+        dot(p, vec3<f32>(127.1, 311.7, 74.7)),
+        dot(p, vec3<f32>(269.5, 183.3, 246.1)),
+        dot(p, vec3<f32>(113.5, 271.9, 124.6))
+    );
+    return fract(sin(q) * 43758.5453);
+}
+
+// Points scattered in a grid of cells around the sphere of directions, kept
+// away from the borders of their cell so that only one cell is looked at
+fn stars(direction: vec3<f32>) -> f32 {
+    let p = direction * STAR_DENSITY;
+    let cell = floor(p);
+    let h = hash3(cell);
+    if (h.z > STAR_COVERAGE) {
+        return 0.0;
+    }
+    let center = cell + vec3<f32>(0.5, 0.5, 0.5) + (h - vec3<f32>(0.5, 0.5, 0.5)) * 0.5;
+    let brightness = 0.3 + 0.7 * h.z / STAR_COVERAGE;
+    return (1.0 - smoothStep(0.0, STAR_SIZE, length(p - center))) * brightness;
+}
+
+// Turns the direction back by the rotation of the stars around the pole
+fn to_star_space(direction: vec3<f32>) -> vec3<f32> {
+    let k = sky_data.pole;
+    let c = cos(-sky_data.star_rotation);
+    let s = sin(-sky_data.star_rotation);
+    return direction * c + cross(k, direction) * s + k * dot(k, direction) * (1.0 - c);
+}
+
+// Disc of the moon lit from the side of the sun
+fn moon(direction: vec3<f32>) -> f32 {
+    let m = sky_data.moon_direction;
+    let cosine = dot(direction, m);
+    if (cosine < MOON_SIZE) {
+        return 0.0;
+    }
+    let radius = sqrt(1.0 - MOON_SIZE * MOON_SIZE);
+    let offset = (direction - m * cosine) / radius;
+    let normal = offset + m * sqrt(max(1.0 - dot(offset, offset), 0.0));
+    let lit = smoothStep(-0.05, 0.05, dot(normal, sky_data.sun_direction));
+    return mix(EARTHSHINE, 1.0, lit);
+}
+
+[[stage(fragment)]]
+fn main([[location(0)]] direction: vec3<f32>) -> [[location(0)]] vec4<f32> {
+    let ray = normalize(direction);
+    let sun = sky_data.sun_direction;
+    let day = smoothStep(-0.15, 0.15, sun.z);
+    let height = clamp(ray.z, 0.0, 1.0);
+    let zenith = mix(NIGHT_ZENITH, DAY_ZENITH, day);
+    let horizon = mix(NIGHT_HORIZON, DAY_HORIZON, day);
+    var color = mix(horizon, zenith, sqrt(height));
+    // The horizon on the side of the sun glows while it rises and sets
+    let twilight = (1.0 - smoothStep(0.0, 0.25, abs(sun.z))) * (1.0 - height);
+    let toward_sun = max(dot(ray.xy, normalize(sun.xy + vec2<f32>(0.0001, 0.0))), 0.0);
+    color = mix(color, TWILIGHT_COLOR, twilight * toward_sun * toward_sun);
+    var glow = 0.0;
+    // Stars and the moon fade in as the sky darkens, all sink below the horizon
+    let above = smoothStep(-0.02, 0.02, ray.z);
+    let night = (1.0 - day) * above;
+    let star = stars(to_star_space(ray)) * night;
+    color = color + vec3<f32>(star, star, star);
+    glow = glow + star * STAR_GLOW;
+    let moon_light = moon(ray) * above;
+    color = mix(color, MOON_COLOR * moon_light, step(0.001, moon_light));
+    glow = glow + moon_light * MOON_GLOW * (1.0 - day);
+    let sun_disc = step(SUN_SIZE, dot(ray, sun)) * above;
+    color = mix(color, SUN_COLOR, sun_disc);
+    glow = glow + sun_disc * SUN_GLOW;
+    return vec4<f32>(color, glow);
+}
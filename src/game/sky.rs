@@ -0,0 +1,209 @@
+use crate::gfx::Instance;
+use std::f32::consts::TAU;
+use std::mem::size_of;
+use std::time::Duration;
+use wgpu::*;
+
+// Real time a whole day takes while the cycle runs
+const DAY_LENGTH: Duration = Duration::from_secs(600);
+// Angle between the path of the sun and the zenith, the stars turn around
+// the axis of that path
+const SUN_TILT: f32 = 0.4;
+// Days for the moon to fall behind the sun by a whole turn
+const LUNAR_MONTH: f32 = 29.5;
+// Noon of the first day, under a full moon at night
+const START_TIME: f32 = 0.5;
+const START_MOON_PHASE: f32 = 0.5;
+
+// Keep in sync with sky.wgsl
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct SkyData {
+    sun_direction: [f32; 3],
+    // Angle the stars turned by around the pole
+    star_rotation: f32,
+    moon_direction: [f32; 3],
+    _pad0: f32,
+    pole: [f32; 3],
+    _pad1: f32,
+}
+
+// Time of day in days since the start, the fraction is the time of the day
+// and the whole part counts the days for the phase of the moon
+pub struct Sky {
+    time: f32,
+    cycle: bool,
+    pipeline: Option<RenderPipeline>,
+    sky_buffer: Option<Buffer>,
+    // One for each camera buffer
+    bind_groups: Vec<BindGroup>,
+}
+
+impl Sky {
+    pub fn new() -> Self {
+        Self {
+            time: START_TIME,
+            cycle: false,
+            pipeline: None,
+            sky_buffer: None,
+            bind_groups: vec![],
+        }
+    }
+
+    // Needs to be called again when the sample count changes
+    pub fn init(
+        &mut self,
+        instance: &Instance,
+        camera_buffers: &[Buffer],
+        target_format: TextureFormat,
+        sample_count: u32,
+    ) {
+        let device = instance.device();
+        let uniform_entry = |binding: u32, visibility: ShaderStages| BindGroupLayoutEntry {
+            binding,
+            visibility,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Uniform,
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("sky_bind_group_layout"),
+            entries: &[
+                // view + projection matrix
+                uniform_entry(0, ShaderStages::VERTEX),
+                // sun, moon and stars
+                uniform_entry(1, ShaderStages::FRAGMENT),
+            ],
+        });
+        let sky_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("sky_uniform_buffer"),
+            size: size_of::<SkyData>() as u64,
+            mapped_at_creation: false,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+        });
+        self.bind_groups = camera_buffers
+            .iter()
+            .map(|camera_buffer| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    label: Some("sky_bind_group"),
+                    layout: &bind_group_layout,
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: camera_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &sky_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                    ],
+                })
+            })
+            .collect();
+        self.sky_buffer = Some(sky_buffer);
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("sky_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/sky.wgsl"));
+        self.pipeline = Some(device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("sky_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            primitive: PrimitiveState::default(),
+            // Drawn first behind everything, the depth is left cleared
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: false,
+                depth_compare: CompareFunction::Always,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "main",
+                targets: &[ColorTargetState {
+                    format: target_format,
+                    blend: None,
+                    write_mask: ColorWrites::ALL,
+                }],
+            }),
+        }));
+    }
+
+    // From 0 at midnight to 24
+    pub fn hours(&self) -> f32 {
+        self.time.fract() * 24.0
+    }
+
+    pub fn set_hours(&mut self, hours: f32) {
+        self.time = self.time.floor() + hours / 24.0;
+    }
+
+    pub fn cycle_mut(&mut self) -> &mut bool {
+        &mut self.cycle
+    }
+
+    pub fn update(&mut self, elapsed_time: Duration) {
+        if self.cycle {
+            self.time += elapsed_time.as_secs_f32() / DAY_LENGTH.as_secs_f32();
+        }
+    }
+
+    // Upload the positions of the sun and the moon, call before the render pass
+    pub fn prepare(&self, instance: &Instance) {
+        let sky_buffer = match self.sky_buffer.as_ref() {
+            Some(sky_buffer) => sky_buffer,
+            None => return,
+        };
+        // The sun rises along x at 6 and is highest at noon
+        let sun_angle = (self.time.fract() - 0.25) * TAU;
+        let phase = (self.time / LUNAR_MONTH + START_MOON_PHASE).fract();
+        let moon_angle = sun_angle - phase * TAU;
+        let (sin_tilt, cos_tilt) = SUN_TILT.sin_cos();
+        let on_path = |angle: f32| {
+            let (sin, cos) = angle.sin_cos();
+            [cos, sin * sin_tilt, sin * cos_tilt]
+        };
+        let data = SkyData {
+            sun_direction: on_path(sun_angle),
+            star_rotation: sun_angle,
+            moon_direction: on_path(moon_angle),
+            _pad0: 0.0,
+            pole: [0.0, -cos_tilt, sin_tilt],
+            _pad1: 0.0,
+        };
+        instance
+            .queue()
+            .write_buffer(sky_buffer, 0, bytemuck::bytes_of(&data));
+    }
+
+    pub fn render<'a>(&'a self, rp: &mut RenderPass<'a>, frame: usize) {
+        if self.bind_groups.is_empty() {
+            return;
+        }
+        rp.set_pipeline(self.pipeline.as_ref().unwrap());
+        rp.set_bind_group(0, &self.bind_groups[frame], &[]);
+        rp.draw(0..3, 0..1);
+    }
+}
@@ -0,0 +1,237 @@
+use crate::gfx::Instance;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use wgpu::*;
+
+// Allocations are bound as storage buffers as well, see calculate_normals
+const ARENA_ALIGNMENT: BufferAddress = 256;
+pub const VERTEX_ARENA_SIZE: BufferAddress = 128 << 20;
+pub const INDEX_ARENA_SIZE: BufferAddress = 32 << 20;
+
+fn align(size: BufferAddress) -> BufferAddress {
+    (size + ARENA_ALIGNMENT - 1) / ARENA_ALIGNMENT * ARENA_ALIGNMENT
+}
+
+// Free ranges of an arena as offset and size, kept sorted by offset and
+// merged with their neighbors when given back
+struct FreeList {
+    ranges: Vec<(BufferAddress, BufferAddress)>,
+}
+
+impl FreeList {
+    fn new(size: BufferAddress) -> Self {
+        Self {
+            ranges: vec![(0, size)],
+        }
+    }
+
+    // First range that fits the aligned size, split off from its start
+    fn take(&mut self, size: BufferAddress) -> Option<BufferAddress> {
+        let size = align(size);
+        let i = self.ranges.iter().position(|(_, x)| *x >= size)?;
+        let (offset, free_size) = self.ranges[i];
+        if free_size == size {
+            self.ranges.remove(i);
+        } else {
+            self.ranges[i] = (offset + size, free_size - size);
+        }
+        Some(offset)
+    }
+
+    fn give_back(&mut self, offset: BufferAddress, size: BufferAddress) {
+        let ranges = &mut self.ranges;
+        let i = ranges.partition_point(|(x, _)| *x < offset);
+        ranges.insert(i, (offset, align(size)));
+        if i + 1 < ranges.len() && ranges[i].0 + ranges[i].1 == ranges[i + 1].0 {
+            ranges[i].1 += ranges[i + 1].1;
+            ranges.remove(i + 1);
+        }
+        if i > 0 && ranges[i - 1].0 + ranges[i - 1].1 == ranges[i].0 {
+            ranges[i - 1].1 += ranges[i].1;
+            ranges.remove(i);
+        }
+    }
+}
+
+// One large buffer the chunk meshes are sub-allocated from
+pub struct BufferArena {
+    buffer: Buffer,
+    label: &'static str,
+    usage: BufferUsages,
+    free: Mutex<FreeList>,
+}
+
+impl BufferArena {
+    pub fn new(
+        instance: &Instance,
+        label: &'static str,
+        size: BufferAddress,
+        usage: BufferUsages,
+    ) -> Arc<Self> {
+        let usage = usage | BufferUsages::COPY_DST;
+        Arc::new(Self {
+            buffer: instance.device().create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                mapped_at_creation: false,
+                usage,
+            }),
+            label,
+            usage,
+            free: Mutex::new(FreeList::new(size)),
+        })
+    }
+
+    // Falls back to a buffer of its own once the arena is full
+    pub fn allocate(self: &Arc<Self>, instance: &Instance, contents: &[u8]) -> ArenaBuffer {
+        // Empty slices would reach to the end of the arena
+        let size = (contents.len() as BufferAddress).max(COPY_BUFFER_ALIGNMENT);
+        let offset = self.free.lock().take(size);
+        let (arena, buffer, offset) = match offset {
+            Some(offset) => (Some(self.clone()), None, offset),
+            None => {
+                let buffer = instance.device().create_buffer(&BufferDescriptor {
+                    label: Some(self.label),
+                    size,
+                    mapped_at_creation: false,
+                    usage: self.usage,
+                });
+                (None, Some(buffer), 0)
+            }
+        };
+        let arena_buffer = ArenaBuffer {
+            arena,
+            buffer,
+            offset,
+            size,
+        };
+        if !contents.is_empty() {
+            instance
+                .queue()
+                .write_buffer(arena_buffer.buffer(), offset, contents);
+        }
+        arena_buffer
+    }
+}
+
+// Vertex and index buffers of the chunk meshes. They outlive the pipelines so
+// that swapping them does not allocate another pair next to the ranges the
+// retired meshes still hold.
+pub struct MeshArenas {
+    pub vertex: Arc<BufferArena>,
+    pub index: Arc<BufferArena>,
+}
+
+impl MeshArenas {
+    pub fn new(instance: &Instance) -> Self {
+        Self {
+            vertex: BufferArena::new(
+                instance,
+                "terrain_vertex_arena",
+                VERTEX_ARENA_SIZE,
+                BufferUsages::VERTEX | BufferUsages::STORAGE,
+            ),
+            index: BufferArena::new(
+                instance,
+                "terrain_index_arena",
+                INDEX_ARENA_SIZE,
+                BufferUsages::INDEX | BufferUsages::STORAGE,
+            ),
+        }
+    }
+}
+
+// Range of an arena, or a buffer of its own when the arena was full. The range
+// is given back to the arena when dropped.
+pub struct ArenaBuffer {
+    arena: Option<Arc<BufferArena>>,
+    buffer: Option<Buffer>,
+    offset: BufferAddress,
+    size: BufferAddress,
+}
+
+impl ArenaBuffer {
+    fn buffer(&self) -> &Buffer {
+        match self.arena.as_ref() {
+            Some(arena) => &arena.buffer,
+            None => self.buffer.as_ref().unwrap(),
+        }
+    }
+
+    pub fn slice(&self) -> BufferSlice {
+        self.buffer().slice(self.offset..self.offset + self.size)
+    }
+
+    pub fn binding(&self) -> BindingResource {
+        BindingResource::Buffer(BufferBinding {
+            buffer: self.buffer(),
+            offset: self.offset,
+            size: BufferSize::new(self.size),
+        })
+    }
+}
+
+impl Drop for ArenaBuffer {
+    fn drop(&mut self) {
+        if let Some(arena) = self.arena.as_ref() {
+            arena.free.lock().give_back(self.offset, self.size);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{FreeList, ARENA_ALIGNMENT};
+
+    const A: u64 = ARENA_ALIGNMENT;
+
+    #[test]
+    fn take_splits_the_first_range_that_fits() {
+        let mut free = FreeList::new(8 * A);
+        assert_eq!(free.take(1), Some(0));
+        assert_eq!(free.take(A + 1), Some(A));
+        assert_eq!(free.ranges, vec![(3 * A, 5 * A)]);
+    }
+
+    #[test]
+    fn take_removes_a_range_it_fills() {
+        let mut free = FreeList::new(2 * A);
+        assert_eq!(free.take(2 * A), Some(0));
+        assert!(free.ranges.is_empty());
+        assert_eq!(free.take(1), None);
+    }
+
+    #[test]
+    fn take_skips_ranges_too_small() {
+        let mut free = FreeList::new(4 * A);
+        let first = free.take(A).unwrap();
+        free.take(A).unwrap();
+        free.give_back(first, A);
+        assert_eq!(free.take(2 * A), Some(2 * A));
+        assert_eq!(free.take(A), Some(0));
+        assert_eq!(free.take(1), None);
+    }
+
+    #[test]
+    fn give_back_merges_with_both_neighbors() {
+        let mut free = FreeList::new(4 * A);
+        let offsets = (0..4).map(|_| free.take(A).unwrap()).collect::<Vec<_>>();
+        free.give_back(offsets[0], A);
+        free.give_back(offsets[2], A);
+        assert_eq!(free.ranges, vec![(0, A), (2 * A, A)]);
+        // Merges with the previous range
+        free.give_back(offsets[1], A);
+        assert_eq!(free.ranges, vec![(0, 3 * A)]);
+        // Merges with the next one once it is back
+        free.give_back(offsets[3], A);
+        assert_eq!(free.ranges, vec![(0, 4 * A)]);
+    }
+
+    #[test]
+    fn give_back_merges_with_the_next_range() {
+        let mut free = FreeList::new(4 * A);
+        let first = free.take(A).unwrap();
+        free.give_back(first, A);
+        assert_eq!(free.ranges, vec![(0, 4 * A)]);
+    }
+}
@@ -0,0 +1,127 @@
+use super::TerrainTask;
+use crate::gfx::Instance;
+use futures::task::SpawnExt;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, AtomicUsize, Ordering};
+use std::sync::Arc;
+use wgpu::*;
+
+// Chunk compute passes recorded before the batch is submitted without waiting
+// for the next frame
+pub const MAX_BATCHED_PASSES: usize = 16;
+
+struct PendingPasses {
+    command_buffers: Vec<CommandBuffer>,
+    // Tickets handed out so far
+    recorded: u64,
+}
+
+// Chunk compute passes recorded by the workers, submitted together once a
+// frame or once a worker runs out of other tasks and waits on one of them,
+// instead of one submission per chunk. Each pass gets a ticket, the
+// buffers it writes can only be mapped once its ticket is submitted.
+//
+// Submissions the GPU has not finished are counted against a budget. Once it
+// is used up new chunks are parked instead of dispatched, and released again
+// as the GPU signals the submissions done.
+pub struct SubmissionBatch {
+    pending: Mutex<PendingPasses>,
+    submitted: AtomicU64,
+    in_flight: Arc<AtomicUsize>,
+    max_in_flight: AtomicUsize,
+    parked: Mutex<Vec<TerrainTask>>,
+}
+
+impl SubmissionBatch {
+    pub fn new(max_in_flight: usize) -> Self {
+        Self {
+            pending: Mutex::new(PendingPasses {
+                command_buffers: vec![],
+                recorded: 0,
+            }),
+            submitted: AtomicU64::new(0),
+            in_flight: Arc::new(AtomicUsize::new(0)),
+            max_in_flight: AtomicUsize::new(max_in_flight.max(1)),
+            parked: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn set_max_in_flight(&self, max_in_flight: usize) {
+        self.max_in_flight
+            .store(max_in_flight.max(1), Ordering::Release);
+    }
+
+    pub fn in_flight(&self) -> usize {
+        self.in_flight.load(Ordering::Acquire)
+    }
+
+    pub fn has_capacity(&self) -> bool {
+        self.in_flight() < self.max_in_flight.load(Ordering::Acquire)
+    }
+
+    pub fn park(&self, task: TerrainTask) {
+        self.parked.lock().push(task);
+    }
+
+    pub fn parked_count(&self) -> usize {
+        self.parked.lock().len()
+    }
+
+    // Oldest parked tasks that fit in the free submissions, each submission
+    // takes up to a full batch of passes
+    pub fn unpark(&self) -> Vec<TerrainTask> {
+        let free = self
+            .max_in_flight
+            .load(Ordering::Acquire)
+            .saturating_sub(self.in_flight());
+        let mut parked = self.parked.lock();
+        let count = (free * MAX_BATCHED_PASSES).min(parked.len());
+        parked.drain(..count).collect()
+    }
+
+    // Returns the ticket of the pass
+    pub fn push(&self, instance: &Instance, encoder: CommandEncoder) -> u64 {
+        let mut pending = self.pending.lock();
+        pending.command_buffers.push(encoder.finish());
+        pending.recorded += 1;
+        let ticket = pending.recorded;
+        if pending.command_buffers.len() >= MAX_BATCHED_PASSES {
+            self.submit(instance, &mut pending);
+        }
+        ticket
+    }
+
+    pub fn flush(&self, instance: &Instance) {
+        self.submit(instance, &mut self.pending.lock());
+    }
+
+    // Submits the encoder right away, after the passes of the batch so that
+    // it sees what they wrote
+    pub fn submit_after(&self, instance: &Instance, encoder: CommandEncoder) {
+        let mut pending = self.pending.lock();
+        pending.command_buffers.push(encoder.finish());
+        self.submit(instance, &mut pending);
+    }
+
+    pub fn is_submitted(&self, ticket: u64) -> bool {
+        self.submitted.load(Ordering::Acquire) >= ticket
+    }
+
+    fn submit(&self, instance: &Instance, pending: &mut PendingPasses) {
+        if pending.command_buffers.is_empty() {
+            return;
+        }
+        instance.queue().submit(pending.command_buffers.drain(..));
+        self.submitted.store(pending.recorded, Ordering::Release);
+        self.in_flight.fetch_add(1, Ordering::AcqRel);
+        let in_flight = self.in_flight.clone();
+        let done = instance.queue().on_submitted_work_done();
+        instance
+            .async_pool()
+            .spawn(async move {
+                done.await;
+                in_flight.fetch_sub(1, Ordering::AcqRel);
+            })
+            .unwrap();
+    }
+}
@@ -0,0 +1,114 @@
+pub const BIOME_COUNT: usize = 4;
+pub const BIOME_NAMES: [&str; BIOME_COUNT] = ["ocean", "plains", "desert", "mountain"];
+
+// Water table of a biome. The sea level is blended with the neighbouring
+// biomes while lakes are carved where the lake noise goes over the coverage.
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod, Default)]
+#[repr(C)]
+pub struct Biome {
+    pub sea_level: f32,
+    pub lake_level: f32,
+    // Fraction of the biome covered by lakes
+    pub lake_coverage: f32,
+    pub lake_depth: f32,
+}
+
+// Ocean, plains, desert, mountain. Keep in sync with generate_voxel.wgsl
+pub const BIOMES: [Biome; BIOME_COUNT] = [
+    // Ocean
+    Biome {
+        sea_level: -0.05,
+        lake_level: 0.0,
+        lake_coverage: 0.0,
+        lake_depth: 0.0,
+    },
+    // Plains
+    Biome {
+        sea_level: -0.05,
+        lake_level: 0.02,
+        lake_coverage: 0.15,
+        lake_depth: 0.03,
+    },
+    // Desert, the sea is pushed down so only oases hold water
+    Biome {
+        sea_level: -0.3,
+        lake_level: 0.01,
+        lake_coverage: 0.05,
+        lake_depth: 0.02,
+    },
+    // Mountain lakes
+    Biome {
+        sea_level: -0.05,
+        lake_level: 0.15,
+        lake_coverage: 0.2,
+        lake_depth: 0.05,
+    },
+];
+
+// Look of a biome, read by render.wgsl and by object scattering. Keep in sync
+// with render.wgsl
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+pub struct BiomeStyle {
+    pub color: [f32; 3],
+    // Height above which the ground is covered in snow
+    pub snow_line: f32,
+    // Light given off by the walls of caves in the biome, picked up by the
+    // bloom pass
+    pub emissive: [f32; 3],
+    // Objects scattered per square world unit
+    pub scatter_density: f32,
+}
+
+// Same order as BIOMES
+pub const BIOME_STYLES: [BiomeStyle; BIOME_COUNT] = [
+    // Ocean floor
+    BiomeStyle {
+        color: [0.76, 0.7, 0.5],
+        snow_line: f32::MAX,
+        emissive: [0.0; 3],
+        scatter_density: 0.0,
+    },
+    // Plains
+    BiomeStyle {
+        color: [0.3, 0.6, 0.2],
+        snow_line: 0.4,
+        emissive: [0.0; 3],
+        scatter_density: 100.0,
+    },
+    // Desert, never snowy, lava flows in its caves
+    BiomeStyle {
+        color: [0.9, 0.75, 0.45],
+        snow_line: f32::MAX,
+        emissive: [1.0, 0.35, 0.05],
+        scatter_density: 20.0,
+    },
+    // Mountain, crystals grow in its caves
+    BiomeStyle {
+        color: [0.45, 0.42, 0.4],
+        snow_line: 0.25,
+        emissive: [0.3, 0.6, 1.0],
+        scatter_density: 300.0,
+    },
+];
+
+// Biome with the largest weight
+pub fn dominant_biome(weights: &[f32; BIOME_COUNT]) -> usize {
+    (0..BIOME_COUNT)
+        .max_by(|a, b| weights[*a].partial_cmp(&weights[*b]).unwrap())
+        .unwrap()
+}
+
+// Biome whose share of the cumulative weights contains the threshold in
+// [0, 1), thresholds that vary from point to point dither the border between
+// biomes. Keep in sync with render.wgsl
+pub fn pick_biome(weights: &[f32; BIOME_COUNT], threshold: f32) -> usize {
+    let mut sum = 0.0;
+    for (i, weight) in weights.iter().enumerate() {
+        sum += weight;
+        if threshold < sum {
+            return i;
+        }
+    }
+    BIOME_COUNT - 1
+}
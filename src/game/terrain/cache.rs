@@ -1,50 +1,226 @@
 use priority_queue::PriorityQueue;
 use std::cmp::Reverse;
-use std::collections::HashMap;
-use std::time::Instant;
+use std::collections::{HashMap, HashSet};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
 
+// Counts are since the cache was created, clearing it does not reset them
+#[derive(Debug, Copy, Clone, Default)]
+pub struct CacheStats {
+    pub entries: usize,
+    pub pinned: usize,
+    pub hits: u64,
+    pub misses: u64,
+    pub evictions: u64,
+    pub total_weight: u64,
+    pub max_weight: u64,
+}
+
+#[derive(Debug, Clone)]
+pub struct CacheEntry<K> {
+    pub key: K,
+    // Since the entry was last accessed
+    pub age: Duration,
+    pub weight: u64,
+    pub pinned: bool,
+}
+
+// Least recently used entries are evicted once the weight of the entries goes
+// over the maximum, values can be weighted by the bytes they hold so that a
+// few large entries do not count as little as many small ones
 pub struct Cache<K, V>
 where
     K: std::hash::Hash + Eq,
 {
     cache: HashMap<K, V>,
     last_accessed: PriorityQueue<K, Reverse<Instant>>,
-    max_size: usize,
+    // Taken when the value is inserted and again by update_weight
+    weights: HashMap<K, u64>,
+    weight: fn(&V) -> u64,
+    total_weight: u64,
+    max_weight: u64,
+    // Skipped by the eviction, the cache goes over its size instead
+    pinned: HashSet<K>,
+    // Lookups go through shared references behind the read locks
+    hits: AtomicU64,
+    misses: AtomicU64,
+    evictions: u64,
+    // Given the evicted entries instead of dropping them, called with the
+    // cache locked so it should only hand them off
+    on_evict: Option<Box<dyn Fn(K, V) + Send + Sync>>,
 }
 
 impl<K, V> Cache<K, V>
 where
     K: Clone + std::hash::Hash + Eq,
 {
-    pub fn new(max_size: usize) -> Self {
+    pub fn new(max_weight: u64, weight: fn(&V) -> u64) -> Self {
         Self {
             cache: HashMap::new(),
             last_accessed: PriorityQueue::new(),
-            max_size,
+            weights: HashMap::new(),
+            weight,
+            total_weight: 0,
+            max_weight,
+            pinned: HashSet::new(),
+            hits: AtomicU64::new(0),
+            misses: AtomicU64::new(0),
+            evictions: 0,
+            on_evict: None,
         }
     }
 
+    pub fn with_on_evict<F>(mut self, on_evict: F) -> Self
+    where
+        F: Fn(K, V) + Send + Sync + 'static,
+    {
+        self.on_evict = Some(Box::new(on_evict));
+        self
+    }
+
     pub fn get(&self, key: &K) -> Option<&V> {
-        self.cache.get(key)
+        let value = self.cache.get(key);
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
     }
 
     pub fn get_mut(&mut self, key: &K) -> Option<&mut V> {
-        self.cache.get_mut(key)
+        let value = self.cache.get_mut(key);
+        let counter = if value.is_some() {
+            &self.hits
+        } else {
+            &self.misses
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+        value
     }
 
-    pub fn insert(&mut self, key: &K, value: V) {
+    // Returns the value the key held before, the evicted ones go to on_evict
+    pub fn insert(&mut self, key: &K, value: V) -> Option<V> {
         self.insert_with_priority(key, value, Reverse(Instant::now()))
     }
 
-    pub fn insert_with_priority(&mut self, key: &K, value: V, priority: Reverse<Instant>) {
+    pub fn insert_with_priority(
+        &mut self,
+        key: &K,
+        value: V,
+        priority: Reverse<Instant>,
+    ) -> Option<V> {
         self.last_accessed.push_decrease(key.clone(), priority);
-        self.cache.insert(key.clone(), value);
-        if self.cache.len() > self.max_size {
-            let (key, _) = self.last_accessed.pop().unwrap();
-            self.cache.remove(&key);
+        let weight = (self.weight)(&value);
+        self.total_weight += weight;
+        if let Some(previous) = self.weights.insert(key.clone(), weight) {
+            self.total_weight -= previous;
+        }
+        let previous = self.cache.insert(key.clone(), value);
+        self.evict();
+        previous
+    }
+
+    // Values changed through get_mut or iter_mut keep their old weight until
+    // this is called, which may evict other entries or the key itself
+    pub fn update_weight(&mut self, key: &K) {
+        let weight = match self.cache.get(key) {
+            Some(value) => (self.weight)(value),
+            None => return,
+        };
+        self.total_weight += weight;
+        if let Some(previous) = self.weights.insert(key.clone(), weight) {
+            self.total_weight -= previous;
+        }
+        self.evict();
+    }
+
+    pub fn update_weights(&mut self) {
+        for key in self.cache.keys().cloned().collect::<Vec<_>>() {
+            self.update_weight(&key);
+        }
+    }
+
+    pub fn set_max_weight(&mut self, max_weight: u64) {
+        self.max_weight = max_weight;
+        self.evict();
+    }
+
+    pub fn total_weight(&self) -> u64 {
+        self.total_weight
+    }
+
+    // Replaces the pinned keys, they do not have to be in the cache yet.
+    // Removing an entry still works whether it is pinned or not.
+    pub fn set_pinned<I>(&mut self, keys: I)
+    where
+        I: IntoIterator<Item = K>,
+    {
+        self.pinned = keys.into_iter().collect();
+        self.evict();
+    }
+
+    fn evict(&mut self) {
+        let mut skipped = vec![];
+        while self.total_weight > self.max_weight {
+            match self.last_accessed.pop() {
+                Some((key, priority)) if self.pinned.contains(&key) => {
+                    skipped.push((key, priority))
+                }
+                Some((key, _)) => {
+                    let value = self.remove_entry(&key);
+                    self.evictions += 1;
+                    if let (Some(value), Some(on_evict)) = (value, &self.on_evict) {
+                        on_evict(key, value);
+                    }
+                }
+                // Everything left is pinned
+                None => break,
+            }
+        }
+        for (key, priority) in skipped {
+            self.last_accessed.push(key, priority);
         }
     }
 
+    pub fn stats(&self) -> CacheStats {
+        CacheStats {
+            entries: self.cache.len(),
+            pinned: self
+                .pinned
+                .iter()
+                .filter(|x| self.cache.contains_key(x))
+                .count(),
+            hits: self.hits.load(Ordering::Relaxed),
+            misses: self.misses.load(Ordering::Relaxed),
+            evictions: self.evictions,
+            total_weight: self.total_weight,
+            max_weight: self.max_weight,
+        }
+    }
+
+    // Least recently accessed first, the order they are evicted in
+    pub fn entries(&self) -> Vec<CacheEntry<K>> {
+        let now = Instant::now();
+        let mut entries = self
+            .last_accessed
+            .iter()
+            .map(|(key, priority)| CacheEntry {
+                key: key.clone(),
+                age: now.saturating_duration_since(priority.0),
+                weight: self.weights.get(key).copied().unwrap_or(0),
+                pinned: self.pinned.contains(key),
+            })
+            .collect::<Vec<_>>();
+        entries.sort_by(|a, b| b.age.cmp(&a.age));
+        entries
+    }
+
+    pub fn last_accessed(&self, key: &K) -> Option<Instant> {
+        self.last_accessed.get_priority(key).map(|x| x.0)
+    }
+
     pub fn update_last_accessed(&mut self, key: &K) {
         if self.cache.contains_key(key) {
             self.last_accessed
@@ -52,12 +228,141 @@ where
         }
     }
 
-    pub fn clear(&mut self) {
-        self.cache.clear();
+    pub fn remove(&mut self, key: &K) -> Option<V> {
+        self.last_accessed.remove(key);
+        self.remove_entry(key)
+    }
+
+    fn remove_entry(&mut self, key: &K) -> Option<V> {
+        if let Some(weight) = self.weights.remove(key) {
+            self.total_weight -= weight;
+        }
+        self.cache.remove(key)
+    }
+
+    // Empties the cache and hands back the values instead of dropping them
+    pub fn drain(&mut self) -> Vec<V> {
         self.last_accessed.clear();
+        self.weights.clear();
+        self.total_weight = 0;
+        self.cache.drain().map(|(_, value)| value).collect()
+    }
+
+    pub fn values(&self) -> std::collections::hash_map::Values<K, V> {
+        self.cache.values()
+    }
+
+    pub fn iter(&self) -> std::collections::hash_map::Iter<K, V> {
+        self.cache.iter()
+    }
+
+    pub fn iter_mut(&mut self) -> std::collections::hash_map::IterMut<K, V> {
+        self.cache.iter_mut()
     }
 
     pub fn values_mut(&mut self) -> std::collections::hash_map::ValuesMut<K, V> {
         self.cache.values_mut()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Cache;
+    use std::cmp::Reverse;
+    use std::sync::{Arc, Mutex};
+    use std::time::{Duration, Instant};
+
+    // Values weigh what they hold
+    fn cache(max_weight: u64) -> Cache<u32, u64> {
+        Cache::new(max_weight, |x| *x)
+    }
+
+    // Each key is accessed as many seconds after the start as the key, so
+    // that the lower keys are evicted first
+    fn insert_in_order(cache: &mut Cache<u32, u64>, start: Instant, entries: &[(u32, u64)]) {
+        for (key, value) in entries {
+            let accessed = start + Duration::from_secs(*key as u64);
+            cache.insert_with_priority(key, *value, Reverse(accessed));
+        }
+    }
+
+    #[test]
+    fn evicts_the_least_recently_accessed_over_the_weight() {
+        let start = Instant::now();
+        let mut cache = cache(10);
+        insert_in_order(&mut cache, start, &[(1, 4), (2, 4), (3, 4)]);
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.get(&2), Some(&4));
+        assert_eq!(cache.get(&3), Some(&4));
+        assert_eq!(cache.total_weight(), 8);
+        assert_eq!(cache.stats().evictions, 1);
+    }
+
+    #[test]
+    fn pinned_entries_survive_the_eviction() {
+        let start = Instant::now();
+        let mut cache = cache(10);
+        cache.set_pinned(vec![1]);
+        insert_in_order(&mut cache, start, &[(1, 4), (2, 4), (3, 4)]);
+        assert_eq!(cache.get(&1), Some(&4));
+        assert!(cache.get(&2).is_none());
+        assert_eq!(cache.get(&3), Some(&4));
+        // Skipped entries keep their place, the pinned key is still the
+        // oldest once it is unpinned
+        cache.set_pinned(vec![]);
+        cache.set_max_weight(4);
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.get(&3), Some(&4));
+    }
+
+    #[test]
+    fn goes_over_the_weight_when_everything_is_pinned() {
+        let start = Instant::now();
+        let mut cache = cache(4);
+        cache.set_pinned(vec![1, 2]);
+        insert_in_order(&mut cache, start, &[(1, 4), (2, 4)]);
+        assert_eq!(cache.total_weight(), 8);
+        assert_eq!(cache.stats().evictions, 0);
+    }
+
+    #[test]
+    fn update_weight_evicts() {
+        let start = Instant::now();
+        let mut cache = cache(10);
+        insert_in_order(&mut cache, start, &[(1, 4), (2, 4)]);
+        *cache.get_mut(&2).unwrap() = 8;
+        // The old weight is kept until it is updated
+        assert_eq!(cache.total_weight(), 8);
+        cache.update_weight(&2);
+        assert!(cache.get(&1).is_none());
+        assert_eq!(cache.total_weight(), 8);
+        // Evicts the key itself once it is the oldest left
+        *cache.get_mut(&2).unwrap() = 12;
+        cache.update_weight(&2);
+        assert!(cache.get(&2).is_none());
+        assert_eq!(cache.total_weight(), 0);
+    }
+
+    #[test]
+    fn on_evict_is_given_evicted_entries_but_not_removed_ones() {
+        let start = Instant::now();
+        let evicted = Arc::new(Mutex::new(vec![]));
+        let mut cache = cache(10).with_on_evict({
+            let evicted = evicted.clone();
+            move |key, value| evicted.lock().unwrap().push((key, value))
+        });
+        insert_in_order(&mut cache, start, &[(1, 4), (2, 4)]);
+        assert_eq!(cache.remove(&1), Some(4));
+        assert!(evicted.lock().unwrap().is_empty());
+        insert_in_order(&mut cache, start, &[(3, 4), (4, 4)]);
+        assert_eq!(*evicted.lock().unwrap(), vec![(2, 4)]);
+    }
+
+    #[test]
+    fn insert_returns_the_previous_value_and_replaces_its_weight() {
+        let mut cache = cache(10);
+        assert_eq!(cache.insert(&1, 4), None);
+        assert_eq!(cache.insert(&1, 6), Some(4));
+        assert_eq!(cache.total_weight(), 6);
+    }
+}
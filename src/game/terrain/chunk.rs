@@ -1,22 +1,89 @@
-use super::SHADER_WORKGROUP_SIZE;
+use super::biome::{Biome, BIOMES, BIOME_COUNT};
+use super::erosion::{ErosionPipelines, ErosionSettings};
+use super::sculpt::TerrainEdit;
+use super::vertex_color::unpack_color;
+use super::weld::WeldPipelines;
+use super::{
+    CaveSettings, DomainWarp, EdgeId, MeshingAlgorithm, NoiseAlgorithm, SHADER_WORKGROUP_SIZE,
+};
 use crate::game::base::WorldSpace;
-use crate::game::mesh::Triangle;
-use crate::gfx::Instance;
-use euclid::{size3, Box3D, Point3D, Size3D, UnknownUnit};
-use futures::executor::block_on;
+use crate::game::mesh::Mesh;
+use crate::gfx::{Instance, PooledBuffer, StagingBuffer};
+use euclid::{point2, size3, vec3, Box3D, Point2D, Point3D, Size3D, UnknownUnit};
+use futures::task::SpawnExt;
 use std::mem::size_of;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::*;
 
+// Keep in sync with erosion.wgsl
+const EROSION_CELL_SIZE: u64 = 16;
+const EROSION_FLUX_SIZE: u64 = 32;
+// Marching cubes writes up to 5 triangles for each cell, dual contouring and
+// surface nets a quad for each of the 3 edges going out of a voxel
+const MAX_VOXEL_TRIANGLES: u32 = 6;
+// The triangle count is padded to the alignment of the triangles, keep in
+// sync with generate_triangle.wgsl
+const TRIANGLE_BUFFER_HEADER_SIZE: u64 = 16;
+// Keep in sync with weld.wgsl: a slot for each step from a voxel to a corner
+// of its box, the position, id and color of a welded vertex in words and the
+// workgroup size of its passes
+const WELD_SLOTS_PER_VOXEL: u64 = 8;
+const WELDED_VERTEX_WORDS: usize = 6;
+const WELD_WORKGROUP_SIZE: u32 = 64;
+// Band of z the generated surface lies in, the same for every chunk whatever
+// part of it the chunk covers. The tree reaches past it so that the finer
+// levels are split along z.
+pub const SURFACE_MIN_Z: i32 = -1;
+pub const SURFACE_MAX_Z: i32 = 1;
+
 #[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod, Default)]
 #[repr(C)]
 struct GenerateVoxelInfo {
     voxel_count: [u32; 3],
     lod: u32,
     min: [f32; 3],
-    _pad1: u32,
+    noise: u32,
+    max: [f32; 3],
+    warp_strength: f32,
+    biomes: [Biome; BIOME_COUNT],
+    warp_frequency: f32,
+    seed: u32,
+    caves: u32,
+    cave_frequency: f32,
+    cave_radius: f32,
+    surface_min_z: f32,
+    surface_max_z: f32,
+    _pad: u32,
+}
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct SculptInfo {
+    voxel_count: [u32; 3],
+    radius: f32,
+    min: [f32; 3],
+    strength: f32,
     max: [f32; 3],
-    _pad2: u32,
+    _pad0: f32,
+    center: [f32; 3],
+    _pad1: f32,
+}
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct ErosionInfo {
+    voxel_count: [u32; 3],
+    isolevel: f32,
+    min: [f32; 3],
+    rain: f32,
+    max: [f32; 3],
+    evaporation: f32,
+    capacity: f32,
+    erosion: f32,
+    deposition: f32,
+    _pad: f32,
 }
 
 #[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
@@ -24,6 +91,17 @@ struct GenerateVoxelInfo {
 struct GenerateTriangleInfo {
     cell_count: [u32; 3],
     isolevel: f32,
+    min: [f32; 3],
+    _pad0: f32,
+    max: [f32; 3],
+    _pad1: f32,
+}
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct WeldInfo {
+    voxel_count: [u32; 3],
+    corner_count: u32,
 }
 
 #[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
@@ -32,22 +110,127 @@ pub struct Voxel {
     pub value: f32,
 }
 
+// Corner of the water surface in local space and the depth of the water under
+// it in world units. Keep in sync with water.wgsl
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+pub struct WaterVertex {
+    pub position: [f32; 3],
+    pub depth: f32,
+}
+
 #[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
 #[repr(C)]
 struct ComputeTriangle {
+    // w holds the packed vertex color, see vertex_color.rs
     position: [[f32; 4]; 3],
-    id: [[u32; 2]; 3],
+    id: [EdgeId; 3],
     _pad: u64,
 }
 
+// Progress of the staging buffers being read back,
+// see Chunk::map_staging_buffers
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MapStatus {
+    Pending,
+    Mapped,
+    Failed,
+}
+
+// Mapping of a set of buffers started without blocking, it completes on the
+// async pool once the device is polled
+struct BufferMapping {
+    pending: AtomicUsize,
+    failed: AtomicBool,
+}
+
+impl BufferMapping {
+    // on_mapped is called once every buffer is mapped or failed to
+    fn start(
+        instance: &Instance,
+        buffers: &[&StagingBuffer],
+        on_mapped: Arc<dyn Fn() + Send + Sync>,
+    ) -> Arc<Self> {
+        let mapping = Arc::new(Self {
+            pending: AtomicUsize::new(buffers.len()),
+            failed: AtomicBool::new(false),
+        });
+        for buffer in buffers {
+            let mapped = buffer.slice().map_async(MapMode::Read);
+            let mapping = mapping.clone();
+            let on_mapped = on_mapped.clone();
+            instance
+                .async_pool()
+                .spawn(async move {
+                    if mapped.await.is_err() {
+                        mapping.failed.store(true, Ordering::Release);
+                    }
+                    if mapping.pending.fetch_sub(1, Ordering::AcqRel) == 1 {
+                        on_mapped();
+                    }
+                })
+                .unwrap();
+        }
+        mapping
+    }
+
+    fn status(&self) -> MapStatus {
+        if self.pending.load(Ordering::Acquire) > 0 {
+            MapStatus::Pending
+        } else if self.failed.load(Ordering::Acquire) {
+            MapStatus::Failed
+        } else {
+            MapStatus::Mapped
+        }
+    }
+}
+
+// Unmaps a staging buffer that was read and gives it back to the pool
+fn recycle(instance: &Instance, staging_buffer: &mut Option<StagingBuffer>) {
+    let staging_buffer = staging_buffer.take().unwrap();
+    staging_buffer.unmap();
+    instance.recycle_staging_buffer(staging_buffer);
+}
+
 pub struct Chunk {
     bounds: Box3D<i32, WorldSpace>,
     level: u32,
     voxel_count: Size3D<u32, UnknownUnit>,
-    staging_voxel_buffer: Option<Buffer>,
-    voxel_buffer: Option<Buffer>,
-    staging_triangle_buffer: Option<Buffer>,
-    triangle_buffer: Option<Buffer>,
+    noise: NoiseAlgorithm,
+    staging_voxel_buffer: Option<StagingBuffer>,
+    voxel_buffer: Option<PooledBuffer>,
+    triangle_buffer: Option<PooledBuffer>,
+    // Algorithm the triangle buffer was generated with
+    triangle_meshing: Option<MeshingAlgorithm>,
+    // Triangle count of a triangle buffer that is not compacted yet
+    staging_count_buffer: Option<StagingBuffer>,
+    // Whether the compacted triangles are welded and read back
+    stage_triangles: bool,
+    // Welded from the compacted triangles
+    staging_vertex_buffer: Option<StagingBuffer>,
+    staging_index_buffer: Option<StagingBuffer>,
+    // Triangle count of the compacted triangle buffer
+    triangle_count: u32,
+    // Staging buffers being mapped, first the triangle count then everything
+    // read back for the mesh
+    mapping: Option<Arc<BufferMapping>>,
+    // Ticket of the batched submission the chunk was last generated in
+    submission: u64,
+    staging_water_buffer: Option<StagingBuffer>,
+    water_buffer: Option<PooledBuffer>,
+    staging_biome_buffer: Option<StagingBuffer>,
+    biome_buffer: Option<PooledBuffer>,
+    // CPU copy of the voxels, used for sampling density
+    voxels: Option<Vec<Voxel>>,
+    // CPU copy of the water level of each voxel column
+    water_levels: Option<Vec<f32>>,
+    // CPU copy of the biome weights of each voxel column
+    biome_weights: Option<Vec<[f32; BIOME_COUNT]>>,
+    // Disk cache hash of freshly generated voxels, taken when they are
+    // stored
+    content_hash: Option<u64>,
+    // Number of edit deltas of the key already in the voxels
+    applied_deltas: usize,
 }
 
 impl Chunk {
@@ -55,15 +238,33 @@ impl Chunk {
         bounds: Box3D<i32, WorldSpace>,
         level: u32,
         voxel_count: Size3D<u32, UnknownUnit>,
+        noise: NoiseAlgorithm,
     ) -> Self {
         Self {
             bounds,
             level,
             voxel_count,
+            noise,
             voxel_buffer: None,
             staging_voxel_buffer: None,
             triangle_buffer: None,
-            staging_triangle_buffer: None,
+            triangle_meshing: None,
+            staging_count_buffer: None,
+            stage_triangles: false,
+            staging_vertex_buffer: None,
+            staging_index_buffer: None,
+            triangle_count: 0,
+            mapping: None,
+            submission: 0,
+            water_buffer: None,
+            staging_water_buffer: None,
+            biome_buffer: None,
+            staging_biome_buffer: None,
+            voxels: None,
+            water_levels: None,
+            biome_weights: None,
+            content_hash: None,
+            applied_deltas: 0,
         }
     }
 
@@ -71,8 +272,28 @@ impl Chunk {
         self.total_voxel_count() as u64 * size_of::<Voxel>() as u64
     }
 
-    fn triangle_buffer_size(&self) -> u64 {
-        8 + self.total_cell_count() as u64 * 5 * size_of::<ComputeTriangle>() as u64
+    fn water_buffer_size(&self) -> u64 {
+        (self.voxel_count.width * self.voxel_count.height) as u64 * size_of::<f32>() as u64
+    }
+
+    fn biome_buffer_size(&self) -> u64 {
+        (self.voxel_count.width * self.voxel_count.height) as u64
+            * size_of::<[f32; BIOME_COUNT]>() as u64
+    }
+
+    pub fn triangle_buffer_size(&self) -> u64 {
+        8 + self.max_triangle_count() as u64 * size_of::<ComputeTriangle>() as u64
+    }
+
+    // Size of the triangle buffer, tight once it is compacted
+    pub fn triangle_buffer_bytes(&self) -> u64 {
+        if self.staging_count_buffer.is_some() {
+            return self.triangle_buffer_size();
+        }
+        // Bound as a storage buffer when drawn from the GPU, which needs room
+        // for at least one triangle
+        TRIANGLE_BUFFER_HEADER_SIZE
+            + self.triangle_count.max(1) as u64 * size_of::<ComputeTriangle>() as u64
     }
 
     #[profiling::function]
@@ -80,55 +301,63 @@ impl Chunk {
         if self.staging_voxel_buffer.is_some() {
             return;
         }
-        let device = instance.device();
+        self.staging_voxel_buffer = Some(instance.staging_buffer(self.voxel_buffer_size()));
+    }
 
-        let buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("chunk_staging_voxel_buffer"),
-            size: self.voxel_buffer_size(),
-            mapped_at_creation: false,
-            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-        });
-        self.staging_voxel_buffer = Some(buffer);
+    fn create_staging_count_buffer(&mut self, instance: &Instance) {
+        self.staging_count_buffer = Some(instance.staging_buffer(size_of::<u32>() as u64));
     }
 
     #[profiling::function]
-    fn create_staging_triangle_buffer(&mut self, instance: &Instance) {
-        if self.staging_triangle_buffer.is_some() {
+    fn create_staging_water_buffer(&mut self, instance: &Instance) {
+        if self.staging_water_buffer.is_some() {
             return;
         }
-        let device = instance.device();
+        self.staging_water_buffer = Some(instance.staging_buffer(self.water_buffer_size()));
+    }
 
-        let buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("chunk_staging_triangle_buffer"),
-            size: self.triangle_buffer_size(),
-            mapped_at_creation: false,
-            usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
-        });
-        self.staging_triangle_buffer = Some(buffer);
+    #[profiling::function]
+    fn create_staging_biome_buffer(&mut self, instance: &Instance) {
+        if self.staging_biome_buffer.is_some() {
+            return;
+        }
+        self.staging_biome_buffer = Some(instance.staging_buffer(self.biome_buffer_size()));
     }
 
     #[profiling::function]
     fn create_voxel_buffer(&mut self, instance: &Instance) {
-        let device = instance.device();
-        let buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("chunk_voxel_buffer"),
-            size: self.voxel_buffer_size(),
-            mapped_at_creation: false,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-        });
-        self.voxel_buffer = Some(buffer);
+        self.voxel_buffer = Some(instance.pooled_buffer(
+            "chunk_voxel_buffer",
+            self.voxel_buffer_size(),
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        ));
+    }
+
+    #[profiling::function]
+    fn create_water_buffer(&mut self, instance: &Instance) {
+        self.water_buffer = Some(instance.pooled_buffer(
+            "chunk_water_buffer",
+            self.water_buffer_size(),
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        ));
+    }
+
+    #[profiling::function]
+    fn create_biome_buffer(&mut self, instance: &Instance) {
+        self.biome_buffer = Some(instance.pooled_buffer(
+            "chunk_biome_buffer",
+            self.biome_buffer_size(),
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        ));
     }
 
     #[profiling::function]
     fn create_triangle_buffer(&mut self, instance: &Instance) {
-        let device = instance.device();
-        let buffer = device.create_buffer(&BufferDescriptor {
-            label: Some("chunk_triangle_buffer"),
-            size: self.triangle_buffer_size(),
-            mapped_at_creation: false,
-            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
-        });
-        self.triangle_buffer = Some(buffer);
+        self.triangle_buffer = Some(instance.pooled_buffer(
+            "chunk_triangle_buffer",
+            self.triangle_buffer_size(),
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        ));
     }
 
     #[profiling::function]
@@ -137,13 +366,22 @@ impl Chunk {
         instance: &Instance,
         encoder: &mut CommandEncoder,
         generate_voxel_pipeline: &ComputePipeline,
+        seed: u32,
+        domain_warp: &DomainWarp,
+        caves: &CaveSettings,
         copy_to_staging: bool,
     ) {
         self.create_voxel_buffer(instance);
+        self.create_water_buffer(instance);
+        self.create_biome_buffer(instance);
         if copy_to_staging {
             self.create_staging_voxel_buffer(instance);
+            self.create_staging_water_buffer(instance);
+            self.create_staging_biome_buffer(instance);
         } else {
             self.staging_voxel_buffer = None;
+            self.staging_water_buffer = None;
+            self.staging_biome_buffer = None;
         }
         let device = instance.device();
         let bounds = self.bounds.to_f32();
@@ -151,7 +389,17 @@ impl Chunk {
             voxel_count: self.voxel_count.to_array(),
             lod: self.level,
             min: bounds.min.to_array(),
+            noise: self.noise as u32,
             max: bounds.max.to_array(),
+            warp_strength: domain_warp.strength,
+            biomes: BIOMES,
+            warp_frequency: domain_warp.frequency,
+            seed,
+            caves: caves.enabled as u32,
+            cave_frequency: caves.frequency,
+            cave_radius: caves.radius,
+            surface_min_z: SURFACE_MIN_Z as f32,
+            surface_max_z: SURFACE_MAX_Z as f32,
             ..Default::default()
         };
         let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
@@ -178,6 +426,22 @@ impl Chunk {
                         size: None,
                     }),
                 },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: self.water_buffer.as_ref().unwrap(),
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: self.biome_buffer.as_ref().unwrap(),
+                        offset: 0,
+                        size: None,
+                    }),
+                },
             ],
             label: Some("chunk_voxel_bind_group"),
             layout: &generate_voxel_pipeline.get_bind_group_layout(0),
@@ -198,12 +462,163 @@ impl Chunk {
             compute_pass.dispatch(group_count_x, group_count_y, group_count_z);
         }
         if copy_to_staging {
+            self.copy_to_staging(encoder);
+        }
+    }
+
+    // Uploads voxels read from the disk cache in place of generate_voxel
+    #[profiling::function]
+    pub fn upload_voxel(
+        &mut self,
+        instance: &Instance,
+        encoder: &mut CommandEncoder,
+        voxels: &[Voxel],
+        water_levels: &[f32],
+        biome_weights: &[[f32; BIOME_COUNT]],
+    ) {
+        self.voxel_buffer = Some(instance.pooled_buffer_init(
+            "chunk_voxel_buffer",
+            bytemuck::cast_slice(voxels),
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        ));
+        self.water_buffer = Some(instance.pooled_buffer_init(
+            "chunk_water_buffer",
+            bytemuck::cast_slice(water_levels),
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        ));
+        self.biome_buffer = Some(instance.pooled_buffer_init(
+            "chunk_biome_buffer",
+            bytemuck::cast_slice(biome_weights),
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        ));
+        self.create_staging_voxel_buffer(instance);
+        self.create_staging_water_buffer(instance);
+        self.create_staging_biome_buffer(instance);
+        self.copy_to_staging(encoder);
+    }
+
+    // Erodes the generated voxels in place, before the triangles are generated
+    #[profiling::function]
+    pub fn erode(
+        &mut self,
+        instance: &Instance,
+        encoder: &mut CommandEncoder,
+        pipelines: &ErosionPipelines,
+        settings: &ErosionSettings,
+        isolevel: f32,
+    ) {
+        // A single layer has no height to erode
+        if self.voxel_count.depth < 2 {
+            return;
+        }
+        let device = instance.device();
+        let voxel_buffer = self.voxel_buffer.as_deref().unwrap();
+        let voxel_buffer_size = self.voxel_buffer_size();
+        let column_count = (self.voxel_count.width * self.voxel_count.height) as u64;
+        let bounds = self.bounds.to_f32();
+        let info = ErosionInfo {
+            voxel_count: self.voxel_count.to_array(),
+            isolevel,
+            min: bounds.min.to_array(),
+            rain: settings.rain,
+            max: bounds.max.to_array(),
+            evaporation: settings.evaporation,
+            capacity: settings.capacity,
+            erosion: settings.erosion,
+            deposition: settings.deposition,
+            _pad: 0.0,
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("terrain_erosion_uniform_buffer"),
+            contents: bytemuck::bytes_of(&info),
+            usage: BufferUsages::UNIFORM,
+        });
+        let create_storage_buffer = |label: &str, size: u64, usage: BufferUsages| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                mapped_at_creation: false,
+                usage: BufferUsages::STORAGE | usage,
+            })
+        };
+        let source_buffer = create_storage_buffer(
+            "terrain_erosion_source_buffer",
+            voxel_buffer_size,
+            BufferUsages::COPY_DST,
+        );
+        let cell_buffer = create_storage_buffer(
+            "terrain_erosion_cell_buffer",
+            column_count * EROSION_CELL_SIZE,
+            BufferUsages::empty(),
+        );
+        let flux_buffer = create_storage_buffer(
+            "terrain_erosion_flux_buffer",
+            column_count * EROSION_FLUX_SIZE,
+            BufferUsages::empty(),
+        );
+        encoder.copy_buffer_to_buffer(voxel_buffer, 0, &source_buffer, 0, voxel_buffer_size);
+        let binding = |buffer| {
+            BindingResource::Buffer(BufferBinding {
+                buffer,
+                offset: 0,
+                size: None,
+            })
+        };
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("terrain_erosion_bind_group"),
+            layout: &pipelines.init_cells.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: binding(&uniform_buffer),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: binding(voxel_buffer),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: binding(&source_buffer),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: binding(&cell_buffer),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: binding(&flux_buffer),
+                },
+            ],
+        });
+        let group_count = |count: u32| (count + SHADER_WORKGROUP_SIZE - 1) / SHADER_WORKGROUP_SIZE;
+        let (group_count_x, group_count_y, group_count_z) = (
+            group_count(self.voxel_count.width),
+            group_count(self.voxel_count.height),
+            group_count(self.voxel_count.depth),
+        );
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("terrain_erosion_compute_pass"),
+            });
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.set_pipeline(&pipelines.init_cells);
+            compute_pass.dispatch(group_count_x, group_count_y, 1);
+            for _ in 0..settings.iterations {
+                compute_pass.set_pipeline(&pipelines.flow);
+                compute_pass.dispatch(group_count_x, group_count_y, 1);
+                compute_pass.set_pipeline(&pipelines.update);
+                compute_pass.dispatch(group_count_x, group_count_y, 1);
+            }
+            compute_pass.set_pipeline(&pipelines.apply);
+            compute_pass.dispatch(group_count_x, group_count_y, group_count_z);
+        }
+        if let Some(staging_voxel_buffer) = self.staging_voxel_buffer.as_ref() {
             encoder.copy_buffer_to_buffer(
-                self.voxel_buffer.as_ref().unwrap(),
+                voxel_buffer,
                 0,
-                self.staging_voxel_buffer.as_ref().unwrap(),
+                staging_voxel_buffer.buffer(),
                 0,
-                self.voxel_buffer_size(),
+                voxel_buffer_size,
             );
         }
     }
@@ -214,19 +629,280 @@ impl Chunk {
         instance: &Instance,
         encoder: &mut CommandEncoder,
         generate_triangle_pipeline: &ComputePipeline,
+        meshing: MeshingAlgorithm,
         copy_to_staging: bool,
         isolevel: f32,
     ) {
         self.create_triangle_buffer(instance);
-        if copy_to_staging {
-            self.create_staging_triangle_buffer(instance);
+        self.create_staging_count_buffer(instance);
+        self.triangle_meshing = Some(meshing);
+        self.stage_triangles = copy_to_staging;
+        self.staging_vertex_buffer = None;
+        self.staging_index_buffer = None;
+        self.triangle_count = 0;
+        self.mapping = None;
+        self.dispatch_triangle(
+            instance,
+            encoder,
+            generate_triangle_pipeline,
+            isolevel,
+            self.triangle_buffer.as_ref().unwrap(),
+        );
+        encoder.copy_buffer_to_buffer(
+            self.triangle_buffer.as_ref().unwrap(),
+            0,
+            self.staging_count_buffer.as_ref().unwrap().buffer(),
+            0,
+            size_of::<u32>() as u64,
+        );
+    }
+
+    // Never blocks, the task calling it is queued again while the status is
+    // pending. The triangle count is mapped first to compact the triangles,
+    // then the staged voxels, water and biomes are mapped along with the
+    // welded mesh. Once mapped the buffers stay so until they are unmapped
+    // and the next call maps them again.
+    #[profiling::function]
+    pub fn map_staging_buffers<F>(
+        &mut self,
+        instance: &Instance,
+        weld_pipelines: &WeldPipelines,
+        on_mapped: F,
+    ) -> MapStatus
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        if let Some(mapping) = self.mapping.as_ref() {
+            let status = mapping.status();
+            if status == MapStatus::Pending {
+                return status;
+            }
+            self.mapping = None;
+            if status == MapStatus::Failed {
+                return status;
+            }
+            match self.staging_count_buffer.take() {
+                Some(staging_count_buffer) => {
+                    let triangle_count: u32 =
+                        *bytemuck::from_bytes(&staging_count_buffer.slice().get_mapped_range()[..]);
+                    staging_count_buffer.unmap();
+                    instance.recycle_staging_buffer(staging_count_buffer);
+                    self.compact_triangle_buffer(instance, weld_pipelines, triangle_count);
+                }
+                None => return MapStatus::Mapped,
+            }
+        }
+        if self.staging_count_buffer.is_none() {
+            self.restage(instance, weld_pipelines);
+        }
+        let mut buffers = vec![];
+        if let Some(staging_count_buffer) = self.staging_count_buffer.as_ref() {
+            buffers.push(staging_count_buffer);
         } else {
-            self.staging_voxel_buffer = None;
+            if self.stage_triangles {
+                buffers.push(self.staging_vertex_buffer.as_ref().unwrap());
+                buffers.push(self.staging_index_buffer.as_ref().unwrap());
+            }
+            buffers.push(self.staging_voxel_buffer.as_ref().unwrap());
+            buffers.push(self.staging_water_buffer.as_ref().unwrap());
+            buffers.push(self.staging_biome_buffer.as_ref().unwrap());
+        }
+        self.mapping = Some(BufferMapping::start(
+            instance,
+            &buffers,
+            Arc::new(on_mapped),
+        ));
+        MapStatus::Pending
+    }
+
+    // Checked before map_staging_buffers so that waiting on a mapping does
+    // not need the chunk mutably
+    pub fn is_mapping(&self) -> bool {
+        self.mapping
+            .as_ref()
+            .map_or(false, |x| x.status() == MapStatus::Pending)
+    }
+
+    // The triangle buffer is sized for the most triangles a chunk can have,
+    // the compute pass appends them from the start so only its count is read
+    // back to move them into a buffer of their size. Only the compacted
+    // triangles are welded and staged.
+    #[profiling::function]
+    fn compact_triangle_buffer(
+        &mut self,
+        instance: &Instance,
+        weld_pipelines: &WeldPipelines,
+        triangle_count: u32,
+    ) {
+        let device = instance.device();
+        self.triangle_count = triangle_count;
+        let size = self.triangle_buffer_bytes();
+        let buffer = instance.pooled_buffer(
+            "chunk_triangle_buffer",
+            size,
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+        );
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        encoder.copy_buffer_to_buffer(self.triangle_buffer.as_ref().unwrap(), 0, &buffer, 0, size);
+        if self.stage_triangles {
+            self.weld_triangles(
+                instance,
+                &mut encoder,
+                weld_pipelines,
+                &buffer,
+                triangle_count,
+            );
         }
+        instance.queue().submit(std::iter::once(encoder.finish()));
+        self.triangle_buffer = Some(buffer);
+    }
+
+    // Every corner of the triangles gets a slot from its id, see weld.wgsl. The
+    // vertex buffer is sized for corners that share nothing, only its count
+    // and the indices are tight.
+    fn weld_triangles(
+        &mut self,
+        instance: &Instance,
+        encoder: &mut CommandEncoder,
+        pipelines: &WeldPipelines,
+        triangle_buffer: &Buffer,
+        triangle_count: u32,
+    ) {
         let device = instance.device();
+        let corner_count = triangle_count * 3;
+        let info = WeldInfo {
+            voxel_count: self.voxel_count.to_array(),
+            corner_count,
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("terrain_weld_uniform_buffer"),
+            contents: bytemuck::bytes_of(&info),
+            usage: BufferUsages::UNIFORM,
+        });
+        let create_buffer = |label: &str, size: u64, usage: BufferUsages| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                mapped_at_creation: false,
+                usage,
+            })
+        };
+        let slot_buffer_size =
+            self.total_voxel_count() as u64 * WELD_SLOTS_PER_VOXEL * size_of::<u32>() as u64;
+        let owner_buffer = create_buffer(
+            "terrain_weld_owner_buffer",
+            slot_buffer_size,
+            BufferUsages::STORAGE,
+        );
+        let slot_vertex_buffer = create_buffer(
+            "terrain_weld_slot_vertex_buffer",
+            slot_buffer_size,
+            BufferUsages::STORAGE,
+        );
+        // Room for at least one corner to be bound
+        let corner_room = corner_count.max(1) as u64;
+        let vertex_buffer_size =
+            (1 + corner_room * WELDED_VERTEX_WORDS as u64) * size_of::<u32>() as u64;
+        let index_buffer_size = corner_room * size_of::<u32>() as u64;
+        let vertex_buffer = create_buffer(
+            "terrain_weld_vertex_buffer",
+            vertex_buffer_size,
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        );
+        let index_buffer = create_buffer(
+            "terrain_weld_index_buffer",
+            index_buffer_size,
+            BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+        );
+        let binding = |buffer| {
+            BindingResource::Buffer(BufferBinding {
+                buffer,
+                offset: 0,
+                size: None,
+            })
+        };
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("terrain_weld_bind_group"),
+            layout: &pipelines.claim_slots.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: binding(&uniform_buffer),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: binding(triangle_buffer),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: binding(&owner_buffer),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: binding(&slot_vertex_buffer),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: binding(&vertex_buffer),
+                },
+                BindGroupEntry {
+                    binding: 5,
+                    resource: binding(&index_buffer),
+                },
+            ],
+        });
+        let group_count = (corner_room as u32 + WELD_WORKGROUP_SIZE - 1) / WELD_WORKGROUP_SIZE;
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("terrain_weld_compute_pass"),
+            });
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.set_pipeline(&pipelines.claim_slots);
+            compute_pass.dispatch(group_count, 1, 1);
+            compute_pass.set_pipeline(&pipelines.emit_vertices);
+            compute_pass.dispatch(group_count, 1, 1);
+            compute_pass.set_pipeline(&pipelines.write_indices);
+            compute_pass.dispatch(group_count, 1, 1);
+        }
+        let staging_vertex_buffer = instance.staging_buffer(vertex_buffer_size);
+        let staging_index_buffer = instance.staging_buffer(index_buffer_size);
+        encoder.copy_buffer_to_buffer(
+            &vertex_buffer,
+            0,
+            staging_vertex_buffer.buffer(),
+            0,
+            vertex_buffer_size,
+        );
+        encoder.copy_buffer_to_buffer(
+            &index_buffer,
+            0,
+            staging_index_buffer.buffer(),
+            0,
+            index_buffer_size,
+        );
+        self.staging_vertex_buffer = Some(staging_vertex_buffer);
+        self.staging_index_buffer = Some(staging_index_buffer);
+    }
+
+    // Run the triangle compute pass into any buffer laid out like the triangle
+    // buffer, the triangle count must be zeroed beforehand
+    pub fn dispatch_triangle(
+        &self,
+        instance: &Instance,
+        encoder: &mut CommandEncoder,
+        generate_triangle_pipeline: &ComputePipeline,
+        isolevel: f32,
+        triangle_buffer: &Buffer,
+    ) {
+        let device = instance.device();
+        let bounds = self.bounds.to_f32();
         let data = GenerateTriangleInfo {
             cell_count: (self.voxel_count - size3(1, 1, 1)).to_array(),
             isolevel,
+            min: bounds.min.to_array(),
+            _pad0: 0.0,
+            max: bounds.max.to_array(),
+            _pad1: 0.0,
         };
 
         let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
@@ -256,7 +932,16 @@ impl Chunk {
                 BindGroupEntry {
                     binding: 2,
                     resource: BindingResource::Buffer(BufferBinding {
-                        buffer: self.triangle_buffer.as_ref().unwrap(),
+                        buffer: triangle_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                // The vertex colors are picked from the biome weights
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: self.biome_buffer.as_ref().unwrap(),
                         offset: 0,
                         size: None,
                     }),
@@ -280,88 +965,492 @@ impl Chunk {
             compute_pass.set_bind_group(0, &bind_group, &[]);
             compute_pass.dispatch(group_count_x, group_count_y, group_count_z);
         }
-        if copy_to_staging {
-            encoder.copy_buffer_to_buffer(
-                self.triangle_buffer.as_ref().unwrap(),
-                0,
-                self.staging_triangle_buffer.as_ref().unwrap(),
-                0,
-                self.triangle_buffer_size(),
-            );
-        }
     }
 
-    // WARNING: Do not call this on main thread, it will block until
-    // GPU device is polled
-    pub fn map_voxel_buffer(&mut self) {
+    // The staging buffers go back to the pool once they are read, see restage
+    pub fn unmap_voxel_buffer(&mut self, instance: &Instance) {
         debug_assert!(self.staging_voxel_buffer.is_some());
-        let buffer_slice = self.staging_voxel_buffer.as_ref().unwrap().slice(..);
-        block_on(buffer_slice.map_async(MapMode::Read)).unwrap();
+        recycle(instance, &mut self.staging_voxel_buffer);
     }
 
-    pub fn unmap_voxel_buffer(&mut self) {
-        debug_assert!(self.staging_voxel_buffer.is_some());
-        self.staging_voxel_buffer.as_ref().unwrap().unmap();
+    pub fn unmap_water_buffer(&mut self, instance: &Instance) {
+        debug_assert!(self.staging_water_buffer.is_some());
+        recycle(instance, &mut self.staging_water_buffer);
     }
 
-    // WARNING: Do not call this on main thread, it will block until
-    // GPU device is polled
-    #[profiling::function]
-    pub fn map_triangle_buffer(&mut self) {
-        debug_assert!(self.staging_triangle_buffer.is_some());
-        let buffer_slice = self.staging_triangle_buffer.as_ref().unwrap().slice(..);
-        block_on(buffer_slice.map_async(MapMode::Read)).unwrap();
+    pub fn unmap_biome_buffer(&mut self, instance: &Instance) {
+        debug_assert!(self.staging_biome_buffer.is_some());
+        recycle(instance, &mut self.staging_biome_buffer);
+    }
+
+    pub fn unmap_mesh_buffers(&mut self, instance: &Instance) {
+        debug_assert!(self.staging_vertex_buffer.is_some());
+        debug_assert!(self.staging_index_buffer.is_some());
+        recycle(instance, &mut self.staging_vertex_buffer);
+        recycle(instance, &mut self.staging_index_buffer);
+    }
+
+    // Borrows staging buffers again for a chunk meshed once more, the voxels
+    // are copied and the compacted triangles welded again
+    fn restage(&mut self, instance: &Instance, weld_pipelines: &WeldPipelines) {
+        let reweld = self.stage_triangles && self.staging_vertex_buffer.is_none();
+        let recopy = self.staging_voxel_buffer.is_none()
+            || self.staging_water_buffer.is_none()
+            || self.staging_biome_buffer.is_none();
+        if !reweld && !recopy {
+            return;
+        }
+        let device = instance.device();
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        if reweld {
+            if let Some(triangle_buffer) = self.triangle_buffer.take() {
+                let triangle_count = self.triangle_count;
+                self.weld_triangles(
+                    instance,
+                    &mut encoder,
+                    weld_pipelines,
+                    &triangle_buffer,
+                    triangle_count,
+                );
+                self.triangle_buffer = Some(triangle_buffer);
+            }
+        }
+        if recopy {
+            self.create_staging_voxel_buffer(instance);
+            self.create_staging_water_buffer(instance);
+            self.create_staging_biome_buffer(instance);
+            self.copy_to_staging(&mut encoder);
+        }
+        instance.queue().submit(std::iter::once(encoder.finish()));
     }
 
-    pub fn unmap_triangle_buffer(&mut self) {
-        debug_assert!(self.staging_triangle_buffer.is_some());
-        self.staging_triangle_buffer.as_ref().unwrap().unmap();
+    fn copy_to_staging(&self, encoder: &mut CommandEncoder) {
+        encoder.copy_buffer_to_buffer(
+            self.voxel_buffer.as_ref().unwrap(),
+            0,
+            self.staging_voxel_buffer.as_ref().unwrap().buffer(),
+            0,
+            self.voxel_buffer_size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            self.water_buffer.as_ref().unwrap(),
+            0,
+            self.staging_water_buffer.as_ref().unwrap().buffer(),
+            0,
+            self.water_buffer_size(),
+        );
+        encoder.copy_buffer_to_buffer(
+            self.biome_buffer.as_ref().unwrap(),
+            0,
+            self.staging_biome_buffer.as_ref().unwrap().buffer(),
+            0,
+            self.biome_buffer_size(),
+        );
     }
 
     pub fn get_mapped_voxel_buffer(&self) -> Vec<Voxel> {
-        let buffer_slice = self.staging_voxel_buffer.as_ref().unwrap().slice(..);
+        let buffer_slice = self.staging_voxel_buffer.as_ref().unwrap().slice();
+        let data = buffer_slice.get_mapped_range();
+        bytemuck::cast_slice(&data).to_vec()
+    }
+
+    pub fn get_mapped_water_buffer(&self) -> Vec<f32> {
+        let buffer_slice = self.staging_water_buffer.as_ref().unwrap().slice();
+        let data = buffer_slice.get_mapped_range();
+        bytemuck::cast_slice(&data).to_vec()
+    }
+
+    pub fn get_mapped_biome_buffer(&self) -> Vec<[f32; BIOME_COUNT]> {
+        let buffer_slice = self.staging_biome_buffer.as_ref().unwrap().slice();
         let data = buffer_slice.get_mapped_range();
         bytemuck::cast_slice(&data).to_vec()
     }
 
     #[profiling::function]
-    pub fn get_mapped_triangle_buffer<T>(&self) -> Vec<Triangle<T>>
+    pub fn get_mapped_mesh<T>(&self) -> Mesh<T>
     where
-        T: Send,
+        T: Send + Sync,
     {
-        let buffer_slice = self.staging_triangle_buffer.as_ref().unwrap().slice(..);
+        let buffer_slice = self.staging_vertex_buffer.as_ref().unwrap().slice();
         let data = buffer_slice.get_mapped_range();
-        let triangle_count: u32 = *bytemuck::from_bytes(&data[..4]);
-        if triangle_count == 0 {
-            vec![]
-        } else {
-            let compute_triangles: &[ComputeTriangle] = bytemuck::cast_slice(
-                &data[16..16 + size_of::<ComputeTriangle>() * triangle_count as usize],
+        let words: &[u32] = bytemuck::cast_slice(&data);
+        let vertex_count = words[0] as usize;
+        let vertices = words[1..1 + vertex_count * WELDED_VERTEX_WORDS].chunks(WELDED_VERTEX_WORDS);
+        let ids = vertices
+            .clone()
+            .map(|x| EdgeId::from([x[3], x[4]]))
+            .collect();
+        let colors = vertices
+            .clone()
+            .map(|x| unpack_color(f32::from_bits(x[5]) as u32))
+            .collect();
+        let vertex = vertices
+            .map(|x| {
+                Point3D::new(
+                    f32::from_bits(x[0]),
+                    f32::from_bits(x[1]),
+                    f32::from_bits(x[2]),
+                )
+            })
+            .collect();
+
+        let buffer_slice = self.staging_index_buffer.as_ref().unwrap().slice();
+        let data = buffer_slice.get_mapped_range();
+        let indices: &[u32] = bytemuck::cast_slice(&data);
+        let faces = indices[..self.triangle_count as usize * 3]
+            .chunks(3)
+            .map(|x| [x[0] as usize, x[1] as usize, x[2] as usize])
+            .collect();
+        Mesh::from_indexed(ids, vertex, faces).with_colors(colors)
+    }
+
+    pub fn set_voxels(&mut self, voxels: Vec<Voxel>) {
+        self.voxels = Some(voxels);
+    }
+
+    pub fn voxels(&self) -> Option<&[Voxel]> {
+        self.voxels.as_deref()
+    }
+
+    pub fn set_water_levels(&mut self, water_levels: Vec<f32>) {
+        self.water_levels = Some(water_levels);
+    }
+
+    pub fn water_levels(&self) -> Option<&[f32]> {
+        self.water_levels.as_deref()
+    }
+
+    pub fn set_biome_weights(&mut self, biome_weights: Vec<[f32; BIOME_COUNT]>) {
+        self.biome_weights = Some(biome_weights);
+    }
+
+    pub fn biome_weights(&self) -> Option<&[[f32; BIOME_COUNT]]> {
+        self.biome_weights.as_deref()
+    }
+
+    // Weights of the closest voxel column, none until they are read back
+    pub fn sample_biome(&self, point: &Point2D<f32, WorldSpace>) -> Option<[f32; BIOME_COUNT]> {
+        let biome_weights = self.biome_weights.as_ref()?;
+        let bounds = self.bounds.to_f32();
+        let size = self.voxel_count;
+        let column = |min: f32, max: f32, count: u32, x: f32| {
+            let last = (count.max(2) - 1) as f32;
+            ((x - min) / (max - min) * last).round().clamp(0.0, last) as u32
+        };
+        let x = column(bounds.min.x, bounds.max.x, size.width, point.x);
+        let y = column(bounds.min.y, bounds.max.y, size.height, point.y);
+        Some(biome_weights[(x + size.width * y) as usize])
+    }
+
+    pub fn set_content_hash(&mut self, content_hash: Option<u64>) {
+        self.content_hash = content_hash;
+    }
+
+    pub fn submission(&self) -> u64 {
+        self.submission
+    }
+
+    pub fn set_submission(&mut self, submission: u64) {
+        self.submission = submission;
+    }
+
+    pub fn applied_deltas(&self) -> usize {
+        self.applied_deltas
+    }
+
+    pub fn set_applied_deltas(&mut self, applied_deltas: usize) {
+        self.applied_deltas = applied_deltas;
+    }
+
+    pub fn take_content_hash(&mut self) -> Option<u64> {
+        self.content_hash.take()
+    }
+
+    // Water surface as a triangle list in local space. Cells where the water
+    // level is buried under terrain at every corner are skipped, the rest is
+    // left to the depth test.
+    #[profiling::function]
+    pub fn water_surface(&self, isolevel: f32) -> Vec<WaterVertex> {
+        let (voxels, water_levels) = match (self.voxels.as_ref(), self.water_levels.as_ref()) {
+            (Some(voxels), Some(water_levels)) => (voxels, water_levels),
+            _ => return vec![],
+        };
+        let bounds = self.bounds.to_f32();
+        let size = self.voxel_count;
+        let last = |count: u32| (count.max(2) - 1) as f32;
+        let value = |x: u32, y: u32, z: u32| {
+            voxels[(x + size.width * (y + size.height * z)) as usize].value
+        };
+        let corner = |x: u32, y: u32| {
+            let level = water_levels[(x + size.width * y) as usize];
+            let z = ((level - bounds.min.z) / bounds.depth()).clamp(0.0, 1.0);
+            let zi = (z * last(size.depth)).round() as u32;
+            let wet = value(x, y, zi) < isolevel;
+            // The ground is where the column first crosses the isolevel below
+            // the surface, water running through the bottom of the chunk is
+            // as deep as the chunk
+            let ground = (0..zi)
+                .rev()
+                .find(|&k| value(x, y, k) >= isolevel)
+                .map_or(0.0, |k| {
+                    let (below, above) = (value(x, y, k), value(x, y, k + 1));
+                    k as f32 + (below - isolevel) / (below - above).max(f32::EPSILON)
+                });
+            let ground = bounds.min.z + ground / last(size.depth) * bounds.depth();
+            (
+                WaterVertex {
+                    position: [x as f32 / last(size.width), y as f32 / last(size.height), z],
+                    depth: (level - ground).max(0.0),
+                },
+                wet,
+            )
+        };
+        let mut triangles = vec![];
+        for y in 0..size.height.max(1) - 1 {
+            for x in 0..size.width.max(1) - 1 {
+                let (p00, wet00) = corner(x, y);
+                let (p10, wet10) = corner(x + 1, y);
+                let (p01, wet01) = corner(x, y + 1);
+                let (p11, wet11) = corner(x + 1, y + 1);
+                if wet00 || wet10 || wet01 || wet11 {
+                    triangles.extend_from_slice(&[p00, p10, p11, p00, p11, p01]);
+                }
+            }
+        }
+        triangles
+    }
+
+    // Trilinear interpolation of the voxel values around a point
+    pub fn sample_voxel(&self, point: &Point3D<f32, WorldSpace>) -> Option<f32> {
+        let voxels = self.voxels.as_ref()?;
+        let bounds = self.bounds.to_f32();
+        let size = self.voxel_count;
+        let cell = |v: f32, min: f32, max: f32, count: u32| {
+            let last = (count.max(2) - 1) as f32;
+            ((v - min) / (max - min) * last).clamp(0.0, last)
+        };
+        let x = cell(point.x, bounds.min.x, bounds.max.x, size.width);
+        let y = cell(point.y, bounds.min.y, bounds.max.y, size.height);
+        let z = cell(point.z, bounds.min.z, bounds.max.z, size.depth);
+        let (x0, y0, z0) = (x.floor() as u32, y.floor() as u32, z.floor() as u32);
+        let x1 = (x0 + 1).min(size.width - 1);
+        let y1 = (y0 + 1).min(size.height - 1);
+        let z1 = (z0 + 1).min(size.depth - 1);
+        let (fx, fy, fz) = (x - x0 as f32, y - y0 as f32, z - z0 as f32);
+        let value = |x: u32, y: u32, z: u32| {
+            voxels[(x + size.width * (y + size.height * z)) as usize].value
+        };
+        let lerp = |a: f32, b: f32, t: f32| a + (b - a) * t;
+        let c00 = lerp(value(x0, y0, z0), value(x1, y0, z0), fx);
+        let c10 = lerp(value(x0, y1, z0), value(x1, y1, z0), fx);
+        let c01 = lerp(value(x0, y0, z1), value(x1, y0, z1), fx);
+        let c11 = lerp(value(x0, y1, z1), value(x1, y1, z1), fx);
+        Some(lerp(lerp(c00, c10, fy), lerp(c01, c11, fy), fz))
+    }
+
+    // March the voxel column from the top and return the first height where
+    // density goes over the isolevel
+    pub fn surface_height(&self, point: &Point2D<f32, WorldSpace>, isolevel: f32) -> Option<f32> {
+        let bounds = self.bounds.to_f32();
+        let steps = self.voxel_count.depth.max(2) - 1;
+        let step = bounds.depth() / steps as f32;
+        let mut previous = self.sample_voxel(&point.extend(bounds.max.z))?;
+        if previous >= isolevel {
+            return None;
+        }
+        for i in (0..steps).rev() {
+            let z = bounds.min.z + i as f32 * step;
+            let value = self.sample_voxel(&point.extend(z))?;
+            if value >= isolevel {
+                let t = (isolevel - value) / (previous - value);
+                return Some(z + t * step);
+            }
+            previous = value;
+        }
+        None
+    }
+
+    // Largest vertical distance between the surface of the chunk and the one
+    // of its parent over the voxel columns, none if no column has a surface
+    // in both
+    pub fn surface_deviation(&self, parent: &Chunk, isolevel: f32) -> Option<f32> {
+        let bounds = self.bounds.to_f32();
+        let (columns, rows) = (self.voxel_count.width, self.voxel_count.height);
+        let mut deviation: Option<f32> = None;
+        for row in 0..rows {
+            for column in 0..columns {
+                let point = point2(
+                    bounds.min.x + bounds.width() * column as f32 / (columns - 1) as f32,
+                    bounds.min.y + bounds.height() * row as f32 / (rows - 1) as f32,
+                );
+                if let (Some(h0), Some(h1)) = (
+                    self.surface_height(&point, isolevel),
+                    parent.surface_height(&point, isolevel),
+                ) {
+                    deviation = Some(deviation.unwrap_or(0.0).max((h0 - h1).abs()));
+                }
+            }
+        }
+        deviation
+    }
+
+    // Run the brush on the voxel buffer and copy it to the staging buffer so
+    // that the CPU voxels are read back with the next mesh. Returns false if
+    // the brush does not touch the chunk.
+    pub fn sculpt(
+        &mut self,
+        instance: &Instance,
+        encoder: &mut CommandEncoder,
+        sculpt_pipeline: &ComputePipeline,
+        edit: &TerrainEdit,
+    ) -> bool {
+        let bounds = self.bounds.to_f32();
+        if !bounds.intersects(&edit.bounds()) || self.voxel_buffer.is_none() {
+            return false;
+        }
+        let device = instance.device();
+        let info = SculptInfo {
+            voxel_count: self.voxel_count.to_array(),
+            radius: edit.radius,
+            min: bounds.min.to_array(),
+            strength: edit.strength,
+            max: bounds.max.to_array(),
+            _pad0: 0.0,
+            center: edit.center.to_array(),
+            _pad1: 0.0,
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("terrain_sculpt_uniform_buffer"),
+            contents: bytemuck::bytes_of(&info),
+            usage: BufferUsages::UNIFORM,
+        });
+        let voxel_buffer = self.voxel_buffer.as_deref().unwrap();
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("terrain_sculpt_bind_group"),
+            layout: &sculpt_pipeline.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &uniform_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: voxel_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+            ],
+        });
+        let group_count = |count: u32| (count + SHADER_WORKGROUP_SIZE - 1) / SHADER_WORKGROUP_SIZE;
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("terrain_sculpt_compute_pass"),
+            });
+            compute_pass.set_pipeline(sculpt_pipeline);
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.dispatch(
+                group_count(self.voxel_count.width),
+                group_count(self.voxel_count.height),
+                group_count(self.voxel_count.depth),
             );
-            compute_triangles
-                .iter()
-                .map(|t| Triangle {
-                    position: t.position.map(|x| Point3D::from([x[0], x[1], x[2]])),
-                    id: unsafe { std::mem::transmute(t.id) },
-                })
-                .collect()
         }
+        self.create_staging_voxel_buffer(instance);
+        encoder.copy_buffer_to_buffer(
+            self.voxel_buffer.as_ref().unwrap(),
+            0,
+            self.staging_voxel_buffer.as_ref().unwrap().buffer(),
+            0,
+            self.voxel_buffer_size(),
+        );
+        self.clear_triangle_buffer();
+        true
+    }
+
+    // Replace every CPU voxel by the result of f from its position and value,
+    // returns true if any voxel changed
+    pub fn edit_voxels<F>(&mut self, f: F) -> bool
+    where
+        F: Fn(&Point3D<f32, WorldSpace>, f32) -> f32,
+    {
+        let bounds = self.bounds.to_f32();
+        let size = self.voxel_count;
+        let voxels = match self.voxels.as_mut() {
+            Some(voxels) => voxels,
+            None => return false,
+        };
+        let step = |min: f32, max: f32, count: u32| (max - min) / (count.max(2) - 1) as f32;
+        let step = vec3(
+            step(bounds.min.x, bounds.max.x, size.width),
+            step(bounds.min.y, bounds.max.y, size.height),
+            step(bounds.min.z, bounds.max.z, size.depth),
+        );
+        let mut modified = false;
+        for z in 0..size.depth {
+            for y in 0..size.height {
+                for x in 0..size.width {
+                    let position =
+                        bounds.min + vec3(x as f32 * step.x, y as f32 * step.y, z as f32 * step.z);
+                    let voxel = &mut voxels[(x + size.width * (y + size.height * z)) as usize];
+                    let value = f(&position, voxel.value);
+                    if value != voxel.value {
+                        voxel.value = value;
+                        modified = true;
+                    }
+                }
+            }
+        }
+        modified
+    }
+
+    // Upload the CPU voxels and drop the triangles so that they are
+    // regenerated from the edited voxels
+    pub fn write_voxel_buffer(&mut self, instance: &Instance) {
+        let voxels = self.voxels.as_ref().unwrap();
+        instance.queue().write_buffer(
+            self.voxel_buffer.as_ref().unwrap(),
+            0,
+            bytemuck::cast_slice(voxels),
+        );
+        if let Some(staging_voxel_buffer) = self.staging_voxel_buffer.as_ref() {
+            let mut encoder = instance
+                .device()
+                .create_command_encoder(&CommandEncoderDescriptor { label: None });
+            encoder.copy_buffer_to_buffer(
+                self.voxel_buffer.as_ref().unwrap(),
+                0,
+                staging_voxel_buffer.buffer(),
+                0,
+                self.voxel_buffer_size(),
+            );
+            instance.queue().submit(std::iter::once(encoder.finish()));
+        }
+        self.clear_triangle_buffer();
     }
 
     fn total_voxel_count(&self) -> u32 {
         self.voxel_count.volume()
     }
 
-    fn total_cell_count(&self) -> u32 {
-        (self.voxel_count - size3(1, 1, 1)).volume()
+    pub fn max_triangle_count(&self) -> u32 {
+        self.total_voxel_count() * MAX_VOXEL_TRIANGLES
     }
 
     pub fn bounds(&self) -> Box3D<i32, WorldSpace> {
         self.bounds
     }
 
+    pub fn level(&self) -> u32 {
+        self.level
+    }
+
     pub fn voxel_buffer(&self) -> Option<&Buffer> {
-        self.voxel_buffer.as_ref()
+        self.voxel_buffer.as_deref()
     }
 
     pub fn voxel_count(&self) -> Size3D<u32, UnknownUnit> {
@@ -369,10 +1458,63 @@ impl Chunk {
     }
 
     pub fn triangle_buffer(&self) -> Option<&Buffer> {
-        self.triangle_buffer.as_ref()
+        self.triangle_buffer.as_deref()
+    }
+
+    pub fn triangle_count(&self) -> u32 {
+        self.triangle_count
+    }
+
+    // Bytes of the buffers the chunk still owns on the GPU
+    // Weight in the chunk cache. The voxels, water and biomes are the same
+    // size on the GPU and once read back, finer levels have more layers.
+    pub fn cache_bytes(&self) -> u64 {
+        self.voxel_buffer_size() + self.water_buffer_size() + self.biome_buffer_size()
+    }
+
+    pub fn gpu_bytes(&self) -> u64 {
+        let buffers = [
+            (self.voxel_buffer.is_some(), self.voxel_buffer_size()),
+            (
+                self.staging_voxel_buffer.is_some(),
+                self.voxel_buffer_size(),
+            ),
+            (self.water_buffer.is_some(), self.water_buffer_size()),
+            (
+                self.staging_water_buffer.is_some(),
+                self.water_buffer_size(),
+            ),
+            (self.biome_buffer.is_some(), self.biome_buffer_size()),
+            (
+                self.staging_biome_buffer.is_some(),
+                self.biome_buffer_size(),
+            ),
+            (self.triangle_buffer.is_some(), self.triangle_buffer_bytes()),
+        ];
+        IntoIterator::into_iter(buffers)
+            .filter(|(owned, _)| *owned)
+            .map(|(_, size)| size)
+            .sum()
+    }
+
+    pub fn triangle_meshing(&self) -> Option<MeshingAlgorithm> {
+        self.triangle_meshing
+    }
+
+    // False when the triangles were generated to be drawn from the GPU
+    pub fn has_staged_triangles(&self) -> bool {
+        self.stage_triangles
+    }
+
+    // The mesh drawn from the triangle buffer owns it from then on
+    pub fn take_triangle_buffer(&mut self) -> Option<PooledBuffer> {
+        self.triangle_buffer.take()
     }
 
     pub fn clear_triangle_buffer(&mut self) {
-        self.triangle_buffer = None
+        self.triangle_buffer = None;
+        self.staging_count_buffer = None;
+        self.triangle_meshing = None;
+        self.mapping = None;
     }
 }
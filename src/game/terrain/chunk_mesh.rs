@@ -1,19 +1,47 @@
-use crate::game::base::{LocalSpace, WorldSpace};
+use crate::game::base::{
+    closest_point_on_triangle, ray_intersects_box, ray_intersects_triangle, LocalSpace, WorldSpace,
+};
 use crate::game::mesh::Mesh;
-use crate::game::terrain::chunk::Voxel;
-use crate::gfx::Instance;
+use crate::game::terrain::arena::{ArenaBuffer, MeshArenas};
+use crate::game::terrain::biome::BIOME_COUNT;
+use crate::game::terrain::chunk::{Voxel, WaterVertex};
+use crate::game::terrain::enclosure::Enclosure;
+use crate::game::terrain::normals::NormalPipelines;
+use crate::game::terrain::pipelines::TerrainPipelines;
+use crate::game::terrain::transition::{Side, StitchStride};
+use crate::game::terrain::traversability::SurfaceMetadata;
+use crate::game::terrain::vertex_color::vertex_color;
+use crate::gfx::{Instance, PooledBuffer};
 use euclid::{
-    point2, point3, vec2, Box3D, Point2D, Point3D, Size2D, Size3D, Transform3D, UnknownUnit,
+    point3, size2, vec3, Box3D, Point2D, Point3D, Size2D, Size3D, Transform3D, UnknownUnit,
+    Vector3D,
 };
-use futures::executor::block_on;
-use futures::select;
-use futures::FutureExt;
-use std::collections::HashSet;
-use std::future::Future;
-use std::pin::Pin;
+use std::collections::HashMap;
+use std::mem::{size_of, size_of_val};
 use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::*;
 
+// Skirts reach this many cells of the chunk below its border, the cells and
+// the cracks toward other levels both grow with coarser levels
+const SKIRT_CELLS: f32 = 2.0;
+// Distance from a side of the chunk within which a vertex lies on it
+const SIDE_EPSILON: f32 = 1e-4;
+// Keep in sync with normals.wgsl
+const NORMAL_WORKGROUP_SIZE: u32 = 64;
+// Face normals are summed in fixed point with this many steps for the area
+// of a cell face, which leaves room for hundreds of faces around a vertex
+const NORMAL_FIXED_POINT: f32 = 1048576.0;
+
+// How the render resources of the meshes are built, picked for the whole
+// world
+#[derive(Copy, Clone, Debug, Default, PartialEq)]
+pub struct MeshStyle {
+    // Border skirts instead of transition cells toward finer neighbors
+    pub skirts: bool,
+    // Every triangle has its own corners and the normal of its face
+    pub flat: bool,
+}
+
 #[derive(Debug)]
 pub struct VoxelFace {
     voxel_count: Size2D<u32, UnknownUnit>,
@@ -28,109 +56,22 @@ impl VoxelFace {
         }
     }
 
-    fn vertex(
-        &self,
-        voxel1: Point2D<u32, UnknownUnit>,
-        voxel2: Point2D<u32, UnknownUnit>,
-        isolevel: f32,
-        stride: u32,
-    ) -> Point2D<f32, LocalSpace> {
-        let min = voxel1.min(voxel2);
-        let max = voxel2.max(voxel1);
-        assert!((max - min).lower_than([2, 2].into()).all());
-        let min_stride = min / stride * stride;
-        let max_stride = (max + vec2(stride - 1, stride - 1)) / stride * stride;
-        assert!((max_stride - min_stride)
-            .lower_than([stride + 1, stride + 1].into())
-            .all());
-        let stride1 = point2(
-            if voxel1.x < voxel2.x {
-                min_stride.x
-            } else {
-                max_stride.x
-            },
-            if voxel1.y < voxel2.y {
-                min_stride.y
-            } else {
-                max_stride.y
-            },
-        );
-        let stride2 = point2(
-            if voxel2.x < voxel1.x {
-                min_stride.x
-            } else {
-                max_stride.x
-            },
-            if voxel2.y < voxel1.y {
-                min_stride.y
-            } else {
-                max_stride.y
-            },
-        );
-        let v1 = self.voxels[self.point_to_index(stride1) as usize];
-        let v2 = self.voxels[self.point_to_index(stride2) as usize];
-        let p1 = stride1
-            .to_vector()
-            .to_f32()
-            .component_div((self.voxel_count.to_vector() - vec2(1, 1)).to_f32())
-            .clamp(vec2(0.0, 0.0), vec2(1.0, 1.0));
-        let p2 = stride2
-            .to_vector()
-            .to_f32()
-            .component_div((self.voxel_count.to_vector() - vec2(1, 1)).to_f32())
-            .clamp(vec2(0.0, 0.0), vec2(1.0, 1.0));
-        let min_v_fract = (voxel1.to_f32() - stride1.to_f32())
-            .dot(stride2.to_f32() - stride1.to_f32())
-            / (stride2.to_f32() - stride1.to_f32()).square_length();
-        let max_v_fract = (voxel2.to_f32() - stride1.to_f32())
-            .dot(stride2.to_f32() - stride1.to_f32())
-            / (stride2.to_f32() - stride1.to_f32()).square_length();
-        let p1 = p1 + (p2 - p1) * min_v_fract;
-        let p2 = p1 + (p2 - p1) * max_v_fract;
-        // if stride > 1 {
-        //     println!("{} {}", min_v_fract, max_v_fract);
-        // }
-        let v1 = v1 + (v2 - v1) * min_v_fract;
-        let v2 = v1 + (v2 - v1) * max_v_fract;
-        let result = Self::vertex_lerp(
-            isolevel.clamp(v1.min(v2), v1.max(v2)),
-            p1.to_array().into(),
-            p2.to_array().into(),
-            v1,
-            v2,
-        );
-        // if result.x > 1.0 || result.y > 1.0 {
-        //     println!(
-        //         "{:?} {:?} {:?} {:?} {:?}",
-        //         p1,
-        //         p2,
-        //         v1 + (v2 - v1) * min_v_fract,
-        //         v1 + (v2 - v1) * max_v_fract,
-        //         result
-        //     );
-        // }
-        result
-    }
-
-    fn point_to_index(&self, p: Point2D<u32, UnknownUnit>) -> u32 {
-        p.x + self.voxel_count.width * p.y
-    }
-
-    fn vertex_lerp<T>(
-        isolevel: f32,
-        p1: Point2D<f32, T>,
-        p2: Point2D<f32, T>,
-        v1: f32,
-        v2: f32,
-    ) -> Point2D<f32, T> {
-        if (isolevel - v1).abs() < 0.00001 {
-            return p1;
-        }
-        if (isolevel - v2).abs() < 0.00001 {
-            return p2;
-        }
-        let mu = (isolevel - v1) / (v2 - v1);
-        p1 + (p2 - p1) * mu
+    pub fn voxel_count(&self) -> Size2D<u32, UnknownUnit> {
+        self.voxel_count
+    }
+
+    pub fn voxel(&self, x: u32, y: u32) -> f32 {
+        self.voxels[(x + self.voxel_count.width * y) as usize]
+    }
+
+    // Bilinear interpolation of the voxels, the point is in voxels
+    pub fn sample(&self, point: Point2D<f32, UnknownUnit>) -> f32 {
+        let x = (point.x.max(0.0) as u32).min(self.voxel_count.width - 2);
+        let y = (point.y.max(0.0) as u32).min(self.voxel_count.height - 2);
+        let (tx, ty) = (point.x - x as f32, point.y - y as f32);
+        let bottom = self.voxel(x, y) + (self.voxel(x + 1, y) - self.voxel(x, y)) * tx;
+        let top = self.voxel(x, y + 1) + (self.voxel(x + 1, y + 1) - self.voxel(x, y + 1)) * tx;
+        bottom + (top - bottom) * ty
     }
 }
 
@@ -182,36 +123,70 @@ impl EdgeVoxel {
     fn voxel_point_to_index(p: Point3D<u32, UnknownUnit>, size: Size3D<u32, UnknownUnit>) -> u32 {
         p.x + size.width * (p.y + size.height * p.z)
     }
-}
 
-#[derive(Debug, Default)]
-struct EdgeVertex {
-    min_x: HashSet<usize>,
-    max_x: HashSet<usize>,
-    min_y: HashSet<usize>,
-    max_y: HashSet<usize>,
+    pub fn face(&self, side: Side) -> &VoxelFace {
+        match side {
+            Side::MinX => &self.min_x,
+            Side::MaxX => &self.max_x,
+            Side::MinY => &self.min_y,
+            Side::MaxY => &self.max_y,
+        }
+    }
 }
 
-type MapFuture = Pin<Box<dyn Future<Output = Result<(), BufferAsyncError>> + Send + Sync>>;
+// Taken off a mesh by release_render_resources, the command buffers of the
+// frames in flight may still use them
+pub struct RenderResources {
+    buffers: Vec<Buffer>,
+    arena_buffers: Vec<ArenaBuffer>,
+    render_bundles: Vec<RenderBundle>,
+}
 
-#[derive(PartialEq)]
-pub enum MapStatus {
-    Mapping,
-    Mapped,
-    Unmap,
+impl RenderResources {
+    // Of a mesh that was not resident
+    pub fn is_empty(&self) -> bool {
+        self.buffers.is_empty() && self.arena_buffers.is_empty() && self.render_bundles.is_empty()
+    }
 }
 
 pub struct ChunkMesh {
     bounds: Box3D<i32, WorldSpace>,
     voxel_count: Size3D<u32, UnknownUnit>,
     mesh: Mesh<LocalSpace>,
-    vertex_buffer: Option<Buffer>,
-    index_buffer: Option<Buffer>,
+    // Triangle list of the water surface
+    water: Vec<WaterVertex>,
+    // Biome weights of each voxel column
+    biome_weights: Vec<[f32; BIOME_COUNT]>,
+    enclosure: Enclosure,
+    surface: SurfaceMetadata,
+    // Sub-allocated from the arenas of the pipelines
+    vertex_buffer: Option<ArenaBuffer>,
+    index_buffer: Option<ArenaBuffer>,
     uniform_buffer: Option<Buffer>,
-    render_bundle: Option<RenderBundle>,
+    // One for each camera buffer, empty when the mesh is not resident
+    render_bundles: Vec<RenderBundle>,
+    water_vertex_buffer: Option<ArenaBuffer>,
+    water_render_bundles: Vec<RenderBundle>,
     edge_voxel: EdgeVoxel,
-    edge_vertex: EdgeVertex,
-    vertex_buffer_map_future: Option<MapFuture>,
+    // Ratios of the finer neighbors the mesh is stitched to and the
+    // transition cells toward them
+    stride: StitchStride,
+    transition: Option<Mesh<LocalSpace>>,
+    transition_vertex_buffer: Option<ArenaBuffer>,
+    transition_index_buffer: Option<ArenaBuffer>,
+    pipeline_generation: Option<u64>,
+    // Surface deviation from the parent chunk in world units
+    geometric_error: Option<f32>,
+    // Compute output drawn in place of the mesh, which is left empty
+    gpu_triangle_buffer: Option<PooledBuffer>,
+    // Voxel count, biome and enclosure buffers read with the GPU triangles
+    gpu_buffers: Vec<Buffer>,
+    // Indirect draw of the GPU triangles
+    draw_args_buffer: Option<Buffer>,
+    gpu_triangle_count: u32,
+    gpu_triangle_bytes: u64,
+    // Bytes of the buffers created with the render resources
+    render_bytes: u64,
 }
 
 #[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
@@ -219,6 +194,21 @@ pub struct ChunkMesh {
 pub struct VertexData {
     position: [f32; 4],
     normal: [f32; 4],
+    traversability: f32,
+    biome_weights: [f32; BIOME_COUNT],
+    enclosure: f32,
+    color: [f32; 3],
+}
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct NormalInfo {
+    mesh_vertex_count: u32,
+    vertex_count: u32,
+    face_count: u32,
+    vertex_words: u32,
+    scale: f32,
+    _pad: [f32; 3],
 }
 
 #[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
@@ -233,19 +223,95 @@ impl ChunkMesh {
         mesh: Mesh<LocalSpace>,
         voxel_count: Size3D<u32, UnknownUnit>,
         edge_voxel: EdgeVoxel,
+        water: Vec<WaterVertex>,
+        biome_weights: Vec<[f32; BIOME_COUNT]>,
+        enclosure: Enclosure,
     ) -> Self {
+        let surface = SurfaceMetadata::from_mesh(
+            &mesh,
+            bounds,
+            size2(voxel_count.width - 1, voxel_count.height - 1),
+        );
         Self {
             bounds,
             mesh,
+            water,
+            biome_weights,
+            enclosure,
+            surface,
             voxel_count,
             vertex_buffer: None,
             index_buffer: None,
             uniform_buffer: None,
-            render_bundle: None,
+            render_bundles: vec![],
+            water_vertex_buffer: None,
+            water_render_bundles: vec![],
             edge_voxel,
-            edge_vertex: Default::default(),
-            vertex_buffer_map_future: None,
+            stride: StitchStride::NONE,
+            transition: None,
+            transition_vertex_buffer: None,
+            transition_index_buffer: None,
+            pipeline_generation: None,
+            geometric_error: None,
+            gpu_triangle_buffer: None,
+            gpu_buffers: vec![],
+            draw_args_buffer: None,
+            gpu_triangle_count: 0,
+            gpu_triangle_bytes: 0,
+            render_bytes: 0,
+        }
+    }
+
+    pub fn geometric_error(&self) -> Option<f32> {
+        self.geometric_error
+    }
+
+    pub fn set_geometric_error(&mut self, geometric_error: Option<f32>) {
+        self.geometric_error = geometric_error;
+    }
+
+    // Meshes drawn from the triangle buffer of their chunk are not read back,
+    // they can not be raycast or stitched
+    pub fn set_gpu_triangles(
+        &mut self,
+        triangle_buffer: PooledBuffer,
+        triangle_count: u32,
+        bytes: u64,
+    ) {
+        self.gpu_triangle_buffer = Some(triangle_buffer);
+        self.gpu_triangle_count = triangle_count;
+        self.gpu_triangle_bytes = bytes;
+    }
+
+    pub fn is_gpu_resident(&self) -> bool {
+        self.gpu_triangle_buffer.is_some()
+    }
+
+    // Triangles drawn by the render resources, including the transition cells
+    pub fn triangle_count(&self) -> usize {
+        if self.is_gpu_resident() {
+            return self.gpu_triangle_count as usize;
         }
+        self.mesh.faces().len() + self.transition.as_ref().map_or(0, |x| x.faces().len())
+    }
+
+    // Weight in the mesh cache, the CPU data and everything the mesh holds on
+    // the GPU. The render resources are created and released in the cache,
+    // which has to be told to weigh the mesh again.
+    pub fn cache_bytes(&self) -> u64 {
+        let mesh = |mesh: &Mesh<LocalSpace>| {
+            size_of_val(mesh.vertex()) + size_of_val(mesh.ids()) + size_of_val(mesh.faces())
+        };
+        let cpu = mesh(&self.mesh)
+            + self.transition.as_ref().map_or(0, mesh)
+            + size_of_val(&self.water[..])
+            + size_of_val(&self.biome_weights[..]);
+        cpu as u64 + self.gpu_bytes()
+    }
+
+    // The GPU triangles stay on the GPU with or without the render resources
+    pub fn gpu_bytes(&self) -> u64 {
+        self.gpu_triangle_bytes + self.render_bytes
     }
 
     fn transformation_matrix(&self) -> Transform3D<f32, LocalSpace, WorldSpace> {
@@ -257,55 +323,99 @@ impl ChunkMesh {
     pub fn create_render_resources(
         &mut self,
         instance: &Instance,
-        pipeline: &RenderPipeline,
-        bind_group_layout: &BindGroupLayout,
-        camera_uniform_buffer: &Buffer,
-        target_format: TextureFormat,
+        pipelines: &TerrainPipelines,
+        arenas: &MeshArenas,
+        camera_buffers: &[Buffer],
+        style: MeshStyle,
     ) {
         if self.vertex_buffer.is_some() || self.uniform_buffer.is_some() {
             return;
         }
-        for (i, id) in self.mesh.ids().iter().enumerate() {
-            let [i1, i2]: [u32; 2] = unsafe { std::mem::transmute(*id) };
-            let p1 = self.voxel_index_to_point(i1);
-            let p2 = self.voxel_index_to_point(i2);
-            if p1.x == 0 && p2.x == 0 {
-                self.edge_vertex.min_x.insert(i);
-            } else if p1.y == 0 && p2.y == 0 {
-                self.edge_vertex.min_y.insert(i);
-            } else if p1.x == self.voxel_count.width - 1 && p2.x == self.voxel_count.width - 1 {
-                self.edge_vertex.max_x.insert(i);
-            } else if p1.y == self.voxel_count.height - 1 && p2.y == self.voxel_count.height - 1 {
-                self.edge_vertex.max_y.insert(i);
-            }
-        }
         let device = instance.device();
-        let vertex_buffer_data: Vec<_> = self
+        // Skirts replace the stitching, the border is left where it is
+        let stride = if style.skirts {
+            StitchStride::NONE
+        } else {
+            self.stride
+        };
+        // The normals are left to calculate_normals
+        let mut vertex_buffer_data: Vec<_> = self
             .mesh
             .vertex()
             .iter()
-            .zip(self.mesh.normals().iter())
-            .map(|(v, n)| VertexData {
-                position: [v.x, v.y, v.z, 1.0],
-                normal: [n.x, n.y, n.z, 1.0],
+            .enumerate()
+            .map(|(i, v)| {
+                self.vertex_data(
+                    &stride.shrink(v, self.voxel_count),
+                    &Vector3D::zero(),
+                    self.mesh_color(i),
+                )
             })
             .collect();
-        let index_buffer_data: Vec<_> = self
-            .mesh
-            .faces()
-            .iter()
-            .flat_map(|x| x.map(|x| x as u32))
-            .collect();
-        self.vertex_buffer = Some(device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("chunk_mesh_vertex_buffer"),
-            contents: bytemuck::cast_slice(&vertex_buffer_data),
-            usage: BufferUsages::VERTEX | BufferUsages::MAP_WRITE,
-        }));
-        self.index_buffer = Some(device.create_buffer_init(&BufferInitDescriptor {
-            label: Some("chunk_mesh_index_buffer"),
-            contents: bytemuck::cast_slice(&index_buffer_data),
-            usage: BufferUsages::INDEX,
-        }));
+        let mut index_buffer_data = index_data(&self.mesh);
+        let mut normal_sources = vec![];
+        if style.skirts {
+            let (skirt_vertex_data, skirt_index_data, skirt_sources) = self.skirt_data();
+            let base = vertex_buffer_data.len() as u32;
+            vertex_buffer_data.extend(skirt_vertex_data);
+            index_buffer_data.extend(skirt_index_data.iter().map(|x| x + base));
+            normal_sources = skirt_sources;
+        }
+        if style.flat {
+            let (flat_vertex_data, flat_index_data) =
+                flatten(&vertex_buffer_data, &index_buffer_data);
+            vertex_buffer_data = flat_vertex_data;
+            index_buffer_data = flat_index_data;
+        }
+        let mut render_bytes = (size_of_val(&vertex_buffer_data[..])
+            + size_of_val(&index_buffer_data[..])
+            + size_of::<UniformData>()) as u64;
+        self.vertex_buffer = Some(
+            arenas
+                .vertex
+                .allocate(instance, bytemuck::cast_slice(&vertex_buffer_data)),
+        );
+        self.index_buffer = Some(
+            arenas
+                .index
+                .allocate(instance, bytemuck::cast_slice(&index_buffer_data)),
+        );
+        // Flat normals are already written
+        if !style.flat {
+            self.calculate_normals(instance, &pipelines.normals, &normal_sources);
+        }
+        let transition_index_count = match self.transition.as_ref().filter(|_| !style.skirts) {
+            Some(transition) if !transition.faces().is_empty() => {
+                let mut transition_vertex_data: Vec<_> = transition
+                    .vertex()
+                    .iter()
+                    .zip(transition.normals().iter())
+                    .map(|(v, n)| self.vertex_data(v, n, self.color_at(v)))
+                    .collect();
+                let mut transition_index_data = index_data(transition);
+                if style.flat {
+                    let (flat_vertex_data, flat_index_data) =
+                        flatten(&transition_vertex_data, &transition_index_data);
+                    transition_vertex_data = flat_vertex_data;
+                    transition_index_data = flat_index_data;
+                }
+                render_bytes += (size_of_val(&transition_vertex_data[..])
+                    + size_of_val(&transition_index_data[..]))
+                    as u64;
+                self.transition_vertex_buffer = Some(
+                    arenas
+                        .vertex
+                        .allocate(instance, bytemuck::cast_slice(&transition_vertex_data)),
+                );
+                self.transition_index_buffer = Some(
+                    arenas
+                        .index
+                        .allocate(instance, bytemuck::cast_slice(&transition_index_data)),
+                );
+                transition_index_data.len() as u32
+            }
+            _ => 0,
+        };
         self.uniform_buffer = Some(device.create_buffer_init(&BufferInitDescriptor {
             label: Some("chunk_mesh_uniform_buffer"),
             contents: bytemuck::bytes_of(&UniformData {
@@ -313,12 +423,179 @@ impl ChunkMesh {
             }),
             usage: BufferUsages::UNIFORM,
         }));
+        let bind_groups = camera_buffers
+            .iter()
+            .map(|camera_buffer| {
+                device.create_bind_group(&BindGroupDescriptor {
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: self.uniform_buffer.as_ref().unwrap(),
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: camera_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &pipelines.biome_style_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 3,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &pipelines.point_light_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 4,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &pipelines.wind_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 5,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &pipelines.magma_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                    ],
+                    label: Some("chunk_mesh_bind_group"),
+                    layout: &pipelines.render_bind_group_layout,
+                })
+            })
+            .collect::<Vec<_>>();
+        let gpu_bind_group = if self.gpu_triangle_buffer.is_some() {
+            Some(self.create_gpu_bind_group(instance, pipelines))
+        } else {
+            None
+        };
+        let bundle_encoder_descriptor = RenderBundleEncoderDescriptor {
+            label: Some("chunk_mesh_render_bundle_encoder"),
+            color_formats: &[pipelines.target_format],
+            depth_stencil: Some(RenderBundleDepthStencil {
+                format: TextureFormat::Depth32Float,
+                depth_read_only: false,
+                stencil_read_only: false,
+            }),
+            sample_count: pipelines.sample_count,
+        };
+        if !self.water.is_empty() {
+            render_bytes += size_of_val(&self.water[..]) as u64;
+            self.water_vertex_buffer = Some(
+                arenas
+                    .vertex
+                    .allocate(instance, bytemuck::cast_slice(&self.water)),
+            );
+        }
+        for bind_group in &bind_groups {
+            let mut encoder = device.create_render_bundle_encoder(&bundle_encoder_descriptor);
+            encoder.set_bind_group(0, bind_group, &[]);
+            if let Some(gpu_bind_group) = gpu_bind_group.as_ref() {
+                encoder.set_bind_group(1, gpu_bind_group, &[]);
+                encoder.set_pipeline(&pipelines.gpu_mesh);
+                encoder.draw_indirect(self.draw_args_buffer.as_ref().unwrap(), 0);
+            } else {
+                encoder.set_vertex_buffer(0, self.vertex_buffer.as_ref().unwrap().slice());
+                encoder.set_index_buffer(
+                    self.index_buffer.as_ref().unwrap().slice(),
+                    IndexFormat::Uint32,
+                );
+                encoder.set_pipeline(&pipelines.render);
+                encoder.draw_indexed(0..index_buffer_data.len() as u32, 0, 0..1);
+            }
+            if transition_index_count > 0 {
+                encoder
+                    .set_vertex_buffer(0, self.transition_vertex_buffer.as_ref().unwrap().slice());
+                encoder.set_index_buffer(
+                    self.transition_index_buffer.as_ref().unwrap().slice(),
+                    IndexFormat::Uint32,
+                );
+                encoder.draw_indexed(0..transition_index_count, 0, 0..1);
+            }
+            self.render_bundles
+                .push(encoder.finish(&RenderBundleDescriptor {
+                    label: Some("chunk_mesh_render_bundle"),
+                }));
+            if let Some(water_vertex_buffer) = self.water_vertex_buffer.as_ref() {
+                let mut encoder = device.create_render_bundle_encoder(&bundle_encoder_descriptor);
+                encoder.set_bind_group(0, bind_group, &[]);
+                encoder.set_vertex_buffer(0, water_vertex_buffer.slice());
+                encoder.set_pipeline(&pipelines.water);
+                encoder.draw(0..self.water.len() as u32, 0..1);
+                self.water_render_bundles
+                    .push(encoder.finish(&RenderBundleDescriptor {
+                        label: Some("chunk_mesh_water_render_bundle"),
+                    }));
+            }
+        }
+        self.pipeline_generation = Some(pipelines.generation);
+        self.render_bytes = render_bytes + self.gpu_buffer_bytes();
+    }
+
+    // Buffers of create_gpu_bind_group, none unless drawn from the GPU
+    fn gpu_buffer_bytes(&self) -> u64 {
+        if self.gpu_buffers.is_empty() {
+            return 0;
+        }
+        // The voxel count uniform and the draw args are four words each
+        (size_of::<[u32; 4]>() * 2
+            + size_of_val(&self.biome_weights[..])
+            + size_of_val(&self.enclosure.packed()[..])) as u64
+    }
+
+    // Bind group of gpu_mesh.wgsl and the indirect draw, their buffers live
+    // with the other render resources
+    fn create_gpu_bind_group(
+        &mut self,
+        instance: &Instance,
+        pipelines: &TerrainPipelines,
+    ) -> BindGroup {
+        let device = instance.device();
+        let voxel_count = self.voxel_count;
+        let chunk_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("chunk_mesh_gpu_chunk_buffer"),
+            contents: bytemuck::bytes_of(&[
+                voxel_count.width,
+                voxel_count.height,
+                voxel_count.depth,
+                0,
+            ]),
+            usage: BufferUsages::UNIFORM,
+        });
+        let biome_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("chunk_mesh_gpu_biome_buffer"),
+            contents: bytemuck::cast_slice(&self.biome_weights),
+            usage: BufferUsages::STORAGE,
+        });
+        let enclosure_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("chunk_mesh_gpu_enclosure_buffer"),
+            contents: bytemuck::cast_slice(&self.enclosure.packed()),
+            usage: BufferUsages::STORAGE,
+        });
         let bind_group = device.create_bind_group(&BindGroupDescriptor {
             entries: &[
                 BindGroupEntry {
                     binding: 0,
                     resource: BindingResource::Buffer(BufferBinding {
-                        buffer: self.uniform_buffer.as_ref().unwrap(),
+                        buffer: self.gpu_triangle_buffer.as_ref().unwrap(),
                         offset: 0,
                         size: None,
                     }),
@@ -326,152 +603,431 @@ impl ChunkMesh {
                 BindGroupEntry {
                     binding: 1,
                     resource: BindingResource::Buffer(BufferBinding {
-                        buffer: camera_uniform_buffer,
+                        buffer: &chunk_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &biome_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &enclosure_buffer,
                         offset: 0,
                         size: None,
                     }),
                 },
             ],
-            label: Some("chunk_mesh_bind_group"),
-            layout: bind_group_layout,
+            label: Some("chunk_mesh_gpu_bind_group"),
+            layout: &pipelines.gpu_mesh_bind_group_layout,
         });
-        let mut encoder = device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
-            label: Some("chunk_mesh_render_bundle_encoder"),
-            color_formats: &[target_format],
-            depth_stencil: Some(RenderBundleDepthStencil {
-                format: TextureFormat::Depth32Float,
-                depth_read_only: false,
-                stencil_read_only: false,
-            }),
-            sample_count: 1,
-        });
-        encoder.set_bind_group(0, &bind_group, &[]);
-        encoder.set_vertex_buffer(0, self.vertex_buffer.as_ref().unwrap().slice(..));
-        encoder.set_index_buffer(
-            self.index_buffer.as_ref().unwrap().slice(..),
-            IndexFormat::Uint32,
+        self.gpu_buffers = vec![chunk_buffer, biome_buffer, enclosure_buffer];
+        let draw_args_buffer = pipelines.draw_args.create_buffer(instance);
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        pipelines.draw_args.dispatch(
+            instance,
+            &mut encoder,
+            self.gpu_triangle_buffer.as_ref().unwrap(),
+            &draw_args_buffer,
         );
-        encoder.set_pipeline(pipeline);
-        encoder.draw_indexed(0..index_buffer_data.len() as u32, 0, 0..1);
-        self.render_bundle = Some(encoder.finish(&RenderBundleDescriptor {
-            label: Some("chunk_mesh_render_bundle"),
-        }));
+        instance.queue().submit(std::iter::once(encoder.finish()));
+        self.draw_args_buffer = Some(draw_args_buffer);
+        bind_group
     }
 
-    pub fn render_bundle(&self) -> Option<&RenderBundle> {
-        self.render_bundle.as_ref()
-    }
-
-    pub fn map_vertex_buffer(&mut self) {
-        if self.vertex_buffer_map_future.is_none() {
-            let buffer_slice = self.vertex_buffer.as_ref().unwrap().slice(..);
-            self.vertex_buffer_map_future = Some(Box::pin(buffer_slice.map_async(MapMode::Write)));
+    // Smooth normals of the mesh written into the vertex buffer, the vertices
+    // after the mesh copy the normal of their source vertex
+    fn calculate_normals(&self, instance: &Instance, pipelines: &NormalPipelines, sources: &[u32]) {
+        if self.mesh.faces().is_empty() {
+            return;
+        }
+        let device = instance.device();
+        let mesh_vertex_count = self.mesh.vertex().len() as u32;
+        let vertex_count = mesh_vertex_count + sources.len() as u32;
+        let face_count = self.mesh.faces().len() as u32;
+        // Cross products are scaled to the cell faces of the finest axis in
+        // local space
+        let voxel_count = self.voxel_count;
+        let cells = voxel_count
+            .width
+            .min(voxel_count.height)
+            .min(voxel_count.depth)
+            .max(2)
+            - 1;
+        let info = NormalInfo {
+            mesh_vertex_count,
+            vertex_count,
+            face_count,
+            vertex_words: (size_of::<VertexData>() / size_of::<f32>()) as u32,
+            scale: NORMAL_FIXED_POINT * (cells * cells) as f32,
+            _pad: [0.0; 3],
+        };
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("chunk_mesh_normal_uniform_buffer"),
+            contents: bytemuck::bytes_of(&info),
+            usage: BufferUsages::UNIFORM,
+        });
+        let sum_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("chunk_mesh_normal_sum_buffer"),
+            size: mesh_vertex_count as u64 * size_of::<[i32; 3]>() as u64,
+            mapped_at_creation: false,
+            usage: BufferUsages::STORAGE,
+        });
+        // Bound even when no vertex copies a normal
+        let source_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("chunk_mesh_normal_source_buffer"),
+            contents: if sources.is_empty() {
+                bytemuck::bytes_of(&0u32)
+            } else {
+                bytemuck::cast_slice(sources)
+            },
+            usage: BufferUsages::STORAGE,
+        });
+        let binding = |buffer| {
+            BindingResource::Buffer(BufferBinding {
+                buffer,
+                offset: 0,
+                size: None,
+            })
+        };
+        let bind_group = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("chunk_mesh_normal_bind_group"),
+            layout: &pipelines.accumulate_normals.get_bind_group_layout(0),
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: binding(&uniform_buffer),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: self.index_buffer.as_ref().unwrap().binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: binding(&sum_buffer),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: self.vertex_buffer.as_ref().unwrap().binding(),
+                },
+                BindGroupEntry {
+                    binding: 4,
+                    resource: binding(&source_buffer),
+                },
+            ],
+        });
+        let group_count = |count: u32| (count + NORMAL_WORKGROUP_SIZE - 1) / NORMAL_WORKGROUP_SIZE;
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("chunk_mesh_normal_compute_pass"),
+            });
+            compute_pass.set_bind_group(0, &bind_group, &[]);
+            compute_pass.set_pipeline(&pipelines.accumulate_normals);
+            compute_pass.dispatch(group_count(face_count), 1, 1);
+            compute_pass.set_pipeline(&pipelines.write_normals);
+            compute_pass.dispatch(group_count(vertex_count), 1, 1);
         }
+        instance.queue().submit(std::iter::once(encoder.finish()));
     }
 
-    pub fn map_vertex_buffer_status(&mut self) -> MapStatus {
-        if self.vertex_buffer_map_future.is_none() {
-            return MapStatus::Unmap;
+    // Returns the distance to the closest triangle and its normal facing the ray
+    #[profiling::function]
+    pub fn raycast(
+        &self,
+        origin: &Point3D<f32, WorldSpace>,
+        direction: &Vector3D<f32, WorldSpace>,
+        max_distance: f32,
+    ) -> Option<(f32, Vector3D<f32, WorldSpace>)> {
+        let (t_min, _) = ray_intersects_box(origin, direction, &self.bounds.to_f32())?;
+        if t_min > max_distance {
+            return None;
         }
-        let mut future = self.vertex_buffer_map_future.as_mut().unwrap().fuse();
-        block_on(async {
-            select! {
-                _ = future => MapStatus::Mapped,
-                default  => MapStatus::Mapping,
-                complete =>  MapStatus::Mapped
+        let vertex = self.world_vertex();
+        let mut closest: Option<(f32, Vector3D<f32, WorldSpace>)> = None;
+        for face in self.mesh.faces() {
+            let triangle = [vertex[face[0]], vertex[face[1]], vertex[face[2]]];
+            if let Some(t) = ray_intersects_triangle(origin, direction, &triangle) {
+                if t <= max_distance && closest.map_or(true, |(d, _)| t < d) {
+                    let normal = (triangle[1] - triangle[0])
+                        .cross(triangle[2] - triangle[0])
+                        .normalize();
+                    let normal = if normal.dot(*direction) > 0.0 {
+                        -normal
+                    } else {
+                        normal
+                    };
+                    closest = Some((t, normal));
+                }
             }
-        })
+        }
+        closest
     }
 
-    pub fn stitch_edges(
+    // Returns the distance to the closest point on the mesh, the point and the
+    // normal facing the query point. Points on the surface get the upward
+    // facing normal.
+    #[profiling::function]
+    pub fn closest_point(
         &self,
-        min_x_stride: u32,
-        max_x_stride: u32,
-        min_y_stride: u32,
-        max_y_stride: u32,
-    ) {
-        {
-            let buffer_slice = self.vertex_buffer.as_ref().unwrap().slice(..);
-            block_on(buffer_slice.map_async(MapMode::Write)).unwrap();
-            let mut raw_buffer = &mut *buffer_slice.get_mapped_range_mut();
-            let buffer = bytemuck::cast_slice_mut::<_, VertexData>(&mut raw_buffer);
-            let normals = self.mesh.normals();
-            let ids = self.mesh.ids();
-            for i in &self.edge_vertex.min_x {
-                let [i1, i2]: [u32; 2] = unsafe { std::mem::transmute(ids[*i]) };
-                let voxel_point_1 = self.voxel_index_to_point(i1);
-                let voxel_point_2 = self.voxel_index_to_point(i2);
-                let p = self.edge_voxel.min_x.vertex(
-                    voxel_point_1.yz(),
-                    voxel_point_2.yz(),
-                    0.5,
-                    min_x_stride,
-                );
-                // println!("{:?}", p);
-                let n = normals[*i];
-                buffer[*i] = VertexData {
-                    position: [0.0, p.x, p.y, 1.0],
-                    normal: [n.x, n.y, n.z, 0.0],
-                }
+        point: &Point3D<f32, WorldSpace>,
+        max_distance: f32,
+    ) -> Option<(f32, Point3D<f32, WorldSpace>, Vector3D<f32, WorldSpace>)> {
+        let bounds = self
+            .bounds
+            .to_f32()
+            .inflate(max_distance, max_distance, max_distance);
+        if !bounds.contains(*point) {
+            return None;
+        }
+        let vertex = self.world_vertex();
+        let mut closest: Option<(f32, Point3D<f32, WorldSpace>, Vector3D<f32, WorldSpace>)> = None;
+        for face in self.mesh.faces() {
+            let triangle = [vertex[face[0]], vertex[face[1]], vertex[face[2]]];
+            let normal = (triangle[1] - triangle[0]).cross(triangle[2] - triangle[0]);
+            if normal.square_length() == 0.0 {
+                continue;
             }
-            for i in &self.edge_vertex.max_x {
-                let [i1, i2]: [u32; 2] = unsafe { std::mem::transmute(ids[*i]) };
-                let voxel_point_1 = self.voxel_index_to_point(i1);
-                let voxel_point_2 = self.voxel_index_to_point(i2);
-                let p = self.edge_voxel.max_x.vertex(
-                    voxel_point_1.yz(),
-                    voxel_point_2.yz(),
-                    0.5,
-                    max_x_stride,
-                );
-                let n = normals[*i];
-                buffer[*i] = VertexData {
-                    position: [1.0, p.x, p.y, 1.0],
-                    normal: [n.x, n.y, n.z, 0.0],
-                }
+            let p = closest_point_on_triangle(point, &triangle);
+            let distance = p.distance_to(*point);
+            if distance <= max_distance && closest.map_or(true, |(d, _, _)| distance < d) {
+                let normal = normal.normalize();
+                let facing = if distance > f32::EPSILON {
+                    normal.dot(*point - p)
+                } else {
+                    normal.z
+                };
+                let normal = if facing < 0.0 { -normal } else { normal };
+                closest = Some((distance, p, normal));
             }
-            for i in &self.edge_vertex.min_y {
-                let [i1, i2]: [u32; 2] = unsafe { std::mem::transmute(ids[*i]) };
-                let voxel_point_1 = self.voxel_index_to_point(i1);
-                let voxel_point_2 = self.voxel_index_to_point(i2);
-                let p = self.edge_voxel.min_y.vertex(
-                    voxel_point_1.xz(),
-                    voxel_point_2.xz(),
-                    0.5,
-                    min_y_stride,
-                );
-                let n = normals[*i];
-                buffer[*i] = VertexData {
-                    position: [p.x, 0.0, p.y, 1.0],
-                    normal: [n.x, n.y, n.z, 0.0],
+        }
+        closest
+    }
+
+    fn world_vertex(&self) -> Vec<Point3D<f32, WorldSpace>> {
+        let transform = self.transformation_matrix();
+        self.mesh
+            .vertex()
+            .iter()
+            .map(|x| transform.transform_point3d(*x).unwrap())
+            .collect()
+    }
+
+    pub fn world_triangles(&self) -> Vec<[Point3D<f32, WorldSpace>; 3]> {
+        let vertex = self.world_vertex();
+        self.mesh
+            .faces()
+            .iter()
+            .map(|x| [vertex[x[0]], vertex[x[1]], vertex[x[2]]])
+            .collect()
+    }
+
+    pub fn is_resident(&self) -> bool {
+        !self.render_bundles.is_empty()
+    }
+
+    pub fn pipeline_generation(&self) -> Option<u64> {
+        self.pipeline_generation
+    }
+
+    // Drop the GPU buffers and render bundle but keep the CPU mesh so that
+    // they can be recreated when the chunk comes back into view. GPU
+    // triangles are kept as they are the mesh.
+    pub fn release_render_resources(&mut self) -> RenderResources {
+        self.pipeline_generation = None;
+        self.render_bytes = 0;
+        let mut buffers = std::mem::take(&mut self.gpu_buffers);
+        buffers.extend(self.draw_args_buffer.take());
+        buffers.extend(self.uniform_buffer.take());
+        let arena_buffers = vec![
+            self.transition_index_buffer.take(),
+            self.transition_vertex_buffer.take(),
+            self.water_vertex_buffer.take(),
+            self.index_buffer.take(),
+            self.vertex_buffer.take(),
+        ]
+        .into_iter()
+        .flatten()
+        .collect();
+        let mut render_bundles = std::mem::take(&mut self.water_render_bundles);
+        render_bundles.append(&mut self.render_bundles);
+        RenderResources {
+            buffers,
+            arena_buffers,
+            render_bundles,
+        }
+    }
+
+    pub fn render_bundle(&self, frame: usize) -> Option<&RenderBundle> {
+        self.render_bundles.get(frame)
+    }
+
+    pub fn surface(&self) -> &SurfaceMetadata {
+        &self.surface
+    }
+
+    // Enclosure of the voxel closest to a point inside the bounds
+    pub fn enclosure_at(&self, point: &Point3D<f32, WorldSpace>) -> Option<f32> {
+        let local = self
+            .transformation_matrix()
+            .inverse()?
+            .transform_point3d(*point)?;
+        Some(self.enclosure.at(&local))
+    }
+
+    pub fn water_render_bundle(&self, frame: usize) -> Option<&RenderBundle> {
+        self.water_render_bundles.get(frame)
+    }
+
+    pub fn edge_voxel(&self) -> &EdgeVoxel {
+        &self.edge_voxel
+    }
+
+    pub fn voxel_count(&self) -> Size3D<u32, UnknownUnit> {
+        self.voxel_count
+    }
+
+    pub fn stride(&self) -> StitchStride {
+        self.stride
+    }
+
+    // Width of a cell in world units
+    pub fn cell_size(&self) -> f32 {
+        self.bounds.to_f32().width() / (self.voxel_count.width - 1) as f32
+    }
+
+    // Open edges of the drawn surface along a side in world space, from the
+    // mesh shrunk by the stitching and from the transition cells
+    pub fn border_edges(&self, side: Side, style: MeshStyle) -> Vec<[Point3D<f32, WorldSpace>; 2]> {
+        let side = Side::ALL.iter().position(|x| *x == side).unwrap();
+        let transform = self.transformation_matrix();
+        let (stride, transition) = if style.skirts {
+            (StitchStride::NONE, None)
+        } else {
+            (self.stride, self.transition.as_ref())
+        };
+        let mut edges = vec![];
+        for (mesh, stride) in
+            std::iter::once((&self.mesh, stride)).chain(transition.map(|x| (x, StitchStride::NONE)))
+        {
+            let vertex = mesh
+                .vertex()
+                .iter()
+                .map(|v| stride.shrink(v, self.voxel_count))
+                .collect::<Vec<_>>();
+            for (a, b) in boundary_edges(mesh) {
+                if on_sides(&vertex[a])[side] && on_sides(&vertex[b])[side] {
+                    edges.push([a, b].map(|i| transform.transform_point3d(vertex[i]).unwrap()));
                 }
             }
-            for i in &self.edge_vertex.max_y {
-                let [i1, i2]: [u32; 2] = unsafe { std::mem::transmute(ids[*i]) };
-                let voxel_point_1 = self.voxel_index_to_point(i1);
-                let voxel_point_2 = self.voxel_index_to_point(i2);
-                let p = self.edge_voxel.max_y.vertex(
-                    voxel_point_1.xz(),
-                    voxel_point_2.xz(),
-                    0.5,
-                    max_y_stride,
-                );
-                let n = normals[*i];
-                buffer[*i] = VertexData {
-                    position: [p.x, 1.0, p.y, 1.0],
-                    normal: [n.x, n.y, n.z, 0.0],
-                }
+        }
+        edges
+    }
+
+    // The render resources of a resident mesh are released and returned, they
+    // have to be built again right away so that it never goes missing from
+    // the render set
+    pub fn set_transition(
+        &mut self,
+        stride: StitchStride,
+        transition: Option<Mesh<LocalSpace>>,
+    ) -> Option<RenderResources> {
+        self.stride = stride;
+        self.transition = transition;
+        if !self.is_resident() {
+            return None;
+        }
+        Some(self.release_render_resources())
+    }
+
+    // The edges of the mesh on the sides of the chunk that belong to a single
+    // triangle are extruded downward, so that the crack toward a neighbor of
+    // another level shows the skirt instead of the sky. Both windings are
+    // written since the skirt can be seen from either neighbor. The skirt
+    // takes the normals of the mesh vertices it hangs from, returned as the
+    // source of each skirt vertex.
+    fn skirt_data(&self) -> (Vec<VertexData>, Vec<u32>, Vec<u32>) {
+        let bounds = self.bounds.to_f32();
+        let cell_size = bounds.width() / (self.voxel_count.width - 1) as f32;
+        let depth = vec3(0.0, 0.0, SKIRT_CELLS * cell_size / bounds.depth());
+        let vertex = self.mesh.vertex();
+        let mut vertex_data = vec![];
+        let mut index_data = vec![];
+        let mut sources = vec![];
+        for (a, b) in boundary_edges(&self.mesh) {
+            let (sides_a, sides_b) = (on_sides(&vertex[a]), on_sides(&vertex[b]));
+            if !(0..4).any(|i| sides_a[i] && sides_b[i]) {
+                continue;
             }
+            let i = vertex_data.len() as u32;
+            let zero = Vector3D::zero();
+            let (color_a, color_b) = (self.mesh_color(a), self.mesh_color(b));
+            vertex_data.push(self.vertex_data(&vertex[a], &zero, color_a));
+            vertex_data.push(self.vertex_data(&vertex[b], &zero, color_b));
+            vertex_data.push(self.vertex_data(&(vertex[b] - depth), &zero, color_b));
+            vertex_data.push(self.vertex_data(&(vertex[a] - depth), &zero, color_a));
+            sources.extend_from_slice(&[a as u32, b as u32, b as u32, a as u32]);
+            index_data.extend_from_slice(&[i, i + 1, i + 2, i, i + 2, i + 3]);
+            index_data.extend_from_slice(&[i, i + 2, i + 1, i, i + 3, i + 2]);
+        }
+        (vertex_data, index_data, sources)
+    }
+
+    fn vertex_data(
+        &self,
+        v: &Point3D<f32, LocalSpace>,
+        n: &Vector3D<f32, LocalSpace>,
+        color: [f32; 3],
+    ) -> VertexData {
+        let transform = self.transformation_matrix();
+        VertexData {
+            position: [v.x, v.y, v.z, 1.0],
+            normal: [n.x, n.y, n.z, 1.0],
+            traversability: self
+                .surface
+                .cell_at(&transform.transform_point3d(*v).unwrap().xy())
+                .map_or(0.0, |x| x.traversability.shader_value()),
+            biome_weights: self.vertex_biome_weights(v),
+            enclosure: self.enclosure.at(v),
+            color,
+        }
+    }
+
+    // Color baked by the meshing pass, the same one is computed for meshes
+    // that were not generated on the GPU
+    fn mesh_color(&self, index: usize) -> [f32; 3] {
+        match self.mesh.colors() {
+            Some(colors) => colors[index],
+            None => self.color_at(&self.mesh.vertex()[index]),
         }
-        self.vertex_buffer.as_ref().unwrap().unmap();
     }
 
-    fn voxel_index_to_point(&self, i: u32) -> Point3D<u32, UnknownUnit> {
-        point3(
-            i % self.voxel_count.width,
-            (i / self.voxel_count.width) % self.voxel_count.height,
-            i / (self.voxel_count.width * self.voxel_count.height),
-        )
+    // Keep in sync with colored_corner in the meshing shaders
+    fn color_at(&self, v: &Point3D<f32, LocalSpace>) -> [f32; 3] {
+        let altitude = self
+            .transformation_matrix()
+            .transform_point3d(*v)
+            .unwrap()
+            .z;
+        vertex_color(altitude, &self.vertex_biome_weights(v))
+    }
+
+    // Weights of the voxel column closest to a vertex in local space
+    fn vertex_biome_weights(&self, vertex: &Point3D<f32, LocalSpace>) -> [f32; BIOME_COUNT] {
+        let column = |v: f32, count: u32| {
+            let last = (count.max(2) - 1) as f32;
+            (v * last).round().clamp(0.0, last) as u32
+        };
+        let x = column(vertex.x, self.voxel_count.width);
+        let y = column(vertex.y, self.voxel_count.height);
+        self.biome_weights[(x + self.voxel_count.width * y) as usize]
     }
 }
 
@@ -480,3 +1036,61 @@ impl From<std::sync::RwLock<ChunkMesh>> for ChunkMesh {
         item.into_inner().unwrap()
     }
 }
+
+// Edges that belong to a single triangle
+fn boundary_edges(mesh: &Mesh<LocalSpace>) -> Vec<(usize, usize)> {
+    let mut edges = HashMap::new();
+    for face in mesh.faces() {
+        for i in 0..3 {
+            let (a, b) = (face[i], face[(i + 1) % 3]);
+            *edges.entry((a.min(b), a.max(b))).or_insert(0) += 1;
+        }
+    }
+    edges
+        .into_iter()
+        .filter(|(_, count)| *count == 1)
+        .map(|(edge, _)| edge)
+        .collect()
+}
+
+// Whether a point in local space lies on each side, in the order of Side::ALL
+fn on_sides(p: &Point3D<f32, LocalSpace>) -> [bool; 4] {
+    [
+        p.x < SIDE_EPSILON,
+        p.x > 1.0 - SIDE_EPSILON,
+        p.y < SIDE_EPSILON,
+        p.y > 1.0 - SIDE_EPSILON,
+    ]
+}
+
+fn index_data(mesh: &Mesh<LocalSpace>) -> Vec<u32> {
+    mesh.faces()
+        .iter()
+        .flat_map(|x| x.map(|x| x as u32))
+        .collect()
+}
+
+// Copies the corners of every triangle apart and gives them the normal of
+// their face, with the winding of Mesh::calculate_normals like the GPU meshes
+// and straight up for degenerate triangles
+fn flatten(vertex_data: &[VertexData], index_data: &[u32]) -> (Vec<VertexData>, Vec<u32>) {
+    let position = |i: u32| {
+        let p = vertex_data[i as usize].position;
+        vec3::<f32, LocalSpace>(p[0], p[1], p[2])
+    };
+    let flat_vertex_data: Vec<_> = index_data
+        .chunks(3)
+        .flat_map(|face| {
+            let normal = (position(face[1]) - position(face[0]))
+                .cross(position(face[0]) - position(face[2]))
+                .try_normalize()
+                .unwrap_or_else(|| vec3(0.0, 0.0, 1.0));
+            face.iter().map(move |&i| VertexData {
+                normal: [normal.x, normal.y, normal.z, 1.0],
+                ..vertex_data[i as usize]
+            })
+        })
+        .collect();
+    let flat_index_data = (0..flat_vertex_data.len() as u32).collect();
+    (flat_vertex_data, flat_index_data)
+}
@@ -0,0 +1,75 @@
+use super::ChunkCacheKey;
+use crate::game::base::WorldSpace;
+use euclid::Point3D;
+
+// Border vertices farther than this many cells of the finer mesh from the
+// border of its neighbor open a crack
+const CRACK_TOLERANCE: f32 = 0.05;
+
+// Seam between two rendered meshes that does not close, see
+// Terrain::find_cracks
+#[derive(Debug, Clone)]
+pub struct Crack {
+    pub keys: [ChunkCacheKey; 2],
+    // Widest gap in world units
+    pub width: f32,
+    // Each border vertex past the tolerance and the closest point of the
+    // other border
+    pub gaps: Vec<[Point3D<f32, WorldSpace>; 2]>,
+}
+
+// Borders are the open edges of each mesh along the shared side. Only the
+// vertices of one border inside the bounds of the other chunk are compared,
+// the other chunk does not have to cover the whole side.
+pub fn measure_crack(
+    keys: [ChunkCacheKey; 2],
+    borders: [&[[Point3D<f32, WorldSpace>; 2]]; 2],
+    cell_size: f32,
+) -> Option<Crack> {
+    let tolerance = CRACK_TOLERANCE * cell_size;
+    let mut gaps = vec![];
+    let mut width = 0.0f32;
+    for (from, to) in [(0, 1), (1, 0)] {
+        if borders[to].is_empty() {
+            continue;
+        }
+        let bounds = keys[to].bounds.to_f32();
+        let within = |v: f32, min: f32, max: f32| (min - tolerance..=max + tolerance).contains(&v);
+        let inside = |p: &Point3D<f32, WorldSpace>| {
+            within(p.x, bounds.min.x, bounds.max.x)
+                && within(p.y, bounds.min.y, bounds.max.y)
+                && within(p.z, bounds.min.z, bounds.max.z)
+        };
+        for p in borders[from].iter().flatten().filter(|p| inside(p)) {
+            let closest = borders[to]
+                .iter()
+                .map(|[a, b]| closest_point_on_segment(p, a, b))
+                .min_by(|a, b| a.distance_to(*p).partial_cmp(&b.distance_to(*p)).unwrap())
+                .unwrap();
+            let distance = closest.distance_to(*p);
+            if distance > tolerance {
+                width = width.max(distance);
+                gaps.push([*p, closest]);
+            }
+        }
+    }
+    if gaps.is_empty() {
+        None
+    } else {
+        Some(Crack { keys, width, gaps })
+    }
+}
+
+fn closest_point_on_segment(
+    p: &Point3D<f32, WorldSpace>,
+    a: &Point3D<f32, WorldSpace>,
+    b: &Point3D<f32, WorldSpace>,
+) -> Point3D<f32, WorldSpace> {
+    let ab = *b - *a;
+    let length = ab.square_length();
+    if length <= f32::EPSILON {
+        return *a;
+    }
+    let t = ((*p - *a).dot(ab) / length).clamp(0.0, 1.0);
+    *a + ab * t
+}
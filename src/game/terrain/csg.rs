@@ -0,0 +1,111 @@
+use crate::game::base::WorldSpace;
+use euclid::{Box3D, Point2D, Point3D, Vector3D};
+
+// Solid primitives of a CSG edit, in world units
+#[derive(Debug, Copy, Clone)]
+pub enum CsgShape {
+    Box {
+        min: Point3D<f32, WorldSpace>,
+        max: Point3D<f32, WorldSpace>,
+    },
+    Sphere {
+        center: Point3D<f32, WorldSpace>,
+        radius: f32,
+    },
+    // Upright, from min z to max z
+    Cylinder {
+        center: Point2D<f32, WorldSpace>,
+        radius: f32,
+        min_z: f32,
+        max_z: f32,
+    },
+    // Segment with rounded ends, for tunnels in any direction
+    Capsule {
+        start: Point3D<f32, WorldSpace>,
+        end: Point3D<f32, WorldSpace>,
+        radius: f32,
+    },
+}
+
+impl CsgShape {
+    // Signed distance to the surface, negative inside
+    pub fn distance(&self, point: &Point3D<f32, WorldSpace>) -> f32 {
+        match self {
+            CsgShape::Box { min, max } => {
+                let center = min.lerp(*max, 0.5);
+                let half_size = (*max - *min) * 0.5;
+                let q = (*point - center).abs() - half_size;
+                let outside = q.max(Vector3D::zero()).length();
+                outside + q.x.max(q.y).max(q.z).min(0.0)
+            }
+            CsgShape::Sphere { center, radius } => point.distance_to(*center) - radius,
+            CsgShape::Cylinder {
+                center,
+                radius,
+                min_z,
+                max_z,
+            } => {
+                let dr = point.xy().distance_to(*center) - radius;
+                let dz = (min_z - point.z).max(point.z - max_z);
+                let outside = (dr.max(0.0).powi(2) + dz.max(0.0).powi(2)).sqrt();
+                outside + dr.max(dz).min(0.0)
+            }
+            CsgShape::Capsule { start, end, radius } => {
+                let segment = *end - *start;
+                let length = segment.square_length();
+                let t = if length > 0.0 {
+                    ((*point - *start).dot(segment) / length).clamp(0.0, 1.0)
+                } else {
+                    0.0
+                };
+                point.distance_to(*start + segment * t) - radius
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Box3D<f32, WorldSpace> {
+        match self {
+            CsgShape::Box { min, max } => Box3D::from_points(&[*min, *max]),
+            CsgShape::Sphere { center, radius } => {
+                Box3D::new(*center, *center).inflate(*radius, *radius, *radius)
+            }
+            CsgShape::Cylinder {
+                center,
+                radius,
+                min_z,
+                max_z,
+            } => Box3D::new(
+                Point3D::new(center.x - radius, center.y - radius, *min_z),
+                Point3D::new(center.x + radius, center.y + radius, *max_z),
+            ),
+            CsgShape::Capsule { start, end, radius } => {
+                Box3D::from_points(&[*start, *end]).inflate(*radius, *radius, *radius)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum CsgOperation {
+    Add,
+    Subtract,
+}
+
+// One step of a CSG edit, steps are applied in order so that later shapes
+// can cut into earlier ones
+#[derive(Debug, Copy, Clone)]
+pub struct CsgEdit {
+    pub shape: CsgShape,
+    pub operation: CsgOperation,
+}
+
+impl CsgEdit {
+    // Density after the step, from the density before it and the solid
+    // amount of the shape at the voxel between zero and one
+    pub fn apply(&self, value: f32, solid: f32) -> f32 {
+        match self.operation {
+            CsgOperation::Add => value.max(solid),
+            CsgOperation::Subtract => value.min(1.0 - solid),
+        }
+    }
+}
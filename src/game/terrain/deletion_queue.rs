@@ -0,0 +1,46 @@
+use crate::game::frames::FRAMES_IN_FLIGHT;
+use parking_lot::Mutex;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+// Holds on to values whose GPU resources may still be referenced by the
+// command buffers of the frames in flight, they are dropped once every frame
+// recorded since they were pushed has come around again
+pub struct DeletionQueue<T> {
+    frame: AtomicU64,
+    // With the frame they were pushed in
+    pending: Mutex<Vec<(u64, T)>>,
+}
+
+impl<T> DeletionQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            frame: AtomicU64::new(0),
+            pending: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn push(&self, value: T) {
+        let frame = self.frame.load(Ordering::Relaxed);
+        self.pending.lock().push((frame, value));
+    }
+
+    // Called once per frame before it is recorded, the frame waited for the
+    // GPU to finish the one it replaces
+    pub fn advance(&self) {
+        let frame = self.frame.fetch_add(1, Ordering::Relaxed) + 1;
+        let expired = {
+            let mut pending = self.pending.lock();
+            let (expired, kept) = std::mem::take(&mut *pending)
+                .into_iter()
+                .partition::<Vec<_>, _>(|(pushed, _)| pushed + FRAMES_IN_FLIGHT as u64 <= frame);
+            *pending = kept;
+            expired
+        };
+        // Destroyed without holding the lock
+        drop(expired);
+    }
+
+    pub fn pending(&self) -> usize {
+        self.pending.lock().len()
+    }
+}
@@ -0,0 +1,139 @@
+use super::chunk::Chunk;
+use super::edit::EditOperation;
+use super::sculpt::TerrainEdit;
+use super::set_piece::SetPiece;
+use super::ChunkCacheKey;
+use crate::game::base::WorldSpace;
+use euclid::{Box2D, Box3D};
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashMap;
+use std::hash::Hash;
+use std::sync::Arc;
+
+// One edit made to a chunk. The operation of an edit job is shared by every
+// chunk it touches so that a large edit is only stored once.
+#[derive(Debug, Clone)]
+pub enum ChunkDelta {
+    Sculpt(TerrainEdit),
+    Edit(Arc<EditOperation>),
+    SetPiece(Arc<SetPiece>),
+}
+
+impl ChunkDelta {
+    // Applied to the CPU voxels, returns true if any voxel changed
+    fn apply(&self, chunk: &mut Chunk) -> bool {
+        match self {
+            ChunkDelta::Sculpt(edit) => edit.apply(chunk),
+            ChunkDelta::Edit(operation) => operation.apply(chunk),
+            ChunkDelta::SetPiece(set_piece) => set_piece.apply(chunk),
+        }
+    }
+
+    fn intersects(&self, bounds: &Box3D<i32, WorldSpace>) -> bool {
+        let area = bounds.to_f32();
+        match self {
+            ChunkDelta::Sculpt(edit) => edit.bounds().intersects(&area),
+            ChunkDelta::Edit(operation) => operation
+                .bounds()
+                .intersects(&Box2D::new(area.min.xy(), area.max.xy())),
+            ChunkDelta::SetPiece(set_piece) => set_piece.intersects(bounds),
+        }
+    }
+}
+
+// Edits of each chunk in the order they were made. Chunks count the deltas
+// already in their voxels, a chunk generated again after being evicted or
+// invalidated starts from none and gets the others replayed.
+pub struct EditDeltas {
+    deltas: HashMap<ChunkCacheKey, Vec<ChunkDelta>>,
+    // Set pieces and edits placed on every level, replayed on every chunk
+    // they touch before its own deltas. They are part of the parameters so
+    // the chunks start over whenever they change.
+    placed: Vec<(u64, ChunkDelta)>,
+    next_placed: u64,
+}
+
+impl EditDeltas {
+    pub fn new() -> Self {
+        Self {
+            deltas: HashMap::new(),
+            placed: vec![],
+            next_placed: 0,
+        }
+    }
+
+    // Returns the number of deltas of the key with the new one
+    pub fn record(&mut self, key: &ChunkCacheKey, delta: ChunkDelta) -> usize {
+        self.deltas.entry(*key).or_insert_with(Vec::new).push(delta);
+        self.count(key)
+    }
+
+    pub fn count(&self, key: &ChunkCacheKey) -> usize {
+        self.placed_on(key).count() + self.deltas.get(key).map_or(0, |x| x.len())
+    }
+
+    fn placed_on<'a>(&'a self, key: &'a ChunkCacheKey) -> impl Iterator<Item = &'a ChunkDelta> {
+        self.placed
+            .iter()
+            .map(|(_, x)| x)
+            .filter(move |x| x.intersects(&key.bounds))
+    }
+
+    // Applies the deltas the chunk does not have yet, returns true if any
+    // voxel changed
+    pub fn replay(&self, key: &ChunkCacheKey, chunk: &mut Chunk) -> bool {
+        let placed = self.placed_on(key).collect::<Vec<_>>();
+        let deltas = self.deltas.get(key).map_or(&[][..], |x| x.as_slice());
+        let count = placed.len() + deltas.len();
+        if count == 0 {
+            return false;
+        }
+        let applied = chunk.applied_deltas();
+        let mut modified = false;
+        for delta in placed.iter().skip(applied) {
+            modified |= delta.apply(chunk);
+        }
+        for delta in deltas.iter().skip(applied.saturating_sub(placed.len())) {
+            modified |= delta.apply(chunk);
+        }
+        chunk.set_applied_deltas(count);
+        modified
+    }
+
+    // Returns the id the delta is removed with
+    pub fn place(&mut self, delta: ChunkDelta) -> u64 {
+        let id = self.next_placed;
+        self.next_placed += 1;
+        self.placed.push((id, delta));
+        id
+    }
+
+    // Returns false if there is no placed delta with the id
+    pub fn remove_placed(&mut self, id: u64) -> bool {
+        let count = self.placed.len();
+        self.placed.retain(|(x, _)| *x != id);
+        self.placed.len() != count
+    }
+
+    pub fn hash_placed(&self, hasher: &mut DefaultHasher) {
+        for (id, _) in &self.placed {
+            id.hash(hasher);
+        }
+    }
+
+    // Copies the edits of every chunk of the old parameters to the same chunk
+    // of the new ones. Chunks that already have edits of their own keep them.
+    // Returns the keys that got edits.
+    pub fn carry(&mut self, from: u64, to: u64) -> Vec<ChunkCacheKey> {
+        let carried = self
+            .deltas
+            .iter()
+            .filter(|(key, _)| key.params == from)
+            .map(|(key, deltas)| (ChunkCacheKey { params: to, ..*key }, deltas.clone()))
+            .filter(|(key, _)| !self.deltas.contains_key(key))
+            .collect::<Vec<_>>();
+        let keys = carried.iter().map(|(key, _)| *key).collect();
+        self.deltas.extend(carried);
+        keys
+    }
+}
@@ -0,0 +1,81 @@
+use super::ChunkCacheKey;
+use crate::game::base::WorldSpace;
+use euclid::Point3D;
+use std::collections::HashSet;
+
+// Vertices closer than this are considered the same when comparing meshes
+const WELD_DISTANCE: f32 = 1e-4;
+
+pub type WorldTriangle = [Point3D<f32, WorldSpace>; 3];
+
+#[derive(Debug, Clone, Default)]
+pub struct ChunkDiff {
+    pub added: Vec<WorldTriangle>,
+    pub removed: Vec<WorldTriangle>,
+}
+
+impl ChunkDiff {
+    #[profiling::function]
+    pub fn new(previous: &[WorldTriangle], current: &[WorldTriangle]) -> Self {
+        let previous_keys = previous.iter().map(triangle_key).collect::<HashSet<_>>();
+        let current_keys = current.iter().map(triangle_key).collect::<HashSet<_>>();
+        Self {
+            added: current
+                .iter()
+                .filter(|x| !previous_keys.contains(&triangle_key(x)))
+                .copied()
+                .collect(),
+            removed: previous
+                .iter()
+                .filter(|x| !current_keys.contains(&triangle_key(x)))
+                .copied()
+                .collect(),
+        }
+    }
+}
+
+// Winding independent key of the quantized corners
+fn triangle_key(triangle: &WorldTriangle) -> [[i32; 3]; 3] {
+    let mut key = triangle.map(|p| {
+        [
+            (p.x / WELD_DISTANCE).round() as i32,
+            (p.y / WELD_DISTANCE).round() as i32,
+            (p.z / WELD_DISTANCE).round() as i32,
+        ]
+    });
+    key.sort_unstable();
+    key
+}
+
+// Chunk selected for diffing, the mesh is retained every time it is written
+// so that the next regeneration can be compared against it
+pub struct DiffSelection {
+    key: ChunkCacheKey,
+    retained: Option<Vec<WorldTriangle>>,
+    diff: Option<ChunkDiff>,
+}
+
+impl DiffSelection {
+    pub fn new(key: ChunkCacheKey, current: Option<Vec<WorldTriangle>>) -> Self {
+        Self {
+            key,
+            retained: current,
+            diff: None,
+        }
+    }
+
+    pub fn key(&self) -> &ChunkCacheKey {
+        &self.key
+    }
+
+    pub fn diff(&self) -> Option<&ChunkDiff> {
+        self.diff.as_ref()
+    }
+
+    pub fn update(&mut self, current: Vec<WorldTriangle>) {
+        if let Some(previous) = &self.retained {
+            self.diff = Some(ChunkDiff::new(previous, &current));
+        }
+        self.retained = Some(current);
+    }
+}
@@ -0,0 +1,246 @@
+use super::biome::BIOME_COUNT;
+use super::chunk::Voxel;
+use super::ChunkCacheKey;
+use memmap2::{Mmap, MmapMut};
+use std::collections::hash_map::DefaultHasher;
+use std::fs::{self, File, OpenOptions};
+use std::hash::{Hash, Hasher};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+
+pub const DISK_CACHE_PATH: &str = "chunk_cache";
+const SPILL_HEADER_SIZE: usize = 8;
+
+type ChunkData = (Vec<Voxel>, Vec<f32>, Vec<[f32; BIOME_COUNT]>);
+
+// Generated voxels and water levels stored by a hash of everything that went
+// into generating them, so chunks that were generated before with the same
+// parameters are read back instead of being generated again. Files are the
+// voxels followed by the water levels and the biome weights of the columns
+// as little endian f32.
+pub struct DiskCache {
+    directory: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(directory: &Path) -> Self {
+        if let Err(e) = fs::create_dir_all(directory) {
+            log::warn!("Failed to create {}: {}", directory.display(), e);
+        }
+        Self {
+            directory: directory.to_path_buf(),
+        }
+    }
+
+    fn path(&self, hash: u64) -> PathBuf {
+        self.directory.join(format!("{:016x}.chunk", hash))
+    }
+
+    // None if the file is missing or does not have the expected sizes
+    #[profiling::function]
+    pub fn load(&self, hash: u64, voxel_count: usize, column_count: usize) -> Option<ChunkData> {
+        let data = fs::read(self.path(hash)).ok()?;
+        decode(&data, voxel_count, column_count)
+    }
+
+    #[profiling::function]
+    pub fn store(
+        &self,
+        hash: u64,
+        voxels: &[Voxel],
+        water_levels: &[f32],
+        biome_weights: &[[f32; BIOME_COUNT]],
+    ) {
+        let path = self.path(hash);
+        if path.exists() {
+            return;
+        }
+        write_atomically(&path, &encode(voxels, water_levels, biome_weights));
+    }
+}
+
+// Chunks evicted from the memory cache with their edits, read back in place
+// of generating them again when they are requested. Files are named after the
+// chunk key and are only valid for the session, the parameters of a key are
+// not stable across runs, so each process spills to its own directory under
+// the temporary directory and removes it when the spill is dropped. A file is
+// removed once it is read back, the chunk is written again if it is evicted
+// again. Files are memory mapped both ways so that chunks are decoded from
+// and encoded into the page cache without another copy on the heap.
+pub struct ChunkSpill {
+    directory: PathBuf,
+    next_temporary: AtomicU64,
+}
+
+pub fn chunk_spill_directory() -> PathBuf {
+    std::env::temp_dir().join(format!("hinoki_chunk_spill_{}", std::process::id()))
+}
+
+impl ChunkSpill {
+    pub fn new(directory: &Path) -> Self {
+        // Left over from a crashed process with the same id
+        if directory.exists() {
+            if let Err(e) = fs::remove_dir_all(directory) {
+                log::warn!("Failed to clear {}: {}", directory.display(), e);
+            }
+        }
+        if let Err(e) = fs::create_dir_all(directory) {
+            log::warn!("Failed to create {}: {}", directory.display(), e);
+        }
+        Self {
+            directory: directory.to_path_buf(),
+            next_temporary: AtomicU64::new(0),
+        }
+    }
+
+    fn path(&self, key: &ChunkCacheKey) -> PathBuf {
+        let mut hasher = DefaultHasher::new();
+        key.hash(&mut hasher);
+        self.directory
+            .join(format!("{:016x}.chunk", hasher.finish()))
+    }
+
+    // Same layout as the disk cache after the count of the deltas already in
+    // the voxels, as a little endian u64
+    #[profiling::function]
+    pub fn load(
+        &self,
+        key: &ChunkCacheKey,
+        voxel_count: usize,
+        column_count: usize,
+    ) -> Option<(ChunkData, usize)> {
+        let path = self.path(key);
+        let file = File::open(&path).ok()?;
+        // Safety: the directory belongs to this process and files in it are
+        // never written in place, a store maps a temporary file of its own and
+        // renames it over the previous one, so the mapped bytes do not change
+        // while they are read
+        let loaded = match unsafe { Mmap::map(&file) } {
+            Ok(map) if map.len() >= SPILL_HEADER_SIZE => {
+                let (count, data) = map.split_at(SPILL_HEADER_SIZE);
+                let mut applied_deltas = [0; SPILL_HEADER_SIZE];
+                applied_deltas.copy_from_slice(count);
+                decode(data, voxel_count, column_count)
+                    .map(|x| (x, u64::from_le_bytes(applied_deltas) as usize))
+            }
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("Failed to map {}: {}", path.display(), e);
+                None
+            }
+        };
+        // Removed after the map is dropped, mapped files can not be removed
+        // on every platform
+        drop(file);
+        if let Err(e) = fs::remove_file(&path) {
+            log::warn!("Failed to remove {}: {}", path.display(), e);
+        }
+        loaded
+    }
+
+    #[profiling::function]
+    pub fn store(
+        &self,
+        key: &ChunkCacheKey,
+        voxels: &[Voxel],
+        water_levels: &[f32],
+        biome_weights: &[[f32; BIOME_COUNT]],
+        applied_deltas: usize,
+    ) {
+        let path = self.path(key);
+        // A chunk evicted again while its previous spill is being written
+        // gets a temporary file of its own
+        let temporary = self.next_temporary.fetch_add(1, Ordering::Relaxed);
+        let temporary_path = path.with_extension(format!("{}.tmp", temporary));
+        let size = SPILL_HEADER_SIZE + encoded_size(voxels.len(), water_levels.len());
+        let written = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(true)
+            .open(&temporary_path)
+            .and_then(|file| {
+                file.set_len(size as u64)?;
+                // Safety: the temporary file was just created by this store and
+                // is not opened anywhere else until it is renamed
+                let mut map = unsafe { MmapMut::map_mut(&file)? };
+                let (count, data) = map.split_at_mut(SPILL_HEADER_SIZE);
+                count.copy_from_slice(&(applied_deltas as u64).to_le_bytes());
+                encode_into(data, voxels, water_levels, biome_weights);
+                map.flush()
+            })
+            .and_then(|_| fs::rename(&temporary_path, &path));
+        if let Err(e) = written {
+            log::warn!("Failed to write {}: {}", path.display(), e);
+            let _ = fs::remove_file(&temporary_path);
+        }
+    }
+}
+
+impl Drop for ChunkSpill {
+    fn drop(&mut self) {
+        if let Err(e) = fs::remove_dir_all(&self.directory) {
+            log::warn!("Failed to remove {}: {}", self.directory.display(), e);
+        }
+    }
+}
+
+fn encoded_size(voxel_count: usize, column_count: usize) -> usize {
+    (voxel_count + column_count * (1 + BIOME_COUNT)) * 4
+}
+
+fn encode(voxels: &[Voxel], water_levels: &[f32], biome_weights: &[[f32; BIOME_COUNT]]) -> Vec<u8> {
+    let mut data = vec![0; encoded_size(voxels.len(), water_levels.len())];
+    encode_into(&mut data, voxels, water_levels, biome_weights);
+    data
+}
+
+fn encode_into(
+    data: &mut [u8],
+    voxels: &[Voxel],
+    water_levels: &[f32],
+    biome_weights: &[[f32; BIOME_COUNT]],
+) {
+    let values = voxels
+        .iter()
+        .map(|x| x.value)
+        .chain(water_levels.iter().copied())
+        .chain(biome_weights.iter().flatten().copied());
+    for (bytes, value) in data.chunks_exact_mut(4).zip(values) {
+        bytes.copy_from_slice(&value.to_le_bytes());
+    }
+}
+
+fn decode(data: &[u8], voxel_count: usize, column_count: usize) -> Option<ChunkData> {
+    if data.len() != encoded_size(voxel_count, column_count) {
+        return None;
+    }
+    let values = data
+        .chunks(4)
+        .map(|x| f32::from_le_bytes([x[0], x[1], x[2], x[3]]))
+        .collect::<Vec<_>>();
+    let voxels = values[..voxel_count]
+        .iter()
+        .map(|x| Voxel { value: *x })
+        .collect();
+    let water_end = voxel_count + column_count;
+    let biome_weights = values[water_end..]
+        .chunks(BIOME_COUNT)
+        .map(|x| [x[0], x[1], x[2], x[3]])
+        .collect();
+    Some((
+        voxels,
+        values[voxel_count..water_end].to_vec(),
+        biome_weights,
+    ))
+}
+
+// Written to a temporary file first so that a worker never reads a partially
+// written chunk
+fn write_atomically(path: &Path, data: &[u8]) {
+    let temporary_path = path.with_extension("tmp");
+    if let Err(e) = fs::write(&temporary_path, data).and_then(|_| fs::rename(&temporary_path, path))
+    {
+        log::warn!("Failed to write {}: {}", path.display(), e);
+    }
+}
@@ -0,0 +1,96 @@
+use crate::gfx::Instance;
+use std::mem::size_of;
+use wgpu::*;
+
+// Writes the indirect draw of a triangle buffer on the GPU so that its
+// triangle count never has to be read back
+pub struct DrawArgsPipeline {
+    pipeline: ComputePipeline,
+}
+
+impl DrawArgsPipeline {
+    pub fn new(instance: &Instance) -> Self {
+        let device = instance.device();
+        let storage_entry = |binding, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("terrain_draw_args_bind_group_layout"),
+            entries: &[
+                // raw triangle buffer
+                storage_entry(0, true),
+                // indirect draw arguments
+                storage_entry(1, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("terrain_draw_args_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/draw_args.wgsl"));
+        Self {
+            pipeline: device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("terrain_draw_args_compute_pipeline"),
+                entry_point: "main",
+                module: &shader_module,
+                layout: Some(&pipeline_layout),
+            }),
+        }
+    }
+
+    pub fn create_buffer(&self, instance: &Instance) -> Buffer {
+        instance.device().create_buffer(&BufferDescriptor {
+            label: Some("terrain_draw_args_buffer"),
+            // vertex count, instance count, first vertex and first instance
+            size: size_of::<[u32; 4]>() as u64,
+            mapped_at_creation: false,
+            usage: BufferUsages::STORAGE | BufferUsages::INDIRECT,
+        })
+    }
+
+    // Has to run after the triangles are generated and before the draw
+    pub fn dispatch(
+        &self,
+        instance: &Instance,
+        encoder: &mut CommandEncoder,
+        triangle_buffer: &Buffer,
+        draw_args_buffer: &Buffer,
+    ) {
+        let bind_group = instance.device().create_bind_group(&BindGroupDescriptor {
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: triangle_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: draw_args_buffer,
+                        offset: 0,
+                        size: None,
+                    }),
+                },
+            ],
+            label: Some("terrain_draw_args_bind_group"),
+            layout: &self.pipeline.get_bind_group_layout(0),
+        });
+        let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+            label: Some("terrain_draw_args_compute_pass"),
+        });
+        compute_pass.set_pipeline(&self.pipeline);
+        compute_pass.set_bind_group(0, &bind_group, &[]);
+        compute_pass.dispatch(1, 1, 1);
+    }
+}
@@ -0,0 +1,69 @@
+// Marching cubes vertices always lie on the edge between two voxels so the
+// pair of voxel indices identifies a vertex. The pair is ordered so that every
+// cell sharing the edge produces the same id. Dual contouring and surface
+// nets vertices are identified the same way by the opposite corners of their
+// voxel box. Layout matches the vec2<u32> written by generate_triangle.wgsl,
+// dual_contouring.wgsl and surface_nets.wgsl.
+#[derive(
+    Debug, Copy, Clone, PartialEq, Eq, Hash, PartialOrd, Ord, bytemuck::Zeroable, bytemuck::Pod,
+)]
+#[repr(C)]
+pub struct EdgeId {
+    min: u32,
+    max: u32,
+}
+
+impl EdgeId {
+    pub fn new(a: u32, b: u32) -> Self {
+        Self {
+            min: a.min(b),
+            max: a.max(b),
+        }
+    }
+
+    pub fn voxels(&self) -> [u32; 2] {
+        [self.min, self.max]
+    }
+}
+
+impl From<[u32; 2]> for EdgeId {
+    fn from(voxels: [u32; 2]) -> Self {
+        Self::new(voxels[0], voxels[1])
+    }
+}
+
+impl From<EdgeId> for [u32; 2] {
+    fn from(id: EdgeId) -> Self {
+        id.voxels()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::EdgeId;
+
+    #[test]
+    fn new_orders_the_voxels() {
+        assert_eq!(EdgeId::new(3, 7), EdgeId::new(7, 3));
+        assert_eq!(EdgeId::new(7, 3).voxels(), [3, 7]);
+        assert_eq!(EdgeId::new(5, 5).voxels(), [5, 5]);
+    }
+
+    #[test]
+    fn converts_to_and_from_pairs() {
+        let id = EdgeId::from([9, 2]);
+        assert_eq!(id, EdgeId::new(2, 9));
+        let voxels: [u32; 2] = id.into();
+        assert_eq!(voxels, [2, 9]);
+        assert_eq!(EdgeId::from(voxels), id);
+    }
+
+    // slot() in weld.wgsl reads x as the lower voxel
+    #[test]
+    fn lower_voxel_is_written_first() {
+        let words: [u32; 2] = bytemuck::cast(EdgeId::new(12, 4));
+        assert_eq!(words, [4, 12]);
+        let id: EdgeId = bytemuck::cast([4u32, 12]);
+        assert_eq!(id, EdgeId::new(4, 12));
+    }
+}
@@ -0,0 +1,298 @@
+use super::chunk::{Chunk, SURFACE_MAX_Z};
+use super::csg::CsgEdit;
+use crate::game::base::WorldSpace;
+use euclid::{Box2D, Point2D, Point3D};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+
+// Half thickness of the transition between solid and empty
+pub const SURFACE_THICKNESS: f32 = 0.02;
+// Segments per span when sampling a spline
+const SPLINE_SEGMENTS: usize = 16;
+
+#[derive(Debug, Clone)]
+pub enum EditOperation {
+    // Solid below the height and empty above inside the bounds, blended back
+    // to the terrain over the falloff distance
+    Flatten {
+        bounds: Box2D<f32, WorldSpace>,
+        height: f32,
+        falloff: f32,
+    },
+    // Carve a U shaped valley along a Catmull-Rom spline through the points,
+    // the floor is at the given height and the walls reach the top of the
+    // surface band at the radius
+    Canyon {
+        points: Vec<Point2D<f32, WorldSpace>>,
+        radius: f32,
+        floor: f32,
+    },
+    // CSG steps applied in order to every voxel
+    Csg(Vec<CsgEdit>),
+    // Sweep a cross section along a Catmull-Rom spline through the points.
+    // The profile is the height of the surface above the spline at each
+    // signed distance from it, positive to the left, linear between its
+    // points and sorted by distance. Outside of its distances the terrain is
+    // left as is, so a cliff is a step in the profile.
+    Sweep {
+        points: Vec<Point3D<f32, WorldSpace>>,
+        profile: Vec<(f32, f32)>,
+        mode: SweepMode,
+    },
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SweepMode {
+    // Only removes the ground above the swept surface
+    Carve,
+    // Only adds ground below the swept surface
+    Extrude,
+}
+
+impl EditOperation {
+    pub fn bounds(&self) -> Box2D<f32, WorldSpace> {
+        match self {
+            EditOperation::Flatten {
+                bounds, falloff, ..
+            } => bounds.inflate(*falloff, *falloff),
+            EditOperation::Canyon { points, radius, .. } => {
+                Box2D::from_points(points).inflate(*radius, *radius)
+            }
+            EditOperation::Sweep {
+                points, profile, ..
+            } => {
+                let reach = profile.iter().fold(0.0f32, |a, (x, _)| a.max(x.abs()));
+                Box2D::from_points(points.iter().map(|x| x.xy())).inflate(reach, reach)
+            }
+            EditOperation::Csg(edits) => edits
+                .iter()
+                .map(|x| {
+                    let bounds = x.shape.bounds();
+                    Box2D::new(bounds.min.xy(), bounds.max.xy())
+                        .inflate(SURFACE_THICKNESS, SURFACE_THICKNESS)
+                })
+                .fold(
+                    Box2D::zero(),
+                    |a, b| if a.is_empty() { b } else { a.union(&b) },
+                ),
+        }
+    }
+
+    // Returns true if any voxel of the chunk was modified
+    pub fn apply(&self, chunk: &mut Chunk) -> bool {
+        let chunk_bounds = chunk.bounds().to_f32();
+        let chunk_bounds = Box2D::new(chunk_bounds.min.xy(), chunk_bounds.max.xy());
+        if !self.bounds().intersects(&chunk_bounds) {
+            return false;
+        }
+        let solid_below = |z: f32, height: f32| {
+            1.0 - smoothstep(height - SURFACE_THICKNESS, height + SURFACE_THICKNESS, z)
+        };
+        match self {
+            EditOperation::Flatten {
+                bounds,
+                height,
+                falloff,
+            } => chunk.edit_voxels(|position, value| {
+                let dx = (bounds.min.x - position.x).max(position.x - bounds.max.x);
+                let dy = (bounds.min.y - position.y).max(position.y - bounds.max.y);
+                let distance = dx.max(dy).max(0.0);
+                let weight = if *falloff > 0.0 {
+                    1.0 - (distance / falloff).min(1.0)
+                } else if distance > 0.0 {
+                    0.0
+                } else {
+                    1.0
+                };
+                value + (solid_below(position.z, *height) - value) * weight
+            }),
+            EditOperation::Canyon {
+                points,
+                radius,
+                floor,
+            } => {
+                let points = points.iter().map(|x| x.extend(0.0)).collect::<Vec<_>>();
+                let polyline = sample_spline(&points);
+                let max_z = SURFACE_MAX_Z as f32;
+                chunk.edit_voxels(|position, value| {
+                    let distance = closest_on_polyline(&polyline, &position.xy()).0.abs();
+                    if distance >= *radius {
+                        return value;
+                    }
+                    let t = distance / radius;
+                    let profile = floor + (max_z - floor) * t * t;
+                    value.min(solid_below(position.z, profile))
+                })
+            }
+            EditOperation::Csg(edits) => chunk.edit_voxels(|position, value| {
+                edits.iter().fold(value, |value, edit| {
+                    let distance = edit.shape.distance(position);
+                    let solid = 1.0 - smoothstep(-SURFACE_THICKNESS, SURFACE_THICKNESS, distance);
+                    edit.apply(value, solid)
+                })
+            }),
+            EditOperation::Sweep {
+                points,
+                profile,
+                mode,
+            } => {
+                let polyline = sample_spline(points);
+                chunk.edit_voxels(|position, value| {
+                    let (distance, z) = closest_on_polyline(&polyline, &position.xy());
+                    let height = match profile_height(profile, distance) {
+                        Some(height) => z + height,
+                        None => return value,
+                    };
+                    let solid = solid_below(position.z, height);
+                    match mode {
+                        SweepMode::Carve => value.min(solid),
+                        SweepMode::Extrude => value.max(solid),
+                    }
+                })
+            }
+        }
+    }
+}
+
+pub fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Uniform Catmull-Rom spline, the end points are repeated so that the curve
+// goes through every point
+fn sample_spline(points: &[Point3D<f32, WorldSpace>]) -> Vec<Point3D<f32, WorldSpace>> {
+    if points.len() < 3 {
+        return points.to_vec();
+    }
+    let point = |i: isize| points[i.clamp(0, points.len() as isize - 1) as usize].to_vector();
+    let mut polyline = vec![];
+    for i in 0..points.len() as isize - 1 {
+        let (p0, p1, p2, p3) = (point(i - 1), point(i), point(i + 1), point(i + 2));
+        for s in 0..SPLINE_SEGMENTS {
+            let t = s as f32 / SPLINE_SEGMENTS as f32;
+            let (t2, t3) = (t * t, t * t * t);
+            let p = (p1 * 2.0
+                + (p2 - p0) * t
+                + (p0 * 2.0 - p1 * 5.0 + p2 * 4.0 - p3) * t2
+                + (p1 * 3.0 - p0 - p2 * 3.0 + p3) * t3)
+                * 0.5;
+            polyline.push(p.to_point());
+        }
+    }
+    polyline.push(*points.last().unwrap());
+    polyline
+}
+
+// Signed distance on the ground to the closest point of the polyline,
+// positive to the left of it, and the height of that point
+fn closest_on_polyline(
+    polyline: &[Point3D<f32, WorldSpace>],
+    point: &Point2D<f32, WorldSpace>,
+) -> (f32, f32) {
+    if polyline.len() == 1 {
+        return (polyline[0].xy().distance_to(*point), polyline[0].z);
+    }
+    polyline
+        .windows(2)
+        .map(|x| {
+            let (a, b) = (x[0].xy(), x[1].xy());
+            let segment = b - a;
+            let length = segment.square_length();
+            let t = if length > 0.0 {
+                ((*point - a).dot(segment) / length).clamp(0.0, 1.0)
+            } else {
+                0.0
+            };
+            let distance = (a + segment * t).distance_to(*point);
+            let side = if segment.cross(*point - a) < 0.0 {
+                -1.0
+            } else {
+                1.0
+            };
+            (distance * side, x[0].z + (x[1].z - x[0].z) * t)
+        })
+        .fold((f32::INFINITY, 0.0), |a, b| {
+            if b.0.abs() < a.0.abs() {
+                b
+            } else {
+                a
+            }
+        })
+}
+
+fn profile_height(profile: &[(f32, f32)], distance: f32) -> Option<f32> {
+    let (first, last) = (profile.first()?, profile.last()?);
+    if distance < first.0 || distance > last.0 {
+        return None;
+    }
+    profile
+        .windows(2)
+        .find(|x| distance <= x[1].0)
+        .map(|x| {
+            let width = x[1].0 - x[0].0;
+            let t = if width > 0.0 {
+                (distance - x[0].0) / width
+            } else {
+                0.0
+            };
+            x[0].1 + (x[1].1 - x[0].1) * t
+        })
+        .or(Some(last.1))
+}
+
+// An edit split into one task per cached chunk. Cancelling skips the chunks
+// that are not done yet, the ones already edited keep the change.
+pub struct EditJob {
+    operation: Arc<EditOperation>,
+    chunk_count: usize,
+    done_count: AtomicUsize,
+    cancelled: AtomicBool,
+}
+
+impl EditJob {
+    pub fn new(operation: EditOperation, chunk_count: usize) -> Self {
+        Self {
+            operation: Arc::new(operation),
+            chunk_count,
+            done_count: AtomicUsize::new(0),
+            cancelled: AtomicBool::new(false),
+        }
+    }
+
+    pub fn operation(&self) -> &Arc<EditOperation> {
+        &self.operation
+    }
+
+    pub fn chunk_count(&self) -> usize {
+        self.chunk_count
+    }
+
+    pub fn done_count(&self) -> usize {
+        self.done_count.load(Ordering::Acquire)
+    }
+
+    pub fn progress(&self) -> f32 {
+        if self.chunk_count == 0 {
+            1.0
+        } else {
+            self.done_count() as f32 / self.chunk_count as f32
+        }
+    }
+
+    pub fn is_finished(&self) -> bool {
+        self.done_count() >= self.chunk_count
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::Release);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::Acquire)
+    }
+
+    pub fn finish_chunk(&self) {
+        self.done_count.fetch_add(1, Ordering::AcqRel);
+    }
+}
@@ -0,0 +1,143 @@
+use crate::game::base::LocalSpace;
+use crate::game::terrain::chunk::Voxel;
+use euclid::{Point3D, Size3D, UnknownUnit};
+use std::collections::VecDeque;
+
+// Air this many voxels away from the open sky is fully enclosed, the light
+// fades in over the voxels from a cave entrance
+const ENCLOSURE_DEPTH: u32 = 8;
+// Enclosure from which a voxel counts as the inside of a cave
+pub const CAVE_ENCLOSURE: f32 = 1.0;
+
+// How far each voxel of a chunk is from the open sky, 0 for air with nothing
+// solid above it up to 1 for air deep inside or cut off by the surface.
+// Caves are only followed inside the chunk, so a tunnel whose entrance is in
+// a neighbor is enclosed up to the border.
+#[derive(Debug, Clone)]
+pub struct Enclosure {
+    voxel_count: Size3D<u32, UnknownUnit>,
+    values: Vec<u8>,
+}
+
+impl Enclosure {
+    #[profiling::function]
+    pub fn from_voxels(
+        voxels: &[Voxel],
+        voxel_count: Size3D<u32, UnknownUnit>,
+        isolevel: f32,
+    ) -> Self {
+        let (width, height, depth) = (voxel_count.width, voxel_count.height, voxel_count.depth);
+        let index = |x: u32, y: u32, z: u32| (x + width * (y + height * z)) as usize;
+        let air = |i: usize| voxels[i].value < isolevel;
+
+        // Breadth first from the air of every column down to its first solid
+        // voxel, the distance is counted in steps through the air
+        let mut distances = vec![u32::MAX; voxels.len()];
+        let mut queue = VecDeque::new();
+        for y in 0..height {
+            for x in 0..width {
+                for z in (0..depth).rev() {
+                    let i = index(x, y, z);
+                    if !air(i) {
+                        break;
+                    }
+                    distances[i] = 0;
+                    queue.push_back((x, y, z));
+                }
+            }
+        }
+        while let Some((x, y, z)) = queue.pop_front() {
+            let distance = distances[index(x, y, z)];
+            if distance >= ENCLOSURE_DEPTH {
+                continue;
+            }
+            for (nx, ny, nz) in neighbors(x, y, z, voxel_count) {
+                let i = index(nx, ny, nz);
+                if air(i) && distances[i] == u32::MAX {
+                    distances[i] = distance + 1;
+                    queue.push_back((nx, ny, nz));
+                }
+            }
+        }
+
+        let value = |distance: u32| {
+            let enclosure = distance.min(ENCLOSURE_DEPTH) as f32 / ENCLOSURE_DEPTH as f32;
+            (enclosure * u8::MAX as f32).round() as u8
+        };
+        // Solid voxels take the most open air next to them so that vertices
+        // closest to either side of the surface get the same term
+        let mut values = vec![u8::MAX; voxels.len()];
+        for z in 0..depth {
+            for y in 0..height {
+                for x in 0..width {
+                    let i = index(x, y, z);
+                    values[i] = if air(i) {
+                        value(distances[i])
+                    } else {
+                        neighbors(x, y, z, voxel_count)
+                            .map(|(nx, ny, nz)| index(nx, ny, nz))
+                            .filter(|i| air(*i))
+                            .map(|i| value(distances[i]))
+                            .min()
+                            .unwrap_or(u8::MAX)
+                    };
+                }
+            }
+        }
+        Self {
+            voxel_count,
+            values,
+        }
+    }
+
+    // Enclosure of the voxel closest to a point in local space
+    pub fn at(&self, point: &Point3D<f32, LocalSpace>) -> f32 {
+        let voxel = |v: f32, count: u32| {
+            let last = (count.max(2) - 1) as f32;
+            (v * last).round().clamp(0.0, last) as u32
+        };
+        let x = voxel(point.x, self.voxel_count.width);
+        let y = voxel(point.y, self.voxel_count.height);
+        let z = voxel(point.z, self.voxel_count.depth);
+        let i = x + self.voxel_count.width * (y + self.voxel_count.height * z);
+        self.values[i as usize] as f32 / u8::MAX as f32
+    }
+
+    // Four values to a word, as read by gpu_mesh.wgsl
+    pub fn packed(&self) -> Vec<u32> {
+        self.values
+            .chunks(4)
+            .map(|x| {
+                x.iter()
+                    .enumerate()
+                    .fold(0, |word, (i, v)| word | ((*v as u32) << (8 * i)))
+            })
+            .collect()
+    }
+}
+
+fn neighbors(
+    x: u32,
+    y: u32,
+    z: u32,
+    voxel_count: Size3D<u32, UnknownUnit>,
+) -> impl Iterator<Item = (u32, u32, u32)> {
+    let (x, y, z) = (x as i64, y as i64, z as i64);
+    IntoIterator::into_iter([
+        (x - 1, y, z),
+        (x + 1, y, z),
+        (x, y - 1, z),
+        (x, y + 1, z),
+        (x, y, z - 1),
+        (x, y, z + 1),
+    ])
+    .filter(move |(x, y, z)| {
+        *x >= 0
+            && *y >= 0
+            && *z >= 0
+            && *x < voxel_count.width as i64
+            && *y < voxel_count.height as i64
+            && *z < voxel_count.depth as i64
+    })
+    .map(|(x, y, z)| (x as u32, y as u32, z as u32))
+}
@@ -0,0 +1,93 @@
+use crate::gfx::Instance;
+use wgpu::*;
+
+// Erosion runs on every chunk on its own, nothing flows across chunk borders
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ErosionSettings {
+    pub enabled: bool,
+    pub iterations: u32,
+    // Water added to every cell per iteration
+    pub rain: f32,
+    // Fraction of the water that evaporates per iteration
+    pub evaporation: f32,
+    // Sediment carried per unit of flowing water
+    pub capacity: f32,
+    pub erosion: f32,
+    pub deposition: f32,
+}
+
+impl Default for ErosionSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            iterations: 64,
+            rain: 0.0005,
+            evaporation: 0.02,
+            capacity: 4.0,
+            erosion: 0.3,
+            deposition: 0.3,
+        }
+    }
+}
+
+pub struct ErosionPipelines {
+    pub init_cells: ComputePipeline,
+    pub flow: ComputePipeline,
+    pub update: ComputePipeline,
+    pub apply: ComputePipeline,
+}
+
+impl ErosionPipelines {
+    pub fn new(instance: &Instance) -> Self {
+        let device = instance.device();
+        let storage_entry = |binding: u32| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only: false },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("terrain_erosion_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                storage_entry(1),
+                storage_entry(2),
+                storage_entry(3),
+                storage_entry(4),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("terrain_erosion_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/erosion.wgsl"));
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("terrain_erosion_compute_pipeline"),
+                entry_point,
+                module: &shader_module,
+                layout: Some(&pipeline_layout),
+            })
+        };
+        Self {
+            init_cells: create_pipeline("init_cells"),
+            flow: create_pipeline("flow"),
+            update: create_pipeline("update"),
+            apply: create_pipeline("apply"),
+        }
+    }
+}
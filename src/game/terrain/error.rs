@@ -0,0 +1,35 @@
+use super::ChunkCacheKey;
+use std::fmt;
+
+// Why the task chain of a chunk stopped, the chunk waits for a retry
+#[derive(Debug, Clone)]
+pub enum TerrainError {
+    // The staging buffers could not be read back from the GPU
+    MappingFailed,
+    // A chunk the task works on left the cache while it ran
+    MissingChunk(ChunkCacheKey),
+    // The voxels of the chunk were not read back before meshing
+    MissingVoxels,
+    // The triangle buffer was taken before the mesh was written
+    MissingTriangles,
+    // Message of a caught panic
+    Panicked(String),
+}
+
+impl fmt::Display for TerrainError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TerrainError::MappingFailed => write!(f, "mapping the chunk buffers failed"),
+            TerrainError::MissingChunk(key) => write!(
+                f,
+                "chunk at {:?} level {} left the cache",
+                key.bounds.min, key.level
+            ),
+            TerrainError::MissingVoxels => write!(f, "the voxels were not read back"),
+            TerrainError::MissingTriangles => write!(f, "the triangle buffer was already taken"),
+            TerrainError::Panicked(reason) => write!(f, "panicked: {}", reason),
+        }
+    }
+}
+
+impl std::error::Error for TerrainError {}
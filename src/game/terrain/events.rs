@@ -0,0 +1,37 @@
+use super::{ChunkCacheKey, TerrainError};
+use parking_lot::Mutex;
+use std::sync::mpsc::{channel, Receiver, Sender};
+
+#[derive(Debug, Clone)]
+pub enum TerrainEvent {
+    // A mesh was written for the chunk
+    Meshed(ChunkCacheKey),
+    // The chunk failed and waits for a retry
+    Failed(ChunkCacheKey, TerrainError),
+}
+
+// Generation events sent to every subscriber, a subscriber is dropped once
+// its receiver is
+pub struct TerrainEvents {
+    senders: Mutex<Vec<Sender<TerrainEvent>>>,
+}
+
+impl TerrainEvents {
+    pub fn new() -> Self {
+        Self {
+            senders: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn subscribe(&self) -> Receiver<TerrainEvent> {
+        let (sender, receiver) = channel();
+        self.senders.lock().push(sender);
+        receiver
+    }
+
+    pub fn send(&self, event: TerrainEvent) {
+        self.senders
+            .lock()
+            .retain(|x| x.send(event.clone()).is_ok());
+    }
+}
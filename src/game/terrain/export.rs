@@ -0,0 +1,86 @@
+use euclid::{Size2D, UnknownUnit};
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::Path;
+
+// What is written to the texels of an export
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum ExportSource {
+    // Height of the topmost surface, scaled to the range of the exported
+    // heights
+    SurfaceHeight,
+    // Density of a horizontal slice at the height, clamped to zero and one
+    Density(f32),
+}
+
+// Single channel 16 bit image, row 0 is the max y edge. Texels without a
+// cached chunk are zero.
+pub struct TerrainImage {
+    size: Size2D<u32, UnknownUnit>,
+    data: Vec<u16>,
+    // Values mapped to zero and the max texel value
+    range: (f32, f32),
+    missing_count: usize,
+}
+
+impl TerrainImage {
+    pub(super) fn new(
+        size: Size2D<u32, UnknownUnit>,
+        values: &[Option<f32>],
+        source: ExportSource,
+    ) -> Self {
+        let range = match source {
+            ExportSource::SurfaceHeight => values
+                .iter()
+                .flatten()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), x| {
+                    (min.min(*x), max.max(*x))
+                }),
+            ExportSource::Density(_) => (0.0, 1.0),
+        };
+        let scale = if range.1 > range.0 {
+            u16::MAX as f32 / (range.1 - range.0)
+        } else {
+            0.0
+        };
+        let data = values
+            .iter()
+            .map(|x| {
+                x.map_or(0, |x| {
+                    ((x - range.0) * scale).clamp(0.0, u16::MAX as f32) as u16
+                })
+            })
+            .collect();
+        Self {
+            size,
+            data,
+            range,
+            missing_count: values.iter().filter(|x| x.is_none()).count(),
+        }
+    }
+
+    pub fn range(&self) -> (f32, f32) {
+        self.range
+    }
+
+    pub fn missing_count(&self) -> usize {
+        self.missing_count
+    }
+
+    pub fn save_png(&self, path: &Path) -> Result<(), png::EncodingError> {
+        let file = File::create(path)?;
+        let mut encoder =
+            png::Encoder::new(BufWriter::new(file), self.size.width, self.size.height);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(png::BitDepth::Sixteen);
+        let mut writer = encoder.write_header()?;
+        // PNG stores 16 bit samples as big endian
+        let bytes = self
+            .data
+            .iter()
+            .flat_map(|x| x.to_be_bytes())
+            .collect::<Vec<_>>();
+        writer.write_image_data(&bytes)?;
+        Ok(())
+    }
+}
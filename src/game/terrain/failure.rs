@@ -0,0 +1,57 @@
+use super::TerrainError;
+use std::any::Any;
+use std::time::{Duration, Instant};
+
+// Delay before the first retry, doubled on every failure after that
+const RETRY_DELAY: Duration = Duration::from_secs(1);
+const MAX_RETRY_DELAY: Duration = Duration::from_secs(64);
+
+#[derive(Debug, Clone)]
+pub enum ChunkState {
+    Pending,
+    Generated,
+    Meshed,
+    Failed(ChunkFailure),
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkFailure {
+    pub error: TerrainError,
+    pub attempts: u32,
+    pub retry_at: Instant,
+}
+
+impl ChunkFailure {
+    pub(super) fn new(error: TerrainError) -> Self {
+        Self {
+            error,
+            attempts: 1,
+            retry_at: Instant::now() + RETRY_DELAY,
+        }
+    }
+
+    pub(super) fn fail_again(&mut self, error: TerrainError) {
+        let delay = RETRY_DELAY
+            .checked_mul(1 << self.attempts.min(16))
+            .map_or(MAX_RETRY_DELAY, |x| x.min(MAX_RETRY_DELAY));
+        self.error = error;
+        self.attempts += 1;
+        self.retry_at = Instant::now() + delay;
+    }
+
+    pub(super) fn can_retry(&self) -> bool {
+        Instant::now() >= self.retry_at
+    }
+}
+
+// Message of a caught panic, panics with a formatted message carry a String
+// and the others a &str
+pub(super) fn panic_reason(payload: &(dyn Any + Send)) -> String {
+    if let Some(reason) = payload.downcast_ref::<String>() {
+        reason.clone()
+    } else if let Some(reason) = payload.downcast_ref::<&str>() {
+        reason.to_string()
+    } else {
+        "unknown panic".to_string()
+    }
+}
@@ -0,0 +1,97 @@
+use super::pipelines::generate_voxel_pipeline_layout;
+use crate::gfx::Instance;
+use std::collections::hash_map::DefaultHasher;
+use std::hash::{Hash, Hasher};
+use wgpu::*;
+
+pub const DEFAULT_DENSITY: &str = include_str!("shaders/density.wgsl");
+const DENSITY_MARKER: &str = "// DENSITY";
+
+pub enum GeneratorSource {
+    // Defines `fn density(pos: vec3<f32>) -> f32`, spliced into
+    // generate_voxel.wgsl so that it can use the noise functions
+    Wgsl(String),
+    // Has to be built with generate_voxel_pipeline_layout
+    Pipeline(ComputePipeline),
+}
+
+// Supplies the density function of the terrain, the source is asked for
+// every time the terrain pipelines are built
+pub trait TerrainGenerator: Send + Sync {
+    fn source(&self, instance: &Instance) -> GeneratorSource;
+
+    // Identifies the generated density in the disk cache, chunks of
+    // generators without one are never stored
+    fn cache_id(&self) -> Option<u64> {
+        None
+    }
+}
+
+fn hash_source(source: &str) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    source.hash(&mut hasher);
+    hasher.finish()
+}
+
+pub struct DensityGenerator {
+    density: String,
+}
+
+impl DensityGenerator {
+    pub fn new(density: String) -> Self {
+        Self { density }
+    }
+}
+
+impl Default for DensityGenerator {
+    fn default() -> Self {
+        Self::new(DEFAULT_DENSITY.to_string())
+    }
+}
+
+impl TerrainGenerator for DensityGenerator {
+    fn source(&self, _instance: &Instance) -> GeneratorSource {
+        GeneratorSource::Wgsl(self.density.clone())
+    }
+
+    fn cache_id(&self) -> Option<u64> {
+        Some(hash_source(&self.density))
+    }
+}
+
+// Complete compute shader with the bindings and entry point of
+// generate_voxel.wgsl
+pub struct ShaderGenerator {
+    shader: String,
+}
+
+impl ShaderGenerator {
+    pub fn new(shader: String) -> Self {
+        Self { shader }
+    }
+}
+
+impl TerrainGenerator for ShaderGenerator {
+    fn source(&self, instance: &Instance) -> GeneratorSource {
+        let device = instance.device();
+        let shader_module = device.create_shader_module(&ShaderModuleDescriptor {
+            label: Some("terrain_generator_shader"),
+            source: ShaderSource::Wgsl(self.shader.as_str().into()),
+        });
+        GeneratorSource::Pipeline(device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("terrain_generator_compute_pipeline"),
+            entry_point: "main",
+            module: &shader_module,
+            layout: Some(&generate_voxel_pipeline_layout(instance)),
+        }))
+    }
+
+    fn cache_id(&self) -> Option<u64> {
+        Some(hash_source(&self.shader))
+    }
+}
+
+// generate_voxel.wgsl with the density function inserted
+pub fn generate_voxel_shader(density: &str) -> String {
+    include_str!("shaders/generate_voxel.wgsl").replacen(DENSITY_MARKER, density, 1)
+}
@@ -0,0 +1,143 @@
+use super::{ChunkCacheKey, TerrainTask};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+
+// Stages of the chunk pipeline in order, each one needs the stage before it
+// done for the chunk: generate -> mesh -> resources -> stitch
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Stage {
+    Generate,
+    Mesh,
+    Resources,
+    Stitch,
+}
+
+struct GraphState {
+    // Stage the chain of each key is at, a key has one chain running at a time
+    running: HashMap<ChunkCacheKey, Stage>,
+    // Stitches waiting on neighbors that are still being meshed, with the
+    // neighbors they were queued with
+    waiting: HashMap<ChunkCacheKey, Vec<ChunkCacheKey>>,
+}
+
+// Dependencies between the task chains of the chunks. A task whose stage the
+// running chain of its key still goes through is dropped, and stitches wait
+// for their neighbors before they read the edges of the neighbor meshes.
+pub struct TaskGraph {
+    state: Mutex<GraphState>,
+}
+
+impl TaskGraph {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(GraphState {
+                running: HashMap::new(),
+                waiting: HashMap::new(),
+            }),
+        }
+    }
+
+    // Returns false if the task starting a chain is duplicate work. Tasks
+    // waiting on the GPU resume their own chain, and the ones that change the
+    // voxels restart the pipeline of their key so they are never dropped.
+    pub fn enter(&self, task: &TerrainTask) -> bool {
+        let stage = match task {
+            TerrainTask::AwaitMapping(_)
+            | TerrainTask::AwaitSubmission(_)
+            | TerrainTask::AwaitCapacity(_) => return true,
+            _ => match task_stage(task) {
+                Some(stage) => stage,
+                None => return true,
+            },
+        };
+        let restarts = matches!(
+            task,
+            TerrainTask::RegenerateTriangle(_) | TerrainTask::ReplayDeltas(_)
+        );
+        let mut state = self.state.lock();
+        let duplicate = match state.running.get(&task.key()) {
+            // A later stitch has newer neighbors
+            Some(Stage::Stitch) => false,
+            Some(running) => *running <= stage && !restarts,
+            None => false,
+        };
+        if !duplicate {
+            state.running.insert(task.key(), stage);
+        }
+        !duplicate
+    }
+
+    // Moves the chain of the key from the current stage to the one of its next
+    // task, the chain is done without one. Returns the stitches that can run
+    // now that the chain is done.
+    pub fn advance(
+        &self,
+        key: &ChunkCacheKey,
+        current: Option<Stage>,
+        next_task: Option<&TerrainTask>,
+    ) -> Vec<TerrainTask> {
+        let mut state = self.state.lock();
+        match next_task.and_then(task_stage) {
+            Some(next_stage) => {
+                state.running.insert(*key, next_stage);
+                vec![]
+            }
+            None if current.is_some() => {
+                state.running.remove(key);
+                let released = state
+                    .waiting
+                    .iter()
+                    .filter(|(_, neighbors)| {
+                        neighbors.contains(key)
+                            && !neighbors.iter().any(|x| is_meshing(&state.running, x))
+                    })
+                    .map(|(key, _)| *key)
+                    .collect::<Vec<_>>();
+                released
+                    .into_iter()
+                    .map(|x| {
+                        let neighbors = state.waiting.remove(&x).unwrap();
+                        TerrainTask::StitchMesh(x, neighbors)
+                    })
+                    .collect()
+            }
+            None => vec![],
+        }
+    }
+
+    // Returns true if the stitch has to wait, it is handed back by advance
+    // once no neighbor is being meshed. A later stitch of the key replaces it.
+    pub fn await_neighbors(&self, key: &ChunkCacheKey, neighbors: &[ChunkCacheKey]) -> bool {
+        let mut state = self.state.lock();
+        if neighbors.iter().any(|x| is_meshing(&state.running, x)) {
+            state.waiting.insert(*key, neighbors.to_vec());
+            true
+        } else {
+            false
+        }
+    }
+}
+
+// Edits are not tracked, they finish their job whatever the chunk is at
+pub fn task_stage(task: &TerrainTask) -> Option<Stage> {
+    match task {
+        TerrainTask::GenerateChunk(_)
+        | TerrainTask::AwaitCapacity(_)
+        | TerrainTask::ErodeChunk(..)
+        | TerrainTask::WriteChunk(..)
+        | TerrainTask::RegenerateTriangle(_)
+        | TerrainTask::ReplayDeltas(_) => Some(Stage::Generate),
+        TerrainTask::GenerateMesh(_)
+        | TerrainTask::AwaitMapping(_)
+        | TerrainTask::AwaitSubmission(_)
+        | TerrainTask::WriteMesh(..) => Some(Stage::Mesh),
+        TerrainTask::GenerateMeshResouces(_) => Some(Stage::Resources),
+        TerrainTask::StitchMesh(..) => Some(Stage::Stitch),
+        TerrainTask::ApplyEdit(..) => None,
+    }
+}
+
+// The mesh of the key is written at the end of the mesh stage
+fn is_meshing(running: &HashMap<ChunkCacheKey, Stage>, key: &ChunkCacheKey) -> bool {
+    running.get(key).map_or(false, |x| *x <= Stage::Mesh)
+}
@@ -0,0 +1,58 @@
+// Ground level the depth is measured from, the default generator puts the
+// surface around the middle of the world
+const GROUND_LEVEL: f32 = 0.0;
+
+// Rock far enough below the ground is shaded as glowing magma. It only
+// changes the material the render shader picks, so caves and canyons that
+// reach the depth show it without generating the terrain again.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MagmaSettings {
+    pub enabled: bool,
+    // Below the ground level where the magma starts
+    pub depth: f32,
+    // Depth over which it fades in
+    pub blend: f32,
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Default for MagmaSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            depth: 0.4,
+            blend: 0.1,
+            color: [1.0, 0.3, 0.05],
+            intensity: 2.0,
+        }
+    }
+}
+
+// Uniform of the render shader. Keep in sync with render.wgsl
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+pub struct MagmaData {
+    color: [f32; 3],
+    // Zero when disabled
+    intensity: f32,
+    // Height below which the rock starts to glow
+    top: f32,
+    blend: f32,
+    _pad: [f32; 2],
+}
+
+impl MagmaData {
+    pub fn new(settings: &MagmaSettings) -> Self {
+        Self {
+            color: settings.color,
+            intensity: if settings.enabled {
+                settings.intensity
+            } else {
+                0.0
+            },
+            top: GROUND_LEVEL - settings.depth,
+            blend: settings.blend.max(f32::EPSILON),
+            _pad: [0.0; 2],
+        }
+    }
+}
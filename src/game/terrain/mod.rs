@@ -1,89 +1,467 @@
+mod arena;
+mod batch;
+mod biome;
 mod cache;
 mod chunk;
 mod chunk_mesh;
+mod crack;
+mod csg;
+mod deletion_queue;
+mod delta;
+mod diff;
+mod disk_cache;
+mod draw_args;
+mod edge_id;
+mod edit;
+mod enclosure;
+mod erosion;
+mod error;
+mod events;
+mod export;
+mod failure;
+mod generator;
+mod graph;
+mod magma;
+mod normals;
+mod occupancy;
+mod pipelines;
+mod point_light;
+mod preview;
+mod preview_world;
+mod queue;
+mod scheduler;
+mod sculpt;
+mod set_piece;
+mod task_audit;
+mod transition;
+mod traversability;
 mod tree;
+mod vertex_color;
+mod waiters;
+mod weld;
 
 use crate::game::base::WorldSpace;
 use crate::game::mesh::Mesh;
+use crate::game::settings::StreamingSettings;
+use crate::game::wind::WindData;
 use crate::{game::base::Region, gfx::Instance};
+use arena::MeshArenas;
+use batch::SubmissionBatch;
+use biome::BIOME_COUNT;
 use cache::Cache;
-use chunk::Chunk;
-use chunk_mesh::{ChunkMesh, EdgeVoxel, MapStatus, VertexData};
-use crossbeam_deque::{Injector, Worker};
+use chunk::{Chunk, MapStatus};
+use chunk_mesh::{ChunkMesh, EdgeVoxel, MeshStyle, RenderResources};
+use crossbeam_deque::{Steal, Stealer, Worker};
+use deletion_queue::DeletionQueue;
+use delta::{ChunkDelta, EditDeltas};
+use diff::DiffSelection;
+use disk_cache::{chunk_spill_directory, ChunkSpill, DiskCache, DISK_CACHE_PATH};
+use enclosure::Enclosure;
+use euclid::point2;
 use euclid::size3;
+use euclid::Box2D;
 use euclid::Box3D;
+use euclid::Point2D;
 use euclid::Point3D;
+use euclid::Size2D;
+use euclid::UnknownUnit;
+use euclid::Vector3D;
+use events::TerrainEvents;
+use failure::{panic_reason, ChunkFailure};
+use futures::executor::block_on;
+use graph::{task_stage, TaskGraph};
+use magma::MagmaData;
+use occupancy::{Occupancy, OccupancyPatch};
 use parking_lot::{RwLock, RwLockReadGuard};
-use std::mem::size_of;
+use pipelines::TerrainPipelines;
+use point_light::PointLightsData;
+use preview::PreviewChunk;
+use preview_world::PreviewWorldSimulation;
+use queue::TaskQueue;
+use scheduler::ChunkScheduler;
+use std::collections::hash_map::DefaultHasher;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::hash::{Hash, Hasher};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::mpsc::Receiver;
 use std::sync::{Arc, Condvar, Mutex};
 use std::thread::JoinHandle;
+use std::time::Duration;
+use task_audit::{ChainAudit, TaskAudit};
+use transition::{FineNeighbor, Side, StitchStride, TransitionFace, MAX_STITCH_RATIO};
 use tree::Tree;
+use waiters::MappingWaiters;
 use wgpu::*;
 
+pub use biome::{dominant_biome, pick_biome, BIOME_NAMES, BIOME_STYLES};
+pub use cache::{CacheEntry, CacheStats};
+pub use crack::Crack;
+pub use csg::{CsgEdit, CsgOperation, CsgShape};
+pub use diff::ChunkDiff;
+pub use edge_id::EdgeId;
+pub use edit::{EditJob, EditOperation, SweepMode};
+pub use enclosure::CAVE_ENCLOSURE;
+pub use erosion::ErosionSettings;
+pub use error::TerrainError;
+pub use events::TerrainEvent;
+pub use export::{ExportSource, TerrainImage};
+pub use failure::ChunkState;
+pub use generator::{
+    generate_voxel_shader, DensityGenerator, GeneratorSource, ShaderGenerator, TerrainGenerator,
+    DEFAULT_DENSITY,
+};
+pub use magma::MagmaSettings;
+pub use point_light::{PointLightData, MAX_POINT_LIGHTS};
+pub use preview_world::{PreviewWorld, PreviewWorldParams, PREVIEW_WORLD_RESOLUTION};
+pub use queue::TaskFocus;
+pub use scheduler::MAX_PENDING_CHUNKS;
+pub use sculpt::TerrainEdit;
+pub use set_piece::SetPiece;
+pub use traversability::SurfaceMetadata;
+pub use tree::{is_near_layer, MAX_LEVEL};
+
 // Keep in sync with shader
 const SHADER_WORKGROUP_SIZE: u32 = 8;
+const BYTES_PER_MIB: u64 = 1 << 20;
+// Points sampled along each side of a chunk outline
+const OUTLINE_SAMPLES: usize = 16;
+// The chunks of the level are five layers of voxels deep and span the whole
+// range of z
+pub const MIN_LEVEL: u32 = 2;
 
 #[derive(Debug, Hash, Eq, PartialEq, Copy, Clone)]
 pub struct ChunkCacheKey {
     pub bounds: Box3D<i32, WorldSpace>,
     pub level: u32,
+    pub noise: NoiseAlgorithm,
+    // Hash of the generation parameters, see TerrainData::update_params
+    pub params: u64,
+}
+
+// Keys of the nodes containing the key, from its parent up to the coarsest
+// level that has chunks
+fn ancestor_keys(key: &ChunkCacheKey) -> Vec<ChunkCacheKey> {
+    let mut ancestors = vec![];
+    let mut current = *key;
+    while current.level > MIN_LEVEL {
+        let bounds = tree::parent_bounds(&current.bounds, current.level).unwrap();
+        current = ChunkCacheKey {
+            bounds,
+            level: current.level - 1,
+            noise: current.noise,
+            params: current.params,
+        };
+        ancestors.push(current);
+    }
+    ancestors
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct RaycastHit {
+    pub position: Point3D<f32, WorldSpace>,
+    pub normal: Vector3D<f32, WorldSpace>,
+    pub distance: f32,
+    pub key: ChunkCacheKey,
+}
+
+// Snapshot of the streaming state, see Terrain::stats
+#[derive(Debug, Clone, Default)]
+pub struct TerrainStats {
+    // Meshes in the cache of the current meshing for each tree level
+    pub chunks_per_level: BTreeMap<u32, usize>,
+    pub resident_triangles: usize,
+    // Buffers owned by the cached chunks and meshes
+    pub gpu_bytes: u64,
+    // Chunk generations queued or running
+    pub pending_chunks: usize,
+    // Tasks waiting in the global queue, not counting the workers' own
+    pub queued_tasks: usize,
+    // From queueing a chunk to the end of its task chain
+    pub average_latency: Duration,
+    // Chunk submissions the GPU has not finished and chunks parked until it
+    // does
+    pub gpu_submissions: usize,
+    pub parked_chunks: usize,
+    // Removed meshes and released render resources waiting for the frames in
+    // flight before being dropped
+    pub retired_meshes: usize,
+    // Weight of the entries of the caches, see Chunk::cache_bytes and
+    // ChunkMesh::cache_bytes
+    pub chunk_cache_bytes: u64,
+    pub mesh_cache_bytes: u64,
+}
+
+// How a side of a rendered chunk meets its neighbors
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum StitchStatus {
+    // No finer neighbor is rendered along the side
+    Matched,
+    // The transition cells toward the finer neighbors are built
+    Stitched,
+    // The finer neighbors are rendered but the transition cells are not built
+    // yet, the seam is open until they are
+    Pending,
+    // The skirts cover the seam
+    Skirted,
+}
+
+// Side of a chunk outline, see Terrain::chunk_outlines
+#[derive(Debug, Clone)]
+pub struct OutlineSide {
+    pub status: StitchStatus,
+    // None where the chunk has no surface under the point
+    pub points: Vec<Option<Point3D<f32, WorldSpace>>>,
+}
+
+#[derive(Debug, Clone)]
+pub struct ChunkOutline {
+    pub key: ChunkCacheKey,
+    // In the order of Side::ALL
+    pub sides: Vec<OutlineSide>,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum TerrainOverlay {
+    None,
+    Traversability,
+}
+
+// Surface extraction of the triangle pass. Surface nets places one vertex in
+// each cell at the average of its edge crossings, dual contouring places it
+// where the surface normals meet so edges of CSG shapes stay sharp instead of
+// being cut off. Keep the order in sync with the generate triangle pipelines.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum MeshingAlgorithm {
+    MarchingCubes,
+    DualContouring,
+    SurfaceNets,
+}
+
+pub const MESHING_ALGORITHMS: [MeshingAlgorithm; 3] = [
+    MeshingAlgorithm::MarchingCubes,
+    MeshingAlgorithm::DualContouring,
+    MeshingAlgorithm::SurfaceNets,
+];
+
+impl MeshingAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            MeshingAlgorithm::MarchingCubes => "marching cubes",
+            MeshingAlgorithm::DualContouring => "dual contouring",
+            MeshingAlgorithm::SurfaceNets => "surface nets",
+        }
+    }
+}
+
+// Base noise of the voxel shader. Keep the order in sync with
+// generate_voxel.wgsl
+#[derive(Debug, Hash, Eq, PartialEq, Copy, Clone)]
+pub enum NoiseAlgorithm {
+    Perlin,
+    Simplex,
+    Worley,
+    RidgedMultifractal,
+}
+
+// Warping is disabled with a strength of zero
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct DomainWarp {
+    pub strength: f32,
+    pub frequency: f32,
+}
+
+impl Default for DomainWarp {
+    fn default() -> Self {
+        Self {
+            strength: 0.0,
+            frequency: 1.0,
+        }
+    }
+}
+
+// Tunnels carved out of the density after the generator runs
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct CaveSettings {
+    pub enabled: bool,
+    pub frequency: f32,
+    // Width of the tunnels in noise units, larger values join them into caverns
+    pub radius: f32,
+}
+
+impl Default for CaveSettings {
+    fn default() -> Self {
+        Self {
+            enabled: false,
+            frequency: 4.0,
+            radius: 0.1,
+        }
+    }
+}
+
+pub const NOISE_ALGORITHMS: [NoiseAlgorithm; 4] = [
+    NoiseAlgorithm::Perlin,
+    NoiseAlgorithm::Simplex,
+    NoiseAlgorithm::Worley,
+    NoiseAlgorithm::RidgedMultifractal,
+];
+
+impl NoiseAlgorithm {
+    pub fn name(&self) -> &'static str {
+        match self {
+            NoiseAlgorithm::Perlin => "perlin",
+            NoiseAlgorithm::Simplex => "simplex",
+            NoiseAlgorithm::Worley => "worley",
+            NoiseAlgorithm::RidgedMultifractal => "ridged multifractal",
+        }
+    }
 }
 
+#[derive(Clone, PartialEq)]
 pub struct TerrainRegion {
     pub region: Region,
     pub level: u32,
 }
 
-#[derive(Debug, Copy, Clone)]
-struct StitchStride {
-    min_x: u32,
-    max_x: u32,
-    min_y: u32,
-    max_y: u32,
+// Keys requested for the regions the tree was last updated for
+struct AppliedRegions {
+    regions: Vec<TerrainRegion>,
+    layer: i32,
+    noise: NoiseAlgorithm,
+    params: u64,
+    keys: Vec<ChunkCacheKey>,
 }
 
 enum TerrainTask {
     GenerateChunk(ChunkCacheKey),
+    // Runs between generating the voxels and the triangles of a chunk
+    ErodeChunk(ChunkCacheKey, Chunk),
     WriteChunk(ChunkCacheKey, Chunk),
-    InvalidateTriangle,
     RegenerateTriangle(ChunkCacheKey),
     GenerateMesh(ChunkCacheKey),
-    WriteMesh(ChunkCacheKey, ChunkMesh),
+    // Meshes a chunk once its staging buffers are mapped, parked until the
+    // mapping is done instead of running right away
+    AwaitMapping(ChunkCacheKey),
+    // Submits the batch holding the pass of the chunk once the worker has
+    // nothing else to do, then meshes it
+    AwaitSubmission(ChunkCacheKey),
+    // Generates a chunk once the GPU has room for more submissions, parked
+    // in the batch until then
+    AwaitCapacity(ChunkCacheKey),
+    WriteMesh(ChunkCacheKey, MeshingAlgorithm, ChunkMesh),
     GenerateMeshResouces(ChunkCacheKey),
-    StitchMesh(ChunkCacheKey, StitchStride),
+    // Builds the transition cells toward the finer rendered neighbors
+    StitchMesh(ChunkCacheKey, Vec<ChunkCacheKey>),
+    ApplyEdit(Arc<EditJob>, ChunkCacheKey),
+    // Runs after the voxels of a chunk generated again are read back
+    ReplayDeltas(ChunkCacheKey),
+}
+
+// Next task of the chain, the chain stops on an error and the chunk fails
+type TaskResult = Result<Option<TerrainTask>, TerrainError>;
+
+impl TerrainTask {
+    fn key(&self) -> ChunkCacheKey {
+        match self {
+            TerrainTask::GenerateChunk(key)
+            | TerrainTask::ErodeChunk(key, _)
+            | TerrainTask::WriteChunk(key, _)
+            | TerrainTask::RegenerateTriangle(key)
+            | TerrainTask::GenerateMesh(key)
+            | TerrainTask::AwaitMapping(key)
+            | TerrainTask::AwaitSubmission(key)
+            | TerrainTask::AwaitCapacity(key)
+            | TerrainTask::WriteMesh(key, ..)
+            | TerrainTask::GenerateMeshResouces(key)
+            | TerrainTask::StitchMesh(key, _)
+            | TerrainTask::ApplyEdit(_, key)
+            | TerrainTask::ReplayDeltas(key) => *key,
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            TerrainTask::GenerateChunk(_) => "GenerateChunk",
+            TerrainTask::ErodeChunk(..) => "ErodeChunk",
+            TerrainTask::WriteChunk(..) => "WriteChunk",
+            TerrainTask::RegenerateTriangle(_) => "RegenerateTriangle",
+            TerrainTask::GenerateMesh(_) => "GenerateMesh",
+            TerrainTask::AwaitMapping(_) => "AwaitMapping",
+            TerrainTask::AwaitSubmission(_) => "AwaitSubmission",
+            TerrainTask::AwaitCapacity(_) => "AwaitCapacity",
+            TerrainTask::WriteMesh(..) => "WriteMesh",
+            TerrainTask::GenerateMeshResouces(_) => "GenerateMeshResouces",
+            TerrainTask::StitchMesh(..) => "StitchMesh",
+            TerrainTask::ApplyEdit(..) => "ApplyEdit",
+            TerrainTask::ReplayDeltas(_) => "ReplayDeltas",
+        }
+    }
 }
 
 pub struct Terrain {
     terrain_data: Arc<TerrainData>,
-    injector: Arc<Injector<TerrainTask>>,
+    queue: Arc<TaskQueue>,
     thread_handles: Vec<JoinHandle<()>>,
     condvar: Arc<Condvar>,
     guard: Arc<Mutex<bool>>,
+    worker_count: usize,
+    instance: Option<Arc<Instance>>,
+    camera_buffers: Option<Arc<Vec<Buffer>>>,
 }
 
 impl Terrain {
-    pub fn new() -> Self {
-        Self {
-            terrain_data: Arc::new(TerrainData::new()),
-            injector: Arc::new(Injector::new()),
+    pub fn new(settings: &StreamingSettings) -> Self {
+        let terrain = Self {
+            terrain_data: Arc::new(TerrainData::new(
+                settings.chunk_cache_mib,
+                settings.mesh_cache_mib,
+                settings.gpu_budget_mib,
+                settings.resident_triangle_budget,
+                settings.max_gpu_submissions,
+                settings.disk_cache,
+            )),
+            queue: Arc::new(TaskQueue::new()),
             thread_handles: vec![],
             condvar: Arc::new(Condvar::new()),
             guard: Arc::new(false.into()),
-        }
+            worker_count: settings.worker_count.max(1),
+            instance: None,
+            camera_buffers: None,
+        };
+        terrain.set_chunk_spill(settings.spill_evicted_chunks);
+        terrain
     }
 
     pub fn init(
         &mut self,
         instance: Arc<Instance>,
         target_format: TextureFormat,
-        camera_buffer: Arc<Buffer>,
+        sample_count: u32,
+        camera_buffers: Arc<Vec<Buffer>>,
         isolevel: f32,
     ) {
-        Arc::get_mut(&mut self.terrain_data)
-            .unwrap()
-            .init(&instance, target_format);
+        self.terrain_data.set_pipelines(TerrainPipelines::new(
+            &instance,
+            target_format,
+            sample_count,
+            TerrainOverlay::None,
+            false,
+            Arc::new(DensityGenerator::default()),
+        ));
+        *self.terrain_data.mesh_arenas.write() = Some(Arc::new(MeshArenas::new(&instance)));
         self.terrain_data.set_isolevel(isolevel);
-        let mut worker_queues = (0..1)
+        self.terrain_data.update_params(false);
+        self.instance = Some(instance.clone());
+        self.camera_buffers = Some(camera_buffers.clone());
+        {
+            let guard = self.guard.clone();
+            let condvar = self.condvar.clone();
+            self.terrain_data.mappings.set_wake(move || {
+                let _guard = guard.lock().unwrap();
+                condvar.notify_one();
+            });
+        }
+        let mut worker_queues = (0..self.worker_count)
             .map(|_| Worker::new_fifo())
             .collect::<Vec<Worker<TerrainTask>>>();
         let stealers = worker_queues
@@ -91,74 +469,23 @@ impl Terrain {
             .map(|x| x.stealer())
             .collect::<Vec<_>>();
         for (i, local) in worker_queues.drain(..).enumerate() {
-            let guard = self.guard.clone();
-            let condvar = self.condvar.clone();
-            let global = self.injector.clone();
             let stealers = stealers
                 .iter()
                 .enumerate()
                 .filter_map(|(j, x)| if i == j { None } else { Some(x.clone()) })
                 .collect::<Vec<_>>();
-            let terrain_data = self.terrain_data.clone();
-            let instance = instance.clone();
-            let camera_buffer = camera_buffer.clone();
-
-            let t = std::thread::spawn(move || {
-                profiling::register_thread!();
-                loop {
-                    loop {
-                        let task = local.pop().or_else(|| {
-                            // Otherwise, we need to look for a task elsewhere.
-                            std::iter::repeat_with(|| {
-                                // Try stealing a batch of tasks from the global queue.
-                                global
-                                    .steal_batch_and_pop(&local)
-                                    // Or try stealing a task from one of the other threads.
-                                    .or_else(|| stealers.iter().map(|s| s.steal()).collect())
-                            })
-                            // Loop while no task was stolen and any steal operation needs to be retried.
-                            .find(|s| !s.is_retry())
-                            // Extract the stolen task, if there is one.
-                            .and_then(|s| s.success())
-                        });
-                        if task.is_none() {
-                            break;
-                        }
-                        let mut next_task = task;
-                        while let Some(t) = next_task {
-                            next_task = match t {
-                                TerrainTask::GenerateChunk(key) => {
-                                    terrain_data.generate_chunk(&instance, &key)
-                                }
-                                TerrainTask::WriteChunk(key, chunk) => {
-                                    terrain_data.write_chunk(&key, chunk)
-                                }
-                                TerrainTask::GenerateMesh(key) => terrain_data.generate_mesh(&key),
-                                TerrainTask::WriteMesh(key, mesh) => {
-                                    terrain_data.write_mesh(&key, mesh)
-                                }
-                                TerrainTask::GenerateMeshResouces(key) => terrain_data
-                                    .generate_mesh_resources(&instance, &camera_buffer, &key),
-                                TerrainTask::RegenerateTriangle(key) => {
-                                    terrain_data.regenerate_triangle(&instance, &key)
-                                }
-                                TerrainTask::InvalidateTriangle => {
-                                    terrain_data.invalidate_triangle()
-                                }
-                                TerrainTask::StitchMesh(key, stride) => {
-                                    terrain_data.stitch_mesh(&key, &stride)
-                                }
-                            }
-                        }
-                    }
-                    let mut done = guard.lock().unwrap();
-                    done = condvar.wait(done).unwrap();
-                    if *done {
-                        break;
-                    }
-                }
-            });
-            self.thread_handles.push(t);
+            let worker = TerrainWorker {
+                index: i,
+                local,
+                stealers,
+                global: self.queue.clone(),
+                guard: self.guard.clone(),
+                condvar: self.condvar.clone(),
+                terrain_data: self.terrain_data.clone(),
+                instance: instance.clone(),
+                camera_buffers: camera_buffers.clone(),
+            };
+            self.thread_handles.push(worker.spawn());
         }
         let instance = instance.clone();
         let guard = self.guard.clone();
@@ -177,17 +504,12 @@ impl Terrain {
         // self.thread_handles.push(t);
     }
 
-    #[profiling::function]
-    pub fn update_terrain(&self, position: &Point3D<f32, WorldSpace>, regions: &[TerrainRegion]) {
-        {
-            let mut tree = self.terrain_data.tree.write();
-            for region in regions {
-                tree.ensure_node_in_region(&region.region);
-                tree.set_level_in_region(&region.region, region.level);
-            }
-            tree.rebuild_tree();
-        }
+    // Keys of the leaves in the regions, the tree has to be updated for the
+    // regions first
+    pub fn region_keys(&self, regions: &[TerrainRegion]) -> Vec<ChunkCacheKey> {
         let tree = self.terrain_data.tree.read();
+        let noise = *self.terrain_data.noise.read();
+        let params = *self.terrain_data.params.read();
         let mut keys = vec![];
         for node in tree.leaf_intersect_regions_iter(
             regions
@@ -198,587 +520,2531 @@ impl Terrain {
         ) {
             let bounds = node.bounds();
             let level = node.level();
-            let key = ChunkCacheKey { bounds, level };
+            let key = ChunkCacheKey {
+                bounds,
+                level,
+                noise,
+                params,
+            };
             keys.push(key);
         }
-        keys.sort_by(|a, b| {
-            b.bounds
-                .center()
-                .to_f32()
-                .distance_to(*position)
-                .partial_cmp(&a.bounds.center().to_f32().distance_to(*position))
-                .unwrap()
-        });
-        self.terrain_data.update_last_accessed(&keys);
-        for (i, key) in keys.iter().rev().enumerate() {
-            self.injector.push(TerrainTask::GenerateChunk(*key));
-            self.condvar.notify_one();
-            // let mut stride = StitchStride {
-            //     min_x: 1,
-            //     max_x: 1,
-            //     min_y: 1,
-            //     max_y: 1,
-            // };
-            // for other in keys.iter().rev().skip(i + 1) {
-            //     if other.bounds.max.x == key.bounds.min.x
-            //         && ((other.bounds.min.y >= key.bounds.min.y
-            //             && other.bounds.min.y < key.bounds.max.y)
-            //             || (other.bounds.max.y <= key.bounds.max.y
-            //                 && other.bounds.max.y > key.bounds.min.y))
-            //     {
-            //         stride.min_x = 2u32
-            //             .pow((key.level as i32 - other.level as i32).abs() as u32)
-            //             .max(stride.min_x)
-            //     }
-            //     if other.bounds.min.x == key.bounds.max.x
-            //         && ((other.bounds.min.y >= key.bounds.min.y
-            //             && other.bounds.min.y < key.bounds.max.y)
-            //             || (other.bounds.max.y <= key.bounds.max.y
-            //                 && other.bounds.max.y > key.bounds.min.y))
-            //     {
-            //         stride.max_x = 2u32
-            //             .pow((key.level as i32 - other.level as i32).abs() as u32)
-            //             .max(stride.max_x)
-            //     }
-            //     if other.bounds.max.y == key.bounds.min.y
-            //         && ((other.bounds.min.x >= key.bounds.min.x
-            //             && other.bounds.min.x < key.bounds.max.x)
-            //             || (other.bounds.max.x <= key.bounds.max.x
-            //                 && other.bounds.max.x > key.bounds.min.x))
-            //     {
-            //         stride.min_y = 2u32
-            //             .pow((key.level as i32 - other.level as i32).abs() as u32)
-            //             .max(stride.min_y)
-            //     }
-            //     if other.bounds.min.y == key.bounds.max.y
-            //         && ((other.bounds.min.x >= key.bounds.min.x
-            //             && other.bounds.min.x < key.bounds.max.x)
-            //             || (other.bounds.max.x <= key.bounds.max.x
-            //                 && other.bounds.max.x > key.bounds.min.x))
-            //     {
-            //         stride.max_y = 2u32
-            //             .pow((key.level as i32 - other.level as i32).abs() as u32)
-            //             .max(stride.max_y)
-            //     }
-            // }
-            // self.terrain_data.stitch_mesh(key, &stride);
-            // self.injector.push(TerrainTask::StitchMesh(*key, stride));
-        }
-    }
-
-    #[profiling::function]
-    pub fn render<'a>(&'a self, regions: &[Region]) -> Vec<TerrainRenderBundle> {
-        self.terrain_data.render(regions)
+        keys
     }
 
-    #[profiling::function]
-    pub fn tree(&self) -> RwLockReadGuard<Tree> {
-        self.terrain_data.tree.read()
+    // Keys of the coarsest chunks inside the region
+    pub fn coarse_keys(&self, region: &Region) -> Vec<ChunkCacheKey> {
+        self.keys_in_region(region, MIN_LEVEL)
     }
 
-    #[profiling::function]
-    pub fn mesh_cache(&self) -> RwLockReadGuard<Cache<ChunkCacheKey, ChunkMesh>> {
-        self.terrain_data.mesh_cache.read()
+    // Keys of the chunks of the level inside the region, whether the tree has
+    // their nodes or not
+    pub fn keys_in_region(&self, region: &Region, level: u32) -> Vec<ChunkCacheKey> {
+        let noise = *self.terrain_data.noise.read();
+        let params = *self.terrain_data.params.read();
+        tree::bounds_in_region(region, level)
+            .into_iter()
+            .map(|bounds| ChunkCacheKey {
+                bounds,
+                level,
+                noise,
+                params,
+            })
+            .collect()
     }
 
-    pub fn set_isolevel(&self, isolevel: f32) {
-        self.terrain_data.set_isolevel(isolevel);
-        self.injector.push(TerrainTask::InvalidateTriangle);
+    // Queued tasks near the camera and in its view are run first
+    pub fn reprioritize(&self, focus: TaskFocus) {
+        self.queue.set_focus(focus);
+        *self.terrain_data.focus.write() = Some(focus);
     }
-}
-
-struct TerrainData {
-    tree: RwLock<Tree>,
-    isolevel: RwLock<f32>,
-    chunk_cache: RwLock<Cache<ChunkCacheKey, Chunk>>,
-    mesh_cache: RwLock<Cache<ChunkCacheKey, ChunkMesh>>,
-    generate_voxel_pipeline: Option<ComputePipeline>,
-    generate_triangle_pipeline: Option<ComputePipeline>,
-    render_pipeline: Option<RenderPipeline>,
-    render_bind_group_layout: Option<BindGroupLayout>,
-    render_target_format: Option<TextureFormat>,
-}
 
-impl TerrainData {
-    fn new() -> Self {
-        Self {
-            chunk_cache: RwLock::new(Cache::new(128)),
-            mesh_cache: RwLock::new(Cache::new(256)),
-            tree: RwLock::new(Tree::new()),
-            isolevel: RwLock::new(0.5),
-            generate_voxel_pipeline: None,
-            generate_triangle_pipeline: None,
-            render_pipeline: None,
-            render_bind_group_layout: None,
-            render_target_format: None,
-        }
+    pub fn subscribe(&self) -> Receiver<TerrainEvent> {
+        self.terrain_data.events.subscribe()
     }
 
-    fn init(&mut self, instance: &Instance, target_format: TextureFormat) {
-        self.init_generate_voxel_pipeline(instance);
-        self.init_generate_triangle_pipeline(instance);
-        self.init_render_pipeline(instance, target_format);
+    // Queue the chunks without touching the tree, chunks waiting for a retry
+    // are skipped
+    pub fn request_chunks(&self, keys: &[ChunkCacheKey]) {
+        self.queue_chunks(keys, false);
     }
 
-    fn init_generate_voxel_pipeline(&mut self, instance: &Instance) {
-        let device = instance.device();
-        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("terrain_voxel_bind_group_layout"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-        });
-        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("terrain_voxel_pipeline_layout"),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
-        let shader_module =
-            device.create_shader_module(&include_wgsl!("shaders/generate_voxel.wgsl"));
-        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: Some("terrain_voxel_compute_pipeline"),
-            entry_point: "main",
-            module: &shader_module,
-            layout: Some(&pipeline_layout),
-        });
-
-        self.generate_voxel_pipeline = Some(pipeline);
+    // Same, behind every other task
+    pub fn request_idle_chunks(&self, keys: &[ChunkCacheKey]) {
+        self.queue_chunks(keys, true);
     }
 
-    fn init_generate_triangle_pipeline(&mut self, instance: &Instance) {
-        let device = instance.device();
-        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("terrain_triangle_bind_group_layout"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Uniform,
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 1,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: true },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-                BindGroupLayoutEntry {
-                    binding: 2,
-                    visibility: ShaderStages::COMPUTE,
-                    ty: BindingType::Buffer {
-                        ty: BufferBindingType::Storage { read_only: false },
-                        has_dynamic_offset: false,
-                        min_binding_size: None,
-                    },
-                    count: None,
-                },
-            ],
-        });
-        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("terrain_triangle_pipeline_layout"),
-            bind_group_layouts: &[&bind_group_layout],
-            push_constant_ranges: &[],
-        });
-        let shader_module =
-            device.create_shader_module(&include_wgsl!("shaders/generate_triangle.wgsl"));
-        let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
-            label: Some("terrain_triangle_compute_pipeline"),
-            entry_point: "main",
-            module: &shader_module,
-            layout: Some(&pipeline_layout),
-        });
-
-        self.generate_triangle_pipeline = Some(pipeline);
+    fn queue_chunks(&self, keys: &[ChunkCacheKey], idle: bool) {
+        self.queue_parked();
+        let failures = self.terrain_data.failures.read();
+        let mut cancelled = self.terrain_data.cancelled.write();
+        for key in keys {
+            cancelled.remove(key);
+            if failures.get(key).map_or(true, |x| x.can_retry())
+                && self.terrain_data.scheduler.schedule(key)
+            {
+                let task = TerrainTask::GenerateChunk(*key);
+                if idle {
+                    self.queue.push_idle(task);
+                } else {
+                    self.queue.push(task);
+                }
+                self.condvar.notify_one();
+            }
+        }
     }
 
-    pub fn init_render_pipeline(&mut self, instance: &Instance, target_format: TextureFormat) {
-        let device = instance.device();
-        self.render_bind_group_layout =
-            Some(device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-                label: Some("terrain_render_bind_group_layout"),
-                entries: &[
-                    // world matrix
-                    BindGroupLayoutEntry {
-                        binding: 0,
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                    // view + projection matrix
-                    BindGroupLayoutEntry {
-                        binding: 1,
-                        visibility: ShaderStages::VERTEX,
-                        ty: BindingType::Buffer {
-                            ty: BufferBindingType::Uniform,
-                            has_dynamic_offset: false,
-                            min_binding_size: None,
-                        },
-                        count: None,
-                    },
-                ],
-            }));
-        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
-            label: Some("terrain_render_pipeline_layout"),
-            bind_group_layouts: &[self.render_bind_group_layout.as_ref().unwrap()],
-            push_constant_ranges: &[],
-        });
-        let shader_module = device.create_shader_module(&include_wgsl!("shaders/render.wgsl"));
-        self.render_pipeline = Some(device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("terrain_render_pipeline"),
-            layout: Some(&pipeline_layout),
-            vertex: VertexState {
-                module: &shader_module,
-                entry_point: "main",
-                buffers: &[VertexBufferLayout {
-                    array_stride: size_of::<VertexData>() as u64,
-                    step_mode: VertexStepMode::Vertex,
-                    attributes: &vertex_attr_array![
-                        0 => Float32x4,
-                        1 => Float32x4,
-                    ],
-                }],
-            },
-            primitive: PrimitiveState {
-                // polygon_mode: PolygonMode::Line,
-                cull_mode: Some(Face::Back),
-                ..Default::default()
-            },
-            depth_stencil: Some(DepthStencilState {
-                format: TextureFormat::Depth32Float,
-                depth_write_enabled: true,
-                depth_compare: CompareFunction::Less,
-                stencil: StencilState::default(),
-                bias: DepthBiasState::default(),
-            }),
-            multisample: MultisampleState::default(),
-            fragment: Some(FragmentState {
-                module: &shader_module,
-                entry_point: "main",
-                targets: &[ColorTargetState {
-                    format: target_format,
-                    blend: Some(BlendState::REPLACE),
-                    write_mask: ColorWrites::ALL,
-                }],
-            }),
-        }));
-        self.render_target_format = Some(target_format);
+    // Chunks still pending are dropped when a worker reaches them, unless the
+    // rings request them
+    pub fn cancel_chunks(&self, keys: &[ChunkCacheKey]) {
+        let applied = self.terrain_data.applied_regions.read();
+        let requested = applied
+            .as_ref()
+            .map_or_else(HashSet::new, |x| x.keys.iter().collect::<HashSet<_>>());
+        self.terrain_data.cancelled.write().extend(
+            keys.iter()
+                .filter(|x| !requested.contains(x) && self.terrain_data.scheduler.is_pending(x)),
+        );
     }
 
     #[profiling::function]
-    fn generate_chunk(&self, instance: &Instance, key: &ChunkCacheKey) -> Option<TerrainTask> {
-        let device = instance.device();
-        {
-            let mesh_cache = self.mesh_cache.read();
-            if let Some(mesh) = mesh_cache.get(key) {
-                if mesh.render_bundle().is_none() {
-                    return Some(TerrainTask::GenerateMeshResouces(*key));
-                } else {
-                    return None;
-                }
+    pub fn update_terrain(&self, position: &Point3D<f32, WorldSpace>, regions: &[TerrainRegion]) {
+        let noise = *self.terrain_data.noise.read();
+        let params = *self.terrain_data.params.read();
+        // Levels fall off along z from the unit the camera is in
+        let layer = position.z.floor() as i32;
+        let mut applied = self.terrain_data.applied_regions.write();
+        let previous = match applied.as_ref() {
+            // Nothing changed since the last update
+            Some(x)
+                if x.regions == regions
+                    && x.layer == layer
+                    && x.noise == noise
+                    && x.params == params =>
+            {
+                None
+            }
+            Some(x) => Some((x.regions.as_slice(), Some(x.layer))),
+            None => Some((&[][..], None)),
+        };
+        if let Some((previous, previous_layer)) = previous {
+            if previous != regions || previous_layer != Some(layer) {
+                // The roots are rebuilt under the read lock, nothing else
+                // writes the tree in between, and only swapped in under the
+                // write lock
+                let roots = self.terrain_data.tree.read().updated_roots(
+                    regions,
+                    previous,
+                    layer,
+                    previous_layer,
+                );
+                let replaced = self.terrain_data.tree.write().replace_roots(roots);
+                drop(replaced);
             }
+            // Farthest first, they are queued in reverse. Ties in the task
+            // queue are taken in the order they were pushed in.
+            let focus = *self.terrain_data.focus.read();
+            let priority = |key: &ChunkCacheKey| match focus.as_ref() {
+                Some(focus) => focus.priority(key),
+                None => key.bounds.center().to_f32().distance_to(*position),
+            };
+            let mut keys = self.region_keys(regions);
+            keys.sort_by(|a, b| priority(b).partial_cmp(&priority(a)).unwrap());
+            // Queued first so that the ground has a coarse mesh to fall back
+            // to while the detail generates, starting from the first update
+            // of a world
+            let mut requested = keys.iter().copied().collect::<HashSet<_>>();
+            let mut coarse_keys = keys
+                .iter()
+                .filter_map(|x| ancestor_keys(x).pop())
+                .filter(|x| requested.insert(*x))
+                .collect::<Vec<_>>();
+            keys.append(&mut coarse_keys);
+            self.terrain_data.cancel_stale(
+                applied.as_ref().map_or(&[][..], |x| x.keys.as_slice()),
+                &keys,
+            );
+            *applied = Some(AppliedRegions {
+                regions: regions.to_vec(),
+                layer,
+                noise,
+                params,
+                keys,
+            });
         }
+        let keys = &applied.as_ref().unwrap().keys;
+        self.terrain_data.update_last_accessed(keys);
+        self.terrain_data.pin_rendered();
+        self.terrain_data.release_mesh_resources(keys);
+        self.terrain_data.enforce_gpu_budget(keys);
+        self.terrain_data.enforce_triangle_budget(position);
+        let failures = self.terrain_data.failures.read();
+        for key in keys
+            .iter()
+            .rev()
+            .filter(|x| failures.get(x).map_or(true, |x| x.can_retry()))
+            .filter(|x| self.terrain_data.scheduler.schedule(x))
         {
-            let chunk_cache = self.chunk_cache.read();
-            let chunk = chunk_cache.get(key);
-            if let Some(chunk) = chunk {
-                if chunk.triangle_buffer().is_none() {
-                    return Some(TerrainTask::RegenerateTriangle(*key));
-                }
-                return Some(TerrainTask::GenerateMesh(*key));
-            }
+            self.queue.push(TerrainTask::GenerateChunk(*key));
+            self.condvar.notify_one();
         }
-        let mut chunk = Chunk::new(key.bounds, key.level, size3(32, 32, 1 << (key.level - 2)));
-        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-        chunk.generate_voxel(
-            instance,
-            &mut encoder,
-            self.generate_voxel_pipeline.as_ref().unwrap(),
-            true,
-        );
+    }
 
-        chunk.generate_triangle(
-            instance,
-            &mut encoder,
-            self.generate_triangle_pipeline.as_ref().unwrap(),
-            true,
-            *self.isolevel.read(),
-        );
-        instance.queue().submit(std::iter::once(encoder.finish()));
-        Some(TerrainTask::WriteChunk(*key, chunk))
+    // Chunks parked while the GPU was busy go back to the global queue as its
+    // submissions finish
+    fn queue_parked(&self) {
+        for task in self.terrain_data.batch.unpark() {
+            self.queue.push(task);
+            self.condvar.notify_one();
+        }
     }
 
     #[profiling::function]
-    fn write_chunk(&self, key: &ChunkCacheKey, chunk: Chunk) -> Option<TerrainTask> {
-        loop {
-            let chunk_cache = self.chunk_cache.try_write();
-            if chunk_cache.is_none() {
-                continue;
+    pub fn render<'a>(&'a self, regions: &[Region], frame: usize) -> Vec<TerrainRenderBundle> {
+        // Chunk passes recorded since the last frame
+        if let Some(instance) = self.instance.as_ref() {
+            self.terrain_data.batch.flush(instance);
+        }
+        self.terrain_data.retired_meshes.advance();
+        self.terrain_data.retired_resources.advance();
+        self.queue_parked();
+        let bundles = self.terrain_data.render(regions, frame);
+        if !self.terrain_data.mesh_style.read().skirts {
+            for (key, neighbors) in self.terrain_data.changed_stitches() {
+                self.queue.push(TerrainTask::StitchMesh(key, neighbors));
+                self.condvar.notify_one();
             }
-            chunk_cache.unwrap().insert(key, chunk);
-            break;
         }
-        Some(TerrainTask::GenerateMesh(*key))
+        bundles
     }
 
     #[profiling::function]
-    fn generate_mesh(&self, key: &ChunkCacheKey) -> Option<TerrainTask> {
-        {
-            let mesh_cache = self.mesh_cache.read();
-            if let Some(mesh) = mesh_cache.get(key) {
-                if mesh.render_bundle().is_none() {
-                    return Some(TerrainTask::GenerateMeshResouces(*key));
-                } else {
-                    return None;
-                }
-            }
+    pub fn tree(&self) -> RwLockReadGuard<Tree> {
+        self.terrain_data.tree.read()
+    }
+
+    #[profiling::function]
+    pub fn mesh_cache(&self) -> RwLockReadGuard<Cache<ChunkCacheKey, ChunkMesh>> {
+        self.terrain_data.mesh_cache().read()
+    }
+
+    #[profiling::function]
+    pub fn stats(&self) -> TerrainStats {
+        let mut stats = TerrainStats {
+            pending_chunks: self.terrain_data.scheduler.pending_count(),
+            queued_tasks: self.queue.len(),
+            average_latency: self.terrain_data.scheduler.average_latency(),
+            gpu_submissions: self.terrain_data.batch.in_flight(),
+            parked_chunks: self.terrain_data.batch.parked_count(),
+            retired_meshes: self.terrain_data.retired_meshes.pending()
+                + self.terrain_data.retired_resources.pending(),
+            ..Default::default()
+        };
+        let mesh_cache = self.terrain_data.mesh_cache().read();
+        for (key, mesh) in mesh_cache.iter() {
+            *stats.chunks_per_level.entry(key.level).or_default() += 1;
+            stats.resident_triangles += mesh.triangle_count();
+            stats.gpu_bytes += mesh.gpu_bytes();
         }
-        let chunk_cache = self.chunk_cache.try_write();
-        if chunk_cache.is_none() {
-            return Some(TerrainTask::GenerateMesh(*key));
+        stats.mesh_cache_bytes = mesh_cache.total_weight();
+        let chunk_cache = self.terrain_data.chunk_cache.read();
+        for (_, chunk) in chunk_cache.iter() {
+            stats.gpu_bytes += chunk.gpu_bytes();
         }
-        let mut chunk_cache = chunk_cache.unwrap();
-        let chunk = chunk_cache.get_mut(key);
-        if chunk.is_none() || chunk.as_ref().unwrap().triangle_buffer().is_none() {
-            return Some(TerrainTask::GenerateChunk(*key));
-        };
-        let chunk = chunk.unwrap();
-
-        chunk.map_triangle_buffer();
-        let triangles = chunk.get_mapped_triangle_buffer();
-        let mut mesh = Mesh::from_triangles(triangles);
-        mesh.calculate_normals();
-        chunk.unmap_triangle_buffer();
+        stats.chunk_cache_bytes = chunk_cache.total_weight();
+        stats
+    }
 
-        chunk.map_voxel_buffer();
-        let edge_voxel =
-            EdgeVoxel::from_voxels(&chunk.get_mapped_voxel_buffer(), chunk.voxel_count());
-        chunk.unmap_voxel_buffer();
+    // Counters of the chunk and mesh caches and their entries in eviction
+    // order
+    pub fn chunk_cache_stats(&self) -> (CacheStats, Vec<CacheEntry<ChunkCacheKey>>) {
+        let chunk_cache = self.terrain_data.chunk_cache.read();
+        (chunk_cache.stats(), chunk_cache.entries())
+    }
 
-        let mesh = ChunkMesh::new(key.bounds, mesh, chunk.voxel_count(), edge_voxel);
-        Some(TerrainTask::WriteMesh(*key, mesh))
+    pub fn mesh_cache_stats(&self) -> (CacheStats, Vec<CacheEntry<ChunkCacheKey>>) {
+        let mesh_cache = self.terrain_data.mesh_cache().read();
+        (mesh_cache.stats(), mesh_cache.entries())
     }
 
-    #[profiling::function]
-    fn write_mesh(&self, key: &ChunkCacheKey, mesh: ChunkMesh) -> Option<TerrainTask> {
-        loop {
-            let mesh_cache = self.mesh_cache.try_write();
-            if mesh_cache.is_none() {
-                continue;
-            }
-            mesh_cache.unwrap().insert(key, mesh);
-            break;
-        }
-        Some(TerrainTask::GenerateMeshResouces(*key))
+    pub fn is_failed(&self, key: &ChunkCacheKey) -> bool {
+        self.terrain_data.failures.read().contains_key(key)
     }
 
-    #[profiling::function]
-    fn generate_mesh_resources(
-        &self,
-        instance: &Instance,
-        camera_uniform_buffer: &Buffer,
-        key: &ChunkCacheKey,
-    ) -> Option<TerrainTask> {
-        let render_pipeline = self.render_pipeline.as_ref().unwrap();
-        let render_bind_group_layout = self.render_bind_group_layout.as_ref().unwrap();
-        let mesh_cache = self.mesh_cache.try_write();
-        if mesh_cache.is_none() {
-            return Some(TerrainTask::GenerateMeshResouces(*key));
-        }
-        let mut mesh_cache = mesh_cache.unwrap();
-        if let Some(mesh) = mesh_cache.get_mut(key) {
-            mesh.create_render_resources(
-                instance,
-                render_pipeline,
-                render_bind_group_layout,
-                camera_uniform_buffer,
-                self.render_target_format.unwrap(),
-            );
-            None
+    // Takes the cache locks, do not call while holding a cache guard
+    pub fn chunk_state(&self, key: &ChunkCacheKey) -> ChunkState {
+        if let Some(failure) = self.terrain_data.failures.read().get(key) {
+            ChunkState::Failed(failure.clone())
+        } else if self.terrain_data.mesh_cache().read().get(key).is_some() {
+            ChunkState::Meshed
+        } else if self.terrain_data.chunk_cache.read().get(key).is_some() {
+            ChunkState::Generated
         } else {
-            Some(TerrainTask::GenerateMesh(*key))
+            ChunkState::Pending
         }
     }
 
-    #[profiling::function]
-    fn update_last_accessed(&self, keys: &[ChunkCacheKey]) {
-        let mut mesh_cache = self.mesh_cache.write();
-        for key in keys {
-            mesh_cache.update_last_accessed(key);
+    // Largest geometric error of the cached meshes by level
+    pub fn geometric_errors(&self) -> HashMap<u32, f32> {
+        let mut errors = HashMap::new();
+        for (key, mesh) in self.terrain_data.mesh_cache().read().iter() {
+            if let Some(error) = mesh.geometric_error() {
+                let max = errors.entry(key.level).or_insert(0.0f32);
+                *max = max.max(error);
+            }
         }
+        errors
     }
 
+    // XY borders of the chunks rendered last frame traced over their own
+    // surface, with how each side is stitched to its neighbors
     #[profiling::function]
-    fn render<'a>(&'a self, regions: &[Region]) -> Vec<TerrainRenderBundle> {
-        let mut bundles = vec![];
-        let mesh_cache = self.mesh_cache.read();
-        let tree = self.tree.read();
-        let mut stack = vec![];
-        for node in tree.root_nodes() {
-            if regions.iter().any(|x| node.intersects_region(x)) {
-                stack.push(node);
-            }
-        }
-        while let Some(node) = stack.pop() {
-            if node.sub_nodes().is_none() {
-                let bounds = node.bounds();
-                let level = node.level();
-                let key = ChunkCacheKey { bounds, level };
-                if let Some(mesh) = mesh_cache.get(&key) {
-                    if mesh.render_bundle().is_some() {
-                        bundles.push(TerrainRenderBundle {
-                            key,
-                            guard: self.mesh_cache.read(),
-                        })
-                    }
-                }
-            } else {
-                let mut sub_nodes_intersect = vec![];
-                for sub_node in node.sub_nodes().unwrap() {
-                    if regions.iter().any(|x| sub_node.intersects_region(x)) {
-                        sub_nodes_intersect.push(sub_node);
-                    }
-                }
-                // If not all sub node is renderable, render the parent
-                if sub_nodes_intersect.iter().all(|x| x.sub_nodes().is_none()) {
-                    if sub_nodes_intersect.iter().any(|x| {
-                        let bounds = x.bounds();
-                        let level = x.level();
-                        let key = ChunkCacheKey { bounds, level };
-                        if let Some(mesh) = mesh_cache.get(&key) {
-                            mesh.render_bundle().is_none()
+    pub fn chunk_outlines(&self) -> Vec<ChunkOutline> {
+        let statuses = {
+            let rendered_keys = self.terrain_data.rendered_keys.read();
+            let mesh_cache = self.terrain_data.mesh_cache().read();
+            let skirts = self.terrain_data.mesh_style.read().skirts;
+            rendered_keys
+                .iter()
+                .filter_map(|key| {
+                    let stride = mesh_cache.get(key)?.stride();
+                    let statuses = Side::ALL.map(|side| {
+                        let finer = rendered_keys.iter().any(|x| {
+                            x.level > key.level
+                                && transition::adjacent_side(&key.bounds, &x.bounds) == Some(side)
+                        });
+                        if !finer {
+                            StitchStatus::Matched
+                        } else if skirts {
+                            StitchStatus::Skirted
+                        } else if stride.get(side) > 1 {
+                            StitchStatus::Stitched
                         } else {
-                            true
-                        }
-                    }) {
-                        let bounds = node.bounds();
-                        let level = node.level();
-                        let key = ChunkCacheKey { bounds, level };
-                        if let Some(mesh) = mesh_cache.get(&key) {
-                            if mesh.render_bundle().is_some() {
-                                bundles.push(TerrainRenderBundle {
-                                    key,
-                                    guard: self.mesh_cache.read(),
-                                })
-                            }
+                            StitchStatus::Pending
                         }
-                    } else {
-                        stack.append(&mut sub_nodes_intersect);
+                    });
+                    Some((*key, statuses))
+                })
+                .collect::<Vec<_>>()
+        };
+        let isolevel = *self.terrain_data.isolevel.read();
+        let chunk_cache = self.terrain_data.chunk_cache.read();
+        statuses
+            .into_iter()
+            .map(|(key, statuses)| {
+                let chunk = chunk_cache.get(&key);
+                let bounds = key.bounds.to_f32();
+                let (min, max) = (bounds.min.xy(), bounds.max.xy());
+                let sides = Side::ALL
+                    .iter()
+                    .zip(statuses)
+                    .map(|(side, status)| {
+                        let (start, end) = match side {
+                            Side::MinX => (min, point2(min.x, max.y)),
+                            Side::MaxX => (point2(max.x, min.y), max),
+                            Side::MinY => (min, point2(max.x, min.y)),
+                            Side::MaxY => (point2(min.x, max.y), max),
+                        };
+                        let points = (0..=OUTLINE_SAMPLES)
+                            .map(|i| {
+                                let point = start.lerp(end, i as f32 / OUTLINE_SAMPLES as f32);
+                                let height = chunk?.surface_height(&point, isolevel)?;
+                                Some(point.extend(height))
+                            })
+                            .collect();
+                        OutlineSide { status, points }
+                    })
+                    .collect();
+                ChunkOutline { key, sides }
+            })
+            .collect()
+    }
+
+    // Compares the borders of every pair of rendered meshes next to each
+    // other. Stitched seams and the ones between meshes of the same level
+    // should close, the meshes drawn from the GPU have no CPU copy to test.
+    #[profiling::function]
+    pub fn find_cracks(&self) -> Vec<Crack> {
+        let mesh_cache = self.terrain_data.mesh_cache().read();
+        let rendered_keys = self.terrain_data.rendered_keys.read();
+        let style = *self.terrain_data.mesh_style.read();
+        let mut cracks = vec![];
+        for (i, a) in rendered_keys.iter().enumerate() {
+            for b in &rendered_keys[i + 1..] {
+                let side = match transition::adjacent_side(&a.bounds, &b.bounds) {
+                    Some(side) => side,
+                    None => continue,
+                };
+                let (mesh_a, mesh_b) = match (mesh_cache.get(a), mesh_cache.get(b)) {
+                    (Some(mesh_a), Some(mesh_b))
+                        if !mesh_a.is_gpu_resident() && !mesh_b.is_gpu_resident() =>
+                    {
+                        (mesh_a, mesh_b)
                     }
-                } else {
-                    stack.append(&mut sub_nodes_intersect);
+                    _ => continue,
+                };
+                let borders = [
+                    mesh_a.border_edges(side, style),
+                    mesh_b.border_edges(side.opposite(), style),
+                ];
+                if let Some(crack) = crack::measure_crack(
+                    [*a, *b],
+                    [&borders[0], &borders[1]],
+                    mesh_a.cell_size().min(mesh_b.cell_size()),
+                ) {
+                    cracks.push(crack);
                 }
             }
         }
-        bundles
-    }
-
-    #[profiling::function]
-    fn set_isolevel(&self, isolevel: f32) {
-        *self.isolevel.write() = isolevel;
+        cracks
     }
 
+    // Only meshes that were rendered last frame are tested so that the hit
+    // matches what is on screen
     #[profiling::function]
-    fn regenerate_triangle(&self, instance: &Instance, key: &ChunkCacheKey) -> Option<TerrainTask> {
-        loop {
-            let chunk_cache = self.chunk_cache.try_write();
-            if chunk_cache.is_none() {
-                continue;
-            }
-            if let Some(chunk) = chunk_cache.unwrap().get_mut(key) {
-                let device = instance.device();
-                let mut encoder =
-                    device.create_command_encoder(&CommandEncoderDescriptor { label: None });
-                chunk.generate_triangle(
-                    instance,
-                    &mut encoder,
-                    self.generate_triangle_pipeline.as_ref().unwrap(),
-                    true,
-                    *self.isolevel.read(),
-                );
-                instance.queue().submit(std::iter::once(encoder.finish()));
-                return Some(TerrainTask::GenerateMesh(*key));
+    pub fn raycast(
+        &self,
+        origin: &Point3D<f32, WorldSpace>,
+        direction: &Vector3D<f32, WorldSpace>,
+        max_distance: f32,
+    ) -> Option<RaycastHit> {
+        let mesh_cache = self.terrain_data.mesh_cache().read();
+        let rendered_keys = self.terrain_data.rendered_keys.read();
+        let mut closest: Option<RaycastHit> = None;
+        for key in rendered_keys.iter() {
+            if let Some(mesh) = mesh_cache.get(key) {
+                let max_distance = closest.map_or(max_distance, |x| x.distance);
+                if let Some((distance, normal)) = mesh.raycast(origin, direction, max_distance) {
+                    closest = Some(RaycastHit {
+                        position: *origin + *direction * distance,
+                        normal,
+                        distance,
+                        key: *key,
+                    });
+                }
             }
-            break;
         }
-        None
+        closest
     }
 
+    // Closest point on the rendered meshes within the distance, the hit distance
+    // is measured from the query point
     #[profiling::function]
-    fn invalidate_triangle(&self) -> Option<TerrainTask> {
-        loop {
-            let chunk_cache = self.chunk_cache.try_write();
-            if chunk_cache.is_none() {
-                continue;
-            }
-            for chunk in chunk_cache.unwrap().values_mut() {
-                chunk.clear_triangle_buffer();
-            }
-            loop {
-                let mesh_cache = self.mesh_cache.try_write();
-                if mesh_cache.is_none() {
-                    continue;
+    pub fn closest_surface(
+        &self,
+        point: &Point3D<f32, WorldSpace>,
+        max_distance: f32,
+    ) -> Option<RaycastHit> {
+        let mesh_cache = self.terrain_data.mesh_cache().read();
+        let rendered_keys = self.terrain_data.rendered_keys.read();
+        let mut closest: Option<RaycastHit> = None;
+        for key in rendered_keys.iter() {
+            if let Some(mesh) = mesh_cache.get(key) {
+                let max_distance = closest.map_or(max_distance, |x| x.distance);
+                if let Some((distance, position, normal)) = mesh.closest_point(point, max_distance)
+                {
+                    closest = Some(RaycastHit {
+                        position,
+                        normal,
+                        distance,
+                        key: *key,
+                    });
                 }
-                mesh_cache.unwrap().clear();
-                break;
             }
-            break;
         }
-        None
+        closest
+    }
+
+    // Sample density from the finest cached chunk containing the point
+    #[profiling::function]
+    pub fn sample_density(&self, point: &Point3D<f32, WorldSpace>) -> Option<f32> {
+        let chunk_cache = self.terrain_data.chunk_cache.read();
+        self.terrain_data
+            .current_chunks(&chunk_cache)
+            .filter(|x| x.bounds().to_f32().contains(*point))
+            .filter_map(|x| x.sample_voxel(point).map(|v| (x.level(), v)))
+            .max_by_key(|(level, _)| *level)
+            .map(|(_, v)| v)
     }
 
+    // Sculpt every cached chunk touched by the edit in one submission, only
+    // the triangles and meshes of those chunks are generated again. Chunks
+    // still waiting for their deltas to be replayed get the brush with them.
     #[profiling::function]
-    fn stitch_mesh(&self, key: &ChunkCacheKey, stride: &StitchStride) -> Option<TerrainTask> {
-        let mesh_cache = self.mesh_cache.read();
-        if let Some(mesh) = mesh_cache.get(key) {
-            if mesh.render_bundle().is_some() {
-                mesh.stitch_edges(stride.min_x, stride.max_x, stride.min_y, stride.max_y);
+    pub fn sculpt(&self, edit: &TerrainEdit) {
+        let instance = self.instance.as_ref().unwrap();
+        let pipelines = self.terrain_data.pipelines();
+        let mut encoder = instance
+            .device()
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let mut keys = vec![];
+        let mut pending_keys = vec![];
+        {
+            let params = *self.terrain_data.params.read();
+            let mut chunk_cache = self.terrain_data.chunk_cache.write();
+            let mut deltas = self.terrain_data.deltas.write();
+            for (key, chunk) in chunk_cache.iter_mut().filter(|(x, _)| x.params == params) {
+                if chunk.applied_deltas() < deltas.count(key) {
+                    if chunk.bounds().to_f32().intersects(&edit.bounds()) {
+                        deltas.record(key, ChunkDelta::Sculpt(*edit));
+                        pending_keys.push(*key);
+                    }
+                } else if chunk.sculpt(instance, &mut encoder, &pipelines.sculpt, edit) {
+                    chunk.set_applied_deltas(deltas.record(key, ChunkDelta::Sculpt(*edit)));
+                    keys.push(*key);
+                }
+            }
+        }
+        self.terrain_data.batch.submit_after(instance, encoder);
+        for key in pending_keys.iter().chain(&keys) {
+            self.terrain_data.drop_inactive_meshes(key);
+        }
+        let mut mesh_cache = self.terrain_data.mesh_cache().write();
+        for key in keys {
+            if let Some(mesh) = mesh_cache.remove(&key) {
+                self.terrain_data.retired_meshes.push(mesh);
             }
+            self.queue.push(TerrainTask::RegenerateTriangle(key));
+            self.condvar.notify_one();
         }
-        None
     }
-}
+
+    // Conforms the terrain to an authored mesh. Like the voxel parameters it
+    // starts every chunk over, so set pieces are meant to be placed before
+    // the terrain is edited around them. Returns the id to remove it with.
+    pub fn add_set_piece(&self, set_piece: SetPiece) -> u64 {
+        self.place(ChunkDelta::SetPiece(Arc::new(set_piece)))
+    }
+
+    // Unlike start_edit the edit is part of every chunk it touches on every
+    // level, including the ones generated later, for shapes the level design
+    // relies on. It starts every chunk over like a set piece.
+    pub fn place_edit(&self, operation: EditOperation) -> u64 {
+        self.place(ChunkDelta::Edit(Arc::new(operation)))
+    }
+
+    fn place(&self, delta: ChunkDelta) -> u64 {
+        let id = self.terrain_data.deltas.write().place(delta);
+        self.update_params(true);
+        id
+    }
+
+    // Removes a set piece or a placed edit
+    pub fn remove_placed(&self, id: u64) {
+        if self.terrain_data.deltas.write().remove_placed(id) {
+            self.update_params(true);
+        }
+    }
+
+    // Run an edit too large for the brush on the workers, one task per cached
+    // chunk it touches. Like the brush, chunks that are not cached are not
+    // edited, neither are the chunks of other generation parameters.
+    pub fn start_edit(&self, operation: EditOperation) -> Arc<EditJob> {
+        let bounds = operation.bounds();
+        let params = *self.terrain_data.params.read();
+        let keys = self
+            .terrain_data
+            .chunk_cache
+            .read()
+            .iter()
+            .filter(|(key, chunk)| {
+                let chunk_bounds = chunk.bounds().to_f32();
+                key.params == params
+                    && Box2D::new(chunk_bounds.min.xy(), chunk_bounds.max.xy()).intersects(&bounds)
+            })
+            .map(|(key, _)| *key)
+            .collect::<Vec<_>>();
+        let job = Arc::new(EditJob::new(operation, keys.len()));
+        for key in keys {
+            self.queue.push(TerrainTask::ApplyEdit(job.clone(), key));
+            self.condvar.notify_one();
+        }
+        job
+    }
+
+    // Height of the topmost surface from the finest cached chunk above the point
+    #[profiling::function]
+    pub fn height_at(&self, point: &Point2D<f32, WorldSpace>) -> Option<f32> {
+        let isolevel = *self.terrain_data.isolevel.read();
+        let chunk_cache = self.terrain_data.chunk_cache.read();
+        self.terrain_data
+            .current_chunks(&chunk_cache)
+            .filter(|x| {
+                let bounds = x.bounds().to_f32();
+                Box2D::new(bounds.min.xy(), bounds.max.xy()).contains(*point)
+            })
+            .filter_map(|x| x.surface_height(point, isolevel).map(|h| (x.level(), h)))
+            // Chunks stacked along z share their level, the topmost surface
+            // of them wins
+            .max_by(|(a, a_height), (b, b_height)| {
+                a.cmp(b).then(
+                    a_height
+                        .partial_cmp(b_height)
+                        .unwrap_or(std::cmp::Ordering::Equal),
+                )
+            })
+            .map(|(_, h)| h)
+    }
+
+    // Coarse queries that do not read the voxels of the chunks, none where no
+    // chunk has been meshed yet
+    pub fn is_occupied(&self, point: &Point3D<f32, WorldSpace>) -> Option<bool> {
+        self.terrain_data.occupancy.read().is_solid(point)
+    }
+
+    pub fn occupied_top(&self, point: &Point2D<f32, WorldSpace>) -> Option<f32> {
+        self.terrain_data.occupancy.read().top(point)
+    }
+
+    pub fn line_of_sight(
+        &self,
+        from: &Point3D<f32, WorldSpace>,
+        to: &Point3D<f32, WorldSpace>,
+    ) -> Option<bool> {
+        self.terrain_data.occupancy.read().line_of_sight(from, to)
+    }
+
+    // Biome weights from the finest cached chunk above the point
+    pub fn biome_at(&self, point: &Point2D<f32, WorldSpace>) -> Option<[f32; BIOME_COUNT]> {
+        let chunk_cache = self.terrain_data.chunk_cache.read();
+        self.terrain_data
+            .current_chunks(&chunk_cache)
+            .filter(|x| {
+                let bounds = x.bounds().to_f32();
+                Box2D::new(bounds.min.xy(), bounds.max.xy()).contains(*point)
+            })
+            .filter_map(|x| {
+                x.sample_biome(point)
+                    .map(|w| ((x.level(), x.bounds().max.z), w))
+            })
+            // Ties between chunks stacked along z go to the top one so that
+            // the order of the cache does not matter
+            .max_by_key(|(order, _)| *order)
+            .map(|(_, w)| w)
+    }
+
+    // Rasterizes the cached chunks over the rectangle, texels are sampled at
+    // their centers
+    #[profiling::function]
+    pub fn export_image(
+        &self,
+        bounds: &Box2D<f32, WorldSpace>,
+        size: Size2D<u32, UnknownUnit>,
+        source: ExportSource,
+    ) -> TerrainImage {
+        let step_x = bounds.width() / size.width as f32;
+        let step_y = bounds.height() / size.height as f32;
+        let mut values = Vec::with_capacity(size.area() as usize);
+        for row in 0..size.height {
+            for column in 0..size.width {
+                let point = point2(
+                    bounds.min.x + (column as f32 + 0.5) * step_x,
+                    bounds.max.y - (row as f32 + 0.5) * step_y,
+                );
+                values.push(match source {
+                    ExportSource::SurfaceHeight => self.height_at(&point),
+                    ExportSource::Density(z) => self.sample_density(&point.extend(z)),
+                });
+            }
+        }
+        TerrainImage::new(size, &values, source)
+    }
+
+    // Keep a copy of the chunk mesh so that the next time it is generated the
+    // added and removed triangles can be shown
+    pub fn select_diff_chunk(&self, key: Option<ChunkCacheKey>) {
+        *self.terrain_data.diff_selection.write() = key.map(|key| {
+            let current = self
+                .terrain_data
+                .mesh_cache()
+                .read()
+                .get(&key)
+                .map(|x| x.world_triangles());
+            DiffSelection::new(key, current)
+        });
+    }
+
+    pub fn chunk_diff(&self) -> Option<(ChunkCacheKey, Option<ChunkDiff>)> {
+        self.terrain_data
+            .diff_selection
+            .read()
+            .as_ref()
+            .map(|x| (*x.key(), x.diff().cloned()))
+    }
+
+    // Surface metadata of a chunk for navigation and placement, only available
+    // once its mesh has been generated
+    pub fn surface_metadata(&self, key: &ChunkCacheKey) -> Option<SurfaceMetadata> {
+        self.terrain_data
+            .mesh_cache()
+            .read()
+            .get(key)
+            .map(|x| x.surface().clone())
+    }
+
+    // How enclosed the air at a point of a meshed chunk is, from 0 under the
+    // open sky to CAVE_ENCLOSURE inside caves
+    pub fn enclosure_at(
+        &self,
+        key: &ChunkCacheKey,
+        point: &Point3D<f32, WorldSpace>,
+    ) -> Option<f32> {
+        self.terrain_data
+            .mesh_cache()
+            .read()
+            .get(key)
+            .and_then(|x| x.enclosure_at(point))
+    }
+
+    // Enclosure from the finest meshed chunk around the point
+    pub fn enclosure_around(&self, point: &Point3D<f32, WorldSpace>) -> Option<f32> {
+        let params = *self.terrain_data.params.read();
+        self.terrain_data
+            .mesh_cache()
+            .read()
+            .iter()
+            .filter(|(key, _)| key.params == params && key.bounds.to_f32().contains(*point))
+            .max_by_key(|(key, _)| key.level)
+            .and_then(|(_, x)| x.enclosure_at(point))
+    }
+
+    // Re-run the triangle pass for the rendered chunks into preview buffers and
+    // draw them directly, nothing is read back so this is cheap enough to call
+    // every frame while the isolevel is being changed
+    #[profiling::function]
+    pub fn preview_isolevel(&self, isolevel: f32) {
+        let instance = self.instance.as_ref().unwrap();
+        let camera_buffers = self.camera_buffers.as_ref().unwrap();
+        let terrain_data = &self.terrain_data;
+        let pipelines = terrain_data.pipelines();
+        let meshing = *terrain_data.meshing.read();
+        let rendered_keys = terrain_data.rendered_keys.read().clone();
+        let chunk_cache = terrain_data.chunk_cache.read();
+        let mut preview = terrain_data.preview.write();
+        let mut encoder = instance
+            .device()
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        for key in rendered_keys {
+            if let Some(chunk) = chunk_cache.get(&key) {
+                if chunk.voxel_buffer().is_none() {
+                    continue;
+                }
+                let preview_chunk = preview.entry(key).or_insert_with(|| {
+                    PreviewChunk::new(
+                        instance,
+                        chunk,
+                        &pipelines.preview,
+                        &pipelines.draw_args,
+                        camera_buffers,
+                    )
+                });
+                preview_chunk.update(
+                    instance,
+                    &mut encoder,
+                    chunk,
+                    pipelines.generate_triangle(meshing),
+                    &pipelines.draw_args,
+                    isolevel,
+                );
+            }
+        }
+        self.terrain_data.batch.submit_after(instance, encoder);
+    }
+
+    // Simulates the params on a coarse grid around their center so they can
+    // be tuned without generating the chunks again, cheap enough to call every
+    // frame as nothing is submitted until the params change
+    #[profiling::function]
+    pub fn update_preview_world(&self, params: &PreviewWorldParams) {
+        let instance = self.instance.as_ref().unwrap();
+        let terrain_data = &self.terrain_data;
+        terrain_data.preview_world.write().update(
+            instance,
+            &terrain_data.pipelines(),
+            params,
+            *terrain_data.seed.read(),
+            *terrain_data.noise.read(),
+        );
+    }
+
+    // Last finished preview, it lags behind the params while simulating
+    pub fn preview_world(&self) -> Option<PreviewWorld> {
+        self.terrain_data.preview_world.read().world().cloned()
+    }
+
+    // Swap in a new set of pipelines, resources built from the previous set are
+    // released and recreated lazily the next time their chunk is requested.
+    // The current set is kept if the new one fails to build.
+    pub fn rebuild_pipelines(
+        &self,
+        target_format: TextureFormat,
+        sample_count: u32,
+    ) -> Result<(), String> {
+        let pipelines = self.terrain_data.pipelines();
+        self.swap_pipelines(
+            target_format,
+            sample_count,
+            pipelines.overlay,
+            pipelines.stylized,
+            pipelines.generator.clone(),
+        )
+    }
+
+    pub fn overlay(&self) -> TerrainOverlay {
+        self.terrain_data.pipelines().overlay
+    }
+
+    // The overlay is picked when the render pipeline is built
+    pub fn set_overlay(&self, overlay: TerrainOverlay) -> Result<(), String> {
+        let pipelines = self.terrain_data.pipelines();
+        if pipelines.overlay == overlay {
+            return Ok(());
+        }
+        self.swap_pipelines(
+            pipelines.target_format,
+            pipelines.sample_count,
+            overlay,
+            pipelines.stylized,
+            pipelines.generator.clone(),
+        )
+    }
+
+    // Like the overlay the shading is picked when the render pipeline is built
+    pub fn set_stylized(&self, stylized: bool) -> Result<(), String> {
+        let pipelines = self.terrain_data.pipelines();
+        if pipelines.stylized == stylized {
+            return Ok(());
+        }
+        self.swap_pipelines(
+            pipelines.target_format,
+            pipelines.sample_count,
+            pipelines.overlay,
+            stylized,
+            pipelines.generator.clone(),
+        )
+    }
+
+    // Lights past MAX_POINT_LIGHTS are dropped, the lights are kept until the
+    // next call or until the pipelines are rebuilt
+    pub fn set_wind(&self, wind: &WindData) {
+        let instance = self.instance.as_ref().unwrap();
+        instance.queue().write_buffer(
+            &self.terrain_data.pipelines().wind_buffer,
+            0,
+            bytemuck::bytes_of(wind),
+        );
+    }
+
+    // Like the wind it is written again every frame, the rebuilt pipelines
+    // start without magma
+    pub fn set_magma(&self, magma: &MagmaSettings) {
+        let instance = self.instance.as_ref().unwrap();
+        instance.queue().write_buffer(
+            &self.terrain_data.pipelines().magma_buffer,
+            0,
+            bytemuck::bytes_of(&MagmaData::new(magma)),
+        );
+    }
+
+    pub fn set_point_lights(&self, lights: &[PointLightData]) {
+        let instance = self.instance.as_ref().unwrap();
+        instance.queue().write_buffer(
+            &self.terrain_data.pipelines().point_light_buffer,
+            0,
+            bytemuck::bytes_of(&PointLightsData::new(lights)),
+        );
+    }
+
+    pub fn meshing_algorithm(&self) -> MeshingAlgorithm {
+        *self.terrain_data.meshing.read()
+    }
+
+    // Meshes are cached per algorithm so switching back to one shows its
+    // meshes right away, the voxels are kept and only the triangles of the
+    // chunks meshed next are generated again
+    pub fn set_meshing_algorithm(&self, meshing: MeshingAlgorithm) {
+        let previous = self.meshing_algorithm();
+        if previous == meshing {
+            return;
+        }
+        *self.terrain_data.meshing.write() = meshing;
+        let mut mesh_cache = self.terrain_data.mesh_cache_of(previous).write();
+        for mesh in mesh_cache.values_mut() {
+            self.terrain_data.retire_render_resources(mesh);
+        }
+        mesh_cache.update_weights();
+        drop(mesh_cache);
+        self.terrain_data.stitches.write().clear();
+        self.terrain_data.stitched_keys.write().clear();
+        self.clear_preview();
+    }
+
+    // The chunks of the new density are generated with the edits of the old
+    // one. If the generator does not compile the error is returned and the
+    // current one is kept.
+    pub fn set_generator(&self, generator: Arc<dyn TerrainGenerator>) -> Result<(), String> {
+        let pipelines = self.terrain_data.pipelines();
+        self.swap_pipelines(
+            pipelines.target_format,
+            pipelines.sample_count,
+            pipelines.overlay,
+            pipelines.stylized,
+            generator,
+        )?;
+        self.update_params(true);
+        Ok(())
+    }
+
+    fn swap_pipelines(
+        &self,
+        target_format: TextureFormat,
+        sample_count: u32,
+        overlay: TerrainOverlay,
+        stylized: bool,
+        generator: Arc<dyn TerrainGenerator>,
+    ) -> Result<(), String> {
+        let instance = self.instance.as_ref().unwrap();
+        let device = instance.device();
+        device.push_error_scope(ErrorFilter::Validation);
+        let pipelines = TerrainPipelines::new(
+            instance,
+            target_format,
+            sample_count,
+            overlay,
+            stylized,
+            generator,
+        );
+        if let Some(error) = block_on(device.pop_error_scope()) {
+            return Err(error.to_string());
+        }
+        self.terrain_data.set_pipelines(pipelines);
+        self.clear_preview();
+        self.terrain_data.release_render_resources();
+        Ok(())
+    }
+
+    pub fn skirts(&self) -> bool {
+        self.terrain_data.mesh_style.read().skirts
+    }
+
+    // Skirts hide the cracks between levels instead of stitching, the render
+    // resources are built again with or without them the next time their
+    // chunk is requested
+    pub fn set_skirts(&self, skirts: bool) {
+        self.terrain_data.mesh_style.write().skirts = skirts;
+        self.terrain_data.stitches.write().clear();
+        self.terrain_data.stitched_keys.write().clear();
+        self.terrain_data.release_render_resources();
+    }
+
+    pub fn flat_shading(&self) -> bool {
+        self.terrain_data.mesh_style.read().flat
+    }
+
+    // Faceted low poly look, only the vertex buffers are built again so the
+    // meshes and the stitches are kept
+    pub fn set_flat_shading(&self, flat: bool) {
+        self.terrain_data.mesh_style.write().flat = flat;
+        self.terrain_data.release_render_resources();
+    }
+
+    pub fn gpu_meshes(&self) -> bool {
+        *self.terrain_data.gpu_meshes.read()
+    }
+
+    // The triangles of every chunk are generated again for the new path the
+    // next time their chunk is requested
+    pub fn set_gpu_meshes(&self, gpu_meshes: bool) {
+        *self.terrain_data.gpu_meshes.write() = gpu_meshes;
+        for chunk in self.terrain_data.chunk_cache.write().values_mut() {
+            chunk.clear_triangle_buffer();
+        }
+        for mesh_cache in &self.terrain_data.mesh_caches {
+            for mesh in mesh_cache.write().drain() {
+                self.terrain_data.retired_meshes.push(mesh);
+            }
+        }
+    }
+
+    pub fn clear_preview(&self) {
+        self.terrain_data.preview.write().clear();
+    }
+
+    pub fn set_isolevel(&self, isolevel: f32) {
+        self.terrain_data.set_isolevel(isolevel);
+        self.update_params(true);
+    }
+
+    pub fn params(&self) -> u64 {
+        *self.terrain_data.params.read()
+    }
+
+    // Like the noise algorithm, the parameters are part of the keys so the
+    // chunks of the old ones age out of the caches instead of being cleared.
+    // Chunks of the new parameters that are already cached get the carried
+    // edits replayed.
+    fn update_params(&self, carry_deltas: bool) {
+        for key in self.terrain_data.update_params(carry_deltas) {
+            self.terrain_data.drop_inactive_meshes(&key);
+            self.queue.push(TerrainTask::ReplayDeltas(key));
+            self.condvar.notify_one();
+        }
+        self.clear_preview();
+    }
+
+    pub fn noise(&self) -> NoiseAlgorithm {
+        *self.terrain_data.noise.read()
+    }
+
+    // Chunks are cached per noise algorithm so the new keys are generated
+    // while the old ones age out of the caches
+    pub fn set_noise(&self, noise: NoiseAlgorithm) {
+        *self.terrain_data.noise.write() = noise;
+        self.terrain_data.occupancy.write().clear();
+        self.clear_preview();
+    }
+
+    // Edits belong to the world of their seed and are not carried, they come
+    // back with the seed
+    pub fn set_seed(&self, seed: u32) {
+        *self.terrain_data.seed.write() = seed;
+        self.update_params(false);
+    }
+
+    pub fn set_domain_warp(&self, domain_warp: DomainWarp) {
+        *self.terrain_data.domain_warp.write() = domain_warp;
+        self.update_params(true);
+    }
+
+    pub fn set_caves(&self, caves: CaveSettings) {
+        *self.terrain_data.caves.write() = caves;
+        self.update_params(true);
+    }
+
+    pub fn set_erosion(&self, erosion: ErosionSettings) {
+        *self.terrain_data.erosion.write() = erosion;
+        self.update_params(true);
+    }
+
+    // Chunks already in the memory cache are not written to disk
+    pub fn set_disk_cache(&self, enabled: bool) {
+        *self.terrain_data.disk_cache.write() = if enabled {
+            Some(DiskCache::new(Path::new(DISK_CACHE_PATH)))
+        } else {
+            None
+        };
+    }
+
+    pub fn set_cache_sizes(&self, chunk_cache_mib: usize, mesh_cache_mib: usize) {
+        self.terrain_data
+            .chunk_cache
+            .write()
+            .set_max_weight(chunk_cache_mib as u64 * BYTES_PER_MIB);
+        self.terrain_data.spill_evicted();
+        for mesh_cache in &self.terrain_data.mesh_caches {
+            mesh_cache
+                .write()
+                .set_max_weight(mesh_cache_mib as u64 * BYTES_PER_MIB);
+        }
+    }
+
+    // Chunks evicted from the memory cache are written to disk and read back
+    // when they are requested again instead of being generated again.
+    // Enabling it starts from an empty spill.
+    pub fn set_chunk_spill(&self, enabled: bool) {
+        let mut chunk_spill = self.terrain_data.chunk_spill.write();
+        // The previous spill removes its directory when it is dropped, so it
+        // goes before the new one creates it again
+        *chunk_spill = None;
+        if enabled {
+            *chunk_spill = Some(ChunkSpill::new(&chunk_spill_directory()));
+        }
+    }
+
+    pub fn set_gpu_budget(&self, budget_mib: usize) {
+        *self.terrain_data.gpu_budget.write() = budget_mib as u64 * BYTES_PER_MIB;
+    }
+
+    pub fn set_triangle_budget(&self, budget: usize) {
+        *self.terrain_data.triangle_budget.write() = budget;
+    }
+
+    pub fn set_max_gpu_submissions(&self, max_gpu_submissions: usize) {
+        self.terrain_data
+            .batch
+            .set_max_in_flight(max_gpu_submissions);
+    }
+}
+
+// What a worker thread runs with, handed over to the thread that replaces it
+// when one of its tasks panics
+struct TerrainWorker {
+    index: usize,
+    local: Worker<TerrainTask>,
+    stealers: Vec<Stealer<TerrainTask>>,
+    global: Arc<TaskQueue>,
+    guard: Arc<Mutex<bool>>,
+    condvar: Arc<Condvar>,
+    terrain_data: Arc<TerrainData>,
+    instance: Arc<Instance>,
+    camera_buffers: Arc<Vec<Buffer>>,
+}
+
+impl TerrainWorker {
+    // The handle of a replacement is dropped, the workers are never joined
+    fn spawn(self) -> JoinHandle<()> {
+        std::thread::spawn(move || {
+            profiling::register_thread!();
+            if self.run() {
+                // The queue of the worker and the chains it parked carry
+                // over, the rest of the thread that panicked is left behind
+                log::error!("Restarting terrain worker {}", self.index);
+                self.spawn();
+            }
+        })
+    }
+
+    // Returns true once a task panicked, false once the terrain is dropped
+    fn run(&self) -> bool {
+        loop {
+            loop {
+                // Handed back by the mappings that finished since
+                for task in self.terrain_data.mappings.take_ready() {
+                    self.local.push(task);
+                }
+                // The local queue only holds the chains waiting on the GPU,
+                // they are polled once the global one is empty so that they
+                // never hold up the other chunks. The global one is taken a
+                // task at a time so that its order holds.
+                let task = self.global.pop().or_else(|| self.local.pop()).or_else(|| {
+                    // Otherwise, we need to look for a task elsewhere.
+                    std::iter::repeat_with(|| {
+                        // Try stealing a task from one of the other threads.
+                        self.stealers
+                            .iter()
+                            .map(|s| s.steal())
+                            .collect::<Steal<_>>()
+                    })
+                    // Loop while no task was stolen and any steal operation needs to be retried.
+                    .find(|s| !s.is_retry())
+                    // Extract the stolen task, if there is one.
+                    .and_then(|s| s.success())
+                });
+                if task.is_none() {
+                    break;
+                }
+                // The key is scheduled again once the chain ends, which a
+                // chain waiting on a mapping does when it resumes
+                let scheduled_key = match &task {
+                    Some(TerrainTask::GenerateChunk(key))
+                    | Some(TerrainTask::AwaitMapping(key))
+                    | Some(TerrainTask::AwaitSubmission(key))
+                    | Some(TerrainTask::AwaitCapacity(key)) => Some(*key),
+                    _ => None,
+                };
+                let mut awaiting = false;
+                let mut panicked = false;
+                let mut next_task = task.filter(|x| self.terrain_data.graph.enter(x));
+                let mut chain_audit = ChainAudit::new();
+                while let Some(t) = next_task {
+                    if let TerrainTask::GenerateChunk(key) | TerrainTask::AwaitCapacity(key) = &t {
+                        if self.terrain_data.take_cancelled(key) {
+                            for task in self.terrain_data.graph.advance(key, task_stage(&t), None) {
+                                self.global.push(task);
+                            }
+                            break;
+                        }
+                    }
+                    chain_audit.step(&t);
+                    self.terrain_data.task_audit.begin(&t);
+                    // A failing or panicking task only loses its own chunk, a
+                    // panicking one also gets the worker replaced once the
+                    // chain is wound up
+                    let key = t.key();
+                    let stage = task_stage(&t);
+                    next_task = match panic::catch_unwind(AssertUnwindSafe(|| {
+                        self.terrain_data
+                            .run_task(&self.instance, &self.camera_buffers, t)
+                    })) {
+                        Ok(Ok(next_task)) => next_task,
+                        Ok(Err(error)) => {
+                            self.terrain_data.fail_chunk(&key, error);
+                            None
+                        }
+                        Err(payload) => {
+                            log::error!("Terrain worker {} panicked", self.index);
+                            panicked = true;
+                            let reason = panic_reason(payload.as_ref());
+                            self.terrain_data
+                                .fail_chunk(&key, TerrainError::Panicked(reason));
+                            None
+                        }
+                    };
+                    self.terrain_data.task_audit.end(next_task.as_ref());
+                    // Stitches waiting on the chunk are queued once its chain
+                    // ends
+                    for task in self
+                        .terrain_data
+                        .graph
+                        .advance(&key, stage, next_task.as_ref())
+                    {
+                        self.global.push(task);
+                    }
+                    // Other chunks are worked on while the GPU maps, the chain
+                    // is handed back once it is done
+                    if let Some(TerrainTask::AwaitMapping(_)) = next_task {
+                        self.terrain_data.mappings.park(next_task.take().unwrap());
+                        awaiting = true;
+                    }
+                    if let Some(TerrainTask::AwaitSubmission(_)) = next_task {
+                        self.local.push(next_task.take().unwrap());
+                        awaiting = true;
+                    }
+                    // Queued again by the render once submissions finish
+                    if let Some(TerrainTask::AwaitCapacity(_)) = next_task {
+                        self.terrain_data.batch.park(next_task.take().unwrap());
+                        awaiting = true;
+                    }
+                }
+                if let Some(key) = scheduled_key.filter(|_| !awaiting) {
+                    self.terrain_data.scheduler.finish(&key);
+                    self.terrain_data.cancelled.write().remove(&key);
+                }
+                if panicked {
+                    return true;
+                }
+            }
+            let mut done = self.guard.lock().unwrap();
+            // Checked under the lock the mappings wake the workers with, so
+            // that none finishing in between is missed
+            if !self.terrain_data.mappings.has_ready() {
+                self.terrain_data
+                    .task_audit
+                    .before_wait(self.index, !self.global.is_empty());
+                done = self.condvar.wait(done).unwrap();
+                self.terrain_data
+                    .task_audit
+                    .after_wait(self.index, *done, !self.global.is_empty());
+            }
+            if *done {
+                break;
+            }
+        }
+        false
+    }
+}
+
+struct TerrainData {
+    tree: RwLock<Tree>,
+    applied_regions: RwLock<Option<AppliedRegions>>,
+    isolevel: RwLock<f32>,
+    noise: RwLock<NoiseAlgorithm>,
+    meshing: RwLock<MeshingAlgorithm>,
+    mesh_style: RwLock<MeshStyle>,
+    // Draw the triangles of chunks that are not stitched without reading them
+    // back
+    gpu_meshes: RwLock<bool>,
+    seed: RwLock<u32>,
+    domain_warp: RwLock<DomainWarp>,
+    caves: RwLock<CaveSettings>,
+    erosion: RwLock<ErosionSettings>,
+    // Part of every key, the chunks of other parameters stay cached so that
+    // switching back to them does not generate them again
+    params: RwLock<u64>,
+    disk_cache: RwLock<Option<DiskCache>>,
+    chunk_spill: RwLock<Option<ChunkSpill>>,
+    // Chunks whose task panicked or whose buffers could not be mapped, they
+    // are requested again after a backoff until a mesh is written
+    failures: RwLock<HashMap<ChunkCacheKey, ChunkFailure>>,
+    // Edits of every chunk, replayed when a chunk is generated again
+    deltas: RwLock<EditDeltas>,
+    events: TerrainEvents,
+    task_audit: TaskAudit,
+    scheduler: ChunkScheduler,
+    // Queued keys that left the requested regions, their GenerateChunk is
+    // dropped by the worker before any GPU work
+    cancelled: RwLock<HashSet<ChunkCacheKey>>,
+    batch: SubmissionBatch,
+    mappings: Arc<MappingWaiters>,
+    graph: TaskGraph,
+    // The workers block on the cache locks, they are handed over fairly so
+    // neither the render loop nor the writers starve
+    chunk_cache: RwLock<Cache<ChunkCacheKey, Chunk>>,
+    // Handed over by the chunk cache as it evicts them, drained into the
+    // spill once the cache lock is released
+    evicted_chunks: Arc<parking_lot::Mutex<Vec<(ChunkCacheKey, Chunk)>>>,
+    // One for each meshing algorithm, in the order of MESHING_ALGORITHMS
+    mesh_caches: Vec<RwLock<Cache<ChunkCacheKey, ChunkMesh>>>,
+    // Evicted, removed and replaced meshes, the render bundles of the frames
+    // in flight may still draw their buffers
+    retired_meshes: Arc<DeletionQueue<ChunkMesh>>,
+    // Released from the meshes that stay cached
+    retired_resources: DeletionQueue<RenderResources>,
+    rendered_keys: RwLock<Vec<ChunkCacheKey>>,
+    // Written as the chunks of the current parameters are meshed, cleared
+    // when they change
+    occupancy: RwLock<Occupancy>,
+    // View of the camera, meshes outside of it get no render resources
+    focus: RwLock<Option<TaskFocus>>,
+    // Bytes the buffers of the cached chunks and meshes may take
+    gpu_budget: RwLock<u64>,
+    // Triangles the cached meshes may hold, zero is unlimited
+    triangle_budget: RwLock<usize>,
+    // Finer neighbors each mesh was last stitched to and the render set they
+    // were found in
+    stitches: RwLock<HashMap<ChunkCacheKey, HashSet<ChunkCacheKey>>>,
+    stitched_keys: RwLock<Vec<ChunkCacheKey>>,
+    preview: RwLock<HashMap<ChunkCacheKey, PreviewChunk>>,
+    preview_world: RwLock<PreviewWorldSimulation>,
+    diff_selection: RwLock<Option<DiffSelection>>,
+    pipelines: RwLock<Option<Arc<TerrainPipelines>>>,
+    mesh_arenas: RwLock<Option<Arc<MeshArenas>>>,
+}
+
+impl TerrainData {
+    fn new(
+        chunk_cache_mib: usize,
+        mesh_cache_mib: usize,
+        gpu_budget_mib: usize,
+        triangle_budget: usize,
+        max_gpu_submissions: usize,
+        disk_cache: bool,
+    ) -> Self {
+        let evicted_chunks = Arc::new(parking_lot::Mutex::new(vec![]));
+        let retired_meshes = Arc::new(DeletionQueue::new());
+        Self {
+            chunk_cache: RwLock::new(
+                Cache::new(chunk_cache_mib as u64 * BYTES_PER_MIB, Chunk::cache_bytes)
+                    .with_on_evict({
+                        let evicted_chunks = evicted_chunks.clone();
+                        move |key, chunk| evicted_chunks.lock().push((key, chunk))
+                    }),
+            ),
+            evicted_chunks,
+            mesh_caches: MESHING_ALGORITHMS
+                .iter()
+                .map(|_| {
+                    let retired_meshes = retired_meshes.clone();
+                    RwLock::new(
+                        Cache::new(
+                            mesh_cache_mib as u64 * BYTES_PER_MIB,
+                            ChunkMesh::cache_bytes,
+                        )
+                        .with_on_evict(move |_, mesh| retired_meshes.push(mesh)),
+                    )
+                })
+                .collect(),
+            retired_meshes,
+            retired_resources: DeletionQueue::new(),
+            rendered_keys: RwLock::new(vec![]),
+            occupancy: RwLock::new(Occupancy::new()),
+            focus: RwLock::new(None),
+            gpu_budget: RwLock::new(gpu_budget_mib as u64 * BYTES_PER_MIB),
+            triangle_budget: RwLock::new(triangle_budget),
+            stitches: RwLock::new(HashMap::new()),
+            stitched_keys: RwLock::new(vec![]),
+            preview: RwLock::new(HashMap::new()),
+            preview_world: RwLock::new(PreviewWorldSimulation::new()),
+            diff_selection: RwLock::new(None),
+            tree: RwLock::new(Tree::new()),
+            applied_regions: RwLock::new(None),
+            isolevel: RwLock::new(0.5),
+            noise: RwLock::new(NoiseAlgorithm::Perlin),
+            meshing: RwLock::new(MeshingAlgorithm::MarchingCubes),
+            mesh_style: RwLock::new(MeshStyle::default()),
+            gpu_meshes: RwLock::new(false),
+            seed: RwLock::new(0),
+            domain_warp: RwLock::new(DomainWarp::default()),
+            caves: RwLock::new(CaveSettings::default()),
+            erosion: RwLock::new(ErosionSettings::default()),
+            params: RwLock::new(0),
+            disk_cache: RwLock::new(if disk_cache {
+                Some(DiskCache::new(Path::new(DISK_CACHE_PATH)))
+            } else {
+                None
+            }),
+            chunk_spill: RwLock::new(None),
+            failures: RwLock::new(HashMap::new()),
+            deltas: RwLock::new(EditDeltas::new()),
+            events: TerrainEvents::new(),
+            task_audit: TaskAudit::new(),
+            scheduler: ChunkScheduler::new(),
+            cancelled: RwLock::new(HashSet::new()),
+            batch: SubmissionBatch::new(max_gpu_submissions),
+            mappings: Arc::new(MappingWaiters::new()),
+            graph: TaskGraph::new(),
+            pipelines: RwLock::new(None),
+            mesh_arenas: RwLock::new(None),
+        }
+    }
+
+    fn pipelines(&self) -> Arc<TerrainPipelines> {
+        self.pipelines.read().as_ref().unwrap().clone()
+    }
+
+    fn set_pipelines(&self, pipelines: TerrainPipelines) {
+        *self.pipelines.write() = Some(Arc::new(pipelines));
+    }
+
+    fn mesh_arenas(&self) -> Arc<MeshArenas> {
+        self.mesh_arenas.read().as_ref().unwrap().clone()
+    }
+
+    // Hashes the generator, the voxel parameters and the isolevel. Keys made
+    // from then on belong to the new parameters, the edits of the old ones
+    // are copied over when carried. Returns the keys that got edits.
+    fn update_params(&self, carry_deltas: bool) -> Vec<ChunkCacheKey> {
+        let mut hasher = DefaultHasher::new();
+        let pipelines = self.pipelines();
+        // Generators that can not be identified are told apart by instance
+        match pipelines.generator.cache_id() {
+            Some(cache_id) => cache_id.hash(&mut hasher),
+            None => (Arc::as_ptr(&pipelines.generator) as *const () as usize).hash(&mut hasher),
+        }
+        self.hash_voxel_params(&mut hasher);
+        self.isolevel.read().to_bits().hash(&mut hasher);
+        // Not part of the disk cache hash, the set pieces and placed edits
+        // are replayed on top of the stored voxels like the edits
+        self.deltas.read().hash_placed(&mut hasher);
+        let params = hasher.finish();
+        let previous = std::mem::replace(&mut *self.params.write(), params);
+        if previous != params {
+            self.occupancy.write().clear();
+        }
+        if carry_deltas && previous != params {
+            self.deltas.write().carry(previous, params)
+        } else {
+            vec![]
+        }
+    }
+
+    // Cached chunks generated with the current parameters
+    fn current_chunks<'a>(
+        &self,
+        chunk_cache: &'a Cache<ChunkCacheKey, Chunk>,
+    ) -> impl Iterator<Item = &'a Chunk> {
+        let params = *self.params.read();
+        chunk_cache
+            .iter()
+            .filter(move |(key, _)| key.params == params)
+            .map(|(_, chunk)| chunk)
+    }
+
+    fn release_render_resources(&self) {
+        for mesh_cache in &self.mesh_caches {
+            let mut mesh_cache = mesh_cache.write();
+            for mesh in mesh_cache.values_mut() {
+                self.retire_render_resources(mesh);
+            }
+            mesh_cache.update_weights();
+        }
+    }
+
+    // The mesh keeps its CPU data, its buffers and bundles wait out the
+    // frames in flight
+    fn retire_render_resources(&self, mesh: &mut ChunkMesh) {
+        let resources = mesh.release_render_resources();
+        if !resources.is_empty() {
+            self.retired_resources.push(resources);
+        }
+    }
+
+    // Meshes of the algorithm in use
+    fn mesh_cache(&self) -> &RwLock<Cache<ChunkCacheKey, ChunkMesh>> {
+        self.mesh_cache_of(*self.meshing.read())
+    }
+
+    fn mesh_cache_of(&self, meshing: MeshingAlgorithm) -> &RwLock<Cache<ChunkCacheKey, ChunkMesh>> {
+        &self.mesh_caches[meshing as usize]
+    }
+
+    // Meshes of the other algorithms are not generated again until they are
+    // switched to, so they are dropped once the voxels of their chunk change.
+    // Takes the mesh cache locks.
+    fn drop_inactive_meshes(&self, key: &ChunkCacheKey) {
+        let meshing = *self.meshing.read();
+        for (&other, mesh_cache) in MESHING_ALGORITHMS.iter().zip(&self.mesh_caches) {
+            if other != meshing {
+                if let Some(mesh) = mesh_cache.write().remove(key) {
+                    self.retired_meshes.push(mesh);
+                }
+            }
+        }
+    }
+
+    // Chunks stitched to finer neighbors need the CPU mesh for their
+    // transition cells, as do the skirts
+    fn meshes_on_gpu(&self, key: &ChunkCacheKey) -> bool {
+        *self.gpu_meshes.read()
+            && !self.mesh_style.read().skirts
+            && self.stitches.read().get(key).map_or(true, |x| x.is_empty())
+    }
+
+    fn fail_chunk(&self, key: &ChunkCacheKey, error: TerrainError) {
+        log::warn!("Chunk {:?} failed: {}", key, error);
+        let mut failures = self.failures.write();
+        match failures.get_mut(key) {
+            Some(failure) => failure.fail_again(error.clone()),
+            None => {
+                failures.insert(*key, ChunkFailure::new(error.clone()));
+            }
+        }
+        self.events.send(TerrainEvent::Failed(*key, error));
+    }
+
+    fn run_task(
+        &self,
+        instance: &Instance,
+        camera_buffers: &[Buffer],
+        task: TerrainTask,
+    ) -> TaskResult {
+        // The chunks of old parameters are kept but not worked on, their
+        // tasks would run with the current ones. Edits only change the voxels
+        // and finish their job.
+        if task.key().params != *self.params.read() && !matches!(task, TerrainTask::ApplyEdit(..)) {
+            return Ok(None);
+        }
+        match task {
+            TerrainTask::GenerateChunk(key) | TerrainTask::AwaitCapacity(key) => {
+                self.generate_chunk(instance, &key)
+            }
+            TerrainTask::ErodeChunk(key, chunk) => self.erode_chunk(instance, &key, chunk),
+            TerrainTask::WriteChunk(key, chunk) => self.write_chunk(&key, chunk),
+            TerrainTask::GenerateMesh(key) | TerrainTask::AwaitMapping(key) => {
+                self.generate_mesh(instance, &key)
+            }
+            TerrainTask::AwaitSubmission(key) => self.await_submission(instance, &key),
+            TerrainTask::WriteMesh(key, meshing, mesh) => self.write_mesh(&key, meshing, mesh),
+            TerrainTask::GenerateMeshResouces(key) => {
+                self.generate_mesh_resources(instance, camera_buffers, &key)
+            }
+            TerrainTask::RegenerateTriangle(key) => self.regenerate_triangle(instance, &key),
+            TerrainTask::StitchMesh(key, neighbors) => {
+                self.stitch_mesh(instance, camera_buffers, &key, &neighbors)
+            }
+            TerrainTask::ApplyEdit(job, key) => self.apply_edit(instance, &job, &key),
+            TerrainTask::ReplayDeltas(key) => self.replay_deltas(instance, &key),
+        }
+    }
+
+    // Hash of every parameter that changes the generated voxels, none if the
+    // generator can not be identified. Chunks of keys that only differ in the
+    // isolevel share their entry when there is no erosion.
+    fn content_hash(&self, key: &ChunkCacheKey, pipelines: &TerrainPipelines) -> Option<u64> {
+        let mut hasher = DefaultHasher::new();
+        pipelines.generator.cache_id()?.hash(&mut hasher);
+        (key.bounds, key.level, key.noise).hash(&mut hasher);
+        self.hash_voxel_params(&mut hasher);
+        Some(hasher.finish())
+    }
+
+    fn hash_voxel_params(&self, hasher: &mut DefaultHasher) {
+        self.seed.read().hash(hasher);
+        let domain_warp = *self.domain_warp.read();
+        let caves = *self.caves.read();
+        let erosion = *self.erosion.read();
+        let mut values = vec![domain_warp.strength, domain_warp.frequency];
+        if caves.enabled {
+            values.extend_from_slice(&[caves.frequency, caves.radius]);
+        }
+        // Erosion follows the surface at the isolevel
+        if erosion.enabled {
+            values.extend_from_slice(&[
+                *self.isolevel.read(),
+                erosion.rain,
+                erosion.evaporation,
+                erosion.capacity,
+                erosion.erosion,
+                erosion.deposition,
+            ]);
+            erosion.iterations.hash(hasher);
+        }
+        (caves.enabled, erosion.enabled).hash(hasher);
+        for value in values {
+            value.to_bits().hash(hasher);
+        }
+    }
+
+    #[profiling::function]
+    fn generate_chunk(&self, instance: &Instance, key: &ChunkCacheKey) -> TaskResult {
+        let device = instance.device();
+        {
+            let mesh_cache = self.mesh_cache().read();
+            if let Some(mesh) = mesh_cache.get(key) {
+                if !mesh.is_resident() {
+                    return Ok(Some(TerrainTask::GenerateMeshResouces(*key)));
+                } else {
+                    return Ok(None);
+                }
+            }
+        }
+        {
+            let chunk_cache = self.chunk_cache.read();
+            let chunk = chunk_cache.get(key);
+            if let Some(chunk) = chunk {
+                if chunk.triangle_buffer().is_none() {
+                    return Ok(Some(TerrainTask::RegenerateTriangle(*key)));
+                }
+                return Ok(Some(TerrainTask::GenerateMesh(*key)));
+            }
+        }
+        if !self.batch.has_capacity() {
+            return Ok(Some(TerrainTask::AwaitCapacity(*key)));
+        }
+        let mut chunk = Chunk::new(
+            key.bounds,
+            key.level,
+            // The layers of a chunk are every other layer of its children so
+            // that the transition cells of the finer neighbors line up, the
+            // voxels are as far apart along z as along x and y
+            size3(
+                32,
+                32,
+                32 * key.bounds.depth() as u32 / key.bounds.width() as u32 + 1,
+            ),
+            key.noise,
+        );
+        let pipelines = self.pipelines();
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        let content_hash = self
+            .disk_cache
+            .read()
+            .as_ref()
+            .and_then(|_| self.content_hash(key, &pipelines));
+        let voxel_count = chunk.voxel_count();
+        let column_count = (voxel_count.width * voxel_count.height) as usize;
+        // Spilled voxels already have their edits and erosion
+        let spilled = self
+            .chunk_spill
+            .read()
+            .as_ref()
+            .and_then(|x| x.load(key, voxel_count.volume() as usize, column_count));
+        let cached = match spilled {
+            Some((cached, applied_deltas)) => {
+                chunk.set_applied_deltas(applied_deltas);
+                Some(cached)
+            }
+            None => content_hash.and_then(|hash| {
+                self.disk_cache.read().as_ref()?.load(
+                    hash,
+                    voxel_count.volume() as usize,
+                    column_count,
+                )
+            }),
+        };
+        if let Some((voxels, water_levels, biome_weights)) = cached {
+            chunk.upload_voxel(
+                instance,
+                &mut encoder,
+                &voxels,
+                &water_levels,
+                &biome_weights,
+            );
+        } else {
+            chunk.generate_voxel(
+                instance,
+                &mut encoder,
+                &pipelines.generate_voxel,
+                *self.seed.read(),
+                &self.domain_warp.read(),
+                &self.caves.read(),
+                true,
+            );
+            chunk.set_content_hash(content_hash);
+            if self.erosion.read().enabled {
+                self.batch.push(instance, encoder);
+                return Ok(Some(TerrainTask::ErodeChunk(*key, chunk)));
+            }
+        }
+
+        let meshing = *self.meshing.read();
+        chunk.generate_triangle(
+            instance,
+            &mut encoder,
+            pipelines.generate_triangle(meshing),
+            meshing,
+            !self.meshes_on_gpu(key),
+            *self.isolevel.read(),
+        );
+        chunk.set_submission(self.batch.push(instance, encoder));
+        Ok(Some(TerrainTask::WriteChunk(*key, chunk)))
+    }
+
+    #[profiling::function]
+    fn erode_chunk(
+        &self,
+        instance: &Instance,
+        key: &ChunkCacheKey,
+        mut chunk: Chunk,
+    ) -> TaskResult {
+        let device = instance.device();
+        let pipelines = self.pipelines();
+        let isolevel = *self.isolevel.read();
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        chunk.erode(
+            instance,
+            &mut encoder,
+            &pipelines.erosion,
+            &self.erosion.read(),
+            isolevel,
+        );
+        let meshing = *self.meshing.read();
+        chunk.generate_triangle(
+            instance,
+            &mut encoder,
+            pipelines.generate_triangle(meshing),
+            meshing,
+            !self.meshes_on_gpu(key),
+            isolevel,
+        );
+        chunk.set_submission(self.batch.push(instance, encoder));
+        Ok(Some(TerrainTask::WriteChunk(*key, chunk)))
+    }
+
+    #[profiling::function]
+    fn write_chunk(&self, key: &ChunkCacheKey, chunk: Chunk) -> TaskResult {
+        self.chunk_cache.write().insert(key, chunk);
+        self.spill_evicted();
+        Ok(Some(TerrainTask::GenerateMesh(*key)))
+    }
+
+    // Writes the chunks the cache evicted to the spill outside of the cache
+    // lock, called right after every write that can evict. Chunks of other
+    // parameters, and the ones evicted before their voxels were read back,
+    // are generated again instead. All of them are dropped with their
+    // buffers here, right away when the spill is disabled.
+    fn spill_evicted(&self) {
+        let evicted = std::mem::take(&mut *self.evicted_chunks.lock());
+        let chunk_spill = self.chunk_spill.read();
+        let chunk_spill = match chunk_spill.as_ref() {
+            Some(chunk_spill) => chunk_spill,
+            None => return,
+        };
+        let params = *self.params.read();
+        for (key, chunk) in evicted.iter().filter(|(key, _)| key.params == params) {
+            if let (Some(voxels), Some(water_levels), Some(biome_weights)) =
+                (chunk.voxels(), chunk.water_levels(), chunk.biome_weights())
+            {
+                chunk_spill.store(
+                    key,
+                    voxels,
+                    water_levels,
+                    biome_weights,
+                    chunk.applied_deltas(),
+                );
+            }
+        }
+    }
+
+    // Workers only poll the chains waiting on the GPU once the global queue
+    // is empty, with no more passes coming to fill the batch it is submitted
+    // right away instead of on the next frame. The headless callers do not
+    // render at all.
+    fn await_submission(&self, instance: &Instance, key: &ChunkCacheKey) -> TaskResult {
+        self.batch.flush(instance);
+        Ok(Some(TerrainTask::GenerateMesh(*key)))
+    }
+
+    #[profiling::function]
+    fn generate_mesh(&self, instance: &Instance, key: &ChunkCacheKey) -> TaskResult {
+        {
+            let mesh_cache = self.mesh_cache().read();
+            if let Some(mesh) = mesh_cache.get(key) {
+                if !mesh.is_resident() {
+                    return Ok(Some(TerrainTask::GenerateMeshResouces(*key)));
+                } else {
+                    return Ok(None);
+                }
+            }
+        }
+        // Polled under the read lock, the write lock is only taken once the
+        // buffers are ready to be read back
+        let meshing = *self.meshing.read();
+        {
+            let chunk_cache = self.chunk_cache.read();
+            let chunk = chunk_cache.get(key);
+            if chunk.is_none() || chunk.unwrap().triangle_buffer().is_none() {
+                return Ok(Some(TerrainTask::GenerateChunk(*key)));
+            }
+            let chunk = chunk.unwrap();
+            // Triangles left from before the meshing algorithm was switched
+            if chunk.triangle_meshing() != Some(meshing) {
+                return Ok(Some(TerrainTask::RegenerateTriangle(*key)));
+            }
+            // The staging buffers are written by a pass still waiting in the
+            // batch or are still being mapped
+            if !self.batch.is_submitted(chunk.submission()) {
+                return Ok(Some(TerrainTask::AwaitSubmission(*key)));
+            }
+            if chunk.is_mapping() {
+                return Ok(Some(TerrainTask::AwaitMapping(*key)));
+            }
+        }
+        let (mesh, gpu_triangles, content_hash, replay) = {
+            let mut chunk_cache = self.chunk_cache.write();
+            let chunk = chunk_cache
+                .get_mut(key)
+                .ok_or(TerrainError::MissingChunk(*key))?;
+            // Triangles generated for the GPU are drawn from their buffer as is
+            let staged = chunk.has_staged_triangles();
+            // Workers move on to other tasks while the buffers are mapped, the
+            // chunk is generated again on the retry when mapping fails
+            let mappings = self.mappings.clone();
+            let mapped_key = *key;
+            match chunk.map_staging_buffers(instance, &self.pipelines().weld, move || {
+                mappings.mapped(&mapped_key)
+            }) {
+                MapStatus::Pending => return Ok(Some(TerrainTask::AwaitMapping(*key))),
+                MapStatus::Failed => {
+                    chunk_cache.remove(key);
+                    return Err(TerrainError::MappingFailed);
+                }
+                MapStatus::Mapped => {}
+            }
+            // Normals are written on the GPU with the render resources
+            let mesh = if staged {
+                let mesh = chunk.get_mapped_mesh();
+                chunk.unmap_mesh_buffers(instance);
+                mesh
+            } else {
+                Mesh::from_indexed(vec![], vec![], vec![])
+            };
+
+            let voxels = chunk.get_mapped_voxel_buffer();
+            chunk.unmap_voxel_buffer(instance);
+
+            let water_levels = chunk.get_mapped_water_buffer();
+            chunk.unmap_water_buffer(instance);
+
+            let biome_weights = chunk.get_mapped_biome_buffer();
+            chunk.unmap_biome_buffer(instance);
+            chunk.set_voxels(voxels);
+            chunk.set_water_levels(water_levels);
+            chunk.set_biome_weights(biome_weights);
+            let content_hash = chunk.take_content_hash();
+            // The stored voxels are the generated ones, the edits are applied
+            // on top of them before meshing
+            let replay = chunk.applied_deltas() < self.deltas.read().count(key);
+            let gpu_triangles = if staged || replay {
+                None
+            } else {
+                let triangle_count = chunk.triangle_count();
+                let bytes = chunk.triangle_buffer_bytes();
+                let triangles = chunk
+                    .take_triangle_buffer()
+                    .ok_or(TerrainError::MissingTriangles)?;
+                Some((triangles, triangle_count, bytes))
+            };
+            (mesh, gpu_triangles, content_hash, replay)
+        };
+
+        // The rest only reads the chunk, other workers and the render are not
+        // held up while it runs
+        let chunk_cache = self.chunk_cache.read();
+        let chunk = chunk_cache
+            .get(key)
+            .ok_or(TerrainError::MissingChunk(*key))?;
+        let voxels = chunk.voxels().ok_or(TerrainError::MissingVoxels)?;
+        let water_levels = chunk.water_levels().ok_or(TerrainError::MissingVoxels)?;
+        let biome_weights = chunk
+            .biome_weights()
+            .ok_or(TerrainError::MissingVoxels)?
+            .to_vec();
+        if let Some(hash) = content_hash {
+            if let Some(disk_cache) = self.disk_cache.read().as_ref() {
+                disk_cache.store(hash, voxels, water_levels, &biome_weights);
+            }
+        }
+        if replay {
+            return Ok(Some(TerrainTask::ReplayDeltas(*key)));
+        }
+        let isolevel = *self.isolevel.read();
+        let voxel_count = chunk.voxel_count();
+        let edge_voxel = EdgeVoxel::from_voxels(voxels, voxel_count);
+        let water = chunk.water_surface(isolevel);
+        let enclosure = Enclosure::from_voxels(voxels, voxel_count, isolevel);
+        if key.params == *self.params.read() && key.noise == *self.noise.read() {
+            if let Some(patch) = OccupancyPatch::from_chunk(chunk, isolevel) {
+                self.occupancy.write().write(&patch);
+            }
+        }
+
+        // The voxels of the parent are only read back once it is meshed so
+        // the error is unknown for chunks meshed before their parent
+        let geometric_error = tree::parent_bounds(&key.bounds, key.level)
+            .and_then(|bounds| {
+                chunk_cache.get(&ChunkCacheKey {
+                    bounds,
+                    level: key.level - 1,
+                    noise: key.noise,
+                    params: key.params,
+                })
+            })
+            .and_then(|parent| chunk.surface_deviation(parent, isolevel));
+        let mut mesh = ChunkMesh::new(
+            key.bounds,
+            mesh,
+            voxel_count,
+            edge_voxel,
+            water,
+            biome_weights,
+            enclosure,
+        );
+        mesh.set_geometric_error(geometric_error);
+        if let Some((triangles, triangle_count, bytes)) = gpu_triangles {
+            mesh.set_gpu_triangles(triangles, triangle_count, bytes);
+        }
+        Ok(Some(TerrainTask::WriteMesh(*key, meshing, mesh)))
+    }
+
+    #[profiling::function]
+    fn write_mesh(
+        &self,
+        key: &ChunkCacheKey,
+        meshing: MeshingAlgorithm,
+        mesh: ChunkMesh,
+    ) -> TaskResult {
+        // Meshed before the algorithm was switched, kept for switching back
+        if meshing != *self.meshing.read() {
+            if let Some(previous) = self.mesh_cache_of(meshing).write().insert(key, mesh) {
+                self.retired_meshes.push(previous);
+            }
+            return Ok(None);
+        }
+        if let Some(selection) = self.diff_selection.write().as_mut() {
+            if selection.key() == key {
+                selection.update(mesh.world_triangles());
+            }
+        }
+        // Replaced when the chunk is meshed again
+        if let Some(previous) = self.mesh_cache_of(meshing).write().insert(key, mesh) {
+            self.retired_meshes.push(previous);
+        }
+        // The new mesh is not stitched and the transition cells of its
+        // coarser neighbors were built from the voxels of the old one
+        {
+            let mut stitches = self.stitches.write();
+            stitches.remove(key);
+            stitches.retain(|_, neighbors| !neighbors.contains(key));
+            self.stitched_keys.write().clear();
+        }
+        self.failures.write().remove(key);
+        self.events.send(TerrainEvent::Meshed(*key));
+        Ok(Some(TerrainTask::GenerateMeshResouces(*key)))
+    }
+
+    #[profiling::function]
+    fn generate_mesh_resources(
+        &self,
+        instance: &Instance,
+        camera_buffers: &[Buffer],
+        key: &ChunkCacheKey,
+    ) -> TaskResult {
+        // The mesh stays on the CPU until it comes into view, the key is
+        // requested again on every update of the terrain and its
+        // GenerateChunk comes back here once it is
+        let focus = *self.focus.read();
+        if !focus.map_or(true, |x| x.in_view(&key.bounds.to_f32())) {
+            return Ok(None);
+        }
+        // Read under the lock that swap_pipelines releases the resources
+        // under, so that resources built with old pipelines are released too
+        let mut mesh_cache = self.mesh_cache().write();
+        let pipelines = self.pipelines();
+        if let Some(mesh) = mesh_cache.get_mut(key) {
+            mesh.create_render_resources(
+                instance,
+                &pipelines,
+                &self.mesh_arenas(),
+                camera_buffers,
+                *self.mesh_style.read(),
+            );
+            mesh_cache.update_weight(key);
+            Ok(None)
+        } else {
+            Ok(Some(TerrainTask::GenerateMesh(*key)))
+        }
+    }
+
+    // Keys requested again are no longer cancelled, chains already past their
+    // GenerateChunk run to the end
+    fn cancel_stale(&self, previous: &[ChunkCacheKey], keys: &[ChunkCacheKey]) {
+        let requested = keys.iter().collect::<HashSet<_>>();
+        let mut cancelled = self.cancelled.write();
+        cancelled.retain(|x| !requested.contains(&x));
+        cancelled.extend(
+            previous
+                .iter()
+                .filter(|x| !requested.contains(x) && self.scheduler.is_pending(x)),
+        );
+    }
+
+    fn take_cancelled(&self, key: &ChunkCacheKey) -> bool {
+        let cancelled = self.cancelled.write().remove(key);
+        if cancelled {
+            self.scheduler.cancel(key);
+        }
+        cancelled
+    }
+
+    // The meshes drawn in the last frame are not evicted when the workers
+    // insert new ones, until the next update pins the ones drawn since
+    fn pin_rendered(&self) {
+        let rendered_keys = self.rendered_keys.read().clone();
+        self.mesh_cache().write().set_pinned(rendered_keys);
+    }
+
+    // Evicts the least recently used chunks and meshes until their buffers fit
+    // in the budget. The requested and rendered keys and the chunks being
+    // generated are kept even if that goes over the budget.
+    #[profiling::function]
+    fn enforce_gpu_budget(&self, keys: &[ChunkCacheKey]) {
+        let budget = *self.gpu_budget.read();
+        let mut keep = keys.iter().copied().collect::<HashSet<_>>();
+        keep.extend(self.rendered_keys.read().iter().copied());
+        let can_evict =
+            |key: &ChunkCacheKey| !keep.contains(key) && !self.scheduler.is_pending(key);
+        let mut total = 0;
+        // Cache of each candidate, None for the chunk cache
+        let mut candidates = vec![];
+        {
+            let chunk_cache = self.chunk_cache.read();
+            for (key, chunk) in chunk_cache.iter() {
+                total += chunk.gpu_bytes();
+                if can_evict(key) {
+                    let accessed = chunk_cache.last_accessed(key);
+                    candidates.push((accessed, None, *key, chunk.gpu_bytes()));
+                }
+            }
+        }
+        for (i, mesh_cache) in self.mesh_caches.iter().enumerate() {
+            let mesh_cache = mesh_cache.read();
+            for (key, mesh) in mesh_cache.iter() {
+                total += mesh.gpu_bytes();
+                if can_evict(key) {
+                    let accessed = mesh_cache.last_accessed(key);
+                    candidates.push((accessed, Some(i), *key, mesh.gpu_bytes()));
+                }
+            }
+        }
+        if total <= budget {
+            return;
+        }
+        candidates.sort_by_key(|(accessed, ..)| *accessed);
+        for (_, cache, key, bytes) in candidates {
+            if total <= budget {
+                break;
+            }
+            // Only the locks of one cache are held at a time, entries that were
+            // removed in the meantime are skipped
+            let removed = match cache {
+                // Spilled like the chunks the cache evicts itself, remove skips
+                // on_evict
+                None => match self.chunk_cache.write().remove(&key) {
+                    Some(chunk) => {
+                        self.evicted_chunks.lock().push((key, chunk));
+                        true
+                    }
+                    None => false,
+                },
+                Some(i) => match self.mesh_caches[i].write().remove(&key) {
+                    Some(mesh) => {
+                        self.retired_meshes.push(mesh);
+                        true
+                    }
+                    None => false,
+                },
+            };
+            if removed {
+                total = total.saturating_sub(bytes);
+            }
+        }
+        self.spill_evicted();
+    }
+
+    // Only meshes that are not rendered are evicted, the finest first and the
+    // farthest of a level first. The LOD rings are coarsened alongside so
+    // that the rendered meshes fit too.
+    #[profiling::function]
+    fn enforce_triangle_budget(&self, position: &Point3D<f32, WorldSpace>) {
+        let budget = *self.triangle_budget.read();
+        if budget == 0 {
+            return;
+        }
+        let rendered_keys = self
+            .rendered_keys
+            .read()
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        let mut total = 0;
+        let mut candidates = vec![];
+        for (i, mesh_cache) in self.mesh_caches.iter().enumerate() {
+            for (key, mesh) in mesh_cache.read().iter() {
+                total += mesh.triangle_count();
+                if !rendered_keys.contains(key) && !self.scheduler.is_pending(key) {
+                    let distance = key.bounds.center().to_f32().distance_to(*position);
+                    candidates.push((key.level, distance, i, *key, mesh.triangle_count()));
+                }
+            }
+        }
+        if total <= budget {
+            return;
+        }
+        candidates.sort_by(|a, b| {
+            b.0.cmp(&a.0)
+                .then(b.1.partial_cmp(&a.1).unwrap_or(std::cmp::Ordering::Equal))
+        });
+        for (_, _, i, key, triangles) in candidates {
+            if total <= budget {
+                break;
+            }
+            if let Some(mesh) = self.mesh_caches[i].write().remove(&key) {
+                self.retired_meshes.push(mesh);
+                total = total.saturating_sub(triangles);
+            }
+        }
+    }
+
+    #[profiling::function]
+    fn update_last_accessed(&self, keys: &[ChunkCacheKey]) {
+        let mut mesh_cache = self.mesh_cache().write();
+        for key in keys {
+            mesh_cache.update_last_accessed(key);
+        }
+    }
+
+    // Meshes outside of the requested keys and the last render set only keep
+    // their CPU data. Ancestors of requested keys that are not resident yet
+    // are kept since render falls back to them.
+    #[profiling::function]
+    fn release_mesh_resources(&self, keys: &[ChunkCacheKey]) {
+        let mut keep = self
+            .rendered_keys
+            .read()
+            .iter()
+            .copied()
+            .collect::<HashSet<_>>();
+        let mut mesh_cache = self.mesh_cache().write();
+        for key in keys {
+            keep.insert(*key);
+            if !mesh_cache.get(key).map_or(false, |x| x.is_resident()) {
+                // Up to the first resident ancestor and the coarsest one,
+                // which is always requested
+                for ancestor in ancestor_keys(key) {
+                    keep.insert(ancestor);
+                    if mesh_cache.get(&ancestor).map_or(false, |x| x.is_resident()) {
+                        break;
+                    }
+                }
+            }
+        }
+        // A worker may have finished building resources with pipelines that
+        // were swapped out in the meantime
+        let generation = self.pipelines().generation;
+        let mut released = vec![];
+        for (key, mesh) in mesh_cache.iter_mut() {
+            if mesh.is_resident()
+                && (!keep.contains(key) || mesh.pipeline_generation() != Some(generation))
+            {
+                self.retire_render_resources(mesh);
+                released.push(*key);
+            }
+        }
+        for key in &released {
+            mesh_cache.update_weight(key);
+        }
+    }
+
+    // Keys to draw for the node inside the regions, returns false if part of
+    // it has nothing to draw. A node whose children do not cover it is drawn
+    // with its own mesh instead when it is resident, so the coarser meshes up
+    // to the root fill in while the detail is generated. The meshes of a
+    // subtree and the one it falls back to are never drawn together, they
+    // would overlap.
+    fn collect_render_keys(
+        &self,
+        node: &tree::Node,
+        regions: &[Region],
+        noise: NoiseAlgorithm,
+        params: u64,
+        drawable: &dyn Fn(&ChunkCacheKey) -> bool,
+        keys: &mut Vec<ChunkCacheKey>,
+    ) -> bool {
+        let key = ChunkCacheKey {
+            bounds: node.bounds(),
+            level: node.level(),
+            noise,
+            params,
+        };
+        let resident = drawable(&key);
+        let sub_nodes = match node.sub_nodes() {
+            Some(sub_nodes) => sub_nodes,
+            None => {
+                if resident {
+                    keys.push(key);
+                }
+                return resident;
+            }
+        };
+        let mut sub_keys = vec![];
+        let mut covered = true;
+        for sub_node in sub_nodes {
+            if regions.iter().any(|x| sub_node.intersects_region(x)) {
+                covered &= self.collect_render_keys(
+                    sub_node,
+                    regions,
+                    noise,
+                    params,
+                    drawable,
+                    &mut sub_keys,
+                );
+            }
+        }
+        if covered {
+            keys.append(&mut sub_keys);
+            true
+        } else if resident {
+            // Instead of every key collected below it, not on top of them
+            keys.push(key);
+            true
+        } else {
+            // Handed up to the nearest resident ancestor, which drops them
+            // along with the rest of its subtree if it falls back
+            keys.append(&mut sub_keys);
+            false
+        }
+    }
+
+    #[profiling::function]
+    fn render<'a>(&'a self, regions: &[Region], frame: usize) -> Vec<TerrainRenderBundle> {
+        {
+            let preview = self.preview.read();
+            if !preview.is_empty() {
+                return preview
+                    .keys()
+                    .map(|key| TerrainRenderBundle::Preview {
+                        key: *key,
+                        frame,
+                        // The outer guard is still held, a plain read would
+                        // queue up behind a waiting writer
+                        guard: self.preview.read_recursive(),
+                    })
+                    .collect();
+            }
+        }
+        let noise = *self.noise.read();
+        let params = *self.params.read();
+        let mesh_cache = self.mesh_cache().read();
+        // Resources built with pipelines that were swapped out are not drawn,
+        // they do not match the pass
+        let generation = self.pipelines().generation;
+        let drawable = |key: &ChunkCacheKey| {
+            mesh_cache.get(key).map_or(false, |x| {
+                x.is_resident() && x.pipeline_generation() == Some(generation)
+            })
+        };
+        let tree = self.tree.read();
+        let mut keys = vec![];
+        for node in tree.root_nodes() {
+            if regions.iter().any(|x| node.intersects_region(x)) {
+                self.collect_render_keys(node, regions, noise, params, &drawable, &mut keys);
+            }
+        }
+        let mut bundles = keys
+            .into_iter()
+            .map(|key| TerrainRenderBundle::Mesh {
+                key,
+                frame,
+                // Taken while mesh_cache is held, like the preview guards
+                guard: self.mesh_cache().read_recursive(),
+            })
+            .collect::<Vec<_>>();
+        *self.rendered_keys.write() = bundles.iter().map(|x| x.key()).collect();
+        // Water is blended over the terrain so it goes after every opaque bundle
+        let water_keys = bundles
+            .iter()
+            .map(|x| x.key())
+            .filter(|key| {
+                mesh_cache
+                    .get(key)
+                    .unwrap()
+                    .water_render_bundle(frame)
+                    .is_some()
+            })
+            .collect::<Vec<_>>();
+        for key in water_keys {
+            bundles.push(TerrainRenderBundle::Water {
+                key,
+                frame,
+                guard: self.mesh_cache().read_recursive(),
+            });
+        }
+        bundles
+    }
+
+    #[profiling::function]
+    fn set_isolevel(&self, isolevel: f32) {
+        *self.isolevel.write() = isolevel;
+    }
+
+    #[profiling::function]
+    fn regenerate_triangle(&self, instance: &Instance, key: &ChunkCacheKey) -> TaskResult {
+        if let Some(chunk) = self.chunk_cache.write().get_mut(key) {
+            let device = instance.device();
+            let mut encoder =
+                device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            let meshing = *self.meshing.read();
+            chunk.generate_triangle(
+                instance,
+                &mut encoder,
+                self.pipelines().generate_triangle(meshing),
+                meshing,
+                !self.meshes_on_gpu(key),
+                *self.isolevel.read(),
+            );
+            self.batch.submit_after(instance, encoder);
+            return Ok(Some(TerrainTask::GenerateMesh(*key)));
+        }
+        Ok(None)
+    }
+
+    #[profiling::function]
+    fn apply_edit(&self, instance: &Instance, job: &EditJob, key: &ChunkCacheKey) -> TaskResult {
+        if job.is_cancelled() {
+            job.finish_chunk();
+            return Ok(None);
+        }
+        self.drop_inactive_meshes(key);
+        let modified = {
+            let mut chunk_cache = self.chunk_cache.write();
+            let mut deltas = self.deltas.write();
+            let delta = ChunkDelta::Edit(job.operation().clone());
+            match chunk_cache.get_mut(key) {
+                // Applied with the other deltas when they are replayed
+                Some(chunk) if chunk.applied_deltas() < deltas.count(key) => {
+                    deltas.record(key, delta);
+                    false
+                }
+                Some(chunk) if job.operation().apply(chunk) => {
+                    chunk.set_applied_deltas(deltas.record(key, delta));
+                    chunk.write_voxel_buffer(instance);
+                    true
+                }
+                _ => false,
+            }
+        };
+        job.finish_chunk();
+        if modified {
+            if let Some(mesh) = self.mesh_cache().write().remove(key) {
+                self.retired_meshes.push(mesh);
+            }
+            Ok(Some(TerrainTask::GenerateChunk(*key)))
+        } else {
+            Ok(None)
+        }
+    }
+
+    #[profiling::function]
+    fn replay_deltas(&self, instance: &Instance, key: &ChunkCacheKey) -> TaskResult {
+        let mut chunk_cache = self.chunk_cache.write();
+        // Evicted since its voxels were read back
+        let chunk = match chunk_cache.get_mut(key) {
+            Some(chunk) => chunk,
+            None => return Ok(None),
+        };
+        if self.deltas.read().replay(key, chunk) {
+            chunk.write_voxel_buffer(instance);
+            Ok(Some(TerrainTask::RegenerateTriangle(*key)))
+        } else {
+            Ok(Some(TerrainTask::GenerateMesh(*key)))
+        }
+    }
+
+    // Rendered chunks whose finer rendered neighbors changed since they were
+    // stitched, with the new neighbors
+    #[profiling::function]
+    fn changed_stitches(&self) -> Vec<(ChunkCacheKey, Vec<ChunkCacheKey>)> {
+        let rendered_keys = self.rendered_keys.read();
+        let mut stitches = self.stitches.write();
+        let mut stitched_keys = self.stitched_keys.write();
+        if *stitched_keys == *rendered_keys {
+            return vec![];
+        }
+        *stitched_keys = rendered_keys.clone();
+        let tree = self.tree.read();
+        let rendered = rendered_keys.iter().copied().collect::<HashSet<_>>();
+        let mut changed = vec![];
+        for key in rendered_keys.iter() {
+            // Finer leaves next to the chunk are drawn with their own mesh or
+            // with the one of an ancestor
+            let neighbors = tree
+                .node(&key.bounds, key.level)
+                .map(|node| {
+                    tree.neighbors(node)
+                        .iter()
+                        .filter(|x| x.level_delta > 0)
+                        .flat_map(|x| &x.leaves)
+                        .filter_map(|leaf| {
+                            let leaf_key = ChunkCacheKey {
+                                bounds: leaf.bounds(),
+                                level: leaf.level(),
+                                ..*key
+                            };
+                            std::iter::once(leaf_key)
+                                .chain(ancestor_keys(&leaf_key))
+                                .find(|x| rendered.contains(x))
+                        })
+                        .filter(|x| x.level > key.level)
+                        .collect::<HashSet<_>>()
+                })
+                .unwrap_or_default();
+            let unchanged = match stitches.get(key) {
+                Some(stitched) => *stitched == neighbors,
+                None => neighbors.is_empty(),
+            };
+            if !unchanged {
+                changed.push((*key, neighbors.iter().copied().collect()));
+                stitches.insert(*key, neighbors);
+            }
+        }
+        changed
+    }
+
+    #[profiling::function]
+    fn stitch_mesh(
+        &self,
+        instance: &Instance,
+        camera_buffers: &[Buffer],
+        key: &ChunkCacheKey,
+        neighbors: &[ChunkCacheKey],
+    ) -> TaskResult {
+        // The edges of neighbors still being meshed would be stale
+        if self.graph.await_neighbors(key, neighbors) {
+            return Ok(None);
+        }
+        // Meshed again on the CPU now that the stitches are recorded
+        {
+            let mut mesh_cache = self.mesh_cache().write();
+            let gpu_resident = match mesh_cache.get(key) {
+                Some(mesh) => mesh.is_gpu_resident(),
+                None => return Ok(None),
+            };
+            if gpu_resident {
+                if neighbors.is_empty() {
+                    return Ok(None);
+                }
+                if let Some(mesh) = mesh_cache.remove(key) {
+                    self.retired_meshes.push(mesh);
+                }
+                return Ok(Some(TerrainTask::RegenerateTriangle(*key)));
+            }
+        }
+        let isolevel = *self.isolevel.read();
+        let (stride, transition) = {
+            let mesh_cache = self.mesh_cache().read();
+            let mesh = match mesh_cache.get(key) {
+                Some(mesh) => mesh,
+                None => return Ok(None),
+            };
+            let neighbors = neighbors
+                .iter()
+                .filter_map(|x| {
+                    let side = transition::adjacent_side(&key.bounds, &x.bounds)?;
+                    Some((side, x, mesh_cache.get(x)?))
+                })
+                .collect::<Vec<_>>();
+            let mut stride = StitchStride::NONE;
+            for (side, neighbor, _) in &neighbors {
+                let ratio = (1 << (neighbor.level - key.level)).min(MAX_STITCH_RATIO);
+                stride.set(*side, stride.get(*side).max(ratio));
+            }
+            let faces = Side::ALL
+                .iter()
+                .filter(|side| stride.get(**side) > 1)
+                .map(|side| {
+                    let fine_neighbors = neighbors
+                        .iter()
+                        .filter(|(x, _, _)| *x == *side)
+                        .map(|(_, neighbor, neighbor_mesh)| FineNeighbor {
+                            bounds: neighbor.bounds,
+                            face: neighbor_mesh.edge_voxel().face(side.opposite()),
+                        })
+                        .collect::<Vec<_>>();
+                    TransitionFace::new(
+                        *side,
+                        stride.get(*side),
+                        &key.bounds,
+                        mesh.edge_voxel().face(*side),
+                        &fine_neighbors,
+                    )
+                })
+                .collect::<Vec<_>>();
+            let transition = if faces.is_empty() {
+                None
+            } else {
+                Some(transition::transition_mesh(
+                    &faces,
+                    &stride,
+                    mesh.voxel_count(),
+                    isolevel,
+                ))
+            };
+            (stride, transition)
+        };
+        // Under the lock like in generate_mesh_resources
+        let mut mesh_cache = self.mesh_cache().write();
+        let pipelines = self.pipelines();
+        if let Some(mesh) = mesh_cache.get_mut(key) {
+            if let Some(released) = mesh.set_transition(stride, transition) {
+                self.retired_resources.push(released);
+                mesh.create_render_resources(
+                    instance,
+                    &pipelines,
+                    &self.mesh_arenas(),
+                    camera_buffers,
+                    *self.mesh_style.read(),
+                );
+            }
+            mesh_cache.update_weight(key);
+        }
+        Ok(None)
+    }
+}
 
 impl Drop for Terrain {
     fn drop(&mut self) {
-        *self.guard.lock().unwrap() = false;
+        *self.guard.lock().unwrap() = true;
         self.condvar.notify_all();
     }
 }
 
-pub struct TerrainRenderBundle<'a> {
-    key: ChunkCacheKey,
-    guard: RwLockReadGuard<'a, Cache<ChunkCacheKey, ChunkMesh>>,
+// The frame picks the bundle bound to the camera buffer of the frame
+pub enum TerrainRenderBundle<'a> {
+    Mesh {
+        key: ChunkCacheKey,
+        frame: usize,
+        guard: RwLockReadGuard<'a, Cache<ChunkCacheKey, ChunkMesh>>,
+    },
+    Water {
+        key: ChunkCacheKey,
+        frame: usize,
+        guard: RwLockReadGuard<'a, Cache<ChunkCacheKey, ChunkMesh>>,
+    },
+    Preview {
+        key: ChunkCacheKey,
+        frame: usize,
+        guard: RwLockReadGuard<'a, HashMap<ChunkCacheKey, PreviewChunk>>,
+    },
+}
+
+impl<'a> TerrainRenderBundle<'a> {
+    fn key(&self) -> ChunkCacheKey {
+        match self {
+            TerrainRenderBundle::Mesh { key, .. } => *key,
+            TerrainRenderBundle::Water { key, .. } => *key,
+            TerrainRenderBundle::Preview { key, .. } => *key,
+        }
+    }
 }
 
 impl<'a, 'b> From<&'b TerrainRenderBundle<'a>> for &'b RenderBundle
@@ -786,6 +3052,16 @@ where
     'a: 'b,
 {
     fn from(item: &'b TerrainRenderBundle<'a>) -> &'b RenderBundle {
-        item.guard.get(&item.key).unwrap().render_bundle().unwrap()
+        match item {
+            TerrainRenderBundle::Mesh { key, frame, guard } => {
+                guard.get(key).unwrap().render_bundle(*frame).unwrap()
+            }
+            TerrainRenderBundle::Water { key, frame, guard } => {
+                guard.get(key).unwrap().water_render_bundle(*frame).unwrap()
+            }
+            TerrainRenderBundle::Preview { key, frame, guard } => {
+                guard.get(key).unwrap().render_bundle(*frame)
+            }
+        }
     }
 }
@@ -0,0 +1,66 @@
+use crate::gfx::Instance;
+use wgpu::*;
+
+// Writes the normals of a chunk mesh into its vertex buffer on the GPU
+// instead of going through Mesh::calculate_normals
+pub struct NormalPipelines {
+    pub accumulate_normals: ComputePipeline,
+    pub write_normals: ComputePipeline,
+}
+
+impl NormalPipelines {
+    pub fn new(instance: &Instance) -> Self {
+        let device = instance.device();
+        let storage_entry = |binding: u32, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("terrain_normal_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // index buffer
+                storage_entry(1, true),
+                // fixed point normal sums
+                storage_entry(2, false),
+                // vertex buffer
+                storage_entry(3, false),
+                // mesh vertex copied by each vertex after the mesh
+                storage_entry(4, true),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("terrain_normal_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/normals.wgsl"));
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("terrain_normal_compute_pipeline"),
+                entry_point,
+                module: &shader_module,
+                layout: Some(&pipeline_layout),
+            })
+        };
+        Self {
+            accumulate_normals: create_pipeline("accumulate_normals"),
+            write_normals: create_pipeline("write_normals"),
+        }
+    }
+}
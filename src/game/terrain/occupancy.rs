@@ -0,0 +1,196 @@
+use super::chunk::{Chunk, SURFACE_MAX_Z, SURFACE_MIN_Z};
+use crate::game::base::WorldSpace;
+use euclid::{point2, vec2, Point2D, Point3D};
+use std::collections::HashMap;
+
+// Cells along x and y per world unit, the finest chunks cover two by two
+const CELLS_PER_UNIT: i32 = 2;
+// One bit per layer of a column, from SURFACE_MIN_Z up to SURFACE_MAX_Z. Only
+// the band of the generated surface is kept, the chunks past it are air.
+const OCCUPANCY_LAYERS: u32 = u16::BITS;
+// Cells along each side of a tile
+const TILE_SIZE: i32 = 32;
+
+#[derive(Debug, Copy, Clone)]
+struct Column {
+    solid: u16,
+    // Of the chunk the column was rasterized from, coarser chunks do not
+    // overwrite the columns of finer ones
+    level: u32,
+}
+
+// Solid columns of the chunks rasterized at cell centers
+pub struct OccupancyPatch {
+    min: Point2D<i32, WorldSpace>,
+    width: i32,
+    level: u32,
+    // Layers inside the chunk, nodes split along z only cover some of them
+    layers: u16,
+    columns: Vec<u16>,
+}
+
+impl OccupancyPatch {
+    #[profiling::function]
+    pub fn from_chunk(chunk: &Chunk, isolevel: f32) -> Option<Self> {
+        let bounds = chunk.bounds();
+        let min = bounds.min.xy() * CELLS_PER_UNIT;
+        let max = bounds.max.xy() * CELLS_PER_UNIT;
+        let width = max.x - min.x;
+        let layers = (0..OCCUPANCY_LAYERS)
+            .filter(|x| {
+                let z = layer_center(*x);
+                z >= bounds.min.z as f32 && z <= bounds.max.z as f32
+            })
+            .fold(0, |layers, x| layers | (1 << x));
+        // Past the band of the surface
+        if layers == 0 {
+            return None;
+        }
+        let mut columns = Vec::with_capacity((width * (max.y - min.y)) as usize);
+        for y in min.y..max.y {
+            for x in min.x..max.x {
+                let center = cell_center(point2(x, y));
+                let mut solid = 0;
+                for layer in (0..OCCUPANCY_LAYERS).filter(|x| layers & (1 << x) != 0) {
+                    let z = layer_center(layer);
+                    if chunk.sample_voxel(&center.extend(z))? >= isolevel {
+                        solid |= 1 << layer;
+                    }
+                }
+                columns.push(solid);
+            }
+        }
+        Some(Self {
+            min,
+            width,
+            level: chunk.level(),
+            layers,
+            columns,
+        })
+    }
+}
+
+// Coarse solid or air grid over every chunk read back with the current
+// parameters, for queries that have to be cheap rather than exact like line
+// of sight or picking spawn points. Unknown where no chunk was rasterized.
+pub struct Occupancy {
+    tiles: HashMap<Point2D<i32, WorldSpace>, Vec<Option<Column>>>,
+}
+
+impl Occupancy {
+    pub fn new() -> Self {
+        Self {
+            tiles: HashMap::new(),
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.tiles.clear();
+    }
+
+    pub fn write(&mut self, patch: &OccupancyPatch) {
+        for (i, solid) in patch.columns.iter().enumerate() {
+            let i = i as i32;
+            let cell = point2(patch.min.x + i % patch.width, patch.min.y + i / patch.width);
+            let (tile, index) = tile_of(cell);
+            let column = &mut self
+                .tiles
+                .entry(tile)
+                .or_insert_with(|| vec![None; (TILE_SIZE * TILE_SIZE) as usize])[index];
+            match column {
+                Some(x) if x.level > patch.level => {}
+                _ => {
+                    let kept = column.map_or(0, |x| x.solid & !patch.layers);
+                    *column = Some(Column {
+                        solid: kept | solid,
+                        level: patch.level,
+                    });
+                }
+            }
+        }
+    }
+
+    fn column(&self, point: &Point2D<f32, WorldSpace>) -> Option<Column> {
+        let cell = (*point * CELLS_PER_UNIT as f32).floor().to_i32();
+        let (tile, index) = tile_of(cell);
+        self.tiles.get(&tile).and_then(|x| x[index])
+    }
+
+    pub fn is_solid(&self, point: &Point3D<f32, WorldSpace>) -> Option<bool> {
+        let column = self.column(&point.xy())?;
+        Some(match layer_of(point.z) {
+            Some(layer) => column.solid & (1 << layer) != 0,
+            // Everything under the terrain is solid and above it is air
+            None => point.z < SURFACE_MIN_Z as f32,
+        })
+    }
+
+    // Top of the highest solid cell of the column
+    pub fn top(&self, point: &Point2D<f32, WorldSpace>) -> Option<f32> {
+        let column = self.column(point)?;
+        if column.solid == 0 {
+            return None;
+        }
+        let layer = OCCUPANCY_LAYERS - column.solid.leading_zeros();
+        Some(SURFACE_MIN_Z as f32 + layer as f32 * layer_height())
+    }
+
+    // Marches the segment at half of a cell, the cells of both ends are
+    // skipped so that points on the surface can see each other. None if it
+    // crosses unknown cells without hitting a solid one.
+    pub fn line_of_sight(
+        &self,
+        from: &Point3D<f32, WorldSpace>,
+        to: &Point3D<f32, WorldSpace>,
+    ) -> Option<bool> {
+        let cell_size = 1.0 / CELLS_PER_UNIT as f32;
+        let length = (*to - *from).length();
+        let steps = (length / (cell_size * 0.5)).ceil() as u32;
+        let mut known = true;
+        for i in 0..=steps {
+            let distance = length * i as f32 / steps.max(1) as f32;
+            if distance < cell_size || length - distance < cell_size {
+                continue;
+            }
+            match self.is_solid(&from.lerp(*to, distance / length)) {
+                Some(true) => return Some(false),
+                Some(false) => {}
+                None => known = false,
+            }
+        }
+        if known {
+            Some(true)
+        } else {
+            None
+        }
+    }
+}
+
+fn layer_height() -> f32 {
+    (SURFACE_MAX_Z - SURFACE_MIN_Z) as f32 / OCCUPANCY_LAYERS as f32
+}
+
+fn layer_center(layer: u32) -> f32 {
+    SURFACE_MIN_Z as f32 + (layer as f32 + 0.5) * layer_height()
+}
+
+fn layer_of(z: f32) -> Option<u32> {
+    let layer = ((z - SURFACE_MIN_Z as f32) / layer_height()).floor();
+    if (0.0..OCCUPANCY_LAYERS as f32).contains(&layer) {
+        Some(layer as u32)
+    } else {
+        None
+    }
+}
+
+fn cell_center(cell: Point2D<i32, WorldSpace>) -> Point2D<f32, WorldSpace> {
+    (cell.to_f32() + vec2(0.5, 0.5)) / CELLS_PER_UNIT as f32
+}
+
+// Tile of the cell and the index of the cell in it
+fn tile_of(cell: Point2D<i32, WorldSpace>) -> (Point2D<i32, WorldSpace>, usize) {
+    let tile = point2(cell.x.div_euclid(TILE_SIZE), cell.y.div_euclid(TILE_SIZE));
+    let x = cell.x.rem_euclid(TILE_SIZE);
+    let y = cell.y.rem_euclid(TILE_SIZE);
+    (tile, (x + y * TILE_SIZE) as usize)
+}
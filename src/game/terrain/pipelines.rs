@@ -0,0 +1,622 @@
+use super::biome::BIOME_STYLES;
+use super::chunk::WaterVertex;
+use super::chunk_mesh::VertexData;
+use super::draw_args::DrawArgsPipeline;
+use super::erosion::ErosionPipelines;
+use super::generator::{generate_voxel_shader, GeneratorSource, TerrainGenerator};
+use super::magma::{MagmaData, MagmaSettings};
+use super::normals::NormalPipelines;
+use super::point_light::PointLightsData;
+use super::preview::PreviewPipeline;
+use super::weld::WeldPipelines;
+use super::{MeshingAlgorithm, TerrainOverlay, MESHING_ALGORITHMS};
+use crate::game::wind::WindData;
+use crate::gfx::Instance;
+use std::mem::size_of;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+static NEXT_GENERATION: AtomicU64 = AtomicU64::new(0);
+
+// Every pipeline used by the terrain, built together so that a task always
+// sees a consistent set. Settings changes build a new set and swap it in,
+// tasks already running keep their Arc to the old one until they finish.
+pub struct TerrainPipelines {
+    pub generate_voxel: ComputePipeline,
+    // One for each meshing algorithm, in the order of MESHING_ALGORITHMS
+    generate_triangle: Vec<ComputePipeline>,
+    pub sculpt: ComputePipeline,
+    pub draw_args: DrawArgsPipeline,
+    pub erosion: ErosionPipelines,
+    pub weld: WeldPipelines,
+    pub normals: NormalPipelines,
+    pub render: RenderPipeline,
+    pub render_bind_group_layout: BindGroupLayout,
+    // BIOME_STYLES, bound with every terrain mesh
+    pub biome_style_buffer: Buffer,
+    // PointLightsData, written every frame
+    pub point_light_buffer: Buffer,
+    // WindData, written every frame
+    pub wind_buffer: Buffer,
+    pub magma_buffer: Buffer,
+    // Draws the meshes left on the GPU, the render bind group is followed by
+    // the one of gpu_mesh_bind_group_layout
+    pub gpu_mesh: RenderPipeline,
+    pub gpu_mesh_bind_group_layout: BindGroupLayout,
+    // Drawn after every terrain bundle, shares the render bind group layout
+    pub water: RenderPipeline,
+    pub preview: PreviewPipeline,
+    pub target_format: TextureFormat,
+    pub sample_count: u32,
+    pub overlay: TerrainOverlay,
+    // Flat shaded faces in bands of light, see the stylized fragment stage
+    pub stylized: bool,
+    pub generator: Arc<dyn TerrainGenerator>,
+    // Render resources created from another generation are stale
+    pub generation: u64,
+}
+
+impl TerrainPipelines {
+    pub fn new(
+        instance: &Instance,
+        target_format: TextureFormat,
+        sample_count: u32,
+        overlay: TerrainOverlay,
+        stylized: bool,
+        generator: Arc<dyn TerrainGenerator>,
+    ) -> Self {
+        let (render, render_bind_group_layout) =
+            create_render_pipeline(instance, target_format, sample_count, overlay, stylized);
+        let (gpu_mesh, gpu_mesh_bind_group_layout) = create_gpu_mesh_pipeline(
+            instance,
+            &render_bind_group_layout,
+            target_format,
+            sample_count,
+            overlay,
+            stylized,
+        );
+        let water = create_water_pipeline(
+            instance,
+            &render_bind_group_layout,
+            target_format,
+            sample_count,
+        );
+        Self {
+            generate_voxel: create_generate_voxel_pipeline(instance, generator.as_ref()),
+            generate_triangle: MESHING_ALGORITHMS
+                .iter()
+                .map(|x| create_generate_triangle_pipeline(instance, *x))
+                .collect(),
+            sculpt: create_sculpt_pipeline(instance),
+            draw_args: DrawArgsPipeline::new(instance),
+            erosion: ErosionPipelines::new(instance),
+            weld: WeldPipelines::new(instance),
+            normals: NormalPipelines::new(instance),
+            render,
+            render_bind_group_layout,
+            gpu_mesh,
+            gpu_mesh_bind_group_layout,
+            biome_style_buffer: instance.device().create_buffer_init(&BufferInitDescriptor {
+                label: Some("terrain_biome_style_buffer"),
+                contents: bytemuck::cast_slice(&BIOME_STYLES),
+                usage: BufferUsages::UNIFORM,
+            }),
+            point_light_buffer: instance.device().create_buffer_init(&BufferInitDescriptor {
+                label: Some("terrain_point_light_buffer"),
+                contents: bytemuck::bytes_of(&PointLightsData::new(&[])),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            }),
+            wind_buffer: instance.device().create_buffer(&BufferDescriptor {
+                label: Some("terrain_wind_buffer"),
+                size: size_of::<WindData>() as u64,
+                mapped_at_creation: false,
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            }),
+            magma_buffer: instance.device().create_buffer_init(&BufferInitDescriptor {
+                label: Some("terrain_magma_buffer"),
+                contents: bytemuck::bytes_of(&MagmaData::new(&MagmaSettings::default())),
+                usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            }),
+            water,
+            preview: PreviewPipeline::new(instance, target_format, sample_count),
+            target_format,
+            sample_count,
+            overlay,
+            stylized,
+            generator,
+            generation: NEXT_GENERATION.fetch_add(1, Ordering::Relaxed),
+        }
+    }
+
+    pub fn generate_triangle(&self, meshing: MeshingAlgorithm) -> &ComputePipeline {
+        &self.generate_triangle[meshing as usize]
+    }
+}
+
+// Custom generator pipelines have to use this layout
+pub fn generate_voxel_pipeline_layout(instance: &Instance) -> PipelineLayout {
+    let device = instance.device();
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("terrain_voxel_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // water level per voxel column
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // biome weights per voxel column
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+    device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("terrain_voxel_pipeline_layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    })
+}
+
+fn create_generate_voxel_pipeline(
+    instance: &Instance,
+    generator: &dyn TerrainGenerator,
+) -> ComputePipeline {
+    let density = match generator.source(instance) {
+        GeneratorSource::Wgsl(density) => density,
+        GeneratorSource::Pipeline(pipeline) => return pipeline,
+    };
+    let device = instance.device();
+    let shader_module = device.create_shader_module(&ShaderModuleDescriptor {
+        label: Some("terrain_voxel_shader"),
+        source: ShaderSource::Wgsl(generate_voxel_shader(&density).into()),
+    });
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("terrain_voxel_compute_pipeline"),
+        entry_point: "main",
+        module: &shader_module,
+        layout: Some(&generate_voxel_pipeline_layout(instance)),
+    })
+}
+
+// Every meshing algorithm reads the voxels and writes triangles with the same
+// layout
+fn create_generate_triangle_pipeline(
+    instance: &Instance,
+    meshing: MeshingAlgorithm,
+) -> ComputePipeline {
+    let device = instance.device();
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("terrain_triangle_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // Biome weights of the voxel columns
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: true },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("terrain_triangle_pipeline_layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let mesher = match meshing {
+        MeshingAlgorithm::MarchingCubes => include_str!("shaders/generate_triangle.wgsl"),
+        MeshingAlgorithm::DualContouring => include_str!("shaders/dual_contouring.wgsl"),
+        MeshingAlgorithm::SurfaceNets => include_str!("shaders/surface_nets.wgsl"),
+    };
+    let shader_module = device.create_shader_module(&ShaderModuleDescriptor {
+        label: Some("terrain_triangle_shader"),
+        source: ShaderSource::Wgsl(
+            format!("{}\n{}", include_str!("shaders/vertex_color.wgsl"), mesher).into(),
+        ),
+    });
+    let pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("terrain_triangle_compute_pipeline"),
+        entry_point: "main",
+        module: &shader_module,
+        layout: Some(&pipeline_layout),
+    });
+
+    pipeline
+}
+
+fn create_sculpt_pipeline(instance: &Instance) -> ComputePipeline {
+    let device = instance.device();
+    let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("terrain_sculpt_bind_group_layout"),
+        entries: &[
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::COMPUTE,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Storage { read_only: false },
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("terrain_sculpt_pipeline_layout"),
+        bind_group_layouts: &[&bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let shader_module = device.create_shader_module(&include_wgsl!("shaders/sculpt.wgsl"));
+    device.create_compute_pipeline(&ComputePipelineDescriptor {
+        label: Some("terrain_sculpt_compute_pipeline"),
+        entry_point: "main",
+        module: &shader_module,
+        layout: Some(&pipeline_layout),
+    })
+}
+
+// Fragment stage of render.wgsl, the overlay replaces the shading
+fn fragment_entry_point(overlay: TerrainOverlay, stylized: bool) -> &'static str {
+    match overlay {
+        TerrainOverlay::Traversability => "traversability",
+        TerrainOverlay::None if stylized => "stylized",
+        TerrainOverlay::None => "main",
+    }
+}
+
+fn create_render_pipeline(
+    instance: &Instance,
+    target_format: TextureFormat,
+    sample_count: u32,
+    overlay: TerrainOverlay,
+    stylized: bool,
+) -> (RenderPipeline, BindGroupLayout) {
+    let device = instance.device();
+    let render_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("terrain_render_bind_group_layout"),
+        entries: &[
+            // world matrix
+            BindGroupLayoutEntry {
+                binding: 0,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // view + projection matrix
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // biome styles
+            BindGroupLayoutEntry {
+                binding: 2,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // point lights
+            BindGroupLayoutEntry {
+                binding: 3,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // wind
+            BindGroupLayoutEntry {
+                binding: 4,
+                visibility: ShaderStages::VERTEX | ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // magma
+            BindGroupLayoutEntry {
+                binding: 5,
+                visibility: ShaderStages::FRAGMENT,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("terrain_render_pipeline_layout"),
+        bind_group_layouts: &[&render_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let shader_module = device.create_shader_module(&include_wgsl!("shaders/render.wgsl"));
+    let render_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("terrain_render_pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &shader_module,
+            entry_point: "main",
+            buffers: &[VertexBufferLayout {
+                array_stride: size_of::<VertexData>() as u64,
+                step_mode: VertexStepMode::Vertex,
+                attributes: &vertex_attr_array![
+                    0 => Float32x4,
+                    1 => Float32x4,
+                    2 => Float32,
+                    3 => Float32x4,
+                    4 => Float32,
+                    5 => Float32x3,
+                ],
+            }],
+        },
+        primitive: PrimitiveState {
+            // polygon_mode: PolygonMode::Line,
+            cull_mode: Some(Face::Back),
+            ..Default::default()
+        },
+        depth_stencil: Some(DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        fragment: Some(FragmentState {
+            module: &shader_module,
+            entry_point: fragment_entry_point(overlay, stylized),
+            targets: &[ColorTargetState {
+                format: target_format,
+                // The alpha of the scene is its glow, see Bloom
+                blend: Some(BlendState::REPLACE),
+                write_mask: ColorWrites::ALL,
+            }],
+        }),
+    });
+    (render_pipeline, render_bind_group_layout)
+}
+
+fn create_gpu_mesh_pipeline(
+    instance: &Instance,
+    render_bind_group_layout: &BindGroupLayout,
+    target_format: TextureFormat,
+    sample_count: u32,
+    overlay: TerrainOverlay,
+    stylized: bool,
+) -> (RenderPipeline, BindGroupLayout) {
+    let device = instance.device();
+    let storage_entry = |binding| BindGroupLayoutEntry {
+        binding,
+        visibility: ShaderStages::VERTEX,
+        ty: BindingType::Buffer {
+            ty: BufferBindingType::Storage { read_only: true },
+            has_dynamic_offset: false,
+            min_binding_size: None,
+        },
+        count: None,
+    };
+    let gpu_mesh_bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+        label: Some("terrain_gpu_mesh_bind_group_layout"),
+        entries: &[
+            // raw triangle buffer
+            storage_entry(0),
+            // voxel count
+            BindGroupLayoutEntry {
+                binding: 1,
+                visibility: ShaderStages::VERTEX,
+                ty: BindingType::Buffer {
+                    ty: BufferBindingType::Uniform,
+                    has_dynamic_offset: false,
+                    min_binding_size: None,
+                },
+                count: None,
+            },
+            // biome weights per voxel column
+            storage_entry(2),
+            // packed enclosure per voxel
+            storage_entry(3),
+        ],
+    });
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("terrain_gpu_mesh_pipeline_layout"),
+        bind_group_layouts: &[render_bind_group_layout, &gpu_mesh_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    // The fragment stages of render.wgsl with another vertex stage
+    let shader_module = device.create_shader_module(&ShaderModuleDescriptor {
+        label: Some("terrain_gpu_mesh_shader"),
+        source: ShaderSource::Wgsl(
+            format!(
+                "{}\n{}\n{}",
+                include_str!("shaders/vertex_color.wgsl"),
+                include_str!("shaders/render.wgsl"),
+                include_str!("shaders/gpu_mesh.wgsl")
+            )
+            .into(),
+        ),
+    });
+    let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("terrain_gpu_mesh_pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &shader_module,
+            entry_point: "gpu_mesh",
+            buffers: &[],
+        },
+        // Winding is not fixed up without the CPU pass so draw both sides
+        primitive: PrimitiveState::default(),
+        depth_stencil: Some(DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: true,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        fragment: Some(FragmentState {
+            module: &shader_module,
+            entry_point: fragment_entry_point(overlay, stylized),
+            targets: &[ColorTargetState {
+                format: target_format,
+                blend: Some(BlendState::REPLACE),
+                write_mask: ColorWrites::ALL,
+            }],
+        }),
+    });
+    (pipeline, gpu_mesh_bind_group_layout)
+}
+
+fn create_water_pipeline(
+    instance: &Instance,
+    render_bind_group_layout: &BindGroupLayout,
+    target_format: TextureFormat,
+    sample_count: u32,
+) -> RenderPipeline {
+    let device = instance.device();
+    let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+        label: Some("terrain_water_pipeline_layout"),
+        bind_group_layouts: &[render_bind_group_layout],
+        push_constant_ranges: &[],
+    });
+    let shader_module = device.create_shader_module(&include_wgsl!("shaders/water.wgsl"));
+    device.create_render_pipeline(&RenderPipelineDescriptor {
+        label: Some("terrain_water_pipeline"),
+        layout: Some(&pipeline_layout),
+        vertex: VertexState {
+            module: &shader_module,
+            entry_point: "main",
+            buffers: &[VertexBufferLayout {
+                array_stride: size_of::<WaterVertex>() as u64,
+                step_mode: VertexStepMode::Vertex,
+                attributes: &vertex_attr_array![0 => Float32x3, 1 => Float32],
+            }],
+        },
+        // Visible from under the water as well
+        primitive: PrimitiveState::default(),
+        // Transparent, only test against the terrain
+        depth_stencil: Some(DepthStencilState {
+            format: TextureFormat::Depth32Float,
+            depth_write_enabled: false,
+            depth_compare: CompareFunction::Less,
+            stencil: StencilState::default(),
+            bias: DepthBiasState::default(),
+        }),
+        multisample: MultisampleState {
+            count: sample_count,
+            ..Default::default()
+        },
+        fragment: Some(FragmentState {
+            module: &shader_module,
+            entry_point: "main",
+            targets: &[ColorTargetState {
+                format: target_format,
+                blend: Some(BlendState::ALPHA_BLENDING),
+                // Emissive ground under the water keeps glowing
+                write_mask: ColorWrites::COLOR,
+            }],
+        }),
+    })
+}
@@ -0,0 +1,47 @@
+use crate::game::base::WorldSpace;
+use euclid::Point3D;
+
+// Lights past this many are not drawn. Keep in sync with render.wgsl
+pub const MAX_POINT_LIGHTS: usize = 16;
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+pub struct PointLightData {
+    // Position and the distance the light reaches
+    position: [f32; 4],
+    // Color and intensity
+    color: [f32; 4],
+}
+
+impl PointLightData {
+    pub fn new(
+        position: &Point3D<f32, WorldSpace>,
+        radius: f32,
+        color: [f32; 3],
+        intensity: f32,
+    ) -> Self {
+        Self {
+            position: [position.x, position.y, position.z, radius],
+            color: [color[0], color[1], color[2], intensity],
+        }
+    }
+}
+
+// Uniform buffer of the render shader
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+pub struct PointLightsData {
+    lights: [PointLightData; MAX_POINT_LIGHTS],
+    count: u32,
+    _pad: [u32; 3],
+}
+
+impl PointLightsData {
+    pub fn new(lights: &[PointLightData]) -> Self {
+        let mut data: Self = bytemuck::Zeroable::zeroed();
+        let count = lights.len().min(MAX_POINT_LIGHTS);
+        data.lights[..count].copy_from_slice(&lights[..count]);
+        data.count = count as u32;
+        data
+    }
+}
@@ -0,0 +1,237 @@
+use super::chunk::Chunk;
+use super::draw_args::DrawArgsPipeline;
+use crate::game::base::{LocalSpace, WorldSpace};
+use crate::gfx::Instance;
+use euclid::Transform3D;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
+use wgpu::*;
+
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+struct UniformData {
+    world_matrix: [f32; 16],
+}
+
+pub struct PreviewPipeline {
+    pipeline: RenderPipeline,
+    bind_group_layout: BindGroupLayout,
+    target_format: TextureFormat,
+    sample_count: u32,
+}
+
+impl PreviewPipeline {
+    pub fn new(instance: &Instance, target_format: TextureFormat, sample_count: u32) -> Self {
+        let device = instance.device();
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("terrain_preview_bind_group_layout"),
+            entries: &[
+                // world matrix
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // view + projection matrix
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // raw triangle buffer
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::VERTEX,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("terrain_preview_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/preview.wgsl"));
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("terrain_preview_pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader_module,
+                entry_point: "main",
+                buffers: &[],
+            },
+            // Winding is not fixed up without the CPU pass so draw both sides
+            primitive: PrimitiveState::default(),
+            depth_stencil: Some(DepthStencilState {
+                format: TextureFormat::Depth32Float,
+                depth_write_enabled: true,
+                depth_compare: CompareFunction::Less,
+                stencil: StencilState::default(),
+                bias: DepthBiasState::default(),
+            }),
+            multisample: MultisampleState {
+                count: sample_count,
+                ..Default::default()
+            },
+            fragment: Some(FragmentState {
+                module: &shader_module,
+                entry_point: "main",
+                targets: &[ColorTargetState {
+                    format: target_format,
+                    blend: Some(BlendState::REPLACE),
+                    // The alpha of the scene is its glow
+                    write_mask: ColorWrites::COLOR,
+                }],
+            }),
+        });
+        Self {
+            pipeline,
+            bind_group_layout,
+            target_format,
+            sample_count,
+        }
+    }
+}
+
+// GPU only copy of a chunk triangles used while the isolevel is being dragged
+pub struct PreviewChunk {
+    triangle_buffer: Buffer,
+    draw_args_buffer: Buffer,
+    _uniform_buffer: Buffer,
+    // One for each camera buffer
+    render_bundles: Vec<RenderBundle>,
+}
+
+impl PreviewChunk {
+    pub fn new(
+        instance: &Instance,
+        chunk: &Chunk,
+        pipeline: &PreviewPipeline,
+        draw_args: &DrawArgsPipeline,
+        camera_buffers: &[Buffer],
+    ) -> Self {
+        let device = instance.device();
+        let triangle_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("chunk_preview_triangle_buffer"),
+            size: chunk.triangle_buffer_size(),
+            mapped_at_creation: false,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_DST,
+        });
+        let draw_args_buffer = draw_args.create_buffer(instance);
+        let bounds = chunk.bounds().to_f32();
+        let world_matrix: Transform3D<f32, LocalSpace, WorldSpace> =
+            Transform3D::scale(bounds.width(), bounds.height(), bounds.depth())
+                .then_translate(bounds.min.to_vector());
+        let uniform_buffer = device.create_buffer_init(&BufferInitDescriptor {
+            label: Some("chunk_preview_uniform_buffer"),
+            contents: bytemuck::bytes_of(&UniformData {
+                world_matrix: world_matrix.to_array(),
+            }),
+            usage: BufferUsages::UNIFORM,
+        });
+        let render_bundles = camera_buffers
+            .iter()
+            .map(|camera_buffer| {
+                let bind_group = device.create_bind_group(&BindGroupDescriptor {
+                    entries: &[
+                        BindGroupEntry {
+                            binding: 0,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &uniform_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 1,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: camera_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                        BindGroupEntry {
+                            binding: 2,
+                            resource: BindingResource::Buffer(BufferBinding {
+                                buffer: &triangle_buffer,
+                                offset: 0,
+                                size: None,
+                            }),
+                        },
+                    ],
+                    label: Some("chunk_preview_bind_group"),
+                    layout: &pipeline.bind_group_layout,
+                });
+                let mut encoder =
+                    device.create_render_bundle_encoder(&RenderBundleEncoderDescriptor {
+                        label: Some("chunk_preview_render_bundle_encoder"),
+                        color_formats: &[pipeline.target_format],
+                        depth_stencil: Some(RenderBundleDepthStencil {
+                            format: TextureFormat::Depth32Float,
+                            depth_read_only: false,
+                            stencil_read_only: false,
+                        }),
+                        sample_count: pipeline.sample_count,
+                    });
+                encoder.set_bind_group(0, &bind_group, &[]);
+                encoder.set_pipeline(&pipeline.pipeline);
+                encoder.draw_indirect(&draw_args_buffer, 0);
+                encoder.finish(&RenderBundleDescriptor {
+                    label: Some("chunk_preview_render_bundle"),
+                })
+            })
+            .collect();
+        Self {
+            triangle_buffer,
+            draw_args_buffer,
+            _uniform_buffer: uniform_buffer,
+            render_bundles,
+        }
+    }
+
+    pub fn update(
+        &self,
+        instance: &Instance,
+        encoder: &mut CommandEncoder,
+        chunk: &Chunk,
+        generate_triangle_pipeline: &ComputePipeline,
+        draw_args: &DrawArgsPipeline,
+        isolevel: f32,
+    ) {
+        // Reset the triangle count, queued writes land before the next submit
+        instance
+            .queue()
+            .write_buffer(&self.triangle_buffer, 0, bytemuck::bytes_of(&0u32));
+        chunk.dispatch_triangle(
+            instance,
+            encoder,
+            generate_triangle_pipeline,
+            isolevel,
+            &self.triangle_buffer,
+        );
+        draw_args.dispatch(
+            instance,
+            encoder,
+            &self.triangle_buffer,
+            &self.draw_args_buffer,
+        );
+    }
+
+    pub fn render_bundle(&self, frame: usize) -> &RenderBundle {
+        &self.render_bundles[frame]
+    }
+}
@@ -0,0 +1,173 @@
+use super::chunk::{Chunk, MapStatus, SURFACE_MAX_Z, SURFACE_MIN_Z};
+use super::pipelines::TerrainPipelines;
+use super::{CaveSettings, DomainWarp, ErosionSettings, NoiseAlgorithm, MIN_LEVEL};
+use crate::game::base::WorldSpace;
+use crate::gfx::Instance;
+use euclid::{point2, point3, size3, vec2, Box2D, Box3D, Point2D};
+use std::time::{Duration, Instant};
+use wgpu::CommandEncoderDescriptor;
+
+// Voxel columns along each side of the preview grid
+pub const PREVIEW_WORLD_RESOLUTION: u32 = 64;
+const PREVIEW_WORLD_LAYERS: u32 = 33;
+// World units the grid covers, the size of the root of the chunk tree
+const PREVIEW_WORLD_SIZE: i32 = 256;
+// The grid only moves once its center is a coarsest chunk away so walking
+// around does not simulate it again every frame
+const PREVIEW_WORLD_SNAP: f32 = 64.0;
+
+// Parameters being tuned, the seed and noise are the ones of the terrain
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct PreviewWorldParams {
+    pub center: Point2D<f32, WorldSpace>,
+    pub isolevel: f32,
+    pub domain_warp: DomainWarp,
+    pub caves: CaveSettings,
+    pub erosion: ErosionSettings,
+}
+
+// Surface of the last finished simulation
+#[derive(Debug, Clone)]
+pub struct PreviewWorld {
+    pub bounds: Box2D<i32, WorldSpace>,
+    // Row by row, none where the column has no surface
+    pub heights: Vec<Option<f32>>,
+    // From the submission to the read back
+    pub duration: Duration,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct SimulationInputs {
+    bounds: Box3D<i32, WorldSpace>,
+    params: PreviewWorldParams,
+    seed: u32,
+    noise: NoiseAlgorithm,
+}
+
+// A single coarse chunk generated and eroded with the parameters being
+// tuned. Erosion runs on far fewer and wider cells than on the chunks of the
+// terrain so the preview shows the shape of the result, not its detail.
+pub struct PreviewWorldSimulation {
+    running: Option<(SimulationInputs, Chunk, Instant)>,
+    // Inputs of the running or last finished simulation
+    last: Option<SimulationInputs>,
+    world: Option<PreviewWorld>,
+}
+
+impl PreviewWorldSimulation {
+    pub fn new() -> Self {
+        Self {
+            running: None,
+            last: None,
+            world: None,
+        }
+    }
+
+    // Never blocks, reads back the running simulation once it is mapped and
+    // starts the next one if the inputs changed since
+    pub fn update(
+        &mut self,
+        instance: &Instance,
+        pipelines: &TerrainPipelines,
+        params: &PreviewWorldParams,
+        seed: u32,
+        noise: NoiseAlgorithm,
+    ) {
+        if let Some((_, chunk, _)) = self.running.as_mut() {
+            match chunk.map_staging_buffers(instance, &pipelines.weld, || {}) {
+                MapStatus::Pending => return,
+                // Simulated again below
+                MapStatus::Failed => {
+                    self.running = None;
+                    self.last = None;
+                }
+                MapStatus::Mapped => {
+                    let (inputs, mut chunk, started) = self.running.take().unwrap();
+                    let voxels = chunk.get_mapped_voxel_buffer();
+                    chunk.unmap_voxel_buffer(instance);
+                    chunk.unmap_water_buffer(instance);
+                    chunk.unmap_biome_buffer(instance);
+                    chunk.set_voxels(voxels);
+                    self.world = Some(read_world(&chunk, inputs.params.isolevel, started));
+                }
+            }
+        }
+        let snap = |v: f32| (v / PREVIEW_WORLD_SNAP).round() as i32 * PREVIEW_WORLD_SNAP as i32;
+        let min = point2(snap(params.center.x), snap(params.center.y))
+            - vec2(PREVIEW_WORLD_SIZE / 2, PREVIEW_WORLD_SIZE / 2);
+        let inputs = SimulationInputs {
+            bounds: Box3D::new(
+                point3(min.x, min.y, SURFACE_MIN_Z),
+                point3(
+                    min.x + PREVIEW_WORLD_SIZE,
+                    min.y + PREVIEW_WORLD_SIZE,
+                    SURFACE_MAX_Z,
+                ),
+            ),
+            params: *params,
+            seed,
+            noise,
+        };
+        if self.last == Some(inputs) {
+            return;
+        }
+        let mut chunk = Chunk::new(
+            inputs.bounds,
+            MIN_LEVEL,
+            size3(
+                PREVIEW_WORLD_RESOLUTION,
+                PREVIEW_WORLD_RESOLUTION,
+                PREVIEW_WORLD_LAYERS,
+            ),
+            noise,
+        );
+        let mut encoder = instance
+            .device()
+            .create_command_encoder(&CommandEncoderDescriptor { label: None });
+        chunk.generate_voxel(
+            instance,
+            &mut encoder,
+            &pipelines.generate_voxel,
+            seed,
+            &params.domain_warp,
+            &params.caves,
+            true,
+        );
+        if params.erosion.enabled {
+            chunk.erode(
+                instance,
+                &mut encoder,
+                &pipelines.erosion,
+                &params.erosion,
+                params.isolevel,
+            );
+        }
+        // Submitted on its own, the batch of the terrain could hold it back
+        // behind chunks
+        instance.queue().submit(std::iter::once(encoder.finish()));
+        self.running = Some((inputs, chunk, Instant::now()));
+        self.last = Some(inputs);
+    }
+
+    pub fn world(&self) -> Option<&PreviewWorld> {
+        self.world.as_ref()
+    }
+}
+
+fn read_world(chunk: &Chunk, isolevel: f32, started: Instant) -> PreviewWorld {
+    let bounds = chunk.bounds();
+    let bounds = Box2D::new(bounds.min.xy(), bounds.max.xy());
+    let step = bounds.to_f32().width() / (PREVIEW_WORLD_RESOLUTION - 1) as f32;
+    let heights = (0..PREVIEW_WORLD_RESOLUTION)
+        .flat_map(|y| (0..PREVIEW_WORLD_RESOLUTION).map(move |x| (x, y)))
+        .map(|(x, y)| {
+            let point = bounds.min.to_f32() + vec2(x as f32, y as f32) * step;
+            chunk.surface_height(&point, isolevel)
+        })
+        .collect();
+    PreviewWorld {
+        bounds,
+        heights,
+        duration: started.elapsed(),
+    }
+}
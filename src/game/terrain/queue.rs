@@ -0,0 +1,164 @@
+use super::{ChunkCacheKey, TerrainTask};
+use crate::game::base::WorldSpace;
+use euclid::{Box3D, Point3D, Vector3D};
+use parking_lot::Mutex;
+use std::cmp::Ordering;
+use std::collections::BinaryHeap;
+
+// Tasks of chunks outside of the view wait as if they were this many times
+// farther away
+const OFF_SCREEN_FACTOR: f32 = 4.0;
+// Same for the chunks at the border of the view, the ones in between are
+// weighted by how far they are from the look direction
+const VIEW_BORDER_FACTOR: f32 = 2.0;
+
+// Where the camera is and looks, the view is a cone around the direction
+#[derive(Copy, Clone, Debug)]
+pub struct TaskFocus {
+    pub position: Point3D<f32, WorldSpace>,
+    pub direction: Vector3D<f32, WorldSpace>,
+    // Cosine of the half angle of the cone
+    pub view_cosine: f32,
+}
+
+impl TaskFocus {
+    // Distance from the camera to the bounds of the chunk, so the chunk under
+    // the camera is at 0. The closest point of the bounds decides whether the
+    // chunk is in view and its angle to the look direction.
+    pub fn priority(&self, key: &ChunkCacheKey) -> f32 {
+        let bounds = key.bounds.to_f32();
+        let offset = self.position.clamp(bounds.min, bounds.max) - self.position;
+        let distance = offset.length();
+        if distance <= 0.0 {
+            return 0.0;
+        }
+        if !self.in_view(&bounds) {
+            return distance * OFF_SCREEN_FACTOR;
+        }
+        let cosine = offset.dot(self.direction) / distance;
+        let border = ((1.0 - cosine) / (1.0 - self.view_cosine).max(f32::EPSILON)).min(1.0);
+        distance * (1.0 + (VIEW_BORDER_FACTOR - 1.0) * border)
+    }
+
+    pub fn in_view(&self, bounds: &Box3D<f32, WorldSpace>) -> bool {
+        let offset = self.position.clamp(bounds.min, bounds.max) - self.position;
+        let distance = offset.length();
+        distance <= 0.0 || offset.dot(self.direction) >= distance * self.view_cosine
+    }
+}
+
+struct QueuedTask {
+    // Only taken once every other task is done, see TaskQueue::push_idle
+    idle: bool,
+    priority: f32,
+    level: u32,
+    // Order of the pushes, ties are taken first in first out
+    sequence: u64,
+    task: TerrainTask,
+}
+
+// The heap pops the greatest task: a task that is not idle, the lowest
+// priority, then the finest level, then the oldest
+impl Ord for QueuedTask {
+    fn cmp(&self, other: &Self) -> Ordering {
+        other
+            .idle
+            .cmp(&self.idle)
+            .then(
+                other
+                    .priority
+                    .partial_cmp(&self.priority)
+                    .unwrap_or(Ordering::Equal),
+            )
+            .then(self.level.cmp(&other.level))
+            .then(other.sequence.cmp(&self.sequence))
+    }
+}
+
+impl PartialOrd for QueuedTask {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl PartialEq for QueuedTask {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for QueuedTask {}
+
+struct QueueState {
+    heap: BinaryHeap<QueuedTask>,
+    // Without a focus every task has the same priority
+    focus: Option<TaskFocus>,
+    next_sequence: u64,
+}
+
+// Global queue of the workers, tasks are picked by the distance of their
+// chunk to the camera and its angle to the look direction instead of the
+// order they were pushed in
+pub struct TaskQueue {
+    state: Mutex<QueueState>,
+}
+
+impl TaskQueue {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(QueueState {
+                heap: BinaryHeap::new(),
+                focus: None,
+                next_sequence: 0,
+            }),
+        }
+    }
+
+    pub fn push(&self, task: TerrainTask) {
+        self.push_task(task, false);
+    }
+
+    // Behind every other task whatever their priorities, for work that only
+    // improves the quality
+    pub fn push_idle(&self, task: TerrainTask) {
+        self.push_task(task, true);
+    }
+
+    fn push_task(&self, task: TerrainTask, idle: bool) {
+        let mut state = self.state.lock();
+        let key = task.key();
+        let queued = QueuedTask {
+            idle,
+            priority: state.focus.map_or(0.0, |x| x.priority(&key)),
+            level: key.level,
+            sequence: state.next_sequence,
+            task,
+        };
+        state.next_sequence += 1;
+        state.heap.push(queued);
+    }
+
+    pub fn pop(&self) -> Option<TerrainTask> {
+        self.state.lock().heap.pop().map(|x| x.task)
+    }
+
+    pub fn len(&self) -> usize {
+        self.state.lock().heap.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.state.lock().heap.is_empty()
+    }
+
+    // The queued tasks are ordered again around the new focus, as are the
+    // ones pushed later
+    pub fn set_focus(&self, focus: TaskFocus) {
+        let mut state = self.state.lock();
+        state.focus = Some(focus);
+        let mut tasks = std::mem::take(&mut state.heap).into_vec();
+        for queued in tasks.iter_mut() {
+            queued.priority = focus.priority(&queued.task.key());
+        }
+        state.heap = tasks.into();
+    }
+}
@@ -0,0 +1,113 @@
+use super::ChunkCacheKey;
+use parking_lot::Mutex;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+// Most chunk generations queued or running at once
+pub const MAX_PENDING_CHUNKS: usize = 256;
+// Task chains averaged for the generation latency
+const LATENCY_SAMPLES: usize = 64;
+
+// Keys with a GenerateChunk queued or running. A key is only queued once
+// until its task chain ends, and nothing is queued while the scheduler is
+// full so that the queue does not grow when generation falls behind.
+pub struct ChunkScheduler {
+    // Time each key was queued at
+    pending: Mutex<HashMap<ChunkCacheKey, Instant>>,
+    // From queued to the end of the chain, most recent last
+    latencies: Mutex<VecDeque<Duration>>,
+}
+
+impl ChunkScheduler {
+    pub fn new() -> Self {
+        Self {
+            pending: Mutex::new(HashMap::new()),
+            latencies: Mutex::new(VecDeque::with_capacity(LATENCY_SAMPLES)),
+        }
+    }
+
+    // Returns true if the key has to be queued
+    pub fn schedule(&self, key: &ChunkCacheKey) -> bool {
+        let mut pending = self.pending.lock();
+        if pending.len() >= MAX_PENDING_CHUNKS || pending.contains_key(key) {
+            return false;
+        }
+        pending.insert(*key, Instant::now());
+        true
+    }
+
+    pub fn finish(&self, key: &ChunkCacheKey) {
+        if let Some(queued) = self.pending.lock().remove(key) {
+            let mut latencies = self.latencies.lock();
+            if latencies.len() == LATENCY_SAMPLES {
+                latencies.pop_front();
+            }
+            latencies.push_back(queued.elapsed());
+        }
+    }
+
+    // Ends the chain without a latency sample
+    pub fn cancel(&self, key: &ChunkCacheKey) {
+        self.pending.lock().remove(key);
+    }
+
+    pub fn is_pending(&self, key: &ChunkCacheKey) -> bool {
+        self.pending.lock().contains_key(key)
+    }
+
+    pub fn pending_count(&self) -> usize {
+        self.pending.lock().len()
+    }
+
+    pub fn average_latency(&self) -> Duration {
+        let latencies = self.latencies.lock();
+        if latencies.is_empty() {
+            return Duration::ZERO;
+        }
+        latencies.iter().sum::<Duration>() / latencies.len() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ChunkScheduler, MAX_PENDING_CHUNKS};
+    use crate::game::terrain::{ChunkCacheKey, NoiseAlgorithm};
+    use euclid::{point3, Box3D};
+
+    fn key(x: i32) -> ChunkCacheKey {
+        ChunkCacheKey {
+            bounds: Box3D::new(point3(x, 0, 0), point3(x + 1, 1, 1)),
+            level: 0,
+            noise: NoiseAlgorithm::Perlin,
+            params: 0,
+        }
+    }
+
+    #[test]
+    fn refuses_keys_already_pending() {
+        let scheduler = ChunkScheduler::new();
+        assert!(scheduler.schedule(&key(0)));
+        assert!(!scheduler.schedule(&key(0)));
+        assert_eq!(scheduler.pending_count(), 1);
+        // Queued again once its chain ends
+        scheduler.finish(&key(0));
+        assert!(!scheduler.is_pending(&key(0)));
+        assert!(scheduler.schedule(&key(0)));
+    }
+
+    #[test]
+    fn refuses_keys_once_full() {
+        let scheduler = ChunkScheduler::new();
+        for x in 0..MAX_PENDING_CHUNKS as i32 {
+            assert!(scheduler.schedule(&key(x)));
+        }
+        let next = key(MAX_PENDING_CHUNKS as i32);
+        assert!(!scheduler.schedule(&next));
+        assert!(!scheduler.is_pending(&next));
+        // Cancelling frees a slot without a latency sample
+        scheduler.cancel(&key(0));
+        assert!(scheduler.schedule(&next));
+        assert_eq!(scheduler.pending_count(), MAX_PENDING_CHUNKS);
+        assert_eq!(scheduler.average_latency(), std::time::Duration::ZERO);
+    }
+}
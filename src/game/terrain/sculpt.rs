@@ -0,0 +1,33 @@
+use super::chunk::Chunk;
+use crate::game::base::WorldSpace;
+use euclid::{Box3D, Point3D};
+
+// Spherical brush applied to the density of the chunks on the GPU, positive
+// strength adds material and negative strength digs
+#[derive(Debug, Copy, Clone)]
+pub struct TerrainEdit {
+    pub center: Point3D<f32, WorldSpace>,
+    pub radius: f32,
+    pub strength: f32,
+}
+
+impl TerrainEdit {
+    pub fn bounds(&self) -> Box3D<f32, WorldSpace> {
+        Box3D::new(self.center, self.center).inflate(self.radius, self.radius, self.radius)
+    }
+
+    // Same brush on the CPU voxels, used to replay it on a chunk generated
+    // again. Keep in sync with shader.
+    pub fn apply(&self, chunk: &mut Chunk) -> bool {
+        if !chunk.bounds().to_f32().intersects(&self.bounds()) {
+            return false;
+        }
+        chunk.edit_voxels(|position, value| {
+            let center_distance = position.distance_to(self.center);
+            if center_distance >= self.radius {
+                return value;
+            }
+            (value + self.strength * (1.0 - center_distance / self.radius)).clamp(0.0, 1.0)
+        })
+    }
+}
@@ -0,0 +1,185 @@
+use super::chunk::Chunk;
+use super::edit::{smoothstep, SURFACE_THICKNESS};
+use crate::game::base::WorldSpace;
+use euclid::{vec2, Box2D, Box3D, Point2D, Point3D, Vector2D};
+
+// Footprint cells per world unit, the voxel spacing of the finest chunks
+const CELLS_PER_UNIT: f32 = 32.0;
+// Larger footprints get coarser cells
+const MAX_CELLS_PER_SIDE: usize = 1024;
+
+// Authored mesh the terrain is conformed to, e.g. a building foundation. Over
+// its footprint the ground is solid up to the bottom of the mesh and empty
+// through the mesh so nothing pokes through its floors, around it the ground
+// blends back to the terrain over the falloff distance.
+#[derive(Debug)]
+pub struct SetPiece {
+    // Footprint grid with the falloff around it
+    bounds: Box2D<f32, WorldSpace>,
+    cell_size: f32,
+    width: usize,
+    height: usize,
+    // Bottom and top of the mesh over each cell, none outside of the footprint
+    columns: Vec<Option<(f32, f32)>>,
+    // Distance to the closest footprint cell and its bottom, for every cell
+    closest: Vec<(f32, f32)>,
+    falloff: f32,
+    max_z: f32,
+}
+
+impl SetPiece {
+    // Triangles seen edge on from above do not cover any column, the faces
+    // around them close the footprint
+    pub fn new(triangles: &[[Point3D<f32, WorldSpace>; 3]], falloff: f32) -> Option<Self> {
+        if triangles.is_empty() {
+            return None;
+        }
+        let bounds = Box3D::from_points(triangles.iter().flatten());
+        let footprint = Box2D::new(bounds.min.xy(), bounds.max.xy());
+        if footprint.is_empty() {
+            return None;
+        }
+        let grid = footprint.inflate(falloff, falloff);
+        let cell_size =
+            (1.0 / CELLS_PER_UNIT).max(grid.width().max(grid.height()) / MAX_CELLS_PER_SIDE as f32);
+        let width = (grid.width() / cell_size).ceil() as usize + 1;
+        let height = (grid.height() / cell_size).ceil() as usize + 1;
+        let mut columns: Vec<Option<(f32, f32)>> = vec![None; width * height];
+        let center =
+            |x: usize, y: usize| grid.min + vec2(x as f32 + 0.5, y as f32 + 0.5) * cell_size;
+        for triangle in triangles {
+            let [a, b, c] = triangle.map(|x| x.xy());
+            let area = (b - a).cross(c - a);
+            if area.abs() <= f32::EPSILON {
+                continue;
+            }
+            let min = ((a.min(b).min(c) - grid.min) / cell_size).floor();
+            let max = ((a.max(b).max(c) - grid.min) / cell_size).ceil();
+            for y in min.y.max(0.0) as usize..(max.y as usize).min(height) {
+                for x in min.x.max(0.0) as usize..(max.x as usize).min(width) {
+                    let p = center(x, y);
+                    // Barycentric weights of the corners
+                    let u = (c - b).cross(p - b) / area;
+                    let v = (a - c).cross(p - c) / area;
+                    let w = 1.0 - u - v;
+                    if u < 0.0 || v < 0.0 || w < 0.0 {
+                        continue;
+                    }
+                    let z = triangle[0].z * u + triangle[1].z * v + triangle[2].z * w;
+                    let column = &mut columns[x + y * width];
+                    *column = Some(match *column {
+                        Some((bottom, top)) => (bottom.min(z), top.max(z)),
+                        None => (z, z),
+                    });
+                }
+            }
+        }
+        let closest = closest_columns(&columns, width, height, cell_size);
+        Some(Self {
+            bounds: grid,
+            cell_size,
+            width,
+            height,
+            columns,
+            closest,
+            falloff,
+            max_z: bounds.max.z,
+        })
+    }
+
+    pub fn intersects(&self, bounds: &Box3D<i32, WorldSpace>) -> bool {
+        let bounds = bounds.to_f32();
+        Box2D::new(bounds.min.xy(), bounds.max.xy()).intersects(&self.bounds)
+            && bounds.min.z <= self.max_z + SURFACE_THICKNESS
+    }
+
+    fn cell(&self, point: &Point2D<f32, WorldSpace>) -> Option<usize> {
+        let cell = ((*point - self.bounds.min) / self.cell_size).floor();
+        if cell.x < 0.0 || cell.y < 0.0 {
+            return None;
+        }
+        let (x, y) = (cell.x as usize, cell.y as usize);
+        if x < self.width && y < self.height {
+            Some(x + y * self.width)
+        } else {
+            None
+        }
+    }
+
+    // Returns true if any voxel of the chunk was modified
+    pub fn apply(&self, chunk: &mut Chunk) -> bool {
+        if !self.intersects(&chunk.bounds()) {
+            return false;
+        }
+        let solid_below = |z: f32, height: f32| {
+            1.0 - smoothstep(height - SURFACE_THICKNESS, height + SURFACE_THICKNESS, z)
+        };
+        chunk.edit_voxels(|position, value| {
+            let cell = match self.cell(&position.xy()) {
+                Some(cell) => cell,
+                None => return value,
+            };
+            match self.columns[cell] {
+                // Ground above the mesh is left as is
+                Some((_, top)) if position.z > top + SURFACE_THICKNESS => value,
+                Some((bottom, _)) => solid_below(position.z, bottom),
+                None => {
+                    let (distance, bottom) = self.closest[cell];
+                    if distance >= self.falloff {
+                        return value;
+                    }
+                    let weight = 1.0 - distance / self.falloff;
+                    value + (solid_below(position.z, bottom) - value) * weight
+                }
+            }
+        })
+    }
+}
+
+// Two pass chamfer distance transform carrying the bottom of the closest
+// footprint cell along, cells without one stay infinitely far
+fn closest_columns(
+    columns: &[Option<(f32, f32)>],
+    width: usize,
+    height: usize,
+    cell_size: f32,
+) -> Vec<(f32, f32)> {
+    let mut closest = columns
+        .iter()
+        .map(|x| match x {
+            Some((bottom, _)) => (0.0, *bottom),
+            None => (f32::INFINITY, 0.0),
+        })
+        .collect::<Vec<_>>();
+    let diagonal = cell_size * std::f32::consts::SQRT_2;
+    let forward = [
+        (vec2(-1, -1), diagonal),
+        (vec2(0, -1), cell_size),
+        (vec2(1, -1), diagonal),
+        (vec2(-1, 0), cell_size),
+    ];
+    let backward = forward.map(|(offset, distance)| (-offset, distance));
+    let mut relax = |x: usize, y: usize, offsets: &[(Vector2D<i32, WorldSpace>, f32)]| {
+        for (offset, distance) in offsets {
+            let (nx, ny) = (x as i32 + offset.x, y as i32 + offset.y);
+            if nx < 0 || ny < 0 || nx as usize >= width || ny as usize >= height {
+                continue;
+            }
+            let (neighbor, bottom) = closest[nx as usize + ny as usize * width];
+            if neighbor + distance < closest[x + y * width].0 {
+                closest[x + y * width] = (neighbor + distance, bottom);
+            }
+        }
+    };
+    for y in 0..height {
+        for x in 0..width {
+            relax(x, y, &forward);
+        }
+    }
+    for y in (0..height).rev() {
+        for x in (0..width).rev() {
+            relax(x, y, &backward);
+        }
+    }
+    closest
+}
@@ -0,0 +1,13 @@
+// Islands below the middle of the surface band and mountains above. Custom
+// generators define the same function and can use everything declared
+// before it in generate_voxel.wgsl.
+fn density(pos: vec3<f32>) -> f32 {
+    let midpoint = mix(chunk_info.surface_min_z, chunk_info.surface_max_z, 0.5);
+    var value: f32;
+    if (pos.z < midpoint) {
+        value = pow(island_noise(vec3<i32>(0), pos), abs((pos.z + 0.5) * 2.0));
+    } else {
+        value = island_noise(vec3<i32>(0), vec3<f32>(pos.xy, midpoint)) * mountain_noise(vec3<i32>(0), pos, midpoint, chunk_info.surface_max_z);
+    }
+    return smoothStep(0.0, 1.0, value);
+}
@@ -0,0 +1,27 @@
+// Turns the triangle count at the start of a triangle buffer into the
+// arguments of a non indexed indirect draw
+
+// Only the count of the layout of generate_triangle.wgsl
+[[block]]
+struct TriangleCount {
+    count: u32;
+};
+
+[[block]]
+struct DrawArgs {
+    vertex_count: u32;
+    instance_count: u32;
+    first_vertex: u32;
+    first_instance: u32;
+};
+
+[[group(0), binding(0)]] var<storage> triangle_buffer: TriangleCount;
+[[group(0), binding(1)]] var<storage, read_write> draw_args: DrawArgs;
+
+[[stage(compute), workgroup_size(1)]]
+fn main() {
+    draw_args.vertex_count = triangle_buffer.count * 3u;
+    draw_args.instance_count = 1u;
+    draw_args.first_vertex = 0u;
+    draw_args.first_instance = 0u;
+}
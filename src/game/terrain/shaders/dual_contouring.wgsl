@@ -0,0 +1,250 @@
+// GLOBALS
+// Pull of the mass point on the QEF solution, keeps the vertex of flat and
+// nearly flat elements from sliding along the surface
+let QEF_BIAS: f32 = 0.05;
+
+// STRUCTS
+
+[[block]]
+struct GenerateTriangleInfo {
+    cell_count: vec3<u32>;
+    isolevel: f32;
+    // World bounds of the chunk
+    min: vec3<f32>;
+    max: vec3<f32>;
+};
+
+struct Voxel {
+    value : f32;
+};
+
+[[block]]
+struct VoxelBuffer {
+    buffer : array<Voxel>;
+};
+
+// Keep in sync with generate_triangle.wgsl and surface_nets.wgsl
+struct Triangle {                 //            align(16) size(80)
+    // w holds the packed vertex color, see vertex_color.wgsl
+    position: array<vec4<f32>,3>; // offset(0)  align(16) size(48)
+    id : array<vec2<u32>,3>;      // offset(48) align(8)  size(24)
+    // padding                       offset(72) align(8)  size(8)
+};
+
+[[block]]
+struct TriangleBuffer {
+    count: atomic<u32>;           // offset(0)  align(4)  size(4)
+    buffer : array<Triangle>;     // offset(16) align(16) size(80)
+};
+
+[[group(0), binding(0)]] var<uniform> info: GenerateTriangleInfo;
+[[group(0), binding(1)]] var<storage> voxel_buffer: VoxelBuffer;
+[[group(0), binding(2)]] var<storage, read_write> triangle_buffer: TriangleBuffer;
+
+// Weights of each voxel column
+[[block]]
+struct BiomeColumns {
+    weights: array<vec4<f32>>;
+};
+
+[[group(0), binding(3)]] var<storage> biome_columns: BiomeColumns;
+
+// UTIL FUNCTIONS
+
+// Corner in local space with the color of the closest voxel column at its
+// altitude. Keep in sync with ChunkMesh::vertex_biome_weights
+fn colored_corner(p: vec3<f32>) -> vec4<f32> {
+    let count = info.cell_count + vec3<u32>(1u, 1u, 1u);
+    let last = vec2<f32>(info.cell_count.xy);
+    let column = vec2<u32>(clamp(round(p.xy * last), vec2<f32>(0.0, 0.0), last));
+    let altitude = mix(info.min.z, info.max.z, p.z);
+    let color = vertex_color(altitude, biome_columns.weights[column.x + count.x * column.y]);
+    return vec4<f32>(p, pack_color(color));
+}
+
+fn point_to_index(p: vec3<u32>, size: vec3<u32>) -> u32 {
+    return p.x + size.x * (p.y + size.y * p.z);
+}
+
+fn value(p: vec3<u32>) -> f32 {
+    return voxel_buffer.buffer[point_to_index(p, info.cell_count + 1u)].value;
+}
+
+// Central differences, one sided on the border of the chunk
+fn gradient(p: vec3<u32>) -> vec3<f32> {
+    let lo = max(p, vec3<u32>(1u)) - 1u;
+    let hi = min(p + 1u, info.cell_count);
+    return vec3<f32>(
+        (value(vec3<u32>(hi.x, p.y, p.z)) - value(vec3<u32>(lo.x, p.y, p.z))) / f32(hi.x - lo.x),
+        (value(vec3<u32>(p.x, hi.y, p.z)) - value(vec3<u32>(p.x, lo.y, p.z))) / f32(hi.y - lo.y),
+        (value(vec3<u32>(p.x, p.y, hi.z)) - value(vec3<u32>(p.x, p.y, lo.z))) / f32(hi.z - lo.z),
+    );
+}
+
+fn corner(box_min: vec3<u32>, box_max: vec3<u32>, i: u32) -> vec3<u32> {
+    return select(box_min, box_max, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+}
+
+// Vertex of the voxel grid box from box_min to box_max, in voxel units. The
+// box is a cell, or for cells outside of the chunk the face, edge or corner
+// they share with it, so neighbors place the same vertex on their border.
+fn dual_vertex(box_min: vec3<u32>, box_max: vec3<u32>) -> vec3<f32> {
+    let isolevel = info.isolevel;
+    // Normals are kept on the axes the box spans so that neighbors solve the
+    // same QEF for a shared face
+    let span = vec3<f32>(box_max - box_min);
+    // Columns of A^T A
+    var ata_x = vec3<f32>(0.0);
+    var ata_y = vec3<f32>(0.0);
+    var ata_z = vec3<f32>(0.0);
+    var atb = vec3<f32>(0.0);
+    var mass = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var i: u32 = 0u; i < 8u; i = i + 1u) {
+        for (var axis: u32 = 0u; axis < 3u; axis = axis + 1u) {
+            let bit = 1u << axis;
+            if ((i & bit) != 0u) {
+                continue;
+            }
+            let c0 = corner(box_min, box_max, i);
+            let c1 = corner(box_min, box_max, i | bit);
+            if (all(c0 == c1)) {
+                continue;
+            }
+            let v0 = value(c0);
+            let v1 = value(c1);
+            if ((v0 < isolevel) == (v1 < isolevel)) {
+                continue;
+            }
+            let t = (isolevel - v0) / (v1 - v0);
+            let p = mix(vec3<f32>(c0), vec3<f32>(c1), vec3<f32>(t));
+            var n = mix(gradient(c0), gradient(c1), vec3<f32>(t)) * span;
+            if (length(n) > 0.00001) {
+                n = normalize(n);
+            }
+            ata_x = ata_x + n * n.x;
+            ata_y = ata_y + n * n.y;
+            ata_z = ata_z + n * n.z;
+            atb = atb + n * dot(n, p);
+            mass = mass + p;
+            count = count + 1.0;
+        }
+    }
+    if (count == 0.0) {
+        return mix(vec3<f32>(box_min), vec3<f32>(box_max), vec3<f32>(0.5));
+    }
+    mass = mass / count;
+
+    // Offset from the mass point, (A^T A + bias) d = A^T b - A^T A mass
+    // solved with Cramer's rule
+    let a = ata_x + vec3<f32>(QEF_BIAS, 0.0, 0.0);
+    let b = ata_y + vec3<f32>(0.0, QEF_BIAS, 0.0);
+    let c = ata_z + vec3<f32>(0.0, 0.0, QEF_BIAS);
+    let r = atb - (ata_x * mass.x + ata_y * mass.y + ata_z * mass.z);
+    let det = dot(a, cross(b, c));
+    let d = vec3<f32>(
+        dot(r, cross(b, c)),
+        dot(a, cross(r, c)),
+        dot(a, cross(b, r)),
+    ) / det;
+    return clamp(mass + d, vec3<f32>(box_min), vec3<f32>(box_max));
+}
+
+fn element_min(cell: vec3<i32>) -> vec3<u32> {
+    return vec3<u32>(clamp(cell, vec3<i32>(0), vec3<i32>(info.cell_count)));
+}
+
+fn element_max(cell: vec3<i32>) -> vec3<u32> {
+    return vec3<u32>(clamp(cell + vec3<i32>(1), vec3<i32>(0), vec3<i32>(info.cell_count)));
+}
+
+fn element_vertex(cell: vec3<i32>) -> vec3<f32> {
+    return dual_vertex(element_min(cell), element_max(cell)) / vec3<f32>(info.cell_count);
+}
+
+// The corners of the box identify the vertex. Keep in sync with EdgeId in
+// edge_id.rs
+fn element_id(cell: vec3<i32>) -> vec2<u32> {
+    let size = info.cell_count + 1u;
+    return vec2<u32>(
+        point_to_index(element_min(cell), size),
+        point_to_index(element_max(cell), size)
+    );
+}
+
+fn emit_quad(c0: vec3<i32>, c1: vec3<i32>, c2: vec3<i32>, c3: vec3<i32>) {
+    let p0 = colored_corner(element_vertex(c0));
+    let p1 = colored_corner(element_vertex(c1));
+    let p2 = colored_corner(element_vertex(c2));
+    let p3 = colored_corner(element_vertex(c3));
+    let i0 = element_id(c0);
+    let i1 = element_id(c1);
+    let i2 = element_id(c2);
+    let i3 = element_id(c3);
+    var index = atomicAdd(&triangle_buffer.count, 2u);
+    triangle_buffer.buffer[index].position = array<vec4<f32>,3>(p0, p1, p2);
+    triangle_buffer.buffer[index].id = array<vec2<u32>,3>(i0, i1, i2);
+    triangle_buffer.buffer[index + 1u].position = array<vec4<f32>,3>(p0, p2, p3);
+    triangle_buffer.buffer[index + 1u].id = array<vec2<u32>,3>(i0, i2, i3);
+}
+
+// Quad of the four cells around a crossing edge, listed counterclockwise
+// seen from the end of the edge so that the front faces the air
+fn emit_edge(c0: vec3<i32>, c1: vec3<i32>, c2: vec3<i32>, c3: vec3<i32>, end_is_air: bool) {
+    if (end_is_air) {
+        emit_quad(c0, c1, c2, c3);
+    } else {
+        emit_quad(c0, c3, c2, c1);
+    }
+}
+
+// One invocation for each voxel, for the edges going from it toward +x, +y
+// and +z
+[[stage(compute), workgroup_size(8u,8u,8u)]]
+fn main(
+    [[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>,
+) {
+    let p = global_invocation_id;
+    if (any(p > info.cell_count)) {
+        return;
+    }
+    let isolevel = info.isolevel;
+    let air = value(p) < isolevel;
+    let cell = vec3<i32>(p);
+    if (p.x < info.cell_count.x) {
+        let end_is_air = value(p + vec3<u32>(1u, 0u, 0u)) < isolevel;
+        if (end_is_air != air) {
+            emit_edge(
+                cell - vec3<i32>(0, 1, 1),
+                cell - vec3<i32>(0, 0, 1),
+                cell,
+                cell - vec3<i32>(0, 1, 0),
+                end_is_air
+            );
+        }
+    }
+    if (p.y < info.cell_count.y) {
+        let end_is_air = value(p + vec3<u32>(0u, 1u, 0u)) < isolevel;
+        if (end_is_air != air) {
+            emit_edge(
+                cell - vec3<i32>(1, 0, 1),
+                cell - vec3<i32>(1, 0, 0),
+                cell,
+                cell - vec3<i32>(0, 0, 1),
+                end_is_air
+            );
+        }
+    }
+    if (p.z < info.cell_count.z) {
+        let end_is_air = value(p + vec3<u32>(0u, 0u, 1u)) < isolevel;
+        if (end_is_air != air) {
+            emit_edge(
+                cell - vec3<i32>(1, 1, 0),
+                cell - vec3<i32>(0, 1, 0),
+                cell,
+                cell - vec3<i32>(1, 0, 0),
+                end_is_air
+            );
+        }
+    }
+}
@@ -0,0 +1,199 @@
+// Hydraulic erosion on the height field of a chunk. The surface height of every
+// voxel column is extracted, water is rained on it and flows to the lower
+// neighbours carrying sediment, then the columns are shifted to follow the
+// eroded height.
+
+// Fraction of the surface difference moved to a neighbour per iteration
+let FLOW_RATE: f32 = 0.25;
+
+// STRUCTS
+
+[[block]]
+struct ErosionInfo {
+    voxel_count: vec3<u32>;
+    isolevel: f32;
+    min: vec3<f32>;
+    rain: f32;
+    max: vec3<f32>;
+    evaporation: f32;
+    capacity: f32;
+    erosion: f32;
+    deposition: f32;
+};
+
+struct Cell {
+    height: f32;
+    water: f32;
+    sediment: f32;
+    // Height before erosion
+    original: f32;
+};
+
+struct Flux {
+    // Water moved toward -x, +x, -y and +y
+    water: vec4<f32>;
+    // Sediment carried per unit of water
+    sediment_ratio: f32;
+};
+
+[[block]]
+struct VoxelBuffer {
+    buffer: array<f32>;
+};
+
+[[block]]
+struct CellBuffer {
+    buffer: array<Cell>;
+};
+
+[[block]]
+struct FluxBuffer {
+    buffer: array<Flux>;
+};
+
+[[group(0), binding(0)]] var<uniform> info: ErosionInfo;
+[[group(0), binding(1)]] var<storage, read_write> voxels: VoxelBuffer;
+// Copy of the voxels before erosion, read when shifting the columns
+[[group(0), binding(2)]] var<storage, read_write> source: VoxelBuffer;
+[[group(0), binding(3)]] var<storage, read_write> cells: CellBuffer;
+[[group(0), binding(4)]] var<storage, read_write> flux: FluxBuffer;
+
+// FUNCTIONS
+
+fn voxel_index(x: u32, y: u32, z: u32) -> u32 {
+    return x + info.voxel_count.x * (y + info.voxel_count.y * z);
+}
+
+fn column_index(x: u32, y: u32) -> u32 {
+    return x + info.voxel_count.x * y;
+}
+
+fn voxel_height() -> f32 {
+    return (info.max.z - info.min.z) / f32(info.voxel_count.z - 1u);
+}
+
+// Water leaves the chunk at the borders, the neighbour outside is taken as
+// the bare ground of the cell
+fn neighbour_surface(x: i32, y: i32, ground: f32) -> f32 {
+    if (x < 0 || y < 0 || x >= i32(info.voxel_count.x) || y >= i32(info.voxel_count.y)) {
+        return ground;
+    }
+    let cell = cells.buffer[column_index(u32(x), u32(y))];
+    return cell.height + cell.water;
+}
+
+// Topmost crossing of the isolevel in every column
+[[stage(compute), workgroup_size(8u, 8u, 1u)]]
+fn init_cells([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    if (id.x >= info.voxel_count.x || id.y >= info.voxel_count.y) {
+        return;
+    }
+    let top = info.voxel_count.z - 1u;
+    var height = info.min.z;
+    if (voxels.buffer[voxel_index(id.x, id.y, top)] >= info.isolevel) {
+        height = info.max.z;
+    }
+    for (var z: i32 = i32(top) - 1 ; z >= 0 ; z = z - 1) {
+        let below = voxels.buffer[voxel_index(id.x, id.y, u32(z))];
+        let above = voxels.buffer[voxel_index(id.x, id.y, u32(z) + 1u)];
+        if (below >= info.isolevel && above < info.isolevel) {
+            let t = (info.isolevel - below) / (above - below);
+            height = info.min.z + (f32(z) + t) * voxel_height();
+            break;
+        }
+    }
+    var cell: Cell;
+    cell.height = height;
+    cell.water = info.rain;
+    cell.sediment = 0.0;
+    cell.original = height;
+    cells.buffer[column_index(id.x, id.y)] = cell;
+}
+
+// Outgoing water of every cell, never more than the cell holds
+[[stage(compute), workgroup_size(8u, 8u, 1u)]]
+fn flow([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    if (id.x >= info.voxel_count.x || id.y >= info.voxel_count.y) {
+        return;
+    }
+    let cell = cells.buffer[column_index(id.x, id.y)];
+    let x = i32(id.x);
+    let y = i32(id.y);
+    let surface = cell.height + cell.water;
+    var water = max(vec4<f32>(0.0), vec4<f32>(
+        surface - neighbour_surface(x - 1, y, cell.height),
+        surface - neighbour_surface(x + 1, y, cell.height),
+        surface - neighbour_surface(x, y - 1, cell.height),
+        surface - neighbour_surface(x, y + 1, cell.height)
+    )) * FLOW_RATE;
+    let total = water.x + water.y + water.z + water.w;
+    if (total > cell.water) {
+        water = water * (cell.water / total);
+    }
+    var out: Flux;
+    out.water = water;
+    out.sediment_ratio = cell.sediment / max(cell.water, 0.000001);
+    flux.buffer[column_index(id.x, id.y)] = out;
+}
+
+// Water and sediment a neighbour sends to the cell, the direction selects the
+// component of its flux
+fn inflow(x: i32, y: i32, direction: vec4<f32>) -> vec2<f32> {
+    if (x < 0 || y < 0 || x >= i32(info.voxel_count.x) || y >= i32(info.voxel_count.y)) {
+        return vec2<f32>(0.0);
+    }
+    let f = flux.buffer[column_index(u32(x), u32(y))];
+    let water = dot(f.water, direction);
+    return vec2<f32>(water, water * f.sediment_ratio);
+}
+
+// Moves the water and sediment, then erodes or deposits depending on how much
+// sediment the flowing water can carry
+[[stage(compute), workgroup_size(8u, 8u, 1u)]]
+fn update([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    if (id.x >= info.voxel_count.x || id.y >= info.voxel_count.y) {
+        return;
+    }
+    let index = column_index(id.x, id.y);
+    var cell = cells.buffer[index];
+    let own = flux.buffer[index];
+    let x = i32(id.x);
+    let y = i32(id.y);
+    let outflow = own.water.x + own.water.y + own.water.z + own.water.w;
+    let incoming = inflow(x - 1, y, vec4<f32>(0.0, 1.0, 0.0, 0.0))
+        + inflow(x + 1, y, vec4<f32>(1.0, 0.0, 0.0, 0.0))
+        + inflow(x, y - 1, vec4<f32>(0.0, 0.0, 0.0, 1.0))
+        + inflow(x, y + 1, vec4<f32>(0.0, 0.0, 1.0, 0.0));
+    cell.water = max(cell.water - outflow + incoming.x, 0.0);
+    cell.sediment = max(cell.sediment - outflow * own.sediment_ratio + incoming.y, 0.0);
+    let capacity = info.capacity * (outflow + incoming.x) * 0.5;
+    if (cell.sediment > capacity) {
+        let deposit = (cell.sediment - capacity) * info.deposition;
+        cell.sediment = cell.sediment - deposit;
+        cell.height = cell.height + deposit;
+    } else {
+        let eroded = min((capacity - cell.sediment) * info.erosion, max(cell.height - info.min.z, 0.0));
+        cell.sediment = cell.sediment + eroded;
+        cell.height = cell.height - eroded;
+    }
+    cell.water = cell.water * (1.0 - info.evaporation) + info.rain;
+    cells.buffer[index] = cell;
+}
+
+// Every column is shifted by the height difference
+[[stage(compute), workgroup_size(8u, 8u, 8u)]]
+fn apply([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    if (id.x >= info.voxel_count.x || id.y >= info.voxel_count.y || id.z >= info.voxel_count.z) {
+        return;
+    }
+    let cell = cells.buffer[column_index(id.x, id.y)];
+    let top = f32(info.voxel_count.z - 1u);
+    let z = clamp(f32(id.z) + (cell.original - cell.height) / voxel_height(), 0.0, top);
+    let z0 = u32(floor(z));
+    let z1 = min(z0 + 1u, info.voxel_count.z - 1u);
+    voxels.buffer[voxel_index(id.x, id.y, id.z)] = mix(
+        source.buffer[voxel_index(id.x, id.y, z0)],
+        source.buffer[voxel_index(id.x, id.y, z1)],
+        fract(z)
+    );
+}
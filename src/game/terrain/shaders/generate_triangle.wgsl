@@ -276,6 +276,9 @@ struct GridCell {
 struct GenerateTriangleInfo {
     cell_count: vec3<u32>;
     isolevel: f32;
+    // World bounds of the chunk
+    min: vec3<f32>;
+    max: vec3<f32>;
 };
 
 struct Voxel {
@@ -288,7 +291,8 @@ struct VoxelBuffer {
 };
 
 struct Triangle {                 //            align(16) size(80)
-    position: array<vec3<f32>,3>; // offset(0)  align(16) size(48)
+    // w holds the packed vertex color, see vertex_color.wgsl
+    position: array<vec4<f32>,3>; // offset(0)  align(16) size(48)
     id : array<vec2<u32>,3>;      // offset(48) align(8)  size(24)
     // padding                       offset(72) align(8)  size(8)
 };
@@ -303,8 +307,27 @@ struct TriangleBuffer {
 [[group(0), binding(1)]] var<storage> voxel_buffer: VoxelBuffer;
 [[group(0), binding(2)]] var<storage, read_write> triangle_buffer: TriangleBuffer;
 
+// Weights of each voxel column
+[[block]]
+struct BiomeColumns {
+    weights: array<vec4<f32>>;
+};
+
+[[group(0), binding(3)]] var<storage> biome_columns: BiomeColumns;
+
 // UTIL FUNCTIONS
 
+// Corner in local space with the color of the closest voxel column at its
+// altitude. Keep in sync with ChunkMesh::vertex_biome_weights
+fn colored_corner(p: vec3<f32>) -> vec4<f32> {
+    let count = info.cell_count + vec3<u32>(1u, 1u, 1u);
+    let last = vec2<f32>(info.cell_count.xy);
+    let column = vec2<u32>(clamp(round(p.xy * last), vec2<f32>(0.0, 0.0), last));
+    let altitude = mix(info.min.z, info.max.z, p.z);
+    let color = vertex_color(altitude, biome_columns.weights[column.x + count.x * column.y]);
+    return vec4<f32>(p, pack_color(color));
+}
+
 fn vertex_lerp(isolevel: f32, p1: vec3<f32>, p2: vec3<f32>, v1: f32, v2: f32) -> vec3<f32> {
     var mu: f32;
     if (abs(isolevel - v1) < 0.00001) {
@@ -317,6 +340,7 @@ fn vertex_lerp(isolevel: f32, p1: vec3<f32>, p2: vec3<f32>, v1: f32, v2: f32) ->
     return p1 + mu * (p2 - p1);
 }
 
+// Keep in sync with EdgeId in edge_id.rs
 fn id_from_index(a: u32, b: u32) -> vec2<u32> {
     return vec2<u32>(min(a,b),max(a,b));
 }
@@ -401,7 +425,11 @@ fn polygonize_gridcell(cell: GridCell, isolevel: f32) {
             value[corner_index_2[c] ]
         );
         var index = atomicAdd(&triangle_buffer.count, 1u);
-        triangle_buffer.buffer[index].position = array<vec3<f32>,3>(vert_a, vert_b, vert_c);
+        triangle_buffer.buffer[index].position = array<vec4<f32>,3>(
+            colored_corner(vert_a),
+            colored_corner(vert_b),
+            colored_corner(vert_c)
+        );
         triangle_buffer.buffer[index].id = array<vec2<u32>,3>(vertex_id(a, cell.index),vertex_id(b, cell.index),vertex_id(c, cell.index));
     }
 }
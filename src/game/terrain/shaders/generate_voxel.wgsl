@@ -1,14 +1,51 @@
 // GLOBALS
 let SHADER_WORKGROUP_SIZE: u32 = 8u;
 
+// Biome order: ocean, plains, desert, mountain. Keep in sync with biome.rs
+let BIOME_COUNT: u32 = 4u;
+// Lakes only hold water where the basin is at least this deep into the mask
+let LAKE_THRESHOLD: f32 = 0.1;
+
+// Noise algorithms. Keep in sync with NoiseAlgorithm in terrain/mod.rs
+let NOISE_SIMPLEX: u32 = 1u;
+let NOISE_WORLEY: u32 = 2u;
+let NOISE_RIDGED_MULTIFRACTAL: u32 = 3u;
+
 // STRUCTS
 
+struct Biome {
+    sea_level: f32;
+    lake_level: f32;
+    lake_coverage: f32;
+    lake_depth: f32;
+};
+
 [[block]]
 struct GenerateVoxelInfo {
     voxel_count: vec3<u32>;
     lod: u32;
     min: vec3<f32>;
+    noise: u32;
     max: vec3<f32>;
+    warp_strength: f32;
+    biomes: array<Biome, 4>;
+    warp_frequency: f32;
+    seed: u32;
+    caves: u32;
+    cave_frequency: f32;
+    cave_radius: f32;
+    // Band of z the generated surface spans, the same for every chunk
+    surface_min_z: f32;
+    surface_max_z: f32;
+};
+
+struct WaterTable {
+    sea_level: f32;
+    lake_level: f32;
+    // Strength of the strongest lake mask, the density is pulled toward the
+    // lake basin by this amount
+    basin: f32;
+    floor: f32;
 };
 
 struct ChunkOutput {
@@ -23,11 +60,28 @@ struct OutputBuffer {
 [[group(0), binding(0)]] var<uniform> chunk_info: GenerateVoxelInfo;
 [[group(0), binding(1)]] var<storage, read_write> output_buffer: OutputBuffer;
 
+[[block]]
+struct WaterBuffer {
+    buffer : array<f32>;
+};
+
+// One water level per voxel column
+[[group(0), binding(2)]] var<storage, read_write> water_buffer: WaterBuffer;
+
+[[block]]
+struct BiomeBuffer {
+    buffer : array<vec4<f32>>;
+};
+
+// Weight of each biome per voxel column, in the order of biome_weights
+[[group(0), binding(3)]] var<storage, read_write> biome_buffer: BiomeBuffer;
+
 // FUNCTIONS
 
 fn inthash(x: vec3<u32>) -> vec3<f32> {
 	let k = 1103515245u;
-	var z: vec3<u32> = x;
+	// Every noise of the world changes with the seed
+	var z: vec3<u32> = x ^ vec3<u32>(chunk_info.seed);
     z = ((z >> vec3<u32>(8u)) ^ z.yzx)*k;
     z = ((z >> vec3<u32>(8u)) ^ z.yzx)*k;
     z = ((z >> vec3<u32>(8u)) ^ z.yzx)*k;
@@ -55,6 +109,58 @@ fn precision_noise(ix: vec3<i32>, fx: vec3<f32>) -> f32 {
                       dot( inthash( p + vec3<u32>(1u,1u,1u) ), w - vec3<f32>(1.0,1.0,1.0) ), u.x), u.y), u.z );
 }
 
+// Offsets are small enough for the simplex lattice to be skewed in floats
+fn simplex_noise(ix: vec3<i32>, fx: vec3<f32>) -> f32 {
+    let p = vec3<f32>(ix) + fx;
+    let s = floor(p + dot(p, vec3<f32>(1.0 / 3.0)));
+    let x = p - s + dot(s, vec3<f32>(1.0 / 6.0));
+    let e = step(vec3<f32>(0.0), x - x.yzx);
+    let i1 = e * (1.0 - e.zxy);
+    let i2 = 1.0 - e.zxy * (1.0 - e);
+    let x1 = x - i1 + 1.0 / 6.0;
+    let x2 = x - i2 + 1.0 / 3.0;
+    let x3 = x - 0.5;
+    let cell = vec3<i32>(s);
+    var w = max(0.6 - vec4<f32>(dot(x, x), dot(x1, x1), dot(x2, x2), dot(x3, x3)), vec4<f32>(0.0));
+    let d = vec4<f32>(
+        dot(inthash(vec3<u32>(cell)), x),
+        dot(inthash(vec3<u32>(cell + vec3<i32>(i1))), x1),
+        dot(inthash(vec3<u32>(cell + vec3<i32>(i2))), x2),
+        dot(inthash(vec3<u32>(cell + vec3<i32>(1))), x3)
+    );
+    w = w * w;
+    w = w * w;
+    return dot(d, w) * 32.0;
+}
+
+// Distance to the closest feature point, mapped to about the same range as
+// the gradient noises
+fn worley_noise(ix: vec3<i32>, fx: vec3<f32>) -> f32 {
+    let cell = ix + vec3<i32>(floor(fx));
+    let w = fract(fx);
+    var closest = 8.0;
+    for (var z: i32 = -1 ; z <= 1 ; z = z + 1) {
+        for (var y: i32 = -1 ; y <= 1 ; y = y + 1) {
+            for (var x: i32 = -1 ; x <= 1 ; x = x + 1) {
+                let offset = vec3<i32>(x, y, z);
+                let feature = vec3<f32>(offset) + inthash(vec3<u32>(cell + offset)) * 0.5 + 0.5;
+                closest = min(closest, distance(feature, w));
+            }
+        }
+    }
+    return closest * 2.0 - 1.0;
+}
+
+fn base_noise(ix: vec3<i32>, fx: vec3<f32>) -> f32 {
+    if (chunk_info.noise == NOISE_SIMPLEX) {
+        return simplex_noise(ix, fx);
+    }
+    if (chunk_info.noise == NOISE_WORLEY) {
+        return worley_noise(ix, fx);
+    }
+    return precision_noise(ix, fx);
+}
+
 fn precision_noise_fractal(ixyz: vec3<i32>, fxyz: vec3<f32>) -> f32 {
     let period = 2;
     var octaves = 3;
@@ -63,16 +169,30 @@ fn precision_noise_fractal(ixyz: vec3<i32>, fxyz: vec3<f32>) -> f32 {
 
     var value = 0.0;
     var curpersistence = 1.0;
+    var amplitude = 0.0;
+    // Ridges of an octave are damped where the previous octave is low
+    var weight = 1.0;
 
     var ispace = ixyz / period;
     var fspace = vec3<f32>(ixyz - ispace * period) / vec3<f32>(f32(period)) + fxyz / vec3<f32>(f32(period));
 
     for (var i: i32 = 0 ; i < octaves ; i = i + 1) {
-        value = value + precision_noise(ispace, fspace) * curpersistence;
+        if (chunk_info.noise == NOISE_RIDGED_MULTIFRACTAL) {
+            var signal = 1.0 - abs(precision_noise(ispace, fspace));
+            signal = signal * signal * weight;
+            weight = clamp(signal * 2.0, 0.0, 1.0);
+            value = value + signal * curpersistence;
+        } else {
+            value = value + base_noise(ispace, fspace) * curpersistence;
+        }
+        amplitude = amplitude + curpersistence;
         curpersistence = curpersistence * persistence;
         ispace = ispace * lacunarity;
         fspace = fspace * f32(lacunarity);
     }
+    if (chunk_info.noise == NOISE_RIDGED_MULTIFRACTAL) {
+        return value / amplitude * 2.0 - 1.0;
+    }
     return value;
 }
 
@@ -93,6 +213,87 @@ fn mountain_noise(ixyz: vec3<i32>, fxyz: vec3<f32>, midpoint: f32, height: f32)
     return 1.0 - noised_height;
 }
 
+// Weight of each biome, they always sum up to one
+fn biome_weights(xy: vec2<f32>) -> vec4<f32> {
+    let island = island_noise(vec3<i32>(0), vec3<f32>(xy, 0.0));
+    let land = land_noise(vec3<i32>(0), vec3<f32>(xy, 0.0));
+    let moisture = smoothStep(-0.7, 0.7, precision_noise_fractal(vec3<i32>(300), vec3<f32>(xy, 2.0)));
+    let ocean = 1.0 - smoothStep(0.3, 0.6, island);
+    let mountain = (1.0 - ocean) * (1.0 - smoothStep(0.3, 0.6, land));
+    let lowland = 1.0 - ocean - mountain;
+    let desert = lowland * (1.0 - smoothStep(0.3, 0.6, moisture));
+    return vec4<f32>(ocean, lowland - desert, desert, mountain);
+}
+
+fn add_biome_water(table: WaterTable, biome: Biome, weight: f32, lake_noise: f32) -> WaterTable {
+    var out = table;
+    out.sea_level = out.sea_level + biome.sea_level * weight;
+    let threshold = 1.0 - biome.lake_coverage;
+    let mask = weight * smoothStep(threshold, threshold + 0.05, lake_noise);
+    if (mask > out.basin) {
+        out.basin = mask;
+        out.lake_level = biome.lake_level;
+        out.floor = biome.lake_level - biome.lake_depth * mask;
+    }
+    return out;
+}
+
+// Sea level is blended between biomes, lakes belong to the biome with the
+// strongest mask
+fn water_table(xy: vec2<f32>) -> WaterTable {
+    let weights = biome_weights(xy);
+    let lake_noise = smoothStep(-0.7, 0.7, precision_noise_fractal(vec3<i32>(500), vec3<f32>(xy * 4.0, 3.0)));
+    var table: WaterTable;
+    table.sea_level = 0.0;
+    table.lake_level = 0.0;
+    table.basin = 0.0;
+    table.floor = 0.0;
+    table = add_biome_water(table, chunk_info.biomes[0], weights.x, lake_noise);
+    table = add_biome_water(table, chunk_info.biomes[1], weights.y, lake_noise);
+    table = add_biome_water(table, chunk_info.biomes[2], weights.z, lake_noise);
+    table = add_biome_water(table, chunk_info.biomes[3], weights.w, lake_noise);
+    return table;
+}
+
+fn water_level(table: WaterTable) -> f32 {
+    if (table.basin > LAKE_THRESHOLD) {
+        return max(table.sea_level, table.lake_level);
+    }
+    return table.sea_level;
+}
+
+// Offsets the sample position by a noise of the position itself so that the
+// features of the density bend into more organic shapes
+fn domain_warp(pos: vec3<f32>) -> vec3<f32> {
+    if (chunk_info.warp_strength <= 0.0) {
+        return pos;
+    }
+    let p = pos * chunk_info.warp_frequency;
+    let offset = vec3<f32>(
+        precision_noise_fractal(vec3<i32>(700), p),
+        precision_noise_fractal(vec3<i32>(800), p),
+        precision_noise_fractal(vec3<i32>(900), p)
+    );
+    return pos + offset * chunk_info.warp_strength;
+}
+
+// Tunnels run where two independent noises are both close to zero, which
+// traces worm like curves through the volume. Returns the amount of rock kept.
+fn cave_mask(pos: vec3<f32>) -> f32 {
+    if (chunk_info.caves == 0u) {
+        return 1.0;
+    }
+    let p = pos * chunk_info.cave_frequency;
+    let tunnel = length(vec2<f32>(
+        precision_noise_fractal(vec3<i32>(1100), p),
+        precision_noise_fractal(vec3<i32>(1200), p)
+    ));
+    return smoothStep(chunk_info.cave_radius * 0.5, chunk_info.cave_radius, tunnel);
+}
+
+// The density function of the active generator is inserted here
+// DENSITY
+
 fn index_to_point(i: u32, size: vec3<u32>) -> vec3<u32> {
     return vec3<u32>(
         i % size.x,
@@ -119,13 +320,16 @@ fn main(
     }
 	let point = index_to_point(index, chunk_info.voxel_count);
 	let pos = mix(chunk_info.min, chunk_info.max, vec3<f32>(point) / (vec3<f32>(chunk_info.voxel_count) - 1.0));
-    let midpoint = mix(chunk_info.min.z, chunk_info.max.z, f32(chunk_info.voxel_count.z / 2u) / f32(chunk_info.voxel_count.z));
-    var value: f32;
-    if (pos.z < midpoint) {
-        value = pow(island_noise(vec3<i32>(0), pos), abs((pos.z + 0.5) * 2.0));
-    } else {
-        value = island_noise(vec3<i32>(0), vec3<f32>(pos.xy, midpoint)) * mountain_noise(vec3<i32>(0), pos, midpoint, chunk_info.max.z);
-    }
-    value = smoothStep(0.0, 1.0, value);
+    var value = density(domain_warp(pos)) * cave_mask(pos);
+    // Lakes replace the terrain with a basin, solid below the floor and empty
+    // above so that the water table always sits on the ground
+    let table = water_table(pos.xy);
+    let basin = 1.0 - smoothStep(table.floor - 0.02, table.floor + 0.02, pos.z);
+    value = mix(value, basin, smoothStep(0.0, LAKE_THRESHOLD, table.basin));
 	output_buffer.buffer[index].value = value;
+    if (point.z == 0u) {
+        let column = point.x + chunk_info.voxel_count.x * point.y;
+        water_buffer.buffer[column] = water_level(table);
+        biome_buffer.buffer[column] = biome_weights(pos.xy);
+    }
 }
\ No newline at end of file
@@ -0,0 +1,78 @@
+// Vertex stage of render.wgsl for meshes drawn straight from the compute
+// output, appended to it when the pipeline is built. Without the CPU pass the
+// normals are flat and the attributes come from the closest voxel.
+// vertex_color.wgsl is prepended for the baked colors.
+
+// Same layout as the compute output of generate_triangle.wgsl
+struct Triangle {
+    position: array<vec4<f32>,3>;
+    id : array<vec2<u32>,3>;
+};
+
+[[block]]
+struct TriangleBuffer {
+    count: u32;
+    buffer : array<Triangle>;
+};
+
+[[group(1), binding(0)]] var<storage> triangle_buffer: TriangleBuffer;
+
+[[block]]
+struct ChunkData {
+    voxel_count: vec4<u32>;
+};
+
+[[group(1), binding(1)]]
+var<uniform> chunk_data: ChunkData;
+
+// Weights of each voxel column
+[[block]]
+struct BiomeColumns {
+    weights: array<vec4<f32>>;
+};
+
+[[group(1), binding(2)]] var<storage> biome_columns: BiomeColumns;
+
+// Enclosure of each voxel, four to a word. Keep in sync with enclosure.rs
+[[block]]
+struct EnclosureValues {
+    values: array<u32>;
+};
+
+[[group(1), binding(3)]] var<storage> enclosure_values: EnclosureValues;
+
+// Like Enclosure::at and ChunkMesh::vertex_biome_weights
+fn closest_voxel(position: vec3<f32>) -> vec3<u32> {
+    let last = vec3<f32>(max(chunk_data.voxel_count.xyz, vec3<u32>(2u)) - vec3<u32>(1u));
+    return vec3<u32>(clamp(round(position * last), vec3<f32>(0.0), last));
+}
+
+// Drawn indirectly with the triangle count, see draw_args.wgsl
+[[stage(vertex)]]
+fn gpu_mesh([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let triangle_index = vertex_index / 3u;
+    let p0 = triangle_buffer.buffer[triangle_index].position[0].xyz;
+    let p1 = triangle_buffer.buffer[triangle_index].position[1].xyz;
+    let p2 = triangle_buffer.buffer[triangle_index].position[2].xyz;
+    let corner = triangle_buffer.buffer[triangle_index].position[vertex_index % 3u];
+    let position = vec4<f32>(corner.xyz, 1.0);
+    out.position =
+        camera_data.projection_matrix *
+        camera_data.view_matrix *
+        mesh_data.world_matrix *
+        position;
+    out.color = vec4<f32>(0.0, 0.8, 0.5, 1.0);
+    // Flat normal with the same winding as Mesh::calculate_normals
+    out.normal = vec4<f32>(cross(p1 - p0, p0 - p2), 0.0);
+    // The surface is not classified without the CPU pass
+    out.traversability = 0.0;
+    let voxel = closest_voxel(position.xyz);
+    let count = chunk_data.voxel_count;
+    out.biome_weights = biome_columns.weights[voxel.x + count.x * voxel.y];
+    let i = voxel.x + count.x * (voxel.y + count.y * voxel.z);
+    out.enclosure = f32((enclosure_values.values[i / 4u] >> (8u * (i % 4u))) & 255u) / 255.0;
+    out.world_position = (mesh_data.world_matrix * position).xyz;
+    out.tint = unpack_color(corner.w);
+    return out;
+}
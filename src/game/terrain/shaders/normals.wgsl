@@ -0,0 +1,102 @@
+// Smooth normals of a chunk mesh written into its vertex buffer. The face
+// normals are weighted by their area like Mesh::calculate_normals and summed
+// in fixed point since there is no atomic float add.
+
+// STRUCTS
+
+[[block]]
+struct NormalInfo {
+    // Vertices of the mesh, the ones after it copy the normal of a mesh vertex
+    mesh_vertex_count: u32;
+    vertex_count: u32;
+    face_count: u32;
+    // Stride of VertexData
+    vertex_words: u32;
+    // Fixed point scale of the face normals
+    scale: f32;
+};
+
+[[block]]
+struct IndexBuffer {
+    values: array<u32>;
+};
+
+[[block]]
+struct SumBuffer {
+    values: array<atomic<i32>>;
+};
+
+// VertexData in chunk_mesh.rs, the position is its first four words and the
+// normal the next four
+[[block]]
+struct VertexBuffer {
+    values: array<f32>;
+};
+
+[[block]]
+struct SourceBuffer {
+    values: array<u32>;
+};
+
+[[group(0), binding(0)]] var<uniform> info: NormalInfo;
+[[group(0), binding(1)]] var<storage> indices: IndexBuffer;
+// Zeroed when created, three per mesh vertex
+[[group(0), binding(2)]] var<storage, read_write> sums: SumBuffer;
+[[group(0), binding(3)]] var<storage, read_write> vertices: VertexBuffer;
+// Mesh vertex of each vertex after the mesh
+[[group(0), binding(4)]] var<storage> sources: SourceBuffer;
+
+// FUNCTIONS
+
+fn vertex_position(i: u32) -> vec3<f32> {
+    let offset = i * info.vertex_words;
+    return vec3<f32>(vertices.values[offset], vertices.values[offset + 1u], vertices.values[offset + 2u]);
+}
+
+fn add_normal(i: u32, normal: vec3<f32>) {
+    atomicAdd(&sums.values[i * 3u], i32(round(normal.x)));
+    atomicAdd(&sums.values[i * 3u + 1u], i32(round(normal.y)));
+    atomicAdd(&sums.values[i * 3u + 2u], i32(round(normal.z)));
+}
+
+[[stage(compute), workgroup_size(64u)]]
+fn accumulate_normals([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let face = global_invocation_id.x;
+    if (face >= info.face_count) {
+        return;
+    }
+    let i0 = indices.values[face * 3u];
+    let i1 = indices.values[face * 3u + 1u];
+    let i2 = indices.values[face * 3u + 2u];
+    let p0 = vertex_position(i0);
+    let p1 = vertex_position(i1);
+    let p2 = vertex_position(i2);
+    // Same winding as Mesh::calculate_normals
+    let normal = cross(p1 - p0, p0 - p2) * info.scale;
+    add_normal(i0, normal);
+    add_normal(i1, normal);
+    add_normal(i2, normal);
+}
+
+[[stage(compute), workgroup_size(64u)]]
+fn write_normals([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let i = global_invocation_id.x;
+    if (i >= info.vertex_count) {
+        return;
+    }
+    var source = i;
+    if (i >= info.mesh_vertex_count) {
+        source = sources.values[i - info.mesh_vertex_count];
+    }
+    let sum = vec3<f32>(
+        f32(atomicLoad(&sums.values[source * 3u])),
+        f32(atomicLoad(&sums.values[source * 3u + 1u])),
+        f32(atomicLoad(&sums.values[source * 3u + 2u]))
+    );
+    let normal = normalize(sum);
+    let offset = i * info.vertex_words + 4u;
+    vertices.values[offset] = normal.x;
+    vertices.values[offset + 1u] = normal.y;
+    vertices.values[offset + 2u] = normal.z;
+    vertices.values[offset + 3u] = 1.0;
+}
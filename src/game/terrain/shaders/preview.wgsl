@@ -0,0 +1,60 @@
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] normal: vec4<f32>;
+};
+
+[[block]]
+struct MeshData {
+    world_matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var mesh_data: MeshData;
+
+[[block]]
+struct CameraData {
+    view_matrix: mat4x4<f32>;
+    projection_matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(1)]]
+var camera_data: CameraData;
+
+// Same layout as the compute output of generate_triangle.wgsl
+struct Triangle {
+    position: array<vec3<f32>,3>;
+    id : array<vec2<u32>,3>;
+};
+
+[[block]]
+struct TriangleBuffer {
+    count: u32;
+    buffer : array<Triangle>;
+};
+
+[[group(0), binding(2)]] var<storage> triangle_buffer: TriangleBuffer;
+
+// Drawn indirectly with the triangle count, see draw_args.wgsl
+[[stage(vertex)]]
+fn main([[builtin(vertex_index)]] vertex_index: u32) -> VertexOutput {
+    var out: VertexOutput;
+    let triangle_index = vertex_index / 3u;
+    let p0 = triangle_buffer.buffer[triangle_index].position[0];
+    let p1 = triangle_buffer.buffer[triangle_index].position[1];
+    let p2 = triangle_buffer.buffer[triangle_index].position[2];
+    let position = triangle_buffer.buffer[triangle_index].position[vertex_index % 3u];
+    out.position =
+        camera_data.projection_matrix *
+        camera_data.view_matrix *
+        mesh_data.world_matrix *
+        vec4<f32>(position, 1.0);
+    // Flat normal with the same winding as Mesh::calculate_normals
+    out.normal = vec4<f32>(cross(p1 - p0, p0 - p2), 0.0);
+    return out;
+}
+
+[[stage(fragment)]]
+fn main([[location(0)]] normal : vec4<f32>) -> [[location(0)]] vec4<f32> {
+    let normal = normalize(normal.xyz);
+    return vec4<f32>(normal.xyz / 2.0 + 0.5, 1.0);
+}
@@ -2,6 +2,12 @@ struct VertexOutput {
     [[builtin(position)]] position: vec4<f32>;
     [[location(0)]] color: vec4<f32>;
     [[location(1)]] normal: vec4<f32>;
+    [[location(2)]] traversability: f32;
+    [[location(3)]] biome_weights: vec4<f32>;
+    [[location(4)]] world_position: vec3<f32>;
+    [[location(5)]] enclosure: f32;
+    // Baked by the meshing passes, see vertex_color.wgsl
+    [[location(6)]] tint: vec3<f32>;
 };
 
 [[block]]
@@ -21,10 +27,95 @@ struct CameraData {
 [[group(0), binding(1)]]
 var camera_data: CameraData;
 
+// Biome order: ocean, plains, desert, mountain. Keep in sync with biome.rs
+struct BiomeStyle {
+    color: vec3<f32>;
+    snow_line: f32;
+    emissive: vec3<f32>;
+    scatter_density: f32;
+};
+
+[[block]]
+struct BiomeStyles {
+    styles: array<BiomeStyle, 4>;
+};
+
+[[group(0), binding(2)]]
+var<uniform> biome_styles: BiomeStyles;
+
+// Keep in sync with point_light.rs
+struct PointLight {
+    // Position and the distance the light reaches
+    position: vec4<f32>;
+    // Color and intensity
+    color: vec4<f32>;
+};
+
+[[block]]
+struct PointLights {
+    lights: array<PointLight, 16>;
+    count: u32;
+};
+
+[[group(0), binding(3)]]
+var<uniform> point_lights: PointLights;
+
+// Keep in sync with magma.rs
+[[block]]
+struct Magma {
+    color: vec3<f32>;
+    intensity: f32;
+    // Height below which the rock starts to glow
+    top: f32;
+    blend: f32;
+};
+
+[[group(0), binding(5)]]
+var<uniform> magma: Magma;
+
+let SNOW_COLOR: vec3<f32> = vec3<f32>(0.95, 0.95, 1.0);
+// Height over which the snow fades in above the snow line
+let SNOW_BLEND: f32 = 0.05;
+// Ambient light under the open sky and deep inside caves
+let SKY_AMBIENT: f32 = 0.4;
+let CAVE_AMBIENT: f32 = 0.05;
+// Enclosure from which cave walls start to glow
+let EMISSIVE_ENCLOSURE: f32 = 0.5;
+// Steps of the diffuse and point light in the stylized shading
+let LIGHT_BANDS: f32 = 4.0;
+
+// 4x4 Bayer matrix value at the pixel in [0, 1), from the interleaved bits
+// of x ^ y and y
+fn dither_threshold(coord: vec2<f32>) -> f32 {
+    let x = u32(coord.x) % 4u;
+    let y = u32(coord.y) % 4u;
+    let v = (((x ^ y) & 1u) << 3u) | ((y & 1u) << 2u) | ((x ^ y) & 2u) | ((y & 2u) >> 1u);
+    return (f32(v) + 0.5) / 16.0;
+}
+
+// Biome whose share of the cumulative weights contains the threshold. Keep in
+// sync with pick_biome in biome.rs
+fn pick_biome(weights: vec4<f32>, threshold: f32) -> u32 {
+    if (threshold < weights.x) {
+        return 0u;
+    }
+    if (threshold < weights.x + weights.y) {
+        return 1u;
+    }
+    if (threshold < weights.x + weights.y + weights.z) {
+        return 2u;
+    }
+    return 3u;
+}
+
 [[stage(vertex)]]
 fn main(
     [[location(0)]] position: vec4<f32>,
     [[location(1)]] normal: vec4<f32>,
+    [[location(2)]] traversability: f32,
+    [[location(3)]] biome_weights: vec4<f32>,
+    [[location(4)]] enclosure: f32,
+    [[location(5)]] tint: vec3<f32>,
 ) -> VertexOutput {
     var out: VertexOutput;
     var p =
@@ -35,12 +126,112 @@ fn main(
     out.color = vec4<f32>(0.0, 0.8, 0.5, 1.0);
     out.position = p;
     out.normal = normal;
+    out.traversability = traversability;
+    out.biome_weights = biome_weights;
+    out.world_position = (mesh_data.world_matrix * position).xyz;
+    out.enclosure = enclosure;
+    out.tint = tint;
     return out;
 }
 
+// Light rounded to the nearest of a few bands, 0 bands leaves it smooth
+fn quantize(light: f32, bands: f32) -> f32 {
+    if (bands <= 0.0) {
+        return light;
+    }
+    return floor(light * bands + 0.5) / bands;
+}
+
+fn shade(
+    normal: vec3<f32>,
+    threshold: f32,
+    biome_weights: vec4<f32>,
+    world_position: vec3<f32>,
+    enclosure: f32,
+    tint: vec3<f32>,
+    bands: f32,
+) -> vec4<f32> {
+    let light_dir = vec3<f32>(0.0,0.0,-1.0);
+    let style = biome_styles.styles[pick_biome(biome_weights, threshold)];
+    // Snow settles on flat ground and the fade is dithered as well
+    let snow = smoothStep(style.snow_line, style.snow_line + SNOW_BLEND, world_position.z) * abs(normal.z);
+    // Snow is not tinted
+    var color = style.color * tint;
+    if (snow > threshold) {
+        color = SNOW_COLOR;
+    }
+    // The sky lights neither the ambient nor the sun term inside caves
+    let sky = 1.0 - enclosure;
+    let ambient = mix(CAVE_AMBIENT, SKY_AMBIENT, sky);
+    let diffuse = quantize(max(dot(normal, -light_dir), 0.0) * sky, bands);
+    // Point lights fade out quadratically toward their radius
+    var point = vec3<f32>(0.0);
+    for (var i: u32 = 0u; i < point_lights.count; i = i + 1u) {
+        let light = point_lights.lights[i];
+        let to_light = light.position.xyz - world_position;
+        let distance = length(to_light);
+        let falloff = clamp(1.0 - distance / light.position.w, 0.0, 1.0);
+        let lambert = max(dot(normal, to_light / max(distance, 0.0001)), 0.0);
+        point = point + light.color.rgb * light.color.w * quantize(lambert * falloff * falloff, bands);
+    }
+    // Emissive materials glow deep inside caves, the alpha carries the glow to
+    // the bloom pass
+    var glow = style.emissive * smoothStep(EMISSIVE_ENCLOSURE, 1.0, enclosure);
+    if (snow > threshold) {
+        glow = vec3<f32>(0.0);
+    }
+    // Magma takes over deep enough below the ground whether or not the sky is
+    // open above it, so the floors of deep canyons glow as well
+    let molten = smoothStep(0.0, magma.blend, magma.top - world_position.z) * magma.intensity;
+    color = mix(color, magma.color, clamp(molten, 0.0, 1.0));
+    glow = max(glow, magma.color * molten);
+    let lit = color * (ambient + 0.6 * diffuse + point);
+    return vec4<f32>(lit + glow, clamp(max(glow.r, max(glow.g, glow.b)), 0.0, 1.0));
+}
+
+// Biomes are dithered into each other where their weights blend, so borders
+// show as a stipple instead of a muddy average of the colors
+[[stage(fragment)]]
+fn main(
+    [[builtin(position)]] coord: vec4<f32>,
+    [[location(1)]] normal: vec4<f32>,
+    [[location(3)]] biome_weights: vec4<f32>,
+    [[location(4)]] world_position: vec3<f32>,
+    [[location(5)]] enclosure: f32,
+    [[location(6)]] tint: vec3<f32>,
+) -> [[location(0)]] vec4<f32> {
+    let threshold = dither_threshold(coord.xy);
+    return shade(normalize(normal.xyz), threshold, biome_weights, world_position, enclosure, tint, 0.0);
+}
+
+// Low poly look, every triangle is lit flat by its own face normal in a few
+// bands of light and biomes meet along hard borders. The outline pass draws
+// the edges on top
 [[stage(fragment)]]
-fn main([[location(0)]] color : vec4<f32>, [[location(1)]] normal : vec4<f32>) -> [[location(0)]] vec4<f32> {
+fn stylized(
+    [[location(1)]] normal: vec4<f32>,
+    [[location(3)]] biome_weights: vec4<f32>,
+    [[location(4)]] world_position: vec3<f32>,
+    [[location(5)]] enclosure: f32,
+    [[location(6)]] tint: vec3<f32>,
+) -> [[location(0)]] vec4<f32> {
+    var face = normalize(cross(dpdx(world_position), dpdy(world_position)));
+    // The winding on screen flips the cross product, the vertex normal tells
+    // which side is out
+    if (dot(face, normal.xyz) < 0.0) {
+        face = -face;
+    }
+    return shade(face, 0.5, biome_weights, world_position, enclosure, tint, LIGHT_BANDS);
+}
+
+// Debug overlay, walkable is green, steep is yellow and cliff is red
+[[stage(fragment)]]
+fn traversability([[location(1)]] normal : vec4<f32>, [[location(2)]] traversability : f32) -> [[location(0)]] vec4<f32> {
     let normal = normalize(normal.xyz);
-    let light_dir = vec3<f32>(0.0,0.0,-1.0);
-    return vec4<f32>(normal.xyz / 2.0 + 0.5, 1.0);
-}
\ No newline at end of file
+    let color = mix(
+        mix(vec3<f32>(0.1, 0.8, 0.1), vec3<f32>(0.9, 0.8, 0.1), clamp(traversability, 0.0, 1.0)),
+        vec3<f32>(0.9, 0.1, 0.1),
+        clamp(traversability - 1.0, 0.0, 1.0)
+    );
+    return vec4<f32>(color * (0.6 + 0.4 * abs(normal.z)), 0.0);
+}
@@ -0,0 +1,41 @@
+// Spherical brush on the density of a chunk. Voxels inside the radius get the
+// strength added with a linear falloff toward the edge.
+
+// STRUCTS
+
+[[block]]
+struct SculptInfo {
+    voxel_count: vec3<u32>;
+    radius: f32;
+    min: vec3<f32>;
+    strength: f32;
+    max: vec3<f32>;
+    center: vec3<f32>;
+};
+
+[[block]]
+struct VoxelBuffer {
+    buffer: array<f32>;
+};
+
+[[group(0), binding(0)]] var<uniform> info: SculptInfo;
+[[group(0), binding(1)]] var<storage, read_write> voxels: VoxelBuffer;
+
+// ENTRY POINT
+
+[[stage(compute), workgroup_size(8u, 8u, 8u)]]
+fn main([[builtin(global_invocation_id)]] id: vec3<u32>) {
+    if (id.x >= info.voxel_count.x || id.y >= info.voxel_count.y || id.z >= info.voxel_count.z) {
+        return;
+    }
+    // Voxels are on the corners of the cells, like the CPU voxels
+    let step = (info.max - info.min) / vec3<f32>(max(info.voxel_count, vec3<u32>(2u)) - vec3<u32>(1u));
+    let position = info.min + vec3<f32>(id) * step;
+    let center_distance = distance(position, info.center);
+    if (center_distance >= info.radius) {
+        return;
+    }
+    let index = id.x + info.voxel_count.x * (id.y + info.voxel_count.y * id.z);
+    let value = voxels.buffer[index] + info.strength * (1.0 - center_distance / info.radius);
+    voxels.buffer[index] = clamp(value, 0.0, 1.0);
+}
@@ -0,0 +1,204 @@
+// STRUCTS
+
+[[block]]
+struct GenerateTriangleInfo {
+    cell_count: vec3<u32>;
+    isolevel: f32;
+    // World bounds of the chunk
+    min: vec3<f32>;
+    max: vec3<f32>;
+};
+
+struct Voxel {
+    value : f32;
+};
+
+[[block]]
+struct VoxelBuffer {
+    buffer : array<Voxel>;
+};
+
+// Keep in sync with generate_triangle.wgsl and dual_contouring.wgsl
+struct Triangle {                 //            align(16) size(80)
+    // w holds the packed vertex color, see vertex_color.wgsl
+    position: array<vec4<f32>,3>; // offset(0)  align(16) size(48)
+    id : array<vec2<u32>,3>;      // offset(48) align(8)  size(24)
+    // padding                       offset(72) align(8)  size(8)
+};
+
+[[block]]
+struct TriangleBuffer {
+    count: atomic<u32>;           // offset(0)  align(4)  size(4)
+    buffer : array<Triangle>;     // offset(16) align(16) size(80)
+};
+
+[[group(0), binding(0)]] var<uniform> info: GenerateTriangleInfo;
+[[group(0), binding(1)]] var<storage> voxel_buffer: VoxelBuffer;
+[[group(0), binding(2)]] var<storage, read_write> triangle_buffer: TriangleBuffer;
+
+// Weights of each voxel column
+[[block]]
+struct BiomeColumns {
+    weights: array<vec4<f32>>;
+};
+
+[[group(0), binding(3)]] var<storage> biome_columns: BiomeColumns;
+
+// UTIL FUNCTIONS
+
+// Corner in local space with the color of the closest voxel column at its
+// altitude. Keep in sync with ChunkMesh::vertex_biome_weights
+fn colored_corner(p: vec3<f32>) -> vec4<f32> {
+    let count = info.cell_count + vec3<u32>(1u, 1u, 1u);
+    let last = vec2<f32>(info.cell_count.xy);
+    let column = vec2<u32>(clamp(round(p.xy * last), vec2<f32>(0.0, 0.0), last));
+    let altitude = mix(info.min.z, info.max.z, p.z);
+    let color = vertex_color(altitude, biome_columns.weights[column.x + count.x * column.y]);
+    return vec4<f32>(p, pack_color(color));
+}
+
+fn point_to_index(p: vec3<u32>, size: vec3<u32>) -> u32 {
+    return p.x + size.x * (p.y + size.y * p.z);
+}
+
+fn value(p: vec3<u32>) -> f32 {
+    return voxel_buffer.buffer[point_to_index(p, info.cell_count + 1u)].value;
+}
+
+fn corner(box_min: vec3<u32>, box_max: vec3<u32>, i: u32) -> vec3<u32> {
+    return select(box_min, box_max, vec3<bool>((i & 1u) != 0u, (i & 2u) != 0u, (i & 4u) != 0u));
+}
+
+// Vertex of the voxel grid box from box_min to box_max, in voxel units, at
+// the average of the crossings on its edges. The box is a cell, or for cells
+// outside of the chunk the face, edge or corner they share with it, so
+// neighbors place the same vertex on their border.
+fn net_vertex(box_min: vec3<u32>, box_max: vec3<u32>) -> vec3<f32> {
+    let isolevel = info.isolevel;
+    var mass = vec3<f32>(0.0);
+    var count = 0.0;
+    for (var i: u32 = 0u; i < 8u; i = i + 1u) {
+        for (var axis: u32 = 0u; axis < 3u; axis = axis + 1u) {
+            let bit = 1u << axis;
+            if ((i & bit) != 0u) {
+                continue;
+            }
+            let c0 = corner(box_min, box_max, i);
+            let c1 = corner(box_min, box_max, i | bit);
+            if (all(c0 == c1)) {
+                continue;
+            }
+            let v0 = value(c0);
+            let v1 = value(c1);
+            if ((v0 < isolevel) == (v1 < isolevel)) {
+                continue;
+            }
+            let t = (isolevel - v0) / (v1 - v0);
+            mass = mass + mix(vec3<f32>(c0), vec3<f32>(c1), vec3<f32>(t));
+            count = count + 1.0;
+        }
+    }
+    if (count == 0.0) {
+        return mix(vec3<f32>(box_min), vec3<f32>(box_max), vec3<f32>(0.5));
+    }
+    return mass / count;
+}
+
+fn element_min(cell: vec3<i32>) -> vec3<u32> {
+    return vec3<u32>(clamp(cell, vec3<i32>(0), vec3<i32>(info.cell_count)));
+}
+
+fn element_max(cell: vec3<i32>) -> vec3<u32> {
+    return vec3<u32>(clamp(cell + vec3<i32>(1), vec3<i32>(0), vec3<i32>(info.cell_count)));
+}
+
+fn element_vertex(cell: vec3<i32>) -> vec3<f32> {
+    return net_vertex(element_min(cell), element_max(cell)) / vec3<f32>(info.cell_count);
+}
+
+// The corners of the box identify the vertex. Keep in sync with EdgeId in
+// edge_id.rs
+fn element_id(cell: vec3<i32>) -> vec2<u32> {
+    let size = info.cell_count + 1u;
+    return vec2<u32>(
+        point_to_index(element_min(cell), size),
+        point_to_index(element_max(cell), size)
+    );
+}
+
+fn emit_quad(c0: vec3<i32>, c1: vec3<i32>, c2: vec3<i32>, c3: vec3<i32>) {
+    let p0 = colored_corner(element_vertex(c0));
+    let p1 = colored_corner(element_vertex(c1));
+    let p2 = colored_corner(element_vertex(c2));
+    let p3 = colored_corner(element_vertex(c3));
+    let i0 = element_id(c0);
+    let i1 = element_id(c1);
+    let i2 = element_id(c2);
+    let i3 = element_id(c3);
+    var index = atomicAdd(&triangle_buffer.count, 2u);
+    triangle_buffer.buffer[index].position = array<vec4<f32>,3>(p0, p1, p2);
+    triangle_buffer.buffer[index].id = array<vec2<u32>,3>(i0, i1, i2);
+    triangle_buffer.buffer[index + 1u].position = array<vec4<f32>,3>(p0, p2, p3);
+    triangle_buffer.buffer[index + 1u].id = array<vec2<u32>,3>(i0, i2, i3);
+}
+
+// Quad of the four cells around a crossing edge, listed counterclockwise
+// seen from the end of the edge so that the front faces the air
+fn emit_edge(c0: vec3<i32>, c1: vec3<i32>, c2: vec3<i32>, c3: vec3<i32>, end_is_air: bool) {
+    if (end_is_air) {
+        emit_quad(c0, c1, c2, c3);
+    } else {
+        emit_quad(c0, c3, c2, c1);
+    }
+}
+
+// One invocation for each voxel, for the edges going from it toward +x, +y
+// and +z
+[[stage(compute), workgroup_size(8u,8u,8u)]]
+fn main(
+    [[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>,
+) {
+    let p = global_invocation_id;
+    if (any(p > info.cell_count)) {
+        return;
+    }
+    let isolevel = info.isolevel;
+    let air = value(p) < isolevel;
+    let cell = vec3<i32>(p);
+    if (p.x < info.cell_count.x) {
+        let end_is_air = value(p + vec3<u32>(1u, 0u, 0u)) < isolevel;
+        if (end_is_air != air) {
+            emit_edge(
+                cell - vec3<i32>(0, 1, 1),
+                cell - vec3<i32>(0, 0, 1),
+                cell,
+                cell - vec3<i32>(0, 1, 0),
+                end_is_air
+            );
+        }
+    }
+    if (p.y < info.cell_count.y) {
+        let end_is_air = value(p + vec3<u32>(0u, 1u, 0u)) < isolevel;
+        if (end_is_air != air) {
+            emit_edge(
+                cell - vec3<i32>(1, 0, 1),
+                cell - vec3<i32>(1, 0, 0),
+                cell,
+                cell - vec3<i32>(0, 0, 1),
+                end_is_air
+            );
+        }
+    }
+    if (p.z < info.cell_count.z) {
+        let end_is_air = value(p + vec3<u32>(0u, 0u, 1u)) < isolevel;
+        if (end_is_air != air) {
+            emit_edge(
+                cell - vec3<i32>(1, 1, 0),
+                cell - vec3<i32>(0, 1, 0),
+                cell,
+                cell - vec3<i32>(1, 0, 0),
+                end_is_air
+            );
+        }
+    }
+}
@@ -0,0 +1,39 @@
+// Vertex color baked into the triangles by the meshing passes, prepended to
+// generate_triangle.wgsl, dual_contouring.wgsl, surface_nets.wgsl and
+// gpu_mesh.wgsl. The color tints the biome color in render.wgsl. Keep in sync
+// with vertex_color.rs
+
+// Tints of the ground from low to high and from dry to wet
+let LOW_TINT: vec3<f32> = vec3<f32>(0.8, 0.9, 0.8);
+let HIGH_TINT: vec3<f32> = vec3<f32>(1.15, 1.1, 1.05);
+let DRY_TINT: vec3<f32> = vec3<f32>(1.15, 1.0, 0.8);
+let WET_TINT: vec3<f32> = vec3<f32>(0.85, 1.05, 0.95);
+// Altitudes over which the ground goes from the low to the high tint
+let LOW_ALTITUDE: f32 = -0.1;
+let HIGH_ALTITUDE: f32 = 0.4;
+// Channels are stored in 8 bits up to this value
+let TINT_RANGE: f32 = 2.0;
+
+// Everything but the desert is wet, mountains are half dry
+fn moisture(biome_weights: vec4<f32>) -> f32 {
+    return clamp(biome_weights.x + biome_weights.y + 0.5 * biome_weights.w, 0.0, 1.0);
+}
+
+fn vertex_color(altitude: f32, biome_weights: vec4<f32>) -> vec3<f32> {
+    let height = smoothStep(LOW_ALTITUDE, HIGH_ALTITUDE, altitude);
+    return mix(LOW_TINT, HIGH_TINT, height) * mix(DRY_TINT, WET_TINT, moisture(biome_weights));
+}
+
+// Stored in the unused w of a triangle corner. The channels take 24 bits so
+// the float holds them exactly as a whole number.
+fn pack_color(color: vec3<f32>) -> f32 {
+    let c = clamp(color / TINT_RANGE, vec3<f32>(0.0, 0.0, 0.0), vec3<f32>(1.0, 1.0, 1.0));
+    let q = vec3<u32>(round(c * 255.0));
+    return f32(q.x | (q.y << 8u) | (q.z << 16u));
+}
+
+fn unpack_color(packed: f32) -> vec3<f32> {
+    let q = u32(packed);
+    let c = vec3<u32>(q & 255u, (q >> 8u) & 255u, (q >> 16u) & 255u);
+    return vec3<f32>(c) / 255.0 * TINT_RANGE;
+}
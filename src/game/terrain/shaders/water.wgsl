@@ -0,0 +1,114 @@
+struct VertexOutput {
+    [[builtin(position)]] position: vec4<f32>;
+    [[location(0)]] world_position: vec3<f32>;
+    [[location(1)]] depth: f32;
+};
+
+[[block]]
+struct MeshData {
+    world_matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(0)]]
+var mesh_data: MeshData;
+
+[[block]]
+struct CameraData {
+    view_matrix: mat4x4<f32>;
+    projection_matrix: mat4x4<f32>;
+};
+
+[[group(0), binding(1)]]
+var camera_data: CameraData;
+
+// Keep in sync with wind.rs
+[[block]]
+struct WindData {
+    direction: vec2<f32>;
+    strength: f32;
+    time: f32;
+};
+
+[[group(0), binding(4)]]
+var wind: WindData;
+
+let WATER_COLOR: vec3<f32> = vec3<f32>(0.1, 0.3, 0.6);
+let FOAM_COLOR: vec3<f32> = vec3<f32>(0.9, 0.95, 1.0);
+let CAUSTIC_COLOR: vec3<f32> = vec3<f32>(0.5, 0.7, 0.8);
+// Opacity of the shallowest and of the deepest water and of the foam
+let SHALLOW_ALPHA: f32 = 0.35;
+let DEEP_ALPHA: f32 = 0.8;
+let FOAM_ALPHA: f32 = 0.9;
+// Depths in world units from which the water is deep, the foam ends and the
+// caustics are gone
+let DEEP_DEPTH: f32 = 4.0;
+let FOAM_DEPTH: f32 = 0.4;
+let CAUSTIC_DEPTH: f32 = 3.0;
+// Caustic cells per world unit at the surface, they spread out with depth
+let CAUSTIC_FREQUENCY: f32 = 3.0;
+let CAUSTIC_SPREAD: f32 = 0.25;
+// World units the caustics and the foam drift per second in full wind
+let DRIFT_SPEED: f32 = 0.3;
+// Ripples running with the wind, per world unit and per second, and how far
+// they tilt the surface in full wind
+let RIPPLE_FREQUENCY: f32 = 6.0;
+let RIPPLE_SPEED: f32 = 2.0;
+let RIPPLE_SLOPE: f32 = 0.3;
+// Shading of the ripples under the sun straight above
+let RIPPLE_SHADE: f32 = 0.3;
+
+// Bright ridges where two warped sine waves cross
+fn caustic(p: vec2<f32>) -> f32 {
+    let a = sin(p.x + 1.5 * sin(p.y * 0.7));
+    let b = sin(p.y + 1.5 * sin(p.x * 0.9));
+    return pow(1.0 - abs(a * b), 8.0);
+}
+
+// Normal of the surface under two ripples, one along the wind and a weaker
+// one across it
+fn ripple_normal(p: vec2<f32>) -> vec3<f32> {
+    let along = dot(p, wind.direction) * RIPPLE_FREQUENCY - wind.time * RIPPLE_SPEED;
+    let across = vec2<f32>(-wind.direction.y, wind.direction.x);
+    let side = dot(p, across) * RIPPLE_FREQUENCY * 0.7 + wind.time * RIPPLE_SPEED * 0.3;
+    let slope = RIPPLE_SLOPE * wind.strength;
+    let tilt = wind.direction * cos(along) * slope + across * cos(side) * slope * 0.4;
+    return normalize(vec3<f32>(-tilt, 1.0));
+}
+
+// The depth is the height of the surface over the ground right below it,
+// see Chunk::water_surface
+[[stage(vertex)]]
+fn main([[location(0)]] position: vec3<f32>, [[location(1)]] depth: f32) -> VertexOutput {
+    var out: VertexOutput;
+    let local = vec4<f32>(position, 1.0);
+    out.position =
+        camera_data.projection_matrix *
+        camera_data.view_matrix *
+        mesh_data.world_matrix *
+        local;
+    out.world_position = (mesh_data.world_matrix * local).xyz;
+    out.depth = depth;
+    return out;
+}
+
+// Shallow water along the shore turns into foam with a wavy edge, the ground
+// under clear water shows caustics projected straight down from the surface
+[[stage(fragment)]]
+fn main(
+    [[location(0)]] world_position: vec3<f32>,
+    [[location(1)]] depth: f32,
+) -> [[location(0)]] vec4<f32> {
+    let normal = ripple_normal(world_position.xy);
+    var color = WATER_COLOR * (1.0 - RIPPLE_SHADE + RIPPLE_SHADE * normal.z * normal.z);
+    var alpha = mix(SHALLOW_ALPHA, DEEP_ALPHA, clamp(depth / DEEP_DEPTH, 0.0, 1.0));
+    // Everything carried by the water drifts with the wind
+    let drifted = world_position.xy - wind.direction * wind.time * wind.strength * DRIFT_SPEED;
+    let p = drifted * CAUSTIC_FREQUENCY / (1.0 + depth * CAUSTIC_SPREAD);
+    let fade = 1.0 - smoothStep(0.0, CAUSTIC_DEPTH, depth);
+    color = color + CAUSTIC_COLOR * caustic(p) * fade;
+    let edge = FOAM_DEPTH * (0.75 + 0.25 * caustic(drifted * CAUSTIC_FREQUENCY * 2.0));
+    let foam = 1.0 - smoothStep(0.5 * edge, edge, depth);
+    color = mix(color, FOAM_COLOR, foam);
+    alpha = mix(alpha, FOAM_ALPHA, foam);
+    return vec4<f32>(color, alpha);
+}
@@ -0,0 +1,117 @@
+// Welds the corners of a compacted triangle buffer into an indexed mesh. The
+// ids of a corner are voxels at most one step apart on every axis, so the
+// lower voxel and the step give every id its own slot without collisions.
+// Every corner claims the slot of its id, the lowest corner of a slot emits
+// the vertex and then every corner looks up the index of its slot.
+
+// STRUCTS
+
+[[block]]
+struct WeldInfo {
+    voxel_count: vec3<u32>;
+    corner_count: u32;
+};
+
+// Keep in sync with generate_triangle.wgsl
+struct Triangle {
+    // w holds the packed vertex color
+    position: array<vec4<f32>,3>;
+    id : array<vec2<u32>,3>;
+};
+
+[[block]]
+struct TriangleBuffer {
+    count: u32;
+    buffer : array<Triangle>;
+};
+
+[[block]]
+struct SlotBuffer {
+    values: array<atomic<u32>>;
+};
+
+[[block]]
+struct SlotVertices {
+    values: array<u32>;
+};
+
+// Position, id and packed color of each vertex, six words each. Keep in sync with
+// WELDED_VERTEX_WORDS in chunk.rs
+[[block]]
+struct VertexBuffer {
+    count: atomic<u32>;
+    values: array<u32>;
+};
+
+[[block]]
+struct IndexBuffer {
+    values: array<u32>;
+};
+
+[[group(0), binding(0)]] var<uniform> info: WeldInfo;
+[[group(0), binding(1)]] var<storage> triangle_buffer: TriangleBuffer;
+// Zeroed when created, the owner is stored inverted so that atomicMax keeps
+// the lowest corner
+[[group(0), binding(2)]] var<storage, read_write> owners: SlotBuffer;
+[[group(0), binding(3)]] var<storage, read_write> slot_vertices: SlotVertices;
+[[group(0), binding(4)]] var<storage, read_write> vertices: VertexBuffer;
+[[group(0), binding(5)]] var<storage, read_write> indices: IndexBuffer;
+
+// FUNCTIONS
+
+fn corner_id(corner: u32) -> vec2<u32> {
+    return triangle_buffer.buffer[corner / 3u].id[corner % 3u];
+}
+
+fn slot(id: vec2<u32>) -> u32 {
+    let d = id.y - id.x;
+    let w = info.voxel_count.x;
+    let h = info.voxel_count.y;
+    let step = vec3<u32>(d % w, (d / w) % h, d / (w * h));
+    return id.x * 8u + step.x + 2u * step.y + 4u * step.z;
+}
+
+fn owner(corner: u32) -> u32 {
+    return 4294967295u - corner;
+}
+
+[[stage(compute), workgroup_size(64u)]]
+fn claim_slots([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let corner = global_invocation_id.x;
+    if (corner >= info.corner_count) {
+        return;
+    }
+    atomicMax(&owners.values[slot(corner_id(corner))], owner(corner));
+}
+
+[[stage(compute), workgroup_size(64u)]]
+fn emit_vertices([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let corner = global_invocation_id.x;
+    if (corner >= info.corner_count) {
+        return;
+    }
+    let id = corner_id(corner);
+    let s = slot(id);
+    if (atomicLoad(&owners.values[s]) != owner(corner)) {
+        return;
+    }
+    let index = atomicAdd(&vertices.count, 1u);
+    let position = triangle_buffer.buffer[corner / 3u].position[corner % 3u];
+    let offset = index * 6u;
+    vertices.values[offset] = bitcast<u32>(position.x);
+    vertices.values[offset + 1u] = bitcast<u32>(position.y);
+    vertices.values[offset + 2u] = bitcast<u32>(position.z);
+    vertices.values[offset + 3u] = id.x;
+    vertices.values[offset + 4u] = id.y;
+    vertices.values[offset + 5u] = bitcast<u32>(position.w);
+    slot_vertices.values[s] = index;
+}
+
+[[stage(compute), workgroup_size(64u)]]
+fn write_indices([[builtin(global_invocation_id)]] global_invocation_id: vec3<u32>) {
+    let corner = global_invocation_id.x;
+    if (corner >= info.corner_count) {
+        return;
+    }
+    indices.values[corner] = slot_vertices.values[slot(corner_id(corner))];
+}
@@ -0,0 +1,102 @@
+use super::{ChunkCacheKey, TerrainTask};
+use parking_lot::Mutex;
+use std::collections::{HashMap, HashSet};
+use std::mem::{discriminant, Discriminant};
+
+// A chain entering the same task for a key this many times is taken as a cycle
+const MAX_CHAIN_REENTRIES: u32 = 4;
+
+// Checks of the task graph invariants in debug builds, a violation is logged
+// and the tasks carry on. Everything is skipped in release builds.
+pub struct TaskAudit {
+    // Keys with a WriteChunk returned but not run yet
+    pending_writes: Mutex<HashSet<ChunkCacheKey>>,
+}
+
+impl TaskAudit {
+    pub fn new() -> Self {
+        Self {
+            pending_writes: Mutex::new(HashSet::new()),
+        }
+    }
+
+    pub fn begin(&self, task: &TerrainTask) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        if let TerrainTask::WriteChunk(key, _) = task {
+            self.pending_writes.lock().remove(key);
+        }
+    }
+
+    pub fn end(&self, next_task: Option<&TerrainTask>) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        if let Some(TerrainTask::WriteChunk(key, _)) = next_task {
+            if !self.pending_writes.lock().insert(*key) {
+                log::warn!("Task audit: a second WriteChunk for {:?} is in flight", key);
+            }
+        }
+    }
+
+    // A notify sent between the empty queue check and the wait is lost, the
+    // worker then sleeps until the next push
+    pub fn before_wait(&self, worker: usize, queued: bool) {
+        if cfg!(debug_assertions) && queued {
+            log::warn!(
+                "Task audit: worker {} waits while the global queue has tasks",
+                worker
+            );
+        }
+    }
+
+    pub fn after_wait(&self, worker: usize, done: bool, queued: bool) {
+        if cfg!(debug_assertions) && !done && !queued {
+            log::debug!("Task audit: worker {} woke up without a task", worker);
+        }
+    }
+}
+
+// Tasks run by one worker from a popped task until the chain ends. Retrying
+// the same task right away is a wait on a lock, entering it again after
+// other tasks is a cycle like GenerateMesh -> GenerateChunk -> GenerateMesh.
+pub struct ChainAudit {
+    previous: Option<(Discriminant<TerrainTask>, ChunkCacheKey)>,
+    entries: HashMap<(Discriminant<TerrainTask>, ChunkCacheKey), u32>,
+    names: Vec<&'static str>,
+    reported: bool,
+}
+
+impl ChainAudit {
+    pub fn new() -> Self {
+        Self {
+            previous: None,
+            entries: HashMap::new(),
+            names: vec![],
+            reported: false,
+        }
+    }
+
+    pub fn step(&mut self, task: &TerrainTask) {
+        if !cfg!(debug_assertions) {
+            return;
+        }
+        let step = (discriminant(task), task.key());
+        if self.previous == Some(step) {
+            return;
+        }
+        self.previous = Some(step);
+        self.names.push(task.name());
+        let entries = self.entries.entry(step).or_insert(0);
+        *entries += 1;
+        if *entries > MAX_CHAIN_REENTRIES && !self.reported {
+            self.reported = true;
+            log::warn!(
+                "Task audit: cycle for {:?}: {}",
+                task.key(),
+                self.names.join(" -> ")
+            );
+        }
+    }
+}
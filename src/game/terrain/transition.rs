@@ -0,0 +1,514 @@
+use super::chunk_mesh::VoxelFace;
+use super::EdgeId;
+use crate::game::base::{LocalSpace, WorldSpace};
+use crate::game::mesh::{Mesh, Triangle};
+use euclid::{point2, point3, size2, Box3D, Point3D, Size3D, UnknownUnit};
+use std::collections::{HashMap, HashSet};
+
+// Thickness of the transition cells as a fraction of a cell of the chunk, the
+// cells of the chunk along a stitched side are shrunk by as much
+const TRANSITION_WIDTH: f32 = 0.5;
+// Neighbors finer than this are resampled at this ratio
+pub const MAX_STITCH_RATIO: u32 = 8;
+// Ids of the samples of each side start at a multiple of this
+const SIDE_ID_STRIDE: u32 = 1 << 30;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Side {
+    MinX,
+    MaxX,
+    MinY,
+    MaxY,
+}
+
+impl Side {
+    pub const ALL: [Side; 4] = [Side::MinX, Side::MaxX, Side::MinY, Side::MaxY];
+
+    pub fn opposite(self) -> Self {
+        match self {
+            Side::MinX => Side::MaxX,
+            Side::MaxX => Side::MinX,
+            Side::MinY => Side::MaxY,
+            Side::MaxY => Side::MinY,
+        }
+    }
+
+    // Start and length of the bounds along the side
+    fn tangent(self, bounds: &Box3D<i32, WorldSpace>) -> (i32, i32) {
+        match self {
+            Side::MinX | Side::MaxX => (bounds.min.y, bounds.height()),
+            Side::MinY | Side::MaxY => (bounds.min.x, bounds.width()),
+        }
+    }
+
+    // Whether u and v along the side then t into the chunk is a right handed
+    // frame, the winding of the triangles is flipped for the others
+    fn right_handed(self) -> bool {
+        matches!(self, Side::MinX | Side::MaxY)
+    }
+}
+
+// Side of the bounds the other bounds are next to, none if they only share a
+// corner or do not touch
+pub fn adjacent_side(
+    bounds: &Box3D<i32, WorldSpace>,
+    other: &Box3D<i32, WorldSpace>,
+) -> Option<Side> {
+    let overlaps_x = other.min.x < bounds.max.x && other.max.x > bounds.min.x;
+    let overlaps_y = other.min.y < bounds.max.y && other.max.y > bounds.min.y;
+    // Nodes split along z only touch the neighbors beside their layer
+    if other.min.z >= bounds.max.z || other.max.z <= bounds.min.z {
+        return None;
+    }
+    if overlaps_y && other.max.x == bounds.min.x {
+        Some(Side::MinX)
+    } else if overlaps_y && other.min.x == bounds.max.x {
+        Some(Side::MaxX)
+    } else if overlaps_x && other.max.y == bounds.min.y {
+        Some(Side::MinY)
+    } else if overlaps_x && other.min.y == bounds.max.y {
+        Some(Side::MaxY)
+    } else {
+        None
+    }
+}
+
+// How many times finer the finest neighbor along each side is, 1 for sides
+// without a finer neighbor
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct StitchStride {
+    pub min_x: u32,
+    pub max_x: u32,
+    pub min_y: u32,
+    pub max_y: u32,
+}
+
+impl StitchStride {
+    pub const NONE: Self = Self {
+        min_x: 1,
+        max_x: 1,
+        min_y: 1,
+        max_y: 1,
+    };
+
+    pub fn get(&self, side: Side) -> u32 {
+        match side {
+            Side::MinX => self.min_x,
+            Side::MaxX => self.max_x,
+            Side::MinY => self.min_y,
+            Side::MaxY => self.max_y,
+        }
+    }
+
+    pub fn set(&mut self, side: Side, ratio: u32) {
+        match side {
+            Side::MinX => self.min_x = ratio,
+            Side::MaxX => self.max_x = ratio,
+            Side::MinY => self.min_y = ratio,
+            Side::MaxY => self.max_y = ratio,
+        }
+    }
+
+    // Moves the vertices of the cells along the stitched sides inward to make
+    // room for the transition cells
+    pub fn shrink(
+        &self,
+        point: &Point3D<f32, LocalSpace>,
+        voxel_count: Size3D<u32, UnknownUnit>,
+    ) -> Point3D<f32, LocalSpace> {
+        point3(
+            shrink(
+                point.x,
+                cell_size(voxel_count.width),
+                self.min_x > 1,
+                self.max_x > 1,
+            ),
+            shrink(
+                point.y,
+                cell_size(voxel_count.height),
+                self.min_y > 1,
+                self.max_y > 1,
+            ),
+            point.z,
+        )
+    }
+}
+
+fn cell_size(voxel_count: u32) -> f32 {
+    1.0 / (voxel_count - 1) as f32
+}
+
+// The first cell from a stitched side is mapped from [0, cell] to
+// [width, cell] so that the vertices keep their order
+fn shrink(x: f32, cell: f32, min: bool, max: bool) -> f32 {
+    let width = TRANSITION_WIDTH * cell;
+    if min && x < cell {
+        width + x * (1.0 - TRANSITION_WIDTH)
+    } else if max && x > 1.0 - cell {
+        1.0 - width - (1.0 - x) * (1.0 - TRANSITION_WIDTH)
+    } else {
+        x
+    }
+}
+
+pub struct FineNeighbor<'a> {
+    pub bounds: Box3D<i32, WorldSpace>,
+    // Face of the neighbor on the side of the chunk
+    pub face: &'a VoxelFace,
+}
+
+// Voxels of a side of the chunk and of its neighbors on it, resampled to a
+// grid as many times finer as the ratio of the side
+pub struct TransitionFace<'a> {
+    side: Side,
+    ratio: u32,
+    coarse: &'a VoxelFace,
+    fine: VoxelFace,
+}
+
+impl<'a> TransitionFace<'a> {
+    // Parts of the side without a neighbor are interpolated from the chunk.
+    // The voxels of neighbors at the ratio are copied, the ones of coarser
+    // neighbors are interpolated so they only match approximately.
+    pub fn new(
+        side: Side,
+        ratio: u32,
+        bounds: &Box3D<i32, WorldSpace>,
+        coarse: &'a VoxelFace,
+        neighbors: &[FineNeighbor],
+    ) -> Self {
+        let size = coarse.voxel_count();
+        let fine_size = size2((size.width - 1) * ratio + 1, (size.height - 1) * ratio + 1);
+        let (min, length) = side.tangent(bounds);
+        let mut voxels = Vec::with_capacity(fine_size.area() as usize);
+        for b in 0..fine_size.height {
+            for a in 0..fine_size.width {
+                // In cells of the chunk
+                let u = a as f32 / ratio as f32;
+                let v = b as f32 / ratio as f32;
+                let value = neighbors
+                    .iter()
+                    .find_map(|neighbor| {
+                        let (neighbor_min, neighbor_length) = side.tangent(&neighbor.bounds);
+                        let neighbor_size = neighbor.face.voxel_count();
+                        let start =
+                            (neighbor_min - min) as f32 / length as f32 * (size.width - 1) as f32;
+                        let scale_u = length as f32 / neighbor_length as f32
+                            * (neighbor_size.width - 1) as f32
+                            / (size.width - 1) as f32;
+                        // Neighbors split along z cover part of the depth
+                        let depth = bounds.depth() as f32;
+                        let start_v = (neighbor.bounds.min.z - bounds.min.z) as f32 / depth
+                            * (size.height - 1) as f32;
+                        let scale_v = depth / neighbor.bounds.depth() as f32
+                            * (neighbor_size.height - 1) as f32
+                            / (size.height - 1) as f32;
+                        let point = point2((u - start) * scale_u, (v - start_v) * scale_v);
+                        if !(0.0..=(neighbor_size.width - 1) as f32).contains(&point.x)
+                            || !(0.0..=(neighbor_size.height - 1) as f32).contains(&point.y)
+                        {
+                            return None;
+                        }
+                        Some(neighbor.face.sample(point))
+                    })
+                    .unwrap_or_else(|| coarse.sample(point2(u, v)));
+                voxels.push(value);
+            }
+        }
+        Self {
+            side,
+            ratio,
+            coarse,
+            fine: VoxelFace::new(fine_size, voxels),
+        }
+    }
+}
+
+#[derive(Copy, Clone)]
+struct Sample {
+    id: u32,
+    position: Point3D<f32, LocalSpace>,
+    value: f32,
+}
+
+// Transition cells of one side. A cell spans a cell of the chunk along the
+// side, its full resolution face on the side holds the voxels of the finer
+// neighbor and its half resolution face inside the chunk the voxels of the
+// shrunk cell next to it.
+struct TransitionSide<'a, 'b> {
+    face: &'b TransitionFace<'a>,
+    stride: &'b StitchStride,
+    voxel_count: Size3D<u32, UnknownUnit>,
+    isolevel: f32,
+    base: u32,
+}
+
+impl<'a, 'b> TransitionSide<'a, 'b> {
+    // u and v in cells of the chunk, t is 0 on the side and 1 inside
+    fn position(&self, u: f32, v: f32, inside: bool) -> Point3D<f32, LocalSpace> {
+        let size = self.face.coarse.voxel_count();
+        let u = u / (size.width - 1) as f32;
+        let z = v / (size.height - 1) as f32;
+        match self.face.side {
+            Side::MinX | Side::MaxX => {
+                let depth = if inside {
+                    TRANSITION_WIDTH * cell_size(self.voxel_count.width)
+                } else {
+                    0.0
+                };
+                let x = if self.face.side == Side::MinX {
+                    depth
+                } else {
+                    1.0 - depth
+                };
+                let y = if inside {
+                    let cell = cell_size(self.voxel_count.height);
+                    shrink(u, cell, self.stride.min_y > 1, self.stride.max_y > 1)
+                } else {
+                    u
+                };
+                point3(x, y, z)
+            }
+            Side::MinY | Side::MaxY => {
+                let depth = if inside {
+                    TRANSITION_WIDTH * cell_size(self.voxel_count.height)
+                } else {
+                    0.0
+                };
+                let y = if self.face.side == Side::MinY {
+                    depth
+                } else {
+                    1.0 - depth
+                };
+                let x = if inside {
+                    let cell = cell_size(self.voxel_count.width);
+                    shrink(u, cell, self.stride.min_x > 1, self.stride.max_x > 1)
+                } else {
+                    u
+                };
+                point3(x, y, z)
+            }
+        }
+    }
+
+    fn fine(&self, a: u32, b: u32) -> Sample {
+        let ratio = self.face.ratio as f32;
+        let width = self.face.fine.voxel_count().width;
+        Sample {
+            id: self.base + a + width * b,
+            position: self.position(a as f32 / ratio, b as f32 / ratio, false),
+            value: self.face.fine.voxel(a, b),
+        }
+    }
+
+    fn coarse(&self, i: u32, k: u32) -> Sample {
+        let size = self.face.coarse.voxel_count();
+        Sample {
+            id: self.base + self.face.fine.voxel_count().area() + i + size.width * k,
+            position: self.position(i as f32, k as f32, true),
+            value: self.face.coarse.voxel(i, k),
+        }
+    }
+
+    // Faces of the cell counter clockwise seen from outside, each with
+    // whether a saddle is resolved by its center
+    fn cell_faces(&self, i: u32, k: u32) -> Vec<(Vec<Sample>, bool)> {
+        let r = self.face.ratio;
+        let (a0, b0) = (i * r, k * r);
+        let mut faces = vec![];
+        for a in a0..a0 + r {
+            for b in b0..b0 + r {
+                faces.push((
+                    vec![
+                        self.fine(a, b),
+                        self.fine(a, b + 1),
+                        self.fine(a + 1, b + 1),
+                        self.fine(a + 1, b),
+                    ],
+                    true,
+                ));
+            }
+        }
+        faces.push((
+            vec![
+                self.coarse(i, k),
+                self.coarse(i + 1, k),
+                self.coarse(i + 1, k + 1),
+                self.coarse(i, k + 1),
+            ],
+            true,
+        ));
+        let mut face = vec![self.fine(a0, b0), self.coarse(i, k), self.coarse(i, k + 1)];
+        face.extend((b0 + 1..=b0 + r).rev().map(|b| self.fine(a0, b)));
+        faces.push((face, false));
+        let mut face = (b0..=b0 + r)
+            .map(|b| self.fine(a0 + r, b))
+            .collect::<Vec<_>>();
+        face.extend([self.coarse(i + 1, k + 1), self.coarse(i + 1, k)]);
+        faces.push((face, false));
+        let mut face = (a0..=a0 + r).map(|a| self.fine(a, b0)).collect::<Vec<_>>();
+        face.extend([self.coarse(i + 1, k), self.coarse(i, k)]);
+        faces.push((face, false));
+        let mut face = vec![
+            self.fine(a0, b0 + r),
+            self.coarse(i, k + 1),
+            self.coarse(i + 1, k + 1),
+        ];
+        face.extend((a0 + 1..=a0 + r).rev().map(|a| self.fine(a, b0 + r)));
+        faces.push((face, false));
+        faces
+    }
+
+    // Instead of a table of the cases, the contour is traced around the
+    // surface of the cell and every loop of it is closed with a fan. The
+    // segments on each face keep the solid side on their left seen from
+    // outside so the loops all turn the same way.
+    fn triangulate_cell(
+        &self,
+        i: u32,
+        k: u32,
+        centers: &mut u32,
+        triangles: &mut Vec<Triangle<LocalSpace>>,
+    ) {
+        let mut next = HashMap::new();
+        let mut positions = HashMap::new();
+        for (face, resolve_saddle) in self.cell_faces(i, k) {
+            let mut crossings = vec![];
+            for (j, a) in face.iter().enumerate() {
+                let b = &face[(j + 1) % face.len()];
+                let solid = a.value >= self.isolevel;
+                if solid != (b.value >= self.isolevel) {
+                    let id = EdgeId::new(a.id, b.id);
+                    positions.entry(id).or_insert_with(|| {
+                        vertex_lerp(self.isolevel, a.position, b.position, a.value, b.value)
+                    });
+                    crossings.push((solid, id));
+                }
+            }
+            let count = crossings.len();
+            let center = face.iter().map(|x| x.value).sum::<f32>() / face.len() as f32;
+            let connect = resolve_saddle && count == 4 && center >= self.isolevel;
+            for (j, (leaves_solid, id)) in crossings.iter().enumerate() {
+                if *leaves_solid {
+                    // To the crossing entering the solid part that it ends, or
+                    // the next one when the solid parts are connected
+                    let enter = if connect {
+                        crossings[(j + 1) % count].1
+                    } else {
+                        crossings[(j + count - 1) % count].1
+                    };
+                    next.insert(*id, enter);
+                }
+            }
+        }
+        let mut starts = next.keys().copied().collect::<Vec<_>>();
+        starts.sort_unstable();
+        let mut visited = HashSet::new();
+        for start in starts {
+            let mut contour = vec![];
+            let mut id = start;
+            while visited.insert(id) {
+                contour.push(id);
+                id = match next.get(&id) {
+                    Some(id) => *id,
+                    None => break,
+                };
+            }
+            if contour.len() < 3 {
+                continue;
+            }
+            let points = contour.iter().map(|x| positions[x]).collect::<Vec<_>>();
+            // The loops turn so that the fans face the solid side, marching
+            // cubes triangles face the empty side
+            if contour.len() == 3 {
+                self.push_triangle(
+                    [points[0], points[2], points[1]],
+                    [contour[0], contour[2], contour[1]],
+                    triangles,
+                );
+                continue;
+            }
+            let center = points
+                .iter()
+                .fold(Point3D::origin(), |sum, x| sum + x.to_vector())
+                / points.len() as f32;
+            let center_id = EdgeId::new(*centers, *centers);
+            *centers += 1;
+            for j in 0..contour.len() {
+                let l = (j + 1) % contour.len();
+                self.push_triangle(
+                    [center, points[l], points[j]],
+                    [center_id, contour[l], contour[j]],
+                    triangles,
+                );
+            }
+        }
+    }
+
+    fn push_triangle(
+        &self,
+        position: [Point3D<f32, LocalSpace>; 3],
+        id: [EdgeId; 3],
+        triangles: &mut Vec<Triangle<LocalSpace>>,
+    ) {
+        if self.face.side.right_handed() {
+            triangles.push(Triangle { position, id });
+        } else {
+            triangles.push(Triangle {
+                position: [position[0], position[2], position[1]],
+                id: [id[0], id[2], id[1]],
+            });
+        }
+    }
+}
+
+// Transition cells of the stitched sides of a chunk, in the local space of the
+// chunk
+#[profiling::function]
+pub fn transition_mesh(
+    faces: &[TransitionFace],
+    stride: &StitchStride,
+    voxel_count: Size3D<u32, UnknownUnit>,
+    isolevel: f32,
+) -> Mesh<LocalSpace> {
+    let mut triangles = vec![];
+    // Ids of the loop centers only repeat the same voxel, unlike the ones of
+    // the crossings
+    let mut centers = 0;
+    for face in faces {
+        let side = TransitionSide {
+            face,
+            stride,
+            voxel_count,
+            isolevel,
+            base: Side::ALL.iter().position(|x| *x == face.side).unwrap() as u32 * SIDE_ID_STRIDE,
+        };
+        let size = face.coarse.voxel_count();
+        for k in 0..size.height - 1 {
+            for i in 0..size.width - 1 {
+                side.triangulate_cell(i, k, &mut centers, &mut triangles);
+            }
+        }
+    }
+    let mut mesh = Mesh::from_triangles(triangles);
+    mesh.calculate_normals();
+    mesh
+}
+
+// Keep in sync with vertex_lerp in generate_triangle.wgsl
+fn vertex_lerp(
+    isolevel: f32,
+    p1: Point3D<f32, LocalSpace>,
+    p2: Point3D<f32, LocalSpace>,
+    v1: f32,
+    v2: f32,
+) -> Point3D<f32, LocalSpace> {
+    if (isolevel - v1).abs() < 0.00001 {
+        return p1;
+    }
+    if (isolevel - v2).abs() < 0.00001 {
+        return p2;
+    }
+    let mu = (isolevel - v1) / (v2 - v1);
+    p1 + (p2 - p1) * mu
+}
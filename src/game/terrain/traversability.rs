@@ -0,0 +1,156 @@
+use crate::game::base::{LocalSpace, WorldSpace};
+use crate::game::mesh::Mesh;
+use euclid::{Box3D, Point2D, Size2D, Transform3D, UnknownUnit, Vector3D};
+
+// Cosine of the steepest slope that is still walkable
+const WALKABLE_SLOPE: f32 = 0.866;
+// Cosine of the steepest slope that can still be climbed
+const STEEP_SLOPE: f32 = 0.5;
+// A drop to the next cell steeper than this ratio is a ledge
+const LEDGE_RATIO: f32 = 1.732;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Traversability {
+    Walkable,
+    Steep,
+    Cliff,
+}
+
+impl Traversability {
+    // Value passed to the render shader
+    pub fn shader_value(&self) -> f32 {
+        match self {
+            Traversability::Walkable => 0.0,
+            Traversability::Steep => 1.0,
+            Traversability::Cliff => 2.0,
+        }
+    }
+
+    fn from_normal(normal: &Vector3D<f32, WorldSpace>) -> Self {
+        let up = normal.z.abs();
+        if up >= WALKABLE_SLOPE {
+            Traversability::Walkable
+        } else if up >= STEEP_SLOPE {
+            Traversability::Steep
+        } else {
+            Traversability::Cliff
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone)]
+pub struct SurfaceCell {
+    // Height and normal of the topmost triangle in the cell
+    pub height: f32,
+    pub normal: Vector3D<f32, WorldSpace>,
+    pub traversability: Traversability,
+}
+
+// Topmost surface of a chunk on the voxel column grid, cells without any
+// triangle are None
+#[derive(Debug, Clone)]
+pub struct SurfaceMetadata {
+    bounds: Box3D<i32, WorldSpace>,
+    cell_count: Size2D<u32, UnknownUnit>,
+    cells: Vec<Option<SurfaceCell>>,
+}
+
+impl SurfaceMetadata {
+    #[profiling::function]
+    pub fn from_mesh(
+        mesh: &Mesh<LocalSpace>,
+        bounds: Box3D<i32, WorldSpace>,
+        cell_count: Size2D<u32, UnknownUnit>,
+    ) -> Self {
+        let world_bounds = bounds.to_f32();
+        let transform: Transform3D<f32, LocalSpace, WorldSpace> = Transform3D::scale(
+            world_bounds.width(),
+            world_bounds.height(),
+            world_bounds.depth(),
+        )
+        .then_translate(world_bounds.min.to_vector());
+        let vertex = mesh
+            .vertex()
+            .iter()
+            .map(|x| transform.transform_point3d(*x).unwrap())
+            .collect::<Vec<_>>();
+        let mut cells: Vec<Option<SurfaceCell>> = vec![None; cell_count.area() as usize];
+        for face in mesh.faces() {
+            let [p0, p1, p2] = [vertex[face[0]], vertex[face[1]], vertex[face[2]]];
+            let center = (p0.to_vector() + p1.to_vector() + p2.to_vector()) / 3.0;
+            let normal = (p1 - p0).cross(p2 - p0);
+            if normal.square_length() == 0.0 {
+                continue;
+            }
+            let index = match Self::cell_index(&bounds, cell_count, &center.xy().to_point()) {
+                Some(index) => index,
+                None => continue,
+            };
+            if cells[index].map_or(true, |x| center.z > x.height) {
+                let normal = normal.normalize();
+                cells[index] = Some(SurfaceCell {
+                    height: center.z,
+                    normal,
+                    traversability: Traversability::from_normal(&normal),
+                });
+            }
+        }
+        // Flat cells next to a big drop are ledges
+        let spacing = (world_bounds.width() / cell_count.width as f32)
+            .min(world_bounds.height() / cell_count.height as f32);
+        let heights = cells
+            .iter()
+            .map(|x| x.map(|x| x.height))
+            .collect::<Vec<_>>();
+        for y in 0..cell_count.height {
+            for x in 0..cell_count.width {
+                let index = (x + cell_count.width * y) as usize;
+                let height = match heights[index] {
+                    Some(height) => height,
+                    None => continue,
+                };
+                let neighbours = [
+                    (x.wrapping_sub(1), y),
+                    (x + 1, y),
+                    (x, y.wrapping_sub(1)),
+                    (x, y + 1),
+                ];
+                let ledge = neighbours.iter().any(|&(nx, ny)| {
+                    nx < cell_count.width
+                        && ny < cell_count.height
+                        && heights[(nx + cell_count.width * ny) as usize]
+                            .map_or(false, |h| (height - h) / spacing > LEDGE_RATIO)
+                });
+                if ledge {
+                    cells[index].as_mut().unwrap().traversability = Traversability::Cliff;
+                }
+            }
+        }
+        Self {
+            bounds,
+            cell_count,
+            cells,
+        }
+    }
+
+    fn cell_index(
+        bounds: &Box3D<i32, WorldSpace>,
+        cell_count: Size2D<u32, UnknownUnit>,
+        point: &Point2D<f32, WorldSpace>,
+    ) -> Option<usize> {
+        let bounds = bounds.to_f32();
+        let x = (point.x - bounds.min.x) / bounds.width() * cell_count.width as f32;
+        let y = (point.y - bounds.min.y) / bounds.height() * cell_count.height as f32;
+        if x < 0.0 || y < 0.0 || x > cell_count.width as f32 || y > cell_count.height as f32 {
+            return None;
+        }
+        // Points on the max edge belong to the last cell
+        let x = (x as u32).min(cell_count.width - 1);
+        let y = (y as u32).min(cell_count.height - 1);
+        Some((x + cell_count.width * y) as usize)
+    }
+
+    pub fn cell_at(&self, point: &Point2D<f32, WorldSpace>) -> Option<&SurfaceCell> {
+        Self::cell_index(&self.bounds, self.cell_count, point).and_then(|x| self.cells[x].as_ref())
+    }
+}
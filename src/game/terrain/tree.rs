@@ -1,16 +1,35 @@
+use super::transition::{adjacent_side, Side};
+use super::{TerrainRegion, MIN_LEVEL};
 use crate::game::base::{Region, WorldSpace};
-use euclid::{point2, point3, size2, Box2D, Box3D, Point2D};
-use std::collections::HashMap;
+use euclid::{point2, point3, size2, vec3, Box2D, Box3D, Point2D};
+use rayon::prelude::*;
+use std::collections::{HashMap, HashSet};
 
-const MAX_LEVEL: u32 = 8;
+pub const MAX_LEVEL: u32 = 8;
 const ROOT_LEVEL_SIZE: i32 = 1 << MAX_LEVEL as i32;
-const MIN_Z: i32 = -1;
-const MAX_Z: i32 = 1;
+// Deep enough that the nodes of the finer levels are split along z, the
+// generated surface only spans part of it, see SURFACE_MIN_Z
+pub const MIN_Z: i32 = -4;
+pub const MAX_Z: i32 = 4;
+// Nodes farther above or below the camera than their width times this are
+// not split any further, so the levels fall off with the distance along z
+// like they do with the rings along x and y
+const VERTICAL_LOD_FACTOR: i32 = 1;
 
 pub struct Tree {
     sub_nodes: HashMap<Point2D<i32, WorldSpace>, Node>,
 }
 
+// Leaves of the tree along one side of a node. A coarser or same level leaf
+// covers the whole side alone, finer ones are all listed.
+pub struct NeighborInfo<'a> {
+    pub leaves: Vec<&'a Node>,
+    // Finest level of the leaves minus the level of the node, zero without
+    // leaves
+    pub level_delta: i32,
+}
+
+#[derive(Clone)]
 pub struct Node {
     bounds: Box3D<i32, WorldSpace>,
     sub_nodes: Option<Vec<Node>>,
@@ -25,24 +44,28 @@ impl Tree {
         }
     }
 
-    pub fn add_node(&mut self, point: &Point2D<i32, WorldSpace>) {
-        if !self.sub_nodes.contains_key(point) {
-            self.sub_nodes.insert(
-                *point,
-                Node::new(
-                    Box3D::new(
-                        point.extend(MIN_Z),
-                        point
-                            .add_size(&size2(ROOT_LEVEL_SIZE, ROOT_LEVEL_SIZE))
-                            .extend(MAX_Z),
-                    ),
-                    0,
-                ),
-            );
-        }
+    fn root_node(point: &Point2D<i32, WorldSpace>) -> Node {
+        Node::new(
+            Box3D::new(
+                point.extend(MIN_Z),
+                point
+                    .add_size(&size2(ROOT_LEVEL_SIZE, ROOT_LEVEL_SIZE))
+                    .extend(MAX_Z),
+            ),
+            0,
+        )
     }
 
-    pub fn ensure_node_in_region(&mut self, region: &Region) {
+    fn root_box(point: &Point2D<i32, WorldSpace>) -> Box2D<f32, WorldSpace> {
+        Box2D::new(
+            *point,
+            point.add_size(&size2(ROOT_LEVEL_SIZE, ROOT_LEVEL_SIZE)),
+        )
+        .to_f32()
+    }
+
+    // Whether the roots exist yet or not
+    fn root_points_in_region(region: &Region) -> Vec<Point2D<i32, WorldSpace>> {
         let bounding_box = Box2D::from_points(region.points()).round_out().to_i32();
         let min_x = round_down_to_multiple_of(bounding_box.min.x, ROOT_LEVEL_SIZE);
         let min_y = round_down_to_multiple_of(bounding_box.min.y, ROOT_LEVEL_SIZE);
@@ -54,27 +77,68 @@ impl Tree {
         if min_y == max_y {
             max_y += ROOT_LEVEL_SIZE;
         }
+        let mut points = vec![];
         for x in (min_x..max_x).step_by(ROOT_LEVEL_SIZE as _) {
             for y in (min_y..max_y).step_by(ROOT_LEVEL_SIZE as _) {
                 let point = point2(x, y);
-                if self.sub_nodes.contains_key(&point) {
-                    continue;
-                } else {
-                    let the_box =
-                        Box2D::new(point, point2(x + ROOT_LEVEL_SIZE, y + ROOT_LEVEL_SIZE))
-                            .to_f32();
-                    if region.intersects_box(&the_box) {
-                        self.add_node(&point);
-                    }
+                if region.intersects_box(&Self::root_box(&point)) {
+                    points.push(point);
                 }
             }
         }
+        points
     }
 
-    pub fn set_level_in_region(&mut self, region: &Region, level: u32) {
-        for sub_node in self.sub_nodes.values_mut() {
-            sub_node.set_level_in_region(region, level);
-        }
+    // Copies of the roots in the regions with their levels set, one root per
+    // rayon task. Only needs the tree for reading, roots outside of every
+    // region would not change and neither would the roots that the previous
+    // regions were applied to in the same way from the same layer. The layer
+    // is the one unit of z the camera is in.
+    pub fn updated_roots(
+        &self,
+        regions: &[TerrainRegion],
+        previous: &[TerrainRegion],
+        layer: i32,
+        previous_layer: Option<i32>,
+    ) -> Vec<(Point2D<i32, WorldSpace>, Node)> {
+        regions
+            .iter()
+            .flat_map(|x| Self::root_points_in_region(&x.region))
+            .collect::<HashSet<_>>()
+            .into_par_iter()
+            .filter(|point| {
+                let the_box = Self::root_box(point);
+                let intersecting = |regions: &'_ [TerrainRegion]| {
+                    regions
+                        .iter()
+                        .filter(|x| x.region.intersects_box(&the_box))
+                        .collect::<Vec<_>>()
+                };
+                !self.sub_nodes.contains_key(point)
+                    || previous_layer != Some(layer)
+                    || intersecting(regions) != intersecting(previous)
+            })
+            .map(|point| {
+                let mut node = self
+                    .sub_nodes
+                    .get(&point)
+                    .cloned()
+                    .unwrap_or_else(|| Self::root_node(&point));
+                for region in regions {
+                    node.set_level_in_region(&region.region, region.level, layer);
+                }
+                node.rebuild_tree();
+                (point, node)
+            })
+            .collect()
+    }
+
+    // Returns the replaced roots so that they are dropped outside of the lock
+    pub fn replace_roots(&mut self, roots: Vec<(Point2D<i32, WorldSpace>, Node)>) -> Vec<Node> {
+        roots
+            .into_iter()
+            .filter_map(|(point, node)| self.sub_nodes.insert(point, node))
+            .collect()
     }
 
     pub fn leaf_iter(&self) -> LeafIter {
@@ -118,10 +182,71 @@ impl Tree {
         LeafIterMut::new(self.sub_nodes.values_mut(), regions, false, true)
     }
 
-    pub fn rebuild_tree(&mut self) {
-        for sub_node in self.sub_nodes.values_mut() {
-            sub_node.rebuild_tree();
+    // Node of the bounds at the level if the tree has it
+    pub fn node(&self, bounds: &Box3D<i32, WorldSpace>, level: u32) -> Option<&Node> {
+        let mut node = self.root_containing(bounds)?;
+        while node.level < level {
+            node = node
+                .sub_nodes
+                .as_ref()?
+                .iter()
+                .find(|x| x.bounds.contains_box(bounds))?;
         }
+        Some(node).filter(|x| x.bounds == *bounds)
+    }
+
+    // Leaves next to each side of the node, in the order of Side::ALL
+    pub fn neighbors(&self, node: &Node) -> [NeighborInfo; 4] {
+        Side::ALL.map(|side| {
+            let size = node.bounds.width();
+            let offset = match side {
+                Side::MinX => vec3(-size, 0, 0),
+                Side::MaxX => vec3(size, 0, 0),
+                Side::MinY => vec3(0, -size, 0),
+                Side::MaxY => vec3(0, size, 0),
+            };
+            let adjacent = node.bounds.translate(offset);
+            // Down to the node of the same level next to it, or to the
+            // coarser leaf containing it
+            let mut leaves = vec![];
+            let mut current = self.root_containing(&adjacent);
+            while let Some(x) = current {
+                match &x.sub_nodes {
+                    Some(sub_nodes) if x.level < node.level => {
+                        current = sub_nodes.iter().find(|x| x.bounds.contains_box(&adjacent));
+                    }
+                    Some(_) => {
+                        x.collect_leaves_on(&node.bounds, side, &mut leaves);
+                        break;
+                    }
+                    None => {
+                        leaves.push(x);
+                        break;
+                    }
+                }
+            }
+            let level_delta = leaves
+                .iter()
+                .map(|x| x.level as i32 - node.level as i32)
+                .max()
+                .unwrap_or(0);
+            NeighborInfo {
+                leaves,
+                level_delta,
+            }
+        })
+    }
+
+    fn root_containing(&self, bounds: &Box3D<i32, WorldSpace>) -> Option<&Node> {
+        let point = point2(
+            round_down_to_multiple_of(bounds.min.x, ROOT_LEVEL_SIZE),
+            round_down_to_multiple_of(bounds.min.y, ROOT_LEVEL_SIZE),
+        );
+        self.sub_nodes.get(&point)
+    }
+
+    pub fn z_range(&self) -> (i32, i32) {
+        (MIN_Z, MAX_Z)
     }
 
     pub fn root_nodes(&self) -> std::collections::hash_map::Values<Point2D<i32, WorldSpace>, Node> {
@@ -146,44 +271,36 @@ impl Node {
         region.intersects_box(&the_box.to_f32())
     }
 
+    // Into eight once the node is at least as deep as it is wide, so tall
+    // nodes get finer along z too, into four otherwise
     pub fn subdivide(&mut self) {
         if self.sub_nodes.is_some() {
             return;
         }
-        let center = self.bounds.center();
-        let top_left_node = Self::new(
-            Box3D::new(self.bounds.min, center.xy().extend(self.bounds.max.z)),
-            self.level + 1,
-        );
-        let top_right_node = Self::new(
-            Box3D::new(
-                point3(center.x, self.bounds.min.y, self.bounds.min.z),
-                point3(self.bounds.max.x, center.y, self.bounds.max.z),
-            ),
-            self.level + 1,
-        );
-        let bottom_left_node = Self::new(
-            Box3D::new(
-                point3(self.bounds.min.x, center.y, self.bounds.min.z),
-                point3(center.x, self.bounds.max.y, self.bounds.max.z),
-            ),
-            self.level + 1,
-        );
-        let bottom_right_node = Self::new(
-            Box3D::new(center.xy().extend(self.bounds.min.z), self.bounds.max),
-            self.level + 1,
-        );
-        self.sub_nodes = Some(vec![
-            top_left_node,
-            top_right_node,
-            bottom_left_node,
-            bottom_right_node,
-        ]);
+        let bounds = self.bounds;
+        let center = bounds.center();
+        let layers = if splits_vertically(bounds.width(), bounds.depth()) {
+            vec![(bounds.min.z, center.z), (center.z, bounds.max.z)]
+        } else {
+            vec![(bounds.min.z, bounds.max.z)]
+        };
+        let mut sub_nodes = vec![];
+        for (min_z, max_z) in layers {
+            for (min_y, max_y) in [(bounds.min.y, center.y), (center.y, bounds.max.y)] {
+                for (min_x, max_x) in [(bounds.min.x, center.x), (center.x, bounds.max.x)] {
+                    sub_nodes.push(Self::new(
+                        Box3D::new(point3(min_x, min_y, min_z), point3(max_x, max_y, max_z)),
+                        self.level + 1,
+                    ));
+                }
+            }
+        }
+        self.sub_nodes = Some(sub_nodes);
     }
 
-    pub fn set_level_in_region(&mut self, region: &Region, level: u32) {
+    pub fn set_level_in_region(&mut self, region: &Region, level: u32, layer: i32) {
         if self.intersects_region(region) {
-            if self.level >= level {
+            if self.level >= level || !is_near_layer(&self.bounds, self.level, layer) {
                 // self.sub_nodes = None;
                 self.remove_sub_nodes = true;
             } else {
@@ -192,7 +309,7 @@ impl Node {
                 }
                 self.remove_sub_nodes = false;
                 for sub_node in self.sub_nodes.as_mut().unwrap() {
-                    sub_node.set_level_in_region(region, level);
+                    sub_node.set_level_in_region(region, level, layer);
                 }
             }
         }
@@ -209,6 +326,26 @@ impl Node {
         }
     }
 
+    // Leaves under the node that touch the side of the bounds
+    fn collect_leaves_on<'a>(
+        &'a self,
+        bounds: &Box3D<i32, WorldSpace>,
+        side: Side,
+        leaves: &mut Vec<&'a Node>,
+    ) {
+        if adjacent_side(bounds, &self.bounds) != Some(side) {
+            return;
+        }
+        match &self.sub_nodes {
+            Some(sub_nodes) => {
+                for sub_node in sub_nodes {
+                    sub_node.collect_leaves_on(bounds, side, leaves);
+                }
+            }
+            None => leaves.push(self),
+        }
+    }
+
     pub fn bounds(&self) -> Box3D<i32, WorldSpace> {
         self.bounds
     }
@@ -399,6 +536,75 @@ where
     }
 }
 
+// Bounds of the node one level up that contains a node
+pub fn parent_bounds(
+    bounds: &Box3D<i32, WorldSpace>,
+    level: u32,
+) -> Option<Box3D<i32, WorldSpace>> {
+    if level == 0 {
+        return None;
+    }
+    let size = ROOT_LEVEL_SIZE >> (level - 1);
+    let depth = level_depth(level - 1);
+    let min = point3(
+        round_down_to_multiple_of(bounds.min.x, size),
+        round_down_to_multiple_of(bounds.min.y, size),
+        MIN_Z + round_down_to_multiple_of(bounds.min.z - MIN_Z, depth),
+    );
+    Some(Box3D::new(min, min + vec3(size, size, depth)))
+}
+
+// Whether a node is close enough to the unit of z the camera is in to be
+// split, see VERTICAL_LOD_FACTOR. Levels coarser than MIN_LEVEL always are so
+// that every column has its coarsest chunks.
+pub fn is_near_layer(bounds: &Box3D<i32, WorldSpace>, level: u32, layer: i32) -> bool {
+    let gap = (bounds.min.z - (layer + 1)).max(layer - bounds.max.z);
+    level < MIN_LEVEL || gap <= bounds.width() * VERTICAL_LOD_FACTOR
+}
+
+// Nodes are split along z while they are at least as deep as they are wide
+fn splits_vertically(width: i32, depth: i32) -> bool {
+    depth >= width && depth % 2 == 0
+}
+
+// Depth of the nodes of the level, the roots span from MIN_Z to MAX_Z
+fn level_depth(level: u32) -> i32 {
+    let mut depth = MAX_Z - MIN_Z;
+    for level in 0..level {
+        if splits_vertically(ROOT_LEVEL_SIZE >> level, depth) {
+            depth /= 2;
+        }
+    }
+    depth
+}
+
+// Bounds of the nodes of the level that intersect the region, whether the
+// tree has them or not
+pub fn bounds_in_region(region: &Region, level: u32) -> Vec<Box3D<i32, WorldSpace>> {
+    let size = ROOT_LEVEL_SIZE >> level;
+    let depth = level_depth(level);
+    let bounding_box = Box2D::from_points(region.points()).round_out().to_i32();
+    let min_x = round_down_to_multiple_of(bounding_box.min.x, size);
+    let min_y = round_down_to_multiple_of(bounding_box.min.y, size);
+    let max_x = round_up_to_multiple_of(bounding_box.max.x, size).max(min_x + size);
+    let max_y = round_up_to_multiple_of(bounding_box.max.y, size).max(min_y + size);
+    let mut bounds = vec![];
+    for x in (min_x..max_x).step_by(size as _) {
+        for y in (min_y..max_y).step_by(size as _) {
+            let the_box = Box2D::new(point2(x, y), point2(x + size, y + size));
+            if region.intersects_box(&the_box.to_f32()) {
+                for z in (MIN_Z..MAX_Z).step_by(depth as _) {
+                    bounds.push(Box3D::new(
+                        the_box.min.extend(z),
+                        the_box.max.extend(z + depth),
+                    ));
+                }
+            }
+        }
+    }
+    bounds
+}
+
 fn round_down_to_multiple_of(n: i32, m: i32) -> i32 {
     if n >= 0 {
         (n / m) * m
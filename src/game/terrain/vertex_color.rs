@@ -0,0 +1,42 @@
+use super::biome::BIOME_COUNT;
+
+// Tint of the ground baked into the vertices, from altitude and moisture.
+// Keep in sync with vertex_color.wgsl
+const LOW_TINT: [f32; 3] = [0.8, 0.9, 0.8];
+const HIGH_TINT: [f32; 3] = [1.15, 1.1, 1.05];
+const DRY_TINT: [f32; 3] = [1.15, 1.0, 0.8];
+const WET_TINT: [f32; 3] = [0.85, 1.05, 0.95];
+const LOW_ALTITUDE: f32 = -0.1;
+const HIGH_ALTITUDE: f32 = 0.4;
+// Channels are stored in 8 bits up to this value
+const TINT_RANGE: f32 = 2.0;
+
+fn smoothstep(edge0: f32, edge1: f32, x: f32) -> f32 {
+    let t = ((x - edge0) / (edge1 - edge0)).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+fn mix(a: [f32; 3], b: [f32; 3], t: f32) -> [f32; 3] {
+    [0, 1, 2].map(|i| a[i] + (b[i] - a[i]) * t)
+}
+
+// Everything but the desert is wet, mountains are half dry
+fn moisture(weights: &[f32; BIOME_COUNT]) -> f32 {
+    (weights[0] + weights[1] + 0.5 * weights[3]).clamp(0.0, 1.0)
+}
+
+// For the vertices generated on the CPU, like the transitions between levels
+pub fn vertex_color(altitude: f32, weights: &[f32; BIOME_COUNT]) -> [f32; 3] {
+    let height = mix(
+        LOW_TINT,
+        HIGH_TINT,
+        smoothstep(LOW_ALTITUDE, HIGH_ALTITUDE, altitude),
+    );
+    let moisture = mix(DRY_TINT, WET_TINT, moisture(weights));
+    [0, 1, 2].map(|i| height[i] * moisture[i])
+}
+
+// Color packed by pack_color in vertex_color.wgsl
+pub fn unpack_color(packed: u32) -> [f32; 3] {
+    [0, 8, 16].map(|shift| ((packed >> shift) & 0xff) as f32 / 255.0 * TINT_RANGE)
+}
@@ -0,0 +1,74 @@
+use super::{ChunkCacheKey, TerrainTask};
+use parking_lot::{Mutex, RwLock};
+use std::collections::{HashMap, HashSet};
+
+struct WaitState {
+    parked: HashMap<ChunkCacheKey, TerrainTask>,
+    // Mappings that finished before their chain was parked
+    mapped: HashSet<ChunkCacheKey>,
+    ready: Vec<TerrainTask>,
+}
+
+// Chains waiting on the staging buffers of their chunk, parked until the
+// mapping finishes instead of being polled by the workers
+pub struct MappingWaiters {
+    state: Mutex<WaitState>,
+    // Wakes a worker to queue the chains that are ready
+    wake: RwLock<Option<Box<dyn Fn() + Send + Sync>>>,
+}
+
+impl MappingWaiters {
+    pub fn new() -> Self {
+        Self {
+            state: Mutex::new(WaitState {
+                parked: HashMap::new(),
+                mapped: HashSet::new(),
+                ready: vec![],
+            }),
+            wake: RwLock::new(None),
+        }
+    }
+
+    pub fn set_wake<F>(&self, wake: F)
+    where
+        F: Fn() + Send + Sync + 'static,
+    {
+        *self.wake.write() = Some(Box::new(wake));
+    }
+
+    pub fn park(&self, task: TerrainTask) {
+        let key = task.key();
+        let mut state = self.state.lock();
+        if state.mapped.remove(&key) {
+            state.ready.push(task);
+        } else {
+            state.parked.insert(key, task);
+        }
+    }
+
+    // Called on the async pool once the mapping of the chunk is done or
+    // failed, the chain finds out which when it runs again
+    pub fn mapped(&self, key: &ChunkCacheKey) {
+        {
+            let mut state = self.state.lock();
+            match state.parked.remove(key) {
+                Some(task) => state.ready.push(task),
+                None => {
+                    state.mapped.insert(*key);
+                    return;
+                }
+            }
+        }
+        if let Some(wake) = self.wake.read().as_ref() {
+            wake();
+        }
+    }
+
+    pub fn has_ready(&self) -> bool {
+        !self.state.lock().ready.is_empty()
+    }
+
+    pub fn take_ready(&self) -> Vec<TerrainTask> {
+        std::mem::take(&mut self.state.lock().ready)
+    }
+}
@@ -0,0 +1,68 @@
+use crate::gfx::Instance;
+use wgpu::*;
+
+// Welds the corners of a compacted triangle buffer into a vertex and an index
+// buffer so the mesh needs no deduplication once read back
+pub struct WeldPipelines {
+    pub claim_slots: ComputePipeline,
+    pub emit_vertices: ComputePipeline,
+    pub write_indices: ComputePipeline,
+}
+
+impl WeldPipelines {
+    pub fn new(instance: &Instance) -> Self {
+        let device = instance.device();
+        let storage_entry = |binding: u32, read_only| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Buffer {
+                ty: BufferBindingType::Storage { read_only },
+                has_dynamic_offset: false,
+                min_binding_size: None,
+            },
+            count: None,
+        };
+        let bind_group_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("terrain_weld_bind_group_layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                // compacted triangle buffer
+                storage_entry(1, true),
+                // owner and vertex index of each slot
+                storage_entry(2, false),
+                storage_entry(3, false),
+                // welded vertices and indices
+                storage_entry(4, false),
+                storage_entry(5, false),
+            ],
+        });
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("terrain_weld_pipeline_layout"),
+            bind_group_layouts: &[&bind_group_layout],
+            push_constant_ranges: &[],
+        });
+        let shader_module = device.create_shader_module(&include_wgsl!("shaders/weld.wgsl"));
+        let create_pipeline = |entry_point: &str| {
+            device.create_compute_pipeline(&ComputePipelineDescriptor {
+                label: Some("terrain_weld_compute_pipeline"),
+                entry_point,
+                module: &shader_module,
+                layout: Some(&pipeline_layout),
+            })
+        };
+        Self {
+            claim_slots: create_pipeline("claim_slots"),
+            emit_vertices: create_pipeline("emit_vertices"),
+            write_indices: create_pipeline("write_indices"),
+        }
+    }
+}
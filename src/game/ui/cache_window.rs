@@ -0,0 +1,103 @@
+use crate::game::terrain::{CacheEntry, CacheStats, ChunkCacheKey, Terrain};
+use imgui::{im_str, ImStr, Ui};
+use std::collections::BTreeMap;
+
+const BYTES_PER_MIB: f32 = 1024.0 * 1024.0;
+const BYTES_PER_KIB: f32 = 1024.0;
+// Entries listed for each cache
+const LISTED_ENTRIES: usize = 32;
+const OLDEST: usize = 0;
+
+// Counters and entries of the chunk and mesh caches, to tune the streaming
+// settings against what actually gets evicted
+pub struct CacheWindow {
+    sort: usize,
+}
+
+impl CacheWindow {
+    pub fn new() -> Self {
+        Self { sort: OLDEST }
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, terrain: &Terrain) {
+        imgui::ComboBox::new(im_str!("list")).build_simple_string(
+            ui,
+            &mut self.sort,
+            &[im_str!("oldest"), im_str!("largest")],
+        );
+        let caches = [
+            (im_str!("Chunk cache"), terrain.chunk_cache_stats()),
+            (im_str!("Mesh cache"), terrain.mesh_cache_stats()),
+        ];
+        for (name, (stats, entries)) in caches.iter() {
+            self.draw_cache(ui, name, stats, entries);
+        }
+    }
+
+    fn draw_cache(
+        &self,
+        ui: &Ui,
+        name: &ImStr,
+        stats: &CacheStats,
+        entries: &[CacheEntry<ChunkCacheKey>],
+    ) {
+        if !imgui::CollapsingHeader::new(name)
+            .default_open(true)
+            .build(ui)
+        {
+            return;
+        }
+        let lookups = stats.hits + stats.misses;
+        ui.text(format!(
+            "{} entries, {} pinned, {:.1}/{:.1} MiB",
+            stats.entries,
+            stats.pinned,
+            stats.total_weight as f32 / BYTES_PER_MIB,
+            stats.max_weight as f32 / BYTES_PER_MIB
+        ));
+        ui.text(format!(
+            "{} hits, {} misses ({:.1}% hit rate), {} evictions",
+            stats.hits,
+            stats.misses,
+            if lookups > 0 {
+                stats.hits as f32 / lookups as f32 * 100.0
+            } else {
+                0.0
+            },
+            stats.evictions
+        ));
+        let mut levels = BTreeMap::new();
+        for entry in entries {
+            let (count, bytes) = levels.entry(entry.key.level).or_insert((0, 0));
+            *count += 1;
+            *bytes += entry.weight;
+        }
+        for (level, (count, bytes)) in levels {
+            ui.text(format!(
+                "level {}: {} entries, {:.1} MiB",
+                level,
+                count,
+                bytes as f32 / BYTES_PER_MIB
+            ));
+        }
+        ui.separator();
+        let mut listed = entries.iter().collect::<Vec<_>>();
+        if self.sort != OLDEST {
+            listed.sort_by(|a, b| b.weight.cmp(&a.weight));
+        }
+        for entry in listed.iter().take(LISTED_ENTRIES) {
+            ui.text(format!(
+                "{:?} level {}: {:.1}s ago, {:.1} KiB{}",
+                entry.key.bounds.min,
+                entry.key.level,
+                entry.age.as_secs_f32(),
+                entry.weight as f32 / BYTES_PER_KIB,
+                if entry.pinned { ", pinned" } else { "" }
+            ));
+        }
+        if listed.len() > LISTED_ENTRIES {
+            ui.text(format!("{} more", listed.len() - LISTED_ENTRIES));
+        }
+    }
+}
@@ -0,0 +1,400 @@
+use crate::game::asset::load_obj;
+use crate::game::base::WorldSpace;
+use crate::game::camera::Camera;
+use crate::game::terrain::{
+    CsgEdit, CsgOperation, CsgShape, EditJob, EditOperation, SetPiece, SweepMode, Terrain,
+};
+use euclid::{point2, Box2D, Point2D, Point3D, Vector3D};
+use imgui::{im_str, ImString, Ui};
+use std::path::Path;
+use std::sync::Arc;
+
+const OPERATIONS: [&str; 4] = ["flatten", "canyon", "csg", "sweep"];
+const FLATTEN: usize = 0;
+const CANYON: usize = 1;
+const CSG: usize = 2;
+const CSG_SHAPES: [&str; 4] = ["box", "sphere", "cylinder", "capsule"];
+const CSG_BOX: usize = 0;
+const CSG_SPHERE: usize = 1;
+const CSG_CYLINDER: usize = 2;
+const CSG_OPERATIONS: [CsgOperation; 2] = [CsgOperation::Add, CsgOperation::Subtract];
+const SWEEP_MODES: [SweepMode; 2] = [SweepMode::Carve, SweepMode::Extrude];
+// Distance between the new profile point and the last one
+const PROFILE_STEP: f32 = 0.25;
+
+pub struct EditWindow {
+    operation: usize,
+    min: [f32; 2],
+    max: [f32; 2],
+    height: f32,
+    falloff: f32,
+    points: Vec<Point2D<f32, WorldSpace>>,
+    radius: f32,
+    floor: f32,
+    // Shape added to the CSG steps next. Position is the box center, the
+    // cylinder base, the sphere center or the capsule start.
+    csg_edits: Vec<CsgEdit>,
+    csg_shape: usize,
+    csg_operation: usize,
+    csg_position: [f32; 3],
+    csg_size: [f32; 3],
+    csg_end: [f32; 3],
+    csg_radius: f32,
+    // Spline points are on the ground below the camera, the profile is a
+    // list of distance and height pairs
+    sweep_points: Vec<Point3D<f32, WorldSpace>>,
+    sweep_profile: Vec<[f32; 2]>,
+    sweep_mode: usize,
+    job: Option<Arc<EditJob>>,
+    // Mesh placed as a set piece next, scaled around the origin of its file
+    set_piece_path: ImString,
+    set_piece_origin: [f32; 3],
+    set_piece_scale: f32,
+    set_piece_falloff: f32,
+    // Set pieces and edits placed on every level
+    placed: Vec<(u64, String)>,
+    set_piece_status: Option<String>,
+}
+
+impl EditWindow {
+    pub fn new() -> Self {
+        let mut set_piece_path = ImString::with_capacity(256);
+        set_piece_path.push_str("set_piece.obj");
+        Self {
+            operation: 0,
+            min: [-8.0, -8.0],
+            max: [8.0, 8.0],
+            height: 0.1,
+            falloff: 1.0,
+            points: vec![],
+            radius: 0.5,
+            floor: -0.2,
+            csg_edits: vec![],
+            csg_shape: 0,
+            csg_operation: 1,
+            csg_position: [0.0, 0.0, 0.0],
+            csg_size: [1.0, 1.0, 0.5],
+            csg_end: [1.0, 0.0, 0.0],
+            csg_radius: 0.2,
+            sweep_points: vec![],
+            // A cliff rising to the left of the spline
+            sweep_profile: vec![[-1.0, 0.5], [-0.05, 0.5], [0.05, 0.0], [1.0, 0.0]],
+            sweep_mode: 1,
+            job: None,
+            set_piece_path,
+            set_piece_origin: [0.0, 0.0, 0.0],
+            set_piece_scale: 1.0,
+            set_piece_falloff: 0.5,
+            placed: vec![],
+            set_piece_status: None,
+        }
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, terrain: &Terrain, camera: &Camera) {
+        let running = self.job.as_ref().map_or(false, |x| !x.is_finished());
+        imgui::ComboBox::new(im_str!("operation")).build_simple(
+            ui,
+            &mut self.operation,
+            &OPERATIONS,
+            &|x| im_str!("{}", x).into(),
+        );
+        match self.operation {
+            FLATTEN => {
+                ui.input_float2(im_str!("min"), &mut self.min).build();
+                ui.input_float2(im_str!("max"), &mut self.max).build();
+                ui.input_float(im_str!("height"), &mut self.height).build();
+                ui.input_float(im_str!("falloff"), &mut self.falloff)
+                    .build();
+                self.falloff = self.falloff.max(0.0);
+            }
+            CANYON => {
+                // Spline points are picked from the camera position
+                if ui.button(im_str!("Add camera position"), [0.0, 0.0]) {
+                    self.points.push(camera.position().xy());
+                }
+                ui.same_line(0.0);
+                if ui.button(im_str!("Clear points"), [0.0, 0.0]) {
+                    self.points.clear();
+                }
+                for point in &self.points {
+                    ui.text(format!("{:.3} {:.3}", point.x, point.y));
+                }
+                ui.input_float(im_str!("radius"), &mut self.radius).build();
+                ui.input_float(im_str!("floor"), &mut self.floor).build();
+                self.radius = self.radius.max(0.01);
+            }
+            CSG => self.draw_csg(ui, camera),
+            _ => self.draw_sweep(ui, terrain, camera),
+        }
+        if running {
+            let job = self.job.as_ref().unwrap();
+            imgui::ProgressBar::new(job.progress())
+                .overlay_text(&im_str!(
+                    "{}/{} chunks",
+                    job.done_count(),
+                    job.chunk_count()
+                ))
+                .build(ui);
+            if job.is_cancelled() {
+                ui.text("Cancelling...");
+            } else if ui.button(im_str!("Cancel"), [0.0, 0.0]) {
+                job.cancel();
+            }
+        } else {
+            if let Some(job) = &self.job {
+                if job.is_cancelled() {
+                    ui.text("Last edit cancelled");
+                } else {
+                    ui.text(format!(
+                        "Last edit finished on {} chunks",
+                        job.chunk_count()
+                    ));
+                }
+            }
+            if let Some(operation) = self.operation() {
+                if ui.button(im_str!("Apply"), [0.0, 0.0]) {
+                    self.job = Some(terrain.start_edit(operation.clone()));
+                }
+                // Also edits the levels and chunks that are not cached
+                ui.same_line(0.0);
+                if ui.button(im_str!("Place on every level"), [0.0, 0.0]) {
+                    let id = terrain.place_edit(operation);
+                    self.placed
+                        .push((id, format!("{} edit", OPERATIONS[self.operation])));
+                }
+            }
+        }
+        ui.separator();
+        self.draw_set_pieces(ui, terrain, camera);
+        self.draw_placed(ui, terrain);
+    }
+
+    fn draw_set_pieces(&mut self, ui: &Ui, terrain: &Terrain, camera: &Camera) {
+        ui.text("Set pieces");
+        ui.input_text(im_str!("mesh path"), &mut self.set_piece_path)
+            .build();
+        ui.input_float3(im_str!("origin"), &mut self.set_piece_origin)
+            .build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Camera##set_piece"), [0.0, 0.0]) {
+            self.set_piece_origin = camera.position().to_array();
+        }
+        ui.input_float(im_str!("scale"), &mut self.set_piece_scale)
+            .build();
+        ui.input_float(im_str!("blend falloff"), &mut self.set_piece_falloff)
+            .build();
+        self.set_piece_scale = self.set_piece_scale.max(0.001);
+        self.set_piece_falloff = self.set_piece_falloff.max(0.0);
+        if ui.button(im_str!("Place"), [0.0, 0.0]) {
+            let path = self.set_piece_path.to_str().to_string();
+            self.set_piece_status = Some(match self.load_set_piece(Path::new(&path)) {
+                Ok(set_piece) => {
+                    let id = terrain.add_set_piece(set_piece);
+                    self.placed.push((id, path));
+                    "Placed, the terrain is generated again".to_string()
+                }
+                Err(e) => format!("Failed to place: {}", e),
+            });
+        }
+        if let Some(status) = &self.set_piece_status {
+            ui.text(status);
+        }
+    }
+
+    fn draw_placed(&mut self, ui: &Ui, terrain: &Terrain) {
+        let mut removed = None;
+        for (id, name) in &self.placed {
+            ui.text(format!("{}: {}", id, name));
+            ui.same_line(0.0);
+            if ui.button(&im_str!("Remove##{}", id), [0.0, 0.0]) {
+                removed = Some(*id);
+            }
+        }
+        if let Some(id) = removed {
+            terrain.remove_placed(id);
+            self.placed.retain(|(x, _)| *x != id);
+        }
+    }
+
+    fn load_set_piece(&self, path: &Path) -> Result<SetPiece, String> {
+        let origin = Point3D::from(self.set_piece_origin);
+        let triangles = load_obj(path)
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|triangle| {
+                triangle.map(|x| origin + x.to_vector().cast_unit() * self.set_piece_scale)
+            })
+            .collect::<Vec<_>>();
+        SetPiece::new(&triangles, self.set_piece_falloff)
+            .ok_or_else(|| "the mesh has no footprint".to_string())
+    }
+
+    fn draw_csg(&mut self, ui: &Ui, camera: &Camera) {
+        imgui::ComboBox::new(im_str!("shape")).build_simple(
+            ui,
+            &mut self.csg_shape,
+            &CSG_SHAPES,
+            &|x| im_str!("{}", x).into(),
+        );
+        imgui::ComboBox::new(im_str!("boolean")).build_simple(
+            ui,
+            &mut self.csg_operation,
+            &CSG_OPERATIONS,
+            &|x| im_str!("{:?}", x).into(),
+        );
+        ui.input_float3(im_str!("position"), &mut self.csg_position)
+            .build();
+        ui.same_line(0.0);
+        if ui.button(im_str!("Camera"), [0.0, 0.0]) {
+            self.csg_position = camera.position().to_array();
+        }
+        match self.csg_shape {
+            CSG_BOX | CSG_CYLINDER => {
+                ui.input_float3(im_str!("size"), &mut self.csg_size).build();
+            }
+            CSG_SPHERE => {}
+            _ => {
+                ui.input_float3(im_str!("end"), &mut self.csg_end).build();
+            }
+        }
+        if self.csg_shape != CSG_BOX {
+            ui.input_float(im_str!("shape radius"), &mut self.csg_radius)
+                .build();
+            self.csg_radius = self.csg_radius.max(0.01);
+        }
+        if ui.button(im_str!("Add step"), [0.0, 0.0]) {
+            let shape = self.csg_shape();
+            self.csg_edits.push(CsgEdit {
+                shape,
+                operation: CSG_OPERATIONS[self.csg_operation],
+            });
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Clear steps"), [0.0, 0.0]) {
+            self.csg_edits.clear();
+        }
+        for (i, edit) in self.csg_edits.iter().enumerate() {
+            ui.text(format!("{}: {:?} {:?}", i, edit.operation, edit.shape));
+        }
+    }
+
+    fn draw_sweep(&mut self, ui: &Ui, terrain: &Terrain, camera: &Camera) {
+        imgui::ComboBox::new(im_str!("mode")).build_simple(
+            ui,
+            &mut self.sweep_mode,
+            &SWEEP_MODES,
+            &|x| im_str!("{:?}", x).into(),
+        );
+        if ui.button(im_str!("Add camera position##sweep"), [0.0, 0.0]) {
+            let position = camera.position();
+            let z = terrain.height_at(&position.xy()).unwrap_or(position.z);
+            self.sweep_points.push(position.xy().extend(z));
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Clear points##sweep"), [0.0, 0.0]) {
+            self.sweep_points.clear();
+        }
+        for (i, point) in self.sweep_points.iter_mut().enumerate() {
+            let mut z = point.z;
+            ui.text(format!("{:.3} {:.3}", point.x, point.y));
+            ui.same_line(0.0);
+            ui.input_float(&im_str!("##sweep_z{}", i), &mut z).build();
+            point.z = z;
+        }
+        ui.text("profile: distance, height");
+        let mut removed = None;
+        for (i, point) in self.sweep_profile.iter_mut().enumerate() {
+            ui.input_float2(&im_str!("##profile{}", i), point).build();
+            ui.same_line(0.0);
+            if ui.button(&im_str!("Remove##profile{}", i), [0.0, 0.0]) {
+                removed = Some(i);
+            }
+        }
+        if let Some(i) = removed {
+            self.sweep_profile.remove(i);
+        }
+        if ui.button(im_str!("Add profile point"), [0.0, 0.0]) {
+            let last = self.sweep_profile.last().copied().unwrap_or([0.0, 0.0]);
+            self.sweep_profile.push([last[0] + PROFILE_STEP, last[1]]);
+        }
+    }
+
+    fn csg_shape(&self) -> CsgShape {
+        let position = Point3D::from(self.csg_position);
+        let size = Vector3D::from(self.csg_size);
+        match self.csg_shape {
+            CSG_BOX => CsgShape::Box {
+                min: position - size * 0.5,
+                max: position + size * 0.5,
+            },
+            CSG_SPHERE => CsgShape::Sphere {
+                center: position,
+                radius: self.csg_radius,
+            },
+            CSG_CYLINDER => CsgShape::Cylinder {
+                center: point2(position.x, position.y),
+                radius: self.csg_radius,
+                min_z: position.z,
+                max_z: position.z + size.z,
+            },
+            _ => CsgShape::Capsule {
+                start: position,
+                end: self.csg_end.into(),
+                radius: self.csg_radius,
+            },
+        }
+    }
+
+    fn operation(&self) -> Option<EditOperation> {
+        match self.operation {
+            FLATTEN => {
+                let bounds = Box2D::new(self.min.into(), self.max.into());
+                if bounds.is_empty() {
+                    None
+                } else {
+                    Some(EditOperation::Flatten {
+                        bounds,
+                        height: self.height,
+                        falloff: self.falloff,
+                    })
+                }
+            }
+            CANYON => {
+                if self.points.is_empty() {
+                    None
+                } else {
+                    Some(EditOperation::Canyon {
+                        points: self.points.clone(),
+                        radius: self.radius,
+                        floor: self.floor,
+                    })
+                }
+            }
+            CSG => {
+                if self.csg_edits.is_empty() {
+                    None
+                } else {
+                    Some(EditOperation::Csg(self.csg_edits.clone()))
+                }
+            }
+            _ => {
+                if self.sweep_points.is_empty() || self.sweep_profile.len() < 2 {
+                    None
+                } else {
+                    let mut profile = self
+                        .sweep_profile
+                        .iter()
+                        .map(|x| (x[0], x[1]))
+                        .collect::<Vec<_>>();
+                    profile.sort_by(|a, b| a.0.partial_cmp(&b.0).unwrap());
+                    Some(EditOperation::Sweep {
+                        points: self.sweep_points.clone(),
+                        profile,
+                        mode: SWEEP_MODES[self.sweep_mode],
+                    })
+                }
+            }
+        }
+    }
+}
@@ -0,0 +1,61 @@
+use crate::game::terrain::{ChunkCacheKey, Terrain, TerrainError, TerrainEvent};
+use imgui::{im_str, Ui};
+use std::collections::VecDeque;
+use std::sync::mpsc::Receiver;
+use std::time::Instant;
+
+// Older failures are dropped from the list
+const MAX_ERRORS: usize = 64;
+const ERROR_COLOR: [f32; 4] = [1.0, 0.4, 0.4, 1.0];
+
+// Failures of the terrain tasks as the workers send them, newest first
+pub struct ErrorWindow {
+    events: Receiver<TerrainEvent>,
+    errors: VecDeque<(Instant, ChunkCacheKey, TerrainError)>,
+    // Failures since the list was last cleared, including the dropped ones
+    count: usize,
+}
+
+impl ErrorWindow {
+    pub fn new(terrain: &Terrain) -> Self {
+        Self {
+            events: terrain.subscribe(),
+            errors: VecDeque::new(),
+            count: 0,
+        }
+    }
+
+    fn receive(&mut self) {
+        for event in self.events.try_iter() {
+            if let TerrainEvent::Failed(key, error) = event {
+                self.errors.push_front((Instant::now(), key, error));
+                self.errors.truncate(MAX_ERRORS);
+                self.count += 1;
+            }
+        }
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui) {
+        self.receive();
+        ui.text(format!("{} failures", self.count));
+        ui.same_line(0.0);
+        if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+            self.errors.clear();
+            self.count = 0;
+        }
+        ui.separator();
+        for (time, key, error) in &self.errors {
+            ui.text_colored(
+                ERROR_COLOR,
+                format!(
+                    "{:.0}s ago, level {} at {:?}: {}",
+                    time.elapsed().as_secs_f32(),
+                    key.level,
+                    key.bounds.min,
+                    error
+                ),
+            );
+        }
+    }
+}
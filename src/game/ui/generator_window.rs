@@ -0,0 +1,66 @@
+use crate::game::terrain::{
+    generate_voxel_shader, DensityGenerator, ShaderGenerator, Terrain, DEFAULT_DENSITY,
+};
+use imgui::{im_str, ImString, Ui};
+use std::sync::Arc;
+
+const DENSITY: usize = 0;
+// Room for editing the source
+const SOURCE_CAPACITY: usize = 1 << 16;
+
+pub struct GeneratorWindow {
+    kind: usize,
+    source: ImString,
+    status: Option<String>,
+}
+
+impl GeneratorWindow {
+    pub fn new() -> Self {
+        Self {
+            kind: DENSITY,
+            source: default_source(DENSITY),
+            status: None,
+        }
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, terrain: &Terrain) {
+        if imgui::ComboBox::new(im_str!("generator")).build_simple_string(
+            ui,
+            &mut self.kind,
+            &[im_str!("density function"), im_str!("compute shader")],
+        ) {
+            self.source = default_source(self.kind);
+            self.status = None;
+        }
+        ui.input_text_multiline(im_str!("##source"), &mut self.source, [480.0, 320.0])
+            .build();
+        if ui.button(im_str!("Apply"), [0.0, 0.0]) {
+            let source = self.source.to_str().to_string();
+            let result = match self.kind {
+                DENSITY => terrain.set_generator(Arc::new(DensityGenerator::new(source))),
+                _ => terrain.set_generator(Arc::new(ShaderGenerator::new(source))),
+            };
+            self.status = Some(match result {
+                Ok(()) => "Regenerating terrain".to_string(),
+                Err(error) => error,
+            });
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Reset"), [0.0, 0.0]) {
+            self.source = default_source(self.kind);
+        }
+        if let Some(status) = &self.status {
+            ui.text(status);
+        }
+    }
+}
+
+fn default_source(kind: usize) -> ImString {
+    let mut source = ImString::with_capacity(SOURCE_CAPACITY);
+    match kind {
+        DENSITY => source.push_str(DEFAULT_DENSITY),
+        _ => source.push_str(&generate_voxel_shader(DEFAULT_DENSITY)),
+    }
+    source
+}
@@ -1,4 +1,4 @@
-use crate::gfx::Instance;
+use crate::gfx::{Instance, SamplerKey};
 use imgui::{internal::RawWrapper, Context, FontConfig, FontSource, TextureId, Ui};
 use imgui_winit_support::{HiDpiMode, WinitPlatform};
 use std::{
@@ -23,7 +23,6 @@ pub struct ImguiRenderer {
     context: Context,
     platform: WinitPlatform,
     pipeline: Option<RenderPipeline>,
-    sampler: Option<Sampler>,
     texture_bind_group_layout: Option<BindGroupLayout>,
     uniform_bind_group_layout: Option<BindGroupLayout>,
     texture_bind_groups: HashMap<TextureId, BindGroup>,
@@ -43,7 +42,6 @@ impl ImguiRenderer {
             context,
             platform,
             pipeline: None,
-            sampler: None,
             texture_bind_group_layout: None,
             uniform_bind_group_layout: None,
             texture_bind_groups: HashMap::new(),
@@ -72,7 +70,6 @@ impl ImguiRenderer {
 
         // Create pipeline objects
         self.create_texture_bind_group_layout(instance);
-        self.create_sampler(instance);
         self.create_uniform_bind_group_layout(instance);
         self.create_pipeline(instance);
         self.create_font_texture(instance);
@@ -350,14 +347,6 @@ impl ImguiRenderer {
         }
     }
 
-    fn create_sampler(&mut self, instance: &Instance) {
-        let device = instance.device();
-        let sampler = device.create_sampler(&SamplerDescriptor {
-            ..Default::default()
-        });
-        self.sampler = Some(sampler);
-    }
-
     fn create_texture_bind_group_layout(&mut self, instance: &Instance) {
         let device = instance.device();
         let texture_bind_group_layout =
@@ -369,7 +358,7 @@ impl ImguiRenderer {
                         visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Sampler {
                             comparison: false,
-                            filtering: false,
+                            filtering: true,
                         },
                         count: None,
                     },
@@ -378,7 +367,7 @@ impl ImguiRenderer {
                         visibility: ShaderStages::FRAGMENT,
                         ty: BindingType::Texture {
                             view_dimension: TextureViewDimension::D2,
-                            sample_type: TextureSampleType::Float { filterable: false },
+                            sample_type: TextureSampleType::Float { filterable: true },
                             multisampled: false,
                         },
                         count: None,
@@ -492,13 +481,19 @@ impl ImguiRenderer {
         let font_texture_view = font_texture.create_view(&TextureViewDescriptor {
             ..Default::default()
         });
-        self.register_texture(instance, &font_texture_view, TextureId::from(0));
+        self.register_texture(
+            instance,
+            &font_texture_view,
+            &instance.sampler(&SamplerKey::default()),
+            TextureId::from(0),
+        );
     }
 
     pub fn register_texture(
         &mut self,
         instance: &Instance,
         texture_view: &TextureView,
+        sampler: &Sampler,
         texture_id: TextureId,
     ) {
         let device = instance.device();
@@ -506,7 +501,7 @@ impl ImguiRenderer {
             entries: &[
                 BindGroupEntry {
                     binding: 0,
-                    resource: BindingResource::Sampler(self.sampler.as_ref().unwrap()),
+                    resource: BindingResource::Sampler(sampler),
                 },
                 BindGroupEntry {
                     binding: 1,
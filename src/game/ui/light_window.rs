@@ -0,0 +1,76 @@
+use crate::game::base::WorldSpace;
+use crate::game::object::{PointLight, LIGHT_KINDS};
+use crate::game::terrain::MAX_POINT_LIGHTS;
+use euclid::Point3D;
+use imgui::{im_str, Ui};
+
+pub struct LightWindow {
+    kind: usize,
+    placing: bool,
+}
+
+impl LightWindow {
+    pub fn new() -> Self {
+        Self {
+            kind: 0,
+            placing: false,
+        }
+    }
+
+    // Shift + left click places a light instead of a rock
+    pub fn is_placing(&self) -> bool {
+        self.placing
+    }
+
+    // Nothing is placed once the shader limit is reached
+    pub fn place(&self, surface: &Point3D<f32, WorldSpace>, lights: &mut Vec<PointLight>) {
+        if lights.len() < MAX_POINT_LIGHTS {
+            lights.push(PointLight::place(LIGHT_KINDS[self.kind], surface));
+        }
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, lights: &mut Vec<PointLight>) {
+        ui.checkbox(
+            im_str!("shift + left click places lights"),
+            &mut self.placing,
+        );
+        imgui::ComboBox::new(im_str!("kind")).build_simple(
+            ui,
+            &mut self.kind,
+            &LIGHT_KINDS,
+            &|x| imgui::im_str!("{}", x.name()).into(),
+        );
+        ui.text(format!("{}/{} lights", lights.len(), MAX_POINT_LIGHTS));
+        ui.same_line(0.0);
+        if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+            lights.clear();
+        }
+        let mut removed = None;
+        for (i, light) in lights.iter_mut().enumerate() {
+            ui.separator();
+            ui.text(format!(
+                "{} {}: {:.3} {:.3} {:.3}",
+                i,
+                light.kind.name(),
+                light.position.x,
+                light.position.y,
+                light.position.z
+            ));
+            ui.same_line(0.0);
+            if ui.button(&im_str!("Remove##{}", i), [0.0, 0.0]) {
+                removed = Some(i);
+            }
+            imgui::ColorEdit::new(&im_str!("color##{}", i), &mut light.color).build(ui);
+            imgui::Slider::new(&im_str!("radius##{}", i))
+                .range(0.01..=0.5)
+                .build(ui, &mut light.radius);
+            imgui::Slider::new(&im_str!("intensity##{}", i))
+                .range(0.0..=4.0)
+                .build(ui, &mut light.intensity);
+        }
+        if let Some(i) = removed {
+            lights.remove(i);
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use crate::game::warmup::Warmup;
+use imgui::{im_str, Condition, Ui};
+
+const PROGRESS_BAR_WIDTH: f32 = 320.0;
+
+// Full screen cover while the chunks around the spawn point are generated
+#[profiling::function]
+pub fn draw_loading_screen(ui: &Ui, warmup: &Warmup) {
+    let display_size = ui.io().display_size;
+    imgui::Window::new(im_str!("Loading"))
+        .position([0.0, 0.0], Condition::Always)
+        .size(display_size, Condition::Always)
+        .title_bar(false)
+        .resizable(false)
+        .movable(false)
+        .scroll_bar(false)
+        .build(ui, || {
+            ui.set_cursor_pos([
+                (display_size[0] - PROGRESS_BAR_WIDTH) / 2.0,
+                display_size[1] / 2.0,
+            ]);
+            ui.text("Generating terrain");
+            ui.set_cursor_pos([
+                (display_size[0] - PROGRESS_BAR_WIDTH) / 2.0,
+                ui.cursor_pos()[1],
+            ]);
+            imgui::ProgressBar::new(warmup.progress())
+                .size([PROGRESS_BAR_WIDTH, 0.0])
+                .overlay_text(&im_str!(
+                    "{}/{} chunks",
+                    warmup.done_count(),
+                    warmup.total()
+                ))
+                .build(ui);
+        });
+}
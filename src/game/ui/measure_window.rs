@@ -0,0 +1,118 @@
+use crate::game::base::WorldSpace;
+use euclid::{vec3, Point3D};
+use imgui::{im_str, Ui};
+
+const RULER: usize = 0;
+// Height of the marker drawn at every point
+const MARKER_HEIGHT: f32 = 0.02;
+
+pub struct MeasureWindow {
+    mode: usize,
+    points: Vec<Point3D<f32, WorldSpace>>,
+}
+
+impl MeasureWindow {
+    pub fn new() -> Self {
+        Self {
+            mode: RULER,
+            points: vec![],
+        }
+    }
+
+    // The ruler starts over on the third point, the area keeps every point as
+    // a corner of the polygon
+    pub fn add_point(&mut self, point: Point3D<f32, WorldSpace>) {
+        if self.mode == RULER && self.points.len() >= 2 {
+            self.points.clear();
+        }
+        self.points.push(point);
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui) {
+        if imgui::ComboBox::new(im_str!("tool")).build_simple_string(
+            ui,
+            &mut self.mode,
+            &[im_str!("ruler"), im_str!("area")],
+        ) {
+            self.points.clear();
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+            self.points.clear();
+        }
+        ui.text("ctrl + left click on the terrain to add a point");
+        if self.mode == RULER {
+            if let [p0, p1] = self.points[..] {
+                let horizontal = p0.xy().distance_to(p1.xy());
+                let elevation = p1.z - p0.z;
+                ui.text(format!("distance: {:.4}", p0.distance_to(p1)));
+                ui.text(format!("horizontal distance: {:.4}", horizontal));
+                ui.text(format!("elevation difference: {:.4}", elevation));
+                ui.text(format!(
+                    "slope: {:.1} degrees",
+                    elevation.atan2(horizontal).to_degrees()
+                ));
+            } else {
+                ui.text(format!("{}/2 points", self.points.len()));
+            }
+        } else if self.points.len() >= 3 {
+            let (min_z, max_z) = self
+                .points
+                .iter()
+                .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), p| {
+                    (min.min(p.z), max.max(p.z))
+                });
+            ui.text(format!("{} points", self.points.len()));
+            ui.text(format!("projected area: {:.4}", self.area()));
+            ui.text(format!("perimeter: {:.4}", self.perimeter()));
+            ui.text(format!("elevation range: {:.4}", max_z - min_z));
+        } else {
+            ui.text(format!("{}/3 points", self.points.len()));
+        }
+    }
+
+    // Lines to draw in the scene. The ruler also shows the horizontal and
+    // vertical legs of the slope.
+    pub fn segments(&self) -> Vec<(Point3D<f32, WorldSpace>, Point3D<f32, WorldSpace>)> {
+        let mut segments = self
+            .points
+            .iter()
+            .map(|p| (*p, *p + vec3(0.0, 0.0, MARKER_HEIGHT)))
+            .collect::<Vec<_>>();
+        if self.mode == RULER {
+            if let [p0, p1] = self.points[..] {
+                let corner = Point3D::new(p1.x, p1.y, p0.z);
+                segments.push((p0, p1));
+                segments.push((p0, corner));
+                segments.push((corner, p1));
+            }
+        } else {
+            segments.extend(self.points.windows(2).map(|x| (x[0], x[1])));
+            if self.points.len() >= 3 {
+                segments.push((*self.points.last().unwrap(), self.points[0]));
+            }
+        }
+        segments
+    }
+
+    // Shoelace formula on the outline projected to the ground plane
+    fn area(&self) -> f32 {
+        let n = self.points.len();
+        (0..n)
+            .map(|i| {
+                let (a, b) = (self.points[i], self.points[(i + 1) % n]);
+                a.x * b.y - b.x * a.y
+            })
+            .sum::<f32>()
+            .abs()
+            / 2.0
+    }
+
+    fn perimeter(&self) -> f32 {
+        let n = self.points.len();
+        (0..n)
+            .map(|i| self.points[i].distance_to(self.points[(i + 1) % n]))
+            .sum()
+    }
+}
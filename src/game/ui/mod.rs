@@ -1,5 +1,35 @@
+mod cache_window;
+mod edit_window;
+mod error_window;
+mod generator_window;
 mod imgui_renderer;
+mod light_window;
+mod loading_screen;
+mod measure_window;
+mod normal_map_window;
+mod object_window;
+mod photo_window;
+mod preview_world_window;
+mod profile_window;
+mod settings_window;
+mod stats_overlay;
 mod terrain_visualizer;
+mod texture_window;
 
+pub use cache_window::CacheWindow;
+pub use edit_window::EditWindow;
+pub use error_window::ErrorWindow;
+pub use generator_window::GeneratorWindow;
 pub use imgui_renderer::ImguiRenderer;
+pub use light_window::LightWindow;
+pub use loading_screen::draw_loading_screen;
+pub use measure_window::MeasureWindow;
+pub use normal_map_window::NormalMapWindow;
+pub use object_window::ObjectWindow;
+pub use photo_window::PhotoWindow;
+pub use preview_world_window::PreviewWorldWindow;
+pub use profile_window::ProfileWindow;
+pub use settings_window::{SettingsResponse, SettingsWindow};
+pub use stats_overlay::draw_stats_overlay;
 pub use terrain_visualizer::TerrainVisualizer;
+pub use texture_window::{TextureWindow, PREVIEW_TEXTURE_ID};
@@ -0,0 +1,125 @@
+use crate::game::camera::Camera;
+use crate::game::normal_map::NormalMap;
+use crate::game::terrain::{ExportSource, Terrain};
+use euclid::{size2, Box2D};
+use imgui::{im_str, ImString, Ui};
+use std::path::Path;
+
+const NORMAL_MAP: usize = 0;
+const HEIGHTMAP: usize = 1;
+
+pub struct NormalMapWindow {
+    output: usize,
+    // Height of the density slice
+    slice_height: f32,
+    min: [f32; 2],
+    max: [f32; 2],
+    resolution: i32,
+    path: ImString,
+    status: Option<String>,
+}
+
+impl NormalMapWindow {
+    pub fn new() -> Self {
+        let mut path = ImString::with_capacity(256);
+        path.push_str("normal_map.png");
+        Self {
+            output: NORMAL_MAP,
+            slice_height: 0.0,
+            min: [-8.0, -8.0],
+            max: [8.0, 8.0],
+            resolution: 256,
+            path,
+            status: None,
+        }
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, terrain: &Terrain, camera: &Camera) {
+        imgui::ComboBox::new(im_str!("output")).build_simple_string(
+            ui,
+            &mut self.output,
+            &[
+                im_str!("normal map"),
+                im_str!("heightmap (16 bit)"),
+                im_str!("density slice (16 bit)"),
+            ],
+        );
+        if self.output > HEIGHTMAP {
+            ui.input_float(im_str!("slice height"), &mut self.slice_height)
+                .build();
+        }
+        ui.input_float2(im_str!("min"), &mut self.min).build();
+        ui.input_float2(im_str!("max"), &mut self.max).build();
+        if ui.button(im_str!("Center on camera"), [0.0, 0.0]) {
+            let position = camera.position();
+            let half_width = (self.max[0] - self.min[0]) / 2.0;
+            let half_height = (self.max[1] - self.min[1]) / 2.0;
+            self.min = [position.x - half_width, position.y - half_height];
+            self.max = [position.x + half_width, position.y + half_height];
+        }
+        if ui
+            .input_int(im_str!("resolution"), &mut self.resolution)
+            .build()
+        {
+            self.resolution = self.resolution.clamp(1, 4096);
+        }
+        ui.input_text(im_str!("path"), &mut self.path).build();
+        if ui.button(im_str!("Export"), [0.0, 0.0]) {
+            let bounds = Box2D::new(self.min.into(), self.max.into());
+            self.status = Some(if bounds.is_empty() {
+                "Region is empty".to_string()
+            } else {
+                // Keep texels square
+                let aspect_ratio = bounds.width() / bounds.height();
+                let size = if aspect_ratio >= 1.0 {
+                    size2(
+                        self.resolution as u32,
+                        ((self.resolution as f32 / aspect_ratio) as u32).max(1),
+                    )
+                } else {
+                    size2(
+                        ((self.resolution as f32 * aspect_ratio) as u32).max(1),
+                        self.resolution as u32,
+                    )
+                };
+                let path = Path::new(self.path.to_str());
+                let result = match self.output {
+                    NORMAL_MAP => NormalMap::bake(terrain, &bounds, size)
+                        .save_png(path)
+                        .map(|_| String::new()),
+                    output => {
+                        let source = if output == HEIGHTMAP {
+                            ExportSource::SurfaceHeight
+                        } else {
+                            ExportSource::Density(self.slice_height)
+                        };
+                        let image = terrain.export_image(&bounds, size, source);
+                        let (min, max) = image.range();
+                        image.save_png(path).map(|_| {
+                            format!(
+                                ", range {:.4} to {:.4}, {} texels without chunk",
+                                min,
+                                max,
+                                image.missing_count()
+                            )
+                        })
+                    }
+                };
+                match result {
+                    Ok(details) => format!(
+                        "Saved {}x{} to {}{}",
+                        size.width,
+                        size.height,
+                        self.path.to_str(),
+                        details
+                    ),
+                    Err(e) => format!("Failed to save: {}", e),
+                }
+            });
+        }
+        if let Some(status) = &self.status {
+            ui.text(status);
+        }
+    }
+}
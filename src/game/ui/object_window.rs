@@ -0,0 +1,88 @@
+use crate::game::camera::Camera;
+use crate::game::object::{Object, Orientation, PlacementSettings};
+use crate::game::random::{RandomStreams, Stream};
+use crate::game::terrain::Terrain;
+use imgui::{im_str, Ui};
+
+pub struct ObjectWindow {
+    orientation: usize,
+    settings: PlacementSettings,
+    seed: i32,
+    scatter_radius: f32,
+}
+
+impl ObjectWindow {
+    pub fn new() -> Self {
+        Self {
+            orientation: 0,
+            settings: PlacementSettings::default(),
+            seed: 0,
+            scatter_radius: 0.25,
+        }
+    }
+
+    pub fn settings(&self) -> &PlacementSettings {
+        &self.settings
+    }
+
+    // Every placed rock gets the next seed
+    pub fn next_seed(&mut self) -> u32 {
+        let seed = self.seed as u32;
+        self.seed = self.seed.wrapping_add(1);
+        seed
+    }
+
+    #[profiling::function]
+    pub fn draw(
+        &mut self,
+        ui: &Ui,
+        terrain: &Terrain,
+        camera: &Camera,
+        random: &RandomStreams,
+        objects: &mut Vec<Object>,
+    ) {
+        ui.text("Shift + left click in the scene to place");
+        if imgui::ComboBox::new(im_str!("orientation")).build_simple_string(
+            ui,
+            &mut self.orientation,
+            &[im_str!("align to normal"), im_str!("upright")],
+        ) {
+            self.settings.orientation = match self.orientation {
+                0 => Orientation::AlignToNormal,
+                _ => Orientation::Upright,
+            };
+        }
+        ui.input_float(im_str!("sink depth"), &mut self.settings.sink_depth)
+            .build();
+        ui.input_float(im_str!("snap distance"), &mut self.settings.snap_distance)
+            .build();
+        self.settings.snap_distance = self.settings.snap_distance.max(0.0);
+        ui.input_int(im_str!("rock seed"), &mut self.seed).build();
+        // The same world and camera position always scatter the same rocks
+        imgui::Slider::new(im_str!("scatter radius"))
+            .range(0.05..=1.0)
+            .build(ui, &mut self.scatter_radius);
+        if ui.button(im_str!("Scatter around camera"), [0.0, 0.0]) {
+            objects.extend(Object::scatter(
+                terrain,
+                &camera.position().xy(),
+                self.scatter_radius,
+                &self.settings,
+                &mut random.stream(Stream::Scattering),
+            ));
+        }
+        ui.text(format!("{} objects", objects.len()));
+        // Objects that are no longer close to the terrain are dropped
+        if ui.button(im_str!("Resnap"), [0.0, 0.0]) {
+            let settings = self.settings;
+            *objects = objects
+                .iter()
+                .filter_map(|x| x.resnap(terrain, &settings))
+                .collect();
+        }
+        ui.same_line(0.0);
+        if ui.button(im_str!("Clear"), [0.0, 0.0]) {
+            objects.clear();
+        }
+    }
+}
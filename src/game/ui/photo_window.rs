@@ -0,0 +1,126 @@
+use crate::game::base::WorldSpace;
+use crate::game::camera::Camera;
+use euclid::{Point3D, Vector3D};
+use imgui::{im_str, ImString, Ui};
+use std::path::Path;
+
+// Multiples of the 640x480 scene size, the render target of every tile has
+// to fit in the texture size limit
+const SCREENSHOT_SCALES: [u32; 4] = [1, 2, 4, 8];
+const MAX_TILES: i32 = 8;
+
+// View restored when leaving photo mode
+struct SavedCamera {
+    position: Point3D<f32, WorldSpace>,
+    direction: Vector3D<f32, WorldSpace>,
+    fov: f32,
+}
+
+pub struct PhotoWindow {
+    saved_camera: Option<SavedCamera>,
+    hide_ui: bool,
+    scale: usize,
+    tiles: i32,
+    path: ImString,
+    status: Option<String>,
+}
+
+impl PhotoWindow {
+    pub fn new() -> Self {
+        let mut path = ImString::with_capacity(256);
+        path.push_str("screenshot.png");
+        Self {
+            saved_camera: None,
+            hide_ui: false,
+            scale: 0,
+            tiles: 1,
+            path,
+            status: None,
+        }
+    }
+
+    pub fn is_active(&self) -> bool {
+        self.saved_camera.is_some()
+    }
+
+    pub fn hide_ui(&self) -> bool {
+        self.is_active() && self.hide_ui
+    }
+
+    pub fn enter(&mut self, camera: &Camera) {
+        self.saved_camera = Some(SavedCamera {
+            position: *camera.position(),
+            direction: *camera.direction(),
+            fov: camera.fov(),
+        });
+        self.hide_ui = false;
+        self.status = None;
+    }
+
+    pub fn exit(&mut self, camera: &mut Camera) {
+        if let Some(saved) = self.saved_camera.take() {
+            camera.move_to(&saved.position);
+            camera.look_in_direction(&saved.direction);
+            camera.set_fov(saved.fov);
+            camera.set_roll(0.0);
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        Path::new(self.path.to_str())
+    }
+
+    pub fn set_status(&mut self, status: String) {
+        self.status = Some(status);
+    }
+
+    // Returns the screenshot scale and tiles per side when the screenshot
+    // button is pressed
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, camera: &mut Camera) -> Option<(u32, u32)> {
+        let mut fov = camera.fov().to_degrees();
+        if imgui::Slider::new(im_str!("fov"))
+            .range(10.0..=120.0)
+            .build(ui, &mut fov)
+        {
+            camera.set_fov(fov.to_radians());
+        }
+        let mut roll = camera.roll().to_degrees();
+        if imgui::Slider::new(im_str!("roll"))
+            .range(-180.0..=180.0)
+            .build(ui, &mut roll)
+        {
+            camera.set_roll(roll.to_radians());
+        }
+        ui.checkbox(im_str!("hide ui"), &mut self.hide_ui);
+        ui.text("the photo mode key leaves photo mode");
+        imgui::ComboBox::new(im_str!("super-resolution")).build_simple(
+            ui,
+            &mut self.scale,
+            &SCREENSHOT_SCALES,
+            &|x| im_str!("{}x", x).into(),
+        );
+        if ui
+            .input_int(im_str!("tiles per side"), &mut self.tiles)
+            .build()
+        {
+            self.tiles = self.tiles.clamp(1, MAX_TILES);
+        }
+        let scale = SCREENSHOT_SCALES[self.scale];
+        ui.text(format!(
+            "output: {}x{}",
+            640 * scale * self.tiles as u32,
+            480 * scale * self.tiles as u32
+        ));
+        ui.input_text(im_str!("path"), &mut self.path).build();
+        let screenshot = if ui.button(im_str!("Take screenshot"), [0.0, 0.0]) {
+            Some((scale, self.tiles as u32))
+        } else {
+            None
+        };
+        if let Some(status) = &self.status {
+            ui.text(status);
+        }
+        screenshot
+    }
+}
@@ -0,0 +1,104 @@
+use crate::game::terrain::{PreviewWorld, PREVIEW_WORLD_RESOLUTION};
+use imgui::{im_str, Ui};
+
+// Side of the drawn grid in pixels
+const MAP_SIZE: f32 = 256.0;
+const LOW_COLOR: [f32; 3] = [0.2, 0.4, 0.2];
+const HIGH_COLOR: [f32; 3] = [0.9, 0.85, 0.75];
+const MISSING_COLOR: [f32; 3] = [0.1, 0.1, 0.3];
+// Direction of the light of the hillshade, from the north west
+const LIGHT: [f32; 2] = [-0.7, 0.7];
+// How much the slopes toward or away from the light change their shade
+const SHADE_STRENGTH: f32 = 8.0;
+
+// Top down view of the preview world. While changes are held the parameter
+// sliders only update the preview and the terrain is generated again once
+// they are applied.
+pub struct PreviewWorldWindow {
+    hold_changes: bool,
+}
+
+impl PreviewWorldWindow {
+    pub fn new() -> Self {
+        Self {
+            hold_changes: false,
+        }
+    }
+
+    pub fn holds_changes(&self) -> bool {
+        self.hold_changes
+    }
+
+    // Returns true when the held changes have to be applied to the terrain,
+    // which is also the case when they stop being held
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, world: Option<&PreviewWorld>) -> bool {
+        let mut apply = ui.checkbox(im_str!("hold terrain changes"), &mut self.hold_changes)
+            && !self.hold_changes;
+        if self.hold_changes {
+            ui.same_line(0.0);
+            apply |= ui.button(im_str!("Apply"), [0.0, 0.0]);
+        }
+        let world = match world {
+            Some(world) => world,
+            None => {
+                ui.text("simulating...");
+                return apply;
+            }
+        };
+        let (min, max) = world
+            .heights
+            .iter()
+            .flatten()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+                (min.min(*h), max.max(*h))
+            });
+        ui.text(format!(
+            "{:?} to {:?}, simulated in {:.1}ms",
+            world.bounds.min,
+            world.bounds.max,
+            world.duration.as_secs_f32() * 1000.0
+        ));
+        if min <= max {
+            ui.text(format!("elevation: {:.4} to {:.4}", min, max));
+        }
+        let resolution = PREVIEW_WORLD_RESOLUTION as i32;
+        let range = (max - min).max(f32::EPSILON);
+        // Normalized so that the shade does not depend on the height range
+        let height = |x: i32, y: i32| {
+            let x = x.clamp(0, resolution - 1);
+            let y = y.clamp(0, resolution - 1);
+            world.heights[(x + y * resolution) as usize].map(|h| (h - min) / range)
+        };
+        let origin = ui.cursor_screen_pos();
+        let cell = MAP_SIZE / resolution as f32;
+        let draw_list = ui.get_window_draw_list();
+        for y in 0..resolution {
+            for x in 0..resolution {
+                let color = match height(x, y) {
+                    Some(h) => {
+                        let dx = height(x + 1, y).unwrap_or(h) - height(x - 1, y).unwrap_or(h);
+                        let dy = height(x, y + 1).unwrap_or(h) - height(x, y - 1).unwrap_or(h);
+                        let shade = (1.0 - (dx * LIGHT[0] + dy * LIGHT[1]) * SHADE_STRENGTH)
+                            .clamp(0.3, 1.3);
+                        let mut color = LOW_COLOR;
+                        for (c, high) in color.iter_mut().zip(HIGH_COLOR.iter()) {
+                            *c = ((*c + (high - *c) * h) * shade).min(1.0);
+                        }
+                        color
+                    }
+                    None => MISSING_COLOR,
+                };
+                // North is up, rows go from the south
+                let p0 = [
+                    origin[0] + x as f32 * cell,
+                    origin[1] + (resolution - 1 - y) as f32 * cell,
+                ];
+                let p1 = [p0[0] + cell, p0[1] + cell];
+                draw_list.add_rect(p0, p1, color).filled(true).build();
+            }
+        }
+        ui.dummy([MAP_SIZE, MAP_SIZE]);
+        apply
+    }
+}
@@ -0,0 +1,116 @@
+use crate::game::base::WorldSpace;
+use crate::game::terrain::Terrain;
+use euclid::Point3D;
+use imgui::{im_str, Ui};
+
+const MAX_SAMPLE_COUNT: i32 = 1024;
+const PLOT_SIZE: [f32; 2] = [300.0, 120.0];
+
+// Elevation profile along a line picked on the terrain
+pub struct ProfileWindow {
+    points: Vec<Point3D<f32, WorldSpace>>,
+    sample_count: i32,
+    // Heights sampled at even steps from the first point to the second, none
+    // where no chunk is cached
+    samples: Vec<Option<f32>>,
+}
+
+impl ProfileWindow {
+    pub fn new() -> Self {
+        Self {
+            points: vec![],
+            sample_count: 128,
+            samples: vec![],
+        }
+    }
+
+    // The line starts over on the third point
+    pub fn add_point(&mut self, point: Point3D<f32, WorldSpace>) {
+        if self.points.len() >= 2 {
+            self.points.clear();
+        }
+        self.points.push(point);
+        self.samples.clear();
+    }
+
+    fn sample(&mut self, terrain: &Terrain) {
+        let (p0, p1) = (self.points[0].xy(), self.points[1].xy());
+        self.samples = (0..self.sample_count)
+            .map(|i| {
+                let t = i as f32 / (self.sample_count - 1) as f32;
+                terrain.height_at(&p0.lerp(p1, t))
+            })
+            .collect();
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, terrain: &Terrain) {
+        ui.text("alt + left click on the terrain to pick the line");
+        if ui
+            .input_int(im_str!("samples"), &mut self.sample_count)
+            .build()
+        {
+            self.sample_count = self.sample_count.clamp(2, MAX_SAMPLE_COUNT);
+            self.samples.clear();
+        }
+        if self.points.len() < 2 {
+            ui.text(format!("{}/2 points", self.points.len()));
+            return;
+        }
+        // Chunks are regenerated in the background so the profile is only
+        // sampled again on request
+        if self.samples.is_empty() || ui.button(im_str!("Resample"), [0.0, 0.0]) {
+            self.sample(terrain);
+        }
+        let heights = self.samples.iter().flatten().copied().collect::<Vec<_>>();
+        if heights.is_empty() {
+            ui.text("no cached chunk along the line");
+            return;
+        }
+        let (min, max) = heights
+            .iter()
+            .fold((f32::INFINITY, f32::NEG_INFINITY), |(min, max), h| {
+                (min.min(*h), max.max(*h))
+            });
+        // Missing samples are drawn at the lowest height
+        let values = self
+            .samples
+            .iter()
+            .map(|x| x.unwrap_or(min))
+            .collect::<Vec<_>>();
+        ui.plot_lines(im_str!("elevation"), &values)
+            .scale_min(min)
+            .scale_max(max.max(min + f32::EPSILON))
+            .graph_size(PLOT_SIZE)
+            .build();
+        let length = self.points[0].xy().distance_to(self.points[1].xy());
+        let step = length / (self.sample_count - 1) as f32;
+        let (mut ascent, mut descent, mut max_slope) = (0.0f32, 0.0f32, 0.0f32);
+        for pair in self.samples.windows(2) {
+            if let [Some(h0), Some(h1)] = pair {
+                let delta = h1 - h0;
+                ascent += delta.max(0.0);
+                descent += (-delta).max(0.0);
+                max_slope = max_slope.max(delta.abs().atan2(step));
+            }
+        }
+        ui.text(format!("horizontal length: {:.4}", length));
+        ui.text(format!("elevation: {:.4} to {:.4}", min, max));
+        ui.text(format!("ascent: {:.4} descent: {:.4}", ascent, descent));
+        ui.text(format!(
+            "steepest slope: {:.1} degrees",
+            max_slope.to_degrees()
+        ));
+        let missing = self.samples.len() - heights.len();
+        if missing > 0 {
+            ui.text(format!("{} samples without a cached chunk", missing));
+        }
+    }
+
+    pub fn line(&self) -> Option<(Point3D<f32, WorldSpace>, Point3D<f32, WorldSpace>)> {
+        match self.points[..] {
+            [p0, p1] => Some((p0, p1)),
+            _ => None,
+        }
+    }
+}
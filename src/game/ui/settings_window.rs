@@ -0,0 +1,211 @@
+use crate::game::settings::{Action, Settings, TextureFilter};
+use imgui::{im_str, Ui};
+use winit::event::VirtualKeyCode;
+
+const MSAA_SAMPLES: [u32; 3] = [1, 2, 4];
+const ANISOTROPY_LEVELS: [u8; 5] = [1, 2, 4, 8, 16];
+
+#[derive(Default)]
+pub struct SettingsResponse {
+    pub changed: bool,
+    pub save: bool,
+    pub reload: bool,
+}
+
+pub struct SettingsWindow {
+    rebinding: Option<Action>,
+}
+
+impl SettingsWindow {
+    pub fn new() -> Self {
+        Self { rebinding: None }
+    }
+
+    #[profiling::function]
+    pub fn draw(&mut self, ui: &Ui, settings: &mut Settings) -> SettingsResponse {
+        let mut response = SettingsResponse::default();
+        if imgui::CollapsingHeader::new(im_str!("Input"))
+            .default_open(true)
+            .build(ui)
+        {
+            for action in Action::ALL {
+                let label = if self.rebinding == Some(action) {
+                    im_str!("{}: press a key...", action.name())
+                } else {
+                    im_str!("{}: {:?}", action.name(), settings.input.key(action))
+                };
+                if ui.button(&label, [240.0, 0.0]) {
+                    self.rebinding = Some(action);
+                }
+            }
+            imgui::Slider::new(im_str!("mouse sensitivity"))
+                .range(0.0005..=0.01)
+                .build(ui, &mut settings.input.mouse_sensitivity);
+        }
+        if imgui::CollapsingHeader::new(im_str!("Graphics"))
+            .default_open(true)
+            .build(ui)
+        {
+            response.changed |= ui.checkbox(im_str!("vsync"), &mut settings.graphics.vsync);
+            let mut msaa_index = MSAA_SAMPLES
+                .iter()
+                .position(|&x| x == settings.graphics.msaa)
+                .unwrap_or(0);
+            if imgui::ComboBox::new(im_str!("msaa")).build_simple_string(
+                ui,
+                &mut msaa_index,
+                &[im_str!("off"), im_str!("2x"), im_str!("4x")],
+            ) {
+                settings.graphics.msaa = MSAA_SAMPLES[msaa_index];
+                response.changed = true;
+            }
+            response.changed |= ui.checkbox(im_str!("stylized"), &mut settings.graphics.stylized);
+            imgui::Slider::new(im_str!("render scale"))
+                .range(0.25..=2.0)
+                .build(ui, &mut settings.graphics.render_scale);
+            response.changed |= ui.is_item_deactivated_after_edit();
+            imgui::Slider::new(im_str!("draw distance"))
+                .range(1.0..=9000.0)
+                .flags(imgui::SliderFlags::LOGARITHMIC)
+                .build(ui, &mut settings.graphics.draw_distance);
+            response.changed |= ui.is_item_deactivated_after_edit();
+            let mut filter_index = TextureFilter::ALL
+                .iter()
+                .position(|&x| x == settings.graphics.texture_filter)
+                .unwrap();
+            if imgui::ComboBox::new(im_str!("texture filter")).build_simple(
+                ui,
+                &mut filter_index,
+                &TextureFilter::ALL,
+                &|x| im_str!("{}", x.name()).into(),
+            ) {
+                settings.graphics.texture_filter = TextureFilter::ALL[filter_index];
+                response.changed = true;
+            }
+            let mut anisotropy_index = ANISOTROPY_LEVELS
+                .iter()
+                .position(|&x| x == settings.graphics.anisotropy)
+                .unwrap_or(0);
+            if imgui::ComboBox::new(im_str!("anisotropy")).build_simple_string(
+                ui,
+                &mut anisotropy_index,
+                &[
+                    im_str!("off"),
+                    im_str!("2x"),
+                    im_str!("4x"),
+                    im_str!("8x"),
+                    im_str!("16x"),
+                ],
+            ) {
+                settings.graphics.anisotropy = ANISOTROPY_LEVELS[anisotropy_index];
+                response.changed = true;
+            }
+            response.changed |=
+                ui.checkbox(im_str!("auto quality"), &mut settings.graphics.auto_quality);
+            imgui::Slider::new(im_str!("target fps"))
+                .range(15.0..=240.0)
+                .build(ui, &mut settings.graphics.target_fps);
+            response.changed |= ui.is_item_deactivated_after_edit();
+        }
+        if imgui::CollapsingHeader::new(im_str!("Level of detail"))
+            .default_open(true)
+            .build(ui)
+        {
+            imgui::Slider::new(im_str!("base distance"))
+                .range(0.1..=8.0)
+                .build(ui, &mut settings.lod.base_distance);
+            response.changed |= ui.is_item_deactivated_after_edit();
+            imgui::Slider::new(im_str!("growth factor"))
+                .range(1.1..=4.0)
+                .build(ui, &mut settings.lod.growth_factor);
+            response.changed |= ui.is_item_deactivated_after_edit();
+            let mut triangle_budget = settings.lod.triangle_budget as usize;
+            if input_usize(ui, im_str!("triangle budget"), &mut triangle_budget, 1) {
+                settings.lod.triangle_budget = triangle_budget as u32;
+                response.changed = true;
+            }
+            imgui::Slider::new(im_str!("max screen error (px)"))
+                .range(0.0..=16.0)
+                .build(ui, &mut settings.lod.max_screen_error);
+            response.changed |= ui.is_item_deactivated_after_edit();
+        }
+        if imgui::CollapsingHeader::new(im_str!("Streaming"))
+            .default_open(true)
+            .build(ui)
+        {
+            response.changed |= input_usize(
+                ui,
+                im_str!("chunk cache (MiB)"),
+                &mut settings.streaming.chunk_cache_mib,
+                1,
+            );
+            response.changed |= input_usize(
+                ui,
+                im_str!("mesh cache (MiB)"),
+                &mut settings.streaming.mesh_cache_mib,
+                1,
+            );
+            response.changed |= input_usize(
+                ui,
+                im_str!("GPU budget (MiB)"),
+                &mut settings.streaming.gpu_budget_mib,
+                1,
+            );
+            response.changed |= input_usize(
+                ui,
+                im_str!("GPU submissions in flight"),
+                &mut settings.streaming.max_gpu_submissions,
+                1,
+            );
+            response.changed |= input_usize(
+                ui,
+                im_str!("resident triangle budget"),
+                &mut settings.streaming.resident_triangle_budget,
+                0,
+            );
+            response.changed |= input_usize(
+                ui,
+                im_str!("workers (restart)"),
+                &mut settings.streaming.worker_count,
+                1,
+            );
+            response.changed |=
+                ui.checkbox(im_str!("disk cache"), &mut settings.streaming.disk_cache);
+            response.changed |= ui.checkbox(
+                im_str!("spill evicted chunks"),
+                &mut settings.streaming.spill_evicted_chunks,
+            );
+            response.changed |= ui.checkbox(
+                im_str!("idle refinement"),
+                &mut settings.streaming.idle_refinement,
+            );
+        }
+        ui.separator();
+        response.save = ui.button(im_str!("Save"), [0.0, 0.0]);
+        ui.same_line(0.0);
+        response.reload = ui.button(im_str!("Reload"), [0.0, 0.0]);
+        response
+    }
+
+    // Returns true if the key was consumed by a pending rebind
+    pub fn handle_key(&mut self, settings: &mut Settings, key: VirtualKeyCode) -> bool {
+        if let Some(action) = self.rebinding.take() {
+            if key != VirtualKeyCode::Escape {
+                settings.input.set_key(action, key);
+            }
+            true
+        } else {
+            false
+        }
+    }
+}
+
+fn input_usize(ui: &Ui, label: &imgui::ImStr, value: &mut usize, min: usize) -> bool {
+    let mut v = *value as i32;
+    if ui.input_int(label, &mut v).build() {
+        *value = (v.max(min as i32)) as usize;
+        true
+    } else {
+        false
+    }
+}
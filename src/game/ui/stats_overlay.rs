@@ -0,0 +1,79 @@
+use crate::game::quality::QualityController;
+use crate::game::terrain::{TerrainStats, MAX_PENDING_CHUNKS};
+use imgui::{im_str, Condition, Ui};
+use std::time::Duration;
+
+const BYTES_PER_MIB: f32 = 1024.0 * 1024.0;
+
+// Frame timing and chunk generation backlog in the corner of the screen,
+// along with the decisions of the quality controller when it is enabled
+#[profiling::function]
+pub fn draw_stats_overlay(
+    ui: &Ui,
+    frame_time: Duration,
+    stats: &TerrainStats,
+    quality: Option<&QualityController>,
+) {
+    imgui::Window::new(im_str!("Stats"))
+        .position([8.0, 8.0], Condition::Always)
+        .title_bar(false)
+        .resizable(false)
+        .movable(false)
+        .always_auto_resize(true)
+        .bg_alpha(0.6)
+        .build(ui, || {
+            let ms = frame_time.as_secs_f32() * 1000.0;
+            ui.text(format!(
+                "frame: {:.2}ms ({:.0} fps)",
+                ms,
+                1000.0 / ms.max(0.001)
+            ));
+            ui.text(format!(
+                "pending chunks: {}/{}, {} tasks queued, {:.0}ms average latency",
+                stats.pending_chunks,
+                MAX_PENDING_CHUNKS,
+                stats.queued_tasks,
+                stats.average_latency.as_secs_f32() * 1000.0
+            ));
+            ui.text(format!(
+                "GPU submissions in flight: {}, {} chunks parked",
+                stats.gpu_submissions, stats.parked_chunks
+            ));
+            ui.text(format!(
+                "retired meshes awaiting deletion: {}",
+                stats.retired_meshes
+            ));
+            let levels: Vec<_> = stats
+                .chunks_per_level
+                .iter()
+                .map(|(level, count)| format!("{}:{}", level, count))
+                .collect();
+            ui.text(format!("chunks per level: {}", levels.join(" ")));
+            ui.text(format!(
+                "{} triangles, {:.1} MiB on the GPU",
+                stats.resident_triangles,
+                stats.gpu_bytes as f32 / BYTES_PER_MIB
+            ));
+            ui.text(format!(
+                "chunk cache: {:.1} MiB, mesh cache: {:.1} MiB",
+                stats.chunk_cache_bytes as f32 / BYTES_PER_MIB,
+                stats.mesh_cache_bytes as f32 / BYTES_PER_MIB
+            ));
+            if let Some(quality) = quality {
+                let level = quality.quality();
+                ui.text(format!(
+                    "auto quality: level {}, {:.2}ms average",
+                    quality.level(),
+                    quality.average_frame_time().as_secs_f32() * 1000.0
+                ));
+                ui.text(format!(
+                    "render scale x{:.2}, lod distance x{:.2}, detail distance x{:.2}",
+                    level.render_scale, level.lod_distance, level.detail_distance
+                ));
+                ui.separator();
+                for decision in quality.decisions() {
+                    ui.text(decision);
+                }
+            }
+        });
+}
@@ -1,24 +1,116 @@
 use crate::game::base::Region;
 use crate::game::base::WorldSpace;
 use crate::game::camera::Camera;
-use crate::game::terrain::{ChunkCacheKey, Terrain};
-use euclid::{point2, vec2, Box2D, Point2D, Scale, Transform2D};
+use crate::game::lod::screen_space_error;
+use crate::game::terrain::{ChunkCacheKey, ChunkState, Crack, Terrain};
+use euclid::{point2, vec2, Box2D, Box3D, Point2D, Scale, Transform2D};
 use imgui::Ui;
 use std::borrow::Borrow;
+use std::time::Instant;
 
 pub struct TerrainVisualizerSpace;
 
 pub struct TerrainVisualizer {
     scale: Scale<f32, WorldSpace, TerrainVisualizerSpace>,
+    z_slice_enabled: bool,
+    z_slice: f32,
+    // Colors chunks by the screen space error of their geometric error, red
+    // at the error scale
+    error_overlay: bool,
+    error_scale: f32,
+    // Outlines of the rendered chunks drawn in the scene, colored by level or
+    // by how their sides are stitched
+    chunk_outlines: bool,
+    outline_stitches: bool,
+    // Seams found open by the last crack search, highlighted here and in the
+    // scene until the next one
+    cracks: Vec<Crack>,
 }
 
 impl TerrainVisualizer {
     pub fn new(scale: Scale<f32, WorldSpace, TerrainVisualizerSpace>) -> Self {
-        Self { scale }
+        Self {
+            scale,
+            z_slice_enabled: false,
+            z_slice: 0.0,
+            error_overlay: false,
+            error_scale: 4.0,
+            chunk_outlines: false,
+            outline_stitches: false,
+            cracks: vec![],
+        }
+    }
+
+    pub fn cracks(&self) -> &[Crack] {
+        &self.cracks
+    }
+
+    pub fn chunk_outlines(&self) -> bool {
+        self.chunk_outlines
+    }
+
+    pub fn outline_stitches(&self) -> bool {
+        self.outline_stitches
     }
 
     #[profiling::function]
-    pub fn draw(&self, ui: &Ui, terrain: &Terrain, camera: &Camera, regions: &[Region]) {
+    pub fn draw(&mut self, ui: &Ui, terrain: &Terrain, camera: &Camera, regions: &[Region]) {
+        {
+            let (min_z, max_z) = terrain.tree().z_range();
+            ui.checkbox(imgui::im_str!("z slice"), &mut self.z_slice_enabled);
+            if self.z_slice_enabled {
+                ui.same_line(0.0);
+                imgui::Slider::new(imgui::im_str!("z level"))
+                    .range(min_z as f32..=max_z as f32)
+                    .build(ui, &mut self.z_slice);
+            }
+        }
+        ui.checkbox(imgui::im_str!("error overlay"), &mut self.error_overlay);
+        if self.error_overlay {
+            ui.same_line(0.0);
+            imgui::Slider::new(imgui::im_str!("error scale (px)"))
+                .range(0.5..=32.0)
+                .build(ui, &mut self.error_scale);
+        }
+        ui.checkbox(
+            imgui::im_str!("outlines in scene"),
+            &mut self.chunk_outlines,
+        );
+        if self.chunk_outlines {
+            ui.same_line(0.0);
+            ui.checkbox(
+                imgui::im_str!("by stitch status"),
+                &mut self.outline_stitches,
+            );
+        }
+        if ui.button(imgui::im_str!("find cracks"), [0.0, 0.0]) {
+            self.cracks = terrain.find_cracks();
+            log::info!("Found {} cracks", self.cracks.len());
+            for crack in &self.cracks {
+                log::info!(
+                    "Crack between level {} {:?} and level {} {:?}: {} gaps up to {}",
+                    crack.keys[0].level,
+                    crack.keys[0].bounds.min.to_tuple(),
+                    crack.keys[1].level,
+                    crack.keys[1].bounds.min.to_tuple(),
+                    crack.gaps.len(),
+                    crack.width
+                );
+            }
+        }
+        if !self.cracks.is_empty() {
+            ui.same_line(0.0);
+            let widest = self.cracks.iter().map(|x| x.width).fold(0.0, f32::max);
+            ui.text(format!(
+                "{} cracks, widest {:.5}",
+                self.cracks.len(),
+                widest
+            ));
+            ui.same_line(0.0);
+            if ui.button(imgui::im_str!("clear cracks"), [0.0, 0.0]) {
+                self.cracks.clear();
+            }
+        }
         // let scale_inversed = self.scale.inverse();
         let win_bounds = Box2D::<_, TerrainVisualizerSpace>::from_origin_and_size(
             ui.cursor_screen_pos().into(),
@@ -27,6 +119,8 @@ impl TerrainVisualizer {
         let center = win_bounds.center();
         // let position = camera.position();
         let draw_list = ui.get_window_draw_list();
+        let mouse_position = Point2D::from(ui.io().mouse_pos);
+        let mut hovered_key = None;
         // let view_width = win_bounds.width() * scale_inversed.get();
         // let view_height = win_bounds.height() * scale_inversed.get();
         // let view_bounds = Box2D::<_, WorldSpace>::new(
@@ -55,20 +149,49 @@ impl TerrainVisualizer {
                         .zip(std::iter::repeat(true)),
                 )
             {
+                if self.z_slice_enabled && !self.in_z_slice(&leaf.bounds()) {
+                    continue;
+                }
                 let p0 = transform.transform_point(leaf.bounds().min.xy().to_f32());
                 let p1 = transform.transform_point(leaf.bounds().max.xy().to_f32());
                 let (border_color, fill_color) = if in_region {
                     let bounds = leaf.bounds();
                     let level = leaf.level();
-                    let key = ChunkCacheKey { bounds, level };
-                    let fill_color = if let Some(mesh) = mesh_cache.get(&key) {
-                        if mesh.render_bundle().is_none() {
+                    let key = ChunkCacheKey {
+                        bounds,
+                        level,
+                        noise: terrain.noise(),
+                        params: terrain.params(),
+                    };
+                    // Red where a worker panicked on the chunk, yellow while
+                    // it is still being generated
+                    let fill_color = if terrain.is_failed(&key) {
+                        [1.0, 0.0, 0.0]
+                    } else if let Some(mesh) = mesh_cache.get(&key) {
+                        if self.error_overlay {
+                            // Gray where the parent was not meshed first
+                            match mesh.geometric_error() {
+                                Some(error) => {
+                                    let bounds = bounds.to_f32();
+                                    let position = camera.position();
+                                    let closest = position.clamp(bounds.min, bounds.max);
+                                    let t = (screen_space_error(
+                                        camera,
+                                        error,
+                                        position.distance_to(closest),
+                                    ) / self.error_scale)
+                                        .min(1.0);
+                                    [t, 1.0 - t, 0.0]
+                                }
+                                None => [0.5, 0.5, 0.5],
+                            }
+                        } else if !mesh.is_resident() {
                             [0.0, 0.0, 1.0]
                         } else {
                             [0.0, 0.5, 1.0]
                         }
                     } else {
-                        [1.0, 0.0, 0.0]
+                        [1.0, 1.0, 0.0]
                     };
                     ([0.0, 1.0, 0.0], fill_color)
                 } else {
@@ -80,6 +203,16 @@ impl TerrainVisualizer {
                             .add_rect(p0.into(), p1.into(), fill_color)
                             .filled(true)
                             .build();
+                        if ui.is_window_hovered()
+                            && Box2D::from_points(&[p0, p1]).contains(mouse_position)
+                        {
+                            hovered_key = Some(ChunkCacheKey {
+                                bounds: leaf.bounds(),
+                                level: leaf.level(),
+                                noise: terrain.noise(),
+                                params: terrain.params(),
+                            });
+                        }
                     }
 
                     draw_list
@@ -88,6 +221,30 @@ impl TerrainVisualizer {
                 }
             }
         }
+        // Chunk inspector, the cache guards above have to be dropped first
+        if let Some(key) = hovered_key {
+            let state = match terrain.chunk_state(&key) {
+                ChunkState::Pending => "pending".to_string(),
+                ChunkState::Generated => "generated".to_string(),
+                ChunkState::Meshed => "meshed".to_string(),
+                ChunkState::Failed(failure) => format!(
+                    "failed {} times: {}\nretry in {:.1} s",
+                    failure.attempts,
+                    failure.error,
+                    failure
+                        .retry_at
+                        .saturating_duration_since(Instant::now())
+                        .as_secs_f32()
+                ),
+            };
+            ui.tooltip_text(format!(
+                "level {} {:?} to {:?}\n{}",
+                key.level,
+                key.bounds.min.to_tuple(),
+                key.bounds.max.to_tuple(),
+                state
+            ));
+        }
         // Draw regions
         {
             let transform = (-camera.position().xy().to_vector())
@@ -105,6 +262,22 @@ impl TerrainVisualizer {
                 }
             }
         }
+        // Draw cracks
+        {
+            let transform = (-camera.position().xy().to_vector())
+                .to_transform()
+                .then_scale(self.scale.get(), -self.scale.get())
+                .then(&center.to_vector().to_transform().with_source());
+            for crack in &self.cracks {
+                for [p0, p1] in &crack.gaps {
+                    let p = transform.transform_point(p0.xy().lerp(p1.xy(), 0.5));
+                    draw_list
+                        .add_circle(p.into(), 3.0, [1.0, 0.0, 1.0])
+                        .filled(true)
+                        .build();
+                }
+            }
+        }
         // Draw camera shape
         {
             let camera_shape: [Point2D<f32, TerrainVisualizerSpace>; 4] = [
@@ -131,4 +304,8 @@ impl TerrainVisualizer {
                 .build();
         }
     }
+
+    fn in_z_slice(&self, bounds: &Box3D<i32, WorldSpace>) -> bool {
+        bounds.min.z as f32 <= self.z_slice && self.z_slice <= bounds.max.z as f32
+    }
 }
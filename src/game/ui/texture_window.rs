@@ -0,0 +1,91 @@
+use crate::game::asset::TextureRegistry;
+use crate::gfx::Instance;
+use imgui::{im_str, ImString, Ui};
+use std::path::Path;
+use wgpu::TextureView;
+
+pub const PREVIEW_TEXTURE_ID: usize = 2;
+const PREVIEW_SIZE: f32 = 256.0;
+
+pub struct TextureWindow {
+    path: ImString,
+    selected: Option<String>,
+    level: i32,
+    // Texture and level currently registered for the preview
+    registered: Option<(String, i32)>,
+    status: Option<String>,
+}
+
+impl TextureWindow {
+    pub fn new() -> Self {
+        let mut path = ImString::with_capacity(256);
+        path.push_str("texture.png");
+        Self {
+            path,
+            selected: None,
+            level: 0,
+            registered: None,
+            status: None,
+        }
+    }
+
+    // Returns the view to register under PREVIEW_TEXTURE_ID when the preview
+    // changes, the image is shown from the next frame on
+    #[profiling::function]
+    pub fn draw(
+        &mut self,
+        ui: &Ui,
+        instance: &Instance,
+        textures: &mut TextureRegistry,
+    ) -> Option<TextureView> {
+        ui.input_text(im_str!("path"), &mut self.path).build();
+        if ui.button(im_str!("Load"), [0.0, 0.0]) {
+            let path = Path::new(self.path.to_str());
+            self.status = Some(match textures.load(instance, path) {
+                Ok(texture) => {
+                    self.selected = Some(path.to_string_lossy().to_string());
+                    self.level = 0;
+                    format!(
+                        "Loaded {}x{} with {} mip levels",
+                        texture.width(),
+                        texture.height(),
+                        texture.mip_level_count()
+                    )
+                }
+                Err(e) => format!("Failed to load: {}", e),
+            });
+        }
+        if let Some(status) = &self.status {
+            ui.text(status);
+        }
+        let names = textures.names().cloned().collect::<Vec<_>>();
+        for name in &names {
+            if imgui::Selectable::new(&ImString::new(name))
+                .selected(self.selected.as_ref() == Some(name))
+                .build(ui)
+            {
+                self.selected = Some(name.clone());
+                self.level = 0;
+            }
+        }
+        let texture = self.selected.as_ref().and_then(|x| textures.get(x))?;
+        imgui::Slider::new(im_str!("mip level"))
+            .range(0..=texture.mip_level_count() as i32 - 1)
+            .build(ui, &mut self.level);
+        let preview = (self.selected.clone().unwrap(), self.level);
+        if self.registered.as_ref() != Some(&preview) {
+            self.registered = Some(preview);
+            return Some(texture.mip_view(self.level as u32));
+        }
+        let scale = PREVIEW_SIZE / texture.width().max(texture.height()) as f32;
+        imgui::Image::new(
+            PREVIEW_TEXTURE_ID.into(),
+            [
+                texture.width() as f32 * scale,
+                texture.height() as f32 * scale,
+            ],
+        )
+        .build(ui);
+        None
+    }
+}
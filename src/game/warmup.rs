@@ -0,0 +1,77 @@
+use crate::game::base::{Region, WorldSpace};
+use crate::game::terrain::{ChunkCacheKey, ChunkState, Terrain, TerrainEvent};
+use euclid::{point2, Point2D};
+use std::collections::HashSet;
+use std::sync::mpsc::Receiver;
+
+// Half size of the square of coarse chunks around the spawn point
+const WARMUP_RADIUS: f32 = 128.0;
+
+// Coarse chunks generated around the spawn point of a world before the
+// player gets control, so that it does not start in empty space. Finer chunks
+// are streamed in afterwards and fall back to these meanwhile.
+pub struct Warmup {
+    remaining: HashSet<ChunkCacheKey>,
+    total: usize,
+    events: Receiver<TerrainEvent>,
+}
+
+impl Warmup {
+    pub fn new(terrain: &Terrain, spawn: &Point2D<f32, WorldSpace>) -> Self {
+        let region = Region::new(vec![
+            point2(spawn.x - WARMUP_RADIUS, spawn.y - WARMUP_RADIUS),
+            point2(spawn.x + WARMUP_RADIUS, spawn.y - WARMUP_RADIUS),
+            point2(spawn.x + WARMUP_RADIUS, spawn.y + WARMUP_RADIUS),
+            point2(spawn.x - WARMUP_RADIUS, spawn.y + WARMUP_RADIUS),
+        ]);
+        // Subscribed first so that no chunk finishes unseen
+        let events = terrain.subscribe();
+        let remaining = terrain
+            .coarse_keys(&region)
+            .into_iter()
+            .filter(|x| !matches!(terrain.chunk_state(x), ChunkState::Meshed))
+            .collect::<HashSet<_>>();
+        log::info!("Warming up {} chunks", remaining.len());
+        Self {
+            total: remaining.len(),
+            remaining,
+            events,
+        }
+    }
+
+    // Requests the chunks the scheduler did not take yet, returns true once
+    // every chunk is meshed. Failed chunks are retried by the streaming so
+    // they are not waited for.
+    pub fn update(&mut self, terrain: &Terrain) -> bool {
+        for event in self.events.try_iter() {
+            match event {
+                TerrainEvent::Meshed(key) => {
+                    self.remaining.remove(&key);
+                }
+                TerrainEvent::Failed(key, _) => {
+                    if self.remaining.remove(&key) {
+                        log::warn!("Warmup skips failed chunk {:?}", key);
+                    }
+                }
+            }
+        }
+        terrain.request_chunks(&self.remaining.iter().copied().collect::<Vec<_>>());
+        self.remaining.is_empty()
+    }
+
+    pub fn done_count(&self) -> usize {
+        self.total - self.remaining.len()
+    }
+
+    pub fn total(&self) -> usize {
+        self.total
+    }
+
+    pub fn progress(&self) -> f32 {
+        if self.total == 0 {
+            1.0
+        } else {
+            self.done_count() as f32 / self.total as f32
+        }
+    }
+}
@@ -0,0 +1,74 @@
+use crate::game::random::{lattice_value, RandomStreams, Stream};
+use std::f32::consts::TAU;
+use std::time::Duration;
+
+// Seconds between the noise lattice points of the direction and the gusts
+const TURN_PERIOD: f32 = 40.0;
+const GUST_PERIOD: f32 = 5.0;
+// Radians the wind turns away from its prevailing direction at most
+const MAX_TURN: f32 = 1.0;
+const MIN_STRENGTH: f32 = 0.2;
+const MAX_STRENGTH: f32 = 1.0;
+
+// Keep in sync with water.wgsl
+#[derive(Copy, Clone, bytemuck::Zeroable, Debug, bytemuck::Pod)]
+#[repr(C)]
+pub struct WindData {
+    // Unit vector on the ground the wind blows toward
+    pub direction: [f32; 2],
+    pub strength: f32,
+    // Seconds the wind has been blowing, animations are driven from it
+    pub time: f32,
+}
+
+// One wind for the whole world so that everything it moves moves together.
+// The direction turns slowly around the prevailing one and the strength gusts,
+// both from value noise over time seeded from the weather stream.
+pub struct Wind {
+    seed: u64,
+    prevailing: f32,
+    time: f32,
+}
+
+impl Wind {
+    pub fn new(random: &RandomStreams) -> Self {
+        let mut wind = Self {
+            seed: 0,
+            prevailing: 0.0,
+            time: 0.0,
+        };
+        wind.set_seed(random);
+        wind
+    }
+
+    // The time goes on so that the animations do not jump
+    pub fn set_seed(&mut self, random: &RandomStreams) {
+        let mut stream = random.stream(Stream::Weather);
+        self.seed = stream.next_u64();
+        self.prevailing = stream.next_f32() * TAU;
+    }
+
+    pub fn update(&mut self, elapsed_time: Duration) {
+        self.time += elapsed_time.as_secs_f32();
+    }
+
+    pub fn data(&self) -> WindData {
+        let turn = value_noise(self.seed, self.time / TURN_PERIOD) * 2.0 - 1.0;
+        let angle = self.prevailing + turn * MAX_TURN;
+        let gust = value_noise(self.seed.rotate_left(32), self.time / GUST_PERIOD);
+        WindData {
+            direction: [angle.cos(), angle.sin()],
+            strength: MIN_STRENGTH + (MAX_STRENGTH - MIN_STRENGTH) * gust,
+            time: self.time,
+        }
+    }
+}
+
+// Smooth noise in [0, 1) between the lattice values around t
+fn value_noise(seed: u64, t: f32) -> f32 {
+    let i = t.floor();
+    let f = t - i;
+    let a = lattice_value(seed, i as i64);
+    let b = lattice_value(seed, i as i64 + 1);
+    a + (b - a) * f * f * (3.0 - 2.0 * f)
+}
@@ -1,23 +1,60 @@
+use super::pool::{BufferPool, PooledBuffer};
+use super::sampler::{SamplerCache, SamplerKey};
+use super::staging::{StagingBuffer, StagingPool};
 use crate::windowing::Window;
 use futures::executor::block_on;
 use futures::executor::ThreadPool;
+use parking_lot::Mutex;
+use std::sync::Arc;
+use wgpu::util::{BufferInitDescriptor, DeviceExt};
 use wgpu::*;
 
 pub struct Instance {
-    surface: Surface,
+    // None for headless instances that never present
+    surface: Option<Surface>,
+    surface_config: Mutex<Option<SurfaceConfiguration>>,
     device: Device,
     queue: Queue,
     adapter: wgpu::Adapter,
     async_pool: ThreadPool,
+    samplers: SamplerCache,
+    staging_pool: StagingPool,
+    buffer_pool: Arc<BufferPool>,
 }
 
 impl Instance {
     pub fn new(window: &Window) -> Self {
         let wgpu_instance = wgpu::Instance::new(Backends::all());
         let surface = unsafe { wgpu_instance.create_surface(window.winit_window()) };
+        let mut instance = Self::with_surface(&wgpu_instance, Some(surface));
+
+        let size = window.winit_window().inner_size();
+
+        let surface = instance.surface();
+        let swapchain_format = surface.get_preferred_format(&instance.adapter).unwrap();
+        let sc_desc = SurfaceConfiguration {
+            usage: TextureUsages::RENDER_ATTACHMENT,
+            format: swapchain_format,
+            width: size.width,
+            height: size.height,
+            present_mode: wgpu::PresentMode::Mailbox,
+        };
+        surface.configure(&instance.device, &sc_desc);
+        instance.surface_config = Mutex::new(Some(sc_desc));
+        instance
+    }
+
+    // Without a window or swapchain, surface() panics and the swapchain
+    // functions do nothing
+    pub fn headless() -> Self {
+        let wgpu_instance = wgpu::Instance::new(Backends::all());
+        Self::with_surface(&wgpu_instance, None)
+    }
+
+    fn with_surface(wgpu_instance: &wgpu::Instance, surface: Option<Surface>) -> Self {
         let adapter = block_on(wgpu_instance.request_adapter(&wgpu::RequestAdapterOptions {
             power_preference: wgpu::PowerPreference::default(),
-            compatible_surface: Some(&surface),
+            compatible_surface: surface.as_ref(),
         }))
         .unwrap();
         let (device, queue) = block_on(adapter.request_device(
@@ -29,38 +66,57 @@ impl Instance {
             None,
         ))
         .unwrap();
+        Self::from_parts(surface, adapter, device, queue)
+    }
 
-        let size = window.winit_window().inner_size();
-
-        let swapchain_format = surface.get_preferred_format(&adapter).unwrap();
-        let sc_desc = SurfaceConfiguration {
-            usage: TextureUsages::RENDER_ATTACHMENT,
-            format: swapchain_format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Mailbox,
-        };
-        surface.configure(&device, &sc_desc);
+    // Wraps the device of an app that sets up wgpu itself and renders to its
+    // own surface, surface() panics as for headless instances. The device
+    // needs POLYGON_MODE_LINE for the wireframe pipelines.
+    pub fn with_device(adapter: Adapter, device: Device, queue: Queue) -> Self {
+        Self::from_parts(None, adapter, device, queue)
+    }
 
+    fn from_parts(
+        surface: Option<Surface>,
+        adapter: Adapter,
+        device: Device,
+        queue: Queue,
+    ) -> Self {
         Self {
             surface,
+            surface_config: Mutex::new(None),
             device,
             queue,
             adapter,
             async_pool: ThreadPool::new().unwrap(),
+            samplers: SamplerCache::new(),
+            staging_pool: StagingPool::new(),
+            buffer_pool: BufferPool::new(),
         }
     }
 
     pub fn recreate_swapchain(&self, size: winit::dpi::PhysicalSize<u32>) {
-        let swapchain_format = self.surface.get_preferred_format(&self.adapter).unwrap();
-        let sc_desc = SurfaceConfiguration {
-            usage: TextureUsages::RENDER_ATTACHMENT,
-            format: swapchain_format,
-            width: size.width,
-            height: size.height,
-            present_mode: wgpu::PresentMode::Immediate,
+        let mut sc_desc = self.surface_config.lock();
+        if let (Some(surface), Some(sc_desc)) = (&self.surface, sc_desc.as_mut()) {
+            sc_desc.width = size.width;
+            sc_desc.height = size.height;
+            surface.configure(&self.device, sc_desc);
+        }
+    }
+
+    pub fn set_vsync(&self, vsync: bool) {
+        let mut sc_desc = self.surface_config.lock();
+        let present_mode = if vsync {
+            PresentMode::Fifo
+        } else {
+            PresentMode::Immediate
         };
-        self.surface.configure(&self.device, &sc_desc);
+        if let (Some(surface), Some(sc_desc)) = (&self.surface, sc_desc.as_mut()) {
+            if sc_desc.present_mode != present_mode {
+                sc_desc.present_mode = present_mode;
+                surface.configure(&self.device, sc_desc);
+            }
+        }
     }
 
     pub fn device(&self) -> &Device {
@@ -72,10 +128,54 @@ impl Instance {
     }
 
     pub fn surface(&self) -> &Surface {
-        &self.surface
+        self.surface.as_ref().unwrap()
+    }
+
+    pub fn surface_format(&self) -> TextureFormat {
+        self.surface_config.lock().as_ref().unwrap().format
     }
 
     pub fn async_pool(&self) -> &ThreadPool {
         &self.async_pool
     }
+
+    pub fn sampler(&self, key: &SamplerKey) -> Arc<Sampler> {
+        self.samplers.get(&self.device, key)
+    }
+
+    // Mappable for reading and a copy destination, see StagingPool
+    pub fn staging_buffer(&self, size: BufferAddress) -> StagingBuffer {
+        self.staging_pool.take(&self.device, size)
+    }
+
+    // The buffer has to be unmapped
+    pub fn recycle_staging_buffer(&self, staging_buffer: StagingBuffer) {
+        self.staging_pool.give_back(staging_buffer);
+    }
+
+    // Reuses a dropped buffer of the same size and usage, see BufferPool
+    pub fn pooled_buffer(
+        &self,
+        label: &str,
+        size: BufferAddress,
+        usage: BufferUsages,
+    ) -> PooledBuffer {
+        self.buffer_pool.take(&self.device, label, size, usage)
+    }
+
+    // Always a new buffer, it is only recycled once dropped
+    pub fn pooled_buffer_init(
+        &self,
+        label: &str,
+        contents: &[u8],
+        usage: BufferUsages,
+    ) -> PooledBuffer {
+        let buffer = self.device.create_buffer_init(&BufferInitDescriptor {
+            label: Some(label),
+            contents,
+            usage,
+        });
+        self.buffer_pool
+            .adopt(buffer, contents.len() as BufferAddress, usage)
+    }
 }
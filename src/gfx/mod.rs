@@ -1,3 +1,9 @@
 mod instance;
+mod pool;
+mod sampler;
+mod staging;
 
 pub use instance::Instance;
+pub use pool::PooledBuffer;
+pub use sampler::SamplerKey;
+pub use staging::StagingBuffer;
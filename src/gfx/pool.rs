@@ -0,0 +1,90 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::ops::Deref;
+use std::sync::Arc;
+use wgpu::*;
+
+// Unused buffers beyond this are dropped instead of being kept for reuse
+const MAX_BUFFERS_PER_KEY: usize = 16;
+
+type PoolKey = (BufferAddress, BufferUsages);
+
+// Free lists of GPU buffers keyed by their exact size and usage. Buffers taken
+// from the pool go back to it when they are dropped, so the chunks evicted
+// from the caches leave their allocations to the chunks generated next.
+pub struct BufferPool {
+    free: Mutex<HashMap<PoolKey, Vec<Buffer>>>,
+}
+
+impl BufferPool {
+    pub fn new() -> Arc<Self> {
+        Arc::new(Self {
+            free: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn take(
+        self: &Arc<Self>,
+        device: &Device,
+        label: &str,
+        size: BufferAddress,
+        usage: BufferUsages,
+    ) -> PooledBuffer {
+        let key = (size, usage);
+        let pooled = self.free.lock().get_mut(&key).and_then(|x| x.pop());
+        let buffer = pooled.unwrap_or_else(|| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some(label),
+                size,
+                mapped_at_creation: false,
+                usage,
+            })
+        });
+        self.adopt(buffer, size, usage)
+    }
+
+    // Buffers created elsewhere, e.g. with their contents, are recycled too
+    pub fn adopt(
+        self: &Arc<Self>,
+        buffer: Buffer,
+        size: BufferAddress,
+        usage: BufferUsages,
+    ) -> PooledBuffer {
+        PooledBuffer {
+            buffer: Some(buffer),
+            key: (size, usage),
+            pool: self.clone(),
+        }
+    }
+
+    fn give_back(&self, key: PoolKey, buffer: Buffer) {
+        let mut free = self.free.lock();
+        let buffers = free.entry(key).or_default();
+        if buffers.len() < MAX_BUFFERS_PER_KEY {
+            buffers.push(buffer);
+        }
+    }
+}
+
+// Buffer given back to its pool when dropped
+pub struct PooledBuffer {
+    buffer: Option<Buffer>,
+    key: PoolKey,
+    pool: Arc<BufferPool>,
+}
+
+impl Deref for PooledBuffer {
+    type Target = Buffer;
+
+    fn deref(&self) -> &Buffer {
+        self.buffer.as_ref().unwrap()
+    }
+}
+
+impl Drop for PooledBuffer {
+    fn drop(&mut self) {
+        if let Some(buffer) = self.buffer.take() {
+            self.pool.give_back(self.key, buffer);
+        }
+    }
+}
@@ -0,0 +1,71 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::num::NonZeroU8;
+use std::sync::Arc;
+use wgpu::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct SamplerKey {
+    pub address_mode: AddressMode,
+    pub mag_filter: FilterMode,
+    pub min_filter: FilterMode,
+    pub mipmap_filter: FilterMode,
+    // 1 disables anisotropic filtering, otherwise a power of two up to 16
+    pub anisotropy: u8,
+}
+
+impl Default for SamplerKey {
+    fn default() -> Self {
+        Self {
+            address_mode: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Nearest,
+            min_filter: FilterMode::Nearest,
+            mipmap_filter: FilterMode::Nearest,
+            anisotropy: 1,
+        }
+    }
+}
+
+impl SamplerKey {
+    pub fn linear() -> Self {
+        Self {
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        }
+    }
+}
+
+// Samplers are created once per configuration and shared by every bind group
+// that uses it
+pub struct SamplerCache {
+    samplers: Mutex<HashMap<SamplerKey, Arc<Sampler>>>,
+}
+
+impl SamplerCache {
+    pub fn new() -> Self {
+        Self {
+            samplers: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn get(&self, device: &Device, key: &SamplerKey) -> Arc<Sampler> {
+        self.samplers
+            .lock()
+            .entry(*key)
+            .or_insert_with(|| {
+                Arc::new(device.create_sampler(&SamplerDescriptor {
+                    label: Some("cached_sampler"),
+                    address_mode_u: key.address_mode,
+                    address_mode_v: key.address_mode,
+                    address_mode_w: key.address_mode,
+                    mag_filter: key.mag_filter,
+                    min_filter: key.min_filter,
+                    mipmap_filter: key.mipmap_filter,
+                    anisotropy_clamp: NonZeroU8::new(key.anisotropy).filter(|x| x.get() > 1),
+                    ..Default::default()
+                }))
+            })
+            .clone()
+    }
+}
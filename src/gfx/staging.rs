@@ -0,0 +1,70 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use wgpu::*;
+
+// Buckets are powers of two from this size up
+const MIN_BUCKET_SIZE: BufferAddress = 256;
+// Buffers beyond this are dropped instead of being kept in their bucket
+const MAX_BUFFERS_PER_BUCKET: usize = 64;
+
+// Readback buffer borrowed from the pool, only the requested size is mapped
+pub struct StagingBuffer {
+    buffer: Buffer,
+    size: BufferAddress,
+    bucket: BufferAddress,
+}
+
+impl StagingBuffer {
+    pub fn buffer(&self) -> &Buffer {
+        &self.buffer
+    }
+
+    pub fn slice(&self) -> BufferSlice {
+        self.buffer.slice(..self.size)
+    }
+
+    pub fn unmap(&self) {
+        self.buffer.unmap();
+    }
+}
+
+// Mappable buffers shared by every readback, bucketed by their size rounded up
+// to a power of two. Buffers are only given back once they are unmapped, those
+// dropped while mapped or with a mapping pending are freed.
+pub struct StagingPool {
+    buckets: Mutex<HashMap<BufferAddress, Vec<Buffer>>>,
+}
+
+impl StagingPool {
+    pub fn new() -> Self {
+        Self {
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    pub fn take(&self, device: &Device, size: BufferAddress) -> StagingBuffer {
+        let bucket = size.next_power_of_two().max(MIN_BUCKET_SIZE);
+        let pooled = self.buckets.lock().get_mut(&bucket).and_then(|x| x.pop());
+        let buffer = pooled.unwrap_or_else(|| {
+            device.create_buffer(&BufferDescriptor {
+                label: Some("pooled_staging_buffer"),
+                size: bucket,
+                mapped_at_creation: false,
+                usage: BufferUsages::MAP_READ | BufferUsages::COPY_DST,
+            })
+        });
+        StagingBuffer {
+            buffer,
+            size,
+            bucket,
+        }
+    }
+
+    pub fn give_back(&self, staging_buffer: StagingBuffer) {
+        let mut buckets = self.buckets.lock();
+        let buffers = buckets.entry(staging_buffer.bucket).or_default();
+        if buffers.len() < MAX_BUFFERS_PER_BUCKET {
+            buffers.push(staging_buffer.buffer);
+        }
+    }
+}
@@ -0,0 +1,3 @@
+pub mod game;
+pub mod gfx;
+pub mod windowing;
@@ -1,12 +1,8 @@
-mod game;
-mod gfx;
-mod windowing;
-
-use game::Game;
-use gfx::Instance;
+use hinoki::game::{self, Game};
+use hinoki::gfx::Instance;
+use hinoki::windowing::Window;
 use std::sync::Arc;
 use std::time::{Duration, Instant};
-use windowing::Window;
 use winit::{
     event::{Event, WindowEvent},
     event_loop::ControlFlow,
@@ -14,6 +10,13 @@ use winit::{
 
 fn main() {
     env_logger::init();
+    // --pregen <radius> fills the chunk cache without opening a window
+    let args = std::env::args().collect::<Vec<_>>();
+    if let Some(i) = args.iter().position(|x| x == "--pregen") {
+        let radius = game::parse_radius(args.get(i + 1).map(|x| x.as_str()));
+        game::pregenerate(Arc::new(Instance::headless()), radius);
+        return;
+    }
     let window = Window::new();
     let instance = Arc::new(Instance::new(&window));
     let mut game = Game::new(instance.clone());